@@ -0,0 +1,88 @@
+use std::{path::Path, time::Duration};
+
+use tokio::time::{Instant, Interval, MissedTickBehavior};
+
+/// Operational notices the room broadcasts to everyone on a fixed interval,
+/// cycling through its messages
+#[derive(Debug)]
+pub struct Announcements {
+    messages: Vec<String>,
+    // the index of the next message
+    next: usize,
+    interval: Duration,
+    // created lazily, so it's bound to the runtime of the room
+    ticker: Option<Interval>,
+}
+
+impl Announcements {
+    /// Returns None when there's nothing to announce
+    pub fn new(interval: Duration, messages: Vec<String>) -> Option<Self> {
+        if messages.is_empty() || interval.is_zero() {
+            return None;
+        }
+
+        Some(Self {
+            messages,
+            next: 0,
+            interval,
+            ticker: None,
+        })
+    }
+
+    /// Reads the messages from a file, one message per line (blank lines are skipped)
+    pub fn load(interval: Duration, path: &Path) -> std::io::Result<Option<Self>> {
+        let messages = std::fs::read_to_string(path)?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_owned)
+            .collect();
+
+        Ok(Self::new(interval, messages))
+    }
+
+    /// Waits for the next announcement to be due, and returns it
+    ///
+    /// the first one is due a whole interval after the first call,
+    /// and a room that was held up doesn't catch up with the ones it has missed
+    pub async fn next(&mut self) -> String {
+        let interval = self.interval;
+        let ticker = self.ticker.get_or_insert_with(|| {
+            let mut ticker = tokio::time::interval_at(Instant::now() + interval, interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            ticker
+        });
+        ticker.tick().await;
+
+        let message = self.messages[self.next].clone();
+        self.next = (self.next + 1) % self.messages.len();
+        message
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time::Instant;
+
+    use super::Announcements;
+
+    #[test]
+    fn skip_empty_announcements() {
+        assert!(Announcements::new(Duration::from_secs(1), vec![]).is_none());
+        assert!(Announcements::new(Duration::ZERO, vec!["hi".into()]).is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cycle_through_messages() {
+        let start = Instant::now();
+        let mut announcements =
+            Announcements::new(Duration::from_secs(60), vec!["one".into(), "two".into()]).unwrap();
+
+        for (idx, expected) in ["one", "two", "one"].into_iter().enumerate() {
+            assert_eq!(announcements.next().await, expected);
+            assert_eq!(start.elapsed(), Duration::from_secs(60 * (idx as u64 + 1)));
+        }
+    }
+}
@@ -1,8 +1,27 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::atomic::{AtomicU64, Ordering},
+};
 
-use tokio::sync::{mpsc, oneshot};
+use tokio::{
+    sync::{
+        mpsc::{self, error::TrySendError},
+        oneshot,
+    },
+    time::{Duration, Instant},
+};
 
-use crate::protocol::*;
+use crate::{
+    announcements::Announcements,
+    protocol::*,
+    settings::{SettingsError, SettingsStore},
+};
+
+// the id of the next user to register, ids are never reused
+static NEXT_USER_ID: AtomicU64 = AtomicU64::new(0);
+
+// how often the room passes on the broadcasts it has queued up for slow users
+const FLUSH_INTERVAL: Duration = Duration::from_millis(100);
 
 // Used to manage a chat room
 #[derive(Debug, Clone)]
@@ -12,7 +31,7 @@ pub struct ChatRoom {
 
 pub struct ChatRoomRegistered {
     sender: mpsc::Sender<ToChatRoomMessage>,
-    username: String,
+    id: UserId,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -29,25 +48,50 @@ pub enum ChatRoomError {
 
 impl ChatRoom {
     // Creates a new chat room and returns an handler that can be used to register new users
-    pub fn create() -> Self {
+    //
+    // the room broadcasts its announcements (if any) from the same loop that handles its messages,
+    // so they are ordered with the rest of the broadcasts
+    pub fn create(settings: SettingsStore, mut announcements: Option<Announcements>) -> Self {
         let (tx, mut rx) = mpsc::channel(MESSAGE_BUFFER_COUNT);
 
         tokio::spawn(async move {
-            let mut users = UserManager::default();
+            let mut users = UserManager::new(settings);
+            let mut flush = tokio::time::interval(FLUSH_INTERVAL);
+
+            loop {
+                let message = tokio::select! {
+                    message = rx.recv() => match message {
+                        Some(message) => message,
+                        None => break,
+                    },
+
+                    // announcements go to everyone, since they have no originator,
+                    // they are operational notices and aren't kept in the history
+                    text = next_announcement(&mut announcements) => {
+                        users.emit_message_to_all(None, FromChatRoomMessage::Announcement(text));
+                        continue;
+                    }
+
+                    _ = flush.tick() => {
+                        users.flush();
+                        continue;
+                    }
+                };
 
-            while let Some(message) = rx.recv().await {
                 match message {
                     // A new user attempts to join the chat room
-                    ToChatRoomMessage::Join(Join { username, response }) => {
-                        match users.add_user(username.clone()) {
+                    ToChatRoomMessage::Join(Join {
+                        id,
+                        username,
+                        response,
+                    }) => {
+                        match users.add_user(id, username.clone()) {
                             Ok(rx) => {
                                 // User was added successfully
-                                users
-                                    .emit_message_to_all(
-                                        &username,
-                                        FromChatRoomMessage::Join(username.clone()),
-                                    )
-                                    .await;
+                                users.emit_message_to_all(
+                                    Some(id),
+                                    FromChatRoomMessage::Join(username.clone()),
+                                );
                                 let _ = response.send(Ok(JoinSuccess {
                                     userlist: users
                                         .get_user_list()
@@ -55,36 +99,53 @@ impl ChatRoom {
                                         // filter the current user from the list
                                         .filter(|current_username| current_username != &username)
                                         .collect(),
+                                    history: users.get_history(),
                                     rx,
                                 }));
                             }
-                            Err(_) => {
-                                // Username is already in use
-                                let _ = response.send(Err(JoinError::BadUsername(username)));
+                            Err(err) => {
+                                // Username is already in use, banned, or the room is full
+                                let _ = response.send(Err(err));
                             }
                         }
                     }
 
                     // A user has disconnected
-                    ToChatRoomMessage::Leave(Leave { username }) => {
-                        users.remove_user(&username);
-                        users
-                            .emit_message_to_all(
-                                &username,
-                                FromChatRoomMessage::Leave(username.clone()),
-                            )
-                            .await
+                    ToChatRoomMessage::Leave(Leave { id }) => {
+                        // a banned or an evicted user has already been removed from the room
+                        if let Some(username) = users.remove_user(id) {
+                            users.emit_message_to_all(None, FromChatRoomMessage::Leave(username))
+                        }
                     }
 
-                    // A user has sent a message
+                    // A user has sent a message,
+                    // the messages of a user that was removed from the room are dropped
                     ToChatRoomMessage::ChatMessage(ChatMessage { from, text }) => {
-                        users
-                            .emit_message_to_all(
-                                &from,
-                                FromChatRoomMessage::ChatMessage(from.clone(), text),
-                            )
-                            .await
+                        if let Some(username) = users.username(from) {
+                            let message = FromChatRoomMessage::ChatMessage(username, text);
+                            users.record(message.clone());
+                            users.emit_message_to_all(Some(from), message)
+                        }
                     }
+
+                    // A user has performed an action
+                    ToChatRoomMessage::Emote(ChatMessage { from, text }) => {
+                        if let Some(username) = users.username(from) {
+                            let message = FromChatRoomMessage::Emote(username, text);
+                            users.record(message.clone());
+                            users.emit_message_to_all(Some(from), message)
+                        }
+                    }
+
+                    // The operator has changed the room's settings
+                    ToChatRoomMessage::Admin(command) => match users.apply(command) {
+                        // dropping the user's sender disconnects them
+                        Ok(Some(kicked)) => {
+                            users.emit_message_to_all(None, FromChatRoomMessage::Leave(kicked))
+                        }
+                        Ok(None) => {}
+                        Err(err) => tracing::error!("failed to persist the room settings: {}", err),
+                    },
                 };
             }
         });
@@ -92,6 +153,13 @@ impl ChatRoom {
         Self { sender: tx }
     }
 
+    // Passes an operator command to the room
+    pub async fn admin(&self, command: AdminCommand) -> Result<(), ChatRoomError> {
+        self.sender.send(ToChatRoomMessage::Admin(command)).await?;
+
+        Ok(())
+    }
+
     // Tries to register a new user
     //
     // on success, returnes a chat handler that can be used to send messages
@@ -100,29 +168,39 @@ impl ChatRoom {
         username: String,
     ) -> Result<(ChatRoomRegistered, JoinSuccess), ChatRoomError> {
         let (tx, rx) = oneshot::channel();
+        let id = NEXT_USER_ID.fetch_add(1, Ordering::Relaxed);
 
         self.sender
             .send(ToChatRoomMessage::Join(Join {
-                username: username.clone(),
+                id,
+                username,
                 response: tx,
             }))
             .await?;
 
         let join_success = rx.await??;
 
-        Ok((ChatRoomRegistered::new(self.sender, username), join_success))
+        Ok((ChatRoomRegistered::new(self.sender, id), join_success))
+    }
+}
+
+// never resolves for a room without announcements
+async fn next_announcement(announcements: &mut Option<Announcements>) -> String {
+    match announcements {
+        Some(announcements) => announcements.next().await,
+        None => std::future::pending().await,
     }
 }
 
 impl ChatRoomRegistered {
-    fn new(sender: mpsc::Sender<ToChatRoomMessage>, username: String) -> Self {
-        Self { sender, username }
+    fn new(sender: mpsc::Sender<ToChatRoomMessage>, id: UserId) -> Self {
+        Self { sender, id }
     }
 
     pub async fn send_message(&self, message: String) -> Result<(), ChatRoomError> {
         self.sender
             .send(ToChatRoomMessage::ChatMessage(ChatMessage {
-                from: self.username.clone(),
+                from: self.id,
                 text: message,
             }))
             .await?;
@@ -130,14 +208,23 @@ impl ChatRoomRegistered {
         Ok(())
     }
 
+    pub async fn send_emote(&self, action: String) -> Result<(), ChatRoomError> {
+        self.sender
+            .send(ToChatRoomMessage::Emote(ChatMessage {
+                from: self.id,
+                text: action,
+            }))
+            .await?;
+
+        Ok(())
+    }
+
     // Leaves the chat room
     //
     // on success, returns an handler that can be used to register new users
     pub async fn leave(self) -> Result<ChatRoom, ChatRoomError> {
         self.sender
-            .send(ToChatRoomMessage::Leave(Leave {
-                username: self.username,
-            }))
+            .send(ToChatRoomMessage::Leave(Leave { id: self.id }))
             .await?;
 
         Ok(ChatRoom {
@@ -146,48 +233,473 @@ impl ChatRoomRegistered {
     }
 }
 
+impl FromChatRoom {
+    fn new(receiver: mpsc::Receiver<Broadcast>) -> Self {
+        Self {
+            receiver,
+            last_seq: None,
+        }
+    }
+
+    /// Receives the next message of the room, returns None once the user was removed from the room
+    ///
+    /// the room emits messages one at a time, so a user receives them in the order they were emitted in,
+    /// a broadcast that breaks that order is counted as an "out-of-order" error (and fails debug builds)
+    pub async fn recv(&mut self) -> Option<FromChatRoomMessage> {
+        let Broadcast { seq, message } = self.receiver.recv().await?;
+        if !self.check_order(seq) {
+            telemetry::stats::error("out-of-order");
+            tracing::error!("broadcast {} was delivered after {:?}", seq, self.last_seq);
+            debug_assert!(false, "broadcast {} was delivered out of order", seq);
+        }
+        self.last_seq = Some(seq);
+
+        Some(message)
+    }
+
+    // a broadcast is in order when it comes after the last one, a duplicate is out of order
+    fn check_order(&self, seq: u64) -> bool {
+        self.last_seq.is_none_or(|last| seq > last)
+    }
+}
+
 #[derive(Debug)]
 struct User {
-    sender: mpsc::Sender<FromChatRoomMessage>,
+    username: String,
+    sender: mpsc::Sender<Broadcast>,
+    // the broadcasts that didn't fit in the user's buffer yet, in the order they were emitted in
+    overflow: VecDeque<Broadcast>,
+    // since when the user's buffer is full, cleared once the overflow fits in
+    full_since: Option<Instant>,
+}
+
+impl User {
+    // Moves as much of the overflow as fits into the user's buffer,
+    // returns false once the user is too slow to be kept in the room
+    fn flush(&mut self, now: Instant) -> bool {
+        while let Some(broadcast) = self.overflow.pop_front() {
+            match self.sender.try_send(broadcast) {
+                Ok(()) => {}
+                Err(TrySendError::Full(broadcast)) => {
+                    self.overflow.push_front(broadcast);
+                    break;
+                }
+                // the user's task has ended, it's about to leave the room
+                Err(TrySendError::Closed(_)) => self.overflow.clear(),
+            }
+        }
+
+        if self.overflow.is_empty() {
+            self.full_since = None;
+            return true;
+        }
+
+        let full_since = *self.full_since.get_or_insert(now);
+        now.duration_since(full_since) < SLOW_USER_TIMEOUT
+            && self.overflow.len() < MAX_OVERFLOW_COUNT
+    }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 struct UserManager {
-    users: HashMap<String, User>,
+    users: HashMap<UserId, User>,
+    settings: SettingsStore,
+    // the most recent messages, replayed to users as they join
+    history: VecDeque<FromChatRoomMessage>,
+    // the sequence number of the next broadcast
+    next_seq: u64,
 }
 
 impl UserManager {
+    fn new(settings: SettingsStore) -> Self {
+        Self {
+            users: HashMap::default(),
+            settings,
+            history: VecDeque::default(),
+            next_seq: 0,
+        }
+    }
+
     /// Tries to add a user
     ///
-    /// returns an error if the username of the user is already in use
+    /// returns an error if the username of the user is already in use, is banned, or the room is full
     /// otherwise returns a receiver the user's task can use to receive messages
-    fn add_user(&mut self, username: String) -> Result<FromChatRoom, ()> {
-        if self.users.get(&username).is_some() {
-            return Err(());
+    fn add_user(&mut self, id: UserId, username: String) -> Result<FromChatRoom, JoinError> {
+        let settings = self.settings.settings();
+        if settings.banned.contains(&username) {
+            return Err(JoinError::Banned(username));
+        }
+
+        if self.find_user(&username).is_some() {
+            return Err(JoinError::BadUsername(username));
+        }
+
+        if settings
+            .capacity
+            .is_some_and(|capacity| self.users.len() >= capacity)
+        {
+            return Err(JoinError::RoomFull);
         }
 
         let (tx, rx) = mpsc::channel(MESSAGE_BUFFER_COUNT);
-        self.users.insert(username.clone(), User { sender: tx });
+        self.users.insert(
+            id,
+            User {
+                username,
+                sender: tx,
+                overflow: VecDeque::new(),
+                full_since: None,
+            },
+        );
+
+        Ok(FromChatRoom::new(rx))
+    }
+
+    // returns the username of the user, or None if the user wasn't in the room
+    fn remove_user(&mut self, id: UserId) -> Option<String> {
+        self.users.remove(&id).map(|user| user.username)
+    }
+
+    fn username(&self, id: UserId) -> Option<String> {
+        self.users.get(&id).map(|user| user.username.clone())
+    }
+
+    // the id of the user in the room that goes by the username
+    fn find_user(&self, username: &str) -> Option<UserId> {
+        self.users
+            .iter()
+            .find_map(|(id, user)| (user.username == username).then_some(*id))
+    }
+
+    /// Applies an operator command, and persists the resulting settings
+    ///
+    /// returns the username of a user that was kicked out of the room
+    fn apply(&mut self, command: AdminCommand) -> Result<Option<String>, SettingsError> {
+        match command {
+            AdminCommand::Ban(username) => {
+                self.settings.update(|settings| {
+                    settings.banned.insert(username.clone());
+                })?;
+
+                Ok(self
+                    .find_user(&username)
+                    .and_then(|id| self.remove_user(id)))
+            }
+            AdminCommand::Unban(username) => {
+                self.settings.update(|settings| {
+                    settings.banned.remove(&username);
+                })?;
+
+                Ok(None)
+            }
+            AdminCommand::Capacity(capacity) => {
+                self.settings
+                    .update(|settings| settings.capacity = capacity)?;
+
+                Ok(None)
+            }
+            AdminCommand::History(size) => {
+                self.settings
+                    .update(|settings| settings.history_size = size)?;
+                self.trim_history();
+
+                Ok(None)
+            }
+        }
+    }
 
-        Ok(FromChatRoom { receiver: rx })
+    fn record(&mut self, message: FromChatRoomMessage) {
+        self.history.push_back(message);
+        self.trim_history();
+    }
+
+    // drops the oldest messages that are beyond the history size
+    fn trim_history(&mut self) {
+        let size = self.settings.settings().history_size;
+        while self.history.len() > size {
+            self.history.pop_front();
+        }
     }
 
-    fn remove_user(&mut self, username: &str) {
-        self.users.remove(username);
+    fn get_history(&self) -> Vec<FromChatRoomMessage> {
+        self.history.iter().cloned().collect()
     }
 
-    // Emits a message to all connected users except for the originator
-    async fn emit_message_to_all(&self, originator: &str, message: FromChatRoomMessage) {
-        for (username, user) in self.users.iter() {
-            if username != originator {
-                if let Err(err) = user.sender.send(message.clone()).await {
-                    eprintln!("failed to emit a message to: {}\n{:?}", username, err);
+    // Emits a message to all connected users except for the originator (if any)
+    //
+    // a slow user never holds up a broadcast, the broadcasts that don't fit in its buffer
+    // are queued up until they do, or until it's evicted for being too slow
+    fn emit_message_to_all(&mut self, originator: Option<UserId>, message: FromChatRoomMessage) {
+        self.queue(originator, message);
+        self.flush();
+    }
+
+    // Queues the message up for all connected users except for the originator (if any)
+    fn queue(&mut self, originator: Option<UserId>, message: FromChatRoomMessage) {
+        let broadcast = Broadcast {
+            seq: self.next_seq,
+            message,
+        };
+        self.next_seq += 1;
+
+        for (id, user) in self.users.iter_mut() {
+            if Some(*id) != originator {
+                user.overflow.push_back(broadcast.clone());
+            }
+        }
+    }
+
+    // Passes the queued up broadcasts on to the users, and evicts the ones that are too slow
+    //
+    // the leave message of an evicted user is passed on by the next round,
+    // which may evict more users, until a round evicts no one
+    fn flush(&mut self) {
+        let now = Instant::now();
+        loop {
+            let evicted: Vec<_> = self
+                .users
+                .iter_mut()
+                .filter_map(|(id, user)| (!user.flush(now)).then_some(*id))
+                .collect();
+            if evicted.is_empty() {
+                break;
+            }
+
+            // dropping the user's sender disconnects them
+            for id in evicted {
+                if let Some(user) = self.users.remove(&id) {
+                    tracing::warn!(
+                        "evicted {}, a slow user with {} broadcasts queued up",
+                        user.username,
+                        user.overflow.len()
+                    );
+                    telemetry::stats::error("evicted");
+                    self.queue(None, FromChatRoomMessage::Leave(user.username));
                 }
             }
         }
     }
 
     fn get_user_list(&self) -> Vec<String> {
-        self.users.keys().cloned().collect()
+        self.users
+            .values()
+            .map(|user| user.username.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::sync::mpsc;
+
+    use crate::{
+        announcements::Announcements,
+        protocol::{
+            AdminCommand, Broadcast, FromChatRoomMessage, JoinError, MESSAGE_BUFFER_COUNT,
+            SLOW_USER_TIMEOUT,
+        },
+        settings::SettingsStore,
+    };
+
+    use super::{ChatRoom, FromChatRoom, UserManager};
+
+    #[test]
+    fn enforce_room_settings() {
+        let mut users = UserManager::new(SettingsStore::default());
+        users.apply(AdminCommand::Capacity(Some(1))).unwrap();
+        users.apply(AdminCommand::Ban("mallory".into())).unwrap();
+
+        assert!(matches!(
+            users.add_user(0, "mallory".into()),
+            Err(JoinError::Banned(_))
+        ));
+        assert!(users.add_user(1, "alice".into()).is_ok());
+        assert!(matches!(
+            users.add_user(2, "bob".into()),
+            Err(JoinError::RoomFull)
+        ));
+
+        // banning a user in the room kicks them out
+        assert_eq!(
+            users.apply(AdminCommand::Ban("alice".into())).unwrap(),
+            Some("alice".into())
+        );
+        assert!(users.get_user_list().is_empty());
+    }
+
+    #[test]
+    fn keep_recent_history() {
+        let mut users = UserManager::new(SettingsStore::default());
+        users.record(FromChatRoomMessage::ChatMessage(
+            "alice".into(),
+            "hi".into(),
+        ));
+        assert!(users.get_history().is_empty());
+
+        users.apply(AdminCommand::History(2)).unwrap();
+        for text in ["one", "two", "three"] {
+            users.record(FromChatRoomMessage::ChatMessage(
+                "alice".into(),
+                text.into(),
+            ));
+        }
+
+        let history: Vec<_> = users
+            .get_history()
+            .into_iter()
+            .map(|message| match message {
+                FromChatRoomMessage::ChatMessage(_, text) => text,
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(history, ["two", "three"]);
+    }
+
+    #[tokio::test]
+    async fn number_broadcasts() {
+        let mut users = UserManager::new(SettingsStore::default());
+        let mut alice = users.add_user(0, "alice".into()).unwrap();
+        let mut bob = users.add_user(1, "bob".into()).unwrap();
+
+        users.emit_message_to_all(Some(0), FromChatRoomMessage::Join("alice".into()));
+        users.emit_message_to_all(Some(1), FromChatRoomMessage::Join("bob".into()));
+
+        // a broadcast gets the same number for every recipient
+        assert!(matches!(
+            bob.recv().await,
+            Some(FromChatRoomMessage::Join(username)) if username == "alice"
+        ));
+        assert_eq!(bob.last_seq, Some(0));
+        assert!(alice.recv().await.is_some());
+        assert_eq!(alice.last_seq, Some(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn evict_slow_users() {
+        let mut users = UserManager::new(SettingsStore::default());
+        let mut alice = users.add_user(0, "alice".into()).unwrap();
+        // bob doesn't read, so his buffer fills up
+        let mut bob = users.add_user(1, "bob".into()).unwrap();
+
+        let chat = |text: &str| FromChatRoomMessage::ChatMessage("carol".into(), text.into());
+        for _ in 0..MESSAGE_BUFFER_COUNT + 1 {
+            users.emit_message_to_all(Some(2), chat("hi"));
+            assert!(alice.recv().await.is_some());
+        }
+
+        // the broadcasts that don't fit are queued up until the timeout, they don't hold up alice
+        tokio::time::advance(SLOW_USER_TIMEOUT / 2).await;
+        users.emit_message_to_all(Some(2), chat("still there?"));
+        assert!(alice.recv().await.is_some());
+        assert_eq!(users.users[&1].overflow.len(), 2);
+
+        // once bob makes room, the queued up broadcasts are passed on in order
+        for _ in 0..2 {
+            assert!(bob.recv().await.is_some());
+        }
+        users.flush();
+        assert!(users.users[&1].overflow.is_empty());
+        assert_eq!(users.users[&1].full_since, None);
+
+        users.emit_message_to_all(Some(2), chat("hi again"));
+        assert!(alice.recv().await.is_some());
+        tokio::time::advance(SLOW_USER_TIMEOUT).await;
+        users.emit_message_to_all(Some(2), chat("bye"));
+        assert!(alice.recv().await.is_some());
+        assert!(matches!(
+            alice.recv().await,
+            Some(FromChatRoomMessage::Leave(username)) if username == "bob"
+        ));
+        assert_eq!(users.get_user_list(), ["alice"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn broadcast_announcements() {
+        let announcements = Announcements::new(
+            Duration::from_secs(60),
+            vec!["maintenance at noon".into(), "be nice".into()],
+        );
+        let room = ChatRoom::create(SettingsStore::default(), announcements);
+        let (alice, mut joined) = room.register("alice".into()).await.unwrap();
+
+        alice.send_message("hi".into()).await.unwrap();
+        for expected in ["maintenance at noon", "be nice"] {
+            assert!(matches!(
+                joined.rx.recv().await,
+                Some(FromChatRoomMessage::Announcement(text)) if text == expected
+            ));
+        }
+
+        // the announcements aren't replayed to new users
+        let (_, bob) = alice
+            .leave()
+            .await
+            .unwrap()
+            .register("bob".into())
+            .await
+            .unwrap();
+        assert!(bob
+            .history
+            .iter()
+            .all(|message| !matches!(message, FromChatRoomMessage::Announcement(_))));
+    }
+
+    #[test]
+    fn ignore_a_stale_leave() {
+        let mut users = UserManager::new(SettingsStore::default());
+        users.add_user(0, "bob".into()).unwrap();
+        // bob is kicked out, and someone else takes his name before his connection is closed
+        users.apply(AdminCommand::Ban("bob".into())).unwrap();
+        users.apply(AdminCommand::Unban("bob".into())).unwrap();
+        users.add_user(1, "bob".into()).unwrap();
+
+        // the leave of the old bob must not remove the new one
+        assert_eq!(users.remove_user(0), None);
+        assert_eq!(users.username(0), None);
+        assert_eq!(users.find_user("bob"), Some(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn evict_several_slow_users_at_once() {
+        let mut users = UserManager::new(SettingsStore::default());
+        let mut alice = users.add_user(0, "alice".into()).unwrap();
+        // bob and carol don't read, so their buffers fill up
+        let _bob = users.add_user(1, "bob".into()).unwrap();
+        let _carol = users.add_user(2, "carol".into()).unwrap();
+
+        let chat = || FromChatRoomMessage::ChatMessage("dave".into(), "hi".into());
+        for _ in 0..MESSAGE_BUFFER_COUNT + 1 {
+            users.emit_message_to_all(Some(3), chat());
+            assert!(alice.recv().await.is_some());
+        }
+
+        tokio::time::advance(SLOW_USER_TIMEOUT).await;
+        users.flush();
+
+        // both are evicted by the same flush, and alice hears both of them leave
+        let mut left = Vec::new();
+        for _ in 0..2 {
+            match alice.recv().await {
+                Some(FromChatRoomMessage::Leave(username)) => left.push(username),
+                message => panic!("expected a leave message, received {:?}", message),
+            }
+        }
+        left.sort();
+        assert_eq!(left, ["bob", "carol"]);
+        assert_eq!(users.get_user_list(), ["alice"]);
+    }
+
+    #[test]
+    fn check_delivery_order() {
+        let (_, rx) = mpsc::channel::<Broadcast>(1);
+        let mut from_room = FromChatRoom::new(rx);
+        assert!(from_room.check_order(3));
+
+        from_room.last_seq = Some(3);
+        assert!(from_room.check_order(7));
+        assert!(!from_room.check_order(3));
+        assert!(!from_room.check_order(2));
     }
 }
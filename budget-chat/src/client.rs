@@ -1,4 +1,5 @@
-use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use framing::{Encoding, FramingError, LineReader, PartialLine};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
 
 use crate::protocol::{MAX_MESSAGE_SIZE, MAX_USERNAME_SIZE, SYSTEM_MESSAGE_PREFIX};
 
@@ -58,6 +59,32 @@ where
         Ok(())
     }
 
+    // an emote is rendered as a message of its sender rather than as a system message,
+    // otherwise a user named after a system message could forge one
+    pub async fn send_emote(&mut self, from: &str, action: &str) -> tokio::io::Result<()>
+    where
+        Self: Unpin,
+    {
+        self.writer
+            .write_all(format!("[{}] * {}\n", from, action).as_bytes())
+            .await?;
+        self.writer.flush().await?;
+
+        Ok(())
+    }
+
+    pub async fn send_announcement(&mut self, message: &str) -> tokio::io::Result<()>
+    where
+        Self: Unpin,
+    {
+        self.writer
+            .write_all(format!("{} {}\n", SYSTEM_MESSAGE_PREFIX, message).as_bytes())
+            .await?;
+        self.writer.flush().await?;
+
+        Ok(())
+    }
+
     pub async fn send_join_message(&mut self, username: &str) -> tokio::io::Result<()>
     where
         Self: Unpin,
@@ -92,7 +119,8 @@ where
 }
 
 pub struct Reader<R> {
-    reader: R,
+    // kept across reads, so nothing that was read ahead of a line is lost
+    reader: LineReader<R>,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -106,6 +134,9 @@ pub enum ReaderError {
     #[error("Received a non ascii message")]
     NonAscii,
 
+    #[error("Received a line longer than {0} characters")]
+    TooLong(usize),
+
     #[error("Username must consist entirely of alphanumeric characteres, and contain at least one character")]
     InvalidUsername,
 }
@@ -116,7 +147,12 @@ where
     R: AsyncRead,
 {
     pub fn new(reader: R) -> Self {
-        Self { reader }
+        Self {
+            // the last line may end at EOF instead of a newline
+            reader: LineReader::new(reader, MAX_MESSAGE_SIZE)
+                .with_encoding(Encoding::Ascii)
+                .with_partial_line(PartialLine::Return),
+        }
     }
 
     pub async fn read_name(&mut self) -> Result<String, ReaderError> {
@@ -132,25 +168,99 @@ where
         self.read_limited_line(MAX_MESSAGE_SIZE).await
     }
 
+    // Reads a line of up to `size` characters, not counting its newline
+    //
+    // the last line may end at EOF instead of a newline,
+    // a line that goes over the size is an error rather than being cut short
     async fn read_limited_line(&mut self, size: usize) -> Result<String, ReaderError> {
-        // limit the reader
-        let mut buf = BufReader::new(&mut self.reader).take(size as u64);
-
-        // reader a line
-        let mut content = String::with_capacity(size);
-        let rcount = buf.read_line(&mut content).await?;
-        if rcount == 0 {
-            return Err(ReaderError::Eof);
+        self.reader.set_max_len(size);
+        match self.reader.read_line().await {
+            Ok(Some(line)) => Ok(line),
+            Ok(None) => Err(ReaderError::Eof),
+            Err(FramingError::Io(err)) => Err(err.into()),
+            Err(FramingError::TooLong(size)) => Err(ReaderError::TooLong(size)),
+            Err(FramingError::NotAscii | FramingError::NotUtf8) => Err(ReaderError::NonAscii),
+            Err(FramingError::PartialLine) => {
+                unreachable!("the reader returns a partial line like any other line")
+            }
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Reader, ReaderError, Writer};
+    use crate::protocol::{MAX_MESSAGE_SIZE, MAX_USERNAME_SIZE};
 
-        // verify that the content is valid ASCII
-        if !content.is_ascii() {
-            return Err(ReaderError::NonAscii);
+    #[tokio::test]
+    async fn keep_data_read_ahead_of_a_line() {
+        let mut reader = Reader::new(&b"alice\nhello\n\nbye"[..]);
+
+        assert_eq!(reader.read_name().await.unwrap(), "alice");
+        assert_eq!(reader.read_message().await.unwrap(), "hello");
+        assert_eq!(reader.read_message().await.unwrap(), "");
+        // the last line may end without a newline
+        assert_eq!(reader.read_message().await.unwrap(), "bye");
+        assert!(matches!(reader.read_message().await, Err(ReaderError::Eof)));
+    }
+
+    #[tokio::test]
+    async fn limit_line_size() {
+        for size in [MAX_MESSAGE_SIZE - 1, MAX_MESSAGE_SIZE] {
+            let line = "a".repeat(size);
+
+            // whether the line ends with a newline or at EOF, it's kept whole
+            for input in [format!("{}\n", line), line.clone()] {
+                let mut reader = Reader::new(input.as_bytes());
+                assert_eq!(reader.read_message().await.unwrap(), line);
+            }
         }
 
-        // remove new line from the end
-        content.pop();
-        println!("{}", content);
-        Ok(content)
+        let line = "a".repeat(MAX_MESSAGE_SIZE + 1);
+        for input in [format!("{}\n", line), line] {
+            let mut reader = Reader::new(input.as_bytes());
+            assert!(matches!(
+                reader.read_message().await,
+                Err(ReaderError::TooLong(MAX_MESSAGE_SIZE))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn limit_username_size() {
+        let name = "a".repeat(MAX_USERNAME_SIZE);
+        let input = format!("{}\n{}a\n", name, name);
+        let mut reader = Reader::new(input.as_bytes());
+
+        assert_eq!(reader.read_name().await.unwrap(), name);
+        assert!(matches!(
+            reader.read_name().await,
+            Err(ReaderError::TooLong(MAX_USERNAME_SIZE))
+        ));
+    }
+
+    #[tokio::test]
+    async fn render_emotes_as_user_messages() {
+        let mut output = Vec::new();
+        let mut writer = Writer::new(&mut output);
+        writer.send_emote("alice", "waves").await.unwrap();
+        // a user can't make its emote pass for a message of the room
+        writer.send_emote("bob", "has left the room").await.unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "[alice] * waves\n[bob] * has left the room\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn reject_non_ascii_lines() {
+        for input in [&b"caf\xc3\xa9\n"[..], &b"\xff\xfe\n"[..]] {
+            let mut reader = Reader::new(input);
+            assert!(matches!(
+                reader.read_message().await,
+                Err(ReaderError::NonAscii)
+            ));
+        }
     }
 }
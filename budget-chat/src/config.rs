@@ -0,0 +1,109 @@
+use std::{env, path::PathBuf, str::FromStr, time::Duration};
+
+const DEFAULT_ANNOUNCE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+const DEFAULT_LISTEN_ADDR: &str = "0.0.0.0:3600";
+
+/// An address the room is reachable on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListenAddr {
+    // tells the connections of the listener apart in the logs, the address itself by default
+    pub label: String,
+    pub addr: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    // when set, the room settings are persisted into this file, and reloaded from it on startup
+    pub state_file: Option<PathBuf>,
+    // every connection starts with a PROXY protocol header (v1 or v2),
+    // for running behind a load balancer
+    pub proxy_protocol: bool,
+    // when set, the room announces the lines of this file to everyone, one at a time
+    pub announcements_file: Option<PathBuf>,
+    // the time between two announcements
+    pub announce_interval: Duration,
+    // every listener registers its users into the same room
+    pub listeners: Vec<ListenAddr>,
+    // when set, the metrics are served for prometheus to scrape on this address
+    pub metrics_addr: Option<String>,
+}
+
+impl Config {
+    pub fn from_env() -> anyhow::Result<Self> {
+        Ok(Self {
+            state_file: env::var_os("ROOM_STATE_FILE").map(PathBuf::from),
+            proxy_protocol: env::var("PROXY_PROTOCOL")
+                .map(|value| value == "1" || value.eq_ignore_ascii_case("true"))
+                .unwrap_or(false),
+            announcements_file: env::var_os("ANNOUNCEMENTS_FILE").map(PathBuf::from),
+            announce_interval: read_var("ANNOUNCE_INTERVAL_SECS")?
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_ANNOUNCE_INTERVAL),
+            listeners: parse_listeners(
+                &env::var("LISTEN_ADDRS").unwrap_or_else(|_| DEFAULT_LISTEN_ADDR.into()),
+            ),
+            metrics_addr: env::var("METRICS_ADDR").ok(),
+        })
+    }
+}
+
+fn read_var<T>(name: &str) -> anyhow::Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|err| anyhow::anyhow!("bad value for {}: {}", name, err)),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(err) => Err(anyhow::anyhow!("bad value for {}: {}", name, err)),
+    }
+}
+
+// a comma separated list of addresses, each may be labeled as "label=addr"
+fn parse_listeners(value: &str) -> Vec<ListenAddr> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| match entry.split_once('=') {
+            Some((label, addr)) => ListenAddr {
+                label: label.trim().into(),
+                addr: addr.trim().into(),
+            },
+            None => ListenAddr {
+                label: entry.into(),
+                addr: entry.into(),
+            },
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_listeners, ListenAddr};
+
+    #[test]
+    fn parse_listen_addrs() {
+        let listen = |label: &str, addr: &str| ListenAddr {
+            label: label.into(),
+            addr: addr.into(),
+        };
+
+        assert_eq!(
+            parse_listeners("0.0.0.0:3600"),
+            [listen("0.0.0.0:3600", "0.0.0.0:3600")]
+        );
+        assert_eq!(
+            parse_listeners("v4=0.0.0.0:3600, v6 = [::]:3600,,0.0.0.0:3601"),
+            [
+                listen("v4", "0.0.0.0:3600"),
+                listen("v6", "[::]:3600"),
+                listen("0.0.0.0:3601", "0.0.0.0:3601"),
+            ]
+        );
+        assert!(parse_listeners("").is_empty());
+    }
+}
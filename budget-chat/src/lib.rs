@@ -0,0 +1,9 @@
+//! The chat room and the user handling behind the server,
+//! a library of their own so the verifier can run the server in-process
+pub mod announcements;
+pub mod chatroom;
+pub mod client;
+pub mod config;
+pub mod protocol;
+pub mod server;
+pub mod settings;
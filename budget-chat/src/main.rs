@@ -1,86 +1,193 @@
-use chatroom::ChatRoom;
-use protocol::JoinSuccess;
-use tokio::net::{TcpListener, TcpStream};
-
-use crate::protocol::FromChatRoomMessage;
-
-mod chatroom;
-mod client;
-mod protocol;
+use std::{net::SocketAddr, sync::Arc};
+
+use anyhow::Context;
+use budget_chat::{
+    announcements::Announcements, chatroom::ChatRoom, config::Config,
+    protocol::parse_admin_command, server::handle_connection, settings::SettingsStore,
+};
+use throttle::{Limits, Throttle};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    net::TcpListener,
+    task::JoinSet,
+};
+use tracing::Instrument;
 
 #[tokio::main]
-async fn main() -> tokio::io::Result<()> {
-    let listener = TcpListener::bind("0.0.0.0:3600").await?;
-    println!("Server listening on: {}", listener.local_addr().unwrap());
+async fn main() -> anyhow::Result<()> {
+    telemetry::init();
+    let config = Config::from_env()?;
+    if let Some(addr) = &config.metrics_addr {
+        if let Err(err) = telemetry::stats::install(addr) {
+            tracing::error!("failed to start the metrics exporter: {}", err);
+        }
+    }
 
-    let chatroom = ChatRoom::create();
+    let settings = match config.state_file {
+        Some(path) => SettingsStore::load(path)?,
+        None => SettingsStore::default(),
+    };
+    let announcements = match &config.announcements_file {
+        Some(path) => Announcements::load(config.announce_interval, path)?,
+        None => None,
+    };
 
-    loop {
-        let (conn, _) = listener.accept().await?;
-        tokio::spawn(handle_connection(conn, chatroom.clone()));
+    anyhow::ensure!(!config.listeners.is_empty(), "no address to listen on");
+    let mut listeners = Vec::with_capacity(config.listeners.len());
+    for listen in config.listeners {
+        let listener = bind(&listen.addr)
+            .await
+            .with_context(|| format!("failed to listen on {}", listen.addr))?;
+        tracing::info!(
+            "[{}] Server listening on: {}",
+            listen.label,
+            listener.local_addr()?
+        );
+        listeners.push((listen.label, listener));
+    }
+
+    // the limits are shared by all of the listeners. behind a load balancer,
+    // the rate of every IP is the rate of the balancer, since the throttle comes before the header
+    let throttle = Arc::new(Throttle::new(Limits::from_env()?));
+
+    let chatroom = ChatRoom::create(settings, announcements);
+    tokio::spawn(handle_admin_commands(chatroom.clone()));
+
+    // all of the listeners feed the same room, the server stops once any of them fails
+    let mut accept_loops = JoinSet::new();
+    for (label, listener) in listeners {
+        accept_loops.spawn(accept(
+            label,
+            listener,
+            throttle.clone(),
+            chatroom.clone(),
+            config.proxy_protocol,
+        ));
+    }
+    match accept_loops.join_next().await {
+        Some(result) => result?,
+        None => Ok(()),
     }
 }
 
-async fn handle_connection(mut client: TcpStream, chatroom: ChatRoom) -> anyhow::Result<()> {
-    let (reader, writer) = client.split();
-    let mut reader = client::Reader::new(reader);
-    let mut writer = client::Writer::new(writer);
-
-    // Register a new user
-    writer.send_welcome_message().await?;
-    let username = reader.read_name().await?;
-    let (
-        chatroom,
-        JoinSuccess {
-            userlist,
-            rx: mut from_chat_room,
-        },
-    ) = chatroom.register(username.trim().to_owned()).await?;
+// an IPv6 listener only takes IPv6 connections,
+// so it can share its port with an IPv4 listener on the same host
+async fn bind(addr: &str) -> anyhow::Result<TcpListener> {
+    let addr: SocketAddr = tokio::net::lookup_host(addr)
+        .await?
+        .next()
+        .context("the address doesn't resolve")?;
+
+    let socket = socket2::Socket::new(
+        socket2::Domain::for_address(addr),
+        socket2::Type::STREAM,
+        Some(socket2::Protocol::TCP),
+    )?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
 
-    // Send the user list
-    writer.send_user_list(userlist).await?;
+    Ok(TcpListener::from_std(socket.into())?)
+}
 
-    // Handle new messages from the user
-    let from_user = async move {
-        loop {
-            let message = match reader.read_message().await {
-                Ok(message) => message,
-                Err(client::ReaderError::Eof) => break,
-                Err(err) => Err(err)?,
-            };
+async fn accept(
+    label: String,
+    listener: TcpListener,
+    throttle: Arc<Throttle>,
+    chatroom: ChatRoom,
+    proxy_protocol: bool,
+) -> anyhow::Result<()> {
+    loop {
+        let (conn, addr, permit) = throttle
+            .accept(&listener)
+            .await
+            .with_context(|| format!("[{}] failed to accept a connection", label))?;
+        let connection = permit.hold(handle_connection(conn, chatroom.clone(), proxy_protocol));
+        tokio::spawn(
+            telemetry::listener_connection(label.clone(), addr, connection)
+                .instrument(tracing::info_span!("listener", label = %label)),
+        );
+    }
+}
 
-            chatroom.send_message(message.trim().to_owned()).await?;
+// Reads operator commands from stdin
+async fn handle_admin_commands(chatroom: ChatRoom) -> anyhow::Result<()> {
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    while let Some(line) = lines.next_line().await? {
+        match parse_admin_command(&line) {
+            Some(command) => chatroom.admin(command).await?,
+            None => eprintln!(
+                "unknown command, expected one of: ban <username>, unban <username>, capacity <count|unlimited>, history <size>"
+            ),
         }
+    }
 
-        // the user has disconnected, leave the room
-        chatroom.leave().await?;
+    Ok(())
+}
 
-        Ok::<(), anyhow::Error>(())
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, sync::Arc};
+
+    use budget_chat::{chatroom::ChatRoom, settings::SettingsStore};
+    use throttle::{Limits, Throttle};
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+        net::{
+            tcp::{OwnedReadHalf, OwnedWriteHalf},
+            TcpStream,
+        },
     };
 
-    // Handle new messages from the server
-    let to_user = async move {
-        while let Some(message) = from_chat_room.receiver.recv().await {
-            match message {
-                FromChatRoomMessage::Join(username) => writer.send_join_message(&username).await?,
-                FromChatRoomMessage::Leave(username) => writer.send_left_message(&username).await?,
-                FromChatRoomMessage::ChatMessage(from, message) => {
-                    writer.send_message(&from, &message).await?
-                }
-            }
-        }
-
-        // the chat room has terminated the client
-        // we don't need to notify the user and can let the socket terminate
+    use super::{accept, bind};
+
+    // connects to the listener and joins the room, returns once the user list was received
+    async fn join(
+        addr: SocketAddr,
+        username: &str,
+    ) -> (Lines<BufReader<OwnedReadHalf>>, OwnedWriteHalf) {
+        let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+        let mut lines = BufReader::new(reader).lines();
+        lines.next_line().await.unwrap(); // welcome
+        writer
+            .write_all(format!("{}\n", username).as_bytes())
+            .await
+            .unwrap();
+        lines.next_line().await.unwrap(); // the user list
+        (lines, writer)
+    }
 
-        Ok::<(), anyhow::Error>(())
-    };
+    #[tokio::test]
+    async fn chat_across_listeners() {
+        let throttle = Arc::new(Throttle::new(Limits::default()));
+        let chatroom = ChatRoom::create(SettingsStore::default(), None);
+
+        let mut addrs = Vec::new();
+        for label in ["public", "internal"] {
+            let listener = bind("127.0.0.1:0").await.unwrap();
+            addrs.push(listener.local_addr().unwrap());
+            tokio::spawn(accept(
+                label.into(),
+                listener,
+                throttle.clone(),
+                chatroom.clone(),
+                false,
+            ));
+        }
 
-    // Terminate once any of the streams reaches EOF
-    tokio::select! {
-        _ = from_user => {}
-        _ = to_user => {}
-    };
+        let (mut alice, mut alice_writer) = join(addrs[0], "alice").await;
+        let (mut bob, mut bob_writer) = join(addrs[1], "bob").await;
+        // the join of bob is announced to alice
+        let joined = alice.next_line().await.unwrap().unwrap();
+        assert!(joined.starts_with("* bob "), "{:?}", joined);
 
-    Ok(())
+        alice_writer.write_all(b"hi bob\n").await.unwrap();
+        assert_eq!(bob.next_line().await.unwrap().unwrap(), "[alice] hi bob");
+        bob_writer.write_all(b"hi alice\n").await.unwrap();
+        assert_eq!(alice.next_line().await.unwrap().unwrap(), "[bob] hi alice");
+    }
 }
@@ -1,19 +1,39 @@
+use std::time::Duration;
+
 use tokio::sync::{mpsc, oneshot};
 
 // back pressure measurements
 pub const MESSAGE_BUFFER_COUNT: usize = 100;
+// a user whose buffer stays full for longer than this is evicted from the room,
+// until then the broadcasts that don't fit in its buffer are queued up by the room
+pub const SLOW_USER_TIMEOUT: Duration = Duration::from_secs(5);
+// a user is evicted right away once the room has queued up this many broadcasts for it
+pub const MAX_OVERFLOW_COUNT: usize = 10 * MESSAGE_BUFFER_COUNT;
+
+// a connection behind a load balancer that hasn't sent its proxy header by then is dropped
+pub const PROXY_HEADER_TIMEOUT: Duration = Duration::from_secs(5);
 
 pub const SYSTEM_MESSAGE_PREFIX: char = '*';
 pub const MAX_USERNAME_SIZE: usize = 16;
 pub const MAX_MESSAGE_SIZE: usize = 1000;
 
+// a message starting with this command is broadcasted as an action of the sender
+pub const EMOTE_COMMAND: &str = "/me ";
+
+/// Identifies a user for as long as they are connected,
+/// unlike their username which a later user may take once they are gone
+pub type UserId = u64;
+
 pub struct Join {
+    pub id: UserId,
     pub username: String,
     pub response: oneshot::Sender<Result<JoinSuccess, JoinError>>,
 }
 
 pub struct JoinSuccess {
     pub userlist: Vec<String>,
+    // the most recent messages of the room, oldest first
+    pub history: Vec<FromChatRoomMessage>,
     pub rx: FromChatRoom,
 }
 
@@ -21,27 +41,44 @@ pub struct JoinSuccess {
 pub enum JoinError {
     #[error("The username \"{0}\" is already in use!")]
     BadUsername(String),
+
+    #[error("The username \"{0}\" is banned from the room")]
+    Banned(String),
+
+    #[error("The room is full")]
+    RoomFull,
 }
 
 #[derive(Debug, Clone)]
 pub struct ChatMessage {
-    pub from: String,
+    pub from: UserId,
     pub text: String,
 }
 
 #[derive(Debug, Clone)]
 pub struct Leave {
-    pub username: String,
+    pub id: UserId,
 }
 
 pub enum ToChatRoomMessage {
     Join(Join),
     ChatMessage(ChatMessage),
+    Emote(ChatMessage),
     Leave(Leave),
+    Admin(AdminCommand),
+}
+
+/// A message the room emitted, numbered in the order it was emitted in
+#[derive(Debug, Clone)]
+pub struct Broadcast {
+    pub seq: u64,
+    pub message: FromChatRoomMessage,
 }
 
 pub struct FromChatRoom {
-    pub receiver: mpsc::Receiver<FromChatRoomMessage>,
+    pub(crate) receiver: mpsc::Receiver<Broadcast>,
+    // the sequence number of the last broadcast the user received
+    pub(crate) last_seq: Option<u64>,
 }
 
 #[derive(Debug, Clone)]
@@ -50,4 +87,93 @@ pub enum FromChatRoomMessage {
     Leave(String),
     // Username , Message
     ChatMessage(String, String),
+    // Username , Action
+    Emote(String, String),
+    // a notice from the operators of the room
+    Announcement(String),
+}
+
+// Commands the operator can issue to the room
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdminCommand {
+    Ban(String),
+    Unban(String),
+    // None lifts the limit
+    Capacity(Option<usize>),
+    History(usize),
+}
+
+/// Parses an operator command, one of:
+///
+/// ban <username>, unban <username>, capacity <count|unlimited>, history <size>
+pub fn parse_admin_command(line: &str) -> Option<AdminCommand> {
+    let mut parts = line.split_whitespace();
+    let command = match (parts.next()?, parts.next()?) {
+        ("ban", username) => AdminCommand::Ban(username.into()),
+        ("unban", username) => AdminCommand::Unban(username.into()),
+        ("capacity", "unlimited") => AdminCommand::Capacity(None),
+        ("capacity", count) => AdminCommand::Capacity(Some(count.parse().ok()?)),
+        ("history", size) => AdminCommand::History(size.parse().ok()?),
+        _ => return None,
+    };
+
+    parts.next().is_none().then_some(command)
+}
+
+/// Extracts the action out of an emote message
+///
+/// returns None when the message isn't an emote, or the action is empty
+/// (in which case the message should be treated as a regular message)
+pub fn parse_emote(message: &str) -> Option<&str> {
+    let action = message.strip_prefix(EMOTE_COMMAND)?.trim();
+    if action.is_empty() {
+        return None;
+    }
+
+    Some(action)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_admin_command, parse_emote, AdminCommand};
+
+    #[test]
+    fn check_emote_parsing() {
+        assert_eq!(parse_emote("/me waves"), Some("waves"));
+        assert_eq!(parse_emote("/me  waves at bob "), Some("waves at bob"));
+
+        assert_eq!(parse_emote("/me"), None);
+        assert_eq!(parse_emote("/me   "), None);
+        assert_eq!(parse_emote("/mewaves"), None);
+        assert_eq!(parse_emote("hi /me waves"), None);
+    }
+
+    #[test]
+    fn check_admin_command_parsing() {
+        assert_eq!(
+            parse_admin_command("ban mallory"),
+            Some(AdminCommand::Ban("mallory".into()))
+        );
+        assert_eq!(
+            parse_admin_command(" unban  mallory "),
+            Some(AdminCommand::Unban("mallory".into()))
+        );
+        assert_eq!(
+            parse_admin_command("capacity 10"),
+            Some(AdminCommand::Capacity(Some(10)))
+        );
+        assert_eq!(
+            parse_admin_command("capacity unlimited"),
+            Some(AdminCommand::Capacity(None))
+        );
+        assert_eq!(
+            parse_admin_command("history 20"),
+            Some(AdminCommand::History(20))
+        );
+
+        assert_eq!(parse_admin_command("ban"), None);
+        assert_eq!(parse_admin_command("ban alice bob"), None);
+        assert_eq!(parse_admin_command("capacity -1"), None);
+        assert_eq!(parse_admin_command("kick alice"), None);
+    }
 }
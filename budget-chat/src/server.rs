@@ -0,0 +1,250 @@
+use telemetry::stats::Metered;
+use tokio::{io::AsyncWrite, net::TcpStream};
+
+use crate::{
+    chatroom::{self, ChatRoom},
+    client,
+    protocol::{parse_emote, FromChatRoomMessage, JoinSuccess, PROXY_HEADER_TIMEOUT},
+};
+
+/// Serves a single user until they leave the room or the room terminates them
+pub async fn handle_connection(
+    mut client: TcpStream,
+    chatroom: ChatRoom,
+    proxy_protocol: bool,
+) -> anyhow::Result<()> {
+    // behind a load balancer, the peer is the balancer rather than the user
+    let addr = if proxy_protocol {
+        // a client that never sends its header would hold the connection forever
+        tokio::time::timeout(
+            PROXY_HEADER_TIMEOUT,
+            proxy_protocol::read_client_addr(&mut client),
+        )
+        .await
+        .map_err(|_| anyhow::anyhow!("timed out waiting for the proxy header"))??
+    } else {
+        client.peer_addr()?
+    };
+    tracing::info!("{} has connected", addr);
+
+    let (reader, writer) = tokio::io::split(Metered::new(client));
+    let mut reader = client::Reader::new(reader);
+    let mut writer = client::Writer::new(writer);
+
+    // Register a new user
+    writer.send_welcome_message().await?;
+    let username = reader.read_name().await?;
+    let (
+        chatroom,
+        JoinSuccess {
+            userlist,
+            history,
+            rx: mut from_chat_room,
+        },
+    ) = chatroom
+        .register(username.trim().to_owned())
+        .await
+        .inspect_err(|err| {
+            if let chatroom::ChatRoomError::Join(_) = err {
+                telemetry::stats::error("rejected-join");
+            }
+        })?;
+    telemetry::stats::request("join");
+
+    // Send the user list, followed by the recent messages
+    writer.send_user_list(userlist).await?;
+    for message in history {
+        send_to_user(&mut writer, message).await?;
+    }
+
+    // Handle new messages from the user
+    let from_user = async move {
+        loop {
+            let message = match reader.read_message().await {
+                Ok(message) => message,
+                Err(client::ReaderError::Eof) => break,
+                Err(err) => Err(err)?,
+            };
+
+            let message = message.trim();
+            match parse_emote(message) {
+                Some(action) => {
+                    telemetry::stats::request("emote");
+                    chatroom.send_emote(action.to_owned()).await?
+                }
+                None => {
+                    telemetry::stats::request("message");
+                    chatroom.send_message(message.to_owned()).await?
+                }
+            }
+        }
+
+        // the user has disconnected, leave the room
+        chatroom.leave().await?;
+
+        Ok::<(), anyhow::Error>(())
+    };
+
+    // Handle new messages from the server
+    let to_user = async move {
+        while let Some(message) = from_chat_room.recv().await {
+            send_to_user(&mut writer, message).await?;
+        }
+
+        // the chat room has terminated the client
+        // we don't need to notify the user and can let the socket terminate
+
+        Ok::<(), anyhow::Error>(())
+    };
+
+    // Terminate once any of the streams reaches EOF
+    tokio::select! {
+        _ = from_user => {}
+        _ = to_user => {}
+    };
+
+    Ok(())
+}
+
+async fn send_to_user<W>(
+    writer: &mut client::Writer<W>,
+    message: FromChatRoomMessage,
+) -> tokio::io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    match message {
+        FromChatRoomMessage::Join(username) => writer.send_join_message(&username).await,
+        FromChatRoomMessage::Leave(username) => writer.send_left_message(&username).await,
+        FromChatRoomMessage::ChatMessage(from, message) => {
+            writer.send_message(&from, &message).await
+        }
+        FromChatRoomMessage::Emote(from, action) => writer.send_emote(&from, &action).await,
+        FromChatRoomMessage::Announcement(message) => writer.send_announcement(&message).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::{TcpListener, TcpStream},
+    };
+
+    use crate::{chatroom::ChatRoom, settings::SettingsStore};
+
+    const USERS: usize = 8;
+    const MESSAGES_PER_USER: usize = 100;
+
+    // (sender, index of the message among the sender's messages)
+    type Received = Vec<(usize, usize)>;
+
+    #[tokio::test(start_paused = true)]
+    async fn drop_connections_without_a_proxy_header() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (conn, _) = listener.accept().await.unwrap();
+
+        let chatroom = ChatRoom::create(SettingsStore::default(), None);
+        let handled = super::handle_connection(conn, chatroom, true).await;
+        assert!(handled.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn deliver_concurrent_messages_in_order() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let chatroom = ChatRoom::create(SettingsStore::default(), None);
+        tokio::spawn(async move {
+            loop {
+                let (conn, _) = listener.accept().await.unwrap();
+                tokio::spawn(super::handle_connection(conn, chatroom.clone(), false));
+            }
+        });
+
+        // everyone joins before anyone speaks, so everyone hears all the messages of the others
+        let mut users = Vec::new();
+        for user in 0..USERS {
+            let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+            let mut lines = BufReader::new(reader).lines();
+            lines.next_line().await.unwrap(); // welcome
+            writer
+                .write_all(format!("user{}\n", user).as_bytes())
+                .await
+                .unwrap();
+            lines.next_line().await.unwrap(); // the user list
+            users.push((lines, writer));
+        }
+
+        let mut tasks = Vec::new();
+        for (user, (mut lines, mut writer)) in users.into_iter().enumerate() {
+            tasks.push(tokio::spawn(async move {
+                let send = async {
+                    for idx in 0..MESSAGES_PER_USER {
+                        let message = format!("message {}\n", idx);
+                        writer.write_all(message.as_bytes()).await.unwrap();
+                    }
+                };
+
+                let receive = async {
+                    let mut received = Received::new();
+                    while received.len() < (USERS - 1) * MESSAGES_PER_USER {
+                        let line = lines.next_line().await.unwrap().unwrap();
+                        // skip the join messages of the users that joined later
+                        let Some(chat) = line.strip_prefix("[user") else {
+                            continue;
+                        };
+
+                        let (sender, message) = chat.split_once("] message ").unwrap();
+                        received.push((sender.parse().unwrap(), message.parse().unwrap()));
+                    }
+
+                    received
+                };
+
+                let ((), received) = tokio::join!(send, receive);
+                (user, received)
+            }));
+        }
+
+        let mut received = HashMap::new();
+        for task in tasks {
+            let (user, messages) = task.await.unwrap();
+            received.insert(user, messages);
+        }
+
+        for (user, messages) in received.iter() {
+            // every message of every other user is received once, in the order it was sent
+            for sender in (0..USERS).filter(|sender| sender != user) {
+                let from_sender: Vec<_> = messages
+                    .iter()
+                    .filter(|(from, _)| *from == sender)
+                    .map(|(_, idx)| *idx)
+                    .collect();
+                assert!(
+                    from_sender.iter().copied().eq(0..MESSAGES_PER_USER),
+                    "user{} received the messages of user{} as {:?}",
+                    user,
+                    sender,
+                    from_sender
+                );
+            }
+
+            // and every user receives the messages they have in common in the same order
+            for (other, other_messages) in received.iter() {
+                let common = |messages: &Received| -> Received {
+                    messages
+                        .iter()
+                        .filter(|(from, _)| from != user && from != other)
+                        .copied()
+                        .collect()
+                };
+                assert_eq!(common(messages), common(other_messages));
+            }
+        }
+    }
+}
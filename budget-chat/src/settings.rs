@@ -0,0 +1,124 @@
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(thiserror::Error, Debug)]
+pub enum SettingsError {
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("{0}")]
+    Parse(#[from] toml::de::Error),
+
+    #[error("{0}")]
+    Serialize(#[from] toml::ser::Error),
+}
+
+/// Room level settings that outlive the server
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct RoomSettings {
+    // usernames that aren't allowed to join the room
+    pub banned: BTreeSet<String>,
+    // the maximal number of users in the room, unlimited when unset
+    pub capacity: Option<usize>,
+    // the number of recent messages replayed to users as they join
+    pub history_size: usize,
+}
+
+/// Keeps the room settings in sync with a state file
+#[derive(Debug, Default)]
+pub struct SettingsStore {
+    // settings are only kept in memory when there's no state file
+    path: Option<PathBuf>,
+    settings: RoomSettings,
+}
+
+impl SettingsStore {
+    /// Loads the settings from the state file, a missing file is treated as a fresh room
+    pub fn load(path: PathBuf) -> Result<Self, SettingsError> {
+        let settings = match std::fs::read_to_string(&path) {
+            Ok(content) => toml::from_str(&content)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => RoomSettings::default(),
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(Self {
+            path: Some(path),
+            settings,
+        })
+    }
+
+    pub fn settings(&self) -> &RoomSettings {
+        &self.settings
+    }
+
+    /// Applies a change to the settings, and writes them back to the state file
+    ///
+    /// the change only takes effect once it's written, so a failed write leaves the settings as they were
+    pub fn update(&mut self, change: impl FnOnce(&mut RoomSettings)) -> Result<(), SettingsError> {
+        let mut settings = self.settings.clone();
+        change(&mut settings);
+
+        if let Some(path) = &self.path {
+            save(path, &settings)?;
+        }
+        self.settings = settings;
+
+        Ok(())
+    }
+}
+
+// the settings are written to a temporary file first,
+// so a crash in the middle of a write can't leave a corrupted state file behind
+fn save(path: &Path, settings: &RoomSettings) -> Result<(), SettingsError> {
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, toml::to_string(settings)?)?;
+    std::fs::rename(tmp, path)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RoomSettings, SettingsStore};
+
+    #[test]
+    fn persist_settings_across_loads() {
+        let dir = std::env::temp_dir().join(format!("budget-chat-settings-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("room.toml");
+
+        let mut store = SettingsStore::load(path.clone()).unwrap();
+        assert_eq!(store.settings(), &RoomSettings::default());
+
+        store
+            .update(|settings| {
+                settings.banned.insert("mallory".into());
+                settings.capacity = Some(10);
+                settings.history_size = 5;
+            })
+            .unwrap();
+
+        let reloaded = SettingsStore::load(path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(reloaded.settings(), store.settings());
+    }
+
+    #[test]
+    fn keep_settings_when_the_write_fails() {
+        // the state file can't be written into a dir that doesn't exist
+        let path = std::env::temp_dir()
+            .join(format!("budget-chat-missing-{}", std::process::id()))
+            .join("room.toml");
+        let mut store = SettingsStore::load(path).unwrap();
+
+        let updated = store.update(|settings| settings.capacity = Some(10));
+        assert!(updated.is_err());
+        assert_eq!(store.settings(), &RoomSettings::default());
+    }
+}
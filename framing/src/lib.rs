@@ -0,0 +1,254 @@
+//! Framing utilities shared by the line based servers
+//!
+//! [`LineReader`] stands in for `read_line`: it never buffers more than a single line of a bounded
+//! length, and keeps a partially received line across calls, so a line that arrives in pieces
+//! is read the same no matter how it was split, and reading is safe to cancel.
+//!
+//! by default lines are utf-8, and a last line that isn't terminated by a newline is still returned,
+//! both can be changed with [`LineReader::with_encoding`] and [`LineReader::with_partial_line`].
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+
+#[derive(thiserror::Error, Debug)]
+pub enum FramingError {
+    #[error("{0}")]
+    Io(#[from] tokio::io::Error),
+
+    #[error("The line is longer than {0} bytes")]
+    TooLong(usize),
+
+    #[error("The line is not a valid utf-8 string")]
+    NotUtf8,
+
+    #[error("The line is not an ascii string")]
+    NotAscii,
+
+    #[error("Reached EOF in the middle of a line")]
+    PartialLine,
+}
+
+/// What a line must be encoded as
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    Ascii,
+}
+
+/// What's done with a last line that isn't terminated by a newline
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PartialLine {
+    /// It's returned like any other line
+    #[default]
+    Return,
+    /// It's dropped, as if the stream ended right after the last newline
+    Discard,
+    /// It's an error
+    Reject,
+}
+
+/// Splits a stream into newline terminated lines of a bounded length
+pub struct LineReader<R> {
+    reader: BufReader<R>,
+    max_len: usize,
+    encoding: Encoding,
+    partial_line: PartialLine,
+    // the part of the next line that was already read
+    line: Vec<u8>,
+}
+
+impl<R> LineReader<R>
+where
+    R: AsyncRead + Unpin,
+{
+    /// Lines longer than `max_len` bytes (not counting the newline) are rejected
+    pub fn new(reader: R, max_len: usize) -> Self {
+        Self {
+            reader: BufReader::new(reader),
+            max_len,
+            encoding: Encoding::default(),
+            partial_line: PartialLine::default(),
+            line: Vec::new(),
+        }
+    }
+
+    pub fn with_encoding(self, encoding: Encoding) -> Self {
+        Self { encoding, ..self }
+    }
+
+    pub fn with_partial_line(self, partial_line: PartialLine) -> Self {
+        Self {
+            partial_line,
+            ..self
+        }
+    }
+
+    /// Changes the limit from the next line on, e.g. when a protocol's lines differ in length
+    pub fn set_max_len(&mut self, max_len: usize) {
+        self.max_len = max_len;
+    }
+
+    /// Reads the next line, without the terminating newline
+    ///
+    /// returns None once the stream reaches EOF,
+    /// a last line that isn't terminated by a newline is handled by the partial line policy
+    pub async fn read_line(&mut self) -> Result<Option<String>, FramingError> {
+        loop {
+            let available = self.reader.fill_buf().await?;
+            if available.is_empty() {
+                if self.line.is_empty() {
+                    return Ok(None);
+                }
+
+                return match self.partial_line {
+                    PartialLine::Return => self.take_line(),
+                    PartialLine::Discard => {
+                        self.line.clear();
+                        Ok(None)
+                    }
+                    PartialLine::Reject => {
+                        self.line.clear();
+                        Err(FramingError::PartialLine)
+                    }
+                };
+            }
+
+            let (chunk, complete) = match available.iter().position(|byte| *byte == b'\n') {
+                Some(idx) => (&available[..idx], true),
+                None => (available, false),
+            };
+
+            // a line that is too long is rejected before any more of it is buffered
+            if self.line.len() + chunk.len() > self.max_len {
+                self.line.clear();
+                return Err(FramingError::TooLong(self.max_len));
+            }
+
+            let len = chunk.len();
+            self.line.extend_from_slice(chunk);
+            self.reader.consume(len + complete as usize);
+
+            if complete {
+                return self.take_line();
+            }
+        }
+    }
+
+    /// Whether the next line can be read without waiting for the stream
+    pub fn has_buffered_line(&self) -> bool {
+        self.reader.buffer().contains(&b'\n')
+    }
+
+    /// The buffered stream underneath, reading from it skips the part of the line that was already read
+    pub fn get_mut(&mut self) -> &mut BufReader<R> {
+        &mut self.reader
+    }
+
+    fn take_line(&mut self) -> Result<Option<String>, FramingError> {
+        let line = std::mem::take(&mut self.line);
+        if self.encoding == Encoding::Ascii && !line.is_ascii() {
+            return Err(FramingError::NotAscii);
+        }
+
+        String::from_utf8(line)
+            .map(Some)
+            .map_err(|_| FramingError::NotUtf8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncWriteExt;
+
+    use super::{Encoding, FramingError, LineReader, PartialLine};
+
+    #[tokio::test]
+    async fn split_lines() {
+        let mut reader = LineReader::new(b"foo=bar\nfoo\n\nlast".as_ref(), 10);
+
+        assert!(!reader.has_buffered_line());
+        for expected in ["foo=bar", "foo", "", "last"] {
+            assert_eq!(reader.read_line().await.unwrap(), Some(expected.into()));
+        }
+        assert_eq!(reader.read_line().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn reject_long_lines() {
+        let mut reader = LineReader::new(b"0123456789\n01234567890\n".as_ref(), 10);
+
+        assert_eq!(reader.read_line().await.unwrap(), Some("0123456789".into()));
+        assert!(reader.has_buffered_line());
+        assert!(matches!(
+            reader.read_line().await,
+            Err(FramingError::TooLong(10))
+        ));
+    }
+
+    #[tokio::test]
+    async fn validate_encoding() {
+        let input = b"caf\xc3\xa9\n\xff\n".as_ref();
+
+        let mut reader = LineReader::new(input, 10);
+        assert_eq!(reader.read_line().await.unwrap(), Some("café".into()));
+        assert!(matches!(
+            reader.read_line().await,
+            Err(FramingError::NotUtf8)
+        ));
+
+        let mut reader = LineReader::new(input, 10).with_encoding(Encoding::Ascii);
+        assert!(matches!(
+            reader.read_line().await,
+            Err(FramingError::NotAscii)
+        ));
+    }
+
+    #[tokio::test]
+    async fn apply_partial_line_policy() {
+        let mut reader =
+            LineReader::new(b"hello\nworld".as_ref(), 10).with_partial_line(PartialLine::Discard);
+        assert_eq!(reader.read_line().await.unwrap(), Some("hello".into()));
+        assert_eq!(reader.read_line().await.unwrap(), None);
+
+        let mut reader =
+            LineReader::new(b"hello\nworld".as_ref(), 10).with_partial_line(PartialLine::Reject);
+        assert_eq!(reader.read_line().await.unwrap(), Some("hello".into()));
+        assert!(matches!(
+            reader.read_line().await,
+            Err(FramingError::PartialLine)
+        ));
+
+        // the limit applies from the next line on
+        let mut reader = LineReader::new(b"hello\nworld\n".as_ref(), 10);
+        reader.set_max_len(5);
+        assert_eq!(reader.read_line().await.unwrap(), Some("hello".into()));
+        reader.set_max_len(4);
+        assert!(matches!(
+            reader.read_line().await,
+            Err(FramingError::TooLong(4))
+        ));
+    }
+
+    #[tokio::test]
+    async fn join_split_lines() {
+        let (client, server) = tokio::io::duplex(4);
+        let mut reader = LineReader::new(server, 100);
+
+        let write = async move {
+            let mut client = client;
+            for piece in [&b"hel"[..], b"lo wo", b"rld\nbye", b"\n"] {
+                client.write_all(piece).await.unwrap();
+                tokio::task::yield_now().await;
+            }
+        };
+        let read = async {
+            let first = reader.read_line().await.unwrap();
+            let second = reader.read_line().await.unwrap();
+            (first, second)
+        };
+        let ((), (first, second)) = tokio::join!(write, read);
+
+        assert_eq!(first.as_deref(), Some("hello world"));
+        assert_eq!(second.as_deref(), Some("bye"));
+        assert_eq!(reader.read_line().await.unwrap(), None);
+    }
+}
@@ -0,0 +1,166 @@
+use std::{str::FromStr, sync::Arc};
+
+use anyhow::Context;
+
+use crate::{
+    blueprint::Toy,
+    metrics::{Recorder, ToyList},
+};
+
+/// An application served behind the cipher layer, one line at a time
+pub trait App {
+    /// Handles a line (without its newline), returns the line to respond with (if any)
+    ///
+    /// an error closes the session
+    async fn handle_line(&mut self, line: &str) -> anyhow::Result<Option<String>>;
+}
+
+/// The applications that can be selected by the config
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AppKind {
+    #[default]
+    Toys,
+    Echo,
+    Uppercase,
+}
+
+impl FromStr for AppKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "toys" => Ok(Self::Toys),
+            "echo" => Ok(Self::Echo),
+            "uppercase" => Ok(Self::Uppercase),
+            _ => Err(format!("unknown application: {}", s)),
+        }
+    }
+}
+
+/// Responds to a list of toys with the one to make first
+#[derive(Clone, Default)]
+pub struct Toys {
+    // every decoded list is reported to each of them
+    recorders: Vec<Arc<dyn Recorder>>,
+}
+
+impl Toys {
+    pub fn new(recorders: Vec<Arc<dyn Recorder>>) -> Self {
+        Self { recorders }
+    }
+}
+
+impl App for Toys {
+    async fn handle_line(&mut self, line: &str) -> anyhow::Result<Option<String>> {
+        let toys = line
+            .split(',')
+            .map(|toy| toy.parse::<Toy>())
+            .collect::<Result<Vec<_>, _>>()
+            .context("expected a list of toys")?;
+
+        let count = toys.len();
+        let most_important = toys
+            .into_iter()
+            .max()
+            .context("expected at least 1 toy in the list")?;
+
+        let request = ToyList {
+            toys: count,
+            max_count: most_important.count(),
+            line_len: line.len(),
+        };
+        for recorder in &self.recorders {
+            recorder.record(line, &request);
+        }
+
+        tracing::debug!("returned toy: {:?}", most_important);
+        Ok(Some(most_important.to_string()))
+    }
+}
+
+/// Responds with the line itself
+#[derive(Debug, Default)]
+pub struct Echo;
+
+impl App for Echo {
+    async fn handle_line(&mut self, line: &str) -> anyhow::Result<Option<String>> {
+        Ok(Some(line.to_string()))
+    }
+}
+
+/// Responds with the line in uppercase
+#[derive(Debug, Default)]
+pub struct Uppercase;
+
+impl App for Uppercase {
+    async fn handle_line(&mut self, line: &str) -> anyhow::Result<Option<String>> {
+        Ok(Some(line.to_uppercase()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::metrics::{Recorder, Reservoir, ToyList};
+
+    use super::{App, Echo, Toys, Uppercase};
+
+    #[tokio::test]
+    async fn handle_lines() {
+        assert_eq!(
+            Toys::default().handle_line("4x dog,5x car").await.unwrap(),
+            Some("5x car".into())
+        );
+        assert!(Toys::default().handle_line("a dog").await.is_err());
+        assert_eq!(
+            Echo.handle_line("4x dog").await.unwrap(),
+            Some("4x dog".into())
+        );
+        assert_eq!(
+            Uppercase.handle_line("4x dog").await.unwrap(),
+            Some("4X DOG".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn record_decoded_toy_lists() {
+        let reservoir = Arc::new(Reservoir::new(10));
+        let mut toys = Toys::new(vec![reservoir.clone()]);
+
+        toys.handle_line("4x dog,5x car,3x rat").await.unwrap();
+        // a line that can't be decoded isn't recorded
+        assert!(toys.handle_line("a dog").await.is_err());
+        toys.handle_line("10x big wagon").await.unwrap();
+
+        assert_eq!(
+            reservoir.sample(),
+            (
+                2,
+                vec!["4x dog,5x car,3x rat".into(), "10x big wagon".into()]
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn describe_toy_lists() {
+        struct Last(Mutex<Option<ToyList>>);
+        impl Recorder for Last {
+            fn record(&self, _line: &str, request: &ToyList) {
+                *self.0.lock().unwrap() = Some(*request);
+            }
+        }
+
+        let last = Arc::new(Last(Default::default()));
+        let mut toys = Toys::new(vec![last.clone()]);
+        toys.handle_line("4x dog,15x car,3x rat").await.unwrap();
+        assert_eq!(
+            *last.0.lock().unwrap(),
+            Some(ToyList {
+                toys: 3,
+                max_count: 15,
+                line_len: 21,
+            })
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use std::{num::ParseIntError, str::FromStr};
+use std::{fmt, num::ParseIntError, str::FromStr};
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Toy {
@@ -15,9 +15,15 @@ pub enum ToyParseErr {
     UnknownNumberFormat(#[from] ParseIntError),
 }
 
-impl ToString for Toy {
-    fn to_string(&self) -> String {
-        self.count.to_string() + "x " + &self.text
+impl Toy {
+    pub fn count(&self) -> usize {
+        self.count
+    }
+}
+
+impl fmt::Display for Toy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x {}", self.count, self.text)
     }
 }
 
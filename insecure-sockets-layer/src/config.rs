@@ -0,0 +1,50 @@
+use std::{env, path::PathBuf, str::FromStr, time::Duration};
+
+use crate::app::AppKind;
+
+const DEFAULT_SAMPLE_PATH: &str = "sampled-lines.txt";
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    // sessions are closed once they have exchanged this many bytes
+    pub max_session_bytes: Option<u64>,
+    // sessions are closed once they have been open for this long
+    pub max_session_duration: Option<Duration>,
+    // the application served behind the cipher layer
+    pub app: AppKind,
+    // when set, a sample of this many decoded toy lists is kept,
+    // and written into the sample path when the server is stopped
+    pub sample_size: Option<usize>,
+    pub sample_path: PathBuf,
+    // when set, the metrics are served for prometheus to scrape on this address
+    pub metrics_addr: Option<String>,
+}
+
+impl Config {
+    /// Builds the configuration from the environment,
+    /// every limit is disabled unless its variable is set, and the toys application is served by default
+    pub fn from_env() -> anyhow::Result<Self> {
+        Ok(Self {
+            max_session_bytes: read_var("MAX_SESSION_BYTES")?,
+            max_session_duration: read_var("MAX_SESSION_SECS")?.map(Duration::from_secs_f64),
+            app: read_var("APP")?.unwrap_or_default(),
+            sample_size: read_var("SAMPLE_SIZE")?,
+            sample_path: read_var("SAMPLE_PATH")?.unwrap_or_else(|| DEFAULT_SAMPLE_PATH.into()),
+            metrics_addr: read_var("METRICS_ADDR")?,
+        })
+    }
+}
+
+fn read_var<T>(name: &str) -> anyhow::Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|err| anyhow::anyhow!("bad value for {}: {}", name, err)),
+        Err(_) => Ok(None),
+    }
+}
@@ -0,0 +1,8 @@
+//! The cipher layer and the applications behind the server,
+//! a library of their own so the verifier can run the server in-process
+mod app;
+mod blueprint;
+pub mod config;
+pub mod metrics;
+mod protocol;
+pub mod server;
@@ -1,47 +1,61 @@
-use anyhow::Context;
-use blueprint::Toy;
-use protocol::connection::Connection;
-use tokio::net::{TcpListener, TcpStream};
+use std::sync::Arc;
 
-mod blueprint;
-mod protocol;
+use anyhow::Context;
+use insecure_sockets_layer::{
+    config::Config,
+    metrics::{Events, Recorder, Reservoir},
+    server::Server,
+};
+use throttle::{Limits, Throttle};
+use tokio::net::TcpListener;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    // connect tracing to stdout
-    tracing_subscriber::fmt::init();
+    telemetry::init();
 
+    let config = Arc::new(Config::from_env()?);
+    if let Some(addr) = &config.metrics_addr {
+        if let Err(err) = telemetry::stats::install(addr) {
+            tracing::error!("failed to start the metrics exporter: {}", err);
+        }
+    }
+
+    let throttle = Throttle::new(Limits::from_env()?);
     let listener = TcpListener::bind("0.0.0.0:3600").await?;
-    println!("Server listening on: {}", listener.local_addr().unwrap());
+    tracing::info!("Server listening on: {}", listener.local_addr()?);
+
+    let reservoir = config
+        .sample_size
+        .map(|size| Arc::new(Reservoir::new(size)));
+    let mut recorders: Vec<Arc<dyn Recorder>> = vec![Arc::new(Events)];
+    if let Some(reservoir) = &reservoir {
+        recorders.push(reservoir.clone());
+    }
+    let server = Server::new(config.clone(), recorders);
 
     loop {
-        let (conn, _) = listener.accept().await?;
-        tokio::spawn(handle_connection(conn));
+        let (conn, addr, permit) = tokio::select! {
+            accepted = throttle.accept(&listener) => accepted?,
+            _ = tokio::signal::ctrl_c() => break,
+        };
+
+        tokio::spawn(telemetry::connection(
+            addr,
+            permit.hold(server.clone().handle(conn)),
+        ));
     }
-}
-
-async fn handle_connection(conn: TcpStream) -> anyhow::Result<()> {
-    let mut conn = Connection::new(conn).await?;
-    tracing::debug!("sucessfully exchanged cipher spec, and initialized connection");
-
-    while let Some(line) = conn.read_until(b'\n').await? {
-        let line = String::from_utf8(line).context("data is assumed to be utf-8 encoded")?;
-        tracing::debug!("received line: {}", line);
-
-        let toys = line
-            .split(',')
-            .map(|toy| toy.parse::<Toy>())
-            .collect::<Result<Vec<_>, _>>()
-            .context("expected a list of toys")?;
-
-        let most_important = toys
-            .iter()
-            .max()
-            .context("expected at least 1 toy in the list")?;
 
-        tracing::debug!("returned toy: {:?}", most_important);
-        conn.write_all((most_important.to_string() + "\n").into())
-            .await?;
+    if let Some(reservoir) = reservoir {
+        let (seen, sample) = reservoir.sample();
+        reservoir
+            .save(&config.sample_path)
+            .with_context(|| format!("failed to save the sample into {:?}", config.sample_path))?;
+        tracing::info!(
+            "Saved {} of {} decoded lines into: {:?}",
+            sample.len(),
+            seen,
+            config.sample_path
+        );
     }
 
     Ok(())
@@ -0,0 +1,177 @@
+use std::{
+    path::Path,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// What's known about a single decoded toy-list request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ToyList {
+    // the number of toys in the list
+    pub toys: usize,
+    // the count of the toy that was picked
+    pub max_count: usize,
+    // the length of the raw line, without its newline
+    pub line_len: usize,
+}
+
+/// Observes the requests decoded by the toys application
+pub trait Recorder: Send + Sync {
+    fn record(&self, line: &str, request: &ToyList);
+}
+
+/// Emits every decoded request as a structured tracing event
+#[derive(Debug, Default)]
+pub struct Events;
+
+impl Recorder for Events {
+    fn record(&self, _line: &str, request: &ToyList) {
+        tracing::debug!(
+            toys = request.toys,
+            max_count = request.max_count,
+            line_len = request.line_len,
+            "decoded a toy list"
+        );
+    }
+}
+
+/// Keeps a uniform sample of the decoded lines, so the inputs of the checker can be analyzed offline
+///
+/// every line seen so far has the same chance of being in the sample (reservoir sampling),
+/// no matter how many lines were seen
+#[derive(Debug)]
+pub struct Reservoir {
+    capacity: usize,
+    state: Mutex<ReservoirState>,
+}
+
+#[derive(Debug)]
+struct ReservoirState {
+    seen: u64,
+    lines: Vec<String>,
+    // a xorshift generator, the sample doesn't need to be cryptographically random
+    rng: u64,
+}
+
+impl ReservoirState {
+    fn next_random(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+}
+
+impl Reservoir {
+    pub fn new(capacity: usize) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.as_nanos() as u64)
+            .unwrap_or_default();
+
+        Self::with_seed(capacity, seed)
+    }
+
+    fn with_seed(capacity: usize, seed: u64) -> Self {
+        Self {
+            capacity,
+            state: Mutex::new(ReservoirState {
+                seen: 0,
+                lines: Vec::with_capacity(capacity),
+                // xorshift is stuck at 0
+                rng: seed | 1,
+            }),
+        }
+    }
+
+    /// Returns the number of lines seen so far, and the sampled lines
+    pub fn sample(&self) -> (u64, Vec<String>) {
+        let state = self.state.lock().unwrap();
+        (state.seen, state.lines.clone())
+    }
+
+    /// Writes the sampled lines into a file, one line per sampled line
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let (_, lines) = self.sample();
+        let mut contents = lines.join("\n");
+        if !contents.is_empty() {
+            contents.push('\n');
+        }
+
+        std::fs::write(path, contents)
+    }
+}
+
+impl Recorder for Reservoir {
+    fn record(&self, line: &str, _request: &ToyList) {
+        let mut state = self.state.lock().unwrap();
+        state.seen += 1;
+
+        if state.lines.len() < self.capacity {
+            state.lines.push(line.to_string());
+            return;
+        }
+
+        // the n-th line replaces a random sampled line with a probability of capacity / n
+        let slot = state.next_random() % state.seen;
+        if let Some(sampled) = state.lines.get_mut(slot as usize) {
+            *sampled = line.to_string();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Recorder, Reservoir, ToyList};
+
+    const REQUEST: ToyList = ToyList {
+        toys: 1,
+        max_count: 1,
+        line_len: 6,
+    };
+
+    #[test]
+    fn fill_the_reservoir_first() {
+        let reservoir = Reservoir::with_seed(3, 7);
+        for line in ["1x dog", "2x cat"] {
+            reservoir.record(line, &REQUEST);
+        }
+        assert_eq!(
+            reservoir.sample(),
+            (2, vec!["1x dog".into(), "2x cat".into()])
+        );
+
+        for count in 3..=1000 {
+            reservoir.record(&format!("{}x dog", count), &REQUEST);
+        }
+        let (seen, lines) = reservoir.sample();
+        assert_eq!(seen, 1000);
+        assert_eq!(lines.len(), 3);
+    }
+
+    #[test]
+    fn sample_uniformly() {
+        // each of the 10 lines should be sampled about a 1/5 of the time
+        let mut sampled = [0u32; 10];
+        for seed in 0..2000 {
+            let reservoir = Reservoir::with_seed(2, seed * 7919);
+            for line in 0..10 {
+                reservoir.record(&line.to_string(), &REQUEST);
+            }
+            for line in reservoir.sample().1 {
+                sampled[line.parse::<usize>().unwrap()] += 1;
+            }
+        }
+
+        for (line, count) in sampled.into_iter().enumerate() {
+            assert!((300..500).contains(&count), "line {}: {}", line, count);
+        }
+    }
+
+    #[test]
+    fn disabled_reservoir() {
+        let reservoir = Reservoir::with_seed(0, 7);
+        reservoir.record("1x dog", &REQUEST);
+        assert_eq!(reservoir.sample(), (1, vec![]));
+    }
+}
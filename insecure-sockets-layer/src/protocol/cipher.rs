@@ -7,94 +7,224 @@ enum Operation {
     XorPos,
     Add(u8),
     AddPos,
+    RotateLeft(u8),
+    SwapNibbles,
+    // xor with the previous plaintext byte of the stream (0 for the first byte)
+    XorPrev,
 }
 
 impl Operation {
+    // whether the result of the operation depends on the position of the byte
+    fn is_positional(&self) -> bool {
+        matches!(self, Self::XorPos | Self::AddPos)
+    }
+
     // Execute the operation
-    fn execute(&self, byte: u8, position: u8) -> u8 {
+    fn execute(&self, byte: u8, position: u8, previous: u8) -> u8 {
         match self {
             Self::ReverseBits => byte.reverse_bits(),
             Self::Xor(number) => byte.bitxor(number),
             Self::XorPos => byte.bitxor(position),
             Self::Add(number) => byte.wrapping_add(*number),
             Self::AddPos => byte.wrapping_add(position),
+            Self::RotateLeft(bits) => byte.rotate_left(*bits as u32),
+            Self::SwapNibbles => byte.rotate_left(4),
+            Self::XorPrev => byte.bitxor(previous),
         }
     }
 
     // Reverse the execution of the operation
     // in other words: reverse_execute(execute(byte)) == byte
-    fn reverse_execute(&self, byte: u8, position: u8) -> u8 {
+    fn reverse_execute(&self, byte: u8, position: u8, previous: u8) -> u8 {
         match self {
             Self::AddPos => byte.wrapping_sub(position),
             Self::Add(number) => byte.wrapping_sub(*number),
+            Self::RotateLeft(bits) => byte.rotate_right(*bits as u32),
             // the rest of the operations are symmetric
-            _ => self.execute(byte, position),
+            _ => self.execute(byte, position, previous),
+        }
+    }
+}
+
+/// Where one direction of the stream is at, the operations depend on it
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Cursor {
+    pub position: u64,
+    // the previous plaintext byte
+    pub previous: u8,
+}
+
+impl Cursor {
+    /// Moves the cursor past the (plaintext) data
+    pub fn advance(&mut self, plaintext: &[u8]) {
+        if let Some(&last) = plaintext.last() {
+            self.position = self.position.wrapping_add(plaintext.len() as u64);
+            self.previous = last;
         }
     }
 }
 
-#[derive(Debug)]
+// the most bytes the substitution tables of a single spec may take,
+// a spec that needs more is applied an operation at a time instead
+const MAX_TABLES_SIZE: usize = 1024 * 1024;
+
+// the positions and previous bytes a spec is checked at to tell whether it's a no-op,
+// between them every bit is set on its own (and all at once)
+const PROBES: [u8; 10] = [0, 1, 2, 4, 8, 16, 32, 64, 128, 255];
+
+// a row per position (mod 256), mapping every byte to its substitution,
+// or a single row for a run that doesn't depend on the position
+type Table = Vec<[u8; 256]>;
+
+struct Tables {
+    encryption: Vec<Table>,
+    // in the order they're applied, which is the reverse of the encryption
+    decryption: Vec<Table>,
+}
+
 pub struct Spec {
     ops: Vec<Operation>,
+    // the ops between every two xor-prev ops only depend on the (position, byte) pair,
+    // so each such run is applied once per pair when the spec is built,
+    // after which encrypting/decrypting is a lookup per run (and an xor between runs).
+    // None when the tables would be too large
+    tables: Option<Tables>,
+}
+
+impl std::fmt::Debug for Spec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Spec").field("ops", &self.ops).finish()
+    }
 }
 
 impl Spec {
-    fn encrypt_byte(&self, byte: u8, position: u8) -> u8 {
-        let mut result = byte;
-        for op in self.ops.iter() {
-            result = op.execute(result, position);
-        }
-        result
+    fn new(ops: Vec<Operation>) -> Self {
+        let tables = Self::build_tables(&ops);
+        Self { ops, tables }
     }
 
-    pub fn encrypt(&self, data: &mut [u8], counter: usize) {
-        let counter = usize_to_mod_u8_field(counter);
+    fn build_tables(ops: &[Operation]) -> Option<Tables> {
+        let runs: Vec<_> = ops.split(|op| *op == Operation::XorPrev).collect();
+        let rows = |run: &[Operation]| match run.iter().any(Operation::is_positional) {
+            true => 256,
+            false => 1,
+        };
+
+        // a table for each direction
+        let size: usize = runs.iter().map(|run| 2 * rows(run) * 256).sum();
+        if size > MAX_TABLES_SIZE {
+            return None;
+        }
+
+        let mut encryption = Vec::with_capacity(runs.len());
+        let mut decryption = Vec::with_capacity(runs.len());
+        for run in &runs {
+            let mut encrypt = vec![[0; 256]; rows(run)];
+            let mut decrypt = vec![[0; 256]; rows(run)];
+            for (position, (encrypt, decrypt)) in
+                (0..=u8::MAX).zip(encrypt.iter_mut().zip(&mut decrypt))
+            {
+                for byte in 0..=u8::MAX {
+                    // a run never holds an xor-prev, so the previous byte doesn't matter
+                    encrypt[byte as usize] = run
+                        .iter()
+                        .fold(byte, |byte, op| op.execute(byte, position, 0));
+                    decrypt[byte as usize] = run
+                        .iter()
+                        .rev()
+                        .fold(byte, |byte, op| op.reverse_execute(byte, position, 0));
+                }
+            }
+
+            encryption.push(encrypt);
+            decryption.push(decrypt);
+        }
+
+        // the decryption reverses the runs as well
+        decryption.reverse();
+        Some(Tables {
+            encryption,
+            decryption,
+        })
+    }
 
-        for (idx, byte) in data.iter_mut().enumerate() {
-            let idx = usize_to_mod_u8_field(idx);
-            let position = counter.wrapping_add(idx);
-            *byte = self.encrypt_byte(*byte, position)
+    fn encrypt_byte(&self, byte: u8, position: u8, previous: u8) -> u8 {
+        match &self.tables {
+            Some(tables) => substitute_byte(&tables.encryption, byte, position, previous),
+            None => self
+                .ops
+                .iter()
+                .fold(byte, |byte, op| op.execute(byte, position, previous)),
         }
     }
 
-    fn decrypt_byte(&self, byte: u8, position: u8) -> u8 {
-        let mut result = byte;
-        for op in self.ops.iter().rev() {
-            result = op.reverse_execute(result, position);
+    fn decrypt_byte(&self, byte: u8, position: u8, previous: u8) -> u8 {
+        match &self.tables {
+            Some(tables) => substitute_byte(&tables.decryption, byte, position, previous),
+            None => self.ops.iter().rev().fold(byte, |byte, op| {
+                op.reverse_execute(byte, position, previous)
+            }),
         }
-        result
     }
 
-    pub fn decrypt(&self, data: &mut [u8], counter: usize) {
-        let counter = usize_to_mod_u8_field(counter);
+    /// Encrypts the data in place, the cursor is where the stream is at before the data
+    pub fn encrypt(&self, data: &mut [u8], cursor: Cursor) {
+        // the operations only see the position mod 256, which is exactly
+        // the truncation to u8, so the stream may grow beyond any usize
+        let mut position = cursor.position as u8;
+        let mut previous = cursor.previous;
+        for byte in data.iter_mut() {
+            let plaintext = *byte;
+            *byte = self.encrypt_byte(plaintext, position, previous);
+            position = position.wrapping_add(1);
+            previous = plaintext;
+        }
+    }
 
-        for (idx, byte) in data.iter_mut().enumerate() {
-            let idx = usize_to_mod_u8_field(idx);
-            let position = counter.wrapping_add(idx);
-            *byte = self.decrypt_byte(*byte, position)
+    /// Decrypts the data in place, the cursor is where the stream is at before the data
+    pub fn decrypt(&self, data: &mut [u8], cursor: Cursor) {
+        let mut position = cursor.position as u8;
+        let mut previous = cursor.previous;
+        for byte in data.iter_mut() {
+            *byte = self.decrypt_byte(*byte, position, previous);
+            position = position.wrapping_add(1);
+            previous = *byte;
         }
     }
 
     // check if the spec is algorithmically equal to no-op
     pub fn is_noop(&self) -> bool {
-        // we know that spec is algorithmically equal to no-op iff for
-        // every byte and position it'll return the byte itself.
-        for byte in 0..u8::MAX {
-            for position in 0..u8::MAX {
-                if byte != self.encrypt_byte(byte, position) {
-                    // we found a pair that proves it's not a no-op
-                    return false;
-                }
-            }
-        }
+        // a spec is a no-op iff it returns every byte as it is, at every position (and after
+        // every previous byte). rather than trying every combination, every byte is tried at the
+        // probed positions, which tell apart any of the ops that depend on the position
+        let positions: &[u8] = match self.ops.iter().any(Operation::is_positional) {
+            true => &PROBES,
+            false => &[0],
+        };
+        let previous_bytes: &[u8] = match self.ops.contains(&Operation::XorPrev) {
+            true => &PROBES,
+            false => &[0],
+        };
 
-        true
+        previous_bytes.iter().all(|&previous| {
+            positions.iter().all(|&position| {
+                (0..=u8::MAX).all(|byte| byte == self.encrypt_byte(byte, position, previous))
+            })
+        })
     }
 }
 
-// converts a usize into the mod_u8 field
-fn usize_to_mod_u8_field(value: usize) -> u8 {
-    (value % (u8::MAX as usize + 1)) as u8
+// applies the runs one after the other, xoring the previous byte in between
+fn substitute_byte(runs: &[Table], byte: u8, position: u8, previous: u8) -> u8 {
+    // a table has either a row per position, or a single row
+    let lookup = |table: &Table, byte: u8| table[position as usize % table.len()][byte as usize];
+
+    let (first, rest) = runs
+        .split_first()
+        .expect("a spec has at least a single run");
+    rest.iter().fold(lookup(first, byte), |byte, table| {
+        lookup(table, byte ^ previous)
+    })
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -125,34 +255,40 @@ impl TryFrom<&[u8]> for Spec {
                     ops.push(Operation::Add(number));
                 }
                 0x05 => ops.push(Operation::AddPos),
+                0x06 => {
+                    let bits = *bytes.next().ok_or(CipherParseErr::UnexpectedEOF(*op))?;
+                    ops.push(Operation::RotateLeft(bits % 8));
+                }
+                0x07 => ops.push(Operation::SwapNibbles),
+                0x08 => ops.push(Operation::XorPrev),
                 _ => return Err(CipherParseErr::UnknownOperation(*op)),
             }
         }
 
-        Ok(Self { ops })
+        Ok(Self::new(ops))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{Operation, Spec};
+    use super::{Cursor, Operation, Spec};
 
     #[test]
     fn parse_spec_correctly() {
-        let raw_spec: &[u8] = b"\x01\x02\x7b\x03\x04\x3e\x05";
+        let raw_spec: &[u8] = b"\x01\x02\x7b\x03\x04\x3e\x05\x06\x0b\x07\x08";
         let parsed_spec: Spec = raw_spec.try_into().unwrap();
-        let expected_spec = Spec {
-            ops: [
-                Operation::ReverseBits,
-                Operation::Xor(0x7b),
-                Operation::XorPos,
-                Operation::Add(0x3e),
-                Operation::AddPos,
-            ]
-            .into(),
-        };
+        let expected_ops = [
+            Operation::ReverseBits,
+            Operation::Xor(0x7b),
+            Operation::XorPos,
+            Operation::Add(0x3e),
+            Operation::AddPos,
+            Operation::RotateLeft(3),
+            Operation::SwapNibbles,
+            Operation::XorPrev,
+        ];
 
-        assert_eq!(parsed_spec.ops, expected_spec.ops);
+        assert_eq!(parsed_spec.ops, expected_ops);
     }
 
     #[test]
@@ -160,7 +296,7 @@ mod tests {
         fn check_encrypt(input: &[u8], spec: &[u8], expected_output: &[u8]) {
             let spec: Spec = spec.try_into().unwrap();
             let mut output = input.to_vec();
-            spec.encrypt(&mut output, 0);
+            spec.encrypt(&mut output, Cursor::default());
             assert_eq!(output, expected_output)
         }
 
@@ -183,7 +319,7 @@ mod tests {
         fn check_decrypt(input: &[u8], spec: &[u8], expected_output: &[u8]) {
             let spec: Spec = spec.try_into().unwrap();
             let mut output = input.to_vec();
-            spec.decrypt(&mut output, 0);
+            spec.decrypt(&mut output, Cursor::default());
             assert_eq!(output, expected_output)
         }
 
@@ -201,6 +337,96 @@ mod tests {
         );
     }
 
+    #[test]
+    fn encrypt_in_chunks_at_any_position() {
+        let spec: Spec = b"\x02\x7b\x05\x01\x03\x08\x06\x03\x07\x08"
+            .as_slice()
+            .try_into()
+            .unwrap();
+        let input: Vec<u8> = (0..=u8::MAX).cycle().take(1000).collect();
+
+        let mut whole = input.clone();
+        spec.encrypt(&mut whole, Cursor::default());
+
+        // the position only matters mod 256, even far beyond what a 32-bit usize can count
+        for position in [0, 256, 1 << 40, u64::MAX - 255] {
+            let start = Cursor {
+                position,
+                previous: 0,
+            };
+
+            let mut chunked = input.clone();
+            let mut cursor = start;
+            for (chunk, plaintext) in chunked.chunks_mut(7).zip(input.chunks(7)) {
+                spec.encrypt(chunk, cursor);
+                cursor.advance(plaintext);
+            }
+            assert_eq!(chunked, whole);
+
+            let mut cursor = start;
+            for chunk in chunked.chunks_mut(13) {
+                spec.decrypt(chunk, cursor);
+                cursor.advance(chunk);
+            }
+            assert_eq!(chunked, input);
+        }
+    }
+
+    #[test]
+    fn reverse_new_operations() {
+        let specs: &[&[u8]] = &[
+            b"\x06\x03",
+            b"\x07",
+            b"\x08",
+            b"\x08\x05\x08",
+            b"\x06\x05\x02\x11\x08\x07\x03",
+        ];
+        let input = b"4x dog,5x car\n3x rat,2x cat\n";
+
+        for &spec in specs {
+            let spec: Spec = spec.try_into().unwrap();
+            let mut output = input.to_vec();
+            spec.encrypt(&mut output, Cursor::default());
+            assert_ne!(output, input);
+            spec.decrypt(&mut output, Cursor::default());
+            assert_eq!(output, input);
+        }
+
+        // xor-prev xors with the previous plaintext byte, and the first byte with 0
+        let spec: Spec = b"\x08".as_slice().try_into().unwrap();
+        let mut output = b"abc".to_vec();
+        spec.encrypt(&mut output, Cursor::default());
+        assert_eq!(output, [b'a', b'b' ^ b'a', b'c' ^ b'b']);
+    }
+
+    #[test]
+    fn apply_large_specs_without_tables() {
+        // 40 runs that depend on the position would take 10MB of tables
+        let raw_spec = b"\x05\x08".repeat(40);
+        let spec = Spec::try_from(&raw_spec[..]).unwrap();
+        assert!(spec.tables.is_none());
+
+        // and are applied the same way an op at a time
+        let mut tabled = Spec::try_from(&raw_spec[..8]).unwrap();
+        assert!(tabled.tables.is_some());
+        let input: Vec<u8> = (0..=u8::MAX).cycle().take(1000).collect();
+        let mut expected = input.clone();
+        tabled.encrypt(&mut expected, Cursor::default());
+
+        tabled.tables = None;
+        let mut output = input.clone();
+        tabled.encrypt(&mut output, Cursor::default());
+        assert_eq!(output, expected);
+        tabled.decrypt(&mut output, Cursor::default());
+        assert_eq!(output, input);
+
+        let mut output = input.clone();
+        spec.encrypt(&mut output, Cursor::default());
+        assert_ne!(output, input);
+        spec.decrypt(&mut output, Cursor::default());
+        assert_eq!(output, input);
+    }
+
     #[test]
     fn noop_detection() {
         let noop_specs: &[&[u8]] = &[
@@ -209,6 +435,10 @@ mod tests {
             b"\x02\xab\x02\xab",
             b"\x01\x01",
             b"\x02\xa0\x02\x0b\x02\xab",
+            b"\x07\x07",
+            b"\x06\x03\x06\x05",
+            b"\x06\x08",
+            b"\x08\x08",
             b"\x02\xa0\x02\x0b\x02\xab\x02\xa0\x02\x0b\x02\xab\x02\xa0\x02\x0b\x02\xab\x02\xa0\x02\x0b\x02\xab\x02\xa0\x02\x0b\x02\xab\x02\xa0\x02\x0b\x02\xab\x02\xa0\x02\x0b\x02\xab\x02\xa0\x02\x0b\x02\xab\x02\xa0\x02\x0b\x02\xab\x02\xa0\x02\x0b\x02\xab\x02\xa0\x02\x0b\x02\xab",
         ];
 
@@ -221,6 +451,10 @@ mod tests {
             b"\x02\x01\x01",
             b"\x05\x05",
             b"\x02\x7b\x05\x01",
+            b"\x07",
+            b"\x06\x01",
+            b"\x08",
+            b"\x08\x02\x01\x08",
             b"\x02\xa0\x02\x0b\x02\xab\x02\x7b\x02\xa0\x02\x0b\x02\xab\x05\x02\xa0\x02\x0b\x02\xab\x01\x02\xa0\x02\x0b\x02\xab",
         ];
 
@@ -1,5 +1,4 @@
-const MAX_LINE_LEN: usize = 5000;
 const MAX_CIPHER_SPEC_LEN: usize = 80;
 
 mod cipher;
-pub mod connection;
+pub mod stream;
@@ -0,0 +1,386 @@
+use std::{
+    io,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use bytes::{Buf, BytesMut};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf},
+    net::TcpStream,
+};
+
+use super::{
+    cipher::{self, CipherParseErr},
+    MAX_CIPHER_SPEC_LEN,
+};
+
+// the most data that is encrypted per write, since a write that's only partially
+// accepted by the inner stream has the rest of it encrypted all over again
+const MAX_WRITE_CHUNK: usize = 8 * 1024;
+
+/// An adapter that obfuscates everything written to the inner stream,
+/// and deobfuscates everything read from it, using the cipher spec of the session
+pub struct CipherStream<S = TcpStream> {
+    stream: S,
+    cipher: cipher::Spec,
+    // data that was read along with the cipher spec, already decrypted
+    leftover: BytesMut,
+    decryption: cipher::Cursor,
+    encryption: cipher::Cursor,
+    // holds the encrypted data of the current write
+    scratch: Vec<u8>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum HandshakeErr {
+    #[error("{0}")]
+    Io(#[from] tokio::io::Error),
+
+    #[error("{0}")]
+    CipherParse(#[from] CipherParseErr),
+
+    #[error("No cipher was provided")]
+    MissingCipher,
+
+    #[error("The cipher spec is too long")]
+    CipherIsTooLong,
+
+    #[error("The cipher must not be equal to a no-op")]
+    NoOpCipher,
+}
+
+impl<S> CipherStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    /// The server side of the handshake, reads the cipher spec sent by the client
+    pub async fn accept(stream: S) -> Result<Self, HandshakeErr> {
+        let mut buffer = BytesMut::new();
+        let mut stream = stream;
+
+        let cipher = read_cipher(&mut stream, &mut buffer).await?;
+        tracing::debug!("received cipher spec: {:?}", cipher);
+        if cipher.is_noop() {
+            tracing::debug!("cipher spec is equal to no-op: {:?}", cipher);
+            return Err(HandshakeErr::NoOpCipher);
+        }
+
+        // decrypt the remianing data in the buffer
+        cipher.decrypt(&mut buffer, cipher::Cursor::default());
+
+        Ok(Self::new(stream, cipher, buffer))
+    }
+
+    /// The client side of the handshake, sends the (raw) cipher spec to the server
+    ///
+    /// note: only the tests act as a client for now
+    #[cfg(test)]
+    pub async fn connect(stream: S, raw_spec: &[u8]) -> Result<Self, HandshakeErr> {
+        if raw_spec.len() >= MAX_CIPHER_SPEC_LEN {
+            return Err(HandshakeErr::CipherIsTooLong);
+        }
+        let cipher: cipher::Spec = raw_spec.try_into()?;
+        if cipher.is_noop() {
+            return Err(HandshakeErr::NoOpCipher);
+        }
+
+        use tokio::io::AsyncWriteExt;
+
+        let mut stream = stream;
+        stream.write_all(raw_spec).await?;
+        stream.write_all(&[0]).await?;
+
+        Ok(Self::new(stream, cipher, BytesMut::new()))
+    }
+
+    fn new(stream: S, cipher: cipher::Spec, leftover: BytesMut) -> Self {
+        let mut decryption = cipher::Cursor::default();
+        decryption.advance(&leftover);
+
+        Self {
+            decryption,
+            leftover,
+            stream,
+            cipher,
+            encryption: cipher::Cursor::default(),
+            scratch: Vec::new(),
+        }
+    }
+
+    /// the number of bytes exchanged over the session so far, in both directions
+    pub fn transferred(&self) -> u64 {
+        self.decryption.position + self.encryption.position
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for CipherStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        if !this.leftover.is_empty() {
+            let len = this.leftover.len().min(buf.remaining());
+            buf.put_slice(&this.leftover[..len]);
+            this.leftover.advance(len);
+            return Poll::Ready(Ok(()));
+        }
+
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut this.stream).poll_read(cx, buf))?;
+
+        // decrypt the new data in the buffer
+        let data = &mut buf.filled_mut()[filled..];
+        this.cipher.decrypt(data, this.decryption);
+        this.decryption.advance(data);
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for CipherStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        // the encryption only depends on the cursor, so whatever the inner stream
+        // doesn't accept now is encrypted the same way when it's written again
+        let len = buf.len().min(MAX_WRITE_CHUNK);
+        this.scratch.clear();
+        this.scratch.extend_from_slice(&buf[..len]);
+        this.cipher.encrypt(&mut this.scratch, this.encryption);
+
+        let written = ready!(Pin::new(&mut this.stream).poll_write(cx, &this.scratch))?;
+        this.encryption.advance(&buf[..written]);
+
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
+
+async fn read_cipher<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    buffer: &mut BytesMut,
+) -> Result<cipher::Spec, HandshakeErr> {
+    // read the cipher spec
+    let mut position = 0;
+    while position < MAX_CIPHER_SPEC_LEN {
+        // read some new data into the buffer
+        let rcount = stream.read_buf(buffer).await?;
+        if rcount == 0 {
+            // reached EOF before reading a cipher
+            return Err(HandshakeErr::MissingCipher);
+        }
+
+        // for every new byte in the buffer
+        let end_idx = buffer.len().min(MAX_CIPHER_SPEC_LEN);
+        for idx in position..end_idx {
+            if buffer[idx] == 0 {
+                // read a cipher into buffer, try to parse and return it
+                let spec: cipher::Spec = buffer[0..idx].try_into()?;
+                // make sure to discard the cipher spec from the buffer
+                buffer.advance(idx + 1);
+
+                return Ok(spec);
+            }
+        }
+        position = end_idx;
+    }
+
+    Err(HandshakeErr::CipherIsTooLong)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::VecDeque,
+        pin::Pin,
+        sync::{Arc, Mutex},
+        task::{Context, Poll},
+    };
+
+    use proptest::prelude::*;
+    use tokio::io::{
+        AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, ReadBuf,
+    };
+
+    use super::CipherStream;
+    use crate::protocol::cipher::{Cursor, Spec};
+
+    // A stream that hands out its input in the given segments,
+    // one segment (at most) per read, and records everything written to it
+    struct SegmentedStream {
+        segments: VecDeque<Vec<u8>>,
+        written: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl AsyncRead for SegmentedStream {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            if let Some(mut segment) = self.segments.pop_front() {
+                let len = segment.len().min(buf.remaining());
+                buf.put_slice(&segment[..len]);
+                if len < segment.len() {
+                    self.segments.push_front(segment.split_off(len));
+                }
+            }
+
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl AsyncWrite for SegmentedStream {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            self.written.lock().unwrap().extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    // a raw cipher spec of 1 to 5 operations, that isn't equal to a no-op
+    fn cipher_spec() -> impl Strategy<Value = Vec<u8>> {
+        let operation = prop_oneof![
+            Just(vec![0x01]),
+            (1..=u8::MAX).prop_map(|number| vec![0x02, number]),
+            Just(vec![0x03]),
+            (1..=u8::MAX).prop_map(|number| vec![0x04, number]),
+            Just(vec![0x05]),
+            (1..8u8).prop_map(|bits| vec![0x06, bits]),
+            Just(vec![0x07]),
+            Just(vec![0x08]),
+        ];
+
+        prop::collection::vec(operation, 1..=5)
+            .prop_map(|ops| ops.concat())
+            .prop_filter("the spec must not be a no-op", |spec| {
+                !Spec::try_from(&spec[..]).unwrap().is_noop()
+            })
+    }
+
+    // a batch of lines, each without its newline
+    fn lines() -> impl Strategy<Value = Vec<String>> {
+        prop::collection::vec("[a-z0-9 ,]{0,64}", 1..16)
+    }
+
+    // splits the data into segments of the given sizes, the leftover ends up in a last segment
+    fn segment(mut data: Vec<u8>, sizes: &[usize]) -> VecDeque<Vec<u8>> {
+        let mut segments = VecDeque::new();
+        for &size in sizes {
+            if data.len() <= size {
+                break;
+            }
+
+            let rest = data.split_off(size);
+            segments.push_back(data);
+            data = rest;
+        }
+        segments.push_back(data);
+        segments
+    }
+
+    proptest! {
+        #[test]
+        fn round_trip_lines_over_segmented_stream(
+            raw_spec in cipher_spec(),
+            lines in lines(),
+            sizes in prop::collection::vec(1..32usize, 0..64),
+        ) {
+            let spec = Spec::try_from(&raw_spec[..]).unwrap();
+
+            // the client sends the spec in the clear, followed by the encrypted lines
+            let mut payload = lines.join("\n").into_bytes();
+            payload.push(b'\n');
+            spec.encrypt(&mut payload, Cursor::default());
+
+            let mut input = raw_spec.clone();
+            input.push(0);
+            input.extend(payload);
+
+            let written = Arc::new(Mutex::new(Vec::new()));
+            let stream = SegmentedStream {
+                segments: segment(input, &sizes),
+                written: written.clone(),
+            };
+
+            let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+            runtime.block_on(async {
+                let mut stream = BufReader::new(CipherStream::accept(stream).await.unwrap());
+                for line in &lines {
+                    let mut received = String::new();
+                    stream.read_line(&mut received).await.unwrap();
+                    prop_assert_eq!(&received, &format!("{}\n", line));
+
+                    // echo every line back, so the encryption counter is tested as well
+                    stream.write_all(received.as_bytes()).await.unwrap();
+                }
+                prop_assert_eq!(stream.read_line(&mut String::new()).await.unwrap(), 0);
+                Ok(())
+            })?;
+
+            let mut response = written.lock().unwrap().clone();
+            spec.decrypt(&mut response, Cursor::default());
+            prop_assert_eq!(response, (lines.join("\n") + "\n").into_bytes());
+        }
+    }
+
+    #[tokio::test]
+    async fn round_trip_over_duplex() {
+        // a tiny buffer, so most writes are only partially accepted
+        let (client, server) = tokio::io::duplex(7);
+        let raw_spec = b"\x02\x7b\x05\x08\x01";
+
+        let server = tokio::spawn(async move {
+            let mut server = CipherStream::accept(server).await.unwrap();
+            let mut received = vec![0; 1000];
+            server.read_exact(&mut received).await.unwrap();
+            server.write_all(&received).await.unwrap();
+            server.shutdown().await.unwrap();
+            server.transferred()
+        });
+
+        let data: Vec<u8> = (0..=u8::MAX).cycle().take(1000).collect();
+        let mut client = CipherStream::connect(client, raw_spec).await.unwrap();
+        client.write_all(&data).await.unwrap();
+
+        let mut echoed = Vec::new();
+        client.read_to_end(&mut echoed).await.unwrap();
+        assert_eq!(echoed, data);
+        assert_eq!(client.transferred(), 2000);
+        assert_eq!(server.await.unwrap(), 2000);
+    }
+
+    #[tokio::test]
+    async fn reject_noop_spec() {
+        let (client, _server) = tokio::io::duplex(64);
+        assert!(CipherStream::connect(client, b"\x02\xab\x02\xab")
+            .await
+            .is_err());
+    }
+}
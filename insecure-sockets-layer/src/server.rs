@@ -0,0 +1,210 @@
+use std::{future::Future, sync::Arc};
+
+use anyhow::Context;
+use telemetry::stats::Metered;
+use tokio::{
+    io::{
+        AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt,
+        BufReader,
+    },
+    time::Instant,
+};
+
+use crate::{
+    app::{self, App, AppKind},
+    config::Config,
+    metrics::Recorder,
+    protocol::stream::CipherStream,
+};
+
+const MAX_LINE_LEN: usize = 5000;
+
+/// Serves the application picked by the config to every connection
+#[derive(Clone)]
+pub struct Server {
+    config: Arc<Config>,
+    toys: app::Toys,
+}
+
+impl Server {
+    /// every list decoded by the toys application is reported to each of the recorders
+    pub fn new(config: Arc<Config>, recorders: Vec<Arc<dyn Recorder>>) -> Self {
+        Self {
+            config,
+            toys: app::Toys::new(recorders),
+        }
+    }
+
+    pub async fn handle<S>(self, conn: S) -> anyhow::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        match self.config.app {
+            AppKind::Toys => handle_connection(conn, self.config, self.toys).await,
+            AppKind::Echo => handle_connection(conn, self.config, app::Echo).await,
+            AppKind::Uppercase => handle_connection(conn, self.config, app::Uppercase).await,
+        }
+    }
+}
+
+// Serves the session until the client disconnects, or it reaches one of its limits
+//
+// a session that reaches a limit is closed right after the response to its last request
+async fn handle_connection<S, A>(conn: S, config: Arc<Config>, mut app: A) -> anyhow::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    A: App,
+{
+    let deadline = config
+        .max_session_duration
+        .map(|duration| Instant::now() + duration);
+
+    let Some(stream) = until(deadline, CipherStream::accept(Metered::new(conn))).await else {
+        tracing::debug!("session timed out before exchanging a cipher spec");
+        return Ok(());
+    };
+    let mut stream =
+        BufReader::new(stream.inspect_err(|_| telemetry::stats::error("cipher-spec"))?);
+    tracing::debug!("sucessfully exchanged cipher spec, and initialized connection");
+
+    loop {
+        let mut line = String::new();
+        let Some(read) = until(deadline, read_line(&mut stream, &mut line)).await else {
+            tracing::debug!("session reached its deadline");
+            break;
+        };
+        if !read? {
+            break;
+        }
+
+        tracing::debug!("received line: {}", line);
+        telemetry::stats::request("line");
+
+        if let Some(response) = app.handle_line(&line).await? {
+            stream.write_all((response + "\n").as_bytes()).await?;
+        }
+
+        if config
+            .max_session_bytes
+            .is_some_and(|max_bytes| stream.get_ref().transferred() >= max_bytes)
+        {
+            tracing::debug!("session reached its byte limit");
+            break;
+        }
+    }
+
+    stream.shutdown().await?;
+    Ok(())
+}
+
+// Reads a line into the buffer (without its newline), returns false on EOF
+async fn read_line<R>(reader: &mut R, line: &mut String) -> anyhow::Result<bool>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let count = (&mut *reader)
+        .take(MAX_LINE_LEN as u64 + 1)
+        .read_line(line)
+        .await
+        .context("data is assumed to be utf-8 encoded")?;
+
+    if count == 0 {
+        return Ok(false);
+    }
+    if count > MAX_LINE_LEN {
+        anyhow::bail!("the line is too long");
+    }
+    if line.pop() != Some('\n') {
+        anyhow::bail!("reached EOF in the middle of a line");
+    }
+
+    Ok(true)
+}
+
+// Runs the future until the deadline, returns None if it didn't complete in time
+async fn until<F: Future>(deadline: Option<Instant>, future: F) -> Option<F::Output> {
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline, future).await.ok(),
+        None => Some(future.await),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+    use crate::{app, config::Config};
+
+    use super::handle_connection;
+
+    // xor(1), applied to every byte regardless of its position
+    const CIPHER_SPEC: &[u8] = b"\x02\x01\x00";
+
+    fn xor(data: &[u8]) -> Vec<u8> {
+        data.iter().map(|byte| byte ^ 1).collect()
+    }
+
+    fn serve(config: Config) -> DuplexStream {
+        let (client, server) = tokio::io::duplex(1024);
+        tokio::spawn(handle_connection(
+            server,
+            Arc::new(config),
+            app::Toys::default(),
+        ));
+        client
+    }
+
+    #[tokio::test]
+    async fn close_after_byte_limit() {
+        let mut client = serve(Config {
+            max_session_bytes: Some(1),
+            ..Default::default()
+        });
+
+        client.write_all(CIPHER_SPEC).await.unwrap();
+        client
+            .write_all(&xor(b"4x dog,5x car\n3x rat,2x cat\n"))
+            .await
+            .unwrap();
+
+        // only the request that crossed the limit is answered
+        let mut response = vec![];
+        client.read_to_end(&mut response).await.unwrap();
+        assert_eq!(xor(&response), b"5x car\n");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn close_after_deadline() {
+        let mut client = serve(Config {
+            max_session_duration: Some(Duration::from_secs(30)),
+            ..Default::default()
+        });
+
+        client.write_all(CIPHER_SPEC).await.unwrap();
+        client.write_all(&xor(b"4x dog,5x car\n")).await.unwrap();
+
+        let mut response = vec![];
+        client.read_to_end(&mut response).await.unwrap();
+        assert_eq!(xor(&response), b"5x car\n");
+    }
+
+    #[tokio::test]
+    async fn serve_another_app() {
+        let (mut client, server) = tokio::io::duplex(1024);
+        tokio::spawn(handle_connection(
+            server,
+            Arc::new(Config::default()),
+            app::Uppercase,
+        ));
+
+        client.write_all(CIPHER_SPEC).await.unwrap();
+        client.write_all(&xor(b"hello\nworld\n")).await.unwrap();
+        client.shutdown().await.unwrap();
+
+        let mut response = vec![];
+        client.read_to_end(&mut response).await.unwrap();
+        assert_eq!(xor(&response), b"HELLO\nWORLD\n");
+    }
+}
@@ -0,0 +1,73 @@
+use std::{collections::HashMap, str::FromStr};
+
+/// The tokens clients authenticate with, every token grants access to the queues of a single tenant
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Tokens {
+    // maps token -> tenant name
+    tenants: HashMap<String, String>,
+}
+
+impl Tokens {
+    /// The tenant the token belongs to, if it's a valid token
+    pub fn tenant(&self, token: &str) -> Option<&str> {
+        self.tenants.get(token).map(String::as_str)
+    }
+}
+
+/// Parses a comma separated list of `token=tenant` pairs,
+/// several tokens may belong to the same tenant
+impl FromStr for Tokens {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut tenants = HashMap::new();
+        for entry in s
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            let Some((token, tenant)) = entry.split_once('=') else {
+                return Err(format!("a token must be followed by its tenant: {}", entry));
+            };
+
+            // the tenant name is the prefix of its queue names, so it can't be ambiguous
+            if token.is_empty() || tenant.is_empty() || tenant.contains('/') {
+                return Err(format!("bad token entry: {}", entry));
+            }
+            if tenants.insert(token.into(), tenant.into()).is_some() {
+                return Err(format!("a token is listed more than once: {}", entry));
+            }
+        }
+
+        if tenants.is_empty() {
+            return Err("no tokens were listed".into());
+        }
+
+        Ok(Self { tenants })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Tokens;
+
+    #[test]
+    fn parse_tokens() {
+        let tokens: Tokens = "s3cret=acme, hunter2=globex,t0ken=acme".parse().unwrap();
+        assert_eq!(tokens.tenant("s3cret"), Some("acme"));
+        assert_eq!(tokens.tenant("hunter2"), Some("globex"));
+        assert_eq!(tokens.tenant("t0ken"), Some("acme"));
+        assert_eq!(tokens.tenant("acme"), None);
+
+        for bad in [
+            "",
+            "s3cret",
+            "=acme",
+            "s3cret=",
+            "s3cret=a/b",
+            "s3cret=a,s3cret=b",
+        ] {
+            assert!(bad.parse::<Tokens>().is_err(), "{:?} was accepted", bad);
+        }
+    }
+}
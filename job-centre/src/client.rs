@@ -1,86 +1,626 @@
 use std::{
-    collections::HashSet,
-    sync::atomic::{self, AtomicU64},
+    sync::{
+        atomic::{self, AtomicU64},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
 
+use tokio::sync::mpsc;
+
 use crate::{
-    jobs::PermissionDeniedErr,
-    request::{Request, Response},
-    SharedJobManager,
+    auth::Tokens,
+    jobs::{self, Job, Namespace, PermissionDeniedErr},
+    notify::NOTIFICATION_BUFFER_SIZE,
+    request::{self, Notification, Request, Response, TakenJob},
+    stats,
 };
 
 static NEW_CLIENT_ID: AtomicU64 = AtomicU64::new(0);
 
+// the connection of a client is closed once it has failed to authenticate this many times
+pub const MAX_FAILED_AUTH_REQUESTS: usize = 3;
+
+#[derive(Debug)]
+enum Auth {
+    // every client shares the same queues
+    Disabled,
+    // nothing but an auth request is served until the client authenticates with one of the tokens
+    Pending {
+        tokens: Arc<Tokens>,
+        // the auth requests the client has failed so far
+        failed: usize,
+    },
+    // the client has failed too many auth requests, its connection should be closed
+    Refused,
+    // the job manager handler is scoped to the namespace of the client's tenant
+    Authenticated,
+}
+
 #[derive(Debug)]
 pub struct Client {
     id: u64,
-    // list of jobs the client is currently working on
-    jobs: HashSet<u64>,
-    job_manager: SharedJobManager,
+    // the manager keeps track of the jobs the client is working on
+    job_manager: jobs::Handler,
+    // set once the client has subscribed to any queue
+    notifications: Option<mpsc::Receiver<Notification>>,
+    auth: Auth,
 }
 
 impl Client {
-    pub fn new(job_manager: SharedJobManager) -> Client {
+    pub fn new(job_manager: jobs::Handler) -> Client {
         Self {
             id: NEW_CLIENT_ID.fetch_add(1, atomic::Ordering::SeqCst),
-            jobs: HashSet::default(),
             job_manager,
+            notifications: None,
+            auth: Auth::Disabled,
+        }
+    }
+
+    /// Requires the client to authenticate with one of the tokens before any other request
+    pub fn require_auth(mut self, tokens: Arc<Tokens>) -> Client {
+        self.auth = Auth::Pending { tokens, failed: 0 };
+        self
+    }
+
+    /// Whether the client has failed too many auth requests,
+    /// in which case its connection should be closed after the last response
+    pub fn is_refused(&self) -> bool {
+        matches!(self.auth, Auth::Refused)
+    }
+
+    /// Resolves with the next notification of the client's subscription
+    ///
+    /// never resolves when the client isn't subscribed to any queue
+    pub async fn notification(&mut self) -> Notification {
+        let Some(notifications) = self.notifications.as_mut() else {
+            return std::future::pending().await;
+        };
+
+        loop {
+            match notifications.recv().await {
+                // the client only subscribes to queues within its namespace,
+                // a queue outside of it is never reported, as its name belongs to another tenant
+                Some(Notification::JobAvailable { queue }) => {
+                    if let Some(queue) = self.job_manager.namespace().unscope(&queue) {
+                        return Notification::JobAvailable {
+                            queue: queue.into(),
+                        };
+                    }
+                }
+                // the manager holds on to the sender as long as the client is subscribed
+                None => return std::future::pending().await,
+            }
         }
     }
 
     pub async fn handle_request(&mut self, request: &str) -> Response {
         let Ok(request) = serde_json::from_str(request) else {
+            telemetry::stats::error("parse");
             return Response::error("failed to parse request".into());
         };
 
+        let request_type = stats::request_type(&request);
+        telemetry::stats::request(request_type);
+        let start = Instant::now();
+        let response = self.execute(request).await;
+        metrics::histogram!(stats::REQUEST_DURATION, "type" => request_type)
+            .record(start.elapsed());
+
+        response
+    }
+
+    async fn execute(&mut self, request: Request) -> Response {
         match request {
+            Request::Auth { token } => self.authenticate(&token),
+            _ if matches!(self.auth, Auth::Pending { .. } | Auth::Refused) => {
+                Response::error("authenticate before any other request".into())
+            }
+            Request::Put { job, .. } | Request::PutRecurring { job, .. }
+                if request::too_deep(&job) =>
+            {
+                Response::error(format!(
+                    "the job is nested more than {} levels deep",
+                    request::MAX_JOB_DEPTH
+                ))
+            }
+            Request::PutBatch { jobs } if jobs.iter().any(|new| request::too_deep(&new.job)) => {
+                Response::error(format!(
+                    "a job is nested more than {} levels deep",
+                    request::MAX_JOB_DEPTH
+                ))
+            }
             Request::Put {
                 queue,
                 job,
                 priority,
+                timeout,
             } => {
-                let job_id = self.job_manager.lock().unwrap().add(queue, job, priority);
+                let timeout = timeout.map(Duration::from_secs);
+                let job_id = self.job_manager.add(queue, job, priority, timeout).await;
                 Response::created(job_id)
             }
-            Request::Delete { id } => match self.job_manager.lock().unwrap().remove(id) {
+            Request::PutBatch { jobs } => {
+                let ids = self.job_manager.add_batch(jobs).await;
+                Response::created_batch(ids)
+            }
+            Request::PutRecurring {
+                queue,
+                job,
+                priority,
+                every_secs,
+            } => {
+                let every = Duration::from_secs(every_secs.get());
+                match self
+                    .job_manager
+                    .add_recurring(queue, job, priority, every)
+                    .await
+                {
+                    Some(recurring_id) => Response::created(recurring_id),
+                    None => Response::error("the interval is too long".into()),
+                }
+            }
+            Request::Cancel { id } => match self.job_manager.cancel(id).await {
                 true => Response::ok(),
                 false => Response::NoJob,
             },
-            Request::Abort { id } => match self.job_manager.lock().unwrap().abort(self.id, id) {
-                Ok(true) => {
-                    self.jobs.remove(&id);
-                    Response::ok()
-                }
+            Request::Delete { id } => match self.job_manager.remove(id).await {
+                true => Response::ok(),
+                false => Response::NoJob,
+            },
+            Request::Abort { id } => match self.job_manager.abort(self.id, id).await {
+                Ok(true) => Response::ok(),
                 Ok(false) => Response::NoJob,
                 Err(PermissionDeniedErr) => {
                     Response::error("you can only abort jobs you're currently working on".into())
                 }
             },
-            Request::Get { queues, wait } => match wait {
+            Request::Get {
+                queues,
+                wait,
+                count: Some(count),
+            } => {
+                let mut jobs = vec![];
+                if wait {
+                    // wait for the first job, and take whatever else is available along with it
+                    jobs.push(self.job_manager.get(self.id, queues.clone()).await);
+                }
+                let count = count.get() - jobs.len();
+                if count > 0 {
+                    jobs.extend(self.job_manager.try_get_many(self.id, queues, count).await);
+                }
+
+                match jobs.is_empty() {
+                    true => Response::NoJob,
+                    false => Response::jobs(jobs.into_iter().map(|job| self.take(job)).collect()),
+                }
+            }
+            Request::Get {
+                queues,
+                wait,
+                count: None,
+            } => match wait {
                 true => {
-                    let fut = self.job_manager.lock().unwrap().get(self.id, &queues);
-                    let job = fut.await;
-                    self.jobs.insert(job.id());
-                    job.into()
+                    let job = self.job_manager.get(self.id, queues).await;
+                    self.take(job).into()
                 }
-                false => match self.job_manager.lock().unwrap().try_get(self.id, &queues) {
-                    Some(job) => {
-                        self.jobs.insert(job.id());
-                        job.into()
+                false => {
+                    let job = self.job_manager.try_get(self.id, queues).await;
+                    match job {
+                        Some(job) => self.take(job).into(),
+                        None => Response::NoJob,
                     }
-                    None => Response::NoJob,
-                },
+                }
             },
+            Request::Subscribe { queues } => {
+                // notifications of a previous subscription are dropped along with its receiver
+                let (tx, rx) = mpsc::channel(NOTIFICATION_BUFFER_SIZE);
+                self.job_manager.subscribe(self.id, queues, tx).await;
+                self.notifications = Some(rx);
+                Response::ok()
+            }
+            Request::Stats => Response::stats(self.job_manager.snapshot().await.jobs),
+            Request::Queues => Response::queues(self.job_manager.snapshot().await.queues),
         }
     }
+
+    // scopes the client to the namespace of the tenant the token belongs to
+    fn authenticate(&mut self, token: &str) -> Response {
+        let tenant = match &mut self.auth {
+            Auth::Disabled => return Response::error("authentication isn't enabled".into()),
+            Auth::Authenticated => return Response::error("already authenticated".into()),
+            Auth::Refused => return Response::error("too many failed auth requests".into()),
+            Auth::Pending { tokens, failed } => match tokens.tenant(token) {
+                Some(tenant) => tenant.to_owned(),
+                None => {
+                    telemetry::stats::error("auth");
+                    *failed += 1;
+                    if *failed >= MAX_FAILED_AUTH_REQUESTS {
+                        self.auth = Auth::Refused;
+                        return Response::error("too many failed auth requests".into());
+                    }
+                    return Response::error("invalid token".into());
+                }
+            },
+        };
+
+        self.job_manager = self.job_manager.scoped(Namespace::tenant(&tenant));
+        self.auth = Auth::Authenticated;
+        Response::ok()
+    }
+
+    // starts working on a job that was retrieved from its queue
+    fn take(&self, job: Job) -> TakenJob {
+        metrics::histogram!(stats::QUEUE_WAIT).record(job.queue_wait());
+        job.into()
+    }
 }
 
 impl Drop for Client {
     fn drop(&mut self) {
         // abort all active jobs
-        let mut job_manager = self.job_manager.lock().unwrap();
-        for job_id in self.jobs.iter() {
-            let _ = job_manager.abort(self.id, *job_id);
+        self.job_manager.disconnect(self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use proptest::prelude::*;
+    use tokio::sync::mpsc;
+
+    use crate::{
+        jobs::Manager,
+        request::{Notification, Response, MAX_JOB_DEPTH},
+    };
+
+    use super::{Client, MAX_FAILED_AUTH_REQUESTS};
+
+    // a response is never bigger than this, on top of the job payloads it carries
+    const RESPONSE_OVERHEAD: usize = 256;
+
+    // requests that are valid, or close to it, so the mutations reach past the parser
+    const SEEDS: &[&str] = &[
+        r#"{"request":"put","queue":"q1","job":{"title":"a"},"pri":123}"#,
+        r#"{"request":"put","queue":"q2","job":[1,2],"pri":18446744073709551615,"timeout":1}"#,
+        r#"{"request":"put-batch","jobs":[{"queue":"q1","job":{},"pri":1},{"queue":"q2","job":null,"pri":0}]}"#,
+        r#"{"request":"get","queues":["q1","q2"]}"#,
+        r#"{"request":"get","queues":["q1","q2"],"count":3}"#,
+        r#"{"request":"get","queues":["q1"],"wait":true}"#,
+        r#"{"request":"get","queues":["q1"],"wait":true,"count":2}"#,
+        r#"{"request":"abort","id":0}"#,
+        r#"{"request":"delete","id":1}"#,
+        r#"{"request":"put-recurring","queue":"q1","job":{},"pri":2,"every_secs":1}"#,
+        r#"{"request":"cancel","id":0}"#,
+        r#"{"request":"subscribe","queues":["q1"]}"#,
+        r#"{"request":"stats"}"#,
+        r#"{"request":"queues"}"#,
+    ];
+
+    // replaces, inserts or removes a few bytes of a seed, the result may not be valid utf-8
+    fn mutated() -> impl Strategy<Value = String> {
+        let mutation = (any::<prop::sample::Index>(), 0u8..3, any::<u8>());
+        (
+            prop::sample::select(SEEDS),
+            prop::collection::vec(mutation, 1..4),
+        )
+            .prop_map(|(seed, mutations)| {
+                let mut bytes = seed.as_bytes().to_vec();
+                for (at, kind, byte) in mutations {
+                    let at = at.index(bytes.len());
+                    match kind {
+                        0 => bytes[at] = byte,
+                        1 => bytes.insert(at, byte),
+                        _ => {
+                            bytes.remove(at);
+                        }
+                    }
+                }
+                String::from_utf8_lossy(&bytes).into_owned()
+            })
+    }
+
+    // a job payload nested up to the given depth
+    fn nested(depth: usize) -> String {
+        "[".repeat(depth) + &"]".repeat(depth)
+    }
+
+    fn line() -> impl Strategy<Value = String> {
+        prop_oneof![
+            ".{0,64}",
+            prop::sample::select(SEEDS).prop_map(String::from),
+            mutated(),
+            (any::<u64>(), 0..200usize).prop_map(|(priority, depth)| format!(
+                r#"{{"request":"put","queue":"q1","job":{},"pri":{}}}"#,
+                nested(depth),
+                priority
+            )),
+        ]
+    }
+
+    // every response has to match the schema of the protocol
+    fn check_schema(response: &Response) -> Result<(), TestCaseError> {
+        let value = serde_json::to_value(response).unwrap();
+        let object = value.as_object().unwrap();
+        let allowed: &[&str] = match object["status"].as_str().unwrap() {
+            "ok" => &[
+                "status", "id", "queue", "job", "pri", "ids", "jobs", "stats", "queues",
+            ],
+            "error" => {
+                prop_assert!(object["error"].is_string());
+                &["status", "error"]
+            }
+            "no-job" => &["status"],
+            status => return Err(TestCaseError::fail(format!("bad status {}", status))),
+        };
+        for key in object.keys() {
+            prop_assert!(allowed.contains(&key.as_str()), "unexpected {}", key);
+        }
+
+        for field in ["id", "pri"] {
+            prop_assert!(object.get(field).is_none_or(|v| v.is_null() || v.is_u64()));
+        }
+        if let Some(jobs) = object.get("jobs") {
+            for job in jobs.as_array().unwrap() {
+                prop_assert!(job["id"].is_u64() && job["pri"].is_u64());
+                prop_assert!(job["queue"].is_string());
+            }
+        }
+
+        // and has to be read back by any client that follows the schema
+        let line = serde_json::to_string(response).unwrap();
+        prop_assert!(serde_json::from_str::<Response>(&line).is_ok());
+        Ok(())
+    }
+
+    fn serve(lines: &[String]) -> Result<(), TestCaseError> {
+        // time is paused, so a waiting get times out as soon as there is nothing else to do
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .start_paused(true)
+            .build()
+            .unwrap();
+
+        runtime.block_on(async {
+            let handler = Manager::default().start();
+            let mut client = Client::new(handler.clone());
+
+            let mut submitted = 0;
+            for line in lines {
+                submitted += line.len();
+                let request = client.handle_request(line);
+                let Ok(response) = tokio::time::timeout(Duration::from_secs(1), request).await
+                else {
+                    continue;
+                };
+
+                check_schema(&response)?;
+                // nothing is stored beyond what the client has submitted itself
+                let size = serde_json::to_string(&response).unwrap().len();
+                prop_assert!(size <= RESPONSE_OVERHEAD * 8 + submitted * 2);
+            }
+
+            let snapshot = handler.snapshot().await;
+            prop_assert!(snapshot.jobs.total <= lines.len() * 2);
+            Ok(())
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn survive_arbitrary_requests(lines in prop::collection::vec(line(), 1..16)) {
+            serve(&lines)?;
         }
     }
+
+    #[test]
+    fn reject_deeply_nested_jobs() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        runtime.block_on(async {
+            let mut client = Client::new(Manager::default().start());
+            let put = |job: &str| {
+                format!(
+                    r#"{{"request":"put","queue":"q1","job":{},"pri":{}}}"#,
+                    job,
+                    u64::MAX
+                )
+            };
+
+            let response = client.handle_request(&put(&nested(MAX_JOB_DEPTH))).await;
+            assert!(matches!(response, Response::Ok { id: Some(_), .. }));
+
+            // a job that's nested deeper couldn't be sent back to a client that uses serde_json
+            let response = client
+                .handle_request(&put(&nested(MAX_JOB_DEPTH + 1)))
+                .await;
+            assert!(matches!(response, Response::Error { .. }));
+
+            // the parser gives up long before the stack is exhausted
+            let response = client.handle_request(&put(&nested(100_000))).await;
+            assert!(matches!(response, Response::Error { .. }));
+
+            let response = client
+                .handle_request(r#"{"request":"get","queues":["q1"]}"#)
+                .await;
+            let Response::Ok { job, priority, .. } = response else {
+                panic!("expected a job, got {:?}", response);
+            };
+            assert_eq!(priority, Some(u64::MAX));
+            assert_eq!(job.map(|job| job.to_string()), Some(nested(MAX_JOB_DEPTH)));
+        });
+    }
+
+    #[test]
+    fn survive_huge_intervals() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        runtime.block_on(async {
+            let mut client = Client::new(Manager::default().start());
+            let response = client
+                .handle_request(&format!(
+                    r#"{{"request":"put-recurring","queue":"q1","job":{{}},"pri":1,"every_secs":{}}}"#,
+                    u64::MAX
+                ))
+                .await;
+            assert!(matches!(response, Response::Error { .. }));
+
+            let response = client
+                .handle_request(&format!(
+                    r#"{{"request":"put","queue":"q1","job":{{}},"pri":1,"timeout":{}}}"#,
+                    u64::MAX
+                ))
+                .await;
+            let Response::Ok { id: Some(id), .. } = response else {
+                panic!("expected the job to be put, got {:?}", response);
+            };
+
+            // the manager is still alive once the job is handed out
+            let response = client
+                .handle_request(r#"{"request":"get","queues":["q1"]}"#)
+                .await;
+            assert!(matches!(response, Response::Ok { id: Some(got), .. } if got == id));
+            let response = client.handle_request(r#"{"request":"stats"}"#).await;
+            assert!(matches!(response, Response::Ok { stats: Some(_), .. }));
+        });
+    }
+
+    #[test]
+    fn authenticate_before_requests() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        runtime.block_on(async {
+            let manager = Manager::default().start();
+            let tokens = Arc::new("s3cret=acme,hunter2=globex".parse().unwrap());
+            let mut acme = Client::new(manager.clone()).require_auth(Arc::clone(&tokens));
+            let mut globex = Client::new(manager.clone()).require_auth(tokens);
+
+            let put = r#"{"request":"put","queue":"q1","job":{},"pri":1}"#;
+            let get = r#"{"request":"get","queues":["q1"]}"#;
+            let response = acme.handle_request(put).await;
+            assert!(matches!(response, Response::Error { .. }));
+            let response = acme
+                .handle_request(r#"{"request":"auth","token":"hunter3"}"#)
+                .await;
+            assert!(matches!(response, Response::Error { .. }));
+
+            for (client, token) in [(&mut acme, "s3cret"), (&mut globex, "hunter2")] {
+                let auth = format!(r#"{{"request":"auth","token":"{}"}}"#, token);
+                assert_eq!(client.handle_request(&auth).await, Response::ok());
+                // a client can't switch to another tenant
+                let response = client.handle_request(&auth).await;
+                assert!(matches!(response, Response::Error { .. }));
+            }
+
+            let subscribe = r#"{"request":"subscribe","queues":["q1"]}"#;
+            assert_eq!(globex.handle_request(subscribe).await, Response::ok());
+            let Response::Ok { id: Some(id), .. } = acme.handle_request(put).await else {
+                panic!("the job wasn't put");
+            };
+
+            // the queues of the tenants share their names, but nothing else
+            assert_eq!(globex.handle_request(get).await, Response::NoJob);
+            let delete = format!(r#"{{"request":"delete","id":{}}}"#, id);
+            assert_eq!(globex.handle_request(&delete).await, Response::NoJob);
+            assert_eq!(
+                acme.handle_request(get).await,
+                Response::job(id, "q1".into(), serde_json::json!({}), 1)
+            );
+
+            globex.handle_request(put).await;
+            let notification = tokio::time::timeout(Duration::from_secs(1), globex.notification())
+                .await
+                .unwrap();
+            assert_eq!(
+                notification,
+                Notification::JobAvailable { queue: "q1".into() }
+            );
+        });
+    }
+
+    #[test]
+    fn refuse_after_failed_auth_requests() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        runtime.block_on(async {
+            let tokens = Arc::new("s3cret=acme".parse().unwrap());
+            let mut client = Client::new(Manager::default().start()).require_auth(tokens);
+
+            // requests other than auth aren't counted
+            let put = r#"{"request":"put","queue":"q1","job":{},"pri":1}"#;
+            for _ in 0..MAX_FAILED_AUTH_REQUESTS {
+                assert!(matches!(
+                    client.handle_request(put).await,
+                    Response::Error { .. }
+                ));
+            }
+            assert!(!client.is_refused());
+
+            let wrong = r#"{"request":"auth","token":"hunter2"}"#;
+            for _ in 1..MAX_FAILED_AUTH_REQUESTS {
+                assert!(matches!(
+                    client.handle_request(wrong).await,
+                    Response::Error { .. }
+                ));
+                assert!(!client.is_refused());
+            }
+            client.handle_request(wrong).await;
+            assert!(client.is_refused());
+
+            // not even the right token is accepted anymore
+            let right = r#"{"request":"auth","token":"s3cret"}"#;
+            assert!(matches!(
+                client.handle_request(right).await,
+                Response::Error { .. }
+            ));
+            assert!(client.is_refused());
+        });
+    }
+
+    #[test]
+    fn drop_notifications_outside_of_the_namespace() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        runtime.block_on(async {
+            let tokens = Arc::new("s3cret=acme".parse().unwrap());
+            let mut client = Client::new(Manager::default().start()).require_auth(tokens);
+            let auth = r#"{"request":"auth","token":"s3cret"}"#;
+            assert_eq!(client.handle_request(auth).await, Response::ok());
+
+            let (sender, receiver) = mpsc::channel(2);
+            client.notifications = Some(receiver);
+            for queue in ["globex/secret", "acme/q1"] {
+                sender
+                    .send(Notification::JobAvailable {
+                        queue: queue.into(),
+                    })
+                    .await
+                    .unwrap();
+            }
+
+            let notification = tokio::time::timeout(Duration::from_secs(1), client.notification())
+                .await
+                .unwrap();
+            assert_eq!(
+                notification,
+                Notification::JobAvailable { queue: "q1".into() }
+            );
+        });
+    }
 }
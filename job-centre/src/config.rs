@@ -0,0 +1,42 @@
+use std::env;
+
+use crate::{auth::Tokens, jobs::TieBreak};
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    // when set, a read-only JSON dashboard is served on this address
+    pub dashboard_addr: Option<String>,
+    // when set, the job operations are also served over WebSocket on this address
+    pub websocket_addr: Option<String>,
+    // when set, prometheus metrics are served on this address
+    pub metrics_addr: Option<String>,
+    // how jobs of equal priority are ordered, `fifo` or `lifo`
+    pub tie_break: TieBreak,
+    // when set, a client has to authenticate with one of the tokens before any other request,
+    // and only reaches the queues of the token's tenant
+    pub auth_tokens: Option<Tokens>,
+}
+
+impl Config {
+    /// Builds the configuration from the environment,
+    /// falling back to the defaults for any variable that isn't set
+    pub fn from_env() -> Result<Self, String> {
+        Ok(Self {
+            dashboard_addr: env::var("DASHBOARD_ADDR").ok(),
+            websocket_addr: env::var("WEBSOCKET_ADDR").ok(),
+            metrics_addr: env::var("METRICS_ADDR").ok(),
+            tie_break: match env::var("TIE_BREAK") {
+                Ok(value) => value.parse()?,
+                Err(_) => TieBreak::default(),
+            },
+            auth_tokens: match env::var("AUTH_TOKENS") {
+                Ok(value) => Some(
+                    value
+                        .parse()
+                        .map_err(|err| format!("bad value for AUTH_TOKENS: {}", err))?,
+                ),
+                Err(_) => None,
+            },
+        })
+    }
+}
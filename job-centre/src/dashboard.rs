@@ -0,0 +1,117 @@
+use std::{sync::Arc, time::Duration};
+
+use throttle::Throttle;
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+};
+
+use crate::jobs;
+
+// requests are tiny, anything bigger than this isn't meant for us
+const MAX_REQUEST_SIZE: u64 = 8 * 1024;
+// a client that hasn't sent its whole request by then is dropped
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Serves a read-only view of the manager state as JSON over HTTP
+///
+/// GET /stats returns a snapshot of all jobs and queues
+pub async fn serve<A: ToSocketAddrs>(
+    addr: A,
+    throttle: Arc<Throttle>,
+    job_manager: jobs::Handler,
+) -> tokio::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    tracing::info!("Dashboard listening on: {}", listener.local_addr()?);
+
+    loop {
+        let (conn, addr, permit) = throttle.accept(&listener).await?;
+        tokio::spawn(telemetry::connection(
+            addr,
+            permit.hold(handle_request(conn, job_manager.clone())),
+        ));
+    }
+}
+
+async fn handle_request(
+    mut stream: TcpStream,
+    job_manager: jobs::Handler,
+) -> tokio::io::Result<()> {
+    let (reader, mut writer) = stream.split();
+    let mut reader = BufReader::new(reader).take(MAX_REQUEST_SIZE);
+
+    let request_line = tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut reader))
+        .await
+        .map_err(|_| tokio::io::Error::from(tokio::io::ErrorKind::TimedOut))??;
+
+    let mut parts = request_line.split_ascii_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/stats")) => {
+            let snapshot = job_manager.snapshot().await;
+            match serde_json::to_string(&snapshot) {
+                Ok(body) => ("200 OK", body),
+                Err(_) => (
+                    "500 Internal Server Error",
+                    r#"{"error":"failed to serialize the snapshot"}"#.into(),
+                ),
+            }
+        }
+        (Some("GET"), _) => ("404 Not Found", r#"{"error":"not found"}"#.into()),
+        _ => (
+            "405 Method Not Allowed",
+            r#"{"error":"method not allowed"}"#.into(),
+        ),
+    };
+
+    tracing::debug!("dashboard request: {:?} -> {}", request_line.trim(), status);
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    writer.write_all(response.as_bytes()).await?;
+    writer.shutdown().await
+}
+
+// reads the request line, and skips the headers since we don't need any of them
+async fn read_request<R: AsyncBufRead + Unpin>(reader: &mut R) -> tokio::io::Result<String> {
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+
+    loop {
+        let mut header = String::new();
+        let rcount = reader.read_line(&mut header).await?;
+        if rcount == 0 || header.trim().is_empty() {
+            break;
+        }
+    }
+
+    Ok(request_line)
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::AsyncWriteExt,
+        net::{TcpListener, TcpStream},
+    };
+
+    use crate::jobs::{Manager, TieBreak};
+
+    #[tokio::test(start_paused = true)]
+    async fn drop_requests_that_never_end() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (conn, _) = listener.accept().await.unwrap();
+
+        // the request line, but never the empty line that ends the headers
+        client.write_all(b"GET /stats HTTP/1.1\r\n").await.unwrap();
+        let job_manager = Manager::new(TieBreak::Fifo).start();
+        let handled = super::handle_request(conn, job_manager).await;
+        assert_eq!(handled.unwrap_err().kind(), tokio::io::ErrorKind::TimedOut);
+    }
+}
@@ -1,14 +1,26 @@
 use std::{
-    collections::{BTreeSet, HashMap},
-    future::Future,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    future,
     hash::Hash,
-    pin::Pin,
-    sync::{Arc, Mutex},
+    str::FromStr,
+    time::{Duration, Instant},
 };
 
-use tokio::sync::oneshot;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{
+    mpsc::{self, error::TrySendError},
+    oneshot,
+};
+
+use crate::{
+    notify::Subscriptions,
+    request::{NewJob, Notification, TakenJob},
+    stats,
+};
 
-use crate::request::Response;
+// Every request of every client goes through the manager,
+// so the buffer should absorb bursts of requests without holding the clients back
+const MANAGER_BUFFER_SIZE: usize = 1024;
 
 #[derive(Debug, Clone)]
 pub struct Job {
@@ -18,31 +30,130 @@ pub struct Job {
     priority: u64,
     // the id of the client that is currently working on it
     owner: Option<u64>,
+    // the last time the job was put on its queue
+    queued_at: Instant,
+    // a logical timestamp that grows with every submitted job,
+    // aborted jobs keep their original submission time
+    submitted: u64,
+    // how long a client may work on the job before it's put back on its queue
+    timeout: Option<Duration>,
+    // when the job is put back on its queue, set while a client with a timeout works on it
+    deadline: Option<Instant>,
 }
 
-impl From<Job> for Response {
+impl From<Job> for TakenJob {
     fn from(value: Job) -> Self {
-        Self::job(value.id, value.queue, value.job, value.priority)
+        Self {
+            id: value.id,
+            queue: value.queue,
+            job: value.job,
+            priority: value.priority,
+        }
     }
 }
 
 impl Job {
-    pub fn id(&self) -> u64 {
-        self.id
+    /// The time the job has spent on its queue before it was retrieved
+    pub fn queue_wait(&self) -> Duration {
+        self.queued_at.elapsed()
+    }
+}
+
+/// How jobs of equal priority are ordered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TieBreak {
+    /// The earliest submitted job is handed out first
+    Fifo,
+    /// The latest submitted job is handed out first
+    #[default]
+    Lifo,
+}
+
+impl TieBreak {
+    // the queued job with the highest rank (among those with the highest priority) is handed out first
+    fn rank(&self, submitted: u64) -> u64 {
+        match self {
+            Self::Fifo => u64::MAX - submitted,
+            Self::Lifo => submitted,
+        }
+    }
+}
+
+impl FromStr for TieBreak {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "fifo" => Ok(Self::Fifo),
+            "lifo" => Ok(Self::Lifo),
+            _ => Err(format!("unknown tie-break policy: {}", s)),
+        }
+    }
+}
+
+/// The queues a client can reach, so tenants can share the manager without seeing each other's jobs
+///
+/// the queues of a tenant are kept under the tenant's name followed by a slash,
+/// while the shared namespace reaches every queue by its own name
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Namespace {
+    // every queue name within the namespace starts with it
+    prefix: String,
+}
+
+impl Namespace {
+    /// The namespace of a tenant, the name must not contain a slash
+    pub fn tenant(name: &str) -> Self {
+        Self {
+            prefix: format!("{}/", name),
+        }
+    }
+
+    // the name the manager keeps a queue of the namespace under
+    fn scope(&self, queue: String) -> String {
+        match self.prefix.is_empty() {
+            true => queue,
+            false => format!("{}{}", self.prefix, queue),
+        }
+    }
+
+    /// The name of a queue within the namespace, None for a queue outside of it
+    pub fn unscope<'a>(&self, queue: &'a str) -> Option<&'a str> {
+        queue.strip_prefix(&self.prefix)
     }
 }
 
-type SharedJobSender = Arc<Mutex<Option<oneshot::Sender<Job>>>>;
+// The position of a job within its queue: (priority, rank, job_id)
+type QueueKey = (u64, u64, u64);
 
 // A stab for a queue structure in the state
 // a queue can either have pending jobs or waiting clients
 #[derive(Debug)]
 enum QueueStab {
-    // set of (priority, job_id)
-    Jobs(BTreeSet<(u64, u64)>),
+    // the last key is the next job to be handed out
+    Jobs(BTreeSet<QueueKey>),
+
+    // list of wait ids, a client that waits on several queues is registered on each of them
+    // until it's handed a job or disconnects
+    Clients(Vec<u64>),
+}
 
-    // list of oneshot channels that contain a list of (waiting_client_id, oneshot::sender<job>)
-    Clients(Vec<(u64, SharedJobSender)>),
+// A template that a fresh job is made of every interval, until it's cancelled
+#[derive(Debug)]
+struct Recurring {
+    queue: String,
+    job: serde_json::Value,
+    priority: u64,
+    every: Duration,
+    next_run: Instant,
+}
+
+// A client that waits for a job on any of a list of queues
+#[derive(Debug)]
+struct Waiter {
+    client: u64,
+    sender: oneshot::Sender<Job>,
+    queues: Vec<String>,
 }
 
 #[derive(Debug, Default)]
@@ -50,33 +161,166 @@ pub struct Manager {
     // maps job_id -> Job
     jobs: HashMap<u64, Job>,
     new_job_id: u64,
+    next_submission: u64,
+    tie_break: TieBreak,
 
     // Maps queue_name -> queue_stab
     queues: HashMap<String, QueueStab>,
+    // clients that are told when a job becomes available on a queue
+    subscriptions: Subscriptions,
+
+    // maps wait_id -> waiter, every wait id listed on a queue is in here
+    waiters: HashMap<u64, Waiter>,
+    new_wait_id: u64,
+    // maps client_id -> the ids of the jobs the client is working on
+    owned: HashMap<u64, HashSet<u64>>,
+    // the jobs in progress that have a timeout, ordered by their deadline
+    deadlines: BTreeSet<(Instant, u64)>,
+    // maps queue_name -> the number of jobs of the queue that were deleted while in progress
+    processed: HashMap<String, u64>,
+
+    // maps recurring_id -> the template of a recurring job
+    recurring: HashMap<u64, Recurring>,
+    new_recurring_id: u64,
+    // the recurring jobs, ordered by the next time a job is made of them
+    runs: BTreeSet<(Instant, u64)>,
 }
 
+#[derive(Debug)]
 pub struct PermissionDeniedErr;
 
+/// A point in time view of the state of the manager
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Snapshot {
+    pub jobs: JobsSnapshot,
+    // ordered by queue name
+    pub queues: Vec<QueueSnapshot>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct JobsSnapshot {
+    pub total: usize,
+    pub pending: usize,
+    pub in_progress: usize,
+    // the number of jobs that were deleted while a client was working on them
+    pub processed: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct QueueSnapshot {
+    pub name: String,
+    pub pending: usize,
+    pub in_progress: usize,
+    pub waiting_clients: usize,
+    pub processed: u64,
+}
+
+impl Snapshot {
+    /// The part of the snapshot that's within the namespace, the queues are named as the namespace names them
+    pub fn within(self, namespace: &Namespace) -> Self {
+        if namespace == &Namespace::default() {
+            return self;
+        }
+
+        let queues: Vec<_> = self
+            .queues
+            .into_iter()
+            .filter_map(|mut queue| {
+                queue.name = namespace.unscope(&queue.name)?.into();
+                Some(queue)
+            })
+            .collect();
+
+        let pending = queues.iter().map(|queue| queue.pending).sum();
+        let in_progress = queues.iter().map(|queue| queue.in_progress).sum();
+        Self {
+            jobs: JobsSnapshot {
+                total: pending + in_progress,
+                pending,
+                in_progress,
+                processed: queues.iter().map(|queue| queue.processed).sum(),
+            },
+            queues,
+        }
+    }
+}
+
 impl Manager {
+    pub fn new(tie_break: TieBreak) -> Self {
+        Self {
+            tie_break,
+            ..Default::default()
+        }
+    }
+
     /// Add a new job to the manager
     ///
+    /// a job with a timeout is put back on its queue once a client works on it for longer than that.
     /// returns an id that can be used to identified the newly added job
-    pub fn add(&mut self, queue: String, job: serde_json::Value, priority: u64) -> u64 {
+    pub fn add(
+        &mut self,
+        queue: String,
+        job: serde_json::Value,
+        priority: u64,
+        timeout: Option<Duration>,
+    ) -> u64 {
+        let id = self.insert(queue.clone(), job, priority, timeout);
+        self.add_job_to_queue(id, queue);
+
+        id
+    }
+
+    /// Adds a batch of jobs to the manager at once
+    ///
+    /// the jobs get sequential ids in the order they are listed,
+    /// and none of them is put on its queue before all of them were added.
+    /// returns the ids of the newly added jobs
+    pub fn add_batch(&mut self, jobs: Vec<NewJob>) -> Vec<u64> {
+        let ids: Vec<_> = jobs
+            .into_iter()
+            .map(|new| {
+                let timeout = new.timeout.map(Duration::from_secs);
+                (
+                    self.insert(new.queue.clone(), new.job, new.priority, timeout),
+                    new.queue,
+                )
+            })
+            .collect();
+
+        for (id, queue) in ids.iter().cloned() {
+            self.add_job_to_queue(id, queue);
+        }
+
+        ids.into_iter().map(|(id, _)| id).collect()
+    }
+
+    // creates a job without putting it on its queue
+    fn insert(
+        &mut self,
+        queue: String,
+        job: serde_json::Value,
+        priority: u64,
+        timeout: Option<Duration>,
+    ) -> u64 {
         let id = self.new_job_id;
         self.new_job_id += 1;
+        let submitted = self.next_submission;
+        self.next_submission += 1;
 
-        // create the job & push to queue
         self.jobs.insert(
             id,
             Job {
                 id,
-                queue: queue.clone(),
+                queue,
                 job,
                 priority,
                 owner: None,
+                queued_at: Instant::now(),
+                submitted,
+                timeout,
+                deadline: None,
             },
         );
-        self.add_job_to_queue(id, queue);
 
         id
     }
@@ -92,74 +336,92 @@ impl Manager {
         requester_id: u64,
         queues: &[T],
     ) -> Option<Job> {
-        let mut best_job: Option<(u64, u64)> = None;
+        // the best job is the one with the highest key across all of the requested queues,
+        // so equal priorities are resolved by the tie-break policy even between queues
+        let best_job = queues
+            .iter()
+            .filter_map(|queue| match self.queues.get(queue.as_ref()) {
+                Some(QueueStab::Jobs(set)) => set.last().copied(),
+                _ => None,
+            })
+            .max();
 
-        // for every queue of pending jobs in the list of requested queues
-        for queue in queues {
-            if let Some(QueueStab::Jobs(set)) = self.queues.get(queue.as_ref()) {
-                // compare the job with the highest priority in this queue
-                // to the best job we've found so far
-                match (best_job, set.last()) {
-                    (Some((best_priority, _)), Some((current_priority, current_job_id)))
-                        if *current_priority > best_priority =>
-                    {
-                        // the current job is better than the best one so far
-                        best_job = Some((*current_priority, *current_job_id));
-                    }
-                    (None, Some(item)) => {
-                        // the first job we've found is also the best one so far
-                        best_job = Some(*item);
-                    }
-                    _ => {}
-                }
-            }
-        }
+        let (_, _, job_id) = best_job?;
 
-        best_job.map(|(_, job_id)| {
-            // fetch the job and remove it from the queue
-            let job = self
-                .jobs
-                .get_mut(&job_id)
-                .expect("a job that was found in a queue must exist within the jobs map");
+        // fetch the job and remove it from the queue
+        let job = self
+            .jobs
+            .get_mut(&job_id)
+            .expect("a job that was found in a queue must exist within the jobs map");
 
-            if let QueueStab::Jobs(set) = self
-                .queues
-                .get_mut(&job.queue)
-                .expect("a job must point back to the queue that contains it")
-            {
-                set.remove(&(job.priority, job.id));
+        if let QueueStab::Jobs(set) = self
+            .queues
+            .get_mut(&job.queue)
+            .expect("a job must point back to the queue that contains it")
+        {
+            if set.remove(&queue_key(self.tie_break, job)) {
+                metrics::gauge!(stats::QUEUE_DEPTH).decrement(1);
             }
+        }
 
-            // make sure to update the owner
-            job.owner = Some(requester_id);
+        // make sure to update the owner
+        self.hand_out(job_id, requester_id);
 
-            // this clone will not be updated
-            // and can only be used as a stem for fetching information from this snapshot of the job
-            job.clone()
-        })
+        // this clone will not be updated
+        // and can only be used as a stem for fetching information from this snapshot of the job
+        Some(self.jobs[&job_id].clone())
+    }
+
+    /// Removes up to `count` jobs from a list of queues, highest priority first
+    ///
+    /// the jobs are handed out in the same order repeated calls to `Self::try_get` would
+    /// have handed them out, so the priority order holds across all of the queues
+    pub fn try_get_many<T: AsRef<str> + Hash + Eq>(
+        &mut self,
+        requester_id: u64,
+        queues: &[T],
+        count: usize,
+    ) -> Vec<Job> {
+        std::iter::from_fn(|| self.try_get(requester_id, queues))
+            .take(count)
+            .collect()
     }
 
     /// Works the same way as `Self::try_get`,
-    /// but instead of returning None, will return a future that will resolve once a job is available
+    /// but instead of returning None, the client waits until a job is put on any of the queues
     ///
-    /// if the list of queues is empty, this function will sleep forever
-    pub fn get<T: AsRef<str> + Hash + Eq>(
+    /// the job is sent over the sender, a client that stopped waiting by then is skipped.
+    /// if the list of queues is empty, the sender is dropped right away
+    pub fn wait<T: AsRef<str> + Hash + Eq>(
         &mut self,
         requester_id: u64,
         queues: &[T],
-    ) -> Pin<Box<dyn Future<Output = Job> + 'static + Send>> {
-        if queues.is_empty() {
-            return Box::pin(std::future::pending());
+        sender: oneshot::Sender<Job>,
+    ) {
+        // if there is an available job, hand it out
+        if let Some(job) = self.try_get(requester_id, queues) {
+            if let Err(job) = sender.send(job) {
+                // the client stopped waiting in the meantime
+                let _ = self.abort(requester_id, job.id);
+            }
+            return;
         }
 
-        // if there is an available job, return it
-        if let Some(job) = self.try_get(requester_id, queues) {
-            return Box::pin(async { job });
+        if queues.is_empty() {
+            return;
         }
 
         // no job is available, register to all requested queues, and wait for a new job
-        let (tx, rx) = oneshot::channel();
-        let sender = Arc::new(Mutex::new(Some(tx)));
+        let wait_id = self.new_wait_id;
+        self.new_wait_id += 1;
+        self.waiters.insert(
+            wait_id,
+            Waiter {
+                client: requester_id,
+                sender,
+                queues: queues.iter().map(|queue| queue.as_ref().into()).collect(),
+            },
+        );
 
         // for every requested queue
         for queue in queues {
@@ -175,15 +437,9 @@ impl Manager {
             }
 
             if let QueueStab::Clients(list) = queue {
-                list.push((requester_id, sender.clone()));
+                list.push(wait_id);
             }
         }
-
-        Box::pin(async move {
-            rx.await.expect(
-                "the sender part of a waiting client should never be dropped before resolved",
-            )
-        })
     }
 
     /// Tries to removes a job from the manager
@@ -195,7 +451,16 @@ impl Manager {
         };
 
         if let Some(QueueStab::Jobs(set)) = self.queues.get_mut(&job.queue) {
-            set.remove(&(job.priority, job.id));
+            if set.remove(&queue_key(self.tie_break, &job)) {
+                metrics::gauge!(stats::QUEUE_DEPTH).decrement(1);
+            }
+        }
+        if let Some(owned) = job.owner.and_then(|owner| self.owned.get_mut(&owner)) {
+            owned.remove(&job_id);
+            *self.processed.entry(job.queue.clone()).or_default() += 1;
+        }
+        if let Some(deadline) = job.deadline {
+            self.deadlines.remove(&(deadline, job_id));
         }
 
         true
@@ -216,50 +481,1162 @@ impl Manager {
             return Err(PermissionDeniedErr);
         }
 
+        job.owner = None;
+        if let Some(owned) = self.owned.get_mut(&requester_id) {
+            owned.remove(&job_id);
+        }
+        if let Some(deadline) = job.deadline.take() {
+            self.deadlines.remove(&(deadline, job_id));
+        }
+
         let queue = job.queue.clone();
         self.add_job_to_queue(job_id, queue);
 
         Ok(true)
     }
 
+    /// The next time a job has to be put back on its queue, if any job has a deadline
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.deadlines.first().map(|(deadline, _)| *deadline)
+    }
+
+    /// Puts every job whose deadline has passed back on its queue
+    ///
+    /// returns the ids of the jobs that timed out
+    pub fn expire(&mut self, now: Instant) -> Vec<u64> {
+        let mut expired = Vec::new();
+        while let Some(&(deadline, job_id)) = self.deadlines.first() {
+            if deadline > now {
+                break;
+            }
+
+            let owner = self.jobs[&job_id]
+                .owner
+                .expect("only a job in progress has a deadline");
+            let _ = self.abort(owner, job_id);
+            expired.push(job_id);
+        }
+
+        expired
+    }
+
+    /// Adds a recurring job, a fresh copy of it is put on its queue every interval
+    ///
+    /// the first copy is put once the first interval has passed.
+    /// returns an id that can be used to cancel it, the ids of recurring jobs are apart from
+    /// the ids of the jobs made of them.
+    /// returns None (and the job isn't added) when the interval is out of the range of the clock
+    pub fn add_recurring(
+        &mut self,
+        queue: String,
+        job: serde_json::Value,
+        priority: u64,
+        every: Duration,
+    ) -> Option<u64> {
+        let next_run = Instant::now().checked_add(every)?;
+        let id = self.new_recurring_id;
+        self.new_recurring_id += 1;
+
+        self.recurring.insert(
+            id,
+            Recurring {
+                queue,
+                job,
+                priority,
+                every,
+                next_run,
+            },
+        );
+        self.runs.insert((next_run, id));
+
+        Some(id)
+    }
+
+    /// Stops making jobs of a recurring job, the jobs that were already made are left as they are
+    ///
+    /// returns false if the recurring job does not exist
+    pub fn cancel(&mut self, recurring_id: u64) -> bool {
+        let Some(recurring) = self.recurring.remove(&recurring_id) else {
+            return false;
+        };
+
+        self.runs.remove(&(recurring.next_run, recurring_id));
+        true
+    }
+
+    /// The next time a job has to be made of a recurring job, if there are any
+    pub fn next_run(&self) -> Option<Instant> {
+        self.runs.first().map(|(run, _)| *run)
+    }
+
+    /// Puts a fresh copy of every recurring job that is due on its queue
+    ///
+    /// a recurring job that has missed several runs is only copied once.
+    /// returns the ids of the new jobs
+    pub fn recur(&mut self, now: Instant) -> Vec<u64> {
+        let mut added = Vec::new();
+        while let Some(&(run, recurring_id)) = self.runs.first() {
+            if run > now {
+                break;
+            }
+            self.runs.remove(&(run, recurring_id));
+
+            let recurring = self
+                .recurring
+                .get_mut(&recurring_id)
+                .expect("only an existing recurring job is scheduled");
+            let (queue, job, priority) = (
+                recurring.queue.clone(),
+                recurring.job.clone(),
+                recurring.priority,
+            );
+
+            // a recurring job whose next run is out of the range of the clock is done
+            let mut next_run = Some(recurring.next_run);
+            while let Some(run) = next_run.filter(|run| *run <= now) {
+                next_run = run.checked_add(recurring.every);
+            }
+            match next_run {
+                Some(next_run) => {
+                    recurring.next_run = next_run;
+                    self.runs.insert((next_run, recurring_id));
+                }
+                None => {
+                    self.recurring.remove(&recurring_id);
+                }
+            }
+
+            added.push(self.add(queue, job, priority, None));
+        }
+
+        added
+    }
+
+    /// Subscribes a client to job availability notifications of a list of queues
+    ///
+    /// a notification is sent whenever a job is put on one of the queues (or aborted back to it),
+    /// unless it was handed to a waiting client right away.
+    /// replaces the previous subscription of the client
+    pub fn subscribe(
+        &mut self,
+        requester_id: u64,
+        queues: Vec<String>,
+        sender: mpsc::Sender<Notification>,
+    ) {
+        self.subscriptions.subscribe(requester_id, queues, sender);
+    }
+
+    pub fn unsubscribe(&mut self, requester_id: u64) {
+        self.subscriptions.unsubscribe(requester_id);
+    }
+
+    /// Forgets a client that went away
+    ///
+    /// the jobs it was working on are put back on their queues,
+    /// including a job it was handed just as it stopped waiting for one.
+    /// its waits are taken off their queues and its subscription is dropped as well
+    pub fn disconnect(&mut self, requester_id: u64) {
+        for job_id in self.owned.remove(&requester_id).into_iter().flatten() {
+            let _ = self.abort(requester_id, job_id);
+        }
+
+        let waits: Vec<_> = self
+            .waiters
+            .iter()
+            .filter(|(_, waiter)| waiter.client == requester_id)
+            .map(|(&wait_id, _)| wait_id)
+            .collect();
+        for wait_id in waits {
+            self.remove_waiter(wait_id);
+        }
+
+        self.unsubscribe(requester_id);
+    }
+
+    /// Takes a snapshot of the state of all jobs and queues
+    pub fn snapshot(&self) -> Snapshot {
+        // a queue is listed as long as it has anything to report,
+        // even once it has no pending jobs or waiting clients left
+        let mut queues: BTreeMap<&str, QueueSnapshot> = BTreeMap::new();
+        fn queue<'a, 'b>(
+            queues: &'b mut BTreeMap<&'a str, QueueSnapshot>,
+            name: &'a str,
+        ) -> &'b mut QueueSnapshot {
+            queues.entry(name).or_insert_with(|| QueueSnapshot {
+                name: name.into(),
+                ..Default::default()
+            })
+        }
+
+        for (name, stab) in self.queues.iter() {
+            match stab {
+                QueueStab::Jobs(set) => queue(&mut queues, name).pending = set.len(),
+                QueueStab::Clients(list) => {
+                    // skip clients that stopped waiting but haven't disconnected yet
+                    queue(&mut queues, name).waiting_clients = list
+                        .iter()
+                        .filter(|wait_id| {
+                            self.waiters
+                                .get(wait_id)
+                                .is_some_and(|waiter| !waiter.sender.is_closed())
+                        })
+                        .count()
+                }
+            }
+        }
+        for job in self.jobs.values().filter(|job| job.owner.is_some()) {
+            queue(&mut queues, &job.queue).in_progress += 1;
+        }
+        for (name, processed) in self.processed.iter() {
+            queue(&mut queues, name).processed = *processed;
+        }
+
+        let queues: Vec<_> = queues.into_values().collect();
+        let pending = queues.iter().map(|queue| queue.pending).sum();
+        Snapshot {
+            jobs: JobsSnapshot {
+                total: self.jobs.len(),
+                pending,
+                in_progress: self.jobs.len() - pending,
+                processed: self.processed.values().sum(),
+            },
+            queues,
+        }
+    }
+
     fn add_job_to_queue(&mut self, job_id: u64, queue: String) {
         let Some(job) = self.jobs.get_mut(&job_id) else {
             // ignore jobs that don't exist
             return;
         };
+        job.queued_at = Instant::now();
+
+        // if the queue is a list of waiting clients, try to submit the job to one of the waiting clients
+        while let Some(Waiter { client, sender, .. }) = self.next_waiter(&queue) {
+            let job = self.jobs.get_mut(&job_id).expect("the job was found above");
 
-        // fetch the queue, and create an empty pending jobs queue if necessary
-        let queue = self
+            // we check that the receiver is open before sending to avoid wasteful clones of 'job'
+            if !sender.is_closed() && sender.send(job.clone()).is_ok() {
+                // successfully submitted the job, update the owner
+                self.hand_out(job_id, client);
+                return;
+            }
+        }
+
+        // no client is waiting on the queue (the waiting clients list is gone by now),
+        // fetch its pending jobs or create an empty pending jobs queue
+        let QueueStab::Jobs(set) = self
             .queues
             .entry(queue)
-            .or_insert(QueueStab::Jobs(BTreeSet::default()));
-
-        match queue {
-            QueueStab::Clients(wait_list) => {
-                // if the queue is a list of waiting clients, try to submit the job to one of the waiting clients
-                while let Some((client, sender)) = wait_list.pop() {
-                    // take ownership of the sender
-                    let sender = sender.lock().unwrap().take();
-                    if let Some(sender) = sender {
-                        // we check that the receiver is open before sending to avoid wasteful clones of 'job'
-                        if !sender.is_closed() && sender.send(job.clone()).is_ok() {
-                            // successfully submitted the job, update the owner
-                            job.owner = Some(client);
-                            return;
+            .or_insert(QueueStab::Jobs(BTreeSet::default()))
+        else {
+            unreachable!("the waiting clients of the queue were all taken off it");
+        };
+
+        let job = &self.jobs[&job_id];
+        if set.insert(queue_key(self.tie_break, job)) {
+            metrics::gauge!(stats::QUEUE_DEPTH).increment(1);
+        }
+        self.subscriptions.notify(&job.queue);
+    }
+
+    // makes the client the owner of a job that was taken off its queue,
+    // and starts counting down its timeout
+    fn hand_out(&mut self, job_id: u64, client: u64) {
+        let job = self
+            .jobs
+            .get_mut(&job_id)
+            .expect("only an existing job can be handed out");
+
+        job.owner = Some(client);
+        self.owned.entry(client).or_default().insert(job_id);
+
+        // a timeout too long to be represented never runs out
+        if let Some(deadline) = job
+            .timeout
+            .and_then(|timeout| Instant::now().checked_add(timeout))
+        {
+            job.deadline = Some(deadline);
+            self.deadlines.insert((deadline, job_id));
+        }
+    }
+
+    // takes the next waiting client off the queue (and off every other queue it waits on)
+    fn next_waiter(&mut self, queue: &str) -> Option<Waiter> {
+        let Some(QueueStab::Clients(wait_list)) = self.queues.get(queue) else {
+            return None;
+        };
+
+        let wait_id = *wait_list.last()?;
+        Some(self.remove_waiter(wait_id))
+    }
+
+    // takes a waiting client off all of its queues,
+    // a queue is dropped along with its last waiting client
+    fn remove_waiter(&mut self, wait_id: u64) -> Waiter {
+        let waiter = self
+            .waiters
+            .remove(&wait_id)
+            .expect("a wait id is only listed while its waiter is registered");
+
+        for queue in &waiter.queues {
+            if let Some(QueueStab::Clients(wait_list)) = self.queues.get_mut(queue) {
+                wait_list.retain(|id| *id != wait_id);
+                if wait_list.is_empty() {
+                    self.queues.remove(queue);
+                }
+            }
+        }
+
+        waiter
+    }
+}
+
+#[derive(Debug)]
+enum Command {
+    Add {
+        queue: String,
+        job: serde_json::Value,
+        priority: u64,
+        timeout: Option<Duration>,
+        response: oneshot::Sender<u64>,
+    },
+    AddBatch {
+        jobs: Vec<NewJob>,
+        response: oneshot::Sender<Vec<u64>>,
+    },
+    AddRecurring {
+        queue: String,
+        job: serde_json::Value,
+        priority: u64,
+        every: Duration,
+        response: oneshot::Sender<Option<u64>>,
+    },
+    Cancel {
+        namespace: Namespace,
+        recurring_id: u64,
+        response: oneshot::Sender<bool>,
+    },
+    TryGet {
+        requester_id: u64,
+        queues: Vec<String>,
+        response: oneshot::Sender<Option<Job>>,
+    },
+    TryGetMany {
+        requester_id: u64,
+        queues: Vec<String>,
+        count: usize,
+        response: oneshot::Sender<Vec<Job>>,
+    },
+    Wait {
+        requester_id: u64,
+        queues: Vec<String>,
+        response: oneshot::Sender<Job>,
+    },
+    Remove {
+        namespace: Namespace,
+        job_id: u64,
+        response: oneshot::Sender<bool>,
+    },
+    Abort {
+        requester_id: u64,
+        job_id: u64,
+        response: oneshot::Sender<Result<bool, PermissionDeniedErr>>,
+    },
+    Subscribe {
+        requester_id: u64,
+        queues: Vec<String>,
+        sender: mpsc::Sender<Notification>,
+    },
+    Disconnect(u64),
+    Snapshot {
+        namespace: Namespace,
+        response: oneshot::Sender<Snapshot>,
+    },
+}
+
+impl Manager {
+    /// Moves the manager into a task of its own, that owns the state of all jobs and queues
+    ///
+    /// returns an handler that can be used to send requests to the manager
+    ///
+    /// note: this function needs to be called from inside a tokio runtime context
+    pub fn start(mut self) -> Handler {
+        let (tx, mut rx) = mpsc::channel(MANAGER_BUFFER_SIZE);
+
+        tokio::spawn(async move {
+            loop {
+                // the jobs that time out are put back on their queues in between commands,
+                // and so are the jobs made of recurring jobs
+                let expired = sleep_until(self.next_deadline());
+                let recurred = sleep_until(self.next_run());
+
+                tokio::select! {
+                    command = rx.recv() => match command {
+                        Some(command) => self.execute(command),
+                        None => return,
+                    },
+                    _ = expired => self.time_out(),
+                    _ = recurred => {
+                        for job_id in self.recur(Instant::now()) {
+                            tracing::debug!("recurring job {} was put on {}", job_id, self.jobs[&job_id].queue);
                         }
                     }
                 }
             }
-            QueueStab::Jobs(set) => {
-                set.insert((job.priority, job.id));
-                return;
+        });
+
+        Handler {
+            sender: tx,
+            namespace: Namespace::default(),
+        }
+    }
+
+    fn time_out(&mut self) {
+        for job_id in self.expire(Instant::now()) {
+            let queue = &self.jobs[&job_id].queue;
+            tracing::debug!("job {} timed out, it's back on {}", job_id, queue);
+            metrics::counter!(stats::TIMED_OUT_JOBS).increment(1);
+        }
+    }
+
+    // a client that went away before it got its response is of no concern here,
+    // unless it was handed a job, which is put back on its queue.
+    // a job (or a recurring job) outside of the namespace of a command is as good as missing
+    fn execute(&mut self, command: Command) {
+        match command {
+            Command::Add {
+                queue,
+                job,
+                priority,
+                timeout,
+                response,
+            } => {
+                let _ = response.send(self.add(queue, job, priority, timeout));
+            }
+            Command::AddBatch { jobs, response } => {
+                let _ = response.send(self.add_batch(jobs));
+            }
+            Command::AddRecurring {
+                queue,
+                job,
+                priority,
+                every,
+                response,
+            } => {
+                let _ = response.send(self.add_recurring(queue, job, priority, every));
+            }
+            Command::Cancel {
+                namespace,
+                recurring_id,
+                response,
+            } => {
+                let within = self
+                    .recurring
+                    .get(&recurring_id)
+                    .is_some_and(|recurring| namespace.unscope(&recurring.queue).is_some());
+                let _ = response.send(within && self.cancel(recurring_id));
+            }
+            Command::TryGet {
+                requester_id,
+                queues,
+                response,
+            } => {
+                if let Err(Some(job)) = response.send(self.try_get(requester_id, &queues)) {
+                    let _ = self.abort(requester_id, job.id);
+                }
+            }
+            Command::TryGetMany {
+                requester_id,
+                queues,
+                count,
+                response,
+            } => {
+                let jobs = self.try_get_many(requester_id, &queues, count);
+                if let Err(jobs) = response.send(jobs) {
+                    for job in jobs {
+                        let _ = self.abort(requester_id, job.id);
+                    }
+                }
+            }
+            Command::Wait {
+                requester_id,
+                queues,
+                response,
+            } => self.wait(requester_id, &queues, response),
+            Command::Remove {
+                namespace,
+                job_id,
+                response,
+            } => {
+                let within = self
+                    .jobs
+                    .get(&job_id)
+                    .is_some_and(|job| namespace.unscope(&job.queue).is_some());
+                let _ = response.send(within && self.remove(job_id));
+            }
+            Command::Abort {
+                requester_id,
+                job_id,
+                response,
+            } => {
+                let _ = response.send(self.abort(requester_id, job_id));
+            }
+            Command::Subscribe {
+                requester_id,
+                queues,
+                sender,
+            } => self.subscribe(requester_id, queues, sender),
+            Command::Disconnect(requester_id) => self.disconnect(requester_id),
+            Command::Snapshot {
+                namespace,
+                response,
+            } => {
+                let _ = response.send(self.snapshot().within(&namespace));
+            }
+        }
+    }
+}
+
+/// A handle to a running manager, see `Manager::start`
+///
+/// every queue the handler names is within its namespace
+#[derive(Debug, Clone)]
+pub struct Handler {
+    sender: mpsc::Sender<Command>,
+    namespace: Namespace,
+}
+
+impl Handler {
+    /// A handler of the same manager, that can only reach the queues of the namespace
+    pub fn scoped(&self, namespace: Namespace) -> Self {
+        Self {
+            sender: self.sender.clone(),
+            namespace,
+        }
+    }
+
+    pub fn namespace(&self) -> &Namespace {
+        &self.namespace
+    }
+
+    /// See `Manager::add`
+    pub async fn add(
+        &self,
+        queue: String,
+        job: serde_json::Value,
+        priority: u64,
+        timeout: Option<Duration>,
+    ) -> u64 {
+        let queue = self.namespace.scope(queue);
+        self.call(|response| Command::Add {
+            queue,
+            job,
+            priority,
+            timeout,
+            response,
+        })
+        .await
+    }
+
+    /// See `Manager::add_batch`
+    pub async fn add_batch(&self, mut jobs: Vec<NewJob>) -> Vec<u64> {
+        for new in jobs.iter_mut() {
+            new.queue = self.namespace.scope(std::mem::take(&mut new.queue));
+        }
+        self.call(|response| Command::AddBatch { jobs, response })
+            .await
+    }
+
+    /// See `Manager::add_recurring`
+    pub async fn add_recurring(
+        &self,
+        queue: String,
+        job: serde_json::Value,
+        priority: u64,
+        every: Duration,
+    ) -> Option<u64> {
+        let queue = self.namespace.scope(queue);
+        self.call(|response| Command::AddRecurring {
+            queue,
+            job,
+            priority,
+            every,
+            response,
+        })
+        .await
+    }
+
+    /// See `Manager::cancel`
+    pub async fn cancel(&self, recurring_id: u64) -> bool {
+        self.call(|response| Command::Cancel {
+            namespace: self.namespace.clone(),
+            recurring_id,
+            response,
+        })
+        .await
+    }
+
+    /// See `Manager::try_get`
+    pub async fn try_get(&self, requester_id: u64, queues: Vec<String>) -> Option<Job> {
+        let queues = self.scope(queues);
+        let job = self
+            .call(|response| Command::TryGet {
+                requester_id,
+                queues,
+                response,
+            })
+            .await;
+
+        job.map(|job| self.unscope(job))
+    }
+
+    /// See `Manager::try_get_many`
+    pub async fn try_get_many(
+        &self,
+        requester_id: u64,
+        queues: Vec<String>,
+        count: usize,
+    ) -> Vec<Job> {
+        let queues = self.scope(queues);
+        let jobs = self
+            .call(|response| Command::TryGetMany {
+                requester_id,
+                queues,
+                count,
+                response,
+            })
+            .await;
+
+        jobs.into_iter().map(|job| self.unscope(job)).collect()
+    }
+
+    /// Works the same way as `Self::try_get`,
+    /// but instead of returning None, will resolve once a job is available
+    ///
+    /// if the list of queues is empty, this function will sleep forever.
+    ///
+    /// cancel safe: a job that's handed out just as the future is dropped
+    /// is put back on its queue once the client disconnects
+    pub async fn get(&self, requester_id: u64, queues: Vec<String>) -> Job {
+        if queues.is_empty() {
+            return std::future::pending().await;
+        }
+
+        let (tx, rx) = oneshot::channel();
+        self.send(Command::Wait {
+            requester_id,
+            queues: self.scope(queues),
+            response: tx,
+        })
+        .await;
+
+        let job = rx
+            .await
+            .expect("the manager only drops the sender of a waiting client once it disconnects");
+        self.unscope(job)
+    }
+
+    /// See `Manager::remove`
+    pub async fn remove(&self, job_id: u64) -> bool {
+        self.call(|response| Command::Remove {
+            namespace: self.namespace.clone(),
+            job_id,
+            response,
+        })
+        .await
+    }
+
+    /// See `Manager::abort`
+    pub async fn abort(&self, requester_id: u64, job_id: u64) -> Result<bool, PermissionDeniedErr> {
+        self.call(|response| Command::Abort {
+            requester_id,
+            job_id,
+            response,
+        })
+        .await
+    }
+
+    /// See `Manager::subscribe`
+    pub async fn subscribe(
+        &self,
+        requester_id: u64,
+        queues: Vec<String>,
+        sender: mpsc::Sender<Notification>,
+    ) {
+        self.send(Command::Subscribe {
+            requester_id,
+            queues: self.scope(queues),
+            sender,
+        })
+        .await
+    }
+
+    /// See `Manager::disconnect`
+    ///
+    /// doesn't wait for the manager, so it can be called while a client is dropped
+    pub fn disconnect(&self, requester_id: u64) {
+        if let Err(TrySendError::Full(command)) =
+            self.sender.try_send(Command::Disconnect(requester_id))
+        {
+            let sender = self.sender.clone();
+            tokio::spawn(async move {
+                let _ = sender.send(command).await;
+            });
+        }
+    }
+
+    /// See `Manager::snapshot`, only the queues within the namespace are listed
+    pub async fn snapshot(&self) -> Snapshot {
+        self.call(|response| Command::Snapshot {
+            namespace: self.namespace.clone(),
+            response,
+        })
+        .await
+    }
+
+    fn scope(&self, queues: Vec<String>) -> Vec<String> {
+        queues
+            .into_iter()
+            .map(|queue| self.namespace.scope(queue))
+            .collect()
+    }
+
+    // names the queue of a job that was handed out as the namespace names it
+    fn unscope(&self, mut job: Job) -> Job {
+        if let Some(queue) = self.namespace.unscope(&job.queue) {
+            job.queue = queue.into();
+        }
+        job
+    }
+
+    async fn send(&self, command: Command) {
+        self.sender
+            .send(command)
+            .await
+            .expect("the manager should live as long as the handlers live");
+    }
+
+    // sends a command and waits for its response
+    async fn call<T>(&self, command: impl FnOnce(oneshot::Sender<T>) -> Command) -> T {
+        let start = Instant::now();
+        let (tx, rx) = oneshot::channel();
+        self.send(command(tx)).await;
+        let response = rx.await.expect("the manager always responds to commands");
+        metrics::histogram!(stats::MANAGER_WAIT).record(start.elapsed());
+
+        response
+    }
+}
+
+// resolves at the instant, or never when there is none
+async fn sleep_until(instant: Option<Instant>) {
+    match instant {
+        Some(instant) => tokio::time::sleep_until(instant.into()).await,
+        None => future::pending().await,
+    }
+}
+
+fn queue_key(tie_break: TieBreak, job: &Job) -> QueueKey {
+    (job.priority, tie_break.rank(job.submitted), job.id)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use serde_json::json;
+    use tokio::sync::{mpsc, oneshot};
+
+    use super::{Job, JobsSnapshot, Manager, Namespace, QueueSnapshot, TieBreak};
+    use crate::request::{NewJob, Notification};
+
+    fn order(manager: &mut Manager, queues: &[&str]) -> Vec<u64> {
+        std::iter::from_fn(|| manager.try_get(0, queues).map(|job| job.id)).collect()
+    }
+
+    #[test]
+    fn break_ties_by_submission_order() {
+        for (tie_break, expected) in [
+            (TieBreak::Fifo, [3, 0, 1, 2, 4]),
+            (TieBreak::Lifo, [3, 2, 1, 0, 4]),
+        ] {
+            let mut manager = Manager::new(tie_break);
+            manager.add("queue1".into(), json!({}), 5, None);
+            manager.add("queue2".into(), json!({}), 5, None);
+            manager.add("queue1".into(), json!({}), 5, None);
+            manager.add("queue2".into(), json!({}), 9, None);
+            manager.add("queue1".into(), json!({}), 1, None);
+
+            assert_eq!(order(&mut manager, &["queue1", "queue2"]), expected);
+        }
+    }
+
+    #[test]
+    fn aborted_jobs_keep_their_place() {
+        let mut manager = Manager::new(TieBreak::Fifo);
+        let first = manager.add("queue".into(), json!({}), 5, None);
+        manager.add("queue".into(), json!({}), 5, None);
+
+        manager.try_get(0, &["queue"]).unwrap();
+        assert!(manager.abort(0, first).is_ok_and(|found| found));
+
+        assert_eq!(order(&mut manager, &["queue"]), [0, 1]);
+    }
+
+    #[test]
+    fn notify_subscribers_of_available_jobs() {
+        let mut manager = Manager::default();
+        let (tx, mut rx) = mpsc::channel(8);
+        manager.subscribe(0, vec!["queue1".into(), "queue2".into()], tx);
+        let available = |queue: &str| Notification::JobAvailable {
+            queue: queue.into(),
+        };
+
+        let job = manager.add("queue1".into(), json!({}), 1, None);
+        manager.add("queue3".into(), json!({}), 1, None);
+        assert_eq!(rx.try_recv(), Ok(available("queue1")));
+        assert!(rx.try_recv().is_err());
+
+        // an aborted job is available again
+        manager.try_get(1, &["queue1"]).unwrap();
+        assert!(manager.abort(1, job).is_ok_and(|found| found));
+        assert_eq!(rx.try_recv(), Ok(available("queue1")));
+
+        // a job that is handed to a waiting client right away is never available
+        manager.try_get(1, &["queue1"]).unwrap();
+        let (tx, _waiting) = oneshot::channel();
+        manager.wait(1, &["queue2"], tx);
+        manager.add("queue2".into(), json!({}), 1, None);
+        assert!(rx.try_recv().is_err());
+
+        manager.unsubscribe(0);
+        manager.add("queue1".into(), json!({}), 1, None);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn snapshot_state() {
+        let mut manager = Manager::default();
+        manager.add("queue1".into(), json!({}), 1, None);
+        manager.add("queue1".into(), json!({}), 2, None);
+        manager.add("queue2".into(), json!({}), 3, None);
+
+        manager.add("queue4".into(), json!({}), 4, None);
+        manager.add("queue4".into(), json!({}), 4, None);
+
+        // one job is in progress, and one client waits on an empty queue
+        manager.try_get(0, &["queue2"]).unwrap();
+        let (tx, _waiting) = oneshot::channel();
+        manager.wait(1, &["queue3"], tx);
+
+        // one job was processed, the other was deleted before anyone worked on it
+        let processed = manager.try_get(0, &["queue4"]).unwrap();
+        manager.remove(processed.id);
+        manager.remove(processed.id - 1);
+
+        let snapshot = manager.snapshot();
+        assert_eq!(
+            snapshot.jobs,
+            JobsSnapshot {
+                total: 3,
+                pending: 2,
+                in_progress: 1,
+                processed: 1,
             }
+        );
+        assert_eq!(
+            snapshot.queues,
+            [
+                QueueSnapshot {
+                    name: "queue1".into(),
+                    pending: 2,
+                    ..Default::default()
+                },
+                QueueSnapshot {
+                    name: "queue2".into(),
+                    in_progress: 1,
+                    ..Default::default()
+                },
+                QueueSnapshot {
+                    name: "queue3".into(),
+                    waiting_clients: 1,
+                    ..Default::default()
+                },
+                QueueSnapshot {
+                    name: "queue4".into(),
+                    processed: 1,
+                    ..Default::default()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn get_many_by_priority_across_queues() {
+        let mut manager = Manager::default();
+        let low = manager.add("queue1".into(), json!({}), 1, None);
+        let high = manager.add("queue2".into(), json!({}), 3, None);
+        let mid = manager.add("queue1".into(), json!({}), 2, None);
+        let other = manager.add("queue3".into(), json!({}), 5, None);
+
+        let ids = |jobs: Vec<Job>| -> Vec<u64> { jobs.into_iter().map(|job| job.id).collect() };
+        assert_eq!(
+            ids(manager.try_get_many(0, &["queue1", "queue2"], 2)),
+            [high, mid]
+        );
+        // a count larger than the number of jobs takes whatever is left
+        assert_eq!(
+            ids(manager.try_get_many(0, &["queue1", "queue2"], 5)),
+            [low]
+        );
+        assert!(manager.try_get_many(0, &["queue1", "queue2"], 5).is_empty());
+
+        // every job that was handed out belongs to the client
+        manager.disconnect(0);
+        assert_eq!(
+            order(&mut manager, &["queue1", "queue2", "queue3"]),
+            [other, high, mid, low]
+        );
+    }
+
+    #[test]
+    fn add_batches() {
+        let mut manager = Manager::new(TieBreak::Fifo);
+        manager.add("queue1".into(), json!({}), 1, None);
+        let (tx, mut waiting) = oneshot::channel();
+        manager.wait(1, &["queue2"], tx);
+
+        let new = |queue: &str, priority| NewJob {
+            queue: queue.into(),
+            job: json!({}),
+            priority,
+            timeout: None,
         };
+        let ids = manager.add_batch(vec![
+            new("queue1", 1),
+            new("queue2", 5),
+            new("queue1", 3),
+            new("queue2", 2),
+        ]);
+        assert_eq!(ids, [1, 2, 3, 4]);
+
+        // a waiting client is handed a job of the batch, the rest are queued
+        assert_eq!(waiting.try_recv().map(|job| job.id), Ok(2));
+        assert_eq!(order(&mut manager, &["queue1", "queue2"]), [3, 4, 0, 1]);
+    }
+
+    #[test]
+    fn requeue_timed_out_jobs() {
+        let mut manager = Manager::default();
+        let timeout = Duration::from_secs(10);
+        let first = manager.add("queue".into(), json!({}), 1, Some(timeout));
+        let second = manager.add("queue".into(), json!({}), 1, Some(timeout));
+        manager.add("queue".into(), json!({}), 1, None);
+        let start = Instant::now();
+
+        // a job without a timeout is never put back
+        manager.try_get(0, &["queue"]).unwrap();
+        assert_eq!(manager.next_deadline(), None);
+
+        assert_eq!(manager.try_get(0, &["queue"]).unwrap().id, second);
+        // a job that is aborted in time doesn't time out
+        assert!(manager.abort(0, second).is_ok_and(|found| found));
+        assert_eq!(manager.next_deadline(), None);
+
+        // handing the job out again restarts its timeout
+        assert_eq!(manager.try_get(1, &["queue"]).unwrap().id, second);
+        assert!(manager
+            .next_deadline()
+            .is_some_and(|deadline| deadline >= start + timeout));
+
+        assert_eq!(manager.try_get(2, &["queue"]).unwrap().id, first);
+        assert!(manager.expire(start).is_empty());
+
+        let mut expired = manager.expire(Instant::now() + timeout);
+        expired.sort_unstable();
+        assert_eq!(expired, [first, second]);
+        assert_eq!(manager.next_deadline(), None);
+
+        // the jobs are back on their queue, and their previous owners can't abort them anymore
+        assert!(manager.abort(2, first).is_err());
+        assert_eq!(order(&mut manager, &["queue"]), [second, first]);
+
+        // a timeout that's out of the range of the clock never runs out
+        let mut manager = Manager::default();
+        let endless = manager.add(
+            "queue".into(),
+            json!({}),
+            1,
+            Some(Duration::from_secs(u64::MAX)),
+        );
+        assert_eq!(manager.try_get(3, &["queue"]).unwrap().id, endless);
+        assert_eq!(manager.next_deadline(), None);
+    }
+
+    #[test]
+    fn recur_until_cancelled() {
+        let mut manager = Manager::default();
+        let every = Duration::from_secs(10);
+        let recurring = manager
+            .add_recurring("queue".into(), json!({"n": 1}), 3, every)
+            .unwrap();
+        manager.add_recurring("other".into(), json!({}), 1, every * 2);
+        // an interval that's out of the range of the clock is refused
+        assert_eq!(
+            manager.add_recurring("queue".into(), json!({}), 1, Duration::MAX),
+            None
+        );
+        let start = Instant::now();
+
+        // nothing is due before the first interval has passed
+        assert!(manager.recur(start).is_empty());
+        assert!(manager
+            .next_run()
+            .is_some_and(|run| run > start && run <= start + every));
+
+        let added = manager.recur(start + every * 2);
+        assert_eq!(added.len(), 2);
+        let job = manager.try_get(0, &["queue"]).unwrap();
+        assert_eq!(
+            (job.id, job.job, job.priority),
+            (added[0], json!({"n": 1}), 3)
+        );
+
+        // the runs that were missed are skipped, a single job is made of the next one
+        assert_eq!(manager.recur(start + every * 5).len(), 2);
+        assert_eq!(order(&mut manager, &["queue"]).len(), 1);
+
+        assert!(manager.cancel(recurring));
+        assert!(!manager.cancel(recurring));
+        // the jobs that were already made are left on their queue
+        let added = manager.recur(start + every * 10);
+        assert_eq!(added.len(), 1);
+        assert!(manager.try_get(0, &["queue"]).is_none());
+        assert_eq!(order(&mut manager, &["other"]).len(), 3);
+    }
+
+    #[test]
+    fn purge_waiting_clients() {
+        let mut manager = Manager::default();
+        let waiting_clients = |manager: &Manager| -> Vec<(String, usize)> {
+            let snapshot = manager.snapshot();
+            snapshot
+                .queues
+                .into_iter()
+                .map(|queue| (queue.name, queue.waiting_clients))
+                .collect()
+        };
+
+        let (tx, _first) = oneshot::channel();
+        manager.wait(0, &["queue1", "queue2"], tx);
+        let (tx, _second) = oneshot::channel();
+        manager.wait(1, &["queue2", "queue3"], tx);
+        let (tx, _third) = oneshot::channel();
+        manager.wait(2, &["queue3", "queue4"], tx);
+
+        // a disconnected client doesn't wait on any of its queues
+        manager.disconnect(0);
+        assert_eq!(
+            waiting_clients(&manager),
+            [
+                ("queue2".into(), 1),
+                ("queue3".into(), 2),
+                ("queue4".into(), 1)
+            ]
+        );
+
+        // neither does a client that was handed a job on another queue,
+        // the queue is left with the job in progress only
+        manager.add("queue2".into(), json!({}), 1, None);
+        assert_eq!(
+            waiting_clients(&manager),
+            [
+                ("queue2".into(), 0),
+                ("queue3".into(), 1),
+                ("queue4".into(), 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn requeue_jobs_of_disconnected_clients() {
+        let mut manager = Manager::default();
+        manager.add("queue1".into(), json!({}), 1, None);
+        manager.try_get(0, &["queue1"]).unwrap();
+
+        // a job is handed to a waiting client that stops waiting before it receives it
+        let (tx, waiting) = oneshot::channel();
+        manager.wait(0, &["queue2"], tx);
+        manager.add("queue2".into(), json!({}), 1, None);
+        drop(waiting);
+
+        // a job is never handed to a client that already stopped waiting
+        let (tx, waiting) = oneshot::channel();
+        manager.wait(0, &["queue3"], tx);
+        drop(waiting);
+        manager.add("queue3".into(), json!({}), 1, None);
+
+        manager.disconnect(0);
+        assert_eq!(
+            order(&mut manager, &["queue1", "queue2", "queue3"]),
+            [2, 1, 0]
+        );
+    }
+
+    #[tokio::test]
+    async fn cancel_waiting_gets() {
+        let manager = Manager::default().start();
+
+        let waiting = tokio::spawn({
+            let manager = manager.clone();
+            async move { manager.get(0, vec!["queue".into()]).await }
+        });
+        while manager.snapshot().await.queues.is_empty() {
+            tokio::task::yield_now().await;
+        }
+        waiting.abort();
+        assert!(waiting.await.unwrap_err().is_cancelled());
+
+        // the job stays on the queue for the next client
+        let id = manager.add("queue".into(), json!({}), 1, None).await;
+        let job = manager.get(1, vec!["queue".into()]).await;
+        assert_eq!(job.id, id);
+
+        // and goes back to it when that client disconnects
+        manager.disconnect(1);
+        let job = manager.try_get(2, vec!["queue".into()]).await;
+        assert_eq!(job.map(|job| job.id), Some(id));
+    }
+
+    #[tokio::test]
+    async fn isolate_namespaces() {
+        let manager = Manager::default().start();
+        let acme = manager.scoped(Namespace::tenant("acme"));
+        let globex = manager.scoped(Namespace::tenant("globex"));
+
+        let id = acme.add("queue".into(), json!({}), 1, None).await;
+        let recurring = acme
+            .add_recurring("queue".into(), json!({}), 1, Duration::from_secs(60))
+            .await
+            .unwrap();
+
+        // another tenant can't reach the jobs, even by their ids
+        assert!(globex.try_get(1, vec!["queue".into()]).await.is_none());
+        assert!(!globex.remove(id).await);
+        assert!(!globex.cancel(recurring).await);
+        assert_eq!(globex.snapshot().await.jobs.total, 0);
+        assert!(globex.snapshot().await.queues.is_empty());
+
+        // the tenant sees its queues by their own names, while the shared namespace sees them all
+        let snapshot = acme.snapshot().await;
+        assert_eq!(snapshot.jobs.total, 1);
+        assert_eq!(snapshot.queues[0].name, "queue");
+        assert_eq!(manager.snapshot().await.queues[0].name, "acme/queue");
 
-        // the waiting clients list is empty
-        // we need to change it to a pending queue and insert the job
-        let mut set = BTreeSet::new();
-        set.insert((job.priority, job.id));
-        *queue = QueueStab::Jobs(set);
+        let job = acme.try_get(0, vec!["queue".into()]).await.unwrap();
+        assert_eq!((job.id, job.queue.as_str()), (id, "queue"));
+        assert!(acme.remove(id).await);
+        assert!(acme.cancel(recurring).await);
     }
 }
@@ -0,0 +1,12 @@
+//! The job manager and the request handling behind the server,
+//! a library of their own so the verifier can run the server in-process
+pub mod auth;
+pub mod client;
+pub mod config;
+pub mod dashboard;
+pub mod jobs;
+pub mod notify;
+pub mod request;
+pub mod server;
+pub mod stats;
+pub mod websocket;
@@ -1,55 +1,61 @@
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 
-use client::Client;
-use jobs::Manager;
-use tokio::{
-    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
-    net::{TcpListener, TcpStream},
+use job_centre::{
+    client::Client, config, dashboard, jobs::Manager, server::handle_request, websocket,
 };
-
-mod client;
-mod jobs;
-mod request;
-
-type SharedJobManager = Arc<Mutex<Manager>>;
+use throttle::{Limits, Throttle};
+use tokio::net::TcpListener;
 
 #[tokio::main]
 async fn main() -> tokio::io::Result<()> {
-    // connect tracing to stdout
-    tracing_subscriber::fmt::init();
+    telemetry::init();
 
+    let throttle = Arc::new(Throttle::new(
+        Limits::from_env().map_err(tokio::io::Error::other)?,
+    ));
     let listener = TcpListener::bind("0.0.0.0:3600").await?;
     tracing::info!("Server listening on: {}", listener.local_addr()?);
 
-    let shared_job_manager = SharedJobManager::default();
+    let config = config::Config::from_env().map_err(tokio::io::Error::other)?;
+    let job_manager = Manager::new(config.tie_break).start();
+    let auth_tokens = config.auth_tokens.map(Arc::new);
 
-    loop {
-        let (conn, _) = listener.accept().await?;
-        let client = Client::new(shared_job_manager.clone());
-        tokio::spawn(handle_request(client, conn));
+    if let Some(addr) = config.metrics_addr {
+        if let Err(err) = telemetry::stats::install(&addr) {
+            tracing::error!("failed to start the metrics exporter: {}", err);
+        }
     }
-}
-
-async fn handle_request(mut client: Client, mut stream: TcpStream) -> tokio::io::Result<()> {
-    let (reader, mut writer) = stream.split();
-    let mut reader = BufReader::new(reader);
 
-    loop {
-        let mut request = String::new();
-        let rcount = reader.read_line(&mut request).await?;
-        if rcount == 0 {
-            break; // EOF
-        }
+    if let Some(addr) = config.dashboard_addr {
+        let throttle = throttle.clone();
+        let job_manager = job_manager.clone();
+        tokio::spawn(async move {
+            if let Err(err) = dashboard::serve(addr, throttle, job_manager).await {
+                tracing::error!("the dashboard has failed: {}", err);
+            }
+        });
+    }
 
-        tracing::debug!("received: {}", request);
-        let response = client.handle_request(&request).await;
-        tracing::debug!("responded: {:?}", response);
+    if let Some(addr) = config.websocket_addr {
+        let throttle = throttle.clone();
+        let job_manager = job_manager.clone();
+        let auth_tokens = auth_tokens.clone();
+        tokio::spawn(async move {
+            if let Err(err) = websocket::serve(addr, throttle, job_manager, auth_tokens).await {
+                tracing::error!("the WebSocket gateway has failed: {}", err);
+            }
+        });
+    }
 
-        if let Ok(mut response) = serde_json::to_string(&response) {
-            response.push('\n');
-            writer.write_all(response.as_bytes()).await?;
+    loop {
+        let (conn, addr, permit) = throttle.accept(&listener).await?;
+        let mut client = Client::new(job_manager.clone());
+        if let Some(tokens) = &auth_tokens {
+            client = client.require_auth(tokens.clone());
         }
+        tokio::spawn(telemetry::connection(
+            addr,
+            permit.hold(handle_request(client, conn)),
+        ));
     }
-
-    Ok(())
 }
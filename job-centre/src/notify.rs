@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+
+use tokio::sync::mpsc;
+
+use crate::request::Notification;
+
+// how many notifications may wait for a subscriber that doesn't read them,
+// any notification beyond it is dropped
+pub const NOTIFICATION_BUFFER_SIZE: usize = 64;
+
+/// Fans out job availability notifications to the clients subscribed to a queue
+///
+/// unlike a waiting client, a subscriber isn't handed the job,
+/// it's only told that one is available, and decides on its own when to get it
+#[derive(Debug, Default)]
+pub struct Subscriptions {
+    // maps queue_name -> (client_id -> sender)
+    queues: HashMap<String, HashMap<u64, mpsc::Sender<Notification>>>,
+    // maps client_id -> subscribed queue names
+    clients: HashMap<u64, Vec<String>>,
+}
+
+impl Subscriptions {
+    /// Subscribes a client to a list of queues, replacing its previous subscription
+    pub fn subscribe(
+        &mut self,
+        client_id: u64,
+        queues: Vec<String>,
+        sender: mpsc::Sender<Notification>,
+    ) {
+        self.unsubscribe(client_id);
+
+        for queue in queues.iter() {
+            self.queues
+                .entry(queue.clone())
+                .or_default()
+                .insert(client_id, sender.clone());
+        }
+        self.clients.insert(client_id, queues);
+    }
+
+    /// Removes the subscription of a client, if it has one
+    pub fn unsubscribe(&mut self, client_id: u64) {
+        for queue in self.clients.remove(&client_id).into_iter().flatten() {
+            if let Some(subscribers) = self.queues.get_mut(&queue) {
+                subscribers.remove(&client_id);
+                if subscribers.is_empty() {
+                    self.queues.remove(&queue);
+                }
+            }
+        }
+    }
+
+    /// Tells every subscriber of the queue that a job is available on it
+    pub fn notify(&self, queue: &str) {
+        let Some(subscribers) = self.queues.get(queue) else {
+            return;
+        };
+
+        for sender in subscribers.values() {
+            // a slow subscriber misses notifications instead of holding the manager back
+            let _ = sender.try_send(Notification::JobAvailable {
+                queue: queue.into(),
+            });
+        }
+    }
+}
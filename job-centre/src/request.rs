@@ -1,18 +1,52 @@
+use std::num::{NonZeroU64, NonZeroUsize};
+
 use serde::{Deserialize, Serialize};
 
+use crate::jobs::{JobsSnapshot, QueueSnapshot};
+
+// Jobs are sent back nested within a response, so they are kept well below the depth
+// at which JSON parsers (serde_json included) give up
+pub const MAX_JOB_DEPTH: usize = 64;
+
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 #[serde(tag = "request", rename_all = "kebab-case")]
 pub enum Request {
+    // scopes every later request to the queues of the tenant the token belongs to,
+    // the only request served before it when authentication is required
+    Auth {
+        token: String,
+    },
     Put {
         queue: String,
         job: serde_json::Value,
         #[serde(rename = "pri")]
         priority: u64,
+        // seconds a client may work on the job before it's put back on its queue
+        timeout: Option<u64>,
+    },
+    // puts all of the jobs at once, they get sequential ids in the order they are listed
+    PutBatch {
+        jobs: Vec<NewJob>,
     },
     Get {
         queues: Vec<String>,
         #[serde(default)]
         wait: bool,
+        // when set, up to this many jobs are handed out at once, and sent back as a list
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        count: Option<NonZeroUsize>,
+    },
+    // a fresh copy of the job is put on the queue every interval, until it's cancelled.
+    // responded to with the id of the recurring job, which is apart from the ids of the jobs
+    PutRecurring {
+        queue: String,
+        job: serde_json::Value,
+        #[serde(rename = "pri")]
+        priority: u64,
+        every_secs: NonZeroU64,
+    },
+    Cancel {
+        id: u64,
     },
     Delete {
         id: u64,
@@ -20,6 +54,52 @@ pub enum Request {
     Abort {
         id: u64,
     },
+    Subscribe {
+        queues: Vec<String>,
+    },
+    // the totals of all jobs
+    Stats,
+    // the state of every queue
+    Queues,
+}
+
+/// A job of a get request with a count
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct TakenJob {
+    pub id: u64,
+    pub queue: String,
+    pub job: serde_json::Value,
+    #[serde(rename = "pri")]
+    pub priority: u64,
+}
+
+/// Whether the job payload is nested deeper than `MAX_JOB_DEPTH`
+pub fn too_deep(job: &serde_json::Value) -> bool {
+    fn depth(value: &serde_json::Value) -> usize {
+        match value {
+            serde_json::Value::Array(values) => 1 + values.iter().map(depth).max().unwrap_or(0),
+            serde_json::Value::Object(values) => 1 + values.values().map(depth).max().unwrap_or(0),
+            _ => 0,
+        }
+    }
+
+    depth(job) > MAX_JOB_DEPTH
+}
+
+impl From<TakenJob> for Response {
+    fn from(value: TakenJob) -> Self {
+        Self::job(value.id, value.queue, value.job, value.priority)
+    }
+}
+
+/// A job of a put-batch request
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct NewJob {
+    pub queue: String,
+    pub job: serde_json::Value,
+    #[serde(rename = "pri")]
+    pub priority: u64,
+    pub timeout: Option<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
@@ -31,6 +111,18 @@ pub enum Response {
         job: Option<serde_json::Value>,
         #[serde(rename = "pri")]
         priority: Option<u64>,
+        // only sent in response to a put-batch request
+        #[serde(skip_serializing_if = "Option::is_none")]
+        ids: Option<Vec<u64>>,
+        // only sent in response to a get request with a count
+        #[serde(skip_serializing_if = "Option::is_none")]
+        jobs: Option<Vec<TakenJob>>,
+        // only sent in response to a stats request
+        #[serde(skip_serializing_if = "Option::is_none")]
+        stats: Option<JobsSnapshot>,
+        // only sent in response to a queues request, ordered by queue name
+        #[serde(skip_serializing_if = "Option::is_none")]
+        queues: Option<Vec<QueueSnapshot>>,
     },
     Error {
         error: Option<String>,
@@ -38,6 +130,13 @@ pub enum Response {
     NoJob,
 }
 
+/// A line the server pushes to a subscribed client, outside of any request
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "notify", rename_all = "kebab-case")]
+pub enum Notification {
+    JobAvailable { queue: String },
+}
+
 impl Response {
     pub fn error(reason: String) -> Self {
         Self::Error {
@@ -51,6 +150,10 @@ impl Response {
             queue: None,
             job: None,
             priority: None,
+            ids: None,
+            jobs: None,
+            stats: None,
+            queues: None,
         }
     }
 
@@ -60,6 +163,62 @@ impl Response {
             queue: Some(queue),
             job: Some(job),
             priority: Some(priority),
+            ids: None,
+            jobs: None,
+            stats: None,
+            queues: None,
+        }
+    }
+
+    pub fn created_batch(ids: Vec<u64>) -> Self {
+        Self::Ok {
+            id: None,
+            queue: None,
+            job: None,
+            priority: None,
+            ids: Some(ids),
+            jobs: None,
+            stats: None,
+            queues: None,
+        }
+    }
+
+    pub fn jobs(jobs: Vec<TakenJob>) -> Self {
+        Self::Ok {
+            id: None,
+            queue: None,
+            job: None,
+            priority: None,
+            ids: None,
+            jobs: Some(jobs),
+            stats: None,
+            queues: None,
+        }
+    }
+
+    pub fn stats(stats: JobsSnapshot) -> Self {
+        Self::Ok {
+            id: None,
+            queue: None,
+            job: None,
+            priority: None,
+            ids: None,
+            jobs: None,
+            stats: Some(stats),
+            queues: None,
+        }
+    }
+
+    pub fn queues(queues: Vec<QueueSnapshot>) -> Self {
+        Self::Ok {
+            id: None,
+            queue: None,
+            job: None,
+            priority: None,
+            ids: None,
+            jobs: None,
+            stats: None,
+            queues: Some(queues),
         }
     }
 
@@ -69,15 +228,24 @@ impl Response {
             queue: None,
             job: None,
             priority: None,
+            ids: None,
+            jobs: None,
+            stats: None,
+            queues: None,
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::num::{NonZeroU64, NonZeroUsize};
+
     use serde_json::json;
 
-    use crate::request::Response;
+    use crate::{
+        jobs::{JobsSnapshot, QueueSnapshot},
+        request::{NewJob, Notification, Response, TakenJob},
+    };
 
     use super::Request;
 
@@ -86,9 +254,18 @@ mod tests {
         let requests = [
             r#"{"request":"put","queue":"queue1","job":{"title":"example-job"},"pri":123}"#,
             r#"{"request":"get","queues":["queue1"]}"#,
+            r#"{"request":"put","queue":"queue1","job":[],"pri":1,"timeout":60}"#,
             r#"{"request":"abort","id":12345}"#,
             r#"{"request":"delete","id":12345}"#,
             r#"{"request":"get","queues":["queue1"],"wait":true}"#,
+            r#"{"request":"subscribe","queues":["queue1","queue2"]}"#,
+            r#"{"request":"stats"}"#,
+            r#"{"request":"queues"}"#,
+            r#"{"request":"get","queues":["queue1","queue2"],"count":10}"#,
+            r#"{"request":"put-batch","jobs":[{"queue":"queue1","job":{},"pri":1,"timeout":30},{"queue":"queue2","job":[],"pri":2}]}"#,
+            r#"{"request":"put-recurring","queue":"queue1","job":{},"pri":5,"every_secs":60}"#,
+            r#"{"request":"cancel","id":12345}"#,
+            r#"{"request":"auth","token":"s3cret"}"#,
         ];
 
         let expected_requests = [
@@ -96,20 +273,65 @@ mod tests {
                 queue: "queue1".into(),
                 job: json!({"title": "example-job"}),
                 priority: 123,
+                timeout: None,
             },
             Request::Get {
                 queues: ["queue1".into()].into(),
                 wait: false,
+                count: None,
+            },
+            Request::Put {
+                queue: "queue1".into(),
+                job: json!([]),
+                priority: 1,
+                timeout: Some(60),
             },
             Request::Abort { id: 12345 },
             Request::Delete { id: 12345 },
             Request::Get {
                 queues: ["queue1".into()].into(),
                 wait: true,
+                count: None,
+            },
+            Request::Subscribe {
+                queues: ["queue1".into(), "queue2".into()].into(),
+            },
+            Request::Stats,
+            Request::Queues,
+            Request::Get {
+                queues: ["queue1".into(), "queue2".into()].into(),
+                wait: false,
+                count: NonZeroUsize::new(10),
+            },
+            Request::PutBatch {
+                jobs: vec![
+                    NewJob {
+                        queue: "queue1".into(),
+                        job: json!({}),
+                        priority: 1,
+                        timeout: Some(30),
+                    },
+                    NewJob {
+                        queue: "queue2".into(),
+                        job: json!([]),
+                        priority: 2,
+                        timeout: None,
+                    },
+                ],
+            },
+            Request::PutRecurring {
+                queue: "queue1".into(),
+                job: json!({}),
+                priority: 5,
+                every_secs: NonZeroU64::new(60).unwrap(),
+            },
+            Request::Cancel { id: 12345 },
+            Request::Auth {
+                token: "s3cret".into(),
             },
         ];
 
-        for (request, expected) in requests.into_iter().zip(expected_requests.into_iter()) {
+        for (request, expected) in requests.into_iter().zip(expected_requests) {
             let request: Request = serde_json::from_str(request).unwrap();
             assert_eq!(request, expected);
         }
@@ -118,6 +340,10 @@ mod tests {
             r#"{"status":"ok","id":12345}"#,
             r#"{"status":"ok","id":12345,"job":{"title":"example-job"},"pri":123,"queue":"queue1"}"#,
             r#"{"status":"ok"}"#,
+            r#"{"status":"ok","ids":[7,8]}"#,
+            r#"{"status":"ok","stats":{"total":3,"pending":1,"in_progress":2,"processed":5}}"#,
+            r#"{"status":"ok","queues":[{"name":"queue1","pending":1,"in_progress":2,"waiting_clients":0,"processed":5}]}"#,
+            r#"{"status":"ok","jobs":[{"id":1,"queue":"queue1","job":{},"pri":5},{"id":2,"queue":"queue2","job":[],"pri":4}]}"#,
             r#"{"status":"no-job"}"#,
         ];
 
@@ -127,25 +353,99 @@ mod tests {
                 queue: None,
                 job: None,
                 priority: None,
+                ids: None,
+                jobs: None,
+                stats: None,
+                queues: None,
             },
             Response::Ok {
                 id: Some(12345),
                 queue: Some("queue1".into()),
                 job: Some(json!({"title": "example-job"})),
                 priority: Some(123),
+                ids: None,
+                jobs: None,
+                stats: None,
+                queues: None,
+            },
+            Response::Ok {
+                id: None,
+                queue: None,
+                job: None,
+                priority: None,
+                ids: None,
+                jobs: None,
+                stats: None,
+                queues: None,
             },
             Response::Ok {
                 id: None,
                 queue: None,
                 job: None,
                 priority: None,
+                ids: Some(vec![7, 8]),
+                jobs: None,
+                stats: None,
+                queues: None,
             },
+            Response::stats(JobsSnapshot {
+                total: 3,
+                pending: 1,
+                in_progress: 2,
+                processed: 5,
+            }),
+            Response::queues(vec![QueueSnapshot {
+                name: "queue1".into(),
+                pending: 1,
+                in_progress: 2,
+                waiting_clients: 0,
+                processed: 5,
+            }]),
+            Response::jobs(vec![
+                TakenJob {
+                    id: 1,
+                    queue: "queue1".into(),
+                    job: json!({}),
+                    priority: 5,
+                },
+                TakenJob {
+                    id: 2,
+                    queue: "queue2".into(),
+                    job: json!([]),
+                    priority: 4,
+                },
+            ]),
             Response::NoJob,
         ];
 
-        for (response, expected) in responses.into_iter().zip(expected_responses.into_iter()) {
+        // a get request never asks for 0 jobs
+        assert!(
+            serde_json::from_str::<Request>(r#"{"request":"get","queues":["q"],"count":0}"#)
+                .is_err()
+        );
+        // and a job never recurs without an interval
+        assert!(serde_json::from_str::<Request>(
+            r#"{"request":"put-recurring","queue":"q","job":{},"pri":1,"every_secs":0}"#
+        )
+        .is_err());
+
+        for (response, expected) in responses.into_iter().zip(expected_responses) {
             let response: Response = serde_json::from_str(response).unwrap();
             assert_eq!(response, expected);
         }
+
+        // the ids are left out of every other response
+        assert_eq!(
+            serde_json::to_string(&Response::ok()).unwrap(),
+            r#"{"status":"ok","id":null,"queue":null,"job":null,"pri":null}"#
+        );
+
+        let notification = Notification::JobAvailable {
+            queue: "queue1".into(),
+        };
+        assert_eq!(
+            serde_json::to_string(&notification).unwrap(),
+            r#"{"notify":"job-available","queue":"queue1"}"#
+        );
     }
 }
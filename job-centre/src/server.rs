@@ -0,0 +1,78 @@
+use framing::{FramingError, LineReader};
+use serde::Serialize;
+use telemetry::stats::Metered;
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use crate::{client::Client, request::Response};
+
+// a request longer than this is answered with an error, and its connection is closed
+pub(crate) const MAX_REQUEST_SIZE: usize = 1024 * 1024;
+
+/// Serves a single client until it disconnects,
+/// its pending notifications are pushed in between its requests
+pub async fn handle_request(mut client: Client, stream: TcpStream) -> tokio::io::Result<()> {
+    let (reader, mut writer) = tokio::io::split(Metered::new(stream));
+    // reading the next line is cancel safe, so a pushed notification never cuts a request in half
+    let mut lines = LineReader::new(reader, MAX_REQUEST_SIZE);
+
+    loop {
+        tokio::select! {
+            request = lines.read_line() => {
+                let request = match request {
+                    Ok(Some(request)) => request,
+                    Ok(None) => break, // EOF
+                    Err(FramingError::Io(err)) => return Err(err),
+                    Err(err) => {
+                        // the stream may be left in the middle of a request, so the connection is closed
+                        telemetry::stats::error("framing");
+                        write_line(&mut writer, &Response::error(err.to_string())).await?;
+                        break;
+                    }
+                };
+
+                tracing::debug!("received: {}", request);
+                // a client that hangs up while waiting for a job stops waiting
+                let response = tokio::select! {
+                    response = client.handle_request(&request) => response,
+                    _ = hang_up(lines.get_mut()) => break,
+                };
+                tracing::debug!("responded: {:?}", response);
+
+                write_line(&mut writer, &response).await?;
+                if client.is_refused() {
+                    break;
+                }
+            }
+            notification = client.notification() => {
+                tracing::debug!("notified: {:?}", notification);
+                write_line(&mut writer, &notification).await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// resolves once the client hangs up, without consuming any of its pipelined requests
+async fn hang_up<R: AsyncBufRead + Unpin>(reader: &mut R) {
+    match reader.fill_buf().await {
+        Ok(buffer) if !buffer.is_empty() => std::future::pending().await,
+        _ => {}
+    }
+}
+
+async fn write_line<W, T>(writer: &mut W, message: &T) -> tokio::io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+    T: Serialize,
+{
+    if let Ok(mut message) = serde_json::to_string(message) {
+        message.push('\n');
+        writer.write_all(message.as_bytes()).await?;
+    }
+
+    Ok(())
+}
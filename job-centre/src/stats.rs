@@ -0,0 +1,31 @@
+use crate::request::Request;
+
+// the time it takes to handle a request, labeled by the request type
+pub const REQUEST_DURATION: &str = "job_centre_request_duration_seconds";
+// the time it takes the job manager to respond to a request, besides a waiting get
+pub const MANAGER_WAIT: &str = "job_centre_manager_wait_seconds";
+// the number of jobs that were put back on their queue since a client held them past their timeout
+//
+// note: the metrics aren't labeled by the queue, since the clients pick the queue names
+pub const TIMED_OUT_JOBS: &str = "job_centre_timed_out_jobs_total";
+// the time a job spends on its queue, from the moment it's put (or aborted) until it's retrieved
+pub const QUEUE_WAIT: &str = "job_centre_queue_wait_seconds";
+// the number of jobs that are pending on any of the queues
+pub const QUEUE_DEPTH: &str = "job_centre_queue_depth";
+
+pub fn request_type(request: &Request) -> &'static str {
+    match request {
+        Request::Auth { .. } => "auth",
+        Request::Put { .. } => "put",
+        Request::PutBatch { .. } => "put-batch",
+        Request::PutRecurring { .. } => "put-recurring",
+        Request::Cancel { .. } => "cancel",
+        Request::Get { wait: true, .. } => "get-wait",
+        Request::Get { wait: false, .. } => "get",
+        Request::Delete { .. } => "delete",
+        Request::Abort { .. } => "abort",
+        Request::Subscribe { .. } => "subscribe",
+        Request::Stats => "stats",
+        Request::Queues => "queues",
+    }
+}
@@ -0,0 +1,213 @@
+use std::sync::Arc;
+
+use futures_util::{Sink, SinkExt, StreamExt};
+use serde::Serialize;
+use telemetry::stats::Metered;
+use throttle::Throttle;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+};
+use tokio_tungstenite::tungstenite::{self, protocol::WebSocketConfig, Message};
+
+use crate::{auth::Tokens, client::Client, jobs, request::Response, server::MAX_REQUEST_SIZE};
+
+/// Serves the same requests as the main listener over WebSocket, a single JSON document per message
+///
+/// requests are text messages, and are answered (along with the notifications) with text messages.
+/// when tokens are given, the clients authenticate the same way they do on the main listener
+pub async fn serve(
+    addr: String,
+    throttle: Arc<Throttle>,
+    job_manager: jobs::Handler,
+    auth_tokens: Option<Arc<Tokens>>,
+) -> tokio::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    tracing::info!("WebSocket gateway listening on: {}", listener.local_addr()?);
+
+    loop {
+        let (conn, addr, permit) = throttle.accept(&listener).await?;
+        let mut client = Client::new(job_manager.clone());
+        if let Some(tokens) = &auth_tokens {
+            client = client.require_auth(tokens.clone());
+        }
+        tokio::spawn(telemetry::connection(
+            addr,
+            permit.hold(handle(client, conn)),
+        ));
+    }
+}
+
+async fn handle<S>(mut client: Client, stream: S) -> Result<(), tungstenite::Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // a message is a single request, so it's bound by the same size as a request on the main listener
+    let config = WebSocketConfig {
+        max_message_size: Some(MAX_REQUEST_SIZE),
+        max_frame_size: Some(MAX_REQUEST_SIZE),
+        ..Default::default()
+    };
+    let socket =
+        tokio_tungstenite::accept_async_with_config(Metered::new(stream), Some(config)).await?;
+    let (mut writer, mut reader) = socket.split();
+
+    // a message that arrived while a request was waiting for a job
+    let mut pipelined = None;
+    loop {
+        let message = match pipelined.take() {
+            Some(message) => message,
+            None => tokio::select! {
+                message = reader.next() => match message {
+                    Some(message) => message?,
+                    None => break, // EOF
+                },
+                notification = client.notification() => {
+                    tracing::debug!("notified: {:?}", notification);
+                    send(&mut writer, &notification).await?;
+                    continue;
+                }
+            },
+        };
+
+        let request = match message {
+            Message::Text(request) => request,
+            Message::Binary(request) => match String::from_utf8(request) {
+                Ok(request) => request,
+                Err(_) => {
+                    telemetry::stats::error("parse");
+                    let response = Response::error("requests must be utf-8 json".into());
+                    send(&mut writer, &response).await?;
+                    continue;
+                }
+            },
+            Message::Close(_) => break,
+            // pings are answered by the socket itself
+            _ => continue,
+        };
+
+        tracing::debug!("received: {}", request);
+        // a client that hangs up while waiting for a job stops waiting
+        let response = {
+            let handled = client.handle_request(&request);
+            tokio::pin!(handled);
+            loop {
+                tokio::select! {
+                    response = &mut handled => break Some(response),
+                    message = reader.next(), if pipelined.is_none() => match message {
+                        Some(Ok(message)) if !message.is_close() => pipelined = Some(message),
+                        _ => break None,
+                    },
+                }
+            }
+        };
+        let Some(response) = response else {
+            break;
+        };
+        tracing::debug!("responded: {:?}", response);
+
+        send(&mut writer, &response).await?;
+        if client.is_refused() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+async fn send<W, T>(writer: &mut W, message: &T) -> Result<(), tungstenite::Error>
+where
+    W: Sink<Message, Error = tungstenite::Error> + Unpin,
+    T: Serialize,
+{
+    if let Ok(message) = serde_json::to_string(message) {
+        writer.send(Message::Text(message)).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures_util::{SinkExt, StreamExt};
+    use tokio_tungstenite::tungstenite::Message;
+
+    use crate::{client::Client, jobs::Manager, request::Response, server::MAX_REQUEST_SIZE};
+
+    use super::handle;
+
+    #[tokio::test]
+    async fn serve_requests_over_websocket() {
+        let (client, server) = tokio::io::duplex(4096);
+        tokio::spawn(handle(Client::new(Manager::default().start()), server));
+        let (mut socket, _) = tokio_tungstenite::client_async("ws://localhost/", client)
+            .await
+            .unwrap();
+
+        for request in [
+            r#"{"request":"put","queue":"q1","job":{"title":"x"},"pri":1}"#,
+            r#"{"request":"get","queues":["q1"]}"#,
+            "not json",
+        ] {
+            socket.send(Message::Text(request.into())).await.unwrap();
+        }
+
+        let mut responses = vec![];
+        for _ in 0..3 {
+            match socket.next().await.unwrap().unwrap() {
+                Message::Text(response) => responses.push(response),
+                message => panic!("unexpected message: {:?}", message),
+            }
+        }
+        assert!(responses[0].starts_with(r#"{"status":"ok","id":0,"#));
+        assert!(responses[1].starts_with(r#"{"status":"ok","id":0,"#));
+        assert!(responses[2].starts_with(r#"{"status":"error""#));
+    }
+
+    #[tokio::test]
+    async fn push_notifications_over_websocket() {
+        let manager = Manager::default().start();
+        let (client, server) = tokio::io::duplex(4096);
+        tokio::spawn(handle(Client::new(manager.clone()), server));
+        let (mut socket, _) = tokio_tungstenite::client_async("ws://localhost/", client)
+            .await
+            .unwrap();
+
+        let subscribe = r#"{"request":"subscribe","queues":["q1"]}"#;
+        socket.send(Message::Text(subscribe.into())).await.unwrap();
+        let response = socket.next().await.unwrap().unwrap().into_text().unwrap();
+        assert!(response.starts_with(r#"{"status":"ok""#));
+
+        // a job put by another client is pushed to the subscriber
+        let mut producer = Client::new(manager);
+        let put = r#"{"request":"put","queue":"q1","job":{},"pri":1}"#;
+        assert!(matches!(
+            producer.handle_request(put).await,
+            Response::Ok { .. }
+        ));
+        let notification = socket.next().await.unwrap().unwrap();
+        assert_eq!(
+            notification,
+            Message::Text(r#"{"notify":"job-available","queue":"q1"}"#.into())
+        );
+    }
+
+    #[tokio::test]
+    async fn refuse_oversized_messages() {
+        let (client, server) = tokio::io::duplex(4096);
+        let handled = tokio::spawn(handle(Client::new(Manager::default().start()), server));
+        let (mut socket, _) = tokio_tungstenite::client_async("ws://localhost/", client)
+            .await
+            .unwrap();
+
+        let oversized = "a".repeat(MAX_REQUEST_SIZE + 1);
+        // the server may close the socket before the whole message is written
+        let _ = socket.send(Message::Text(oversized)).await;
+        let handled = tokio::time::timeout(Duration::from_secs(5), handled)
+            .await
+            .expect("the connection should have been closed");
+        assert!(handled.unwrap().is_err());
+    }
+}
@@ -0,0 +1,79 @@
+use std::{env, time::Duration};
+
+/// Builds the transport configuration from the environment
+///
+/// keep-alive is enabled by setting KEEPALIVE_INTERVAL_SECS,
+/// and KEEPALIVE_TIMEOUT_SECS (defaults to 3 intervals) controls when silent peers are dropped.
+/// IDLE_TIMEOUT_SECS closes sessions that haven't moved any data for that long,
+/// MAX_SESSIONS caps the number of concurrent sessions,
+/// WINDOW_BYTES sets how many unacked bytes each session keeps in flight,
+/// RTO_INITIAL_SECS, RTO_MIN_SECS and RTO_MAX_SECS bound the retransmission timeout,
+/// and SESSION_EXPIRY_SECS sets how long a session waits for an ack before it expires
+pub fn from_env() -> anyhow::Result<lrcp::Config> {
+    let mut config = lrcp::Config::default();
+
+    if let Some(interval) = read_secs("KEEPALIVE_INTERVAL_SECS")? {
+        let timeout = read_secs("KEEPALIVE_TIMEOUT_SECS")?.unwrap_or(interval * 3);
+        config.keepalive = Some(lrcp::KeepAlive { interval, timeout });
+    }
+
+    if let Some(idle_timeout) = read_secs("IDLE_TIMEOUT_SECS")? {
+        anyhow::ensure!(
+            !idle_timeout.is_zero(),
+            "bad value for IDLE_TIMEOUT_SECS: must not be 0"
+        );
+        config.idle_timeout = Some(idle_timeout);
+    }
+
+    config.max_sessions = match env::var("MAX_SESSIONS") {
+        Ok(value) => Some(
+            value
+                .parse()
+                .map_err(|err| anyhow::anyhow!("bad value for MAX_SESSIONS: {}", err))?,
+        ),
+        Err(_) => None,
+    };
+
+    if let Ok(value) = env::var("WINDOW_BYTES") {
+        config.window = value
+            .parse()
+            .map_err(|err| anyhow::anyhow!("bad value for WINDOW_BYTES: {}", err))?;
+        anyhow::ensure!(
+            config.window > 0,
+            "bad value for WINDOW_BYTES: must not be 0"
+        );
+    }
+
+    if let Some(initial) = read_secs("RTO_INITIAL_SECS")? {
+        config.rto.initial = initial;
+    }
+    if let Some(min) = read_secs("RTO_MIN_SECS")? {
+        config.rto.min = min;
+    }
+    if let Some(max) = read_secs("RTO_MAX_SECS")? {
+        config.rto.max = max;
+    }
+    anyhow::ensure!(
+        !config.rto.min.is_zero()
+            && config.rto.min <= config.rto.initial
+            && config.rto.initial <= config.rto.max,
+        "bad retransmission timeout: expected 0 < RTO_MIN_SECS <= RTO_INITIAL_SECS <= RTO_MAX_SECS"
+    );
+
+    if let Some(expiry) = read_secs("SESSION_EXPIRY_SECS")? {
+        config.session_expiry = expiry;
+    }
+
+    Ok(config)
+}
+
+fn read_secs(name: &str) -> anyhow::Result<Option<Duration>> {
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map(|secs| Some(Duration::from_secs_f64(secs)))
+            .map_err(|err| anyhow::anyhow!("bad value for {}: {}", name, err)),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(err) => Err(anyhow::anyhow!("bad value for {}: {}", name, err)),
+    }
+}
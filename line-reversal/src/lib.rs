@@ -0,0 +1,32 @@
+//! The application behind the server,
+//! a library of its own so the verifier can run it in-process
+use telemetry::stats::Metered;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+/// Reverses every line of the session until the client closes it
+pub async fn handle_connection(conn: lrcp::LrcpStream) -> tokio::io::Result<()> {
+    tracing::debug!("session {}", conn.session_id());
+    let (reader, mut writer) = tokio::io::split(Metered::new(conn));
+    let mut reader = BufReader::new(reader);
+
+    loop {
+        let mut line = String::new();
+        let rcount = reader.read_line(&mut line).await?;
+        if rcount == 0 {
+            break;
+        }
+        telemetry::stats::request("line");
+
+        // remove the newline char
+        line.pop();
+        // reverse the line
+        let mut reversed_line = line.chars().rev().collect::<String>();
+        // add the new line back
+        reversed_line.push('\n');
+
+        // reverse the line and send it back
+        writer.write_all(reversed_line.as_bytes()).await?;
+    }
+
+    Ok(())
+}
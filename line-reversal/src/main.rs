@@ -1,39 +1,56 @@
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream};
+use line_reversal::handle_connection;
 
-mod lrcp;
+mod config;
+
+const USAGE: &str = "usage: line-reversal [connect <addr>]";
 
 #[tokio::main]
-async fn main() -> tokio::io::Result<()> {
-    let mut listener = lrcp::Listener::bind("0.0.0.0:3600").await?;
-    println!("listening on: {}", listener.local_addr());
+async fn main() -> anyhow::Result<()> {
+    let config = config::from_env()?;
+
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        None => {}
+        Some("connect") => {
+            let addr = args.next().ok_or_else(|| anyhow::anyhow!(USAGE))?;
+            return connect(&addr, config).await;
+        }
+        Some(_) => anyhow::bail!(USAGE),
+    }
+
+    // only the server logs, the client's stdout is taken by the session
+    telemetry::init();
+    if let Ok(addr) = std::env::var("METRICS_ADDR") {
+        if let Err(err) = telemetry::stats::install(&addr) {
+            tracing::error!("failed to start the metrics exporter: {}", err);
+        }
+    }
+
+    let mut listener = lrcp::Listener::bind("0.0.0.0:3600", config).await?;
+    tracing::info!("listening on: {}", listener.local_addr());
 
     loop {
         let conn = listener.accept().await?;
-        tokio::spawn(handle_connection(conn));
+        tokio::spawn(telemetry::connection(
+            conn.peer_addr(),
+            handle_connection(conn),
+        ));
     }
 }
 
-async fn handle_connection(conn: DuplexStream) -> tokio::io::Result<()> {
-    let (reader, mut writer) = tokio::io::split(conn);
-    let mut reader = BufReader::new(reader);
+// Talks to an LRCP server over stdin and stdout, until the server closes the session
+async fn connect(addr: &str, config: lrcp::Config) -> anyhow::Result<()> {
+    let conn = lrcp::connect(addr, config).await?;
+    let (mut reader, mut writer) = tokio::io::split(conn);
 
-    loop {
-        let mut line = String::new();
-        let rcount = reader.read_line(&mut line).await?;
-        if rcount == 0 {
-            break;
-        }
+    // the writer is kept open after stdin is exhausted, since closing it would close the session
+    tokio::spawn(async move {
+        tokio::io::copy(&mut tokio::io::stdin(), &mut writer).await?;
+        std::future::pending::<()>().await;
 
-        // remove the newline char
-        line.pop();
-        // reverse the line
-        let mut reversed_line = line.chars().rev().collect::<String>();
-        // add the new line back
-        reversed_line.push('\n');
-
-        // reverse the line and send it back
-        writer.write_all(reversed_line.as_bytes()).await?;
-    }
+        Ok::<(), std::io::Error>(())
+    });
 
+    tokio::io::copy(&mut reader, &mut tokio::io::stdout()).await?;
     Ok(())
 }
@@ -0,0 +1,68 @@
+use std::{fmt::Debug, future::Future, pin::Pin, sync::Arc};
+
+use tokio::time::Instant;
+
+/// A pending sleep of a clock
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+/// A clock that is shared by all the sessions of a listener (or a connector)
+pub type SharedClock = Arc<dyn Clock>;
+
+/// The source of time for the retransmission, expiry and keep-alive timers
+///
+/// the transport never reads the time or sleeps without going through its clock,
+/// so tests can drive the timers with a virtual clock instead of waiting for them
+pub trait Clock: Send + Sync + Debug {
+    fn now(&self) -> Instant;
+
+    fn sleep_until(&self, deadline: Instant) -> Sleep;
+}
+
+/// The real clock, backed by the tokio timers
+#[derive(Debug, Default)]
+pub struct TokioClock;
+
+impl Clock for TokioClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> Sleep {
+        Box::pin(tokio::time::sleep_until(deadline))
+    }
+}
+
+/// A clock that only moves when it's advanced
+#[cfg(test)]
+#[derive(Debug)]
+pub struct VirtualClock {
+    now: tokio::sync::watch::Sender<Instant>,
+}
+
+#[cfg(test)]
+impl VirtualClock {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            now: tokio::sync::watch::Sender::new(Instant::now()),
+        })
+    }
+
+    /// Moves the time forward, waking every sleeper whose deadline has passed
+    pub fn advance(&self, duration: std::time::Duration) {
+        self.now.send_modify(|now| *now += duration);
+    }
+}
+
+#[cfg(test)]
+impl Clock for VirtualClock {
+    fn now(&self) -> Instant {
+        *self.now.borrow()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> Sleep {
+        let mut now = self.now.subscribe();
+        Box::pin(async move {
+            // the sender lives as long as the clock, which outlives every sleeper
+            let _ = now.wait_for(|now| *now >= deadline).await;
+        })
+    }
+}
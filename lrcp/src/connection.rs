@@ -0,0 +1,734 @@
+use std::{collections::VecDeque, future::Future, net::SocketAddr, sync::Arc, time::Duration};
+
+use anyhow::Context;
+use tokio::{
+    net::UdpSocket,
+    sync::{mpsc, Mutex},
+    time::Instant,
+};
+
+use crate::CLOSE_TIMEOUT;
+
+use super::{
+    clock::SharedClock,
+    message::Message,
+    rtt::RttEstimator,
+    spec::{self, AckCheck, DataCheck},
+    stream::LrcpStream,
+    Config, KeepAlive, Rto,
+};
+
+// when the buffer is full, the server is expected to drop messages
+// allowing the client to re-transmit at a later time (no ack is sent)
+const CONNECTION_INCOMING_BUFFER_SIZE: usize = 128;
+
+const INTERNAL_BUFFER_SIZE: usize = 128;
+
+#[derive(Debug)]
+enum InternalMessage {
+    Ack { len: u32 },
+    Data { position: u32, text: String },
+}
+
+#[derive(Debug, Clone)]
+struct Connection {
+    socket: Arc<UdpSocket>,
+    addr: SocketAddr,
+    session: u32,
+    // the last time we've heard from the peer
+    last_seen: Arc<Mutex<Instant>>,
+    // the last time data has moved, either way
+    last_active: Arc<Mutex<Instant>>,
+    clock: SharedClock,
+}
+
+pub(super) fn spawn(
+    socket: Arc<UdpSocket>,
+    addr: SocketAddr,
+    session: u32,
+    config: Config,
+    clock: SharedClock,
+) -> (Handler, LrcpStream) {
+    // the receiver is dropped once the session has terminated
+    let (terminated, session_alive) = mpsc::channel(1);
+
+    let (tx, mut from_listener) = mpsc::channel(CONNECTION_INCOMING_BUFFER_SIZE);
+    let listener_handler = Handler {
+        sender: tx,
+        terminated: terminated.clone(),
+        addr,
+    };
+
+    let (send_data_from_client, receive_data_from_client) = mpsc::channel(1);
+    let (send_data_to_client, receive_data_to_client) = mpsc::channel(INTERNAL_BUFFER_SIZE);
+    let (send_ack, receive_ack) = mpsc::unbounded_channel();
+
+    let connection = Connection {
+        socket,
+        addr,
+        session,
+        last_seen: Arc::new(Mutex::new(clock.now())),
+        last_active: Arc::new(Mutex::new(clock.now())),
+        clock,
+    };
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = listen_to_server(connection.clone(), &mut from_listener, send_data_to_client, send_ack) => {},
+            _ = data_sender(connection.clone(), config, receive_data_from_client, receive_ack) => {},
+            _ = probe_peer(connection.clone(), config.keepalive) => {},
+            _ = expire_idle(connection.clone(), config.idle_timeout) => {},
+        };
+
+        close(&connection, config.rto, &mut from_listener).await;
+        drop(session_alive);
+    });
+
+    let stream = LrcpStream::new(
+        session,
+        addr,
+        receive_data_to_client,
+        send_data_from_client,
+        terminated,
+    );
+    (listener_handler, stream)
+}
+
+// Sends a close message until the peer closes its side too,
+// which drops the listener's handler of the session
+async fn close(
+    connection: &Connection,
+    rto: Rto,
+    from_server: &mut mpsc::Receiver<InternalMessage>,
+) {
+    let message = Message::close(connection.session).to_string();
+
+    let mut retry_at = connection.clock.now();
+    let give_up_at = retry_at + CLOSE_TIMEOUT;
+    loop {
+        tokio::select! {
+            biased;
+
+            _ = connection.clock.sleep_until(give_up_at) => return,
+            // anything but the peer closing the session is ignored
+            message = from_server.recv() => {
+                if message.is_none() {
+                    return;
+                }
+            }
+            _ = connection.clock.sleep_until(retry_at) => {
+                let _ = connection.socket.send_to(message.as_bytes(), connection.addr).await;
+                retry_at += rto.initial;
+            }
+        }
+    }
+}
+
+async fn listen_to_server(
+    connection: Connection,
+    from_server: &mut mpsc::Receiver<InternalMessage>,
+    data_to_client: mpsc::Sender<String>,
+    send_ack: mpsc::UnboundedSender<u32>,
+) -> anyhow::Result<()> {
+    let mut ack = 0;
+    while let Some(message) = from_server.recv().await {
+        *connection.last_seen.lock().await = connection.clock.now();
+
+        match message {
+            InternalMessage::Ack { len } => {
+                send_ack
+                    .send(len)
+                    .context("the ack channel should live as long as the connection is open")?;
+            }
+            InternalMessage::Data { position, text } => {
+                match spec::check_data(position, &text, ack) {
+                    DataCheck::Fresh("") | DataCheck::Gap => {}
+                    DataCheck::Fresh(fresh) => {
+                        match data_to_client.try_send(fresh.to_string()) {
+                            Ok(_) => {
+                                // data was sent succesfully
+                            }
+                            Err(mpsc::error::TrySendError::Full(_)) => {
+                                // internal buffer is full, simply ignore this message
+                                // and let the client re-transmit it again, when hopefully
+                                // some space in the buffer will be freed
+                                continue;
+                            }
+                            // client was terminated
+                            Err(mpsc::error::TrySendError::Closed(_)) => return Ok(()),
+                        }
+
+                        ack += fresh.len() as u32;
+                        *connection.last_active.lock().await = connection.clock.now();
+                    }
+                    // the client is misbehaving, terminate the connection
+                    DataCheck::Misbehaving => return Ok(()),
+                }
+
+                // send an ack of what we've received so far
+                connection
+                    .socket
+                    .send_to(
+                        Message::ack(connection.session, ack).to_string().as_bytes(),
+                        connection.addr,
+                    )
+                    .await?;
+            }
+        }
+    }
+
+    // the server has closed the connection
+    Ok(())
+}
+
+// An unacked data message
+struct Segment {
+    position: u32,
+    data: String,
+    retry_at: Instant,
+    // when the segment was first sent, none until then
+    sent_at: Option<Instant>,
+    // the ack of a retransmitted segment isn't used to measure the round-trip time
+    retransmitted: bool,
+}
+
+impl Segment {
+    fn end(&self) -> u32 {
+        self.position + self.data.len() as u32
+    }
+}
+
+// Sends the data of the client, keeping up to a window worth of unacked bytes in flight
+//
+// every segment is retransmitted on its own timer until it's acked,
+// acks are cumulative and may acknowledge a segment only partially.
+// the timers adapt to the round-trip time measured from the acks
+async fn data_sender(
+    connection: Connection,
+    config: Config,
+    mut receive_data: mpsc::Receiver<String>,
+    mut receive_ack: mpsc::UnboundedReceiver<u32>,
+) -> anyhow::Result<()> {
+    let mut in_flight: VecDeque<Segment> = VecDeque::new();
+    let mut position: u32 = 0;
+    let mut ack: u32 = 0;
+    let mut client_closed = false;
+    let mut rtt = RttEstimator::new(config.rto);
+    // the session expires when the peer stops acking the data in flight
+    let mut expire_at = connection.clock.now() + config.session_expiry;
+
+    loop {
+        if client_closed && in_flight.is_empty() {
+            // the client handler was dropped, and all of its data was delivered
+            // terminate the connection
+            return Ok(());
+        }
+
+        let retry_at = in_flight
+            .iter()
+            .map(|segment| segment.retry_at)
+            .min()
+            .unwrap_or(expire_at);
+
+        tokio::select! {
+            // when several branches are ready, the session expires before it handles acks,
+            // and handles acks before it retransmits
+            biased;
+
+            // client has disconnected
+            _ = connection.clock.sleep_until(expire_at), if !in_flight.is_empty() => return Ok(()),
+            Some(ack_len) = receive_ack.recv() => {
+                match spec::check_ack(ack_len, ack, position) {
+                    AckCheck::New => {}
+                    AckCheck::Duplicate => continue,
+                    // client is misbehaving
+                    AckCheck::Misbehaving => return Ok(()),
+                }
+
+                ack = ack_len;
+                let now = connection.clock.now();
+                expire_at = now + config.session_expiry;
+                // the peer has taken more of our data, so data has moved
+                *connection.last_active.lock().await = now;
+
+                // the latest segment that was acked on its first transmission
+                let mut sent_at = None;
+                while in_flight.front().is_some_and(|segment| segment.end() <= ack) {
+                    let segment = in_flight.pop_front().unwrap();
+                    if !segment.retransmitted {
+                        sent_at = segment.sent_at.or(sent_at);
+                    }
+                }
+
+                match sent_at {
+                    Some(sent_at) => rtt.sample(now - sent_at),
+                    None => rtt.recover(),
+                }
+
+                // the peer is making progress, restart the timers of the segments it hasn't acked yet
+                for segment in in_flight.iter_mut().filter(|segment| segment.sent_at.is_some()) {
+                    segment.retry_at = now + rtt.timeout();
+                }
+
+                if let Some(segment) = in_flight.front_mut().filter(|segment| segment.position < ack) {
+                    // retransmit the rest of a partially acked segment right away,
+                    // an ack that splits a character is misbehaving like one past the data
+                    let Some(rest) = segment.data.get((ack - segment.position) as usize..) else {
+                        return Ok(());
+                    };
+                    segment.data = rest.into();
+                    segment.position = ack;
+                    segment.retransmitted = true;
+                    segment.retry_at = now + rtt.timeout();
+
+                    let message = Message::data(connection.session, segment.position, segment.data.clone());
+                    connection.socket.send_to(message.to_string().as_bytes(), connection.addr).await?;
+                }
+            },
+            _ = connection.clock.sleep_until(retry_at), if !in_flight.is_empty() => {
+                let now = connection.clock.now();
+                // the peer drops anything past the first segment it's missing,
+                // so only the expiry of the oldest segment means the timeout was too short
+                let expired = in_flight
+                    .front()
+                    .is_some_and(|segment| segment.retry_at <= now && segment.sent_at.is_some());
+                if expired {
+                    rtt.back_off();
+                }
+
+                for segment in in_flight.iter_mut().filter(|segment| segment.retry_at <= now) {
+                    let message = Message::data(connection.session, segment.position, segment.data.clone());
+                    connection.socket.send_to(message.to_string().as_bytes(), connection.addr).await?;
+                    if segment.sent_at.is_some() {
+                        segment.retransmitted = true;
+                    } else {
+                        segment.sent_at = Some(now);
+                    }
+                    segment.retry_at = now + rtt.timeout();
+                }
+            }
+            data = receive_data.recv(), if !client_closed && position - ack < config.window => {
+                let Some(data) = data else {
+                    client_closed = true;
+                    continue;
+                };
+
+                if in_flight.is_empty() {
+                    expire_at = connection.clock.now() + config.session_expiry;
+                }
+                *connection.last_active.lock().await = connection.clock.now();
+
+                // the first transmission is sent right away
+                let segment = Segment {
+                    position,
+                    data,
+                    retry_at: connection.clock.now(),
+                    sent_at: None,
+                    retransmitted: false,
+                };
+                position = segment.end();
+                in_flight.push_back(segment);
+            }
+        };
+    }
+}
+
+// Probes the peer when the session is idle, and returns once the peer stopped responding
+//
+// never returns when keep-alive is disabled
+async fn probe_peer(connection: Connection, keepalive: Option<KeepAlive>) -> anyhow::Result<()> {
+    let Some(keepalive) = keepalive else {
+        return std::future::pending().await;
+    };
+
+    // a zero-length data message at position 0 is always acked by the peer,
+    // without affecting the state of the session
+    let probe = Message::data(connection.session, 0, String::new()).to_string();
+
+    let mut check_at = connection.clock.now();
+    loop {
+        check_at += keepalive.interval;
+        connection.clock.sleep_until(check_at).await;
+
+        let idle = connection.clock.now() - *connection.last_seen.lock().await;
+        if idle >= keepalive.timeout {
+            // the peer stopped responding, reclaim the session
+            return Ok(());
+        }
+
+        if idle >= keepalive.interval {
+            connection
+                .socket
+                .send_to(probe.as_bytes(), connection.addr)
+                .await?;
+        }
+    }
+}
+
+// Returns once the session hasn't moved any data for the idle timeout
+//
+// never returns when there's no idle timeout
+async fn expire_idle(connection: Connection, idle_timeout: Option<Duration>) {
+    let Some(idle_timeout) = idle_timeout else {
+        return std::future::pending().await;
+    };
+
+    loop {
+        let expire_at = *connection.last_active.lock().await + idle_timeout;
+        if connection.clock.now() >= expire_at {
+            return;
+        }
+
+        connection.clock.sleep_until(expire_at).await;
+    }
+}
+
+pub(super) struct BufferIsFull;
+
+// Handler for the listener (or the connector) to send incoming messages
+pub(super) struct Handler {
+    sender: mpsc::Sender<InternalMessage>,
+    terminated: mpsc::Sender<()>,
+    addr: SocketAddr,
+}
+
+impl Handler {
+    pub(super) fn ack(&self, len: u32) -> Result<(), BufferIsFull> {
+        self.sender
+            .try_send(InternalMessage::Ack { len })
+            .map_err(|_| BufferIsFull)
+    }
+
+    pub(super) fn data(&self, position: u32, text: String) -> Result<(), BufferIsFull> {
+        self.sender
+            .try_send(InternalMessage::Data { position, text })
+            .map_err(|_| BufferIsFull)
+    }
+
+    pub(super) fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Resolves once the connection has terminated
+    pub(super) fn closed(&self) -> impl Future<Output = ()> + Send + 'static {
+        let terminated = self.terminated.clone();
+        async move { terminated.closed().await }
+    }
+
+    pub(super) fn is_closed(&self) -> bool {
+        self.terminated.is_closed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+    use tokio::{io::AsyncWriteExt, net::UdpSocket};
+
+    use crate::{clock::VirtualClock, Config, KeepAlive, Listener, LrcpStream, Rto, CLOSE_TIMEOUT};
+
+    // how long to wait for a packet that should have been sent, in real time
+    const RECV_TIMEOUT: Duration = Duration::from_secs(5);
+
+    struct Peer {
+        socket: UdpSocket,
+        server: SocketAddr,
+    }
+
+    impl Peer {
+        async fn send(&self, message: &str) {
+            self.socket
+                .send_to(message.as_bytes(), self.server)
+                .await
+                .unwrap();
+        }
+
+        async fn recv(&self) -> String {
+            let mut packet = [0; 1000];
+            let len = tokio::time::timeout(RECV_TIMEOUT, self.socket.recv(&mut packet))
+                .await
+                .expect("the server should have sent a packet")
+                .unwrap();
+
+            String::from_utf8(packet[..len].to_vec()).unwrap()
+        }
+    }
+
+    async fn connect(config: Config, clock: Arc<VirtualClock>) -> (Listener, LrcpStream, Peer) {
+        let mut listener = Listener::bind_with_clock("127.0.0.1:0", config, clock)
+            .await
+            .unwrap();
+        let peer = Peer {
+            socket: UdpSocket::bind("127.0.0.1:0").await.unwrap(),
+            server: listener.local_addr(),
+        };
+
+        peer.send("/connect/12345/").await;
+        assert_eq!(peer.recv().await, "/ack/12345/0/");
+        let conn = listener.accept().await.unwrap();
+
+        (listener, conn, peer)
+    }
+
+    #[tokio::test]
+    async fn retransmit_until_acked() {
+        let clock = VirtualClock::new();
+        // a single line fills the window, so the next one is only sent once it's acked
+        let config = Config {
+            window: 6,
+            ..Default::default()
+        };
+        let (_listener, mut conn, peer) = connect(config, clock.clone()).await;
+
+        conn.write_all(b"hello\n").await.unwrap();
+        assert_eq!(peer.recv().await, "/data/12345/0/hello\n/");
+
+        // the timeout doubles on every retransmission
+        let mut timeout = config.rto.initial;
+        for _ in 0..3 {
+            clock.advance(timeout);
+            assert_eq!(peer.recv().await, "/data/12345/0/hello\n/");
+            timeout *= 2;
+        }
+
+        // once acked, the data is never sent again
+        peer.send("/ack/12345/6/").await;
+        conn.write_all(b"world\n").await.unwrap();
+        assert_eq!(peer.recv().await, "/data/12345/6/world\n/");
+        // acking new data drops the back off
+        clock.advance(config.rto.initial);
+        assert_eq!(peer.recv().await, "/data/12345/6/world\n/");
+    }
+
+    #[tokio::test]
+    async fn keep_a_window_in_flight() {
+        let clock = VirtualClock::new();
+        let config = Config {
+            window: 10,
+            ..Default::default()
+        };
+        let (_listener, mut conn, peer) = connect(config, clock.clone()).await;
+
+        conn.write_all(b"hello\n").await.unwrap();
+        assert_eq!(peer.recv().await, "/data/12345/0/hello\n/");
+        conn.write_all(b"world\n").await.unwrap();
+        assert_eq!(peer.recv().await, "/data/12345/6/world\n/");
+
+        // the window is full, until some of it is acked
+        conn.write_all(b"again\n").await.unwrap();
+        peer.send("/ack/12345/6/").await;
+        assert_eq!(peer.recv().await, "/data/12345/12/again\n/");
+
+        // only the unacked segments are retransmitted
+        clock.advance(config.rto.initial);
+        assert_eq!(peer.recv().await, "/data/12345/6/world\n/");
+        assert_eq!(peer.recv().await, "/data/12345/12/again\n/");
+
+        // a partial ack retransmits the rest of the segment right away
+        peer.send("/ack/12345/9/").await;
+        assert_eq!(peer.recv().await, "/data/12345/9/ld\n/");
+    }
+
+    #[tokio::test]
+    async fn adapt_to_the_round_trip_time() {
+        let clock = VirtualClock::new();
+        let config = Config {
+            rto: Rto {
+                initial: Duration::from_secs(1),
+                min: Duration::from_millis(10),
+                max: Duration::from_secs(5),
+            },
+            ..Default::default()
+        };
+        let (_listener, mut conn, peer) = connect(config, clock.clone()).await;
+
+        conn.write_all(b"hello\n").await.unwrap();
+        assert_eq!(peer.recv().await, "/data/12345/0/hello\n/");
+        clock.advance(Duration::from_millis(40));
+        peer.send("/ack/12345/6/").await;
+        // make sure the ack was handled before moving on
+        peer.send("/data/12345/0//").await;
+        assert_eq!(peer.recv().await, "/ack/12345/0/");
+
+        // a 40ms round-trip makes for a 40ms + 4 * 20ms timeout
+        conn.write_all(b"world\n").await.unwrap();
+        assert_eq!(peer.recv().await, "/data/12345/6/world\n/");
+        clock.advance(Duration::from_millis(120));
+        assert_eq!(peer.recv().await, "/data/12345/6/world\n/");
+    }
+
+    #[tokio::test]
+    async fn expire_silent_sessions() {
+        let clock = VirtualClock::new();
+        let config = Config {
+            session_expiry: Duration::from_secs(20),
+            ..Default::default()
+        };
+        let (_listener, mut conn, peer) = connect(config, clock.clone()).await;
+
+        conn.write_all(b"hello\n").await.unwrap();
+        assert_eq!(peer.recv().await, "/data/12345/0/hello\n/");
+
+        // every retransmission up to the expiry is sent
+        let rto = config.rto;
+        let mut timeout = rto.initial;
+        let mut elapsed = timeout;
+        while elapsed < config.session_expiry {
+            clock.advance(timeout);
+            assert_eq!(peer.recv().await, "/data/12345/0/hello\n/");
+            timeout = (timeout * 2).min(rto.max);
+            elapsed += timeout;
+        }
+
+        clock.advance(timeout);
+        assert_eq!(peer.recv().await, "/close/12345/");
+    }
+
+    #[tokio::test]
+    async fn probe_idle_sessions() {
+        let clock = VirtualClock::new();
+        let keepalive = KeepAlive {
+            interval: Duration::from_secs(5),
+            timeout: Duration::from_secs(12),
+        };
+        let config = Config {
+            keepalive: Some(keepalive),
+            ..Default::default()
+        };
+        let (_listener, _conn, peer) = connect(config, clock.clone()).await;
+
+        // an answered probe keeps the session alive
+        clock.advance(keepalive.interval);
+        assert_eq!(peer.recv().await, "/data/12345/0//");
+        peer.send("/ack/12345/0/").await;
+        // make sure the ack was handled before moving on
+        peer.send("/data/12345/0//").await;
+        assert_eq!(peer.recv().await, "/ack/12345/0/");
+
+        // an unanswered one doesn't
+        clock.advance(keepalive.interval);
+        assert_eq!(peer.recv().await, "/data/12345/0//");
+        clock.advance(keepalive.interval);
+        assert_eq!(peer.recv().await, "/data/12345/0//");
+        clock.advance(keepalive.interval);
+        assert_eq!(peer.recv().await, "/close/12345/");
+    }
+
+    #[tokio::test]
+    async fn expire_idle_sessions() {
+        let clock = VirtualClock::new();
+        // nothing is retransmitted while the clock is advanced
+        let rto = Duration::from_secs(60);
+        let config = Config {
+            idle_timeout: Some(Duration::from_secs(30)),
+            rto: Rto {
+                initial: rto,
+                min: rto,
+                max: rto,
+            },
+            ..Default::default()
+        };
+        let (_listener, mut conn, peer) = connect(config, clock.clone()).await;
+
+        // data moving either way keeps the session alive
+        clock.advance(Duration::from_secs(20));
+        peer.send("/data/12345/0/hi\n/").await;
+        assert_eq!(peer.recv().await, "/ack/12345/3/");
+        clock.advance(Duration::from_secs(20));
+        conn.write_all(b"hello\n").await.unwrap();
+        assert_eq!(peer.recv().await, "/data/12345/0/hello\n/");
+
+        // as does an ack of data that wasn't acked before
+        clock.advance(Duration::from_secs(20));
+        peer.send("/ack/12345/6/").await;
+        peer.send("/data/12345/3//").await;
+        assert_eq!(peer.recv().await, "/ack/12345/3/");
+        clock.advance(Duration::from_secs(20));
+        peer.send("/data/12345/3//").await;
+        assert_eq!(peer.recv().await, "/ack/12345/3/");
+
+        // probes and repeated acks don't
+        peer.send("/ack/12345/6/").await;
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(peer.recv().await, "/close/12345/");
+    }
+
+    #[tokio::test]
+    async fn shutdown_waits_for_peer_close() {
+        let clock = VirtualClock::new();
+        let (_listener, mut conn, peer) = connect(Config::default(), clock.clone()).await;
+        assert_eq!(conn.session_id(), 12345);
+
+        // the data in flight is delivered before the session is closed
+        conn.write_all(b"hello\n").await.unwrap();
+        assert_eq!(peer.recv().await, "/data/12345/0/hello\n/");
+        let shutdown = tokio::spawn(async move { conn.shutdown().await });
+        peer.send("/ack/12345/6/").await;
+        assert_eq!(peer.recv().await, "/close/12345/");
+        clock.advance(Config::default().rto.initial);
+        assert_eq!(peer.recv().await, "/close/12345/");
+        assert!(!shutdown.is_finished());
+
+        peer.send("/close/12345/").await;
+        assert_eq!(peer.recv().await, "/close/12345/");
+        tokio::time::timeout(RECV_TIMEOUT, shutdown)
+            .await
+            .expect("the shutdown should have completed")
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn forget_dropped_sessions() {
+        let clock = VirtualClock::new();
+        let (_listener, conn, peer) = connect(Config::default(), clock.clone()).await;
+
+        // the peer never answers the close message
+        drop(conn);
+        assert_eq!(peer.recv().await, "/close/12345/");
+        clock.advance(CLOSE_TIMEOUT);
+
+        // the session is eventually forgotten, and treated like any unknown session
+        let forgotten = async {
+            loop {
+                peer.send("/data/12345/0/hello\n/").await;
+                let reply = tokio::time::timeout(Duration::from_millis(50), peer.recv()).await;
+                if reply.is_ok_and(|reply| reply == "/close/12345/") {
+                    break;
+                }
+            }
+        };
+        tokio::time::timeout(RECV_TIMEOUT, forgotten)
+            .await
+            .expect("the session should have been forgotten");
+    }
+
+    #[tokio::test]
+    async fn close_misbehaving_sessions() {
+        let clock = VirtualClock::new();
+
+        // acking data that was never sent
+        let (_listener, mut conn, peer) = connect(Config::default(), clock.clone()).await;
+        conn.write_all(b"hello\n").await.unwrap();
+        assert_eq!(peer.recv().await, "/data/12345/0/hello\n/");
+        peer.send("/ack/12345/7/").await;
+        assert_eq!(peer.recv().await, "/close/12345/");
+
+        // acking part of a character that was sent
+        let (_listener, mut conn, peer) = connect(Config::default(), clock.clone()).await;
+        conn.write_all("café\n".as_bytes()).await.unwrap();
+        assert_eq!(peer.recv().await, "/data/12345/0/café\n/");
+        peer.send("/ack/12345/4/").await;
+        assert_eq!(peer.recv().await, "/close/12345/");
+
+        // resending data that splits a character that was already received in part
+        let (_listener, _conn, peer) = connect(Config::default(), clock.clone()).await;
+        peer.send("/data/12345/0/é/").await;
+        assert_eq!(peer.recv().await, "/ack/12345/2/");
+        // a gap is only answered with what was received so far
+        peer.send("/data/12345/10/abc/").await;
+        assert_eq!(peer.recv().await, "/ack/12345/2/");
+        peer.send("/data/12345/1/éa/").await;
+        assert_eq!(peer.recv().await, "/close/12345/");
+    }
+}
@@ -0,0 +1,238 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+};
+
+use tokio::net::{ToSocketAddrs, UdpSocket};
+
+use super::{
+    clock::{SharedClock, TokioClock},
+    connection::{self, Handler},
+    message::{Message, MessageType, MAX_NUMBER},
+    spec,
+    stream::LrcpStream,
+    Config, MAX_MESSAGE_SIZE,
+};
+
+/// Opens a session with the server, returns the stream of the session
+///
+/// the session is closed once the stream is shut down or dropped
+pub async fn connect<A>(addr: A, config: Config) -> io::Result<LrcpStream>
+where
+    A: ToSocketAddrs,
+{
+    connect_with_clock(addr, config, Arc::new(TokioClock)).await
+}
+
+/// Opens a session with the server, with all of its timers driven by the clock
+pub async fn connect_with_clock<A>(
+    addr: A,
+    config: Config,
+    clock: SharedClock,
+) -> io::Result<LrcpStream>
+where
+    A: ToSocketAddrs,
+{
+    let peer = tokio::net::lookup_host(addr)
+        .await?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address to connect to"))?;
+
+    let local: SocketAddr = match peer {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = Arc::new(UdpSocket::bind(local).await?);
+
+    let session = new_session();
+    handshake(&socket, peer, session, config, &clock).await?;
+
+    let (handler, stream) = connection::spawn(socket.clone(), peer, session, config, clock);
+    tokio::spawn(receive(socket, peer, session, handler));
+
+    Ok(stream)
+}
+
+// every process hashes with its own random keys, which makes for a good enough random id
+fn new_session() -> u32 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u32(std::process::id());
+    (hasher.finish() % MAX_NUMBER as u64) as u32
+}
+
+// sends a connect message until the server acks it, or the session expires
+async fn handshake(
+    socket: &UdpSocket,
+    peer: SocketAddr,
+    session: u32,
+    config: Config,
+    clock: &SharedClock,
+) -> io::Result<()> {
+    let connect = Message::connect(session).to_string();
+
+    let mut retry_at = clock.now();
+    let expire_at = retry_at + config.session_expiry;
+
+    let mut packet = [0; MAX_MESSAGE_SIZE];
+    loop {
+        tokio::select! {
+            biased;
+
+            _ = clock.sleep_until(expire_at) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "the server didn't accept the session",
+                ));
+            }
+            received = socket.recv_from(&mut packet) => {
+                let (len, addr) = received?;
+                match spec::decode(&packet[..len]) {
+                    Some(message) if addr == peer && message.session == session => {
+                        match message.ty {
+                            MessageType::Ack { length: 0 } => return Ok(()),
+                            MessageType::Close => {
+                                return Err(io::Error::new(
+                                    io::ErrorKind::ConnectionRefused,
+                                    "the server closed the session",
+                                ));
+                            }
+                            _ => {}
+                        }
+                    }
+                    _ => {} // not ours, ignore it
+                }
+            }
+            _ = clock.sleep_until(retry_at) => {
+                socket.send_to(connect.as_bytes(), peer).await?;
+                retry_at += config.rto.initial;
+            }
+        }
+    }
+}
+
+// feeds the messages of the session to the connection, until either side closes it
+async fn receive(
+    socket: Arc<UdpSocket>,
+    peer: SocketAddr,
+    session: u32,
+    handler: Handler,
+) -> io::Result<()> {
+    let mut packet = [0; MAX_MESSAGE_SIZE];
+    loop {
+        let (len, addr) = tokio::select! {
+            _ = handler.closed() => return Ok(()),
+            received = socket.recv_from(&mut packet) => received?,
+        };
+
+        let Some(message) = spec::decode(&packet[..len]) else {
+            continue; // badly formated message, ignore it
+        };
+        if addr != peer || message.session != session {
+            continue;
+        }
+
+        match message.ty {
+            // if the buffer is full, allow the server retransmit
+            MessageType::Ack { length } => {
+                let _ = handler.ack(length);
+            }
+            MessageType::Data { position, data } => {
+                let _ = handler.data(position, data);
+            }
+            // dropping the handler closes the connection
+            MessageType::Close => return Ok(()),
+            // only servers accept sessions
+            MessageType::Connect => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::UdpSocket,
+    };
+
+    use crate::{
+        clock::VirtualClock,
+        connector,
+        simulator::{self, Faults},
+        Config, Listener,
+    };
+
+    #[tokio::test]
+    async fn talk_over_lossy_link() {
+        let mut listener = Listener::bind("127.0.0.1:0", Config::default())
+            .await
+            .unwrap();
+        let faults = Faults {
+            loss: 0.3,
+            ..Default::default()
+        };
+        let relay = simulator::link(listener.local_addr(), faults).await;
+
+        // echo everything back
+        tokio::spawn(async move {
+            let conn = listener.accept().await.unwrap();
+            let (mut reader, mut writer) = tokio::io::split(conn);
+            tokio::io::copy(&mut reader, &mut writer).await.unwrap();
+        });
+
+        let conn = connector::connect(relay, Config::default()).await.unwrap();
+        let (reader, mut writer) = tokio::io::split(conn);
+        let mut lines = BufReader::new(reader).lines();
+
+        for idx in 0..10 {
+            let line = format!("line number {} with a \\ and a /", idx);
+            writer
+                .write_all(format!("{}\n", line).as_bytes())
+                .await
+                .unwrap();
+
+            let echoed = tokio::time::timeout(Duration::from_secs(5), lines.next_line())
+                .await
+                .expect("the line should have been echoed")
+                .unwrap();
+            assert_eq!(echoed, Some(line));
+        }
+    }
+
+    #[tokio::test]
+    async fn retry_the_handshake() {
+        let clock = VirtualClock::new();
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let connecting = tokio::spawn(connector::connect_with_clock(
+            server.local_addr().unwrap(),
+            Config::default(),
+            clock.clone(),
+        ));
+
+        let mut packet = [0; 1000];
+        let (len, _) = server.recv_from(&mut packet).await.unwrap();
+        let connect = String::from_utf8(packet[..len].to_vec()).unwrap();
+        assert!(connect.starts_with("/connect/"));
+
+        // the first connect is lost
+        clock.advance(Config::default().rto.initial);
+        let (len, client) = server.recv_from(&mut packet).await.unwrap();
+        assert_eq!(packet[..len], *connect.as_bytes());
+
+        let session = connect.trim_matches('/').split('/').nth(1).unwrap();
+        let ack = format!("/ack/{}/0/", session);
+        server.send_to(ack.as_bytes(), client).await.unwrap();
+        let mut conn = connecting.await.unwrap().unwrap();
+
+        conn.write_all(b"hi\n").await.unwrap();
+        let (len, _) = server.recv_from(&mut packet).await.unwrap();
+        assert_eq!(
+            packet[..len],
+            *format!("/data/{}/0/hi\n/", session).as_bytes()
+        );
+    }
+}
@@ -0,0 +1,96 @@
+//! The Line Reversal Control Protocol (LRCP), a reliable ordered byte stream over UDP
+//!
+//! a server binds a [`Listener`] and accepts sessions from it, a client opens one with [`connect`].
+//! either way, a session is handed out as an [`LrcpStream`] that implements `AsyncRead` and `AsyncWrite`.
+//! the transport is tuned through a [`Config`].
+//!
+//! see: https://protohackers.com/problem/7
+use std::time::Duration;
+
+// how long a closing session waits for the peer to close its side too
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(3);
+const MAX_MESSAGE_SIZE: usize = 1000;
+
+// internal limitation to make sure we're within the max_message_size,
+// applies to the data once it's escaped
+const MAX_DATA_SIZE: usize = 910;
+const DEFAULT_WINDOW: u32 = 16 * MAX_DATA_SIZE as u32;
+const DEFAULT_SESSION_EXPIRY: Duration = Duration::from_secs(60);
+const DEFAULT_RTO: Rto = Rto {
+    initial: Duration::from_millis(100),
+    min: Duration::from_millis(50),
+    max: Duration::from_secs(5),
+};
+
+pub mod clock;
+mod connection;
+mod connector;
+mod listener;
+mod message;
+mod rtt;
+#[cfg(test)]
+mod simulator;
+mod spec;
+mod stream;
+
+pub use connector::{connect, connect_with_clock};
+pub use listener::Listener;
+pub use stream::LrcpStream;
+
+/// Tunables of the LRCP transport
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    /// Probe idle sessions, disabled by default
+    pub keepalive: Option<KeepAlive>,
+    /// Close the sessions that haven't moved any data (either way) for this long,
+    /// even while their peers answer the probes. disabled by default
+    pub idle_timeout: Option<Duration>,
+    /// The number of concurrent sessions a listener accepts,
+    /// new sessions beyond it are closed right away. unlimited by default
+    pub max_sessions: Option<usize>,
+    /// The number of unacked bytes a session keeps in flight,
+    /// a new data message is only sent while the window isn't full
+    pub window: u32,
+    /// Bounds of the retransmission timeout, which adapts to the round-trip time of each session
+    pub rto: Rto,
+    /// How long a session waits for the peer to ack its data (or the handshake) before giving up
+    pub session_expiry: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            keepalive: None,
+            idle_timeout: None,
+            max_sessions: None,
+            window: DEFAULT_WINDOW,
+            rto: DEFAULT_RTO,
+            session_expiry: DEFAULT_SESSION_EXPIRY,
+        }
+    }
+}
+
+/// Server-side keep-alive probing of idle sessions
+///
+/// a probe is a zero-length data message at position 0, which the peer
+/// must answer with an ack, and is invisible to the application on both ends.
+#[derive(Debug, Clone, Copy)]
+pub struct KeepAlive {
+    /// How long a session may be idle before it's probed
+    pub interval: Duration,
+    /// How long a peer may stay silent before its session is reclaimed,
+    /// should be shorter than the session expiry to be useful
+    pub timeout: Duration,
+}
+
+/// Bounds of the adaptive retransmission timeout
+///
+/// the handshake and the close message are always retransmitted on the initial timeout,
+/// since there's no round-trip to measure before a session is established
+#[derive(Debug, Clone, Copy)]
+pub struct Rto {
+    /// Used until the first round-trip of a session is measured
+    pub initial: Duration,
+    pub min: Duration,
+    pub max: Duration,
+}
@@ -0,0 +1,275 @@
+use std::{
+    collections::{hash_map, HashMap},
+    net::SocketAddr,
+    sync::Arc,
+};
+
+use tokio::{
+    net::{ToSocketAddrs, UdpSocket},
+    sync::mpsc,
+};
+
+use super::{
+    clock::{SharedClock, TokioClock},
+    connection::{self, Handler},
+    message::{Message, MessageType},
+    spec,
+    stream::LrcpStream,
+    Config, MAX_MESSAGE_SIZE,
+};
+
+/// Accepts LRCP sessions on a UDP socket
+///
+/// the sessions live on in the background as long as either the listener or their stream is alive
+pub struct Listener {
+    connections: mpsc::UnboundedReceiver<LrcpStream>,
+    local_addr: SocketAddr,
+}
+
+impl Listener {
+    /// Accepts a new session
+    pub async fn accept(&mut self) -> tokio::io::Result<LrcpStream> {
+        self.connections.recv().await.ok_or_else(|| {
+            tokio::io::Error::new(
+                tokio::io::ErrorKind::ConnectionAborted,
+                "the connection channel has been closed",
+            )
+        })
+    }
+
+    /// Binds a new listener to an address
+    pub async fn bind<A>(addr: A, config: Config) -> tokio::io::Result<Self>
+    where
+        A: ToSocketAddrs,
+    {
+        Self::bind_with_clock(addr, config, Arc::new(TokioClock)).await
+    }
+
+    /// Binds a new listener to an address, with all of its sessions timed by the clock
+    pub async fn bind_with_clock<A>(
+        addr: A,
+        config: Config,
+        clock: SharedClock,
+    ) -> tokio::io::Result<Self>
+    where
+        A: ToSocketAddrs,
+    {
+        // use unbounded channel in order to never block the background task in charge of new connections.
+        let (send_to_listener, rx) = mpsc::unbounded_channel();
+        let socket = Arc::new(UdpSocket::bind(addr).await?);
+        let local_addr = socket.local_addr()?;
+
+        tokio::spawn(async move {
+            let mut sessions: HashMap<u32, Handler> = HashMap::default();
+            // sessions that have terminated on their own, e.g. when their stream was dropped
+            let (send_terminated, mut terminated) = mpsc::unbounded_channel();
+
+            // for every new packet
+            let mut packet = [0; MAX_MESSAGE_SIZE];
+            while !send_to_listener.is_closed() || !sessions.is_empty() {
+                let (len, addr) = tokio::select! {
+                    Some(session) = terminated.recv() => {
+                        // the id might have been reused by a newer session since
+                        if sessions.get(&session).is_some_and(Handler::is_closed) {
+                            sessions.remove(&session);
+                        }
+                        continue;
+                    }
+                    received = socket.recv_from(&mut packet) => received?,
+                };
+
+                let Some(message) = spec::decode(&packet[..len]) else {
+                    continue; // invalid message, ignore it
+                };
+
+                // data and acks for a session that isn't open (anymore) are answered with a close
+                if !sessions.contains_key(&message.session) {
+                    if let Some(response) = spec::unknown_session(&message) {
+                        socket
+                            .send_to(response.to_string().as_bytes(), addr)
+                            .await?;
+                        continue;
+                    }
+                }
+
+                match message.ty {
+                    MessageType::Connect => {
+                        let full = config
+                            .max_sessions
+                            .is_some_and(|max_sessions| sessions.len() >= max_sessions);
+                        if let hash_map::Entry::Vacant(entry) = sessions.entry(message.session) {
+                            if send_to_listener.is_closed() {
+                                // listener was dropped - early exit
+                                continue;
+                            }
+
+                            if full {
+                                // too many sessions, turn the new one away
+                                socket
+                                    .send_to(
+                                        Message::close(message.session).to_string().as_bytes(),
+                                        addr,
+                                    )
+                                    .await?;
+                                continue;
+                            }
+
+                            let (handler, conn) = connection::spawn(
+                                socket.clone(),
+                                addr,
+                                message.session,
+                                config,
+                                clock.clone(),
+                            );
+                            if send_to_listener.send(conn).is_err() {
+                                // listener was dropped
+                                continue;
+                            }
+
+                            let closed = handler.closed();
+                            let send_terminated = send_terminated.clone();
+                            let session = message.session;
+                            tokio::spawn(async move {
+                                closed.await;
+                                let _ = send_terminated.send(session);
+                            });
+                            entry.insert(handler);
+                        }
+
+                        socket
+                            .send_to(
+                                Message::ack(message.session, 0).to_string().as_bytes(),
+                                addr,
+                            )
+                            .await?;
+                    }
+                    MessageType::Close => {
+                        if let Some(conn) = sessions.get(&message.session) {
+                            if addr == conn.addr() {
+                                // make sure the client owns the session
+                                sessions.remove(&message.session);
+                            }
+                        }
+
+                        // either way send a close message
+                        socket
+                            .send_to(Message::close(message.session).to_string().as_bytes(), addr)
+                            .await?;
+                    }
+                    MessageType::Ack { length } => {
+                        if let Some(conn) = sessions.get(&message.session) {
+                            // if the buffer is full, allow the client retransmit the ack
+                            let _ = conn.ack(length);
+                        }
+                    }
+                    MessageType::Data { position, data } => {
+                        if let Some(conn) = sessions.get(&message.session) {
+                            // if the buffer is full, allow the client retransmit the data
+                            let _ = conn.data(position, data);
+                        }
+                    }
+                }
+            }
+
+            Ok::<(), anyhow::Error>(())
+        });
+
+        Ok(Self {
+            connections: rx,
+            local_addr,
+        })
+    }
+
+    /// The address the listener is bound to
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::net::UdpSocket;
+
+    use super::Listener;
+    use crate::Config;
+
+    // how long to wait for a packet that should have been sent
+    const RECV_TIMEOUT: Duration = Duration::from_secs(5);
+
+    async fn request(socket: &UdpSocket, message: &str) -> String {
+        socket.send(message.as_bytes()).await.unwrap();
+
+        let mut packet = [0; 1000];
+        let len = tokio::time::timeout(RECV_TIMEOUT, socket.recv(&mut packet))
+            .await
+            .expect("the listener should have answered")
+            .unwrap();
+        String::from_utf8(packet[..len].to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn close_sessions_over_the_cap() {
+        let config = Config {
+            max_sessions: Some(1),
+            ..Default::default()
+        };
+        let mut listener = Listener::bind("127.0.0.1:0", config).await.unwrap();
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        peer.connect(listener.local_addr()).await.unwrap();
+
+        assert_eq!(request(&peer, "/connect/1/").await, "/ack/1/0/");
+        let _conn = listener.accept().await.unwrap();
+        assert_eq!(request(&peer, "/connect/2/").await, "/close/2/");
+        // an existing session can still reconnect
+        assert_eq!(request(&peer, "/connect/1/").await, "/ack/1/0/");
+
+        // once a session is gone, there's room for a new one
+        assert_eq!(request(&peer, "/close/1/").await, "/close/1/");
+        assert_eq!(request(&peer, "/connect/2/").await, "/ack/2/0/");
+    }
+
+    #[tokio::test]
+    async fn respond_to_spec_violations() {
+        let mut listener = Listener::bind("127.0.0.1:0", Config::default())
+            .await
+            .unwrap();
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        peer.connect(listener.local_addr()).await.unwrap();
+
+        assert_eq!(request(&peer, "/connect/1/").await, "/ack/1/0/");
+        let _conn = listener.accept().await.unwrap();
+        assert_eq!(request(&peer, "/close/2/").await, "/close/2/");
+
+        let oversized = format!("/data/1/0/{}/", "a".repeat(1000));
+        let table = [
+            ("/data/2/0/hello/", Some("/close/2/")),
+            ("/ack/2/0/", Some("/close/2/")),
+            ("/data/3/0/hello/", Some("/close/3/")),
+            ("/connect/2147483648/", None),
+            ("/ack/1/2147483648/", None),
+            ("/data/1/+0/hello/", None),
+            ("/data/1/0/", None),
+            (oversized.as_str(), None),
+            ("/data/1/0/hello/", Some("/ack/1/5/")),
+            ("/data/1/10/world/", Some("/ack/1/5/")),
+        ];
+
+        for (packet, expected) in table {
+            match expected {
+                Some(expected) => assert_eq!(request(&peer, packet).await, expected, "{}", packet),
+                None => {
+                    // an ignored packet is followed by the answer to the next request
+                    peer.send(packet.as_bytes()).await.unwrap();
+                    assert_eq!(
+                        request(&peer, "/close/99/").await,
+                        "/close/99/",
+                        "{}",
+                        packet
+                    );
+                }
+            }
+        }
+    }
+}
@@ -1,12 +1,22 @@
-use std::{num::ParseIntError, str::FromStr};
+use std::{fmt, num::ParseIntError, str::FromStr};
 
-#[derive(Debug, PartialEq)]
+// every numeric field must be smaller than 2^31
+pub const MAX_NUMBER: u32 = i32::MAX as u32;
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Message {
     pub session: u32,
     pub ty: MessageType,
 }
 
 impl Message {
+    pub fn connect(session: u32) -> Self {
+        Self {
+            session,
+            ty: MessageType::Connect,
+        }
+    }
+
     pub fn data(session: u32, position: u32, data: String) -> Self {
         Self {
             session,
@@ -29,7 +39,7 @@ impl Message {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum MessageType {
     Connect,
     Data { position: u32, data: String },
@@ -37,8 +47,8 @@ pub enum MessageType {
     Close,
 }
 
-impl ToString for Message {
-    fn to_string(&self) -> String {
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let session = self.session.to_string();
         let session = session.as_str();
 
@@ -61,7 +71,7 @@ impl ToString for Message {
         };
 
         // wrap body inside two '/'
-        "/".to_string() + &body + "/"
+        write!(f, "/{}/", body)
     }
 }
 
@@ -73,6 +83,9 @@ pub enum ParseMessageError {
     #[error("{0}")]
     ParseInt(#[from] ParseIntError),
 
+    #[error("numeric fields must be smaller than 2^31")]
+    NumberTooLarge,
+
     #[error("the data part wasn't escaped properly")]
     BadDataFormat,
 }
@@ -88,7 +101,7 @@ impl FromStr for Message {
         // remove the wrapping '/' and split over all parts (ignore escaping problems for now)
         let mut parts = s[1..s.len() - 1].split('/');
         let ty = parts.next().ok_or(ParseMessageError::Unknown)?;
-        let session: u32 = parts.next().ok_or(ParseMessageError::Unknown)?.parse()?;
+        let session = parse_number(parts.next())?;
 
         let message = match ty {
             "connect" => {
@@ -112,7 +125,7 @@ impl FromStr for Message {
                 }
             }
             "ack" => {
-                let length: u32 = parts.next().ok_or(ParseMessageError::Unknown)?.parse()?;
+                let length = parse_number(parts.next())?;
                 if parts.next().is_some() {
                     return Err(ParseMessageError::Unknown);
                 }
@@ -123,8 +136,13 @@ impl FromStr for Message {
                 }
             }
             "data" => {
-                let position: u32 = parts.next().ok_or(ParseMessageError::Unknown)?.parse()?;
-                let data = parts.collect::<Vec<_>>().join("/");
+                let position = parse_number(parts.next())?;
+                let data = parts.collect::<Vec<_>>();
+                if data.is_empty() {
+                    // even empty data has a field of its own
+                    return Err(ParseMessageError::Unknown);
+                }
+                let data = data.join("/");
 
                 Self {
                     session,
@@ -141,39 +159,48 @@ impl FromStr for Message {
     }
 }
 
+// a numeric field is made of digits only (no sign), and must be smaller than 2^31
+fn parse_number(part: Option<&str>) -> Result<u32, ParseMessageError> {
+    let part = part.ok_or(ParseMessageError::Unknown)?;
+    if !part.bytes().all(|byte| byte.is_ascii_digit()) {
+        return Err(ParseMessageError::Unknown);
+    }
+
+    match part.parse::<u32>() {
+        Ok(number) if number <= MAX_NUMBER => Ok(number),
+        Ok(_) => Err(ParseMessageError::NumberTooLarge),
+        Err(err) => match err.kind() {
+            std::num::IntErrorKind::PosOverflow => Err(ParseMessageError::NumberTooLarge),
+            _ => Err(err.into()),
+        },
+    }
+}
+
 fn unescape_data(data: &str) -> Result<String, ParseMessageError> {
     // make sure the data is properly formated:
-    // every '\' follows either '\' or '/'
-    // no '/' or '\' appears without a '\' before it
+    // every '\' is followed by either '\' or '/'
+    // no '/' appears without a '\' before it
+    let mut unescaped = String::with_capacity(data.len());
     let mut chars = data.chars();
 
-    let mut last = chars.next();
     while let Some(ch) = chars.next() {
-        if matches!(last, Some('\\')) {
-            if ch != '/' && ch != '\\' {
-                return Err(ParseMessageError::BadDataFormat);
-            }
-
-            last = chars.next();
-            continue;
-        }
-
-        if ch == '/' {
-            return Err(ParseMessageError::BadDataFormat);
+        match ch {
+            '\\' => match chars.next() {
+                Some(escaped @ ('\\' | '/')) => unescaped.push(escaped),
+                _ => return Err(ParseMessageError::BadDataFormat),
+            },
+            '/' => return Err(ParseMessageError::BadDataFormat),
+            ch => unescaped.push(ch),
         }
-
-        last = Some(ch);
     }
 
-    if matches!(last, Some('\\') | Some('/')) {
-        return Err(ParseMessageError::BadDataFormat);
-    }
-
-    Ok(data.replace(r"\\", r"\").replace(r"\/", "/"))
+    Ok(unescaped)
 }
 
 #[cfg(test)]
 mod tests {
+    use proptest::prelude::*;
+
     use super::{Message, MessageType};
 
     #[test]
@@ -245,6 +272,7 @@ mod tests {
             r"/data/4/5/hello\///",
             r"/data/6/7/\/",
             r"/data/6/7///",
+            r"/data/6/7//hello/",
         ];
 
         for raw in raw_messages {
@@ -271,4 +299,38 @@ mod tests {
             assert_eq!(raw.parse::<Message>().unwrap().to_string(), raw);
         }
     }
+
+    // text that is dense with the characters that have to be escaped
+    fn data() -> impl Strategy<Value = String> {
+        r"[a-z/\\ \n]{0,64}"
+    }
+
+    fn message() -> impl Strategy<Value = Message> {
+        let session = 0..=i32::MAX as u32;
+        let ty = prop_oneof![
+            Just(MessageType::Connect),
+            Just(MessageType::Close),
+            (0..=i32::MAX as u32).prop_map(|length| MessageType::Ack { length }),
+            (0..=i32::MAX as u32, data())
+                .prop_map(|(position, data)| MessageType::Data { position, data }),
+        ];
+
+        (session, ty).prop_map(|(session, ty)| Message { session, ty })
+    }
+
+    proptest! {
+        #[test]
+        fn round_trip_messages(message in message()) {
+            prop_assert_eq!(message.to_string().parse::<Message>(), Ok(message));
+        }
+
+        #[test]
+        fn accept_only_canonical_escaping(raw in r"[a-z/\\]{0,32}") {
+            // any data field that is accepted is the one the serializer would have produced
+            let packet = format!("/data/1/0/{}/", raw);
+            if let Ok(message) = packet.parse::<Message>() {
+                prop_assert_eq!(message.to_string(), packet);
+            }
+        }
+    }
 }
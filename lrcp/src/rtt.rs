@@ -0,0 +1,130 @@
+use std::time::Duration;
+
+use super::Rto;
+
+// the granularity of the clock, keeps the timeout above the smoothed rtt
+// even when the round-trip time doesn't vary at all
+const GRANULARITY: Duration = Duration::from_millis(1);
+
+/// Estimates the retransmission timeout of a session from its round-trip times
+///
+/// follows RFC 6298: the timeout is the smoothed rtt plus four times its variance,
+/// and is doubled on every expiry until the peer acks new data.
+/// following Karn's algorithm, the caller must only sample segments that were never retransmitted,
+/// since the ack of a retransmitted segment can't be matched with a specific transmission
+#[derive(Debug)]
+pub(super) struct RttEstimator {
+    bounds: Rto,
+    srtt: Option<Duration>,
+    rttvar: Duration,
+    // the timeout before any back off
+    estimate: Duration,
+    timeout: Duration,
+}
+
+impl RttEstimator {
+    pub(super) fn new(bounds: Rto) -> Self {
+        Self {
+            bounds,
+            srtt: None,
+            rttvar: Duration::ZERO,
+            estimate: bounds.initial,
+            timeout: bounds.initial,
+        }
+    }
+
+    /// The current retransmission timeout
+    pub(super) fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Feeds a measured round-trip time
+    pub(super) fn sample(&mut self, rtt: Duration) {
+        let srtt = match self.srtt {
+            None => {
+                self.rttvar = rtt / 2;
+                rtt
+            }
+            Some(srtt) => {
+                let error = srtt.abs_diff(rtt);
+                self.rttvar = (self.rttvar * 3 + error) / 4;
+                (srtt * 7 + rtt) / 8
+            }
+        };
+
+        self.srtt = Some(srtt);
+        self.estimate =
+            (srtt + GRANULARITY.max(self.rttvar * 4)).clamp(self.bounds.min, self.bounds.max);
+        self.timeout = self.estimate;
+    }
+
+    /// Doubles the timeout after it has expired
+    pub(super) fn back_off(&mut self) {
+        self.timeout = (self.timeout * 2).min(self.bounds.max);
+    }
+
+    /// Drops the back off once the peer is acking new data again,
+    /// even when the ack can't be sampled
+    pub(super) fn recover(&mut self) {
+        self.timeout = self.estimate;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::RttEstimator;
+    use crate::Rto;
+
+    const BOUNDS: Rto = Rto {
+        initial: Duration::from_millis(100),
+        min: Duration::from_millis(10),
+        max: Duration::from_secs(1),
+    };
+
+    #[test]
+    fn smooth_the_samples() {
+        let mut rtt = RttEstimator::new(BOUNDS);
+        assert_eq!(rtt.timeout(), BOUNDS.initial);
+
+        // srtt = 40, rttvar = 20
+        rtt.sample(Duration::from_millis(40));
+        assert_eq!(rtt.timeout(), Duration::from_millis(120));
+
+        // srtt = 35 + 80 / 8 = 45, rttvar = 15 + 40 / 4 = 25
+        rtt.sample(Duration::from_millis(80));
+        assert_eq!(rtt.timeout(), Duration::from_millis(145));
+    }
+
+    #[test]
+    fn stay_within_bounds() {
+        let mut rtt = RttEstimator::new(BOUNDS);
+
+        rtt.sample(Duration::ZERO);
+        assert_eq!(rtt.timeout(), BOUNDS.min);
+
+        rtt.sample(Duration::from_secs(5));
+        assert_eq!(rtt.timeout(), BOUNDS.max);
+    }
+
+    #[test]
+    fn back_off_until_recovered() {
+        let mut rtt = RttEstimator::new(BOUNDS);
+
+        rtt.back_off();
+        assert_eq!(rtt.timeout(), Duration::from_millis(200));
+        for _ in 0..10 {
+            rtt.back_off();
+        }
+        assert_eq!(rtt.timeout(), BOUNDS.max);
+        rtt.recover();
+        assert_eq!(rtt.timeout(), BOUNDS.initial);
+
+        rtt.sample(Duration::from_millis(40));
+        rtt.back_off();
+        assert_eq!(rtt.timeout(), Duration::from_millis(240));
+        rtt.recover();
+        assert_eq!(rtt.timeout(), Duration::from_millis(120));
+    }
+}
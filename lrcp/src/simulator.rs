@@ -0,0 +1,275 @@
+use std::net::SocketAddr;
+
+use tokio::net::UdpSocket;
+
+use super::MAX_MESSAGE_SIZE;
+
+/// The faults a simulated link applies to the datagrams it forwards, in both directions
+///
+/// every probability is a number between 0 and 1,
+/// the seed makes the faults of a link reproducible (for the same traffic)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Faults {
+    // the datagram is never delivered
+    pub loss: f64,
+    // the datagram is delivered twice
+    pub duplication: f64,
+    // the datagram is held back, and delivered right after the next one
+    pub reordering: f64,
+    pub seed: u64,
+}
+
+/// Forwards datagrams between a single client and the server, applying the faults on the way
+///
+/// returns the address the client should send its datagrams to,
+/// the first one to send a datagram is considered the client
+pub async fn link(server: SocketAddr, faults: Faults) -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let mut rng = Rng::new(faults.seed);
+        let mut client = None;
+        let mut held: Option<(Vec<u8>, SocketAddr)> = None;
+
+        let mut packet = [0; MAX_MESSAGE_SIZE];
+        loop {
+            let (len, from) = socket.recv_from(&mut packet).await.unwrap();
+            let to = if from == server {
+                let Some(client) = client else { continue };
+                client
+            } else {
+                client = Some(from);
+                server
+            };
+
+            if rng.chance(faults.loss) {
+                continue;
+            }
+
+            if held.is_none() && rng.chance(faults.reordering) {
+                held = Some((packet[..len].to_vec(), to));
+                continue;
+            }
+
+            socket.send_to(&packet[..len], to).await.unwrap();
+            if rng.chance(faults.duplication) {
+                socket.send_to(&packet[..len], to).await.unwrap();
+            }
+            if let Some((packet, to)) = held.take() {
+                socket.send_to(&packet, to).await.unwrap();
+            }
+        }
+    });
+
+    addr
+}
+
+// xorshift64, good enough to pick faults
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // the state must never be 0
+        Self(seed | 1)
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+
+        // the top 53 bits make for a uniform number in [0, 1)
+        ((self.0 >> 11) as f64 / (1u64 << 53) as f64) < probability
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, time::Duration};
+
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+        net::UdpSocket,
+    };
+
+    use super::{link, Faults};
+    use crate::{
+        connector,
+        message::{Message, MessageType},
+        Config, Listener,
+    };
+
+    // how long a whole exchange may take over a faulty link, in real time
+    const DELIVERY_TIMEOUT: Duration = Duration::from_secs(20);
+    // how often the raw peer retransmits everything that isn't acked
+    const PEER_RETRANSMISSION_TIMEOUT: Duration = Duration::from_millis(50);
+    // the size of the segments the raw peer sends
+    const SEGMENT_SIZE: usize = 100;
+
+    const FAULTS: [Faults; 4] = [
+        Faults {
+            loss: 0.3,
+            duplication: 0.0,
+            reordering: 0.0,
+            seed: 1,
+        },
+        Faults {
+            loss: 0.0,
+            duplication: 0.5,
+            reordering: 0.0,
+            seed: 2,
+        },
+        Faults {
+            loss: 0.0,
+            duplication: 0.0,
+            reordering: 0.5,
+            seed: 3,
+        },
+        Faults {
+            loss: 0.2,
+            duplication: 0.2,
+            reordering: 0.2,
+            seed: 4,
+        },
+    ];
+
+    async fn echo_server() -> SocketAddr {
+        let mut listener = Listener::bind("127.0.0.1:0", Config::default())
+            .await
+            .unwrap();
+        let addr = listener.local_addr();
+
+        tokio::spawn(async move {
+            let conn = listener.accept().await.unwrap();
+            let (mut reader, mut writer) = tokio::io::split(conn);
+            tokio::io::copy(&mut reader, &mut writer).await.unwrap();
+        });
+
+        addr
+    }
+
+    #[tokio::test]
+    async fn deliver_over_faulty_links() {
+        for faults in FAULTS {
+            let relay = link(echo_server().await, faults).await;
+            let conn = connector::connect(relay, Config::default()).await.unwrap();
+            let (reader, mut writer) = tokio::io::split(conn);
+            let mut lines = BufReader::new(reader).lines();
+
+            // long enough lines to span several data messages
+            let sent: Vec<_> = (0..20)
+                .map(|idx| format!("{} with a \\ and a / ", idx).repeat(idx * 4))
+                .collect();
+            // the echoes are read while writing, so neither side's buffers fill up
+            let write = async {
+                for line in sent.iter() {
+                    writer
+                        .write_all(format!("{}\n", line).as_bytes())
+                        .await
+                        .unwrap();
+                }
+            };
+            let read = async {
+                let mut received = Vec::new();
+                while received.len() < sent.len() {
+                    received.push(lines.next_line().await.unwrap().unwrap());
+                }
+                received
+            };
+            let exchange = async { tokio::join!(write, read).1 };
+
+            let received = tokio::time::timeout(DELIVERY_TIMEOUT, exchange)
+                .await
+                .unwrap_or_else(|_| panic!("the lines should have been echoed over {:?}", faults));
+            assert_eq!(received, sent, "over {:?}", faults);
+        }
+    }
+
+    // Sends the text to the server as a raw peer, retransmitting every segment until it's acked
+    //
+    // checks that every ack the server sends ends on a segment the peer has sent,
+    // acks only ever cover a prefix of whole segments since the peer never splits them
+    async fn send_raw(server: SocketAddr, session: u32, text: &str) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        socket.connect(server).await.unwrap();
+
+        let mut segments = Vec::new();
+        // the position every segment ends at, preceded by the start of the stream
+        let mut boundaries = vec![0];
+        for chunk in text.as_bytes().chunks(SEGMENT_SIZE) {
+            let position = *boundaries.last().unwrap();
+            let data = String::from_utf8(chunk.to_vec()).unwrap();
+            segments.push(Message::data(session, position, data).to_string());
+            boundaries.push(position + chunk.len() as u32);
+        }
+        let end = *boundaries.last().unwrap();
+
+        let connect = format!("/connect/{}/", session);
+        let mut acked = None;
+        let mut retry = tokio::time::interval(PEER_RETRANSMISSION_TIMEOUT);
+        let mut packet = [0; 1000];
+        while acked != Some(end) {
+            tokio::select! {
+                _ = retry.tick() => match acked {
+                    None => {
+                        socket.send(connect.as_bytes()).await.unwrap();
+                    }
+                    Some(acked) => {
+                        let unacked = boundaries.iter().position(|end| *end == acked).unwrap();
+                        for segment in &segments[unacked..] {
+                            socket.send(segment.as_bytes()).await.unwrap();
+                        }
+                    }
+                },
+                received = socket.recv(&mut packet) => {
+                    let len = received.unwrap();
+                    let message: Message = std::str::from_utf8(&packet[..len]).unwrap().parse().unwrap();
+                    assert_eq!(message.session, session);
+
+                    let length = match message.ty {
+                        MessageType::Ack { length } => length,
+                        ty => panic!("the server should only ack, sent: {:?}", ty),
+                    };
+                    assert!(
+                        boundaries.contains(&length),
+                        "acked {} which isn't the end of a sent segment",
+                        length
+                    );
+                    acked = acked.max(Some(length));
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn ack_received_prefix_over_faulty_links() {
+        for (idx, faults) in FAULTS.into_iter().enumerate() {
+            let mut listener = Listener::bind("127.0.0.1:0", Config::default())
+                .await
+                .unwrap();
+            let relay = link(listener.local_addr(), faults).await;
+
+            let text = "hello, world\n".repeat(200);
+            let session = idx as u32;
+            let sending = tokio::spawn({
+                let text = text.clone();
+                async move { send_raw(relay, session, &text).await }
+            });
+
+            let exchange = async {
+                let mut conn = listener.accept().await.unwrap();
+                let mut received = vec![0; text.len()];
+                conn.read_exact(&mut received).await.unwrap();
+                sending.await.unwrap();
+                received
+            };
+
+            let received = tokio::time::timeout(DELIVERY_TIMEOUT, exchange)
+                .await
+                .unwrap_or_else(|_| panic!("the text should have been acked over {:?}", faults));
+            // every byte is handed to the application exactly once, and in order
+            assert_eq!(received, text.as_bytes(), "over {:?}", faults);
+        }
+    }
+}
@@ -0,0 +1,195 @@
+// The responses the spec requires to every message a peer may send, valid or not
+//
+// | violation                                       | response                       |
+// |-------------------------------------------------|--------------------------------|
+// | a packet of 1000 bytes or more                  | ignored                        |
+// | not utf-8, or not a well formed message         | ignored                        |
+// | a numeric field of 2^31 or more                 | ignored                        |
+// | data/ack for a session that isn't open (closed) | a close message                |
+// | an ack beyond what was sent                     | the session is closed          |
+// | an ack of what was already acked                | ignored                        |
+// | data past what was received so far              | an ack of what was received    |
+// | data that would grow the stream to 2^31 or more | the session is closed          |
+// | data that overlaps what was already received    | only the rest of it is taken   |
+// | data that splits a character received in part   | the session is closed          |
+//
+// what was already received isn't kept around once it's read, so the overlapping part of a
+// resend is skipped rather than compared
+use super::{
+    message::{Message, MessageType, MAX_NUMBER},
+    MAX_MESSAGE_SIZE,
+};
+
+/// Decodes a packet, returns None if it should be ignored
+pub(crate) fn decode(packet: &[u8]) -> Option<Message> {
+    if packet.len() >= MAX_MESSAGE_SIZE {
+        return None;
+    }
+
+    std::str::from_utf8(packet).ok()?.parse().ok()
+}
+
+/// The response to a data or ack message for a session that isn't open,
+/// returns None for the messages that don't get one
+pub(crate) fn unknown_session(message: &Message) -> Option<Message> {
+    match message.ty {
+        MessageType::Data { .. } | MessageType::Ack { .. } => Some(Message::close(message.session)),
+        MessageType::Connect | MessageType::Close => None,
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum AckCheck {
+    /// acks data that wasn't acked before
+    New,
+    /// acks nothing new, ignore it
+    Duplicate,
+    /// acks data that was never sent, close the session
+    Misbehaving,
+}
+
+/// Checks an ack against the length that was acked so far, and the length that was sent
+pub(crate) fn check_ack(length: u32, acked: u32, sent: u32) -> AckCheck {
+    if length > sent {
+        AckCheck::Misbehaving
+    } else if length <= acked {
+        AckCheck::Duplicate
+    } else {
+        AckCheck::New
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum DataCheck<'a> {
+    /// the part of the data that wasn't received before, possibly empty
+    Fresh(&'a str),
+    /// the data starts past what was received, ack what was received so the peer retransmits
+    Gap,
+    /// close the session
+    Misbehaving,
+}
+
+/// Checks a data message against the length that was received so far
+pub(crate) fn check_data(position: u32, data: &str, received: u32) -> DataCheck<'_> {
+    if position > received {
+        return DataCheck::Gap;
+    }
+
+    // the length of the stream is acked, so it's bound by the numeric fields as well
+    if position as u64 + data.len() as u64 > MAX_NUMBER as u64 {
+        return DataCheck::Misbehaving;
+    }
+
+    let skip = ((received - position) as usize).min(data.len());
+    match data.get(skip..) {
+        Some(fresh) => DataCheck::Fresh(fresh),
+        // the data splits a character we've already received part of
+        None => DataCheck::Misbehaving,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_ack, check_data, decode, unknown_session, AckCheck, DataCheck};
+    use crate::message::Message;
+
+    #[test]
+    fn decode_packets() {
+        // data messages of 999 and 1000 bytes
+        let (fits, oversized) = (
+            format!("/data/1/0/{}/", "a".repeat(988)),
+            format!("/data/1/0/{}/", "a".repeat(989)),
+        );
+        let table: &[(&[u8], Option<Message>)] = &[
+            (b"/connect/2147483647/", Some(Message::connect(2147483647))),
+            (b"/connect/2147483648/", None),
+            (b"/connect/4294967296/", None),
+            (b"/ack/1/2147483647/", Some(Message::ack(1, 2147483647))),
+            (b"/ack/1/2147483648/", None),
+            (b"/data/1/2147483648/a/", None),
+            (b"/ack/1/+5/", None),
+            (b"/ack/-1/5/", None),
+            (b"/ack/1//", None),
+            (b"/data/1/0/", None),
+            (b"/data/1/0//", Some(Message::data(1, 0, "".into()))),
+            (b"/close/1/\xff/", None),
+            (b"/connect/1", None),
+            (b"/bye/1/", None),
+            (fits.as_bytes(), Some(Message::data(1, 0, "a".repeat(988)))),
+            (oversized.as_bytes(), None),
+        ];
+
+        for (packet, expected) in table {
+            assert_eq!(
+                decode(packet),
+                *expected,
+                "{:?}",
+                String::from_utf8_lossy(packet)
+            );
+        }
+    }
+
+    #[test]
+    fn respond_to_unknown_sessions() {
+        let table = [
+            (Message::data(1, 0, "hi".into()), Some(Message::close(1))),
+            (Message::ack(1, 0), Some(Message::close(1))),
+            (Message::close(1), None),
+            (Message::connect(1), None),
+        ];
+
+        for (message, expected) in table {
+            assert_eq!(unknown_session(&message), expected, "{:?}", message);
+        }
+    }
+
+    #[test]
+    fn check_acks() {
+        // (length, acked, sent)
+        let table = [
+            ((5, 0, 10), AckCheck::New),
+            ((10, 5, 10), AckCheck::New),
+            ((5, 5, 10), AckCheck::Duplicate),
+            ((0, 0, 0), AckCheck::Duplicate),
+            ((3, 5, 10), AckCheck::Duplicate),
+            ((11, 5, 10), AckCheck::Misbehaving),
+            ((1, 0, 0), AckCheck::Misbehaving),
+        ];
+
+        for ((length, acked, sent), expected) in table {
+            assert_eq!(
+                check_ack(length, acked, sent),
+                expected,
+                "{:?}",
+                (length, acked, sent)
+            );
+        }
+    }
+
+    #[test]
+    fn check_data_messages() {
+        let max = i32::MAX as u32;
+        // (position, data, received)
+        let table = [
+            ((0, "hello", 0), DataCheck::Fresh("hello")),
+            ((0, "hello", 3), DataCheck::Fresh("lo")),
+            ((2, "hello", 3), DataCheck::Fresh("ello")),
+            ((0, "hello", 5), DataCheck::Fresh("")),
+            ((0, "hello", 8), DataCheck::Fresh("")),
+            ((0, "", 0), DataCheck::Fresh("")),
+            ((4, "hello", 3), DataCheck::Gap),
+            ((max - 2, "ab", max - 2), DataCheck::Fresh("ab")),
+            ((max - 2, "abc", max - 2), DataCheck::Misbehaving),
+            ((0, "héllo", 2), DataCheck::Misbehaving),
+        ];
+
+        for ((position, data, received), expected) in table {
+            assert_eq!(
+                check_data(position, data, received),
+                expected,
+                "{:?}",
+                (position, data, received)
+            );
+        }
+    }
+}
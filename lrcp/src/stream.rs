@@ -0,0 +1,215 @@
+use std::{
+    future::Future,
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf},
+    sync::mpsc::{self, error::SendError, OwnedPermit},
+};
+
+use super::MAX_DATA_SIZE;
+
+type Reserve = Pin<Box<dyn Future<Output = Result<OwnedPermit<String>, SendError<()>>> + Send>>;
+
+/// The application side of an LRCP session
+///
+/// reads and writes go straight through the session, every write is sent as (at most)
+/// a single data message. LRCP only carries text, so the written data must be valid UTF-8.
+///
+/// shutting the stream down closes the session once all of its data was acked, and resolves
+/// once the peer has closed its side too (or has failed to answer in time).
+/// dropping the stream closes the session in the background.
+pub struct LrcpStream {
+    session: u32,
+    peer: SocketAddr,
+    // the data the peer has sent, in order
+    incoming: mpsc::Receiver<String>,
+    // what's left of the last data that was received
+    pending: String,
+    read: usize,
+    // dropped once the stream is shut down
+    outgoing: Option<mpsc::Sender<String>>,
+    // a write waits for the session to take more data
+    reserve: Option<Reserve>,
+    // closed by the session once it has fully terminated
+    terminated: mpsc::Sender<()>,
+    shutdown: Option<Pin<Box<dyn Future<Output = ()> + Send>>>,
+}
+
+impl LrcpStream {
+    pub(super) fn new(
+        session: u32,
+        peer: SocketAddr,
+        incoming: mpsc::Receiver<String>,
+        outgoing: mpsc::Sender<String>,
+        terminated: mpsc::Sender<()>,
+    ) -> Self {
+        Self {
+            session,
+            peer,
+            incoming,
+            pending: String::new(),
+            read: 0,
+            outgoing: Some(outgoing),
+            reserve: None,
+            terminated,
+            shutdown: None,
+        }
+    }
+
+    pub fn session_id(&self) -> u32 {
+        self.session
+    }
+
+    /// The address of the other end of the session
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer
+    }
+
+    /// Closes the session, see [`AsyncWriteExt::shutdown`]
+    pub async fn shutdown(&mut self) -> io::Result<()> {
+        AsyncWriteExt::shutdown(self).await
+    }
+}
+
+impl AsyncRead for LrcpStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        while this.read == this.pending.len() {
+            match ready!(this.incoming.poll_recv(cx)) {
+                Some(data) => {
+                    this.pending = data;
+                    this.read = 0;
+                }
+                // the session has terminated
+                None => return Poll::Ready(Ok(())),
+            }
+        }
+
+        let rest = &this.pending.as_bytes()[this.read..];
+        let len = rest.len().min(buf.remaining());
+        buf.put_slice(&rest[..len]);
+        this.read += len;
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for LrcpStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        let Some(outgoing) = &this.outgoing else {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        };
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        let chunk = next_chunk(buf)?;
+
+        let reserve = this
+            .reserve
+            .get_or_insert_with(|| Box::pin(outgoing.clone().reserve_owned()));
+        let permit = ready!(reserve.as_mut().poll(cx));
+        this.reserve = None;
+
+        match permit {
+            Ok(permit) => {
+                permit.send(chunk.into());
+                Poll::Ready(Ok(chunk.len()))
+            }
+            // the session has terminated
+            Err(_) => Poll::Ready(Err(io::ErrorKind::BrokenPipe.into())),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // written data is handed to the session right away
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        // the session sees that no more data is coming, and starts closing
+        this.outgoing = None;
+        this.reserve = None;
+
+        let terminated = this.terminated.clone();
+        this.shutdown
+            .get_or_insert_with(|| Box::pin(async move { terminated.closed().await }))
+            .as_mut()
+            .poll(cx)
+            .map(Ok)
+    }
+}
+
+// The longest prefix of the data that stays within MAX_DATA_SIZE once it's escaped,
+// without splitting a character
+fn next_chunk(data: &[u8]) -> io::Result<&str> {
+    let mut end = 0;
+    let mut escaped_len = 0;
+    for byte in data {
+        let len = match byte {
+            b'/' | b'\\' => 2,
+            _ => 1,
+        };
+
+        if escaped_len + len > MAX_DATA_SIZE {
+            break;
+        }
+        escaped_len += len;
+        end += 1;
+    }
+
+    let valid = match std::str::from_utf8(&data[..end]) {
+        Ok(text) => return Ok(text),
+        Err(err) => err.valid_up_to(),
+    };
+    if valid == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "LRCP only carries text",
+        ));
+    }
+
+    Ok(std::str::from_utf8(&data[..valid]).expect("the prefix was validated"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{next_chunk, MAX_DATA_SIZE};
+
+    #[test]
+    fn chunk_escaped_data() {
+        assert_eq!(next_chunk(b"hello\n").unwrap(), "hello\n");
+
+        let data = "/".repeat(MAX_DATA_SIZE);
+        assert_eq!(
+            next_chunk(data.as_bytes()).unwrap().len(),
+            MAX_DATA_SIZE / 2
+        );
+
+        // a character is never split between chunks
+        let data = format!("{}é", "a".repeat(MAX_DATA_SIZE - 1));
+        assert_eq!(
+            next_chunk(data.as_bytes()).unwrap().len(),
+            MAX_DATA_SIZE - 1
+        );
+
+        assert!(next_chunk(b"\xffhello").is_err());
+    }
+}
@@ -0,0 +1,245 @@
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::Notify,
+};
+
+use crate::protocol::Request;
+
+// Every response ends with this line, so it's easy to tell where it ends
+const END: &str = "END";
+
+#[derive(Debug, Clone)]
+struct Activity {
+    // the number of prices in the session's table
+    prices: usize,
+    inserts: u64,
+    queries: u64,
+    last_activity: SystemTime,
+}
+
+#[derive(Debug)]
+struct Entry {
+    activity: Activity,
+    close: Arc<Notify>,
+}
+
+/// Keeps track of the active sessions
+#[derive(Debug, Default)]
+pub struct Sessions {
+    sessions: Mutex<HashMap<SocketAddr, Entry>>,
+}
+
+impl Sessions {
+    /// Registers a newly accepted session, it's unregistered once the returned entry is dropped
+    pub fn register(self: &Arc<Self>, addr: SocketAddr) -> SessionEntry {
+        let close = Arc::new(Notify::new());
+        self.sessions.lock().unwrap().insert(
+            addr,
+            Entry {
+                activity: Activity {
+                    prices: 0,
+                    inserts: 0,
+                    queries: 0,
+                    last_activity: SystemTime::now(),
+                },
+                close: close.clone(),
+            },
+        );
+
+        SessionEntry {
+            sessions: self.clone(),
+            addr,
+            close,
+        }
+    }
+
+    // ordered by address
+    fn list(&self) -> Vec<(SocketAddr, Activity)> {
+        let mut sessions: Vec<_> = self
+            .sessions
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(addr, entry)| (*addr, entry.activity.clone()))
+            .collect();
+        sessions.sort_unstable_by_key(|(addr, _)| *addr);
+
+        sessions
+    }
+
+    // returns false when there's no such session
+    fn close(&self, addr: &SocketAddr) -> bool {
+        match self.sessions.lock().unwrap().get(addr) {
+            Some(entry) => {
+                // the notification is kept until a session that is busy with a request gets to it
+                entry.close.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+pub struct SessionEntry {
+    sessions: Arc<Sessions>,
+    addr: SocketAddr,
+    close: Arc<Notify>,
+}
+
+impl SessionEntry {
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Records a request that was just served, along with the table size it left behind
+    pub fn record(&self, request: &Request, prices: usize) {
+        if let Some(entry) = self.sessions.sessions.lock().unwrap().get_mut(&self.addr) {
+            let activity = &mut entry.activity;
+            match request {
+                Request::Insert { .. } => activity.inserts += 1,
+                Request::Query { .. } => activity.queries += 1,
+                Request::Auth { .. } => {}
+            }
+            activity.prices = prices;
+            activity.last_activity = SystemTime::now();
+        }
+    }
+
+    /// Resolves once an admin asks to close the session
+    pub async fn closed(&self) {
+        self.close.notified().await
+    }
+}
+
+impl Drop for SessionEntry {
+    fn drop(&mut self) {
+        self.sessions.sessions.lock().unwrap().remove(&self.addr);
+    }
+}
+
+/// Serves the textual admin commands, a single command per line
+pub async fn serve(addr: SocketAddr, sessions: Arc<Sessions>) -> tokio::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    tracing::info!("Admin listening on: {}", listener.local_addr()?);
+
+    loop {
+        let (conn, addr) = listener.accept().await?;
+        tokio::spawn(telemetry::connection(addr, handle(conn, sessions.clone())));
+    }
+}
+
+async fn handle(mut conn: TcpStream, sessions: Arc<Sessions>) -> tokio::io::Result<()> {
+    let (reader, mut writer) = conn.split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = execute(&line, &sessions);
+        writer.write_all(response.as_bytes()).await?;
+    }
+
+    Ok(())
+}
+
+fn execute(command: &str, sessions: &Sessions) -> String {
+    let mut response = String::new();
+    let mut parts = command.split_ascii_whitespace();
+    match (
+        parts.next().map(str::to_ascii_uppercase).as_deref(),
+        parts.next(),
+        parts.next(),
+    ) {
+        (Some("SESSIONS"), None, _) => {
+            for (addr, activity) in sessions.list() {
+                let last_activity = activity
+                    .last_activity
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                let _ = writeln!(
+                    response,
+                    "{} prices {} inserts {} queries {} last_activity {}",
+                    addr, activity.prices, activity.inserts, activity.queries, last_activity
+                );
+            }
+        }
+        (Some("CLOSE"), Some(addr), None) => {
+            let Ok(addr) = addr.parse::<SocketAddr>() else {
+                return format!("ERR bad session address: {}\n", addr);
+            };
+            if !sessions.close(&addr) {
+                return format!("ERR no such session: {}\n", addr);
+            }
+
+            let _ = writeln!(response, "closed {}", addr);
+        }
+        _ => return "ERR unknown command, expected one of: SESSIONS, CLOSE <addr>\n".into(),
+    }
+
+    response.push_str(END);
+    response.push('\n');
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use crate::protocol::Request;
+
+    use super::{execute, Sessions};
+
+    #[tokio::test]
+    async fn list_and_close_sessions() {
+        let sessions = Arc::new(Sessions::default());
+        let first = sessions.register("127.0.0.1:2000".parse().unwrap());
+        let second = sessions.register("127.0.0.1:1000".parse().unwrap());
+
+        first.record(
+            &Request::Insert {
+                timestamp: 1,
+                price: 10,
+            },
+            1,
+        );
+        first.record(
+            &Request::Query {
+                min_time: 0,
+                max_time: 1,
+            },
+            1,
+        );
+
+        let listing = execute("sessions", &sessions);
+        let lines: Vec<_> = listing.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("127.0.0.1:1000 prices 0 inserts 0 queries 0 last_activity "));
+        assert!(lines[1].starts_with("127.0.0.1:2000 prices 1 inserts 1 queries 1 last_activity "));
+        assert_eq!(lines[2], "END");
+
+        assert_eq!(
+            execute("CLOSE 127.0.0.1:1000", &sessions),
+            "closed 127.0.0.1:1000\nEND\n"
+        );
+        tokio::time::timeout(Duration::from_secs(1), second.closed())
+            .await
+            .expect("the session wasn't asked to close");
+
+        drop(second);
+        assert!(execute("CLOSE 127.0.0.1:1000", &sessions).starts_with("ERR"));
+        assert!(execute("CLOSE", &sessions).starts_with("ERR"));
+        assert!(execute("BOGUS", &sessions).starts_with("ERR"));
+    }
+}
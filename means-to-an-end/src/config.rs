@@ -0,0 +1,101 @@
+use std::{env, net::SocketAddr, num::NonZeroU32, path::PathBuf};
+
+use crate::{protocol::TOKEN_LEN, timetable::TimestampPolicy};
+
+const DEFAULT_MAX_UNAUTHENTICATED_REQUESTS: usize = 10;
+
+#[derive(Debug, Clone)]
+pub struct Auth {
+    pub token: [u8; TOKEN_LEN],
+    // the number of requests an unauthenticated session may send before it's disconnected
+    pub max_unauthenticated_requests: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    // when set, every session is journaled into this directory
+    pub journal_dir: Option<PathBuf>,
+    // send an error frame before closing a connection over a malformed frame
+    pub send_error_frame: bool,
+    // when set, sessions must start with an auth frame carrying the token
+    pub auth: Option<Auth>,
+    // when set, every table pre-aggregates its prices into time buckets of this width
+    pub bucket_width: Option<NonZeroU32>,
+    // whether sessions accept negative timestamps
+    pub timestamps: TimestampPolicy,
+    // when set, the metrics are served for prometheus to scrape on this address
+    pub metrics_addr: Option<String>,
+    // when set, the admin commands are served on this address, which must be a loopback one
+    pub admin_addr: Option<SocketAddr>,
+}
+
+impl Config {
+    /// Builds the configuration from the environment,
+    /// falling back to the defaults for any variable that isn't set
+    pub fn from_env() -> anyhow::Result<Self> {
+        Ok(Self {
+            journal_dir: env::var_os("JOURNAL_DIR").map(PathBuf::from),
+            send_error_frame: env::var("SEND_ERROR_FRAME")
+                .map(|value| value == "1" || value.eq_ignore_ascii_case("true"))
+                .unwrap_or(false),
+            auth: read_auth()?,
+            bucket_width: match env::var("BUCKET_WIDTH") {
+                Ok(value) => Some(
+                    value
+                        .parse()
+                        .map_err(|err| anyhow::anyhow!("bad value for BUCKET_WIDTH: {}", err))?,
+                ),
+                Err(_) => None,
+            },
+            timestamps: match env::var("NEGATIVE_TIMESTAMPS") {
+                Ok(value) => value
+                    .parse()
+                    .map_err(|err| anyhow::anyhow!("bad value for NEGATIVE_TIMESTAMPS: {}", err))?,
+                Err(_) => TimestampPolicy::default(),
+            },
+            metrics_addr: env::var("METRICS_ADDR").ok(),
+            admin_addr: read_admin_addr()?,
+        })
+    }
+}
+
+// the admin commands can close sessions, so they're never exposed beyond the host
+fn read_admin_addr() -> anyhow::Result<Option<SocketAddr>> {
+    let Ok(addr) = env::var("ADMIN_ADDR") else {
+        return Ok(None);
+    };
+
+    let addr: SocketAddr = addr
+        .parse()
+        .map_err(|err| anyhow::anyhow!("bad value for ADMIN_ADDR: {}", err))?;
+    if !addr.ip().is_loopback() {
+        anyhow::bail!("bad value for ADMIN_ADDR: must be a loopback address");
+    }
+
+    Ok(Some(addr))
+}
+
+fn read_auth() -> anyhow::Result<Option<Auth>> {
+    let Ok(token) = env::var("AUTH_TOKEN") else {
+        return Ok(None);
+    };
+
+    let token = token.as_bytes().try_into().map_err(|_| {
+        anyhow::anyhow!(
+            "bad value for AUTH_TOKEN: must be exactly {} bytes long",
+            TOKEN_LEN
+        )
+    })?;
+
+    let max_unauthenticated_requests = match env::var("MAX_UNAUTHENTICATED_REQUESTS") {
+        Ok(value) => value.parse().map_err(|err| {
+            anyhow::anyhow!("bad value for MAX_UNAUTHENTICATED_REQUESTS: {}", err)
+        })?,
+        Err(_) => DEFAULT_MAX_UNAUTHENTICATED_REQUESTS,
+    };
+
+    Ok(Some(Auth {
+        token,
+        max_unauthenticated_requests,
+    }))
+}
@@ -0,0 +1,317 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use tokio::{
+    fs::{File, OpenOptions},
+    io::AsyncWriteExt,
+};
+
+use crate::timetable::Table;
+
+// Every journal starts with a header of MAGIC followed by a single VERSION byte,
+// and continues with a sequence of fixed size records: timestamp (i32 BE), price (i32 BE)
+const MAGIC: &[u8; 4] = b"MTAE";
+const VERSION: u8 = 1;
+const HEADER_LEN: usize = MAGIC.len() + 1;
+const RECORD_LEN: usize = 8;
+
+const JOURNAL_EXTENSION: &str = "journal";
+
+#[derive(thiserror::Error, Debug)]
+pub enum JournalError {
+    #[error("{0}")]
+    Io(#[from] tokio::io::Error),
+
+    #[error("{0:?} is not a journal file")]
+    BadMagic(PathBuf),
+
+    #[error("{0:?} has an unsupported journal version: {1}")]
+    UnsupportedVersion(PathBuf, u8),
+}
+
+/// Keeps track of the journaled price tables of all peers
+///
+/// sessions are identified by the peer's ip address, a peer that reconnects
+/// (even after a server restart) continues working on the same table.
+/// only a single connection per peer can own the session at a time.
+#[derive(Debug)]
+pub struct Store {
+    dir: PathBuf,
+    bucket_width: Option<NonZeroU32>,
+    // peer -> table, None when the session is currently checked out by a connection
+    sessions: Mutex<HashMap<IpAddr, Option<Table>>>,
+}
+
+impl Store {
+    /// Opens a journal directory and replays every journal found in it
+    pub async fn open(
+        dir: PathBuf,
+        bucket_width: Option<NonZeroU32>,
+    ) -> Result<Self, JournalError> {
+        tokio::fs::create_dir_all(&dir).await?;
+
+        let mut sessions = HashMap::new();
+        let mut entries = tokio::fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(JOURNAL_EXTENSION) {
+                continue;
+            }
+
+            let Some(peer) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse::<IpAddr>().ok())
+            else {
+                continue;
+            };
+
+            let mut table = Table::new(bucket_width);
+            for (timestamp, price) in decode(&path, &tokio::fs::read(&path).await?)? {
+                table.set_price(timestamp, price);
+            }
+            sessions.insert(peer, Some(table));
+        }
+
+        Ok(Self {
+            dir,
+            bucket_width,
+            sessions: Mutex::new(sessions),
+        })
+    }
+
+    /// Checks out the session of a peer, creating a new one if necessary
+    ///
+    /// returns None when the session is already owned by another connection
+    pub async fn checkout(&self, peer: IpAddr) -> Result<Option<Session>, JournalError> {
+        let table = {
+            let mut sessions = self.sessions.lock().unwrap();
+            match sessions.get_mut(&peer) {
+                Some(slot) => match slot.take() {
+                    Some(table) => table,
+                    None => return Ok(None),
+                },
+                None => {
+                    sessions.insert(peer, None);
+                    Table::new(self.bucket_width)
+                }
+            }
+        };
+
+        match open_journal(&self.journal_path(peer)).await {
+            Ok(journal) => Ok(Some(Session {
+                table,
+                journal: Some(journal),
+            })),
+            Err(err) => {
+                // give the table back, so the session isn't lost for good
+                self.checkin(peer, table);
+                Err(err)
+            }
+        }
+    }
+
+    /// Returns a checked out session table back to the store
+    pub fn checkin(&self, peer: IpAddr, table: Table) {
+        self.sessions.lock().unwrap().insert(peer, Some(table));
+    }
+
+    fn journal_path(&self, peer: IpAddr) -> PathBuf {
+        self.dir.join(format!("{}.{}", peer, JOURNAL_EXTENSION))
+    }
+}
+
+/// The price table of a single connection,
+/// every insert is journaled before it is applied when persistence is enabled
+#[derive(Debug)]
+pub struct Session {
+    table: Table,
+    journal: Option<File>,
+}
+
+impl Session {
+    /// A session that only lives in memory
+    pub fn in_memory(bucket_width: Option<NonZeroU32>) -> Self {
+        Self {
+            table: Table::new(bucket_width),
+            journal: None,
+        }
+    }
+
+    pub async fn set_price(&mut self, timestamp: i32, price: i32) -> tokio::io::Result<()> {
+        if let Some(journal) = &mut self.journal {
+            journal.write_all(&encode(timestamp, price)).await?;
+            // tokio hands the write to a background thread, wait for it to land
+            journal.flush().await?;
+        }
+
+        self.table.set_price(timestamp, price);
+        Ok(())
+    }
+
+    pub fn table(&self) -> &Table {
+        &self.table
+    }
+
+    pub fn into_table(self) -> Table {
+        self.table
+    }
+}
+
+// Opens a journal for appending, writing the header if the journal is new
+//
+// a partially written record at the end of the journal is dropped,
+// or the records appended after it would be read out of frame,
+// and so is a partially written header, the journal is started over
+async fn open_journal(path: &Path) -> Result<File, JournalError> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+
+    let len = file.metadata().await?.len();
+    if len < HEADER_LEN as u64 {
+        file.set_len(0).await?;
+        let mut header = MAGIC.to_vec();
+        header.push(VERSION);
+        file.write_all(&header).await?;
+    } else if let Some(records) = len.checked_sub(HEADER_LEN as u64) {
+        let torn = records % RECORD_LEN as u64;
+        if torn != 0 {
+            file.set_len(len - torn).await?;
+            file.sync_all().await?;
+        }
+    }
+
+    Ok(file)
+}
+
+fn encode(timestamp: i32, price: i32) -> [u8; RECORD_LEN] {
+    let mut record = [0u8; RECORD_LEN];
+    record[..4].copy_from_slice(&timestamp.to_be_bytes());
+    record[4..].copy_from_slice(&price.to_be_bytes());
+    record
+}
+
+// Decodes the records of a journal
+//
+// a partially written record at the end of the journal (e.g. the server crashed mid-write) is ignored,
+// and a journal whose header was partially written holds no records
+fn decode(path: &Path, bytes: &[u8]) -> Result<Vec<(i32, i32)>, JournalError> {
+    if bytes.len() < HEADER_LEN {
+        return Ok(Vec::new());
+    }
+    if &bytes[..MAGIC.len()] != MAGIC {
+        return Err(JournalError::BadMagic(path.into()));
+    }
+
+    let version = bytes[MAGIC.len()];
+    if version != VERSION {
+        return Err(JournalError::UnsupportedVersion(path.into(), version));
+    }
+
+    Ok(bytes[HEADER_LEN..]
+        .chunks_exact(RECORD_LEN)
+        .map(|record| {
+            let timestamp = i32::from_be_bytes([record[0], record[1], record[2], record[3]]);
+            let price = i32::from_be_bytes([record[4], record[5], record[6], record[7]]);
+            (timestamp, price)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::IpAddr, path::Path};
+
+    use super::{decode, encode, JournalError, Store, MAGIC, VERSION};
+
+    fn journal(records: &[(i32, i32)]) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        for (timestamp, price) in records {
+            bytes.extend_from_slice(&encode(*timestamp, *price));
+        }
+        bytes
+    }
+
+    #[test]
+    fn round_trip_records() {
+        let records = [(12345, 101), (-650, -69), (i32::MIN, i32::MAX)];
+        let decoded = decode(Path::new("test"), &journal(&records)).unwrap();
+        assert_eq!(decoded, records);
+    }
+
+    #[test]
+    fn ignore_partial_record() {
+        let mut bytes = journal(&[(1, 2), (3, 4)]);
+        bytes.extend_from_slice(&[0, 0, 0]);
+
+        let decoded = decode(Path::new("test"), &bytes).unwrap();
+        assert_eq!(decoded, [(1, 2), (3, 4)]);
+    }
+
+    #[tokio::test]
+    async fn append_after_partial_record() {
+        let dir = std::env::temp_dir().join(format!("mtae-torn-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // the server crashed in the middle of journaling the second record
+        let path = dir.join("127.0.0.1.journal");
+        let mut bytes = journal(&[(1, 2)]);
+        bytes.extend_from_slice(&encode(3, 4)[..5]);
+        std::fs::write(&path, bytes).unwrap();
+
+        let store = Store::open(dir.clone(), None).await.unwrap();
+        let peer: IpAddr = "127.0.0.1".parse().unwrap();
+        let mut session = store.checkout(peer).await.unwrap().unwrap();
+        session.set_price(5, 6).await.unwrap();
+        drop(session);
+
+        let decoded = decode(&path, &std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(decoded, [(1, 2), (5, 6)]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rewrite_partial_header() {
+        let dir = std::env::temp_dir().join(format!("mtae-header-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // the server crashed in the middle of writing the header
+        let path = dir.join("127.0.0.1.journal");
+        std::fs::write(&path, &MAGIC[..2]).unwrap();
+
+        let store = Store::open(dir.clone(), None).await.unwrap();
+        let peer: IpAddr = "127.0.0.1".parse().unwrap();
+        let mut session = store.checkout(peer).await.unwrap().unwrap();
+        session.set_price(5, 6).await.unwrap();
+        drop(session);
+
+        let decoded = decode(&path, &std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(decoded, [(5, 6)]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reject_bad_header() {
+        assert!(matches!(
+            decode(Path::new("test"), b"NOPE\x01"),
+            Err(JournalError::BadMagic(_))
+        ));
+        assert!(matches!(
+            decode(Path::new("test"), b"MTAE\x07"),
+            Err(JournalError::UnsupportedVersion(_, 7))
+        ));
+    }
+}
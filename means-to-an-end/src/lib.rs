@@ -0,0 +1,8 @@
+//! The sessions behind the server,
+//! a library of their own so the verifier can run the server in-process
+pub mod admin;
+pub mod config;
+pub mod journal;
+pub mod protocol;
+pub mod server;
+pub mod timetable;
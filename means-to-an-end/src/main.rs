@@ -1,41 +1,53 @@
-use protocol::{Request, Response};
-use timetable::Table;
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::{TcpListener, TcpStream},
-};
+use std::sync::Arc;
 
-mod protocol;
-mod timetable;
+use means_to_an_end::{admin::Sessions, config::Config, journal::Store, server::handle_connection};
+use throttle::{Limits, Throttle};
+use tokio::net::TcpListener;
 
 #[tokio::main]
-async fn main() -> tokio::io::Result<()> {
-    let listener = TcpListener::bind("0.0.0.0:3600").await?;
-
-    loop {
-        let (conn, _) = listener.accept().await?;
-        tokio::spawn(handle_request(conn));
+async fn main() -> anyhow::Result<()> {
+    telemetry::init();
+    let config = Arc::new(Config::from_env()?);
+    if let Some(addr) = &config.metrics_addr {
+        if let Err(err) = telemetry::stats::install(addr) {
+            tracing::error!("failed to start the metrics exporter: {}", err);
+        }
     }
-}
 
-async fn handle_request(mut client: TcpStream) {
-    let mut table = Table::default();
+    let store = match &config.journal_dir {
+        Some(dir) => {
+            tracing::info!("Journaling sessions into: {:?}", dir);
+            Some(Arc::new(
+                Store::open(dir.clone(), config.bucket_width).await?,
+            ))
+        }
+        None => None,
+    };
 
-    let mut frame = [0u8; 9];
-    while let Ok(9) = client.read_exact(&mut frame).await {
-        let request = Request::from_bytes(&frame).expect("received bad frame");
-        match request {
-            Request::Insert { timestamp, price } => {
-                table.set_price(timestamp, price);
-            }
-            Request::Query { min_time, max_time } => {
-                let avg = table.average(min_time, max_time);
-                let response = Response::create_query_response(avg);
-                client
-                    .write_all(&response.to_bytes()[..])
-                    .await
-                    .expect("write to client");
+    let sessions = Arc::new(Sessions::default());
+    if let Some(addr) = config.admin_addr {
+        let sessions = sessions.clone();
+        tokio::spawn(async move {
+            if let Err(err) = means_to_an_end::admin::serve(addr, sessions).await {
+                tracing::error!("the admin listener has failed: {}", err);
             }
-        }
+        });
+    }
+
+    let throttle = Throttle::new(Limits::from_env()?);
+    let listener = TcpListener::bind("0.0.0.0:3600").await?;
+    tracing::info!("Server listening on: {}", listener.local_addr()?);
+
+    loop {
+        let (conn, addr, permit) = throttle.accept(&listener).await?;
+        tokio::spawn(telemetry::connection(
+            addr,
+            permit.hold(handle_connection(
+                conn,
+                sessions.register(addr),
+                store.clone(),
+                config.clone(),
+            )),
+        ));
     }
 }
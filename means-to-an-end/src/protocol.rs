@@ -6,10 +6,18 @@ pub enum RequestError {
     UnknownType(u8),
 }
 
+// Sent (when enabled) right before a connection is closed over a malformed frame,
+// it can never be mistaken for a query response since those are always 4 bytes long
+pub const ERROR_FRAME: &[u8] = b"E";
+
+// The length of the shared token carried by an auth frame
+pub const TOKEN_LEN: usize = 8;
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum Request {
     Insert { timestamp: i32, price: i32 },
     Query { min_time: i32, max_time: i32 },
+    Auth { token: [u8; TOKEN_LEN] },
 }
 
 impl Request {
@@ -27,6 +35,9 @@ impl Request {
                 min_time: i1,
                 max_time: i2,
             }),
+            b'A' => Ok(Request::Auth {
+                token: bytes[1..].try_into().unwrap(),
+            }),
             _ => Err(RequestError::UnknownType(bytes[0])),
         }
     }
@@ -56,6 +67,7 @@ mod tests {
         let raw_requests = [
             b"\x49\x00\x00\xa0\x00\x00\x00\x00\x05",
             b"\x51\x00\x00\x30\x00\x00\x00\x40\x00",
+            b"\x41secret!!",
         ];
 
         let expected_requests = [
@@ -67,6 +79,9 @@ mod tests {
                 min_time: 12288,
                 max_time: 16384,
             },
+            Request::Auth {
+                token: *b"secret!!",
+            },
         ];
 
         for (raw, expected) in raw_requests.iter().zip(expected_requests) {
@@ -0,0 +1,321 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use telemetry::stats::Metered;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use crate::{
+    admin::SessionEntry,
+    config::Config,
+    journal::{Session, Store},
+    protocol::{Request, RequestError, Response, ERROR_FRAME},
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum HandleError {
+    #[error("{0}")]
+    Io(#[from] tokio::io::Error),
+
+    #[error("{0}")]
+    BadFrame(#[from] RequestError),
+
+    #[error("Reached EOF in the middle of a frame")]
+    PartialFrame,
+
+    #[error("Too many unauthenticated requests")]
+    Unauthenticated,
+
+    #[error("Closed by an admin")]
+    Closed,
+}
+
+/// Serves a single client until it disconnects or has to be closed,
+/// its session is checked out of the store (when there's one) for the duration of the connection
+pub async fn handle_connection(
+    client: TcpStream,
+    entry: SessionEntry,
+    store: Option<Arc<Store>>,
+    config: Arc<Config>,
+) -> anyhow::Result<()> {
+    let peer = entry.addr().ip();
+    let mut session = match &store {
+        Some(store) => match store.checkout(peer).await {
+            Ok(Some(session)) => session,
+            Ok(None) => {
+                // the peer's session is owned by another connection, don't persist this one
+                Session::in_memory(config.bucket_width)
+            }
+            Err(err) => {
+                return Err(err).with_context(|| format!("failed to open the journal of {}", peer))
+            }
+        },
+        None => Session::in_memory(config.bucket_width),
+    };
+
+    let result = handle_request(&mut Metered::new(client), &mut session, &config, &entry).await;
+
+    if let Some(store) = store {
+        store.checkin(peer, session.into_table());
+    }
+
+    Ok(result?)
+}
+
+// Serves requests until the client disconnects
+//
+// returns an error when the connection has to be closed early,
+// the session is left intact so it can be reused regardless of the result
+//
+// when authentication is required, a session that doesn't start with a valid auth frame
+// never touches the table: its inserts are ignored and its queries are answered with zeros
+//
+// an admin may close the session while it waits for a frame, never in the middle of a request
+async fn handle_request<S>(
+    client: &mut S,
+    session: &mut Session,
+    config: &Config,
+    entry: &SessionEntry,
+) -> Result<(), HandleError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut authenticated = config.auth.is_none();
+    let mut unauthenticated_requests = 0;
+
+    let mut first_frame = true;
+    let mut frame = [0u8; 9];
+    loop {
+        // a disconnection between frames is the normal way for a session to end
+        let received = tokio::select! {
+            received = read_frame(client, &mut frame) => received?,
+            _ = entry.closed() => return Err(HandleError::Closed),
+        };
+        if !received {
+            return Ok(());
+        }
+
+        let request = match Request::from_bytes(&frame) {
+            Ok(request) => request,
+            Err(err) => {
+                telemetry::stats::error("malformed");
+                if config.send_error_frame {
+                    client.write_all(ERROR_FRAME).await?;
+                }
+
+                return Err(err.into());
+            }
+        };
+
+        telemetry::stats::request(match request {
+            Request::Insert { .. } => "insert",
+            Request::Query { .. } => "query",
+            Request::Auth { .. } => "auth",
+        });
+        match request {
+            Request::Auth { token } => {
+                // only the first frame may authenticate the session, any other auth frame is ignored
+                if first_frame && config.auth.as_ref().is_some_and(|auth| auth.token == token) {
+                    authenticated = true;
+                }
+            }
+            _ if !authenticated => {
+                if let Request::Query { .. } = request {
+                    let response = Response::create_query_response(0);
+                    client.write_all(&response.to_bytes()[..]).await?;
+                }
+
+                telemetry::stats::error("unauthenticated");
+                unauthenticated_requests += 1;
+                if config.auth.as_ref().is_some_and(|auth| {
+                    unauthenticated_requests >= auth.max_unauthenticated_requests
+                }) {
+                    return Err(HandleError::Unauthenticated);
+                }
+            }
+            Request::Insert { timestamp, price } => {
+                if config.timestamps.admits(timestamp) {
+                    session.set_price(timestamp, price).await?
+                }
+            }
+            Request::Query { min_time, max_time } => {
+                let (min_time, max_time) = config.timestamps.clamp(min_time, max_time);
+                let avg = session.table().average(min_time, max_time);
+                let response = Response::create_query_response(avg);
+                client.write_all(&response.to_bytes()[..]).await?;
+            }
+        }
+
+        entry.record(&request, session.table().len());
+        first_frame = false;
+    }
+}
+
+// Reads the next frame, returns false when the client disconnects before it starts
+async fn read_frame<S>(client: &mut S, frame: &mut [u8; 9]) -> Result<bool, HandleError>
+where
+    S: AsyncRead + Unpin,
+{
+    frame[0] = match client.read_u8().await {
+        Ok(ty) => ty,
+        Err(err) if err.kind() == tokio::io::ErrorKind::UnexpectedEof => return Ok(false),
+        Err(err) => return Err(err.into()),
+    };
+
+    if let Err(err) = client.read_exact(&mut frame[1..]).await {
+        return match err.kind() {
+            tokio::io::ErrorKind::UnexpectedEof => Err(HandleError::PartialFrame),
+            _ => Err(err.into()),
+        };
+    }
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::{handle_request, HandleError};
+    use crate::{
+        admin::Sessions,
+        config::{Auth, Config},
+        journal::Session,
+        protocol::ERROR_FRAME,
+        timetable::TimestampPolicy,
+    };
+
+    // Runs the handler against the given input, returning its result and everything it responded
+    async fn serve(input: &[u8], send_error_frame: bool) -> (Result<(), HandleError>, Vec<u8>) {
+        let config = Config {
+            send_error_frame,
+            ..Default::default()
+        };
+        serve_with_config(input, &config).await
+    }
+
+    async fn serve_with_config(
+        input: &[u8],
+        config: &Config,
+    ) -> (Result<(), HandleError>, Vec<u8>) {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        client.write_all(input).await.unwrap();
+        client.shutdown().await.unwrap();
+
+        let mut session = Session::in_memory(config.bucket_width);
+        let entry = Arc::new(Sessions::default()).register("127.0.0.1:1000".parse().unwrap());
+        let result = handle_request(&mut server, &mut session, config, &entry).await;
+        drop(server);
+
+        let mut output = vec![];
+        client.read_to_end(&mut output).await.unwrap();
+        (result, output)
+    }
+
+    #[tokio::test]
+    async fn serve_valid_session() {
+        let (result, output) = serve(
+            b"\x49\x00\x00\x30\x39\x00\x00\x00\x65\x49\x00\x00\x30\x3a\x00\x00\x00\x66\x51\x00\x00\x30\x00\x00\x00\x40\x00",
+            false,
+        )
+        .await;
+
+        assert!(result.is_ok());
+        assert_eq!(output, 101i32.to_be_bytes());
+    }
+
+    #[tokio::test]
+    async fn close_on_undefined_type() {
+        let input = b"\x58\x00\x00\x30\x00\x00\x00\x40\x00\x51\x00\x00\x30\x00\x00\x00\x40\x00";
+
+        let (result, output) = serve(input, false).await;
+        assert!(matches!(result, Err(HandleError::BadFrame(_))));
+        assert!(output.is_empty());
+
+        let (result, output) = serve(input, true).await;
+        assert!(matches!(result, Err(HandleError::BadFrame(_))));
+        assert_eq!(output, ERROR_FRAME);
+    }
+
+    #[tokio::test]
+    async fn close_on_partial_frame() {
+        let (result, output) = serve(
+            b"\x51\x00\x00\x30\x00\x00\x00\x40\x00\x51\x00\x00\x30",
+            false,
+        )
+        .await;
+
+        assert!(matches!(result, Err(HandleError::PartialFrame)));
+        assert_eq!(output, 0i32.to_be_bytes());
+    }
+
+    fn auth_config() -> Config {
+        Config {
+            auth: Some(Auth {
+                token: *b"secret!!",
+                max_unauthenticated_requests: 3,
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn serve_authenticated_session() {
+        let (result, output) = serve_with_config(
+            b"\x41secret!!\x49\x00\x00\x30\x39\x00\x00\x00\x65\x51\x00\x00\x30\x00\x00\x00\x40\x00",
+            &auth_config(),
+        )
+        .await;
+
+        assert!(result.is_ok());
+        assert_eq!(output, 101i32.to_be_bytes());
+    }
+
+    #[tokio::test]
+    async fn zero_out_unauthenticated_session() {
+        // a wrong token, followed by an insert and 2 queries
+        let (result, output) = serve_with_config(
+            b"\x41secret??\x49\x00\x00\x30\x39\x00\x00\x00\x65\x51\x00\x00\x30\x00\x00\x00\x40\x00\x51\x00\x00\x30\x00\x00\x00\x40\x00",
+            &auth_config(),
+        )
+        .await;
+
+        assert!(matches!(result, Err(HandleError::Unauthenticated)));
+        assert_eq!(output, [0u8; 8]);
+
+        // the token must come first
+        let (result, output) = serve_with_config(
+            b"\x51\x00\x00\x30\x00\x00\x00\x40\x00\x41secret!!\x51\x00\x00\x30\x00\x00\x00\x40\x00",
+            &auth_config(),
+        )
+        .await;
+
+        assert!(result.is_ok());
+        assert_eq!(output, [0u8; 8]);
+    }
+
+    #[tokio::test]
+    async fn ignore_negative_timestamps_when_rejected() {
+        // inserts at -1, 0 and 1, followed by queries over MIN..=MAX and MIN..=-1
+        let input = b"\x49\xff\xff\xff\xff\x00\x00\x03\xe8\x49\x00\x00\x00\x00\x00\x00\x00\x0a\x49\x00\x00\x00\x01\x00\x00\x00\x14\x51\x80\x00\x00\x00\x7f\xff\xff\xff\x51\x80\x00\x00\x00\xff\xff\xff\xff";
+
+        let (result, output) = serve(input, false).await;
+        assert!(result.is_ok());
+        assert_eq!(
+            output,
+            [343i32.to_be_bytes(), 1000i32.to_be_bytes()].concat()
+        );
+
+        let config = Config {
+            timestamps: TimestampPolicy::RejectNegative,
+            ..Default::default()
+        };
+        let (result, output) = serve_with_config(input, &config).await;
+        assert!(result.is_ok());
+        assert_eq!(output, [15i32.to_be_bytes(), 0i32.to_be_bytes()].concat());
+    }
+}
@@ -1,32 +1,177 @@
-use std::collections::BTreeMap;
+use std::{collections::BTreeMap, num::NonZeroU32, ops::RangeInclusive, str::FromStr};
 
-#[derive(Default)]
-pub struct Table(BTreeMap<i32, i32>);
+/// Which timestamps a session accepts
+///
+/// timestamps are plain i32s, nothing in the protocol forbids negative ones,
+/// so they are accepted unless the server is configured otherwise
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimestampPolicy {
+    #[default]
+    AcceptAll,
+    // inserts at a negative timestamp are ignored,
+    // and queries only cover the non-negative part of their period
+    RejectNegative,
+}
+
+impl TimestampPolicy {
+    pub fn admits(&self, timestamp: i32) -> bool {
+        match self {
+            Self::AcceptAll => true,
+            Self::RejectNegative => timestamp >= 0,
+        }
+    }
+
+    // Narrows a query period down to the timestamps the policy admits,
+    // the returned period is empty (min > max) when none of them are
+    pub fn clamp(&self, min_time: i32, max_time: i32) -> (i32, i32) {
+        match self {
+            Self::AcceptAll => (min_time, max_time),
+            Self::RejectNegative => (min_time.max(0), max_time),
+        }
+    }
+}
+
+impl FromStr for TimestampPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "accept" => Ok(Self::AcceptAll),
+            "reject" => Ok(Self::RejectNegative),
+            _ => Err(anyhow::anyhow!(
+                "expected \"accept\" or \"reject\", got {:?}",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Table {
+    prices: BTreeMap<i32, i32>,
+    buckets: Option<Buckets>,
+}
+
+// Pre-aggregated (sum, count) of fixed width time buckets,
+// bucket n covers the timestamps in [n * width, (n + 1) * width)
+#[derive(Debug)]
+struct Buckets {
+    width: i64,
+    totals: BTreeMap<i64, (i128, u64)>,
+}
+
+impl Buckets {
+    fn bucket(&self, timestamp: i32) -> i64 {
+        (timestamp as i64).div_euclid(self.width)
+    }
+}
 
 impl Table {
+    /// When a bucket width is given, the table aggregates its prices into time buckets,
+    /// so queries over long periods only scan the partial buckets at their edges
+    pub fn new(bucket_width: Option<NonZeroU32>) -> Self {
+        Self {
+            prices: BTreeMap::new(),
+            buckets: bucket_width.map(|width| Buckets {
+                width: width.get() as i64,
+                totals: BTreeMap::new(),
+            }),
+        }
+    }
+
+    // The number of prices in the table
+    pub fn len(&self) -> usize {
+        self.prices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.prices.is_empty()
+    }
+
     // Sets the price at the given timestamp
     // if it wasn't set before, otherwise does nothing.
     pub fn set_price(&mut self, timestamp: i32, price: i32) {
-        self.0.entry(timestamp).or_insert(price);
+        if self.prices.contains_key(&timestamp) {
+            return;
+        }
+        self.prices.insert(timestamp, price);
+
+        if let Some(buckets) = &mut self.buckets {
+            let bucket = buckets.bucket(timestamp);
+            let (sum, count) = buckets.totals.entry(bucket).or_default();
+            *sum += price as i128;
+            *count += 1;
+        }
     }
 
-    // Returns the average price over a time period, rounded down
+    // Returns the average price over a time period, rounded towards zero
+    //
+    // any period is valid, including ones that span 0 or the whole i32 range:
+    // the bucket math is done in i64 and the sums in i128, so nothing can overflow.
+    // an empty period (min > max) has an average of 0
     pub fn average(&self, min_time: i32, max_time: i32) -> i32 {
         if min_time > max_time {
             return 0;
         }
-        let mut avg = 0f64;
-        for (idx, (_, price)) in self.0.range(min_time..=max_time).enumerate() {
-            avg += (*price as f64 - avg) / (idx + 1) as f64;
+
+        let (sum, count) = match &self.buckets {
+            Some(buckets) => self.bucketed_totals(buckets, min_time, max_time),
+            None => self.scan(min_time..=max_time),
+        };
+        if count == 0 {
+            return 0;
         }
 
-        avg as i32
+        // an average of i32 prices is always within their range, saturate just in case
+        (sum / count as i128).clamp(i32::MIN as i128, i32::MAX as i128) as i32
+    }
+
+    // combines the buckets that are fully inside the period,
+    // with a scan of the partial buckets at both of its edges
+    fn bucketed_totals(&self, buckets: &Buckets, min_time: i32, max_time: i32) -> (i128, u64) {
+        let (min, max, width) = (min_time as i64, max_time as i64, buckets.width);
+        let first = (min + width - 1).div_euclid(width);
+        let last = (max + 1).div_euclid(width) - 1;
+        if first > last {
+            return self.scan(min_time..=max_time);
+        }
+
+        let (mut sum, mut count) = buckets.totals.range(first..=last).fold(
+            (0, 0),
+            |(sum, count), (_, (bucket_sum, bucket_count))| {
+                (sum + bucket_sum, count + bucket_count)
+            },
+        );
+
+        let edges = [(min, first * width - 1), ((last + 1) * width, max)];
+        for (start, end) in edges {
+            if start > end {
+                continue;
+            }
+
+            // a non empty edge is within the period, so it fits in an i32
+            let (edge_sum, edge_count) = self.scan(start as i32..=end as i32);
+            sum += edge_sum;
+            count += edge_count;
+        }
+
+        (sum, count)
+    }
+
+    fn scan(&self, period: RangeInclusive<i32>) -> (i128, u64) {
+        self.prices
+            .range(period)
+            .fold((0, 0), |(sum, count), (_, price)| {
+                (sum + *price as i128, count + 1)
+            })
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Table;
+    use std::num::NonZeroU32;
+
+    use super::{Table, TimestampPolicy};
 
     #[test]
     fn check_normal_flow() {
@@ -60,4 +205,94 @@ mod tests {
         table.set_price(-1000, 100);
         assert_eq!(table.average(899999, 1000), 0);
     }
+
+    #[test]
+    fn buckets_are_transparent() {
+        let mut plain = Table::default();
+        let mut bucketed = Table::new(NonZeroU32::new(100));
+
+        // a cheap deterministic spread of timestamps, prices and periods
+        let mut seed = 7i64;
+        let mut next = |modulo: i64| {
+            seed = (seed * 48271) % 2147483647;
+            seed % modulo - modulo / 2
+        };
+        for _ in 0..2000 {
+            let (timestamp, price) = (next(20000) as i32, next(2000) as i32);
+            plain.set_price(timestamp, price);
+            bucketed.set_price(timestamp, price);
+        }
+        for (timestamp, price) in [(i32::MIN, -5), (i32::MAX, 9)] {
+            plain.set_price(timestamp, price);
+            bucketed.set_price(timestamp, price);
+        }
+
+        let mut periods = vec![(i32::MIN, i32::MAX), (-100, 99), (0, 0), (-250, 130)];
+        periods.extend((0..500).map(|_| {
+            let (a, b) = (next(24000) as i32, next(24000) as i32);
+            (a.min(b), a.max(b))
+        }));
+        for (min_time, max_time) in periods {
+            assert_eq!(
+                bucketed.average(min_time, max_time),
+                plain.average(min_time, max_time),
+                "{}..={}",
+                min_time,
+                max_time
+            );
+        }
+    }
+
+    #[test]
+    fn extreme_timestamps_and_prices() {
+        for bucket_width in [None, NonZeroU32::new(1), NonZeroU32::new(u32::MAX)] {
+            let mut table = Table::new(bucket_width);
+            table.set_price(i32::MIN, i32::MIN);
+            table.set_price(i32::MIN + 1, i32::MIN);
+            table.set_price(-1, 10);
+            table.set_price(0, 20);
+            table.set_price(i32::MAX - 1, i32::MAX);
+            table.set_price(i32::MAX, i32::MAX);
+
+            assert_eq!(table.average(i32::MIN, i32::MIN), i32::MIN);
+            assert_eq!(table.average(i32::MAX, i32::MAX), i32::MAX);
+            assert_eq!(table.average(i32::MIN, i32::MIN + 1), i32::MIN);
+            assert_eq!(table.average(i32::MAX - 1, i32::MAX), i32::MAX);
+            // (2 * MIN + 10 + 20 + 2 * MAX) / 6, rounded towards zero
+            assert_eq!(table.average(i32::MIN, i32::MAX), 4);
+            // periods that span 0
+            assert_eq!(table.average(-1, 0), 15);
+            assert_eq!(
+                table.average(i32::MIN + 1, 0),
+                ((i32::MIN as i64 + 30) / 3) as i32
+            );
+            assert_eq!(
+                table.average(-1, i32::MAX),
+                ((2 * i32::MAX as i64 + 30) / 4) as i32
+            );
+            // empty periods
+            assert_eq!(table.average(i32::MAX, i32::MIN), 0);
+            assert_eq!(table.average(1, i32::MAX - 2), 0);
+        }
+    }
+
+    #[test]
+    fn timestamp_policies() {
+        let accept = TimestampPolicy::AcceptAll;
+        assert!(accept.admits(i32::MIN) && accept.admits(-1) && accept.admits(0));
+        assert_eq!(accept.clamp(i32::MIN, -1), (i32::MIN, -1));
+
+        let reject = TimestampPolicy::RejectNegative;
+        assert!(!reject.admits(i32::MIN) && !reject.admits(-1));
+        assert!(reject.admits(0) && reject.admits(i32::MAX));
+        assert_eq!(reject.clamp(i32::MIN, i32::MAX), (0, i32::MAX));
+        assert_eq!(reject.clamp(-10, 10), (0, 10));
+        // a period that is entirely negative becomes empty
+        let (min, max) = reject.clamp(i32::MIN, -1);
+        assert!(min > max);
+
+        assert_eq!("accept".parse::<TimestampPolicy>().unwrap(), accept);
+        assert_eq!("reject".parse::<TimestampPolicy>().unwrap(), reject);
+        assert!("maybe".parse::<TimestampPolicy>().is_err());
+    }
 }
@@ -0,0 +1,142 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::File,
+    io::{AsyncWriteExt, BufWriter},
+    sync::Mutex,
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum CaptureError {
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("bad record on line {line}: {source}")]
+    Parse {
+        line: usize,
+        source: serde_json::Error,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    ClientToServer,
+    ServerToClient,
+}
+
+/// A single proxied line
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Record {
+    // milliseconds since the client has connected
+    pub elapsed_ms: u64,
+    pub direction: Direction,
+    // the line as it was received
+    pub received: String,
+    // the line as it was sent, after the rewrite rules were applied
+    pub sent: String,
+}
+
+/// Records the traffic of a single client connection, one JSON record per line
+pub struct Capture {
+    path: PathBuf,
+    start: Instant,
+    file: Mutex<BufWriter<File>>,
+}
+
+impl Capture {
+    /// Creates a new capture file inside of the directory
+    pub async fn create(dir: &Path, session: u64) -> std::io::Result<Self> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+
+        tokio::fs::create_dir_all(dir).await?;
+        let path = dir.join(format!("{}-{}.jsonl", timestamp, session));
+        let file = File::create(&path).await?;
+
+        Ok(Self {
+            path,
+            start: Instant::now(),
+            file: Mutex::new(BufWriter::new(file)),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends a record to the capture
+    ///
+    /// a failing capture shouldn't take the proxy down, so errors are only reported
+    pub async fn record(&self, direction: Direction, received: &str, sent: &str) {
+        let record = Record {
+            elapsed_ms: self.start.elapsed().as_millis() as u64,
+            direction,
+            received: received.into(),
+            sent: sent.into(),
+        };
+
+        if let Err(err) = self.write(&record).await {
+            tracing::error!("failed to capture into {}: {}", self.path.display(), err);
+        }
+    }
+
+    async fn write(&self, record: &Record) -> std::io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+
+        // flushed right away, so the capture is complete even if the proxy crashes
+        let mut file = self.file.lock().await;
+        file.write_all(&line).await?;
+        file.flush().await
+    }
+}
+
+/// Reads the records of a capture file
+pub fn load(path: &Path) -> Result<Vec<Record>, CaptureError> {
+    std::fs::read_to_string(path)?
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(idx, line)| {
+            serde_json::from_str(line).map_err(|source| CaptureError::Parse {
+                line: idx + 1,
+                source,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load, Capture, Direction};
+
+    #[tokio::test]
+    async fn round_trip_capture() {
+        let dir = std::env::temp_dir().join(format!("mob-capture-{}", std::process::id()));
+        let capture = Capture::create(&dir, 7).await.unwrap();
+
+        capture
+            .record(Direction::ClientToServer, "alice\n", "alice\n")
+            .await;
+        capture
+            .record(Direction::ServerToClient, "[bob] 7abc\n", "[bob] 7xyz\n")
+            .await;
+
+        let records = load(capture.path()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].direction, Direction::ClientToServer);
+        assert_eq!(records[0].received, "alice\n");
+        assert_eq!(records[1].direction, Direction::ServerToClient);
+        assert_eq!(records[1].sent, "[bob] 7xyz\n");
+        assert!(records[0].elapsed_ms <= records[1].elapsed_ms);
+    }
+}
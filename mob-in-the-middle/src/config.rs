@@ -0,0 +1,60 @@
+use std::{env, path::PathBuf};
+
+const DEFAULT_UPSTREAM_ADDR: &str = "chat.protohackers.com:16963";
+
+#[derive(Debug, Clone)]
+pub struct UpstreamTls {
+    // the name the upstream's certificate is verified against,
+    // defaults to the host part of the upstream address
+    pub server_name: Option<String>,
+    // PEM encoded CA certificates to trust instead of the bundled web PKI roots
+    pub ca_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    // when set, the rewrite rules are loaded from this file instead of the built-in ones
+    pub rules_file: Option<PathBuf>,
+    pub upstream_addr: String,
+    // when set, the upstream leg is encrypted (the client leg always remains plaintext)
+    pub upstream_tls: Option<UpstreamTls>,
+    // when set, the traffic of every client is recorded into a file inside of this directory
+    pub capture_dir: Option<PathBuf>,
+    // when set, the metrics are served for prometheus to scrape on this address
+    pub metrics_addr: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            rules_file: None,
+            upstream_addr: DEFAULT_UPSTREAM_ADDR.into(),
+            upstream_tls: None,
+            capture_dir: None,
+            metrics_addr: None,
+        }
+    }
+}
+
+impl Config {
+    /// Builds the configuration from the environment,
+    /// falling back to the defaults for any variable that isn't set
+    pub fn from_env() -> Self {
+        let default = Self::default();
+
+        let upstream_tls = env::var("UPSTREAM_TLS")
+            .is_ok_and(|value| value == "1" || value.eq_ignore_ascii_case("true"))
+            .then(|| UpstreamTls {
+                server_name: env::var("UPSTREAM_TLS_SERVER_NAME").ok(),
+                ca_file: env::var_os("UPSTREAM_TLS_CA_FILE").map(PathBuf::from),
+            });
+
+        Self {
+            rules_file: env::var_os("RULES_FILE").map(PathBuf::from),
+            upstream_addr: env::var("UPSTREAM_ADDR").unwrap_or(default.upstream_addr),
+            upstream_tls,
+            capture_dir: env::var_os("CAPTURE_DIR").map(PathBuf::from),
+            metrics_addr: env::var("METRICS_ADDR").ok(),
+        }
+    }
+}
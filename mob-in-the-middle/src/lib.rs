@@ -0,0 +1,229 @@
+//! The proxy in front of the chat server,
+//! a library of its own so the verifier can run it in-process
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use capture::{Capture, Direction};
+use config::Config;
+use framing::{FramingError, LineReader, PartialLine};
+use rules::Rules;
+use telemetry::stats::Metered;
+use tokio::{
+    io::{AsyncRead, AsyncWriteExt},
+    net::TcpStream,
+};
+use upstream::{Connector, Upstream};
+
+mod capture;
+pub mod config;
+mod proxy;
+mod replay;
+mod rules;
+mod upstream;
+
+// lines longer than this are cut off, along with the connection they came from
+pub(crate) const MAX_LINE_LEN: usize = 8 * 1024;
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_RECONNECT_ATTEMPTS: u32 = 5;
+
+// sent to the client right before it's disconnected, once the upstream is gone for good
+const TEARDOWN_MSG: &str = "* The chat server is unavailable, disconnecting\n";
+
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Proxies clients to the upstream chat server, rewriting their messages on the way
+#[derive(Clone)]
+pub struct Proxy {
+    rules: Arc<Rules>,
+    connector: Arc<Connector>,
+    capture_dir: Option<Arc<Path>>,
+}
+
+impl Proxy {
+    pub fn new(config: &Config) -> anyhow::Result<Self> {
+        let rules = match &config.rules_file {
+            Some(path) => Rules::load(path)?,
+            None => Rules::default(),
+        };
+
+        Ok(Self {
+            rules: Arc::new(rules),
+            connector: Arc::new(Connector::new(config)?),
+            capture_dir: config.capture_dir.clone().map(PathBuf::into),
+        })
+    }
+
+    /// Proxies a single client, until either the client or the upstream is gone for good
+    pub async fn handle(self, client: TcpStream) -> anyhow::Result<()> {
+        handle_connection(client, self.rules, self.connector, self.capture_dir).await
+    }
+
+    /// Replays the client's side of a capture against the upstream
+    pub async fn replay(&self, path: &Path) -> anyhow::Result<()> {
+        replay::run(path, &self.connector, self.rules.clone()).await
+    }
+}
+
+// Proxies a single client, reconnecting to the upstream server whenever it drops
+async fn handle_connection(
+    client: TcpStream,
+    rules: Arc<Rules>,
+    connector: Arc<Connector>,
+    capture_dir: Option<Arc<Path>>,
+) -> anyhow::Result<()> {
+    let capture = match capture_dir {
+        Some(dir) => {
+            let session = NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed);
+            let capture = Capture::create(&dir, session).await?;
+            tracing::info!(
+                "capturing session {} into {}",
+                session,
+                capture.path().display()
+            );
+            Some(Arc::new(capture))
+        }
+        None => None,
+    };
+
+    let (creader, cwriter) = tokio::io::split(Metered::new(client));
+    let mut client_reader = line_reader(creader);
+    let mut client_writer = proxy::Writer::new(cwriter, rules.clone())
+        .with_capture(capture.clone(), Direction::ServerToClient);
+
+    // the first line the client sent (its name), replayed to every new upstream connection
+    let mut handshake = None;
+
+    let mut upstream = connector.connect().await?;
+    let mut resume = false;
+    loop {
+        let session = Session {
+            client_reader: &mut client_reader,
+            client_writer: &mut client_writer,
+            handshake: &mut handshake,
+            rules: rules.clone(),
+            capture: capture.clone(),
+        };
+
+        match session.run(&mut upstream, resume).await? {
+            SessionEnd::ClientClosed => return Ok(()),
+            SessionEnd::UpstreamLost => telemetry::stats::error("upstream-lost"),
+        }
+
+        match reconnect(&connector).await {
+            Some(stream) => {
+                upstream = stream;
+                resume = true;
+            }
+            None => {
+                client_writer.write(TEARDOWN_MSG).await?;
+                return Ok(());
+            }
+        }
+    }
+}
+
+// Tries to connect to the upstream server, backing off exponentially between attempts
+async fn reconnect(connector: &Connector) -> Option<Upstream> {
+    let mut backoff = INITIAL_BACKOFF;
+    for attempt in 1..=MAX_RECONNECT_ATTEMPTS {
+        tokio::time::sleep(backoff).await;
+
+        match connector.connect().await {
+            Ok(stream) => return Some(stream),
+            Err(err) => tracing::warn!("reconnect attempt {} has failed: {}", attempt, err),
+        }
+
+        backoff *= 2;
+    }
+
+    None
+}
+
+// a partial line at the end of the stream is never proxied, it may be the part of a cut off message
+pub(crate) fn line_reader<R: AsyncRead + Unpin>(reader: R) -> LineReader<R> {
+    LineReader::new(reader, MAX_LINE_LEN).with_partial_line(PartialLine::Discard)
+}
+
+// Reads the next line along with its newline, which is proxied as a part of the line
+pub(crate) async fn read_line<R: AsyncRead + Unpin>(
+    reader: &mut LineReader<R>,
+) -> Result<Option<String>, FramingError> {
+    let line = reader.read_line().await?;
+    Ok(line.map(|line| line + "\n"))
+}
+
+enum SessionEnd {
+    ClientClosed,
+    UpstreamLost,
+}
+
+// The part of a client connection that is served by a single upstream connection
+struct Session<'a, R, W> {
+    client_reader: &'a mut LineReader<R>,
+    client_writer: &'a mut proxy::Writer<W>,
+    handshake: &'a mut Option<String>,
+    rules: Arc<Rules>,
+    capture: Option<Arc<Capture>>,
+}
+
+impl<R, W> Session<'_, R, W>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWriteExt + Unpin,
+{
+    // proxies lines in both directions until either of the ends terminate,
+    // errors are only returned for the client's end
+    async fn run(self, upstream: &mut Upstream, resume: bool) -> anyhow::Result<SessionEnd> {
+        let (sreader, swriter) = tokio::io::split(upstream);
+        let mut server_reader = line_reader(sreader);
+        let mut server_writer = proxy::Writer::new(swriter, self.rules);
+
+        if resume {
+            if let Some(handshake) = self.handshake.as_deref() {
+                // the client has already seen the server's greeting, only the name is sent again
+                if server_writer.write(handshake).await.is_err()
+                    || !matches!(read_line(&mut server_reader).await, Ok(Some(_)))
+                {
+                    return Ok(SessionEnd::UpstreamLost);
+                }
+            }
+        }
+
+        // the handshake replayed above isn't something the client has sent, so it isn't captured
+        let mut server_writer = server_writer.with_capture(self.capture, Direction::ClientToServer);
+
+        loop {
+            tokio::select! {
+                line = read_line(self.client_reader) => {
+                    let Some(line) = line? else {
+                        return Ok(SessionEnd::ClientClosed);
+                    };
+
+                    if self.handshake.is_none() {
+                        *self.handshake = Some(line.clone());
+                    }
+                    telemetry::stats::request("message");
+
+                    if server_writer.write(&line).await.is_err() {
+                        return Ok(SessionEnd::UpstreamLost);
+                    }
+                }
+                line = read_line(&mut server_reader) => match line {
+                    Ok(Some(line)) => self.client_writer.write(&line).await?,
+                    Ok(None) => return Ok(SessionEnd::UpstreamLost),
+                    Err(err) => {
+                        tracing::warn!("lost the upstream connection: {}", err);
+                        return Ok(SessionEnd::UpstreamLost);
+                    }
+                },
+            }
+        }
+    }
+}
@@ -1,60 +1,50 @@
-use tokio::{
-    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
-    net::{TcpListener, TcpStream},
-};
+use std::path::Path;
 
-mod proxy;
+use mob_in_the_middle::{config::Config, Proxy};
+use throttle::{Limits, Throttle};
+use tokio::net::TcpListener;
 
-const BUDGET_CHAT_ADDR: &str = "chat.protohackers.com:16963";
+const USAGE: &str = "usage: mob-in-the-middle [replay <capture file>]";
 
 #[tokio::main]
-async fn main() -> tokio::io::Result<()> {
-    let listener = TcpListener::bind("0.0.0.0:3600").await?;
-    println!("Server listening on: {}", listener.local_addr()?);
-
-    loop {
-        let (conn, _) = listener.accept().await?;
-        tokio::spawn(handle_connection(conn));
+async fn main() -> anyhow::Result<()> {
+    telemetry::init();
+    let config = Config::from_env();
+    let proxy = Proxy::new(&config)?;
+
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        None => {}
+        Some("replay") => {
+            let path = args.next().ok_or_else(|| anyhow::anyhow!(USAGE))?;
+            return proxy.replay(Path::new(&path)).await;
+        }
+        Some(_) => anyhow::bail!(USAGE),
     }
-}
-
-async fn handle_connection(mut client: TcpStream) -> tokio::io::Result<()> {
-    let mut server = TcpStream::connect(BUDGET_CHAT_ADDR).await?;
-
-    // Split the streams into reader/writer
-    let (creader, cwriter) = client.split();
-    let (sreader, swriter) = server.split();
-
-    // connect creader with swriter & sreader with cwriter
-    let client_to_server_proxy = connect_reader_to_writer(creader, swriter);
-    let server_to_client_proxy = connect_reader_to_writer(sreader, cwriter);
-
-    // wait until either of the ends terminate
-    tokio::select! {
-        _ = client_to_server_proxy => {}
-        _ = server_to_client_proxy => {}
-    };
 
-    Ok(())
-}
-
-async fn connect_reader_to_writer<R, W>(reader: R, writer: W) -> tokio::io::Result<()>
-where
-    R: AsyncReadExt + Unpin,
-    W: AsyncWriteExt + Unpin,
-{
-    let mut reader = BufReader::new(reader);
-    let mut writer = proxy::Writer::new(writer);
-
-    loop {
-        let mut line = String::new();
-        let rcount = reader.read_line(&mut line).await?;
-        if rcount == 0 {
-            break;
+    if let Some(addr) = &config.metrics_addr {
+        if let Err(err) = telemetry::stats::install(addr) {
+            tracing::error!("failed to start the metrics exporter: {}", err);
         }
+    }
 
-        writer.write(&line).await?;
+    let throttle = Throttle::new(Limits::from_env()?);
+    let listener = TcpListener::bind("0.0.0.0:3600").await?;
+    tracing::info!("Server listening on: {}", listener.local_addr()?);
+    tracing::info!(
+        "Proxying to: {} (tls: {})",
+        config.upstream_addr,
+        config.upstream_tls.is_some()
+    );
+    if let Some(dir) = &config.capture_dir {
+        tracing::info!("Capturing traffic into: {}", dir.display());
     }
 
-    Ok(())
+    loop {
+        let (conn, addr, permit) = throttle.accept(&listener).await?;
+        tokio::spawn(telemetry::connection(
+            addr,
+            permit.hold(proxy.clone().handle(conn)),
+        ));
+    }
 }
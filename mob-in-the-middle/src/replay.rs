@@ -0,0 +1,62 @@
+use std::{path::Path, sync::Arc, time::Duration};
+
+use tokio::time::Instant;
+
+use crate::{
+    capture::{self, Direction},
+    line_reader, proxy, read_line,
+    rules::Rules,
+    upstream::Connector,
+};
+
+// how long to keep listening to the upstream once the last client line has been sent
+const LINGER: Duration = Duration::from_secs(1);
+
+/// Replays the client's side of a capture against the upstream, keeping the original timing
+///
+/// both directions go through the rewrite rules, just like they would through the proxy
+pub async fn run(path: &Path, connector: &Connector, rules: Arc<Rules>) -> anyhow::Result<()> {
+    let records = capture::load(path)?;
+    let mut lines = records
+        .into_iter()
+        .filter(|record| record.direction == Direction::ClientToServer)
+        .map(|record| (Duration::from_millis(record.elapsed_ms), record.received));
+
+    let upstream = connector.connect().await?;
+    let (sreader, swriter) = tokio::io::split(upstream);
+    let mut server_reader = line_reader(sreader);
+    let mut server_writer = proxy::Writer::new(swriter, rules.clone());
+    // the rewritten server lines are only printed, there's no client to send them to
+    let mut client_writer = proxy::Writer::new(tokio::io::sink(), rules);
+
+    let start = Instant::now();
+    let mut next = lines.next();
+    let mut last_sent = Duration::ZERO;
+    loop {
+        let deadline = match &next {
+            Some((elapsed, _)) => start + *elapsed,
+            None => start + last_sent + LINGER,
+        };
+
+        tokio::select! {
+            _ = tokio::time::sleep_until(deadline) => {
+                let Some((elapsed, line)) = next.take() else {
+                    break;
+                };
+
+                server_writer.write(&line).await?;
+                last_sent = elapsed;
+                next = lines.next();
+            }
+            line = read_line(&mut server_reader) => match line? {
+                Some(line) => client_writer.write(&line).await?,
+                None => {
+                    tracing::info!("the upstream has closed the connection");
+                    break;
+                }
+            },
+        }
+    }
+
+    Ok(())
+}
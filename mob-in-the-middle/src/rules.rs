@@ -0,0 +1,206 @@
+use std::path::Path;
+
+use serde::Deserialize;
+
+const TONYS_ADDR: &str = "7YWHMfk9JZe0LM0g1ZauHuiSxhI";
+
+#[derive(thiserror::Error, Debug)]
+pub enum RulesError {
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("{0}")]
+    Parse(#[from] toml::de::Error),
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Charset {
+    Alphanumeric,
+    Alphabetic,
+    Numeric,
+}
+
+impl Charset {
+    fn contains(&self, ch: char) -> bool {
+        match self {
+            Self::Alphanumeric => ch.is_ascii_alphanumeric(),
+            Self::Alphabetic => ch.is_ascii_alphabetic(),
+            Self::Numeric => ch.is_ascii_digit(),
+        }
+    }
+}
+
+/// A single rewrite rule
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(tag = "match", rename_all = "snake_case")]
+pub enum Rule {
+    /// Replaces every space separated word that satisfies all of the constraints
+    Word {
+        prefix: Option<String>,
+        min_len: Option<usize>,
+        max_len: Option<usize>,
+        charset: Option<Charset>,
+        replace: String,
+    },
+    /// Replaces every occurrence of the pattern
+    Literal { pattern: String, replace: String },
+}
+
+impl Rule {
+    fn apply(&self, message: String) -> String {
+        match self {
+            Self::Word { replace, .. } => message
+                .split(' ')
+                .map(|part| {
+                    // the last word carries the line's newline
+                    let word = part.trim();
+                    if self.is_match(word) {
+                        part.replace(word, replace)
+                    } else {
+                        part.to_string()
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(" "),
+            Self::Literal { pattern, replace } if !pattern.is_empty() => {
+                message.replace(pattern, replace)
+            }
+            Self::Literal { .. } => message,
+        }
+    }
+
+    fn is_match(&self, word: &str) -> bool {
+        let Self::Word {
+            prefix,
+            min_len,
+            max_len,
+            charset,
+            ..
+        } = self
+        else {
+            return false;
+        };
+
+        !word.is_empty()
+            && prefix
+                .as_ref()
+                .is_none_or(|prefix| word.starts_with(prefix))
+            && min_len.is_none_or(|min_len| word.len() >= min_len)
+            && max_len.is_none_or(|max_len| word.len() <= max_len)
+            && charset.is_none_or(|charset| word.chars().all(|ch| charset.contains(ch)))
+    }
+}
+
+/// An ordered list of rewrite rules, applied one after the other to every message
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+pub struct Rules {
+    #[serde(rename = "rule", default)]
+    rules: Vec<Rule>,
+}
+
+impl Default for Rules {
+    /// Rewrites every Boguscoin address into Tony's address
+    fn default() -> Self {
+        Self {
+            rules: vec![Rule::Word {
+                prefix: Some("7".into()),
+                min_len: Some(26),
+                max_len: Some(35),
+                charset: Some(Charset::Alphanumeric),
+                replace: TONYS_ADDR.into(),
+            }],
+        }
+    }
+}
+
+impl Rules {
+    /// Loads the rules from a TOML file, e.g.
+    ///
+    /// ```toml
+    /// [[rule]]
+    /// match = "word"
+    /// prefix = "7"
+    /// min_len = 26
+    /// max_len = 35
+    /// charset = "alphanumeric"
+    /// replace = "7YWHMfk9JZe0LM0g1ZauHuiSxhI"
+    ///
+    /// [[rule]]
+    /// match = "literal"
+    /// pattern = "Bob"
+    /// replace = "Tony"
+    /// ```
+    pub fn load(path: &Path) -> Result<Self, RulesError> {
+        std::fs::read_to_string(path)?.parse()
+    }
+
+    pub fn apply(&self, message: &str) -> String {
+        self.rules
+            .iter()
+            .fold(message.to_string(), |message, rule| rule.apply(message))
+    }
+}
+
+impl std::str::FromStr for Rules {
+    type Err = RulesError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(toml::from_str(s)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Rules, TONYS_ADDR};
+
+    #[test]
+    fn check_is_bogus_address() {
+        let rules = Rules::default();
+
+        let valid_addresses = [
+            "7F1u3wSD5RbOHQmupo9nx4TnhQ",
+            "7iKDZEwPZSqIvDnHvVN2r0hUWXD5rHX",
+            "7LOrwbDlS8NujgjddyogWgIM93MV5N2VR",
+            "7adNeSwJkMakpEcln9HEtthSRtxdmEHOT8T",
+        ];
+
+        for addr in valid_addresses {
+            assert_eq!(rules.apply(addr), TONYS_ADDR)
+        }
+
+        let invalid_addresses = [
+            "7F1u3wSD5RbOHQmupo9",
+            "8iKDZEwPZSqIvDnHvVN2r0hUWXD5rHX",
+            "7adNeSwJkMakpEcln9HEtthSRtxdmEHOT8T7adNeSwJkMakpEcln9HEtthSRtxdmEHOT8T",
+            "7LOrwbDlS8Nujgj gWgIM93MV5N2VR",
+        ];
+
+        for addr in invalid_addresses {
+            assert_eq!(rules.apply(addr), addr);
+        }
+    }
+
+    #[test]
+    fn apply_rules_in_order() {
+        let rules: Rules = r#"
+            [[rule]]
+            match = "literal"
+            pattern = "Bob"
+            replace = "Tony"
+
+            [[rule]]
+            match = "word"
+            prefix = "To"
+            charset = "alphabetic"
+            replace = "Anthony"
+        "#
+        .parse()
+        .unwrap();
+
+        assert_eq!(
+            rules.apply("[Bob] send it to Bob\n"),
+            "[Tony] send it to Anthony\n"
+        );
+    }
+}
@@ -0,0 +1,146 @@
+use std::{
+    io,
+    path::Path,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpStream,
+};
+use tokio_rustls::{
+    client::TlsStream,
+    rustls::{
+        self,
+        pki_types::{pem::PemObject, CertificateDer, ServerName},
+        ClientConfig, RootCertStore,
+    },
+    TlsConnector,
+};
+
+use crate::config::{Config, UpstreamTls};
+
+#[derive(thiserror::Error, Debug)]
+pub enum ConnectorError {
+    #[error("invalid TLS server name: {0}")]
+    BadServerName(String),
+
+    #[error("failed to load the CA certificates: {0}")]
+    BadCaFile(#[from] rustls::pki_types::pem::Error),
+
+    #[error("{0}")]
+    Tls(#[from] rustls::Error),
+}
+
+/// Opens connections to the upstream server, wrapping them in TLS when configured to
+pub struct Connector {
+    addr: String,
+    tls: Option<(TlsConnector, ServerName<'static>)>,
+}
+
+impl Connector {
+    pub fn new(config: &Config) -> Result<Self, ConnectorError> {
+        let tls = match &config.upstream_tls {
+            Some(tls) => Some(tls_connector(&config.upstream_addr, tls)?),
+            None => None,
+        };
+
+        Ok(Self {
+            addr: config.upstream_addr.clone(),
+            tls,
+        })
+    }
+
+    pub async fn connect(&self) -> io::Result<Upstream> {
+        let stream = TcpStream::connect(&self.addr).await?;
+
+        match &self.tls {
+            Some((connector, server_name)) => {
+                let stream = connector.connect(server_name.clone(), stream).await?;
+                Ok(Upstream::Tls(Box::new(stream)))
+            }
+            None => Ok(Upstream::Plain(stream)),
+        }
+    }
+}
+
+// the server name defaults to the host part of the upstream address
+fn tls_connector(
+    addr: &str,
+    tls: &UpstreamTls,
+) -> Result<(TlsConnector, ServerName<'static>), ConnectorError> {
+    let name = match &tls.server_name {
+        Some(name) => name.as_str(),
+        None => addr.rsplit_once(':').map_or(addr, |(host, _)| host),
+    };
+    let server_name = ServerName::try_from(name.to_string())
+        .map_err(|_| ConnectorError::BadServerName(name.to_string()))?;
+
+    let mut roots = RootCertStore::empty();
+    match &tls.ca_file {
+        Some(path) => add_ca_file(&mut roots, path)?,
+        None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+    }
+
+    let config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    Ok((TlsConnector::from(Arc::new(config)), server_name))
+}
+
+fn add_ca_file(roots: &mut RootCertStore, path: &Path) -> Result<(), ConnectorError> {
+    for cert in CertificateDer::pem_file_iter(path)? {
+        roots.add(cert?)?;
+    }
+
+    Ok(())
+}
+
+/// A connection to the upstream server
+pub enum Upstream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl AsyncRead for Upstream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            Self::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Upstream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            Self::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            Self::Tls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            Self::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
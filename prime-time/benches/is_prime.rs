@@ -0,0 +1,45 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use prime_time::prime::{trial_division, wheel};
+
+type Check = fn(u64) -> bool;
+
+const IMPLEMENTATIONS: [(&str, Check); 2] = [("trial_division", trial_division), ("wheel", wheel)];
+
+fn small(c: &mut Criterion) {
+    let mut group = c.benchmark_group("small");
+    for (name, check) in IMPLEMENTATIONS {
+        // a mix of primes and composites, most of them are answered right away
+        group.bench_function(name, |b| {
+            b.iter(|| (0..1000).filter(|number| check(black_box(*number))).count())
+        });
+    }
+    group.finish();
+}
+
+fn medium(c: &mut Criterion) {
+    let mut group = c.benchmark_group("medium");
+    for number in [1_000_003, 1_000_000_007] {
+        for (name, check) in IMPLEMENTATIONS {
+            group.bench_with_input(BenchmarkId::new(name, number), &number, |b, number| {
+                b.iter(|| check(black_box(*number)))
+            });
+        }
+    }
+    group.finish();
+}
+
+fn worst_case(c: &mut Criterion) {
+    let mut group = c.benchmark_group("worst_case");
+    // every divisor up to the square root is tried for a large prime
+    group.sample_size(10);
+    let number = 9_007_199_254_740_881;
+    for (name, check) in IMPLEMENTATIONS {
+        group.bench_with_input(BenchmarkId::new(name, number), &number, |b, number| {
+            b.iter(|| check(black_box(*number)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, small, medium, worst_case);
+criterion_main!(benches);
@@ -0,0 +1,195 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Limit {
+    // connections per second
+    pub rate: f64,
+    // the number of connections that may be accepted in a single burst
+    pub burst: f64,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+// Counters to tune the thresholds by
+#[derive(Debug, Default)]
+pub struct Stats {
+    admitted: AtomicU64,
+    // shed since connections came in faster than the accept rate
+    rate_limited: AtomicU64,
+    // shed since too many connections were waiting for their first request
+    congested: AtomicU64,
+}
+
+impl Stats {
+    pub fn admitted(&self) -> u64 {
+        self.admitted.load(Ordering::Relaxed)
+    }
+
+    pub fn rate_limited(&self) -> u64 {
+        self.rate_limited.load(Ordering::Relaxed)
+    }
+
+    pub fn congested(&self) -> u64 {
+        self.congested.load(Ordering::Relaxed)
+    }
+}
+
+/// Sheds new connections during connection floods, before they cost anything to serve
+///
+/// a connection is shed when connections come in faster than the accept rate (a token bucket),
+/// or when too many connections have been accepted but haven't sent their first request yet.
+#[derive(Debug)]
+pub struct Admission {
+    limit: Option<Limit>,
+    bucket: Mutex<Bucket>,
+    max_pending: Option<usize>,
+    pending: AtomicUsize,
+    stats: Stats,
+}
+
+/// A connection that was admitted but hasn't sent its first request yet,
+/// it counts towards the pending connections until it's dropped
+#[derive(Debug)]
+pub struct Pending(Arc<Admission>);
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        self.0.pending.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Admission {
+    pub fn new(limit: Option<Limit>, max_pending: Option<usize>) -> Self {
+        Self {
+            limit,
+            bucket: Mutex::new(Bucket {
+                tokens: limit.map_or(0.0, |limit| limit.burst),
+                refilled: Instant::now(),
+            }),
+            max_pending,
+            pending: AtomicUsize::default(),
+            stats: Stats::default(),
+        }
+    }
+
+    /// Decides whether to serve a new connection, returns None if it should be closed right away
+    pub fn admit(self: &Arc<Self>) -> Option<Pending> {
+        self.admit_at(Instant::now())
+    }
+
+    fn admit_at(self: &Arc<Self>, now: Instant) -> Option<Pending> {
+        let pending = self.pending.fetch_add(1, Ordering::Relaxed) + 1;
+        // from here on, dropping the guard is what takes the connection off the gauge
+        let guard = Pending(self.clone());
+
+        if self
+            .max_pending
+            .is_some_and(|max_pending| pending > max_pending)
+        {
+            self.stats.congested.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+
+        if !self.take_token(now) {
+            self.stats.rate_limited.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+
+        self.stats.admitted.fetch_add(1, Ordering::Relaxed);
+        Some(guard)
+    }
+
+    fn take_token(&self, now: Instant) -> bool {
+        let Some(limit) = self.limit else {
+            return true;
+        };
+
+        let mut bucket = self.bucket.lock().unwrap();
+        let elapsed = now.saturating_duration_since(bucket.refilled).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * limit.rate).min(limit.burst);
+        bucket.refilled = now;
+
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    /// The number of admitted connections that haven't sent their first request yet
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::Relaxed)
+    }
+
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use super::{Admission, Limit};
+
+    #[test]
+    fn limit_the_accept_rate() {
+        let admission = Arc::new(Admission::new(
+            Some(Limit {
+                rate: 10.0,
+                burst: 3.0,
+            }),
+            None,
+        ));
+        let now = Instant::now();
+
+        let admitted: Vec<_> = (0..3).map(|_| admission.admit_at(now)).collect();
+        assert!(admitted.iter().all(Option::is_some));
+        assert!(admission.admit_at(now).is_none());
+
+        // a token is refilled every 100ms
+        let later = now + Duration::from_millis(100);
+        assert!(admission.admit_at(later).is_some());
+        assert!(admission.admit_at(later).is_none());
+
+        assert_eq!(admission.stats().admitted(), 4);
+        assert_eq!(admission.stats().rate_limited(), 2);
+        assert_eq!(admission.stats().congested(), 0);
+    }
+
+    #[test]
+    fn limit_the_pending_connections() {
+        let admission = Arc::new(Admission::new(None, Some(2)));
+        let now = Instant::now();
+
+        let first = admission.admit_at(now).unwrap();
+        let second = admission.admit_at(now).unwrap();
+        assert!(admission.admit_at(now).is_none());
+        // a shed connection doesn't stay on the gauge
+        assert_eq!(admission.pending(), 2);
+
+        // once a connection sent its first request, there's room for another one
+        drop(first);
+        let _third = admission.admit_at(now).unwrap();
+        assert!(admission.admit_at(now).is_none());
+        drop(second);
+        assert_eq!(admission.pending(), 1);
+
+        assert_eq!(admission.stats().admitted(), 3);
+        assert_eq!(admission.stats().congested(), 2);
+    }
+}
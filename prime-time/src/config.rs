@@ -0,0 +1,66 @@
+use std::{env, io, str::FromStr};
+
+use prime_time::{admission::Limit, server::DEFAULT_MAX_REQUEST_SIZE};
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    // accept requests that go beyond the spec (e.g. a batch of numbers),
+    // strict mode is what the checker expects
+    pub lenient: bool,
+    // when set, connections above the rate are closed right away
+    pub accept_limit: Option<Limit>,
+    // when set, new connections are closed right away while this many
+    // connections haven't sent their first request yet
+    pub max_pending: Option<usize>,
+    // when set, the metrics are served for prometheus to scrape on this address
+    pub metrics_addr: Option<String>,
+    // requests longer than this many bytes are answered as malformed
+    pub max_request_size: usize,
+}
+
+impl Config {
+    /// Builds the configuration from the environment,
+    /// falling back to the defaults for any variable that isn't set
+    pub fn from_env() -> io::Result<Self> {
+        Ok(Self {
+            lenient: env::var("LENIENT_MODE")
+                .map(|value| value == "1" || value.eq_ignore_ascii_case("true"))
+                .unwrap_or(false),
+            accept_limit: read_accept_limit()?,
+            max_pending: read_var::<usize>("MAX_PENDING")?.map(|max_pending| max_pending.max(1)),
+            metrics_addr: env::var("METRICS_ADDR").ok(),
+            max_request_size: read_var("MAX_REQUEST_SIZE")?.unwrap_or(DEFAULT_MAX_REQUEST_SIZE),
+        })
+    }
+}
+
+// the burst defaults to a single second worth of connections
+fn read_accept_limit() -> io::Result<Option<Limit>> {
+    let Some(rate) = read_var::<f64>("ACCEPT_RATE")? else {
+        return Ok(None);
+    };
+    if rate <= 0.0 {
+        return Err(bad_value("ACCEPT_RATE", "must be positive"));
+    }
+
+    let burst = read_var::<f64>("ACCEPT_BURST")?.unwrap_or(rate).max(1.0);
+    Ok(Some(Limit { rate, burst }))
+}
+
+fn read_var<T>(name: &str) -> io::Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    match env::var(name) {
+        Ok(value) => value.parse().map(Some).map_err(|err| bad_value(name, err)),
+        Err(_) => Ok(None),
+    }
+}
+
+fn bad_value(name: &str, reason: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("bad value for {}: {}", name, reason),
+    )
+}
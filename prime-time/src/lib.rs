@@ -0,0 +1,7 @@
+//! The primality checks and the request handling behind the server,
+//! a library of their own so they can be benchmarked against each other,
+//! and so the verifier can run the server in-process
+pub mod admission;
+pub mod prime;
+pub mod protocol;
+pub mod server;
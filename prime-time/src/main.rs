@@ -1,97 +1,74 @@
-use protocol::MALFORMED_RESPONSE;
-use tokio::{
-    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
-    net::{TcpListener, TcpStream},
-};
+use std::{sync::Arc, time::Duration};
 
-mod protocol;
+use config::Config;
+use prime_time::{admission::Admission, server::serve};
+use throttle::{Limits, Throttle};
+use tokio::net::TcpListener;
 
-#[tokio::main]
-async fn main() -> std::io::Result<()> {
-    let listener = TcpListener::bind("0.0.0.0:3600").await?;
-    loop {
-        let (conn, _) = listener.accept().await?;
-        tokio::spawn(serve(conn));
-    }
-}
+mod config;
 
-async fn serve(mut client: TcpStream) {
-    let (reader, mut writer) = client.split();
-    let mut reader = BufReader::new(reader);
-    loop {
-        let mut line = String::new();
-        let rcount = reader
-            .read_line(&mut line)
-            .await
-            .expect("reading from socket");
-        if rcount == 0 {
-            // reached EOF
-            return;
-        }
+// how often the admission statistics are reported
+const STATS_INTERVAL: Duration = Duration::from_secs(10);
 
-        match protocol::get_number_from_request(&line) {
-            Err(_) => {
-                // received a bad request, return a malformed response and close the socket
-                writer
-                    .write_all(MALFORMED_RESPONSE.as_bytes())
-                    .await
-                    .expect("write to socket");
-                return;
-            }
-            Ok(number) => {
-                let response = protocol::Response::new(number.map(is_prime).unwrap_or(false));
-                let response =
-                    serde_json::to_string(&response).expect("failed to serialize response") + "\n";
+#[tokio::main]
+async fn main() -> std::io::Result<()> {
+    telemetry::init();
 
-                writer
-                    .write_all(response.as_bytes())
-                    .await
-                    .expect("write to socket");
-            }
+    let config = Config::from_env()?;
+    if let Some(addr) = &config.metrics_addr {
+        if let Err(err) = telemetry::stats::install(addr) {
+            tracing::error!("failed to start the metrics exporter: {}", err);
         }
     }
-}
-
-fn is_prime(number: u64) -> bool {
-    if number == 2 || number == 3 {
-        return true;
-    }
 
-    if number <= 1 {
-        return false;
+    let throttle = Throttle::new(Limits::from_env().map_err(std::io::Error::other)?);
+    let admission = Arc::new(Admission::new(config.accept_limit, config.max_pending));
+    if config.accept_limit.is_some() || config.max_pending.is_some() {
+        tokio::spawn(report_stats(admission.clone()));
     }
 
-    for div in 2..number {
-        if div * div > number {
-            break;
-        }
-
-        if number % div == 0 {
-            return false;
+    let listener = TcpListener::bind("0.0.0.0:3600").await?;
+    tracing::info!("Server listening on: {}", listener.local_addr()?);
+    loop {
+        let (conn, addr, permit) = throttle.accept(&listener).await?;
+        // a shed connection is closed (dropped) right away
+        match admission.admit() {
+            Some(pending) => {
+                tokio::spawn(telemetry::connection(
+                    addr,
+                    permit.hold(serve(
+                        conn,
+                        pending,
+                        config.lenient,
+                        config.max_request_size,
+                    )),
+                ));
+            }
+            None => tracing::debug!("shed the connection of {}", addr),
         }
     }
-
-    true
 }
 
-#[cfg(test)]
-mod tests {
-    use super::is_prime;
+async fn report_stats(admission: Arc<Admission>) {
+    let mut interval = tokio::time::interval(STATS_INTERVAL);
+    let mut last_admitted = 0;
+    let mut last_shed = 0;
 
-    #[test]
-    fn check_is_prime() {
-        // primes
-        assert!(is_prime(2));
-        assert!(is_prime(3));
-        assert!(is_prime(5));
-        assert!(is_prime(13));
-        assert!(is_prime(8191));
+    loop {
+        interval.tick().await;
 
-        // not primes
-        assert!(!is_prime(0));
-        assert!(!is_prime(1));
-        assert!(!is_prime(4));
-        assert!(!is_prime(6));
-        assert!(!is_prime(45));
+        // only report when there was some activity since the last report
+        let stats = admission.stats();
+        let (admitted, shed) = (stats.admitted(), stats.rate_limited() + stats.congested());
+        if (admitted, shed) != (last_admitted, last_shed) {
+            tracing::info!(
+                "connections admitted: {}, rate limited: {}, shed while congested: {}, pending: {}",
+                admitted,
+                stats.rate_limited(),
+                stats.congested(),
+                admission.pending(),
+            );
+            (last_admitted, last_shed) = (admitted, shed);
+        }
     }
 }
@@ -0,0 +1,82 @@
+/// Whether a number is prime, the check the server answers with
+pub fn is_prime(number: u64) -> bool {
+    wheel(number)
+}
+
+/// Trial division by every number up to the square root
+pub fn trial_division(number: u64) -> bool {
+    if number < 2 {
+        return false;
+    }
+
+    let mut div = 2;
+    // the division can't overflow, unlike squaring the divisor
+    while div <= number / div {
+        if number.is_multiple_of(div) {
+            return false;
+        }
+        div += 1;
+    }
+
+    true
+}
+
+/// Trial division that skips the multiples of 2 and 3
+///
+/// past 3, every prime is of the form 6k ± 1, so only a third of the divisors are tried
+pub fn wheel(number: u64) -> bool {
+    if number < 4 {
+        return number >= 2;
+    }
+
+    if number.is_multiple_of(2) || number.is_multiple_of(3) {
+        return false;
+    }
+
+    let mut div = 5;
+    while div <= number / div {
+        if number.is_multiple_of(div) || number.is_multiple_of(div + 2) {
+            return false;
+        }
+        div += 6;
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_prime, trial_division, wheel};
+
+    #[test]
+    fn check_is_prime() {
+        // primes
+        assert!(is_prime(2));
+        assert!(is_prime(3));
+        assert!(is_prime(5));
+        assert!(is_prime(13));
+        assert!(is_prime(8191));
+        assert!(is_prime(1_000_000_007));
+        // the largest prime a JSON number holds exactly
+        assert!(is_prime(9_007_199_254_740_881));
+
+        // not primes
+        assert!(!is_prime(0));
+        assert!(!is_prime(1));
+        assert!(!is_prime(4));
+        assert!(!is_prime(6));
+        assert!(!is_prime(45));
+        // squares of primes on both sides of the wheel
+        assert!(!is_prime(17 * 17));
+        assert!(!is_prime(19 * 19));
+        assert!(!is_prime(1_000_000_007 * 1_000_000_009));
+        assert!(!is_prime(u64::MAX));
+    }
+
+    #[test]
+    fn implementations_agree() {
+        for number in 0..20_000 {
+            assert_eq!(wheel(number), trial_division(number), "{}", number);
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use serde_json::value::Number;
 use thiserror::Error;
 
 const METHOD_NAME: &str = "isPrime";
@@ -10,34 +11,117 @@ pub enum ParseRequestError {
     Parse(#[from] serde_json::Error),
     #[error("Unknown method name: {0}")]
     UnknownMethod(String),
+    #[error("Batch requests are only accepted in lenient mode")]
+    BatchNotAllowed,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Numbers {
+    Single(Number),
+    Batch(Vec<Number>),
 }
 
 #[derive(Deserialize)]
 pub struct Request {
     method: String,
-    number: serde_json::value::Number,
+    number: Numbers,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Prime {
+    Single(bool),
+    Batch(Vec<bool>),
 }
 
 #[derive(Serialize)]
 pub struct Response {
     method: String,
-    prime: bool,
+    prime: Prime,
 }
 
 impl Response {
-    pub fn new(is_prime: bool) -> Self {
+    pub fn new(prime: Prime) -> Self {
         Self {
             method: METHOD_NAME.into(),
-            prime: is_prime,
+            prime,
         }
     }
 }
 
-pub fn get_number_from_request(request: &str) -> Result<Option<u64>, ParseRequestError> {
+/// The numbers a request asks about,
+/// a number that isn't a non-negative integer is None
+#[derive(Debug, PartialEq, Eq)]
+pub enum Query {
+    Single(Option<u64>),
+    Batch(Vec<Option<u64>>),
+}
+
+impl Query {
+    /// Answers the query, numbers that aren't non-negative integers are never prime
+    pub fn answer(&self, is_prime: impl Fn(u64) -> bool) -> Response {
+        let check = |number: &Option<u64>| number.is_some_and(&is_prime);
+        let prime = match self {
+            Self::Single(number) => Prime::Single(check(number)),
+            Self::Batch(numbers) => Prime::Batch(numbers.iter().map(check).collect()),
+        };
+
+        Response::new(prime)
+    }
+}
+
+/// Parses a request, batches of numbers are only accepted in lenient mode
+pub fn parse_request(request: &str, lenient: bool) -> Result<Query, ParseRequestError> {
     let req: Request = serde_json::from_str(request)?;
     if req.method != METHOD_NAME {
         return Err(ParseRequestError::UnknownMethod(req.method));
     }
 
-    Ok(req.number.as_u64())
+    match req.number {
+        Numbers::Single(number) => Ok(Query::Single(number.as_u64())),
+        Numbers::Batch(numbers) if lenient => {
+            Ok(Query::Batch(numbers.iter().map(Number::as_u64).collect()))
+        }
+        Numbers::Batch(_) => Err(ParseRequestError::BatchNotAllowed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_request, ParseRequestError, Query};
+
+    #[test]
+    fn answer_batch_requests() {
+        let request = r#"{"method":"isPrime","number":[2,4,-3,5.5,7]}"#;
+        let query = parse_request(request, true).unwrap();
+        assert_eq!(
+            query,
+            Query::Batch(vec![Some(2), Some(4), None, None, Some(7)])
+        );
+
+        let response = serde_json::to_string(&query.answer(|number| number % 2 == 1)).unwrap();
+        assert_eq!(
+            response,
+            r#"{"method":"isPrime","prime":[false,false,false,false,true]}"#
+        );
+    }
+
+    #[test]
+    fn reject_batch_requests_in_strict_mode() {
+        let request = r#"{"method":"isPrime","number":[2,3]}"#;
+        assert!(matches!(
+            parse_request(request, false),
+            Err(ParseRequestError::BatchNotAllowed)
+        ));
+
+        let request = r#"{"method":"isPrime","number":13}"#;
+        assert_eq!(
+            parse_request(request, false).unwrap(),
+            Query::Single(Some(13))
+        );
+
+        let request = r#"{"method":"isPrime","number":"13"}"#;
+        assert!(parse_request(request, true).is_err());
+    }
 }
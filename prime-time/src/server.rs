@@ -0,0 +1,120 @@
+use framing::{FramingError, LineReader};
+use telemetry::stats::Metered;
+use tokio::{
+    io::{AsyncWriteExt, BufWriter},
+    net::TcpStream,
+};
+
+use crate::{
+    admission::Pending,
+    prime::is_prime,
+    protocol::{self, Query, MALFORMED_RESPONSE},
+};
+
+// the maximum amount of responses (in bytes) held back before they're written
+const CORK_BUFFER_SIZE: usize = 64 * 1024;
+
+/// The length of the longest request that is handled, unless configured otherwise
+pub const DEFAULT_MAX_REQUEST_SIZE: usize = 1024 * 1024;
+
+/// Answers the requests of a single client until it disconnects or sends a bad request,
+/// the client stops being pending once its first request arrives
+pub async fn serve(
+    client: TcpStream,
+    pending: Pending,
+    lenient: bool,
+    max_request_size: usize,
+) -> std::io::Result<()> {
+    // the connection is pending until its first request arrives
+    let mut pending = Some(pending);
+    let (reader, writer) = tokio::io::split(Metered::new(client));
+    let mut reader = LineReader::new(reader, max_request_size);
+
+    // responses are corked while there are pipelined requests waiting to be handled,
+    // so a burst of requests is answered with a single write
+    let mut writer = BufWriter::with_capacity(CORK_BUFFER_SIZE, writer);
+    loop {
+        // the reader is about to wait for the socket, release the corked responses
+        if !reader.has_buffered_line() {
+            writer.flush().await?;
+        }
+
+        let line = reader.read_line().await;
+        pending.take();
+        let request = match line {
+            Ok(Some(line)) => protocol::parse_request(&line, lenient).map_err(|_| "malformed"),
+            // reached EOF
+            Ok(None) => return Ok(()),
+            Err(FramingError::Io(err)) => return Err(err),
+            Err(FramingError::TooLong(_)) => Err("oversized"),
+            Err(_) => Err("malformed"),
+        };
+
+        match request {
+            Err(reason) => {
+                // received a bad request, return a malformed response and close the socket
+                telemetry::stats::error(reason);
+                writer.write_all(MALFORMED_RESPONSE.as_bytes()).await?;
+                writer.flush().await?;
+                return Ok(());
+            }
+            Ok(query) => {
+                telemetry::stats::request(match query {
+                    Query::Single(_) => "single",
+                    Query::Batch(_) => "batch",
+                });
+                let response = query.answer(is_prime);
+                let response =
+                    serde_json::to_string(&response).expect("failed to serialize response") + "\n";
+
+                writer.write_all(response.as_bytes()).await?;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    use crate::{admission::Admission, protocol::MALFORMED_RESPONSE};
+
+    use super::serve;
+
+    const MAX_REQUEST_SIZE: usize = 64;
+
+    #[tokio::test]
+    async fn close_on_oversized_requests() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (conn, _) = listener.accept().await.unwrap();
+        let admission = Arc::new(Admission::new(None, None));
+        let pending = admission.admit().unwrap();
+        let served = tokio::spawn(serve(conn, pending, false, MAX_REQUEST_SIZE));
+
+        // a request that fits is answered, the one that doesn't gets the malformed response
+        let request = r#"{"method":"isPrime","number":7}"#;
+        let oversized = format!(r#"{{"method":"isPrime","number":{}}}"#, "1".repeat(64));
+        client
+            .write_all(format!("{}\n{}\n", request, oversized).as_bytes())
+            .await
+            .unwrap();
+
+        // the server closes the connection right after the malformed response
+        let mut responses = String::new();
+        client.read_to_string(&mut responses).await.unwrap();
+        let (answer, rest) = responses.split_once('\n').unwrap();
+        assert!(answer.contains(r#""prime":true"#), "{:?}", answer);
+        assert_eq!(rest, MALFORMED_RESPONSE);
+
+        served.await.unwrap().unwrap();
+        assert_eq!(admission.pending(), 0);
+    }
+}
@@ -0,0 +1,100 @@
+//! see: https://protohackers.com/problem/9
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream, ToSocketAddrs,
+    },
+};
+
+use crate::ClientError;
+
+/// A job that was handed out by the server
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Job {
+    pub id: u64,
+    pub queue: String,
+    pub job: Value,
+    pub pri: u64,
+}
+
+/// A session with a job centre server
+///
+/// the jobs a session has taken with [`Client::get`] are aborted once it disconnects
+pub struct Client {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl Client {
+    pub async fn connect(addr: impl ToSocketAddrs) -> Result<Self, ClientError> {
+        let (reader, writer) = TcpStream::connect(addr).await?.into_split();
+        Ok(Self {
+            reader: BufReader::new(reader),
+            writer,
+        })
+    }
+
+    /// Adds a job to the queue, returns the id of the job
+    pub async fn put(&mut self, queue: &str, job: Value, pri: u64) -> Result<u64, ClientError> {
+        let response = self
+            .request(json!({"request": "put", "queue": queue, "job": job, "pri": pri}))
+            .await?;
+
+        response
+            .get("id")
+            .and_then(Value::as_u64)
+            .ok_or_else(|| ClientError::UnexpectedResponse(response.to_string()))
+    }
+
+    /// Takes the job with the highest priority out of the queues,
+    /// waits for one to arrive when `wait` is set, and returns `None` otherwise
+    pub async fn get(&mut self, queues: &[&str], wait: bool) -> Result<Option<Job>, ClientError> {
+        let response = self
+            .request(json!({"request": "get", "queues": queues, "wait": wait}))
+            .await?;
+        if response["status"] == "no-job" {
+            return Ok(None);
+        }
+
+        Job::deserialize(&response)
+            .map(Some)
+            .map_err(|_| ClientError::UnexpectedResponse(response.to_string()))
+    }
+
+    /// Deletes the job wherever it is, returns whether it existed
+    pub async fn delete(&mut self, id: u64) -> Result<bool, ClientError> {
+        let response = self.request(json!({"request": "delete", "id": id})).await?;
+        Ok(response["status"] == "ok")
+    }
+
+    /// Puts a job the session is working on back in its queue, returns whether it was
+    pub async fn abort(&mut self, id: u64) -> Result<bool, ClientError> {
+        let response = self.request(json!({"request": "abort", "id": id})).await?;
+        Ok(response["status"] == "ok")
+    }
+
+    /// Sends a raw request, and returns the response unless it's an error
+    pub async fn request(&mut self, request: Value) -> Result<Value, ClientError> {
+        let mut line = request.to_string();
+        line.push('\n');
+        self.writer.write_all(line.as_bytes()).await?;
+
+        let mut line = String::new();
+        if self.reader.read_line(&mut line).await? == 0 {
+            return Err(ClientError::Closed);
+        }
+        let response: Value = serde_json::from_str(&line)
+            .map_err(|_| ClientError::UnexpectedResponse(line.trim_end().into()))?;
+
+        match response["status"].as_str() {
+            Some("ok") | Some("no-job") => Ok(response),
+            Some("error") => Err(ClientError::Server(
+                response["error"].as_str().unwrap_or_default().into(),
+            )),
+            _ => Err(ClientError::UnexpectedResponse(response.to_string())),
+        }
+    }
+}
@@ -0,0 +1,27 @@
+//! Typed async clients for the servers in this repo
+//!
+//! they're meant for end-to-end tests against a running server, and for scripting load tests
+//! against a deployment. every client speaks its protocol as the spec defines it, independently
+//! of the server's code, except for the line reversal client that runs on top of the shared
+//! [`lrcp`] transport.
+pub mod job_centre;
+pub mod line_reversal;
+pub mod means_to_an_end;
+pub mod speed_daemon;
+pub mod vcs;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ClientError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("the server has closed the connection")]
+    Closed,
+    #[error("the server has answered with an error: {0}")]
+    Server(String),
+    #[error("unexpected response: {0}")]
+    UnexpectedResponse(String),
+    #[error("the request can't be sent: {0}")]
+    InvalidRequest(String),
+    #[error("the response is too large: {0} bytes")]
+    TooLarge(u64),
+}
@@ -0,0 +1,48 @@
+//! see: https://protohackers.com/problem/7
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, ReadHalf, WriteHalf},
+    net::ToSocketAddrs,
+};
+
+use lrcp::LrcpStream;
+
+use crate::ClientError;
+
+/// An LRCP session with a line reversal server
+pub struct Client {
+    reader: BufReader<ReadHalf<LrcpStream>>,
+    writer: WriteHalf<LrcpStream>,
+}
+
+impl Client {
+    pub async fn connect(
+        addr: impl ToSocketAddrs,
+        config: lrcp::Config,
+    ) -> Result<Self, ClientError> {
+        let (reader, writer) = tokio::io::split(lrcp::connect(addr, config).await?);
+        Ok(Self {
+            reader: BufReader::new(reader),
+            writer,
+        })
+    }
+
+    /// Sends a line (without its newline) and returns the line reversed by the server
+    pub async fn reverse(&mut self, line: &str) -> Result<String, ClientError> {
+        self.writer
+            .write_all(format!("{}\n", line).as_bytes())
+            .await?;
+
+        let mut reversed = String::new();
+        self.reader.read_line(&mut reversed).await?;
+        match reversed.strip_suffix('\n') {
+            Some(reversed) => Ok(reversed.into()),
+            None => Err(ClientError::Closed),
+        }
+    }
+
+    /// Closes the session, and waits for the server to close its side
+    pub async fn close(mut self) -> Result<(), ClientError> {
+        self.writer.shutdown().await?;
+        Ok(())
+    }
+}
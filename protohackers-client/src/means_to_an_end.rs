@@ -0,0 +1,75 @@
+//! see: https://protohackers.com/problem/2
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpStream, ToSocketAddrs},
+};
+
+use crate::ClientError;
+
+/// A session with a means-to-an-end server
+///
+/// the prices of a session are private to it, a new connection starts with no prices
+pub struct Client {
+    stream: TcpStream,
+}
+
+impl Client {
+    pub async fn connect(addr: impl ToSocketAddrs) -> Result<Self, ClientError> {
+        Ok(Self {
+            stream: TcpStream::connect(addr).await?,
+        })
+    }
+
+    /// Records the price of the asset at the timestamp, the server doesn't answer inserts
+    pub async fn insert(&mut self, timestamp: i32, price: i32) -> Result<(), ClientError> {
+        self.stream
+            .write_all(&frame(b'I', timestamp, price))
+            .await?;
+        Ok(())
+    }
+
+    /// The mean price between the timestamps (inclusive), 0 when there are no prices in between
+    pub async fn query(&mut self, min_time: i32, max_time: i32) -> Result<i32, ClientError> {
+        self.stream
+            .write_all(&frame(b'Q', min_time, max_time))
+            .await?;
+
+        // a malformed frame is answered with a single "E" before the server hangs up,
+        // which is never a complete answer
+        let mut mean = [0; 4];
+        match self.stream.read_exact(&mut mean).await {
+            Ok(_) => Ok(i32::from_be_bytes(mean)),
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => Err(ClientError::Closed),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+fn frame(ty: u8, first: i32, second: i32) -> [u8; 9] {
+    let mut frame = [ty; 9];
+    frame[1..5].copy_from_slice(&first.to_be_bytes());
+    frame[5..].copy_from_slice(&second.to_be_bytes());
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::frame;
+
+    #[test]
+    fn encode_frames() {
+        // the examples from the spec
+        assert_eq!(
+            frame(b'I', 12345, 101),
+            [0x49, 0x00, 0x00, 0x30, 0x39, 0x00, 0x00, 0x00, 0x65]
+        );
+        assert_eq!(
+            frame(b'Q', 1000, 100000),
+            [0x51, 0x00, 0x00, 0x03, 0xe8, 0x00, 0x01, 0x86, 0xa0]
+        );
+        assert_eq!(
+            frame(b'I', -1, i32::MIN)[1..],
+            [0xff, 0xff, 0xff, 0xff, 0x80, 0, 0, 0]
+        );
+    }
+}
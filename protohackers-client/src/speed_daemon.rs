@@ -0,0 +1,316 @@
+//! see: https://protohackers.com/problem/6
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream, ToSocketAddrs,
+    },
+};
+
+use crate::ClientError;
+
+/// A message a client sends to the server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FromClient {
+    Plate {
+        plate: String,
+        timestamp: u32,
+    },
+    /// The interval is in deciseconds, 0 turns the heartbeats off
+    WantHeartbeat {
+        interval: u32,
+    },
+    IAmCamera {
+        road: u16,
+        mile: u16,
+        limit: u16,
+    },
+    IAmDispatcher {
+        roads: Vec<u16>,
+    },
+    /// Only accepted by a server in multi-camera mode
+    AddCamera {
+        id: u16,
+        road: u16,
+        mile: u16,
+        limit: u16,
+    },
+    /// Only accepted by a server in multi-camera mode
+    CameraPlate {
+        camera: u16,
+        plate: String,
+        timestamp: u32,
+    },
+}
+
+/// A message the server sends to a client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ToClient {
+    Error {
+        msg: String,
+    },
+    /// The speed is in 100x miles per hour
+    Ticket {
+        plate: String,
+        road: u16,
+        mile1: u16,
+        timestamp1: u32,
+        mile2: u16,
+        timestamp2: u32,
+        speed: u16,
+    },
+    Heartbeat,
+}
+
+/// A camera or a dispatcher connected to a speed daemon server
+///
+/// the client only identifies itself once it sends an [`FromClient::IAmCamera`]
+/// or an [`FromClient::IAmDispatcher`]
+pub struct Client {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl Client {
+    pub async fn connect(addr: impl ToSocketAddrs) -> Result<Self, ClientError> {
+        let (reader, writer) = TcpStream::connect(addr).await?.into_split();
+        Ok(Self {
+            reader: BufReader::new(reader),
+            writer,
+        })
+    }
+
+    pub async fn send(&mut self, message: &FromClient) -> Result<(), ClientError> {
+        self.writer.write_all(&encode(message)?).await?;
+        Ok(())
+    }
+
+    pub async fn recv(&mut self) -> Result<ToClient, ClientError> {
+        decode(&mut self.reader).await
+    }
+
+    /// Waits for the next ticket, skipping heartbeats
+    pub async fn ticket(&mut self) -> Result<ToClient, ClientError> {
+        loop {
+            match self.recv().await? {
+                ToClient::Heartbeat => continue,
+                ToClient::Error { msg } => return Err(ClientError::Server(msg)),
+                ticket => return Ok(ticket),
+            }
+        }
+    }
+}
+
+// Fails on a string or a road list that is longer than the protocol can carry,
+// rather than sending a part of it
+fn encode(message: &FromClient) -> Result<Vec<u8>, ClientError> {
+    fn put_len(buf: &mut Vec<u8>, len: usize, what: &str) -> Result<(), ClientError> {
+        let len = u8::try_from(len).map_err(|_| {
+            ClientError::InvalidRequest(format!("{} of length {} is too long", what, len))
+        })?;
+        buf.push(len);
+        Ok(())
+    }
+
+    fn put_str(buf: &mut Vec<u8>, str: &str) -> Result<(), ClientError> {
+        put_len(buf, str.len(), "a string")?;
+        buf.extend_from_slice(str.as_bytes());
+        Ok(())
+    }
+
+    let mut buf = Vec::new();
+    match message {
+        FromClient::Plate { plate, timestamp } => {
+            buf.push(0x20);
+            put_str(&mut buf, plate)?;
+            buf.extend_from_slice(&timestamp.to_be_bytes());
+        }
+        FromClient::WantHeartbeat { interval } => {
+            buf.push(0x40);
+            buf.extend_from_slice(&interval.to_be_bytes());
+        }
+        FromClient::IAmCamera { road, mile, limit } => {
+            buf.push(0x80);
+            for field in [road, mile, limit] {
+                buf.extend_from_slice(&field.to_be_bytes());
+            }
+        }
+        FromClient::IAmDispatcher { roads } => {
+            buf.push(0x81);
+            put_len(&mut buf, roads.len(), "a road list")?;
+            for road in roads {
+                buf.extend_from_slice(&road.to_be_bytes());
+            }
+        }
+        FromClient::AddCamera {
+            id,
+            road,
+            mile,
+            limit,
+        } => {
+            buf.push(0x82);
+            for field in [id, road, mile, limit] {
+                buf.extend_from_slice(&field.to_be_bytes());
+            }
+        }
+        FromClient::CameraPlate {
+            camera,
+            plate,
+            timestamp,
+        } => {
+            buf.push(0x22);
+            buf.extend_from_slice(&camera.to_be_bytes());
+            put_str(&mut buf, plate)?;
+            buf.extend_from_slice(&timestamp.to_be_bytes());
+        }
+    }
+
+    Ok(buf)
+}
+
+async fn decode(reader: &mut (impl AsyncRead + Unpin)) -> Result<ToClient, ClientError> {
+    async fn read_str(reader: &mut (impl AsyncRead + Unpin)) -> std::io::Result<String> {
+        let len = reader.read_u8().await?;
+        let mut str = vec![0; len as usize];
+        reader.read_exact(&mut str).await?;
+        Ok(String::from_utf8_lossy(&str).into())
+    }
+
+    let ty = match reader.read_u8().await {
+        Ok(ty) => ty,
+        Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
+            return Err(ClientError::Closed)
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    let message = match ty {
+        0x10 => ToClient::Error {
+            msg: read_str(reader).await?,
+        },
+        0x21 => ToClient::Ticket {
+            plate: read_str(reader).await?,
+            road: reader.read_u16().await?,
+            mile1: reader.read_u16().await?,
+            timestamp1: reader.read_u32().await?,
+            mile2: reader.read_u16().await?,
+            timestamp2: reader.read_u32().await?,
+            speed: reader.read_u16().await?,
+        },
+        0x41 => ToClient::Heartbeat,
+        ty => {
+            return Err(ClientError::UnexpectedResponse(format!(
+                "message type {:#04x}",
+                ty
+            )))
+        }
+    };
+
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode, FromClient, ToClient};
+    use crate::ClientError;
+
+    #[test]
+    fn encode_messages() {
+        // the examples from the spec
+        assert_eq!(
+            encode(&FromClient::Plate {
+                plate: "UN1X".into(),
+                timestamp: 1000
+            })
+            .unwrap(),
+            [0x20, 0x04, 0x55, 0x4e, 0x31, 0x58, 0x00, 0x00, 0x03, 0xe8]
+        );
+        assert_eq!(
+            encode(&FromClient::WantHeartbeat { interval: 10 }).unwrap(),
+            [0x40, 0x00, 0x00, 0x00, 0x0a]
+        );
+        assert_eq!(
+            encode(&FromClient::IAmCamera {
+                road: 66,
+                mile: 100,
+                limit: 60
+            })
+            .unwrap(),
+            [0x80, 0x00, 0x42, 0x00, 0x64, 0x00, 0x3c]
+        );
+        assert_eq!(
+            encode(&FromClient::IAmDispatcher {
+                roads: vec![66, 368, 5000]
+            })
+            .unwrap(),
+            [0x81, 0x03, 0x00, 0x42, 0x01, 0x70, 0x13, 0x88]
+        );
+        assert_eq!(
+            encode(&FromClient::CameraPlate {
+                camera: 1,
+                plate: "A".into(),
+                timestamp: 2
+            })
+            .unwrap(),
+            [0x22, 0x00, 0x01, 0x01, 0x41, 0x00, 0x00, 0x00, 0x02]
+        );
+    }
+
+    #[test]
+    fn encode_refuses_what_the_protocol_cant_carry() {
+        let plate = "A".repeat(255);
+        assert_eq!(
+            encode(&FromClient::Plate {
+                plate: plate.clone(),
+                timestamp: 0
+            })
+            .unwrap()
+            .len(),
+            1 + 1 + 255 + 4
+        );
+
+        assert!(matches!(
+            encode(&FromClient::Plate {
+                plate: plate + "A",
+                timestamp: 0
+            }),
+            Err(ClientError::InvalidRequest(_))
+        ));
+        assert!(matches!(
+            encode(&FromClient::IAmDispatcher {
+                roads: vec![0; 256]
+            }),
+            Err(ClientError::InvalidRequest(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn decode_messages() {
+        let mut wire: &[u8] = &[
+            0x10, 0x03, 0x62, 0x61, 0x64, // error "bad"
+            0x21, 0x04, 0x55, 0x4e, 0x31, 0x58, 0x00, 0x42, 0x00, 0x64, 0x00, 0x01, 0xe2, 0x40,
+            0x00, 0x6e, 0x00, 0x01, 0xe3, 0xa8, 0x27, 0x10, // the ticket from the spec
+            0x41, // heartbeat
+        ];
+
+        assert_eq!(
+            decode(&mut wire).await.unwrap(),
+            ToClient::Error { msg: "bad".into() }
+        );
+        assert_eq!(
+            decode(&mut wire).await.unwrap(),
+            ToClient::Ticket {
+                plate: "UN1X".into(),
+                road: 66,
+                mile1: 100,
+                timestamp1: 123456,
+                mile2: 110,
+                timestamp2: 123816,
+                speed: 10000,
+            }
+        );
+        assert_eq!(decode(&mut wire).await.unwrap(), ToClient::Heartbeat);
+        assert!(matches!(decode(&mut wire).await, Err(ClientError::Closed)));
+        assert!(decode(&mut &[0x99][..]).await.is_err());
+    }
+}
@@ -0,0 +1,230 @@
+//! see: https://protohackers.com/problem/10
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpStream, ToSocketAddrs},
+};
+
+use vcs_protocol::{parse_child, parse_revision, Encoding, Request, Status, READY};
+
+use crate::ClientError;
+
+pub use vcs_protocol::{Glob, ListResult, Metadata};
+
+/// The largest file [`Client::get`] accepts, unless configured otherwise
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 16 * 1024 * 1024;
+
+/// A connection to a storage server, that sends one request at a time
+///
+/// after an error other than [`ClientError::Server`] the connection may be left
+/// in the middle of a response, so it shouldn't be used any further
+pub struct Client {
+    stream: BufReader<TcpStream>,
+    max_file_size: u64,
+}
+
+impl Client {
+    /// Connects to a server, and waits for it to be ready
+    pub async fn connect(addr: impl ToSocketAddrs) -> Result<Self, ClientError> {
+        let mut client = Self {
+            stream: BufReader::new(TcpStream::connect(addr).await?),
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+        };
+        client.ready().await?;
+
+        Ok(client)
+    }
+
+    /// Changes the largest file [`Client::get`] accepts,
+    /// a larger one is an error rather than being read into memory
+    pub fn with_max_file_size(self, max_file_size: u64) -> Self {
+        Self {
+            max_file_size,
+            ..self
+        }
+    }
+
+    /// Stores a new revision of a file, returns its revision number
+    pub async fn put(&mut self, filename: &str, content: &[u8]) -> Result<u64, ClientError> {
+        self.send(Request::Put {
+            filename: filename.into(),
+            byte_count: content.len() as u64,
+            encoding: Encoding::Plain,
+        })
+        .await?;
+        self.stream.write_all(content).await?;
+
+        let status = self.status().await?;
+        let revision = status
+            .strip_prefix('r')
+            .and_then(|revision| revision.parse().ok())
+            .ok_or(ClientError::UnexpectedResponse(status))?;
+        self.ready().await?;
+
+        Ok(revision)
+    }
+
+    /// Fetches the content of a file, the latest revision when no revision is given
+    pub async fn get(
+        &mut self,
+        filename: &str,
+        revision: Option<u64>,
+    ) -> Result<Vec<u8>, ClientError> {
+        self.send(Request::Get {
+            filename: filename.into(),
+            revision,
+            encoding: Encoding::Plain,
+        })
+        .await?;
+
+        let status = self.status().await?;
+        let size: u64 = status
+            .parse()
+            .map_err(|_| ClientError::UnexpectedResponse(status))?;
+        if size > self.max_file_size {
+            return Err(ClientError::TooLarge(size));
+        }
+
+        let mut content = Vec::with_capacity(size as usize);
+        (&mut self.stream)
+            .take(size)
+            .read_to_end(&mut content)
+            .await?;
+        if content.len() as u64 != size {
+            return Err(ClientError::Closed);
+        }
+        self.ready().await?;
+
+        Ok(content)
+    }
+
+    /// Lists the children of a dir
+    pub async fn list(&mut self, dir: &str) -> Result<Vec<ListResult>, ClientError> {
+        self.list_matching(dir, None, false).await
+    }
+
+    /// Lists the children of a dir whose names match the glob pattern,
+    /// or every file under the dir (by its relative path) when it's recursive
+    pub async fn list_matching(
+        &mut self,
+        dir: &str,
+        pattern: Option<Glob>,
+        recursive: bool,
+    ) -> Result<Vec<ListResult>, ClientError> {
+        self.send(Request::List {
+            path: dir.into(),
+            pattern,
+            recursive,
+        })
+        .await?;
+
+        let status = self.status().await?;
+        let count = status
+            .parse()
+            .map_err(|_| ClientError::UnexpectedResponse(status))?;
+
+        let mut children = Vec::new();
+        for _ in 0..count {
+            let line = self.read_line().await?;
+            let child = parse_child(&line);
+            children.push(child.ok_or(ClientError::UnexpectedResponse(line))?);
+        }
+        self.ready().await?;
+
+        Ok(children)
+    }
+
+    /// Removes a single revision of a file, or the whole file when no revision is given
+    pub async fn del(&mut self, filename: &str, revision: Option<u64>) -> Result<(), ClientError> {
+        self.send(Request::Del {
+            filename: filename.into(),
+            revision,
+        })
+        .await?;
+
+        let status = self.status().await?;
+        if !status.is_empty() {
+            return Err(ClientError::UnexpectedResponse(status));
+        }
+        self.ready().await
+    }
+
+    /// Fetches the metadata of every revision of a file, by revision number
+    pub async fn stat(&mut self, filename: &str) -> Result<Vec<(u64, Metadata)>, ClientError> {
+        self.send(Request::Stat {
+            filename: filename.into(),
+        })
+        .await?;
+
+        let status = self.status().await?;
+        let count = status
+            .parse()
+            .map_err(|_| ClientError::UnexpectedResponse(status))?;
+
+        let mut revisions = Vec::new();
+        for _ in 0..count {
+            let line = self.read_line().await?;
+            let revision = parse_revision(&line);
+            revisions.push(revision.ok_or(ClientError::UnexpectedResponse(line))?);
+        }
+        self.ready().await?;
+
+        Ok(revisions)
+    }
+
+    /// Returns the usage line of the server
+    pub async fn help(&mut self) -> Result<String, ClientError> {
+        self.send(Request::Help).await?;
+
+        let usage = self.status().await?;
+        self.ready().await?;
+
+        Ok(usage)
+    }
+
+    async fn send(&mut self, request: Request) -> Result<(), ClientError> {
+        // a newline would end the request early, and start another one
+        let request = request.to_string();
+        if request.contains('\n') {
+            return Err(ClientError::InvalidRequest(request));
+        }
+
+        self.stream
+            .write_all(format!("{}\n", request).as_bytes())
+            .await?;
+
+        Ok(())
+    }
+
+    // Reads the status line of a response, and returns whatever follows its OK
+    async fn status(&mut self) -> Result<String, ClientError> {
+        let line = self.read_line().await?;
+        match Status::parse(&line) {
+            Some(Status::Ok(rest)) => Ok(rest),
+            Some(Status::Err(reason)) => {
+                // the server may close the connection right after a refusal,
+                // which doesn't change the outcome of the request
+                let _ = self.ready().await;
+                Err(ClientError::Server(reason))
+            }
+            None => Err(ClientError::UnexpectedResponse(line)),
+        }
+    }
+
+    // every response ends with a READY
+    async fn ready(&mut self) -> Result<(), ClientError> {
+        let line = self.read_line().await?;
+        match line == READY.trim_end_matches('\n') {
+            true => Ok(()),
+            false => Err(ClientError::UnexpectedResponse(line)),
+        }
+    }
+
+    async fn read_line(&mut self) -> Result<String, ClientError> {
+        let mut line = String::new();
+        if self.stream.read_line(&mut line).await? == 0 {
+            return Err(ClientError::Closed);
+        }
+
+        Ok(line.trim_end_matches('\n').into())
+    }
+}
@@ -0,0 +1,191 @@
+//! End-to-end tests of the clients against the servers of this repo,
+//! every test starts its own instance of the server in-process, on an ephemeral port
+use std::{future::Future, net::SocketAddr, sync::Arc, time::Duration};
+
+use protohackers_client::{
+    job_centre, line_reversal, means_to_an_end,
+    speed_daemon::{self, FromClient, ToClient},
+    vcs, ClientError,
+};
+use serde_json::json;
+use tokio::net::{TcpListener, TcpStream};
+
+// Binds an ephemeral port and hands every connection it accepts to the handler
+async fn serve<H, F>(handler: H) -> SocketAddr
+where
+    H: Fn(TcpStream) -> F + Send + 'static,
+    F: Future + Send + 'static,
+    F::Output: Send,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((conn, _)) = listener.accept().await {
+            tokio::spawn(handler(conn));
+        }
+    });
+
+    addr
+}
+
+#[tokio::test]
+async fn means_to_an_end_mean_price() {
+    use ::means_to_an_end::{admin::Sessions, config::Config, server};
+
+    let sessions = Arc::new(Sessions::default());
+    let config = Arc::new(Config::default());
+    let addr = serve(move |conn| {
+        let entry = conn.peer_addr().map(|addr| sessions.register(addr));
+        let config = config.clone();
+        async move { server::handle_connection(conn, entry?, None, config).await }
+    })
+    .await;
+
+    let mut client = means_to_an_end::Client::connect(addr).await.unwrap();
+    for (timestamp, price) in [(12345, 101), (12346, 102), (12347, 100), (40960, 5)] {
+        client.insert(timestamp, price).await.unwrap();
+    }
+    assert_eq!(client.query(12288, 16384).await.unwrap(), 101);
+    assert_eq!(client.query(16384, 12288).await.unwrap(), 0);
+
+    // the prices are private to the session
+    let mut other = means_to_an_end::Client::connect(addr).await.unwrap();
+    assert_eq!(other.query(i32::MIN, i32::MAX).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn speed_daemon_ticket() {
+    use ::speed_daemon::{client, SharedSystems};
+
+    let systems = SharedSystems::in_memory().unwrap();
+    let addr =
+        serve(move |conn| client::handle(conn, systems.clone(), false, client::Protocol::Binary))
+            .await;
+
+    let mut dispatcher = speed_daemon::Client::connect(addr).await.unwrap();
+    dispatcher
+        .send(&FromClient::IAmDispatcher { roads: vec![123] })
+        .await
+        .unwrap();
+
+    for (mile, timestamp) in [(8, 0), (9, 45)] {
+        let mut camera = speed_daemon::Client::connect(addr).await.unwrap();
+        camera
+            .send(&FromClient::IAmCamera {
+                road: 123,
+                mile,
+                limit: 60,
+            })
+            .await
+            .unwrap();
+        camera
+            .send(&FromClient::Plate {
+                plate: "UN1X".into(),
+                timestamp,
+            })
+            .await
+            .unwrap();
+        // the camera must stay connected until its plate was handled
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    let ticket = tokio::time::timeout(Duration::from_secs(5), dispatcher.ticket())
+        .await
+        .expect("no ticket was dispatched")
+        .unwrap();
+    assert_eq!(
+        ticket,
+        ToClient::Ticket {
+            plate: "UN1X".into(),
+            road: 123,
+            mile1: 8,
+            timestamp1: 0,
+            mile2: 9,
+            timestamp2: 45,
+            speed: 8000,
+        }
+    );
+}
+
+#[tokio::test]
+async fn job_centre_jobs() {
+    use ::job_centre::{
+        client::Client,
+        jobs::{Manager, TieBreak},
+        server,
+    };
+
+    let job_manager = Manager::new(TieBreak::default()).start();
+    let addr =
+        serve(move |conn| server::handle_request(Client::new(job_manager.clone()), conn)).await;
+
+    let queue = "e2e";
+    let mut client = job_centre::Client::connect(addr).await.unwrap();
+
+    let low = client.put(queue, json!({"n": 1}), 1).await.unwrap();
+    let high = client.put(queue, json!({"n": 2}), 2).await.unwrap();
+
+    let job = client.get(&[queue], false).await.unwrap().unwrap();
+    assert_eq!((job.id, job.job), (high, json!({"n": 2})));
+    assert!(client.abort(high).await.unwrap());
+    assert!(client.delete(high).await.unwrap());
+    assert!(!client.delete(high).await.unwrap());
+
+    let job = client.get(&[queue], false).await.unwrap().unwrap();
+    assert_eq!(job.id, low);
+    assert!(client.delete(low).await.unwrap());
+    assert_eq!(client.get(&[queue], false).await.unwrap(), None);
+
+    assert!(matches!(
+        client.request(json!({"request": "nope"})).await,
+        Err(ClientError::Server(_))
+    ));
+}
+
+#[tokio::test]
+async fn vcs_revisions() {
+    use voracious_code_storage::{
+        admission::Admission, server, storage::TempFileSystem, uploads::Uploads, SharedAdmission,
+        SharedFileSystem, SharedUploads,
+    };
+
+    let fs: SharedFileSystem = Box::leak(Box::new(TempFileSystem::default()));
+    let admission: SharedAdmission = Box::leak(Box::new(Admission::new(None)));
+    let uploads: SharedUploads = Box::leak(Box::new(Uploads::default()));
+    let addr =
+        serve(move |conn| server::handle_connection(conn, fs, admission, uploads, false)).await;
+
+    let name = "/e2e/file.txt";
+    let mut client = vcs::Client::connect(addr).await.unwrap();
+
+    let first = client.put(name, b"one\n").await.unwrap();
+    let second = client.put(name, b"two\n").await.unwrap();
+    assert_eq!(second, first + 1);
+    assert_eq!(client.get(name, Some(first)).await.unwrap(), b"one\n");
+    assert_eq!(client.get(name, None).await.unwrap(), b"two\n");
+}
+
+#[tokio::test]
+async fn line_reversal_lines() {
+    let mut listener = lrcp::Listener::bind("127.0.0.1:0", lrcp::Config::default())
+        .await
+        .unwrap();
+    let addr = listener.local_addr();
+    tokio::spawn(async move {
+        while let Ok(conn) = listener.accept().await {
+            tokio::spawn(::line_reversal::handle_connection(conn));
+        }
+    });
+
+    let mut client = line_reversal::Client::connect(addr, lrcp::Config::default())
+        .await
+        .unwrap();
+
+    assert_eq!(client.reverse("hello").await.unwrap(), "olleh");
+    // a line longer than a single LRCP message
+    let line = "ab/\\".repeat(500);
+    let reversed: String = line.chars().rev().collect();
+    assert_eq!(client.reverse(&line).await.unwrap(), reversed);
+
+    client.close().await.unwrap();
+}
@@ -0,0 +1,280 @@
+use std::{
+    net::SocketAddr,
+    time::{Duration, SystemTime},
+};
+
+use protohackers_client::{
+    vcs::{Client, Glob, ListResult},
+    ClientError,
+};
+use tokio::net::TcpListener;
+use voracious_code_storage::{
+    admission::Admission,
+    server::handle_connection,
+    storage::{DiskStorage, TempFileSystem},
+    uploads::Uploads,
+    SharedFileSystem,
+};
+
+// runs a server with a filesystem of its own, returns its address
+async fn start_server(uploads: Uploads) -> SocketAddr {
+    serve(Box::leak(Box::new(TempFileSystem::default())), uploads).await
+}
+
+async fn serve(fs: SharedFileSystem, uploads: Uploads) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let admission = Box::leak(Box::new(Admission::new(None)));
+    let uploads = Box::leak(Box::new(uploads));
+    tokio::spawn(async move {
+        loop {
+            let (conn, _) = listener.accept().await.unwrap();
+            tokio::spawn(handle_connection(conn, fs, admission, uploads, false));
+        }
+    });
+
+    addr
+}
+
+#[tokio::test]
+async fn store_and_fetch_revisions() {
+    let addr = start_server(Uploads::default()).await;
+    let mut client = Client::connect(addr).await.unwrap();
+
+    assert_eq!(client.put("/notes.txt", b"first\n").await.unwrap(), 1);
+    assert_eq!(client.put("/notes.txt", b"second\n").await.unwrap(), 2);
+    // the same content doesn't make a new revision
+    assert_eq!(client.put("/notes.txt", b"first\n").await.unwrap(), 1);
+
+    assert_eq!(client.get("/notes.txt", None).await.unwrap(), b"second\n");
+    assert_eq!(client.get("/notes.txt", Some(1)).await.unwrap(), b"first\n");
+    assert_eq!(client.put("/empty.txt", b"").await.unwrap(), 1);
+    assert!(client.get("/empty.txt", None).await.unwrap().is_empty());
+
+    // files are shared between the connections
+    let mut other = Client::connect(addr).await.unwrap();
+    assert_eq!(other.get("/notes.txt", Some(2)).await.unwrap(), b"second\n");
+}
+
+#[tokio::test]
+async fn list_and_delete() {
+    let addr = start_server(Uploads::default()).await;
+    let mut client = Client::connect(addr).await.unwrap();
+
+    client.put("/src/main.rs", b"fn main() {}\n").await.unwrap();
+    client.put("/src/lib.rs", b"pub mod a;\n").await.unwrap();
+    client.put("/src/lib.rs", b"pub mod b;\n").await.unwrap();
+    client.put("/README.md", b"# hi\n").await.unwrap();
+
+    assert_eq!(
+        client.list("/").await.unwrap(),
+        [
+            ListResult::File {
+                name: "README.md".into(),
+                last_revision: 1
+            },
+            ListResult::Dir("src".into()),
+        ]
+    );
+    assert_eq!(
+        client.list("/src").await.unwrap(),
+        [
+            ListResult::File {
+                name: "lib.rs".into(),
+                last_revision: 2
+            },
+            ListResult::File {
+                name: "main.rs".into(),
+                last_revision: 1
+            },
+        ]
+    );
+
+    client.del("/src/lib.rs", Some(2)).await.unwrap();
+    assert_eq!(
+        client.get("/src/lib.rs", None).await.unwrap(),
+        b"pub mod a;\n"
+    );
+
+    client.del("/src/lib.rs", None).await.unwrap();
+    client.del("/src/main.rs", None).await.unwrap();
+    assert_eq!(
+        client.list("/").await.unwrap(),
+        [ListResult::File {
+            name: "README.md".into(),
+            last_revision: 1
+        }]
+    );
+
+    assert!(client.help().await.unwrap().contains("DEL"));
+}
+
+#[tokio::test]
+async fn list_recursively() {
+    let addr = start_server(Uploads::default()).await;
+    let mut client = Client::connect(addr).await.unwrap();
+
+    client.put("/src/main.rs", b"fn main() {}\n").await.unwrap();
+    client
+        .put("/src/bin/cli.rs", b"fn main() {}\n")
+        .await
+        .unwrap();
+    client.put("/src/bin/README.md", b"# cli\n").await.unwrap();
+    client.put("/src/lib.md", b"# lib\n").await.unwrap();
+
+    let names = |children: Vec<ListResult>| -> Vec<String> {
+        children
+            .into_iter()
+            .map(|child| child.name().to_string())
+            .collect()
+    };
+
+    let rust: Option<Glob> = Some("*.rs".parse().unwrap());
+    assert_eq!(
+        names(
+            client
+                .list_matching("/src/", rust.clone(), true)
+                .await
+                .unwrap()
+        ),
+        ["bin/cli.rs", "main.rs"]
+    );
+    assert_eq!(
+        names(client.list_matching("/", None, true).await.unwrap()),
+        [
+            "src/bin/README.md",
+            "src/bin/cli.rs",
+            "src/lib.md",
+            "src/main.rs"
+        ]
+    );
+    // without -r, the pattern applies to the direct children only
+    assert_eq!(
+        names(client.list_matching("/src/", rust, false).await.unwrap()),
+        ["main.rs"]
+    );
+    assert_eq!(
+        names(
+            client
+                .list_matching("/src/", Some("b?n".parse().unwrap()), false)
+                .await
+                .unwrap()
+        ),
+        ["bin"]
+    );
+}
+
+#[tokio::test]
+async fn stat_revisions() {
+    let addr = start_server(Uploads::default()).await;
+    let mut client = Client::connect(addr).await.unwrap();
+
+    let before = SystemTime::now() - Duration::from_secs(1);
+    client.put("/notes.txt", b"first\n").await.unwrap();
+    client.put("/notes.txt", b"the second\n").await.unwrap();
+
+    let stat = client.stat("/notes.txt").await.unwrap();
+    let sizes: Vec<_> = stat
+        .iter()
+        .map(|(revision, meta)| (*revision, meta.size))
+        .collect();
+    assert_eq!(sizes, [(1, 6), (2, 11)]);
+    // the creation time is sent in whole seconds
+    assert!(stat.iter().all(|(_, meta)| meta.created >= before));
+
+    assert_eq!(refusal(client.stat("/missing.txt").await), "no such file");
+}
+
+fn refusal<T: std::fmt::Debug>(result: Result<T, ClientError>) -> String {
+    match result {
+        Err(ClientError::Server(reason)) => reason,
+        other => panic!("expected a refusal, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn report_refusals() {
+    let addr = start_server(Uploads::new(Some(16), 1024, None)).await;
+    let mut client = Client::connect(addr).await.unwrap();
+
+    assert_eq!(
+        refusal(client.get("/missing.txt", None).await),
+        "no such file"
+    );
+    assert_eq!(
+        refusal(client.put("/bin", b"\x00\x01").await),
+        "text files only"
+    );
+    client.put("/a.txt", b"a\n").await.unwrap();
+    assert_eq!(
+        refusal(client.get("/a.txt", Some(7)).await),
+        "no such revision"
+    );
+    assert_eq!(
+        refusal(client.del("/a.txt", Some(7)).await),
+        "no such revision"
+    );
+
+    // the connection survives the refusals
+    assert_eq!(client.get("/a.txt", None).await.unwrap(), b"a\n");
+
+    // an oversized file is refused before its body is read, which ends the connection
+    assert_eq!(
+        refusal(client.put("/big.txt", &[b'x'; 17]).await),
+        "file too large"
+    );
+    assert!(client.help().await.is_err());
+}
+
+#[tokio::test]
+async fn limit_fetched_file_size() {
+    let addr = start_server(Uploads::default()).await;
+    let mut client = Client::connect(addr).await.unwrap().with_max_file_size(4);
+
+    client.put("/small.txt", b"abc\n").await.unwrap();
+    client.put("/large.txt", b"abcd\n").await.unwrap();
+    assert_eq!(client.get("/small.txt", None).await.unwrap(), b"abc\n");
+    // the size is checked before anything is read into memory
+    assert!(matches!(
+        client.get("/large.txt", None).await,
+        Err(ClientError::TooLarge(5))
+    ));
+}
+
+#[tokio::test]
+async fn persist_files_across_restarts() {
+    let dir = std::env::temp_dir().join(format!(
+        "voracious-code-storage-restart-{}",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let storage = DiskStorage::open(dir.clone()).await.unwrap();
+    let addr = serve(Box::leak(Box::new(storage)), Uploads::default()).await;
+    let mut client = Client::connect(addr).await.unwrap();
+    client.put("/dir/kept.txt", b"kept\n").await.unwrap();
+    client.put("/dir/kept.txt", b"kept again\n").await.unwrap();
+    client.put("/gone.txt", b"gone\n").await.unwrap();
+    client.del("/gone.txt", None).await.unwrap();
+
+    // a new server over the same dir
+    let storage = DiskStorage::open(dir.clone()).await.unwrap();
+    let addr = serve(Box::leak(Box::new(storage)), Uploads::default()).await;
+    let mut client = Client::connect(addr).await.unwrap();
+    assert_eq!(
+        client.get("/dir/kept.txt", Some(1)).await.unwrap(),
+        b"kept\n"
+    );
+    assert_eq!(
+        client.get("/dir/kept.txt", None).await.unwrap(),
+        b"kept again\n"
+    );
+    assert_eq!(
+        client.list("/").await.unwrap(),
+        [ListResult::Dir("dir".into())]
+    );
+    assert_eq!(refusal(client.get("/gone.txt", None).await), "no such file");
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,268 @@
+//! Support for the HAProxy PROXY protocol (v1 and v2),
+//! used by the servers that run behind a load balancer to learn the real address of their clients
+//!
+//! see: https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    net::TcpStream,
+};
+
+// both versions can be told apart by their first 6 bytes
+const PREFIX_LEN: usize = 6;
+const V1_PREFIX: &[u8; PREFIX_LEN] = b"PROXY ";
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+
+// the longest possible v1 header, including the CRLF
+const V1_MAX_LEN: usize = 107;
+
+mod v2 {
+    pub const VERSION: u8 = 0x2;
+
+    pub const COMMAND_LOCAL: u8 = 0x0;
+    pub const COMMAND_PROXY: u8 = 0x1;
+
+    pub const FAMILY_INET: u8 = 0x1;
+    pub const FAMILY_INET6: u8 = 0x2;
+
+    // the length of the addresses block of every family
+    pub const INET_LEN: usize = 12;
+    pub const INET6_LEN: usize = 36;
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ProxyError {
+    #[error("{0}")]
+    Io(#[from] tokio::io::Error),
+
+    #[error("The connection doesn't start with a PROXY header")]
+    MissingHeader,
+
+    #[error("Unsupported PROXY protocol version: {0}")]
+    UnsupportedVersion(u8),
+
+    #[error("Malformed PROXY header: {0}")]
+    Malformed(&'static str),
+}
+
+/// The addresses of the original connection, as reported by the proxy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Addresses {
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+}
+
+/// Reads the PROXY header from the start of the connection,
+/// and returns the address of the client the proxy connected on behalf of
+///
+/// falls back to the address of the proxy itself when the header doesn't carry one
+/// (e.g. a health check)
+pub async fn read_client_addr(stream: &mut TcpStream) -> Result<SocketAddr, ProxyError> {
+    match read_header(stream).await? {
+        Some(addresses) => Ok(addresses.source),
+        None => Ok(stream.peer_addr()?),
+    }
+}
+
+/// Reads a v1 or v2 PROXY header, without reading anything past it
+///
+/// returns None when the header doesn't carry any addresses
+pub async fn read_header<R>(reader: &mut R) -> Result<Option<Addresses>, ProxyError>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut prefix = [0u8; PREFIX_LEN];
+    reader.read_exact(&mut prefix).await?;
+
+    if &prefix == V1_PREFIX {
+        read_v1(reader).await
+    } else if prefix == V2_SIGNATURE[..PREFIX_LEN] {
+        read_v2(reader).await
+    } else {
+        Err(ProxyError::MissingHeader)
+    }
+}
+
+// e.g. "PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\n", the prefix was already read
+async fn read_v1<R>(reader: &mut R) -> Result<Option<Addresses>, ProxyError>
+where
+    R: AsyncRead + Unpin,
+{
+    // the header has no length field, so it's read a byte at a time to not read past it
+    let mut line = Vec::new();
+    while !line.ends_with(b"\r\n") {
+        if PREFIX_LEN + line.len() >= V1_MAX_LEN {
+            return Err(ProxyError::Malformed("the header is too long"));
+        }
+        line.push(reader.read_u8().await?);
+    }
+    line.truncate(line.len() - 2);
+
+    let line = std::str::from_utf8(&line).map_err(|_| ProxyError::Malformed("not ascii"))?;
+    let mut parts = line.split(' ');
+    match parts.next() {
+        Some("TCP4") | Some("TCP6") => {}
+        // the proxy doesn't know the addresses, the rest of the line should be ignored
+        Some("UNKNOWN") => return Ok(None),
+        _ => return Err(ProxyError::Malformed("unknown protocol")),
+    }
+
+    let mut next = || parts.next().ok_or(ProxyError::Malformed("missing field"));
+    let source_ip: IpAddr = next()?
+        .parse()
+        .map_err(|_| ProxyError::Malformed("bad source address"))?;
+    let destination_ip: IpAddr = next()?
+        .parse()
+        .map_err(|_| ProxyError::Malformed("bad destination address"))?;
+    let source_port: u16 = next()?
+        .parse()
+        .map_err(|_| ProxyError::Malformed("bad source port"))?;
+    let destination_port: u16 = next()?
+        .parse()
+        .map_err(|_| ProxyError::Malformed("bad destination port"))?;
+
+    Ok(Some(Addresses {
+        source: SocketAddr::new(source_ip, source_port),
+        destination: SocketAddr::new(destination_ip, destination_port),
+    }))
+}
+
+// the first 6 bytes of the signature were already read
+async fn read_v2<R>(reader: &mut R) -> Result<Option<Addresses>, ProxyError>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut header = [0u8; 10];
+    reader.read_exact(&mut header).await?;
+    if header[..6] != V2_SIGNATURE[PREFIX_LEN..] {
+        return Err(ProxyError::MissingHeader);
+    }
+
+    let version = header[6] >> 4;
+    let command = header[6] & 0x0f;
+    let family = header[7] >> 4;
+    let len = u16::from_be_bytes([header[8], header[9]]) as usize;
+    if version != v2::VERSION {
+        return Err(ProxyError::UnsupportedVersion(version));
+    }
+
+    // the addresses are followed by optional TLVs, which are skipped along with the addresses
+    let mut payload = vec![0u8; len];
+    reader.read_exact(&mut payload).await?;
+
+    match command {
+        // a connection made by the proxy on its own behalf
+        v2::COMMAND_LOCAL => return Ok(None),
+        v2::COMMAND_PROXY => {}
+        _ => return Err(ProxyError::Malformed("unknown command")),
+    }
+
+    let addresses = match family {
+        v2::FAMILY_INET if len >= v2::INET_LEN => {
+            let ip = |at: usize| {
+                let octets: [u8; 4] = payload[at..at + 4].try_into().unwrap();
+                IpAddr::V4(Ipv4Addr::from(octets))
+            };
+            let port = |at: usize| u16::from_be_bytes([payload[at], payload[at + 1]]);
+
+            Addresses {
+                source: SocketAddr::new(ip(0), port(8)),
+                destination: SocketAddr::new(ip(4), port(10)),
+            }
+        }
+        v2::FAMILY_INET6 if len >= v2::INET6_LEN => {
+            let ip = |at: usize| {
+                let octets: [u8; 16] = payload[at..at + 16].try_into().unwrap();
+                IpAddr::V6(Ipv6Addr::from(octets))
+            };
+            let port = |at: usize| u16::from_be_bytes([payload[at], payload[at + 1]]);
+
+            Addresses {
+                source: SocketAddr::new(ip(0), port(32)),
+                destination: SocketAddr::new(ip(16), port(34)),
+            }
+        }
+        v2::FAMILY_INET | v2::FAMILY_INET6 => {
+            return Err(ProxyError::Malformed("the addresses are truncated"))
+        }
+        // unix sockets and unspecified families carry nothing a server could use
+        _ => return Ok(None),
+    };
+
+    Ok(Some(addresses))
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncReadExt;
+
+    use super::{read_header, Addresses, ProxyError};
+
+    #[tokio::test]
+    async fn read_v1_headers() {
+        let mut stream = &b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 3600\r\nhello\n"[..];
+        assert_eq!(
+            read_header(&mut stream).await.unwrap(),
+            Some(Addresses {
+                source: "192.168.0.1:56324".parse().unwrap(),
+                destination: "192.168.0.11:3600".parse().unwrap(),
+            })
+        );
+
+        // the data that follows the header is left untouched
+        let mut rest = String::new();
+        stream.read_to_string(&mut rest).await.unwrap();
+        assert_eq!(rest, "hello\n");
+
+        let mut stream = &b"PROXY TCP6 ::1 ::2 1 2\r\n"[..];
+        let addresses = read_header(&mut stream).await.unwrap().unwrap();
+        assert_eq!(addresses.source, "[::1]:1".parse().unwrap());
+
+        let mut stream = &b"PROXY UNKNOWN ff:ff::1 ::2 1 2\r\n"[..];
+        assert_eq!(read_header(&mut stream).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn read_v2_headers() {
+        let mut header = b"\r\n\r\n\0\r\nQUIT\n\x21\x11\x00\x13".to_vec();
+        header.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2, 0x1f, 0x90, 0x0e, 0x10]);
+        // a TLV that should be skipped
+        header.extend_from_slice(&[0x04, 0x00, 0x04, 1, 2, 3, 4]);
+        header.extend_from_slice(b"hello\n");
+
+        let mut stream = &header[..];
+        assert_eq!(
+            read_header(&mut stream).await.unwrap(),
+            Some(Addresses {
+                source: "10.0.0.1:8080".parse().unwrap(),
+                destination: "10.0.0.2:3600".parse().unwrap(),
+            })
+        );
+        assert_eq!(stream, b"hello\n");
+
+        // a health check made by the proxy itself
+        let mut stream = &b"\r\n\r\n\0\r\nQUIT\n\x20\x00\x00\x00"[..];
+        assert_eq!(read_header(&mut stream).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn reject_bad_headers() {
+        assert!(matches!(
+            read_header(&mut &b"hello there\n"[..]).await,
+            Err(ProxyError::MissingHeader)
+        ));
+        assert!(matches!(
+            read_header(&mut &b"PROXY UDP4 1.1.1.1 2.2.2.2 1 2\r\n"[..]).await,
+            Err(ProxyError::Malformed(_))
+        ));
+        assert!(matches!(
+            read_header(&mut &[b"PROXY ".as_slice(), &[b'1'; 200]].concat()[..]).await,
+            Err(ProxyError::Malformed(_))
+        ));
+        assert!(matches!(
+            read_header(&mut &b"\r\n\r\n\0\r\nQUIT\n\x11\x11\x00\x00"[..]).await,
+            Err(ProxyError::UnsupportedVersion(1))
+        ));
+    }
+}
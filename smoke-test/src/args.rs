@@ -0,0 +1,120 @@
+use std::{num::NonZeroU64, time::Duration};
+
+pub const USAGE: &str = "usage: smoke-test [--listen <addr>] [--mode echo|discard|chargen] [--latency <ms>] [--bandwidth <bytes/s>]";
+
+const DEFAULT_LISTEN: &str = "0.0.0.0:3600";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mode {
+    /// Sends back everything it receives
+    #[default]
+    Echo,
+    /// Reads everything it receives and never sends anything back
+    Discard,
+    /// Sends an endless stream of characters, ignoring whatever it receives
+    Chargen,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    pub listen: String,
+    pub mode: Mode,
+    // how long echoed data is held before it's sent back
+    pub latency: Duration,
+    // when set, no connection is sent more than this many bytes per second
+    pub bandwidth: Option<NonZeroU64>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            listen: DEFAULT_LISTEN.into(),
+            mode: Mode::default(),
+            latency: Duration::ZERO,
+            bandwidth: None,
+        }
+    }
+}
+
+impl Options {
+    /// Parses the command line arguments, without the program name
+    pub fn parse(args: impl IntoIterator<Item = String>) -> anyhow::Result<Self> {
+        let mut options = Self::default();
+        let mut args = args.into_iter();
+        while let Some(flag) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("{} is missing its value\n{}", flag, USAGE))?;
+            match flag.as_str() {
+                "--listen" => options.listen = value,
+                "--mode" => {
+                    options.mode = match value.as_str() {
+                        "echo" => Mode::Echo,
+                        "discard" => Mode::Discard,
+                        "chargen" => Mode::Chargen,
+                        _ => anyhow::bail!("unknown mode: {}\n{}", value, USAGE),
+                    }
+                }
+                "--latency" => {
+                    options.latency = Duration::from_millis(
+                        value
+                            .parse()
+                            .map_err(|err| anyhow::anyhow!("bad value for --latency: {}", err))?,
+                    )
+                }
+                "--bandwidth" => {
+                    options.bandwidth = Some(
+                        value
+                            .parse()
+                            .map_err(|err| anyhow::anyhow!("bad value for --bandwidth: {}", err))?,
+                    )
+                }
+                _ => anyhow::bail!("unknown flag: {}\n{}", flag, USAGE),
+            }
+        }
+
+        // only echoed data can be held back
+        if !options.latency.is_zero() && options.mode != Mode::Echo {
+            anyhow::bail!("--latency only applies to the echo mode");
+        }
+
+        Ok(options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Mode, Options};
+
+    fn parse(args: &[&str]) -> anyhow::Result<Options> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parse_flags() {
+        assert_eq!(parse(&[]).unwrap(), Options::default());
+
+        let options = parse(&[
+            "--listen",
+            "127.0.0.1:4000",
+            "--latency",
+            "250",
+            "--bandwidth",
+            "1024",
+        ])
+        .unwrap();
+        assert_eq!(options.listen, "127.0.0.1:4000");
+        assert_eq!(options.mode, Mode::Echo);
+        assert_eq!(options.latency, Duration::from_millis(250));
+        assert_eq!(options.bandwidth.unwrap().get(), 1024);
+
+        assert_eq!(parse(&["--mode", "chargen"]).unwrap().mode, Mode::Chargen);
+        assert!(parse(&["--mode", "chargen", "--latency", "10"]).is_err());
+        assert!(parse(&["--mode", "bogus"]).is_err());
+        assert!(parse(&["--bandwidth", "0"]).is_err());
+        assert!(parse(&["--latency"]).is_err());
+        assert!(parse(&["--bogus", "1"]).is_err());
+    }
+}
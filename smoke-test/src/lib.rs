@@ -0,0 +1,4 @@
+//! The modes behind the server,
+//! a library of their own so the verifier can run them in-process
+pub mod args;
+pub mod modes;
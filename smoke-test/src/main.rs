@@ -1,14 +1,36 @@
-use std::io;
-use tokio::net::{TcpListener, TcpStream};
+use std::env;
+
+use smoke_test::{args::Options, modes};
+use telemetry::stats::Metered;
+use throttle::{Limits, Throttle};
+use tokio::net::TcpListener;
 
 #[tokio::main]
-async fn main() -> io::Result<()> {
-    let listener = TcpListener::bind("0.0.0.0:3600").await?;
+async fn main() -> anyhow::Result<()> {
+    let options = Options::parse(env::args().skip(1))?;
+
+    telemetry::init();
+    if let Ok(addr) = env::var("METRICS_ADDR") {
+        if let Err(err) = telemetry::stats::install(&addr) {
+            tracing::error!("failed to start the metrics exporter: {}", err);
+        }
+    }
+
+    let throttle = Throttle::new(Limits::from_env()?);
+
+    let listener = TcpListener::bind(&options.listen).await?;
+    tracing::info!(
+        "Server listening on: {} ({:?} mode)",
+        listener.local_addr()?,
+        options.mode
+    );
     loop {
-        let (mut conn, _) = listener.accept().await?;
-        tokio::spawn(async move {
-            let (mut reader, mut writer) = TcpStream::split(&mut conn);
-            let _ = tokio::io::copy(&mut reader, &mut writer).await;
-        });
+        let (conn, addr, permit) = throttle.accept(&listener).await?;
+        let options = options.clone();
+        tokio::spawn(telemetry::connection(
+            addr,
+            permit
+                .hold(async move { modes::handle_connection(Metered::new(conn), &options).await }),
+        ));
     }
 }
@@ -0,0 +1,301 @@
+use std::{fmt, num::NonZeroU64, time::Duration};
+
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ErrorKind},
+    sync::mpsc,
+    time::Instant,
+};
+
+use crate::args::{Mode, Options};
+
+const BUFFER_SIZE: usize = 16 * 1024;
+// how many chunks an echo may hold back while they wait out the latency
+const DELAYED_CHUNKS: usize = 64;
+// chargen sends lines of this many characters, each one rotated by a character from the last
+const CHARGEN_LINE: usize = 72;
+
+/// The bytes a single connection has transferred
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Transfer {
+    pub received: u64,
+    pub sent: u64,
+}
+
+impl fmt::Display for Transfer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "received {} bytes, sent {} bytes",
+            self.received, self.sent
+        )
+    }
+}
+
+/// Serves a single connection in the configured mode, and logs its transfer once it's closed
+pub async fn handle_connection<S>(conn: S, options: &Options) -> std::io::Result<()>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    let mut transfer = Transfer::default();
+    let result = serve(conn, options, &mut transfer).await;
+    tracing::info!("the connection has {}", transfer);
+
+    result
+}
+
+/// Serves a single connection in the configured mode until it's closed,
+/// the transfer is counted even when the connection fails midway
+pub async fn serve<S>(conn: S, options: &Options, transfer: &mut Transfer) -> std::io::Result<()>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    let (mut reader, mut writer) = tokio::io::split(conn);
+    let Transfer { received, sent } = transfer;
+    let mut pacer = Pacer::new(options.bandwidth, sent);
+
+    match options.mode {
+        Mode::Echo => {
+            echo(
+                &mut reader,
+                &mut writer,
+                options.latency,
+                received,
+                &mut pacer,
+            )
+            .await
+        }
+        Mode::Discard => discard(&mut reader, received).await,
+        Mode::Chargen => {
+            // a client that is done sending may still be reading
+            let drain = async {
+                discard(&mut reader, received).await?;
+                std::future::pending().await
+            };
+            tokio::select! {
+                result = drain => result,
+                result = chargen(&mut writer, &mut pacer) => result,
+            }
+        }
+    }
+}
+
+async fn echo<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    latency: Duration,
+    received: &mut u64,
+    pacer: &mut Pacer<'_>,
+) -> std::io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    // every chunk is sent back once it's due, so a slow writer doesn't stretch the latency
+    let (tx, mut rx) = mpsc::channel::<(Instant, Vec<u8>)>(DELAYED_CHUNKS);
+    let read = async move {
+        let mut buffer = vec![0; BUFFER_SIZE];
+        loop {
+            let rcount = reader.read(&mut buffer).await?;
+            if rcount == 0 {
+                return Ok(()); // EOF
+            }
+
+            *received += rcount as u64;
+            let due = Instant::now() + latency;
+            if tx.send((due, buffer[..rcount].to_vec())).await.is_err() {
+                return Ok(());
+            }
+        }
+    };
+    let write = async {
+        while let Some((due, chunk)) = rx.recv().await {
+            tokio::time::sleep_until(due).await;
+            pacer.write(writer, &chunk).await?;
+        }
+
+        Ok(())
+    };
+
+    tokio::try_join!(read, write).map(|_| ())
+}
+
+async fn discard<R>(reader: &mut R, received: &mut u64) -> std::io::Result<()>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut buffer = vec![0; BUFFER_SIZE];
+    loop {
+        let rcount = reader.read(&mut buffer).await?;
+        if rcount == 0 {
+            return Ok(()); // EOF
+        }
+
+        *received += rcount as u64;
+    }
+}
+
+// keeps sending until the client hangs up
+async fn chargen<W>(writer: &mut W, pacer: &mut Pacer<'_>) -> std::io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let mut first = 0;
+    loop {
+        match pacer.write(writer, &chargen_line(first)).await {
+            Ok(()) => {}
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    ErrorKind::BrokenPipe | ErrorKind::ConnectionReset
+                ) =>
+            {
+                return Ok(())
+            }
+            Err(err) => return Err(err),
+        }
+        first = (first + 1) % PRINTABLE.len();
+    }
+}
+
+// the printable ASCII characters, as sent by the classic chargen service
+const PRINTABLE: std::ops::RangeInclusive<u8> = b' '..=b'~';
+
+fn chargen_line(first: usize) -> Vec<u8> {
+    let mut line: Vec<u8> = PRINTABLE.cycle().skip(first).take(CHARGEN_LINE).collect();
+    line.extend_from_slice(b"\r\n");
+    line
+}
+
+// Writes no faster than the bandwidth allows, counting every byte it sends
+struct Pacer<'a> {
+    bandwidth: Option<NonZeroU64>,
+    started: Instant,
+    sent: &'a mut u64,
+}
+
+impl<'a> Pacer<'a> {
+    fn new(bandwidth: Option<NonZeroU64>, sent: &'a mut u64) -> Self {
+        Self {
+            bandwidth,
+            started: Instant::now(),
+            sent,
+        }
+    }
+
+    async fn write<W>(&mut self, writer: &mut W, data: &[u8]) -> std::io::Result<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let Some(bandwidth) = self.bandwidth else {
+            writer.write_all(data).await?;
+            *self.sent += data.len() as u64;
+            return Ok(());
+        };
+
+        // slices of a tenth of a second worth of bytes keep the rate smooth
+        let slice = (bandwidth.get() / 10).max(1) as usize;
+        for slice in data.chunks(slice) {
+            writer.write_all(slice).await?;
+            *self.sent += slice.len() as u64;
+
+            let elapsed = Duration::from_secs_f64(*self.sent as f64 / bandwidth.get() as f64);
+            tokio::time::sleep_until(self.started + elapsed).await;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use crate::args::{Mode, Options};
+
+    use super::{chargen_line, serve, Transfer};
+
+    // Serves the input in the given mode, returning the transfer and at most `limit` bytes of output
+    async fn run(options: Options, input: &[u8], limit: u64) -> (Transfer, Vec<u8>) {
+        let (mut client, server) = tokio::io::duplex(1024);
+        let mut transfer = Transfer::default();
+        let mut output = vec![];
+
+        let serve = serve(server, &options, &mut transfer);
+        let talk = async {
+            client.write_all(input).await.unwrap();
+            client.shutdown().await.unwrap();
+            (&mut client)
+                .take(limit)
+                .read_to_end(&mut output)
+                .await
+                .unwrap();
+            drop(client);
+        };
+        let (result, ()) = tokio::join!(serve, talk);
+        result.unwrap();
+
+        (transfer, output)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn echo_after_latency() {
+        let options = Options {
+            latency: Duration::from_millis(500),
+            ..Default::default()
+        };
+        let started = tokio::time::Instant::now();
+        let (transfer, output) = run(options, b"hello", u64::MAX).await;
+
+        assert_eq!(output, b"hello");
+        assert_eq!(
+            transfer,
+            Transfer {
+                received: 5,
+                sent: 5
+            }
+        );
+        assert!(started.elapsed() >= Duration::from_millis(500));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn limit_bandwidth() {
+        let options = Options {
+            bandwidth: Some(100.try_into().unwrap()),
+            ..Default::default()
+        };
+        let started = tokio::time::Instant::now();
+        let (transfer, output) = run(options, &[b'x'; 300], u64::MAX).await;
+
+        assert_eq!(output.len(), 300);
+        assert_eq!(transfer.sent, 300);
+        assert!(started.elapsed() >= Duration::from_secs(3));
+    }
+
+    #[tokio::test]
+    async fn discard_and_chargen() {
+        let options = Options {
+            mode: Mode::Discard,
+            ..Default::default()
+        };
+        let (transfer, output) = run(options, b"hello", u64::MAX).await;
+        assert!(output.is_empty());
+        assert_eq!(
+            transfer,
+            Transfer {
+                received: 5,
+                sent: 0
+            }
+        );
+
+        let options = Options {
+            mode: Mode::Chargen,
+            ..Default::default()
+        };
+        let (transfer, output) = run(options, b"hello", 148).await;
+        assert_eq!(output, [chargen_line(0), chargen_line(1)].concat());
+        assert!(transfer.sent >= 148);
+        assert!(chargen_line(1).starts_with(b"!\"#"));
+    }
+}
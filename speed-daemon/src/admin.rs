@@ -0,0 +1,243 @@
+use std::{fmt::Write as _, net::SocketAddr, sync::Arc};
+
+use dashmap::DashMap;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{
+    systems::{CameraPosition, Road},
+    SharedSystems,
+};
+
+// Every response ends with this line, so it's easy to tell where it ends
+const END: &str = "END";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Role {
+    Unregistered,
+    Camera {
+        road: Road,
+        mile: CameraPosition,
+    },
+    // a client that has added multiple cameras, ordered by road and mile
+    Cameras {
+        cameras: Vec<(Road, CameraPosition)>,
+    },
+    Dispatcher {
+        roads: Vec<Road>,
+    },
+}
+
+/// Keeps track of the connected clients
+#[derive(Debug, Default)]
+pub struct Clients {
+    clients: DashMap<SocketAddr, Role>,
+}
+
+impl Clients {
+    /// Registers a newly connected client, it's unregistered once the returned entry is dropped
+    pub fn register(self: &Arc<Self>, addr: SocketAddr) -> ClientEntry {
+        self.clients.insert(addr, Role::Unregistered);
+
+        ClientEntry {
+            clients: self.clone(),
+            addr,
+        }
+    }
+
+    // ordered by address
+    fn list(&self) -> Vec<(SocketAddr, Role)> {
+        let mut clients: Vec<_> = self
+            .clients
+            .iter()
+            .map(|entry| (*entry.key(), entry.value().clone()))
+            .collect();
+        clients.sort_unstable_by_key(|(addr, _)| *addr);
+
+        clients
+    }
+}
+
+pub struct ClientEntry {
+    clients: Arc<Clients>,
+    addr: SocketAddr,
+}
+
+impl ClientEntry {
+    pub fn set_role(&self, role: Role) {
+        self.clients.clients.insert(self.addr, role);
+    }
+}
+
+impl Drop for ClientEntry {
+    fn drop(&mut self) {
+        self.clients.clients.remove(&self.addr);
+    }
+}
+
+/// Serves the textual admin commands, a single command per line
+pub async fn serve(addr: String, systems: SharedSystems) -> tokio::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    tracing::info!("Admin listening on: {}", listener.local_addr()?);
+
+    loop {
+        let (conn, addr) = listener.accept().await?;
+        tokio::spawn(telemetry::connection(addr, handle(conn, systems.clone())));
+    }
+}
+
+async fn handle(mut conn: TcpStream, systems: SharedSystems) -> tokio::io::Result<()> {
+    let (reader, mut writer) = conn.split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = execute(&line, &systems).await;
+        writer.write_all(response.as_bytes()).await?;
+    }
+
+    Ok(())
+}
+
+async fn execute(command: &str, systems: &SharedSystems) -> String {
+    let mut response = String::new();
+    match command.trim().to_ascii_uppercase().as_str() {
+        "STATS" => {
+            let roads = systems.record.roads().await;
+            let tickets = systems.ticket.stats().await;
+            let clients = systems.clients.list();
+            let cameras = clients
+                .iter()
+                .filter(|(_, role)| matches!(role, Role::Camera { .. } | Role::Cameras { .. }))
+                .count();
+            let dispatchers = clients
+                .iter()
+                .filter(|(_, role)| matches!(role, Role::Dispatcher { .. }))
+                .count();
+
+            let _ = writeln!(response, "roads {}", roads.len());
+            let records: u64 = roads.iter().map(|road| road.records).sum();
+            let _ = writeln!(response, "records {}", records);
+            let _ = writeln!(response, "pending_tickets {}", tickets.pending_tickets);
+            let _ = writeln!(response, "delivered_tickets {}", tickets.delivered_tickets);
+            let _ = writeln!(response, "clients {}", clients.len());
+            let _ = writeln!(response, "cameras {}", cameras);
+            let _ = writeln!(response, "dispatchers {}", dispatchers);
+        }
+        "ROADS" => {
+            let dispatchers = systems.ticket.stats().await.dispatchers;
+            for road in systems.record.roads().await {
+                let _ = writeln!(
+                    response,
+                    "road {} limit {} records {} dispatchers {}",
+                    road.road,
+                    road.limit,
+                    road.records,
+                    dispatchers.get(&road.road).copied().unwrap_or(0)
+                );
+            }
+        }
+        "PENDING_TICKETS" => {
+            for ticket in systems.ticket.pending_tickets().await {
+                let _ = writeln!(response, "{}", ticket);
+            }
+        }
+        "CLIENTS" => {
+            for (addr, role) in systems.clients.list() {
+                let _ = match role {
+                    Role::Unregistered => writeln!(response, "{} unregistered", addr),
+                    Role::Camera { road, mile } => {
+                        writeln!(response, "{} camera road {} mile {}", addr, road, mile)
+                    }
+                    Role::Cameras { cameras } => {
+                        let cameras: Vec<_> = cameras
+                            .iter()
+                            .map(|(road, mile)| format!("{}@{}", road, mile))
+                            .collect();
+                        writeln!(response, "{} cameras {}", addr, cameras.join(","))
+                    }
+                    Role::Dispatcher { roads } => {
+                        let roads: Vec<_> = roads.iter().map(Road::to_string).collect();
+                        writeln!(response, "{} dispatcher roads {}", addr, roads.join(","))
+                    }
+                };
+            }
+        }
+        _ => {
+            return "ERR unknown command, expected one of: STATS, ROADS, PENDING_TICKETS, CLIENTS\n"
+                .into()
+        }
+    }
+
+    response.push_str(END);
+    response.push('\n');
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{
+        systems::{policy::Policy, record, storage::MemoryStorage, ticket},
+        SharedSystems,
+    };
+
+    use super::{execute, Clients, Role};
+
+    #[tokio::test]
+    async fn report_roads_tickets_and_clients() {
+        let ticket = ticket::System::start().unwrap();
+        let record = record::System::start(
+            ticket.clone(),
+            Arc::new(MemoryStorage::default()),
+            Policy::default(),
+        );
+        let systems = SharedSystems {
+            ticket,
+            record,
+            clients: Arc::new(Clients::default()),
+        };
+
+        let camera_entry = systems.clients.register("127.0.0.1:1000".parse().unwrap());
+        camera_entry.set_role(Role::Camera {
+            road: 66,
+            mile: 100,
+        });
+        let _unregistered = systems.clients.register("127.0.0.1:2000".parse().unwrap());
+
+        let mut first = systems.record.clone().register_camera(66, 60).await;
+        let mut second = systems.record.clone().register_camera(66, 60).await;
+        first.submit_record(100, "UN1X".into(), 123456).await;
+        second.submit_record(110, "UN1X".into(), 123816).await;
+
+        // wait for the ticket to reach the ticket system
+        while systems.ticket.stats().await.pending_tickets == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        assert_eq!(
+            execute("roads", &systems).await,
+            "road 66 limit 60 records 2 dispatchers 0\nEND\n"
+        );
+        assert_eq!(
+            execute("PENDING_TICKETS", &systems).await,
+            "plate UN1X road 66 mile1 100 timestamp1 123456 mile2 110 timestamp2 123816 speed 100\nEND\n"
+        );
+        assert_eq!(
+            execute("CLIENTS", &systems).await,
+            "127.0.0.1:1000 camera road 66 mile 100\n127.0.0.1:2000 unregistered\nEND\n"
+        );
+
+        drop(camera_entry);
+        assert!(execute("STATS", &systems)
+            .await
+            .contains("pending_tickets 1\ndelivered_tickets 0\nclients 1\ncameras 0\n"));
+        assert!(execute("BOGUS", &systems).await.starts_with("ERR"));
+    }
+}
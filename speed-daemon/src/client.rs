@@ -1,66 +1,211 @@
-use std::{future::pending, time::Duration};
+use std::{collections::HashMap, future::pending, sync::Arc, time::Duration};
 
+use telemetry::stats::Metered;
+use throttle::Throttle;
 use tokio::{
-    io::{AsyncWriteExt, BufReader, BufWriter},
-    net::{
-        tcp::{ReadHalf, WriteHalf},
-        TcpStream,
+    io::{
+        AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, BufWriter, ReadHalf,
     },
+    net::{TcpListener, TcpStream},
     sync::{mpsc, oneshot},
 };
 
 use crate::{
+    admin::{ClientEntry, Role},
     protocol::{
-        deserializer::{Deserialize, DeserializeError},
+        deserializer::{Decoder, DeserializeError},
+        json,
         message::{FromClient, ToClient},
         serializer::Serialize,
     },
-    systems::{record::CameraHandler, CameraPosition},
+    systems::{
+        record::{self, CameraHandler},
+        ticket::{self, DispatcherId},
+        CameraPosition,
+    },
     SharedSystems,
 };
 
 const TO_CLIENT_BUFFER_SIZE: usize = 32;
+// the most messages that are written to a client with a single flush
+const MAX_BATCH_SIZE: usize = TO_CLIENT_BUFFER_SIZE;
+// a client that doesn't take a batch within this long has stopped reading, and is disconnected
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+type ConnReader = BufReader<ReadHalf<Metered<TcpStream>>>;
+
+/// The wire format a client speaks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Binary,
+    // newline-delimited JSON messages, for testing the server by hand
+    Json,
+}
+
+/// Serves the clients that speak the JSON protocol,
+/// they are handled exactly like the clients of the binary protocol
+pub async fn serve_json(
+    addr: String,
+    throttle: Arc<Throttle>,
+    systems: SharedSystems,
+    multi_camera: bool,
+) -> tokio::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    tracing::info!("JSON debug listening on: {}", listener.local_addr()?);
 
-type ConnWriter<'a> = BufWriter<WriteHalf<'a>>;
-type ConnReader<'a> = BufReader<ReadHalf<'a>>;
+    loop {
+        let (conn, addr, permit) = throttle.accept(&listener).await?;
+        tokio::spawn(telemetry::connection(
+            addr,
+            permit.hold(handle(conn, systems.clone(), multi_camera, Protocol::Json)),
+        ));
+    }
+}
+
+/// Serves a single client,
+/// the multi-camera extension messages are rejected as unknown unless `multi_camera` is set
+pub async fn handle(
+    connection: TcpStream,
+    systems: SharedSystems,
+    multi_camera: bool,
+    protocol: Protocol,
+) -> anyhow::Result<()> {
+    let entry = systems.clients.register(connection.peer_addr()?);
 
-pub async fn handle(mut connection: TcpStream, systems: SharedSystems) -> anyhow::Result<()> {
-    let (reader, writer) = connection.split();
+    let (reader, writer) = tokio::io::split(Metered::new(connection));
     let reader = BufReader::new(reader);
     let writer = BufWriter::new(writer);
 
     let (to_client, rx) = mpsc::channel(TO_CLIENT_BUFFER_SIZE);
-    let managed_writer = managed_writer(writer, rx);
+    let managed_writer = managed_writer(writer, rx, protocol);
 
     // Create future for each of the sub-systems
     let (set_heartbeat, rx) = oneshot::channel();
     let heartbeat = heartbeat(to_client.clone(), rx);
 
-    let from_client_fut = from_client(reader, to_client, systems, Some(set_heartbeat));
+    let from_client_fut = from_client(
+        reader,
+        to_client,
+        systems,
+        &entry,
+        Some(set_heartbeat),
+        multi_camera,
+        protocol,
+    );
 
-    // run all sub-systems until any exits
-    // we can't use select! because we need to allow managed_writer to try and clean
-    // its buffer even in a situation where the 'from_client_fut' reached an error an returned.
-    let (r1, r2, r3) = tokio::join!(managed_writer, heartbeat, from_client_fut);
+    // the heartbeat only runs as long as the client does, otherwise its sender would keep
+    // the writer (and the connection) open after the client is gone
+    let client = async {
+        tokio::select! {
+            result = from_client_fut => result,
+            result = heartbeat => result,
+        }
+    };
+
+    // the writer isn't raced against the rest, so it may still clean its buffer
+    // when 'from_client_fut' reached an error and returned
+    let (r1, r2) = tokio::join!(managed_writer, client);
     r1?;
-    r2?;
-    r3
+    r2
 }
 
-async fn managed_writer(
-    mut writer: ConnWriter<'_>,
+// Forwards the messages on the mpsc to the writer part of the socket
+//
+// the messages that are already waiting are written along with the first one and flushed together,
+// while a client that stops reading fills up the mpsc (holding up its senders) until it's disconnected
+async fn managed_writer<W>(
+    mut writer: BufWriter<W>,
     mut from_server: mpsc::Receiver<ToClient>,
-) -> anyhow::Result<()> {
-    // forward all messages on the mpsc to the writer part of the socket
+    protocol: Protocol,
+) -> anyhow::Result<()>
+where
+    W: AsyncWrite + Unpin + Send,
+{
     while let Some(message) = from_server.recv().await {
-        let mut writer = BufWriter::new(&mut writer);
-        message.serialize(&mut writer).await?;
-        writer.flush().await?;
+        let batch = async {
+            write_message(&mut writer, &message, protocol).await?;
+            for _ in 1..MAX_BATCH_SIZE {
+                let Ok(message) = from_server.try_recv() else {
+                    break;
+                };
+                write_message(&mut writer, &message, protocol).await?;
+            }
+
+            writer.flush().await?;
+            Ok::<_, anyhow::Error>(())
+        };
+
+        match tokio::time::timeout(WRITE_TIMEOUT, batch).await {
+            Ok(result) => result?,
+            Err(_) => {
+                telemetry::stats::error("write-timeout");
+                anyhow::bail!("the client has stopped reading, disconnecting");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn write_message<W>(
+    writer: &mut BufWriter<W>,
+    message: &ToClient,
+    protocol: Protocol,
+) -> anyhow::Result<()>
+where
+    W: AsyncWrite + Unpin + Send,
+{
+    match protocol {
+        Protocol::Binary => message.serialize(writer).await?,
+        Protocol::Json => {
+            writer
+                .write_all(format!("{}\n", json::encode(message)).as_bytes())
+                .await?
+        }
     }
 
     Ok(())
 }
 
+// Reads the messages of a client in the protocol it speaks
+enum MessageReader {
+    Binary(Decoder),
+    // the buffer of the current line
+    Json(String),
+}
+
+impl MessageReader {
+    fn new(protocol: Protocol) -> Self {
+        match protocol {
+            Protocol::Binary => Self::Binary(Decoder::default()),
+            Protocol::Json => Self::Json(String::new()),
+        }
+    }
+
+    async fn read(&mut self, reader: &mut ConnReader) -> Result<FromClient, DeserializeError> {
+        let line = match self {
+            Self::Binary(decoder) => return decoder.deserialize(reader).await,
+            Self::Json(line) => line,
+        };
+
+        // blank lines are skipped
+        loop {
+            line.clear();
+            let limit = json::MAX_LINE_LEN as u64 + 1;
+            if reader.take(limit).read_line(line).await? == 0 {
+                return Err(tokio::io::Error::from(tokio::io::ErrorKind::UnexpectedEof).into());
+            }
+            if line.len() > json::MAX_LINE_LEN {
+                return Err(DeserializeError::TooLong("the line", json::MAX_LINE_LEN));
+            }
+
+            if !line.trim().is_empty() {
+                return json::decode(line);
+            }
+        }
+    }
+}
+
 async fn heartbeat(
     to_client: mpsc::Sender<ToClient>,
     rx: oneshot::Receiver<f64>,
@@ -81,34 +226,62 @@ async fn heartbeat(
 enum Mode {
     Unregistered(SharedSystems),
     Camera(CameraPosition, CameraHandler),
-    Dispatcher,
+    // a client that adds its cameras one by one, keyed by the ids the client gave them
+    Cameras(
+        record::Handler,
+        HashMap<u16, (CameraPosition, CameraHandler)>,
+    ),
+    Dispatcher(ticket::Handler, DispatcherId),
 }
 
 // handle incoming messages from the client
 async fn from_client(
-    mut reader: ConnReader<'_>,
+    mut reader: ConnReader,
     to_client: mpsc::Sender<ToClient>,
     systems: SharedSystems,
+    entry: &ClientEntry,
     mut set_heartbeat: Option<oneshot::Sender<f64>>,
+    multi_camera: bool,
+    protocol: Protocol,
 ) -> anyhow::Result<()> {
     let mut mode = Mode::Unregistered(systems);
+    let mut messages = MessageReader::new(protocol);
 
     loop {
         // extract the message
-        let message = match FromClient::deserialize(&mut reader).await {
+        let message = match messages.read(&mut reader).await {
             Ok(message) => message,
             Err(reason) => {
-                let reason = match reason {
+                let (kind, reason) = match reason {
                     DeserializeError::Io(_) => return Ok(()), // client disconnected
-                    DeserializeError::Utf(_) => "invalid string format".into(),
-                    DeserializeError::UnknownType(_) => "unknown message".into(),
+                    DeserializeError::Utf(_) => ("utf8", "invalid string format".into()),
+                    DeserializeError::UnknownType(_) => ("unknown", "unknown message".into()),
+                    DeserializeError::Json(err) => {
+                        ("malformed", format!("malformed message: {}", err))
+                    }
+                    err @ DeserializeError::TooLong(..) => ("too-long", err.to_string()),
                 };
+                telemetry::stats::error(kind);
                 to_client.send(ToClient::error(reason)).await?;
 
                 return Ok(());
             }
         };
 
+        let extension = matches!(
+            message,
+            FromClient::AddCamera { .. } | FromClient::CameraPlate { .. }
+        );
+        if extension && !multi_camera {
+            telemetry::stats::error("unknown");
+            to_client
+                .send(ToClient::error("unknown message".into()))
+                .await?;
+
+            return Ok(());
+        }
+
+        telemetry::stats::request(message_type(&message));
         match message {
             FromClient::WantHeartbeat { interval } => {
                 if let Some(tx) = set_heartbeat.take() {
@@ -129,6 +302,7 @@ async fn from_client(
                 if let Mode::Unregistered(systems) = mode {
                     let camera_handler = systems.record.register_camera(road, limit).await;
                     mode = Mode::Camera(mile, camera_handler);
+                    entry.set_role(Role::Camera { road, mile });
                 } else {
                     to_client
                         .send(ToClient::error(
@@ -140,30 +314,102 @@ async fn from_client(
                 }
             }
             FromClient::IAmDispatcher { roads } => {
-                if let Mode::Unregistered(mut systems) = mode {
-                    systems
-                        .ticket
-                        .register_dispatcher(roads, to_client.clone())
-                        .await;
+                // a dispatcher may identify itself again to replace its roads
+                let (mut ticket, id) = match mode {
+                    Mode::Unregistered(systems) => (systems.ticket, DispatcherId::next()),
+                    Mode::Dispatcher(ticket, id) => (ticket, id),
+                    _ => {
+                        to_client
+                            .send(ToClient::error(
+                                "the client has already identified itself".into(),
+                            ))
+                            .await?;
 
-                    mode = Mode::Dispatcher;
+                        return Ok(());
+                    }
+                };
+
+                entry.set_role(Role::Dispatcher {
+                    roads: roads.clone(),
+                });
+                ticket
+                    .register_dispatcher(id, roads, to_client.clone())
+                    .await;
+
+                mode = Mode::Dispatcher(ticket, id);
+            }
+            FromClient::Plate { plate, timestamp } => {
+                if let Mode::Camera(mile, handler) = &mut mode {
+                    handler.submit_record(*mile, plate, timestamp).await;
                 } else {
                     to_client
                         .send(ToClient::error(
-                            "the client has already identified itself".into(),
+                            "the client has not identified itself as a camera".into(),
                         ))
                         .await?;
 
                     return Ok(());
                 }
             }
-            FromClient::Plate { plate, timestamp } => {
-                if let Mode::Camera(mile, handler) = &mut mode {
+            FromClient::AddCamera {
+                id,
+                road,
+                mile,
+                limit,
+            } => {
+                let (record, mut cameras) = match mode {
+                    Mode::Unregistered(systems) => (systems.record, HashMap::new()),
+                    Mode::Cameras(record, cameras) => (record, cameras),
+                    _ => {
+                        to_client
+                            .send(ToClient::error(
+                                "the client has already identified itself".into(),
+                            ))
+                            .await?;
+
+                        return Ok(());
+                    }
+                };
+
+                if cameras.contains_key(&id) {
+                    to_client
+                        .send(ToClient::error(format!(
+                            "camera {} has already been added",
+                            id
+                        )))
+                        .await?;
+
+                    return Ok(());
+                }
+
+                let camera_handler = record.clone().register_camera(road, limit).await;
+                cameras.insert(id, (mile, camera_handler));
+
+                let mut roles: Vec<_> = cameras
+                    .values()
+                    .map(|(mile, handler)| (handler.road(), *mile))
+                    .collect();
+                roles.sort_unstable();
+                entry.set_role(Role::Cameras { cameras: roles });
+
+                mode = Mode::Cameras(record, cameras);
+            }
+            FromClient::CameraPlate {
+                camera,
+                plate,
+                timestamp,
+            } => {
+                let camera = match &mut mode {
+                    Mode::Cameras(_, cameras) => cameras.get_mut(&camera),
+                    _ => None,
+                };
+
+                if let Some((mile, handler)) = camera {
                     handler.submit_record(*mile, plate, timestamp).await;
                 } else {
                     to_client
                         .send(ToClient::error(
-                            "the client has not identified itself as a camera".into(),
+                            "the client has not added such a camera".into(),
                         ))
                         .await?;
 
@@ -173,3 +419,66 @@ async fn from_client(
         }
     }
 }
+
+// the label a message is counted under
+fn message_type(message: &FromClient) -> &'static str {
+    match message {
+        FromClient::Plate { .. } => "plate",
+        FromClient::WantHeartbeat { .. } => "want-heartbeat",
+        FromClient::IAmCamera { .. } => "i-am-camera",
+        FromClient::IAmDispatcher { .. } => "i-am-dispatcher",
+        FromClient::AddCamera { .. } => "add-camera",
+        FromClient::CameraPlate { .. } => "camera-plate",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::{
+        io::{AsyncReadExt, BufWriter},
+        sync::mpsc,
+    };
+
+    use crate::protocol::message::ToClient;
+
+    use super::{managed_writer, Protocol, WRITE_TIMEOUT};
+
+    #[tokio::test]
+    async fn write_waiting_messages_together() {
+        let (mut client, server) = tokio::io::duplex(1024);
+        let (tx, rx) = mpsc::channel(8);
+        for _ in 0..3 {
+            tx.send(ToClient::heartbeat()).await.unwrap();
+        }
+        drop(tx);
+
+        managed_writer(BufWriter::new(server), rx, Protocol::Binary)
+            .await
+            .unwrap();
+        let mut output = vec![];
+        client.read_to_end(&mut output).await.unwrap();
+        assert_eq!(output, [0x41; 3]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn disconnect_a_client_that_stopped_reading() {
+        // the client never reads, so the writer gets stuck once the pipe is full
+        let (_client, server) = tokio::io::duplex(16);
+        let (tx, rx) = mpsc::channel(8);
+        let writer = tokio::spawn(managed_writer(BufWriter::new(server), rx, Protocol::Json));
+
+        let sent = async {
+            loop {
+                if tx.send(ToClient::heartbeat()).await.is_err() {
+                    break;
+                }
+            }
+        };
+        tokio::time::timeout(WRITE_TIMEOUT + Duration::from_secs(1), sent)
+            .await
+            .expect("the client wasn't disconnected");
+        assert!(writer.await.unwrap().is_err());
+    }
+}
@@ -0,0 +1,71 @@
+use std::{env, path::PathBuf, str::FromStr, time::Duration};
+
+use crate::systems::record::Retention;
+
+const DEFAULT_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+const DEFAULT_SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    // when set, observations and ticketed days are persisted in a sled database at this path,
+    // otherwise they are only kept in memory
+    pub storage_path: Option<PathBuf>,
+    // when set, the ticketing policy (tolerance and limit overrides) is loaded from this TOML file
+    pub policy_file: Option<PathBuf>,
+    // when set, the admin commands are served on this address
+    pub admin_addr: Option<String>,
+    // when set, clients speaking newline-delimited JSON are served on this address,
+    // so the server can be tested by hand
+    pub debug_addr: Option<String>,
+    // when set, observations that are older than the horizon are pruned,
+    // otherwise every observation is kept since a ticket may span any number of days
+    pub retention: Option<Retention>,
+    // accept the extension messages that let a single client add multiple cameras
+    pub multi_camera: bool,
+    // how long the dispatchers are given to take their tickets on shutdown
+    pub shutdown_grace: Duration,
+    // when set, the metrics are served for prometheus to scrape on this address
+    pub metrics_addr: Option<String>,
+}
+
+impl Config {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let interval = read_var("RETENTION_SWEEP_SECS")?
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_SWEEP_INTERVAL);
+        if interval.is_zero() {
+            anyhow::bail!("RETENTION_SWEEP_SECS must be positive");
+        }
+        let retention = read_var("RETENTION_SECS")?.map(|horizon| Retention { horizon, interval });
+
+        Ok(Self {
+            storage_path: env::var_os("STORAGE_PATH").map(PathBuf::from),
+            policy_file: env::var_os("POLICY_FILE").map(PathBuf::from),
+            admin_addr: env::var("ADMIN_ADDR").ok(),
+            debug_addr: env::var("DEBUG_ADDR").ok(),
+            retention,
+            multi_camera: env::var("MULTI_CAMERA")
+                .map(|value| value == "1" || value.eq_ignore_ascii_case("true"))
+                .unwrap_or(false),
+            shutdown_grace: read_var("SHUTDOWN_GRACE_SECS")?
+                .map(Duration::from_secs_f64)
+                .unwrap_or(DEFAULT_SHUTDOWN_GRACE),
+            metrics_addr: env::var("METRICS_ADDR").ok(),
+        })
+    }
+}
+
+fn read_var<T>(name: &str) -> anyhow::Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|err| anyhow::anyhow!("bad value for {}: {}", name, err)),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(err) => Err(anyhow::anyhow!("bad value for {}: {}", name, err)),
+    }
+}
@@ -0,0 +1,39 @@
+//! The wire protocol, the systems and the client handling behind the speed daemon,
+//! a library of their own so they can be tested in-process
+use std::sync::Arc;
+
+pub mod admin;
+pub mod client;
+pub mod protocol;
+pub mod shutdown;
+pub mod systems;
+
+/// The systems every client talks to
+#[derive(Debug, Clone)]
+pub struct SharedSystems {
+    ticket: systems::ticket::Handler,
+    record: systems::record::Handler,
+    clients: Arc<admin::Clients>,
+}
+
+impl SharedSystems {
+    pub fn new(ticket: systems::ticket::Handler, record: systems::record::Handler) -> Self {
+        Self {
+            ticket,
+            record,
+            clients: Arc::default(),
+        }
+    }
+
+    /// Starts systems that keep their records in memory and follow the default policy
+    pub fn in_memory() -> std::io::Result<Self> {
+        let ticket = systems::ticket::System::start()?;
+        let record = systems::record::System::start(
+            ticket.clone(),
+            Arc::new(systems::storage::MemoryStorage::default()),
+            systems::policy::Policy::default(),
+        );
+
+        Ok(Self::new(ticket, record))
+    }
+}
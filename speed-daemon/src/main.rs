@@ -1,30 +1,113 @@
-use tokio::net::TcpListener;
+use std::sync::Arc;
 
-mod client;
-mod protocol;
-mod systems;
+use config::Config;
+use speed_daemon::{admin, client, shutdown::Coordinator, systems, SharedSystems};
+use systems::{
+    policy::Policy,
+    storage::{MemoryStorage, SharedStorage, SledStorage},
+};
+use throttle::{Limits, Throttle};
+use tokio::net::TcpListener;
 
-#[derive(Debug, Clone)]
-pub struct SharedSystems {
-    ticket: systems::ticket::Handler,
-    record: systems::record::Handler,
-}
+mod config;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let ticket_system = systems::ticket::System::start();
-    let record_system = systems::record::System::start(ticket_system.clone());
+    telemetry::init();
+    let config = Config::from_env()?;
+    if let Some(addr) = &config.metrics_addr {
+        if let Err(err) = telemetry::stats::install(addr) {
+            tracing::error!("failed to start the metrics exporter: {}", err);
+        }
+    }
+
+    let storage: SharedStorage = match &config.storage_path {
+        Some(path) => {
+            tracing::info!("Storing records in: {}", path.display());
+            Arc::new(SledStorage::open(path)?)
+        }
+        None => Arc::new(MemoryStorage::default()),
+    };
 
-    let shared_systems = SharedSystems {
-        ticket: ticket_system,
-        record: record_system,
+    let policy = match &config.policy_file {
+        Some(path) => Policy::load(path)?,
+        None => Policy::default(),
     };
 
+    let ticket_system = systems::ticket::System::start()?;
+    let record_system =
+        systems::record::System::start(ticket_system.clone(), storage.clone(), policy);
+    if let Some(retention) = config.retention {
+        record_system.spawn_sweeper(retention);
+    }
+
+    let mut coordinator = Coordinator::new(
+        record_system.clone(),
+        ticket_system.clone(),
+        storage,
+        config.shutdown_grace,
+    );
+    let restored = coordinator.restore().await?;
+    if restored > 0 {
+        tracing::info!("Restored {} undelivered tickets", restored);
+    }
+
+    let shared_systems = SharedSystems::new(ticket_system, record_system);
+
+    if let Some(addr) = config.admin_addr {
+        let systems = shared_systems.clone();
+        tokio::spawn(async move {
+            if let Err(err) = admin::serve(addr, systems).await {
+                tracing::error!("the admin listener has failed: {}", err);
+            }
+        });
+    }
+
+    // the limits are shared by the clients of both protocols
+    let throttle = Arc::new(Throttle::new(Limits::from_env()?));
+
+    if let Some(addr) = config.debug_addr.clone() {
+        let systems = shared_systems.clone();
+        let throttle = throttle.clone();
+        tokio::spawn(async move {
+            if let Err(err) = client::serve_json(addr, throttle, systems, config.multi_camera).await
+            {
+                tracing::error!("the JSON debug listener has failed: {}", err);
+            }
+        });
+    }
+
     let listener = TcpListener::bind("0.0.0.0:3600").await?;
-    println!("Server listening on: {}", listener.local_addr().unwrap());
+    tracing::info!("Server listening on: {}", listener.local_addr().unwrap());
 
     loop {
-        let (conn, _) = listener.accept().await?;
-        tokio::spawn(client::handle(conn, shared_systems.clone()));
+        let (conn, addr, permit) = tokio::select! {
+            accepted = throttle.accept(&listener) => accepted?,
+            _ = tokio::signal::ctrl_c() => break,
+        };
+
+        tokio::spawn(telemetry::connection(
+            addr,
+            permit.hold(client::handle(
+                conn,
+                shared_systems.clone(),
+                config.multi_camera,
+                client::Protocol::Binary,
+            )),
+        ));
     }
+
+    // the connected dispatchers are kept around, so they can take the remaining tickets
+    drop(listener);
+    tracing::info!("Shutting down, draining the systems");
+    let drained = coordinator.drain().await?;
+    if !drained.flushed {
+        tracing::warn!("the dispatchers didn't take their tickets within the grace period");
+    }
+    tracing::info!(
+        "Persisted {} undelivered tickets for the next run",
+        drained.persisted
+    );
+
+    Ok(())
 }
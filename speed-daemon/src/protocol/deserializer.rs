@@ -1,8 +1,19 @@
+use std::collections::HashSet;
+
 use async_trait::async_trait;
 use tokio::io::AsyncReadExt;
 
+use crate::systems::Plate;
+
 use super::message::{message_type, FromClient};
 
+// plates are prefixed by a single length byte
+pub(super) const MAX_PLATE_LEN: usize = u8::MAX as usize;
+
+// the interned plates are dropped once a connection has seen this many distinct plates,
+// so a long lived camera doesn't keep every plate it ever reported around
+const MAX_INTERNED_PLATES: usize = 4096;
+
 #[async_trait]
 pub trait Deserialize: Sized {
     type Error;
@@ -16,13 +27,21 @@ pub trait Deserialize: Sized {
 #[derive(thiserror::Error, Debug)]
 pub enum DeserializeError {
     #[error("{0}")]
-    Utf(#[from] std::string::FromUtf8Error),
+    Utf(#[from] std::str::Utf8Error),
 
     #[error("{0}")]
     Io(#[from] tokio::io::Error),
 
     #[error("Unknown message type: {0}")]
     UnknownType(u8),
+
+    // a message of the JSON debug protocol that couldn't be parsed
+    #[error("{0}")]
+    Json(#[from] serde_json::Error),
+
+    // a JSON line or plate that couldn't be represented by the binary protocol
+    #[error("{0} is longer than {1} bytes")]
+    TooLong(&'static str, usize),
 }
 
 #[async_trait]
@@ -54,7 +73,7 @@ impl Deserialize for String {
         reader.read_exact(&mut raw).await?;
 
         // Parse the raw bytes into a string
-        let text = String::from_utf8(raw)?;
+        let text = String::from_utf8(raw).map_err(|err| err.utf8_error())?;
 
         Ok(text)
     }
@@ -71,26 +90,116 @@ impl Deserialize for FromClient {
 
         let msg = match ty {
             message_type::PLATE => Self::Plate {
-                plate: String::deserialize(reader).await?.trim().to_owned(),
+                plate: String::deserialize(reader).await?.trim().into(),
                 timestamp: reader.read_u32().await?,
             },
-            message_type::WANT_HEARTBEAT => Self::WantHeartbeat {
-                interval: reader.read_u32().await?,
+            message_type::CAMERA_PLATE => Self::CameraPlate {
+                camera: reader.read_u16().await?,
+                plate: String::deserialize(reader).await?.trim().into(),
+                timestamp: reader.read_u32().await?,
             },
-            message_type::I_AM_CAMERA => Self::IAmCamera {
-                road: reader.read_u16().await?,
-                mile: reader.read_u16().await?,
-                limit: reader.read_u16().await?,
+            _ => deserialize_control(ty, reader).await?,
+        };
+
+        Ok(msg)
+    }
+}
+
+// the messages that aren't plate observations, they are rare enough that their allocations don't matter
+async fn deserialize_control<R: AsyncReadExt + Unpin + Send>(
+    ty: u8,
+    reader: &mut R,
+) -> Result<FromClient, DeserializeError> {
+    let msg = match ty {
+        message_type::WANT_HEARTBEAT => FromClient::WantHeartbeat {
+            interval: reader.read_u32().await?,
+        },
+        message_type::I_AM_CAMERA => FromClient::IAmCamera {
+            road: reader.read_u16().await?,
+            mile: reader.read_u16().await?,
+            limit: reader.read_u16().await?,
+        },
+        message_type::I_AM_DISPATCHER => FromClient::IAmDispatcher {
+            roads: Vec::deserialize(reader).await?,
+        },
+        message_type::ADD_CAMERA => FromClient::AddCamera {
+            id: reader.read_u16().await?,
+            road: reader.read_u16().await?,
+            mile: reader.read_u16().await?,
+            limit: reader.read_u16().await?,
+        },
+
+        _ => return Err(DeserializeError::UnknownType(ty)),
+    };
+
+    Ok(msg)
+}
+
+/// Deserializes the messages of a single connection
+///
+/// the plate observations take a fast path: the plate is read into a buffer that's reused
+/// across messages and then interned, so a plate the connection has reported before
+/// is deserialized without allocating.
+#[derive(Debug)]
+pub struct Decoder {
+    buffer: Vec<u8>,
+    plates: HashSet<Plate>,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self {
+            buffer: Vec::with_capacity(MAX_PLATE_LEN),
+            plates: HashSet::default(),
+        }
+    }
+}
+
+impl Decoder {
+    pub async fn deserialize<R: AsyncReadExt + Unpin + Send>(
+        &mut self,
+        reader: &mut R,
+    ) -> Result<FromClient, DeserializeError> {
+        let ty = reader.read_u8().await?;
+
+        let msg = match ty {
+            message_type::PLATE => FromClient::Plate {
+                plate: self.plate(reader).await?,
+                timestamp: reader.read_u32().await?,
             },
-            message_type::I_AM_DISPATCHER => Self::IAmDispatcher {
-                roads: Vec::deserialize(reader).await?,
+            message_type::CAMERA_PLATE => FromClient::CameraPlate {
+                camera: reader.read_u16().await?,
+                plate: self.plate(reader).await?,
+                timestamp: reader.read_u32().await?,
             },
-
-            _ => return Err(DeserializeError::UnknownType(ty)),
+            _ => deserialize_control(ty, reader).await?,
         };
 
         Ok(msg)
     }
+
+    async fn plate<R: AsyncReadExt + Unpin + Send>(
+        &mut self,
+        reader: &mut R,
+    ) -> Result<Plate, DeserializeError> {
+        let length = reader.read_u8().await?;
+        // never grows past its initial capacity
+        self.buffer.resize(length as usize, 0);
+        reader.read_exact(&mut self.buffer).await?;
+
+        let plate = std::str::from_utf8(&self.buffer)?.trim();
+        if let Some(interned) = self.plates.get(plate) {
+            return Ok(interned.clone());
+        }
+
+        if self.plates.len() >= MAX_INTERNED_PLATES {
+            self.plates.clear();
+        }
+        let interned = Plate::from(plate);
+        self.plates.insert(interned.clone());
+
+        Ok(interned)
+    }
 }
 
 #[cfg(test)]
@@ -112,7 +221,7 @@ mod tests {
 
     #[tokio::test]
     async fn deserialize_messages() {
-        let raw_values: [&[u8]; 8] = [
+        let raw_values: [&[u8]; 10] = [
             b"\x20\x04\x55\x4E\x31\x58\x00\x00\x03\xE8",
             b"\x20\x07\x52\x45\x30\x35\x42\x4b\x47\x00\x01\xE2\x40",
             b"\x40\x00\x00\x00\x0a",
@@ -121,6 +230,8 @@ mod tests {
             b"\x80\x01\x70\x04\xd2\x00\x28",
             b"\x81\x01\x00\x42",
             b"\x81\x03\x00\x42\x01\x70\x13\x88",
+            b"\x82\x00\x07\x00\x42\x00\x64\x00\x3c",
+            b"\x22\x00\x07\x04\x55\x4E\x31\x58\x00\x00\x03\xE8",
         ];
 
         let mut deserialized_values = Vec::with_capacity(raw_values.len());
@@ -153,6 +264,17 @@ mod tests {
             FromClient::IAmDispatcher {
                 roads: [66, 368, 5000].into(),
             },
+            FromClient::AddCamera {
+                id: 7,
+                road: 66,
+                mile: 100,
+                limit: 60,
+            },
+            FromClient::CameraPlate {
+                camera: 7,
+                plate: "UN1X".into(),
+                timestamp: 1000,
+            },
         ];
 
         assert_eq!(deserialized_values, expected_values)
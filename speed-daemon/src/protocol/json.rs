@@ -0,0 +1,219 @@
+//! A newline-delimited JSON rendition of the messages, so the server can be poked at by hand
+//!
+//! every message is an object tagged by its "type", named after the message in the spec,
+//! e.g. `{"type":"IAmCamera","road":66,"mile":100,"limit":60}`.
+//! the fields carry the same values as the binary protocol, so a ticket's speed is 100x mph.
+use serde::{Deserialize, Serialize};
+
+use super::{
+    deserializer::{DeserializeError, MAX_PLATE_LEN},
+    message::{FromClient, ToClient, ToClientInternal},
+};
+
+/// The longest line a client may send, far longer than any valid message
+pub const MAX_LINE_LEN: usize = 4096;
+
+#[derive(Deserialize)]
+#[serde(tag = "type", deny_unknown_fields)]
+enum JsonFromClient {
+    Plate {
+        plate: String,
+        timestamp: u32,
+    },
+    WantHeartbeat {
+        interval: u32,
+    },
+    IAmCamera {
+        road: u16,
+        mile: u16,
+        limit: u16,
+    },
+    IAmDispatcher {
+        roads: Vec<u16>,
+    },
+    AddCamera {
+        id: u16,
+        road: u16,
+        mile: u16,
+        limit: u16,
+    },
+    CameraPlate {
+        camera: u16,
+        plate: String,
+        timestamp: u32,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type")]
+enum JsonToClient<'a> {
+    Error {
+        msg: &'a str,
+    },
+    Ticket {
+        plate: &'a str,
+        road: u16,
+        mile1: u16,
+        timestamp1: u32,
+        mile2: u16,
+        timestamp2: u32,
+        speed: u16,
+    },
+    Heartbeat,
+}
+
+// the plates are sent to dispatchers that may speak the binary protocol,
+// so they are held to its limit
+fn checked_plate(plate: &str) -> Result<crate::systems::Plate, DeserializeError> {
+    let plate = plate.trim();
+    if plate.len() > MAX_PLATE_LEN {
+        return Err(DeserializeError::TooLong("the plate", MAX_PLATE_LEN));
+    }
+
+    Ok(plate.into())
+}
+
+/// Parses a single line into a message
+pub fn decode(line: &str) -> Result<FromClient, DeserializeError> {
+    let message = match serde_json::from_str(line)? {
+        JsonFromClient::Plate { plate, timestamp } => FromClient::Plate {
+            plate: checked_plate(&plate)?,
+            timestamp,
+        },
+        JsonFromClient::WantHeartbeat { interval } => FromClient::WantHeartbeat { interval },
+        JsonFromClient::IAmCamera { road, mile, limit } => {
+            FromClient::IAmCamera { road, mile, limit }
+        }
+        JsonFromClient::IAmDispatcher { roads } => FromClient::IAmDispatcher { roads },
+        JsonFromClient::AddCamera {
+            id,
+            road,
+            mile,
+            limit,
+        } => FromClient::AddCamera {
+            id,
+            road,
+            mile,
+            limit,
+        },
+        JsonFromClient::CameraPlate {
+            camera,
+            plate,
+            timestamp,
+        } => FromClient::CameraPlate {
+            camera,
+            plate: checked_plate(&plate)?,
+            timestamp,
+        },
+    };
+
+    Ok(message)
+}
+
+/// Writes a message as a single line, without its newline
+pub fn encode(message: &ToClient) -> String {
+    let message = match &message.internal {
+        ToClientInternal::Error { msg } => JsonToClient::Error { msg },
+        ToClientInternal::Ticket {
+            plate,
+            road,
+            first_record,
+            second_record,
+            speed,
+        } => JsonToClient::Ticket {
+            plate,
+            road: *road,
+            mile1: first_record.0,
+            timestamp1: first_record.1,
+            mile2: second_record.0,
+            timestamp2: second_record.1,
+            speed: *speed,
+        },
+        ToClientInternal::Heartbeat => JsonToClient::Heartbeat,
+    };
+
+    serde_json::to_string(&message).expect("the messages are always serializable")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::protocol::message::{FromClient, ToClient};
+
+    use super::{decode, encode};
+
+    #[test]
+    fn decode_messages() {
+        let lines = [
+            r#"{"type":"Plate","plate":"UN1X","timestamp":1000}"#,
+            r#"{"type":"WantHeartbeat","interval":10}"#,
+            r#"{"type":"IAmCamera","road":66,"mile":100,"limit":60}"#,
+            r#"{"type":"IAmDispatcher","roads":[66,368]}"#,
+            r#"{"type":"AddCamera","id":7,"road":66,"mile":100,"limit":60}"#,
+            r#"{"type":"CameraPlate","camera":7,"plate":"UN1X","timestamp":1000}"#,
+        ];
+        let expected = [
+            FromClient::Plate {
+                plate: "UN1X".into(),
+                timestamp: 1000,
+            },
+            FromClient::WantHeartbeat { interval: 10 },
+            FromClient::IAmCamera {
+                road: 66,
+                mile: 100,
+                limit: 60,
+            },
+            FromClient::IAmDispatcher {
+                roads: vec![66, 368],
+            },
+            FromClient::AddCamera {
+                id: 7,
+                road: 66,
+                mile: 100,
+                limit: 60,
+            },
+            FromClient::CameraPlate {
+                camera: 7,
+                plate: "UN1X".into(),
+                timestamp: 1000,
+            },
+        ];
+
+        for (line, expected) in lines.into_iter().zip(expected) {
+            assert_eq!(decode(line).unwrap(), expected, "{}", line);
+        }
+
+        for line in [
+            r#"{"type":"Ticket"}"#,
+            r#"{"type":"IAmCamera","road":66,"mile":100}"#,
+            r#"{"type":"IAmCamera","road":66,"mile":100,"limit":-1}"#,
+            r#"{"type":"Plate","plate":"UN1X","timestamp":1000,"extra":1}"#,
+            "IAmCamera 66 100 60",
+        ] {
+            assert!(decode(line).is_err(), "{}", line);
+        }
+
+        // a plate has to fit in the binary protocol
+        let line = |plate: &str| format!(r#"{{"type":"Plate","plate":"{}","timestamp":1}}"#, plate);
+        assert!(decode(&line(&"A".repeat(255))).is_ok());
+        assert!(decode(&line(&"A".repeat(256))).is_err());
+    }
+
+    #[test]
+    fn encode_messages() {
+        assert_eq!(
+            encode(&ToClient::ticket(
+                "UN1X".into(),
+                66,
+                (100, 123456),
+                (110, 123816),
+                100
+            )),
+            r#"{"type":"Ticket","plate":"UN1X","road":66,"mile1":100,"timestamp1":123456,"mile2":110,"timestamp2":123816,"speed":10000}"#
+        );
+        assert_eq!(
+            encode(&ToClient::error("bad".into())),
+            r#"{"type":"Error","msg":"bad"}"#
+        );
+        assert_eq!(encode(&ToClient::heartbeat()), r#"{"type":"Heartbeat"}"#);
+    }
+}
@@ -1,3 +1,5 @@
+use crate::systems::Plate;
+
 const SPEED_FACTOR: u16 = 100;
 
 pub mod message_type {
@@ -8,14 +10,42 @@ pub mod message_type {
     pub const HEARTBEAT: u8 = 0x41;
     pub const I_AM_CAMERA: u8 = 0x80;
     pub const I_AM_DISPATCHER: u8 = 0x81;
+
+    // extension messages, only accepted when multi-camera mode is enabled
+    pub const CAMERA_PLATE: u8 = 0x22;
+    pub const ADD_CAMERA: u8 = 0x82;
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum FromClient {
-    Plate { plate: String, timestamp: u32 },
-    WantHeartbeat { interval: u32 },
-    IAmCamera { road: u16, mile: u16, limit: u16 },
-    IAmDispatcher { roads: Vec<u16> },
+    Plate {
+        plate: Plate,
+        timestamp: u32,
+    },
+    WantHeartbeat {
+        interval: u32,
+    },
+    IAmCamera {
+        road: u16,
+        mile: u16,
+        limit: u16,
+    },
+    IAmDispatcher {
+        roads: Vec<u16>,
+    },
+    // registers one of the client's cameras under an id of the client's choosing
+    AddCamera {
+        id: u16,
+        road: u16,
+        mile: u16,
+        limit: u16,
+    },
+    // a plate observed by one of the cameras added by the client
+    CameraPlate {
+        camera: u16,
+        plate: Plate,
+        timestamp: u32,
+    },
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
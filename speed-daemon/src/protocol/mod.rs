@@ -1,3 +1,4 @@
 pub mod deserializer;
+pub mod json;
 pub mod message;
 pub mod serializer;
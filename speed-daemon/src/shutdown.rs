@@ -0,0 +1,80 @@
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::systems::{
+    record,
+    storage::{self, SharedStorage, StorageError},
+    ticket,
+};
+
+/// What a drain has done with the tickets that were still around
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Drained {
+    // tickets that couldn't be delivered, and were persisted for the next run
+    pub persisted: usize,
+    // whether the dispatchers took every ticket they were handed before the grace period was over
+    pub flushed: bool,
+}
+
+/// Brings the systems down in an order that doesn't lose any ticket
+///
+/// once the daemon stops accepting connections:
+/// 1. the record system stops accepting records, and checks the ones it already has
+/// 2. the ticket system flushes its tickets to the connected dispatchers, within the grace period
+/// 3. the tickets that couldn't be delivered are persisted, to be restored on the next run
+#[derive(Clone)]
+pub struct Coordinator {
+    record: record::Handler,
+    ticket: ticket::Handler,
+    storage: SharedStorage,
+    // how long the dispatchers are given to take their tickets
+    grace: Duration,
+}
+
+impl Coordinator {
+    pub fn new(
+        record: record::Handler,
+        ticket: ticket::Handler,
+        storage: SharedStorage,
+        grace: Duration,
+    ) -> Self {
+        Self {
+            record,
+            ticket,
+            storage,
+            grace,
+        }
+    }
+
+    /// Resubmits the tickets persisted by the previous run,
+    /// returns the number of restored tickets
+    pub async fn restore(&mut self) -> Result<usize, StorageError> {
+        let tickets = storage::blocking(&self.storage, |storage| storage.take_tickets()).await?;
+        let restored = tickets.len();
+        for ticket in tickets {
+            self.ticket.submit_ticket(ticket).await;
+        }
+
+        Ok(restored)
+    }
+
+    pub async fn drain(self) -> Result<Drained, StorageError> {
+        let deadline = Instant::now() + self.grace;
+
+        self.record.drain().await;
+
+        // the ticket system never waits on a dispatcher, so it always responds by the deadline
+        let drain = self.ticket.drain(deadline).await;
+        let persisted = drain.undelivered.len();
+        let undelivered = drain.undelivered;
+        storage::blocking(&self.storage, move |storage| {
+            storage.persist_tickets(&undelivered)
+        })
+        .await?;
+        Ok(Drained {
+            persisted,
+            flushed: drain.flushed,
+        })
+    }
+}
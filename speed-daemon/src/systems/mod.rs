@@ -1,8 +1,14 @@
-pub type Plate = String;
+use std::sync::Arc;
+
+// plates are interned by the connections that report them, so they are cheap to pass around
+pub type Plate = Arc<str>;
 pub type CameraPosition = u16;
 pub type Timestamp = u32;
 pub type Road = u16;
 pub type Limit = u16;
 
+pub mod policy;
 pub mod record;
+pub mod snapshot;
+pub mod storage;
 pub mod ticket;
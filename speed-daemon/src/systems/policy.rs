@@ -0,0 +1,130 @@
+use std::{collections::HashMap, path::Path};
+
+use serde::Deserialize;
+
+use super::{Limit, Road};
+
+// the spec tickets cars that go 0.5 mph or more over the limit
+const DEFAULT_TOLERANCE: f64 = 0.5;
+
+#[derive(thiserror::Error, Debug)]
+pub enum PolicyError {
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("{0}")]
+    Parse(#[from] toml::de::Error),
+
+    #[error("The tolerance must be a non-negative number, got: {0}")]
+    BadTolerance(f64),
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+struct RoadLimit {
+    road: Road,
+    limit: Limit,
+}
+
+/// Decides which speeds are ticketed
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct Policy {
+    // how far above the limit (in mph) a car may go before it's ticketed
+    #[serde(default = "default_tolerance")]
+    tolerance: f64,
+    // limits that override the ones reported by the cameras
+    #[serde(rename = "road", default)]
+    overrides: Vec<RoadLimit>,
+    #[serde(skip)]
+    limits: HashMap<Road, Limit>,
+}
+
+fn default_tolerance() -> f64 {
+    DEFAULT_TOLERANCE
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            tolerance: DEFAULT_TOLERANCE,
+            overrides: Vec::new(),
+            limits: HashMap::new(),
+        }
+    }
+}
+
+impl Policy {
+    /// Loads the policy from a TOML file, e.g.
+    ///
+    /// ```toml
+    /// tolerance = 2.0
+    ///
+    /// [[road]]
+    /// road = 66
+    /// limit = 80
+    /// ```
+    pub fn load(path: &Path) -> Result<Self, PolicyError> {
+        std::fs::read_to_string(path)?.parse()
+    }
+
+    /// The limit of the road, the camera's limit is used unless it's overridden
+    pub fn limit(&self, road: Road, reported: Limit) -> Limit {
+        self.limits.get(&road).copied().unwrap_or(reported)
+    }
+
+    /// Whether a car going at the (unrounded) speed should be ticketed
+    pub fn is_speeding(&self, speed: f64, limit: Limit) -> bool {
+        speed >= limit as f64 + self.tolerance
+    }
+}
+
+impl std::str::FromStr for Policy {
+    type Err = PolicyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut policy: Self = toml::from_str(s)?;
+        if policy.tolerance.is_nan() || policy.tolerance < 0.0 {
+            return Err(PolicyError::BadTolerance(policy.tolerance));
+        }
+
+        policy.limits = policy
+            .overrides
+            .iter()
+            .map(|road| (road.road, road.limit))
+            .collect();
+        Ok(policy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Policy, PolicyError};
+
+    #[test]
+    fn parse_policy() {
+        let policy: Policy = r#"
+            tolerance = 2.0
+
+            [[road]]
+            road = 66
+            limit = 80
+        "#
+        .parse()
+        .unwrap();
+
+        assert_eq!(policy.limit(66, 60), 80);
+        assert_eq!(policy.limit(368, 60), 60);
+        assert!(!policy.is_speeding(61.9, 60));
+        assert!(policy.is_speeding(62.0, 60));
+
+        // the default policy tickets speeds that round above the limit
+        let policy: Policy = "".parse().unwrap();
+        assert_eq!(policy, Policy::default());
+        assert!(!policy.is_speeding(60.4, 60));
+        assert!(policy.is_speeding(60.5, 60));
+
+        assert!(matches!(
+            "tolerance = -1.0".parse::<Policy>(),
+            Err(PolicyError::BadTolerance(_))
+        ));
+    }
+}
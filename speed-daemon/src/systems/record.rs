@@ -1,11 +1,15 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, time::Duration};
 
-use dashmap::DashSet;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot};
 
-use super::{ticket::Ticket, CameraPosition, Limit, Plate, Road, Timestamp};
+use super::{
+    policy::Policy,
+    storage::{self, SharedStorage},
+    ticket::Ticket,
+    CameraPosition, Limit, Plate, Road, Timestamp,
+};
 
-const DAY_IN_SECS: u32 = 86400;
+pub const DAY_IN_SECS: u32 = 86400;
 
 // Since the system submits it work into subsystems,
 // there is no need for a big buffer
@@ -15,18 +19,41 @@ const SYSTEM_BUFFER_SIZE: usize = 64;
 // we don't need a particularly big buffer
 const WORKER_BUFFER_SIZE: usize = 64;
 
-type SharedTicketRecords = Arc<DashSet<(Plate, u32)>>;
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoadStats {
+    pub road: Road,
+    pub limit: Limit,
+    // the number of plate records submitted on the road
+    pub records: u64,
+}
+
+/// How long observations are kept around
+#[derive(Debug, Clone, Copy)]
+pub struct Retention {
+    // observations older than the newest observation on their road by more than this are pruned
+    pub horizon: u32,
+    // how often the roads are swept
+    pub interval: Duration,
+}
 
 #[derive(Debug)]
 enum InternalMessage {
     RegisterCamera(Road, Limit),
     SubmitRecord(Road, CameraPosition, Plate, Timestamp),
+    Roads(oneshot::Sender<Vec<RoadStats>>),
+    Prune(u32),
+    Drain(oneshot::Sender<()>),
+    #[cfg(feature = "test-util")]
+    Settle(oneshot::Sender<()>),
 }
 
 pub struct System {
     workers: HashMap<Road, RoadWorkerHandler>,
+    // once draining, records are no longer accepted
+    draining: bool,
     ticket_system: super::ticket::Handler,
-    ticket_records: SharedTicketRecords,
+    storage: SharedStorage,
+    policy: Policy,
 }
 
 impl System {
@@ -35,13 +62,19 @@ impl System {
     /// returns an handler that can be used to control the system
     ///
     /// note: this function needs to be called from inside a tokio runtime context
-    pub fn start(ticket_system: super::ticket::Handler) -> Handler {
+    pub fn start(
+        ticket_system: super::ticket::Handler,
+        storage: SharedStorage,
+        policy: Policy,
+    ) -> Handler {
         let (tx, mut rx) = mpsc::channel(SYSTEM_BUFFER_SIZE);
 
         let mut this = Self {
             workers: HashMap::default(),
+            draining: false,
             ticket_system,
-            ticket_records: Arc::default(),
+            storage,
+            policy,
         };
         tokio::spawn(async move {
             while let Some(message) = rx.recv().await {
@@ -52,6 +85,20 @@ impl System {
                     InternalMessage::SubmitRecord(road, camera, plate, timestamp) => {
                         this.submit_record(road, camera, plate, timestamp).await
                     }
+                    InternalMessage::Roads(response) => {
+                        let _ = response.send(this.roads());
+                    }
+                    InternalMessage::Prune(horizon) => this.prune(horizon).await,
+                    InternalMessage::Drain(done) => {
+                        this.draining = true;
+                        this.settle().await;
+                        let _ = done.send(());
+                    }
+                    #[cfg(feature = "test-util")]
+                    InternalMessage::Settle(done) => {
+                        this.settle().await;
+                        let _ = done.send(());
+                    }
                 }
             }
         });
@@ -59,13 +106,31 @@ impl System {
         Handler { sender: tx }
     }
 
+    /// Starts a new record system that keeps its observations in memory
+    #[cfg(feature = "test-util")]
+    pub fn start_in_memory(ticket_system: super::ticket::Handler) -> Handler {
+        Self::start(
+            ticket_system,
+            std::sync::Arc::new(super::storage::MemoryStorage::default()),
+            Policy::default(),
+        )
+    }
+
+    // waits for every worker to go through the reports submitted so far
+    async fn settle(&self) {
+        for worker in self.workers.values() {
+            worker.settle().await;
+        }
+    }
+
     async fn register_camera(&mut self, road: Road, limit: Limit) {
         self.workers.entry(road).or_insert_with(|| {
             RoadWorker::start(
                 road,
-                limit,
+                self.policy.limit(road, limit),
+                self.policy.clone(),
                 self.ticket_system.clone(),
-                self.ticket_records.clone(),
+                self.storage.clone(),
             )
         });
     }
@@ -77,15 +142,43 @@ impl System {
         plate: Plate,
         timestamp: Timestamp,
     ) {
+        if self.draining {
+            return;
+        }
+
         let road_worker = self
             .workers
             .get_mut(&road)
             .expect("a camera must be registered to submit a report");
 
+        road_worker.records += 1;
         road_worker
             .submit_plate_report(plate, camera, timestamp)
             .await;
     }
+
+    // every worker prunes its own road, so the system isn't blocked by the storage
+    async fn prune(&mut self, horizon: u32) {
+        for worker in self.workers.values() {
+            worker.prune(horizon).await;
+        }
+    }
+
+    // ordered by road
+    fn roads(&self) -> Vec<RoadStats> {
+        let mut roads: Vec<_> = self
+            .workers
+            .iter()
+            .map(|(&road, worker)| RoadStats {
+                road,
+                limit: worker.limit,
+                records: worker.records,
+            })
+            .collect();
+        roads.sort_unstable_by_key(|stats| stats.road);
+
+        roads
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -107,6 +200,61 @@ impl Handler {
             road,
         }
     }
+
+    /// Returns the stats of every road with a registered camera
+    pub async fn roads(&self) -> Vec<RoadStats> {
+        let (tx, rx) = oneshot::channel();
+        self.sender
+            .send(InternalMessage::Roads(tx))
+            .await
+            .expect("the system should live as long as the handler live");
+
+        rx.await.expect("the system always responds to queries")
+    }
+
+    /// Starts a background task that periodically prunes old observations,
+    /// the task stops once the system is gone
+    pub fn spawn_sweeper(&self, retention: Retention) {
+        let sender = self.sender.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(retention.interval);
+            loop {
+                interval.tick().await;
+                if sender
+                    .send(InternalMessage::Prune(retention.horizon))
+                    .await
+                    .is_err()
+                {
+                    return;
+                }
+            }
+        });
+    }
+
+    /// Stops accepting records, and waits until every record submitted so far has been checked,
+    /// and the tickets it resulted in were handed to the ticket system
+    pub async fn drain(&self) {
+        let (tx, rx) = oneshot::channel();
+        self.sender
+            .send(InternalMessage::Drain(tx))
+            .await
+            .expect("the system should live as long as the handler live");
+
+        rx.await.expect("the system always responds to queries")
+    }
+
+    /// Waits until every record submitted so far has been checked,
+    /// and the tickets it resulted in were handed to the ticket system
+    #[cfg(feature = "test-util")]
+    pub async fn settled(&self) {
+        let (tx, rx) = oneshot::channel();
+        self.sender
+            .send(InternalMessage::Settle(tx))
+            .await
+            .expect("the system should live as long as the handler live");
+
+        rx.await.expect("the system always responds to queries")
+    }
 }
 
 pub struct CameraHandler {
@@ -115,6 +263,10 @@ pub struct CameraHandler {
 }
 
 impl CameraHandler {
+    pub fn road(&self) -> Road {
+        self.road
+    }
+
     pub async fn submit_record(
         &mut self,
         camera: CameraPosition,
@@ -133,14 +285,19 @@ impl CameraHandler {
 // Road worker
 enum InternalWorkerMessage {
     PlateReport(Plate, CameraPosition, Timestamp),
+    Prune(u32),
+    Settle(oneshot::Sender<()>),
 }
 
 struct RoadWorker {
-    records: HashMap<Plate, HashMap<CameraPosition, Timestamp>>,
     road: Road,
     speed_limit: Limit,
+    policy: Policy,
+    // the newest observation on the road, the retention horizon is relative to it
+    // since the timestamps have nothing to do with the server's clock
+    latest: Timestamp,
     ticket_handler: super::ticket::Handler,
-    ticket_records: SharedTicketRecords,
+    storage: SharedStorage,
 }
 
 impl RoadWorker {
@@ -148,17 +305,19 @@ impl RoadWorker {
     fn start(
         road: Road,
         speed_limit: Limit,
+        policy: Policy,
         ticket_handler: super::ticket::Handler,
-        ticket_records: SharedTicketRecords,
+        storage: SharedStorage,
     ) -> RoadWorkerHandler {
         let (tx, mut rx) = mpsc::channel(WORKER_BUFFER_SIZE);
 
         let mut this = Self {
-            records: HashMap::new(),
             road,
             speed_limit,
+            policy,
+            latest: 0,
             ticket_handler,
-            ticket_records,
+            storage,
         };
         tokio::spawn(async move {
             while let Some(message) = rx.recv().await {
@@ -166,37 +325,69 @@ impl RoadWorker {
                     InternalWorkerMessage::PlateReport(plate, camera, timestamp) => {
                         this.record(plate, camera, timestamp).await
                     }
+                    InternalWorkerMessage::Prune(horizon) => this.prune(horizon).await,
+                    InternalWorkerMessage::Settle(done) => {
+                        let _ = done.send(());
+                    }
                 }
             }
         });
 
-        RoadWorkerHandler { sender: tx }
+        RoadWorkerHandler {
+            sender: tx,
+            limit: speed_limit,
+            records: 0,
+        }
+    }
+
+    async fn prune(&self, horizon: u32) {
+        let before = self.latest.saturating_sub(horizon);
+        let road = self.road;
+        if let Err(err) =
+            storage::blocking(&self.storage, move |storage| storage.prune(road, before)).await
+        {
+            tracing::error!("failed to prune the records of road {}: {}", self.road, err);
+        }
     }
 
     async fn record(&mut self, plate: Plate, camera: CameraPosition, timetsamp: Timestamp) {
-        // Insert the new record to the system
-        let records = self.records.entry(plate.clone()).or_default();
-        records.insert(camera, timetsamp);
+        self.latest = self.latest.max(timetsamp);
+
+        // Insert the new record to the system,
+        // a repeated record comes back without any records since it was already checked
+        let (road, observed) = (self.road, plate.clone());
+        let records = match storage::blocking(&self.storage, move |storage| {
+            storage.observe(road, &observed, camera, timetsamp)
+        })
+        .await
+        {
+            Ok(records) => records,
+            Err(err) => {
+                tracing::error!("failed to store a record of {}: {}", plate, err);
+                return;
+            }
+        };
 
         // Check the new record against the existing records to find speed limit violations
-        'record_loop: for (entry_camera, entry_timestamp) in records {
+        for (entry_camera, entry_timestamp) in records {
             let distance = entry_camera.abs_diff(camera);
             let time: f64 = entry_timestamp.abs_diff(timetsamp) as f64 / 60f64 / 60f64; // convert secs to hours
             if time == 0.0 || distance == 0 {
                 continue;
             }
 
-            let Ok(speed) = ((distance as f64 / time).round() as u64).try_into() else {
+            let exact_speed = distance as f64 / time;
+            let Ok(speed) = (exact_speed.round() as u64).try_into() else {
                 // we are guarnteed that no drive can reach a speed limit high enough for this to fail
                 return;
             };
 
-            if speed > self.speed_limit {
-                let start = (timetsamp, camera).min((*entry_timestamp, *entry_camera));
-                let end = (timetsamp, camera).max((*entry_timestamp, *entry_camera));
+            if self.policy.is_speeding(exact_speed, self.speed_limit) {
+                let start = (timetsamp, camera).min((entry_timestamp, entry_camera));
+                let end = (timetsamp, camera).max((entry_timestamp, entry_camera));
 
                 let ticket = Ticket::new(
-                    plate.clone(),
+                    plate.to_string(),
                     self.road,
                     start.1,
                     start.0,
@@ -205,17 +396,18 @@ impl RoadWorker {
                     speed,
                 );
 
-                for day in (start.0 / DAY_IN_SECS)..=(end.0 / DAY_IN_SECS) {
-                    if self.ticket_records.get(&(plate.clone(), day)).is_some() {
-                        continue 'record_loop;
-                    }
-                }
-
-                for day in (start.0 / DAY_IN_SECS)..=(end.0 / DAY_IN_SECS) {
-                    self.ticket_records.insert((plate.clone(), day));
+                // a car can only be ticketed once per day
+                let days = (start.0 / DAY_IN_SECS)..=(end.0 / DAY_IN_SECS);
+                let ticketed = plate.clone();
+                match storage::blocking(&self.storage, move |storage| {
+                    storage.try_ticket(&ticketed, days)
+                })
+                .await
+                {
+                    Ok(true) => self.ticket_handler.submit_ticket(ticket).await,
+                    Ok(false) => {}
+                    Err(err) => tracing::error!("failed to record a ticket of {}: {}", plate, err),
                 }
-
-                self.ticket_handler.submit_ticket(ticket.clone()).await;
             }
         }
     }
@@ -224,6 +416,8 @@ impl RoadWorker {
 #[derive(Debug, Clone)]
 struct RoadWorkerHandler {
     sender: mpsc::Sender<InternalWorkerMessage>,
+    limit: Limit,
+    records: u64,
 }
 
 impl RoadWorkerHandler {
@@ -238,4 +432,91 @@ impl RoadWorkerHandler {
             .await
             .expect("the road worker should live as long as the handlers live")
     }
+
+    async fn prune(&self, horizon: u32) {
+        self.sender
+            .send(InternalWorkerMessage::Prune(horizon))
+            .await
+            .expect("the road worker should live as long as the handlers live")
+    }
+
+    async fn settle(&self) {
+        let (tx, rx) = oneshot::channel();
+        self.sender
+            .send(InternalWorkerMessage::Settle(tx))
+            .await
+            .expect("the road worker should live as long as the handlers live");
+
+        rx.await
+            .expect("the road worker always responds to queries")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::sync::mpsc;
+
+    use crate::systems::{policy::Policy, storage::MemoryStorage, ticket, Plate};
+
+    use super::System;
+
+    // the number of roads flooded with (non speeding) observations
+    const FLOODED_ROADS: u16 = 8;
+    const FLOOD_RECORDS_PER_ROAD: u32 = 5_000;
+    const MEASURED_TICKETS: usize = 100;
+
+    // time is paused, so the test depends on the order the work is done in rather than on the machine
+    #[tokio::test(start_paused = true)]
+    async fn tickets_flow_during_ingestion_spike() {
+        let mut ticket_system = ticket::System::start().unwrap();
+        let record_system = System::start(
+            ticket_system.clone(),
+            Arc::new(MemoryStorage::default()),
+            Policy::default(),
+        );
+
+        let (dispatcher, mut tickets) = mpsc::channel(32);
+        ticket_system
+            .register_dispatcher(ticket::DispatcherId::next(), vec![0], dispatcher)
+            .await;
+
+        // start the ingestion spike
+        let mut flood = Vec::new();
+        for road in 1..=FLOODED_ROADS {
+            let record_system = record_system.clone();
+            flood.push(tokio::spawn(async move {
+                let mut camera = record_system.register_camera(road, 60).await;
+                for timestamp in 0..FLOOD_RECORDS_PER_ROAD {
+                    let plate = format!("FLOOD{}", timestamp % 512);
+                    camera
+                        .submit_record(0, plate.into(), timestamp * 3600)
+                        .await;
+                }
+            }));
+        }
+
+        // every ticket should reach the dispatcher while the spike is still being ingested,
+        // rather than waiting behind the records of the flooded roads
+        let mut first = record_system.clone().register_camera(0, 60).await;
+        let mut second = record_system.clone().register_camera(0, 60).await;
+        for idx in 0..MEASURED_TICKETS {
+            let plate: Plate = format!("SPEED{}", idx).into();
+            first.submit_record(0, plate.clone(), 0).await;
+            second.submit_record(10, plate, 60).await;
+            tickets
+                .recv()
+                .await
+                .expect("the dispatcher should receive a ticket");
+        }
+        assert!(
+            !flood.iter().all(|task| task.is_finished()),
+            "the tickets were only delivered once the spike was over"
+        );
+
+        for task in flood {
+            task.await.unwrap();
+        }
+    }
 }
@@ -0,0 +1,221 @@
+use super::{ticket::Ticket, Plate};
+
+// A snapshot starts with a header of MAGIC followed by a single VERSION byte,
+// and continues with a sequence of sections: id (u8), payload length (u32 BE), payload.
+// readers skip sections they don't know, so new sections can be added without a version bump
+const MAGIC: &[u8; 4] = b"SDSN";
+const VERSION: u8 = 1;
+
+// 0x01 and 0x03 were taken by the ticket records and the cameras, which were never persisted,
+// they are skipped as any other unknown section and aren't to be reused
+mod section {
+    pub const PENDING_TICKETS: u8 = 0x02;
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum SnapshotError {
+    #[error("Not a snapshot")]
+    BadMagic,
+
+    #[error("Unsupported snapshot version: {0}")]
+    UnsupportedVersion(u8),
+
+    #[error("The snapshot is truncated")]
+    Truncated,
+
+    #[error("{0}")]
+    Utf(#[from] std::string::FromUtf8Error),
+
+    #[error("A plate is too long to be stored: {0}")]
+    PlateTooLong(Plate),
+}
+
+/// The state of the systems at a single point in time
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    // tickets that are waiting for a dispatcher
+    pub pending_tickets: Vec<Ticket>,
+}
+
+impl Snapshot {
+    pub fn encode(&self) -> Result<Vec<u8>, SnapshotError> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+
+        let mut payload = Writer::default();
+        payload.u32(self.pending_tickets.len() as u32);
+        for ticket in &self.pending_tickets {
+            payload.plate(&ticket.plate)?;
+            payload.u16(ticket.road);
+            payload.u16(ticket.mile1);
+            payload.u32(ticket.timestamp1);
+            payload.u16(ticket.mile2);
+            payload.u32(ticket.timestamp2);
+            payload.u16(ticket.speed);
+        }
+        push_section(&mut bytes, section::PENDING_TICKETS, payload);
+
+        Ok(bytes)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        let mut reader = Reader { bytes };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+
+        let version = reader.u8()?;
+        if version != VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+
+        let mut snapshot = Self::default();
+        while !reader.bytes.is_empty() {
+            let id = reader.u8()?;
+            let len = reader.u32()? as usize;
+            let mut payload = Reader {
+                bytes: reader.take(len)?,
+            };
+
+            // any other section was written by a newer (or retired) version, skip it
+            if id != section::PENDING_TICKETS {
+                continue;
+            }
+
+            for _ in 0..payload.u32()? {
+                snapshot.pending_tickets.push(Ticket {
+                    plate: payload.plate()?.to_string(),
+                    road: payload.u16()?,
+                    mile1: payload.u16()?,
+                    timestamp1: payload.u32()?,
+                    mile2: payload.u16()?,
+                    timestamp2: payload.u32()?,
+                    speed: payload.u16()?,
+                });
+            }
+        }
+
+        Ok(snapshot)
+    }
+}
+
+fn push_section(bytes: &mut Vec<u8>, id: u8, payload: Writer) {
+    bytes.push(id);
+    bytes.extend_from_slice(&(payload.bytes.len() as u32).to_be_bytes());
+    bytes.extend(payload.bytes);
+}
+
+#[derive(Default)]
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    // plates are prefixed by a single length byte, just like on the wire
+    fn plate(&mut self, plate: &str) -> Result<(), SnapshotError> {
+        let len: u8 = plate
+            .len()
+            .try_into()
+            .map_err(|_| SnapshotError::PlateTooLong(plate.into()))?;
+
+        self.bytes.push(len);
+        self.bytes.extend_from_slice(plate.as_bytes());
+        Ok(())
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SnapshotError> {
+        if self.bytes.len() < len {
+            return Err(SnapshotError::Truncated);
+        }
+
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, SnapshotError> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, SnapshotError> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn plate(&mut self) -> Result<Plate, SnapshotError> {
+        let len = self.u8()? as usize;
+        Ok(String::from_utf8(self.take(len)?.to_vec())?.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::systems::ticket::Ticket;
+
+    use super::{section, Snapshot, SnapshotError, MAGIC};
+
+    fn snapshot() -> Snapshot {
+        Snapshot {
+            pending_tickets: vec![Ticket::new(
+                "UN1X".into(),
+                66,
+                100,
+                123456,
+                110,
+                123816,
+                100,
+            )],
+        }
+    }
+
+    #[test]
+    fn round_trip_snapshot() {
+        let snapshot = snapshot();
+        let decoded = Snapshot::decode(&snapshot.encode().unwrap()).unwrap();
+        assert_eq!(decoded, snapshot);
+
+        let empty = Snapshot::default();
+        assert_eq!(Snapshot::decode(&empty.encode().unwrap()).unwrap(), empty);
+    }
+
+    #[test]
+    fn skip_unknown_sections() {
+        let mut bytes = snapshot().encode().unwrap();
+        bytes.extend_from_slice(&[0x7f, 0, 0, 0, 3, 1, 2, 3]);
+        // the retired sections of the ticket records and the cameras
+        bytes.extend_from_slice(&[0x01, 0, 0, 0, 4, 0, 0, 0, 0]);
+        bytes.extend_from_slice(&[0x03, 0, 0, 0, 4, 0, 0, 0, 0]);
+
+        assert_eq!(Snapshot::decode(&bytes).unwrap(), snapshot());
+    }
+
+    #[test]
+    fn reject_bad_snapshots() {
+        assert_eq!(Snapshot::decode(b"NOPE\x01"), Err(SnapshotError::BadMagic));
+        assert_eq!(
+            Snapshot::decode(b"SDSN\x02"),
+            Err(SnapshotError::UnsupportedVersion(2))
+        );
+
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&[1, section::PENDING_TICKETS, 0, 0, 0, 8, 0, 0, 0, 1]);
+        assert_eq!(Snapshot::decode(&bytes), Err(SnapshotError::Truncated));
+    }
+}
@@ -0,0 +1,432 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    ops::RangeInclusive,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use dashmap::DashMap;
+
+use super::{
+    snapshot::{Snapshot, SnapshotError},
+    ticket::Ticket,
+    CameraPosition, Plate, Road, Timestamp,
+};
+
+pub type SharedStorage = Arc<dyn Storage>;
+
+#[derive(thiserror::Error, Debug)]
+pub enum StorageError {
+    #[error("{0}")]
+    Sled(#[from] sled::Error),
+
+    #[error("{0}")]
+    Snapshot(#[from] SnapshotError),
+}
+
+/// Runs a call of the storage on the blocking threads,
+/// as a storage may wait on its disk, which would stall the other tasks of an async worker
+pub async fn blocking<T, F>(storage: &SharedStorage, call: F) -> Result<T, StorageError>
+where
+    T: Send + 'static,
+    F: FnOnce(&dyn Storage) -> Result<T, StorageError> + Send + 'static,
+{
+    let storage = Arc::clone(storage);
+    match tokio::task::spawn_blocking(move || call(&*storage)).await {
+        Ok(result) => result,
+        Err(err) => std::panic::resume_unwind(err.into_panic()),
+    }
+}
+
+/// Where the plate observations and the ticketed days are kept
+pub trait Storage: Send + Sync {
+    /// Stores an observation of a plate,
+    /// returns every observation of the plate on the road (including the new one),
+    /// or nothing if the very same observation was already stored
+    ///
+    /// a camera keeps every observation of each plate, no matter the order they arrive in
+    fn observe(
+        &self,
+        road: Road,
+        plate: &str,
+        camera: CameraPosition,
+        timestamp: Timestamp,
+    ) -> Result<Vec<(CameraPosition, Timestamp)>, StorageError>;
+
+    /// Forgets the observations on the road that were taken before the timestamp,
+    /// returns the number of forgotten observations
+    fn prune(&self, road: Road, before: Timestamp) -> Result<usize, StorageError>;
+
+    /// Marks the days as ticketed for the plate,
+    /// unless the plate was already ticketed on any of them (in which case false is returned)
+    fn try_ticket(&self, plate: &str, days: RangeInclusive<u32>) -> Result<bool, StorageError>;
+
+    /// Keeps the tickets that couldn't be delivered before a shutdown,
+    /// replacing any tickets that were kept before
+    fn persist_tickets(&self, tickets: &[Ticket]) -> Result<(), StorageError>;
+
+    /// Takes the tickets kept by `persist_tickets`, they aren't kept any longer afterwards
+    fn take_tickets(&self) -> Result<Vec<Ticket>, StorageError>;
+}
+
+/// Keeps everything in memory, the state is lost on restart
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    records: DashMap<Road, HashMap<Plate, HashMap<CameraPosition, BTreeSet<Timestamp>>>>,
+    ticketed: Mutex<HashSet<(Plate, u32)>>,
+    undelivered: Mutex<Vec<Ticket>>,
+}
+
+impl Storage for MemoryStorage {
+    fn observe(
+        &self,
+        road: Road,
+        plate: &str,
+        camera: CameraPosition,
+        timestamp: Timestamp,
+    ) -> Result<Vec<(CameraPosition, Timestamp)>, StorageError> {
+        let mut road = self.records.entry(road).or_default();
+        // only a plate that's new to the road is copied
+        let records = match road.get_mut(plate) {
+            Some(records) => records,
+            None => road.entry(plate.into()).or_default(),
+        };
+        if !records.entry(camera).or_default().insert(timestamp) {
+            return Ok(vec![]);
+        }
+
+        Ok(records
+            .iter()
+            .flat_map(|(&camera, timestamps)| timestamps.iter().map(move |&ts| (camera, ts)))
+            .collect())
+    }
+
+    fn prune(&self, road: Road, before: Timestamp) -> Result<usize, StorageError> {
+        let Some(mut road) = self.records.get_mut(&road) else {
+            return Ok(0);
+        };
+
+        let mut pruned = 0;
+        road.retain(|_, records| {
+            records.retain(|_, timestamps| {
+                let len = timestamps.len();
+                timestamps.retain(|&timestamp| timestamp >= before);
+                pruned += len - timestamps.len();
+
+                !timestamps.is_empty()
+            });
+
+            !records.is_empty()
+        });
+
+        Ok(pruned)
+    }
+
+    fn try_ticket(&self, plate: &str, days: RangeInclusive<u32>) -> Result<bool, StorageError> {
+        let mut ticketed = self.ticketed.lock().unwrap();
+        if days
+            .clone()
+            .any(|day| ticketed.contains(&(plate.into(), day)))
+        {
+            return Ok(false);
+        }
+
+        ticketed.extend(days.map(|day| (plate.into(), day)));
+        Ok(true)
+    }
+
+    fn persist_tickets(&self, tickets: &[Ticket]) -> Result<(), StorageError> {
+        *self.undelivered.lock().unwrap() = tickets.to_vec();
+        Ok(())
+    }
+
+    fn take_tickets(&self) -> Result<Vec<Ticket>, StorageError> {
+        Ok(std::mem::take(&mut *self.undelivered.lock().unwrap()))
+    }
+}
+
+/// Keeps everything in a sled database, so a restarted daemon doesn't ticket a car twice
+pub struct SledStorage {
+    // road (u16 BE), plate length (u8), plate, camera (u16 BE), timestamp (u32 BE) => empty
+    records: sled::Tree,
+    // plate length (u8), plate, day (u32 BE) => empty
+    ticketed: sled::Tree,
+    // UNDELIVERED_KEY => a snapshot that only holds the undelivered tickets
+    undelivered: sled::Tree,
+    // makes the check and the marking of the ticketed days atomic
+    ticketing: Mutex<()>,
+}
+
+impl SledStorage {
+    pub fn open(path: &Path) -> Result<Self, StorageError> {
+        let db = sled::open(path)?;
+        let records = db.open_tree("observations")?;
+        migrate_records(&db, &records)?;
+
+        Ok(Self {
+            records,
+            ticketed: db.open_tree("ticketed")?,
+            undelivered: db.open_tree("undelivered")?,
+            ticketing: Mutex::default(),
+        })
+    }
+}
+
+const OLD_RECORDS_TREE: &str = "records";
+
+// older versions only kept the latest timestamp of every camera, under the layout of
+// road (u16 BE), plate length (u8), plate, camera (u16 BE) => timestamp (u32 BE),
+// their observations are moved over before the old tree is dropped
+fn migrate_records(db: &sled::Db, records: &sled::Tree) -> Result<(), StorageError> {
+    if !db
+        .tree_names()
+        .iter()
+        .any(|name| name == OLD_RECORDS_TREE.as_bytes())
+    {
+        return Ok(());
+    }
+
+    let old = db.open_tree(OLD_RECORDS_TREE)?;
+    for entry in old.iter() {
+        let (key, timestamp) = entry?;
+        let mut key = key.to_vec();
+        key.extend_from_slice(&timestamp);
+        records.insert(key, &[])?;
+    }
+    // a crash before the drop only repeats the migration on the next open
+    records.flush()?;
+    db.drop_tree(OLD_RECORDS_TREE)?;
+
+    Ok(())
+}
+
+const UNDELIVERED_KEY: &[u8] = b"tickets";
+
+// plates are at most 255 bytes long on the wire, so the length always fits
+fn plate_key(plate: &str) -> Vec<u8> {
+    let mut key = vec![plate.len() as u8];
+    key.extend_from_slice(plate.as_bytes());
+    key
+}
+
+// splits the camera (u16 BE) and the timestamp (u32 BE) that end the key of an observation
+fn observation_key(key: &[u8]) -> (CameraPosition, Timestamp) {
+    let camera = u16::from_be_bytes(key[..2].try_into().unwrap());
+    let timestamp = u32::from_be_bytes(key[2..].try_into().unwrap());
+    (camera, timestamp)
+}
+
+impl Storage for SledStorage {
+    fn observe(
+        &self,
+        road: Road,
+        plate: &str,
+        camera: CameraPosition,
+        timestamp: Timestamp,
+    ) -> Result<Vec<(CameraPosition, Timestamp)>, StorageError> {
+        let mut prefix = road.to_be_bytes().to_vec();
+        prefix.extend(plate_key(plate));
+
+        let mut key = prefix.clone();
+        key.extend_from_slice(&camera.to_be_bytes());
+        key.extend_from_slice(&timestamp.to_be_bytes());
+        if self.records.insert(key, &[])?.is_some() {
+            return Ok(vec![]);
+        }
+
+        self.records
+            .scan_prefix(&prefix)
+            .keys()
+            .map(|key| {
+                let (camera, timestamp) = observation_key(&key?[prefix.len()..]);
+                Ok((camera, timestamp))
+            })
+            .collect()
+    }
+
+    fn prune(&self, road: Road, before: Timestamp) -> Result<usize, StorageError> {
+        let mut pruned = 0;
+        for key in self.records.scan_prefix(road.to_be_bytes()).keys() {
+            let key = key?;
+            let (_, timestamp) = observation_key(&key[key.len() - 6..]);
+            if timestamp < before {
+                self.records.remove(key)?;
+                pruned += 1;
+            }
+        }
+
+        Ok(pruned)
+    }
+
+    fn try_ticket(&self, plate: &str, days: RangeInclusive<u32>) -> Result<bool, StorageError> {
+        let day_key = |day: u32| {
+            let mut key = plate_key(plate);
+            key.extend_from_slice(&day.to_be_bytes());
+            key
+        };
+
+        let _guard = self.ticketing.lock().unwrap();
+        for day in days.clone() {
+            if self.ticketed.contains_key(day_key(day))? {
+                return Ok(false);
+            }
+        }
+
+        for day in days {
+            self.ticketed.insert(day_key(day), &[])?;
+        }
+        // a ticket must never be issued twice, even if the daemon crashes right after
+        self.ticketed.flush()?;
+
+        Ok(true)
+    }
+
+    fn persist_tickets(&self, tickets: &[Ticket]) -> Result<(), StorageError> {
+        let snapshot = Snapshot {
+            pending_tickets: tickets.to_vec(),
+        };
+        self.undelivered
+            .insert(UNDELIVERED_KEY, snapshot.encode()?)?;
+        self.undelivered.flush()?;
+
+        Ok(())
+    }
+
+    fn take_tickets(&self) -> Result<Vec<Ticket>, StorageError> {
+        let Some(bytes) = self.undelivered.remove(UNDELIVERED_KEY)? else {
+            return Ok(vec![]);
+        };
+        self.undelivered.flush()?;
+
+        Ok(Snapshot::decode(&bytes)?.pending_tickets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::systems::ticket::Ticket;
+
+    use super::{MemoryStorage, SledStorage, Storage, StorageError, OLD_RECORDS_TREE};
+
+    // sled releases the lock of its directory from a background thread once a database is dropped,
+    // so opening it again right away may still find it locked (which sled only reports as a message)
+    fn open_unlocked<T>(open: impl Fn() -> Result<T, StorageError>) -> T {
+        for _ in 0..100 {
+            match open() {
+                Err(StorageError::Sled(sled::Error::Io(err)))
+                    if err.to_string().starts_with("could not acquire lock") =>
+                {
+                    std::thread::sleep(std::time::Duration::from_millis(10))
+                }
+                result => return result.unwrap(),
+            }
+        }
+
+        panic!("the database stayed locked");
+    }
+
+    fn check_storage(storage: &dyn Storage) {
+        storage.observe(1, "UN1X", 8, 0).unwrap();
+        storage.observe(2, "UN1X", 9, 10).unwrap();
+        let mut records = storage.observe(1, "UN1X", 9, 45).unwrap();
+        records.sort();
+        assert_eq!(records, [(8, 0), (9, 45)]);
+
+        // an observation is only stored once
+        assert_eq!(storage.observe(1, "UN1X", 9, 45).unwrap(), []);
+
+        // a camera keeps every observation, even one that arrives late
+        storage.observe(2, "UN1X", 9, 20).unwrap();
+        let mut records = storage.observe(2, "UN1X", 9, 15).unwrap();
+        records.sort();
+        assert_eq!(records, [(9, 10), (9, 15), (9, 20)]);
+
+        // only the road's own observations are pruned
+        assert_eq!(storage.prune(1, 45).unwrap(), 1);
+        assert_eq!(storage.prune(2, 15).unwrap(), 1);
+        let mut records = storage.observe(2, "UN1X", 8, 30).unwrap();
+        records.sort();
+        assert_eq!(records, [(8, 30), (9, 15), (9, 20)]);
+
+        assert!(storage.try_ticket("UN1X", 1..=2).unwrap());
+        assert!(!storage.try_ticket("UN1X", 2..=3).unwrap());
+        assert!(storage.try_ticket("UN1X", 3..=3).unwrap());
+        assert!(storage.try_ticket("RE05BKG", 1..=1).unwrap());
+
+        let tickets = [
+            Ticket::new("UN1X".into(), 1, 8, 0, 9, 45, 100),
+            Ticket::new("RE05BKG".into(), 2, 9, 10, 10, 20, 120),
+        ];
+        assert_eq!(storage.take_tickets().unwrap(), []);
+        storage.persist_tickets(&tickets[..1]).unwrap();
+        storage.persist_tickets(&tickets).unwrap();
+        assert_eq!(storage.take_tickets().unwrap(), tickets);
+        assert_eq!(storage.take_tickets().unwrap(), []);
+    }
+
+    #[test]
+    fn memory_storage() {
+        check_storage(&MemoryStorage::default());
+    }
+
+    #[test]
+    fn sled_storage_survives_restart() {
+        let path =
+            std::env::temp_dir().join(format!("speed-daemon-storage-{}", std::process::id()));
+
+        check_storage(&open_unlocked(|| SledStorage::open(&path)));
+
+        let reopened = open_unlocked(|| SledStorage::open(&path));
+        let records = reopened.observe(1, "UN1X", 10, 60).unwrap();
+        let ticketed = reopened.try_ticket("UN1X", 1..=1).unwrap();
+        let ticket = Ticket::new("UN1X".into(), 1, 8, 0, 9, 45, 100);
+        reopened
+            .persist_tickets(std::slice::from_ref(&ticket))
+            .unwrap();
+        drop(reopened);
+
+        let reopened = open_unlocked(|| SledStorage::open(&path));
+        let undelivered = reopened.take_tickets().unwrap();
+        drop(reopened);
+        std::fs::remove_dir_all(&path).unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert!(!ticketed);
+        assert_eq!(undelivered, [ticket]);
+    }
+
+    #[test]
+    fn sled_storage_migrates_old_records() {
+        let path =
+            std::env::temp_dir().join(format!("speed-daemon-migration-{}", std::process::id()));
+
+        // road 1, "UN1X" seen by the camera at mile 8 on timestamp 45
+        let db = sled::open(&path).unwrap();
+        let mut key = 1u16.to_be_bytes().to_vec();
+        key.push(4);
+        key.extend_from_slice(b"UN1X");
+        key.extend_from_slice(&8u16.to_be_bytes());
+        db.open_tree(OLD_RECORDS_TREE)
+            .unwrap()
+            .insert(key, &45u32.to_be_bytes())
+            .unwrap();
+        db.flush().unwrap();
+        drop(db);
+
+        let storage = open_unlocked(|| SledStorage::open(&path));
+        let mut records = storage.observe(1, "UN1X", 9, 90).unwrap();
+        records.sort();
+        drop(storage);
+
+        let db = open_unlocked(|| Ok(sled::open(&path)?));
+        let migrated = !db
+            .tree_names()
+            .iter()
+            .any(|name| name == OLD_RECORDS_TREE.as_bytes());
+        drop(db);
+        std::fs::remove_dir_all(&path).unwrap();
+
+        assert_eq!(records, [(8, 45), (9, 90)]);
+        assert!(migrated);
+    }
+}
@@ -1,27 +1,55 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    sync::atomic::{AtomicU64, Ordering},
+};
 
-use tokio::sync::mpsc;
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::{Instant, MissedTickBehavior},
+};
 
 use crate::protocol::message::ToClient;
 
-use super::Road;
+use super::{CameraPosition, Limit, Road, Timestamp};
 
 // Since this system is mostly used by internal systems,
 // we want to provide a big enough buffer that wouldn't stuck
 // other systems from doing their own work
 const SYSTEM_BUFFER_SIZE: usize = 1024;
 
+// how often a draining system checks whether the dispatchers have taken their tickets
+const DRAIN_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(10);
+// how often the pending tickets are offered again to the dispatchers of their road,
+// a dispatcher is skipped while its channel is full
+const RETRY_INTERVAL: std::time::Duration = std::time::Duration::from_millis(50);
+
+// the number of tickets that are waiting for a dispatcher of their road
+pub const PENDING_TICKETS: &str = "speed_daemon_pending_tickets";
+
 pub type DispatcherSender = mpsc::Sender<ToClient>;
 
-#[derive(Debug, Clone)]
+/// Identifies a single dispatcher connection across its registrations
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DispatcherId(u64);
+
+impl DispatcherId {
+    pub fn next() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        Self(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Ticket {
-    plate: String,
-    road: u16,
-    mile1: u16,
-    timestamp1: u32,
-    mile2: u16,
-    timestamp2: u32,
-    speed: u16,
+    pub(super) plate: String,
+    pub(super) road: u16,
+    pub(super) mile1: u16,
+    pub(super) timestamp1: u32,
+    pub(super) mile2: u16,
+    pub(super) timestamp2: u32,
+    pub(super) speed: u16,
 }
 
 impl Ticket {
@@ -44,6 +72,44 @@ impl Ticket {
             speed,
         }
     }
+
+    pub fn plate(&self) -> &str {
+        &self.plate
+    }
+
+    pub fn road(&self) -> Road {
+        self.road
+    }
+
+    /// The earlier of the two observations, as (mile, timestamp)
+    pub fn first(&self) -> (CameraPosition, Timestamp) {
+        (self.mile1, self.timestamp1)
+    }
+
+    /// The later of the two observations, as (mile, timestamp)
+    pub fn second(&self) -> (CameraPosition, Timestamp) {
+        (self.mile2, self.timestamp2)
+    }
+
+    pub fn speed(&self) -> Limit {
+        self.speed
+    }
+}
+
+impl fmt::Display for Ticket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "plate {} road {} mile1 {} timestamp1 {} mile2 {} timestamp2 {} speed {}",
+            self.plate,
+            self.road,
+            self.mile1,
+            self.timestamp1,
+            self.mile2,
+            self.timestamp2,
+            self.speed
+        )
+    }
 }
 
 impl From<Ticket> for ToClient {
@@ -58,15 +124,40 @@ impl From<Ticket> for ToClient {
     }
 }
 
+/// The outcome of a drain of the ticket system
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Drain {
+    // the tickets no dispatcher could take
+    pub undelivered: Vec<Ticket>,
+    // whether the dispatchers have taken every ticket they were handed before the deadline
+    pub flushed: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub pending_tickets: usize,
+    pub delivered_tickets: u64,
+    // the number of connected dispatchers for every road
+    pub dispatchers: HashMap<Road, usize>,
+}
+
 // Used for communication between the handler and the system
 enum InternalMessage {
     SubmitTicket(Ticket),
-    RegisterDispatcher(Vec<Road>, DispatcherSender),
+    RegisterDispatcher(DispatcherId, Vec<Road>, DispatcherSender),
+    PendingTickets(oneshot::Sender<Vec<Ticket>>),
+    Stats(oneshot::Sender<Stats>),
+    Drain(Instant, oneshot::Sender<Drain>),
 }
 
 pub struct System {
-    dispatchers: HashMap<Road, Vec<DispatcherSender>>,
+    dispatchers: HashMap<Road, Vec<(DispatcherId, DispatcherSender)>>,
+    // the roads every dispatcher is currently registered on
+    subscriptions: HashMap<DispatcherId, Vec<Road>>,
     pending_tickets: HashMap<Road, Vec<Ticket>>,
+    // the number of pending tickets over all of the roads
+    pending_count: usize,
+    delivered_tickets: u64,
 }
 
 impl System {
@@ -74,63 +165,240 @@ impl System {
     ///
     /// returns an handler that can be used to control the system
     ///
-    /// note: this function needs to be called from inside a tokio runtime context
-    pub fn start() -> Handler {
+    /// the system runs on a dedicated thread with its own runtime, so ticket delivery
+    /// never has to compete with camera ingestion over the workers of the main runtime.
+    pub fn start() -> std::io::Result<Handler> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+
+        let (handler, system) = Self::create();
+        std::thread::Builder::new()
+            .name("ticket-system".into())
+            .spawn(move || runtime.block_on(system))?;
+
+        Ok(handler)
+    }
+
+    /// Starts a new ticket system on the current runtime,
+    /// so tests don't need a dedicated thread for it
+    #[cfg(feature = "test-util")]
+    pub fn spawn() -> Handler {
+        let (handler, system) = Self::create();
+        tokio::spawn(system);
+
+        handler
+    }
+
+    // returns an handler and the future that runs the system
+    fn create() -> (Handler, impl Future<Output = ()>) {
         let (tx, mut rx) = mpsc::channel(SYSTEM_BUFFER_SIZE);
 
         let mut this = Self {
             dispatchers: HashMap::default(),
+            subscriptions: HashMap::default(),
             pending_tickets: HashMap::default(),
+            pending_count: 0,
+            delivered_tickets: 0,
         };
-        tokio::spawn(async move {
-            while let Some(message) = rx.recv().await {
+        let system = async move {
+            let mut retry = tokio::time::interval(RETRY_INTERVAL);
+            retry.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+            loop {
+                let message = tokio::select! {
+                    message = rx.recv() => match message {
+                        Some(message) => message,
+                        None => return,
+                    },
+                    _ = retry.tick() => {
+                        this.retry_pending();
+                        continue;
+                    }
+                };
+
                 match message {
-                    InternalMessage::RegisterDispatcher(roads, drx) => {
-                        this.register_dispatcher(roads, drx).await
+                    InternalMessage::RegisterDispatcher(id, roads, drx) => {
+                        this.register_dispatcher(id, roads, drx)
+                    }
+                    InternalMessage::SubmitTicket(ticket) => this.submit_ticket(ticket),
+                    InternalMessage::PendingTickets(response) => {
+                        let _ = response.send(this.pending_tickets());
+                    }
+                    InternalMessage::Stats(response) => {
+                        let _ = response.send(this.stats());
+                    }
+                    InternalMessage::Drain(deadline, response) => {
+                        let _ = response.send(this.drain(deadline).await);
                     }
-                    InternalMessage::SubmitTicket(ticket) => this.submit_ticket(ticket).await,
                 }
             }
-        });
+        };
 
-        Handler { sender: tx }
+        (Handler { sender: tx }, system)
     }
 
-    async fn register_dispatcher(&mut self, roads: Vec<Road>, drx: DispatcherSender) {
+    // registering an already registered dispatcher replaces the roads it's registered on
+    fn register_dispatcher(&mut self, id: DispatcherId, roads: Vec<Road>, drx: DispatcherSender) {
+        // drop the previous registration
+        for road in self.subscriptions.remove(&id).unwrap_or_default() {
+            if let Some(dispatchers) = self.dispatchers.get_mut(&road) {
+                dispatchers.retain(|(dispatcher, _)| *dispatcher != id);
+                if dispatchers.is_empty() {
+                    self.dispatchers.remove(&road);
+                }
+            }
+        }
+
         // register the dispatcher in the system
         for &road in roads.iter() {
-            self.dispatchers.entry(road).or_default().push(drx.clone());
+            self.dispatchers
+                .entry(road)
+                .or_default()
+                .push((id, drx.clone()));
         }
+        self.subscriptions.insert(id, roads.clone());
 
         // check if there are any pending tickets that the dispatcher can accept
         for road in roads {
-            if let Some(tickets) = self.pending_tickets.remove(&road) {
-                for ticket in tickets {
-                    // if the 'send' fails it means that the dispatcher just disconnected
-                    // in which case we can simply discard these tickets
-                    if drx.send(ticket.into()).await.is_err() {
-                        return;
-                    }
-                }
+            self.retry_road(road);
+        }
+    }
+
+    // tickets of a road are handed out in the order they were issued in,
+    // so a ticket waits behind the pending tickets of its road
+    fn submit_ticket(&mut self, ticket: Ticket) {
+        let road = ticket.road;
+        self.pending_tickets.entry(road).or_default().push(ticket);
+        self.set_pending_count(self.pending_count + 1);
+        self.retry_road(road);
+    }
+
+    // Hands a ticket to any of the dispatchers of its road without waiting for them,
+    // a dispatcher that doesn't read its tickets never holds the system up.
+    // returns the ticket when no dispatcher could take it
+    fn deliver(&mut self, ticket: Ticket) -> Result<(), Ticket> {
+        let dispatchers = self.dispatchers.get(&ticket.road).into_iter().flatten();
+        for (_, dispatcher) in dispatchers {
+            if dispatcher.try_send(ticket.clone().into()).is_ok() {
+                self.delivered_tickets += 1;
+                return Ok(());
             }
         }
+
+        Err(ticket)
     }
 
-    async fn submit_ticket(&mut self, ticket: Ticket) {
-        // try to submit the ticket to any of the registered dispatchers
-        if let Some(dispatchers) = self.dispatchers.get(&ticket.road) {
-            for dispatcher in dispatchers {
-                if dispatcher.send(ticket.clone().into()).await.is_ok() {
-                    return; // successfully submitted the ticket
-                }
+    // offers the pending tickets of the road to its dispatchers, until one isn't taken
+    fn retry_road(&mut self, road: Road) {
+        let Some(mut tickets) = self.pending_tickets.remove(&road) else {
+            return;
+        };
+
+        let mut delivered = 0;
+        for ticket in tickets.iter() {
+            if self.deliver(ticket.clone()).is_err() {
+                break;
             }
+            delivered += 1;
         }
 
-        // failed to submit the ticket, add it to a pending queue
-        self.pending_tickets
-            .entry(ticket.road)
-            .or_default()
-            .push(ticket);
+        tickets.drain(..delivered);
+        self.set_pending_count(self.pending_count - delivered);
+        if !tickets.is_empty() {
+            self.pending_tickets.insert(road, tickets);
+        }
+    }
+
+    // whether any pending ticket has a connected dispatcher that may take it
+    fn deliverable(&self) -> bool {
+        self.pending_tickets.keys().any(|road| {
+            self.dispatchers
+                .get(road)
+                .into_iter()
+                .flatten()
+                .any(|(_, dispatcher)| !dispatcher.is_closed())
+        })
+    }
+
+    fn retry_pending(&mut self) {
+        let roads: Vec<_> = self
+            .pending_tickets
+            .keys()
+            .filter(|road| self.dispatchers.contains_key(road))
+            .copied()
+            .collect();
+        for road in roads {
+            self.retry_road(road);
+        }
+    }
+
+    // Keeps offering the pending tickets to the dispatchers, and waits for the connected
+    // dispatchers to take every ticket they were handed, until the deadline
+    //
+    // the tickets that are still pending are taken out of the system,
+    // a ticket that was handed to a dispatcher that didn't take it is lost along with the dispatcher
+    async fn drain(&mut self, deadline: Instant) -> Drain {
+        // the writer of a dispatcher empties its channel as it writes the tickets out
+        let handed_out = |dispatcher: &DispatcherSender| {
+            !dispatcher.is_closed() && dispatcher.capacity() < dispatcher.max_capacity()
+        };
+
+        let flushed = loop {
+            self.retry_pending();
+            let flushed = !self
+                .dispatchers
+                .values()
+                .flatten()
+                .any(|(_, dispatcher)| handed_out(dispatcher));
+            if Instant::now() >= deadline || (flushed && !self.deliverable()) {
+                break flushed;
+            }
+
+            tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+        };
+
+        let undelivered = self.pending_tickets();
+        self.pending_tickets.clear();
+        self.set_pending_count(0);
+        Drain {
+            undelivered,
+            flushed,
+        }
+    }
+
+    // keeps the gauge in step with the count, rather than counting the tickets of every road
+    fn set_pending_count(&mut self, count: usize) {
+        self.pending_count = count;
+        metrics::gauge!(PENDING_TICKETS).set(count as f64);
+    }
+
+    // ordered by road, and then by the order the tickets were issued in
+    fn pending_tickets(&self) -> Vec<Ticket> {
+        let mut roads: Vec<_> = self.pending_tickets.keys().collect();
+        roads.sort_unstable();
+
+        roads
+            .into_iter()
+            .flat_map(|road| self.pending_tickets[road].iter().cloned())
+            .collect()
+    }
+
+    fn stats(&self) -> Stats {
+        Stats {
+            pending_tickets: self.pending_count,
+            delivered_tickets: self.delivered_tickets,
+            // dispatchers are never unregistered, so the disconnected ones are skipped here
+            dispatchers: self
+                .dispatchers
+                .iter()
+                .map(|(road, dispatchers)| {
+                    let connected = dispatchers.iter().filter(|(_, d)| !d.is_closed()).count();
+                    (*road, connected)
+                })
+                .filter(|(_, connected)| *connected > 0)
+                .collect(),
+        }
     }
 }
 
@@ -147,17 +415,90 @@ impl Handler {
             .expect("the system should live as long as the handler does");
     }
 
+    /// Registers the dispatcher on the roads,
+    /// replacing the roads it was registered on before under the same id
     pub async fn register_dispatcher(
         &mut self,
+        id: DispatcherId,
         roads: Vec<Road>,
         dispatcher_channel: DispatcherSender,
     ) {
         self.sender
             .send(InternalMessage::RegisterDispatcher(
+                id,
                 roads,
                 dispatcher_channel,
             ))
             .await
             .expect("the system should live as long as the handler does");
     }
+
+    pub async fn pending_tickets(&self) -> Vec<Ticket> {
+        let (tx, rx) = oneshot::channel();
+        self.sender
+            .send(InternalMessage::PendingTickets(tx))
+            .await
+            .expect("the system should live as long as the handler does");
+
+        rx.await.expect("the system always responds to queries")
+    }
+
+    /// Flushes the tickets to the connected dispatchers until the deadline,
+    /// the tickets that couldn't be delivered are taken out of the system
+    pub async fn drain(&self, deadline: Instant) -> Drain {
+        let (tx, rx) = oneshot::channel();
+        self.sender
+            .send(InternalMessage::Drain(deadline, tx))
+            .await
+            .expect("the system should live as long as the handler does");
+
+        rx.await.expect("the system always responds to queries")
+    }
+
+    pub async fn stats(&self) -> Stats {
+        let (tx, rx) = oneshot::channel();
+        self.sender
+            .send(InternalMessage::Stats(tx))
+            .await
+            .expect("the system should live as long as the handler does");
+
+        rx.await.expect("the system always responds to queries")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc;
+
+    use super::{DispatcherId, System, Ticket};
+
+    fn ticket(road: u16) -> Ticket {
+        Ticket::new("UN1X".into(), road, 100, 123456, 110, 123816, 100)
+    }
+
+    #[tokio::test]
+    async fn reregister_dispatcher_on_other_roads() {
+        let (mut handler, system) = System::create();
+        tokio::spawn(system);
+
+        let id = DispatcherId::next();
+        let (tx, mut rx) = mpsc::channel(8);
+        handler
+            .register_dispatcher(id, vec![1, 2], tx.clone())
+            .await;
+        handler.register_dispatcher(id, vec![2, 3], tx).await;
+
+        handler.submit_ticket(ticket(1)).await;
+        handler.submit_ticket(ticket(3)).await;
+
+        // the ticket of the dropped road waits for another dispatcher
+        assert_eq!(rx.recv().await.unwrap(), ticket(3).into());
+        assert_eq!(handler.pending_tickets().await, [ticket(1)]);
+
+        let stats = handler.stats().await;
+        assert_eq!(stats.pending_tickets, 1);
+        assert_eq!(stats.delivered_tickets, 1);
+        assert_eq!(stats.dispatchers.get(&1), None);
+        assert_eq!(stats.dispatchers.get(&2), Some(&1));
+    }
 }
@@ -0,0 +1,104 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use speed_daemon::protocol::{
+    deserializer::{Decoder, Deserialize},
+    message::FromClient,
+};
+
+// counts every allocation made by the test binary,
+// this file holds a single test so nothing else allocates while it measures
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+// the distinct cars driving past the camera
+const FLEET: usize = 500;
+// every car is observed this many times
+const PASSES: u32 = 20;
+
+// the plate observations of a camera that sees the same fleet of cars over and over
+fn camera_load() -> Vec<u8> {
+    let mut stream = Vec::new();
+    for pass in 0..PASSES {
+        for car in 0..FLEET {
+            let plate = format!("CAR{:04}", car);
+            stream.push(0x20);
+            stream.push(plate.len() as u8);
+            stream.extend_from_slice(plate.as_bytes());
+            stream.extend_from_slice(&(pass * 3600 + car as u32).to_be_bytes());
+        }
+    }
+
+    stream
+}
+
+fn allocations() -> usize {
+    ALLOCATIONS.load(Ordering::Relaxed)
+}
+
+#[tokio::test]
+async fn plates_are_deserialized_without_allocating() {
+    let stream = camera_load();
+    let observations = FLEET * PASSES as usize;
+
+    // the generic deserializer allocates at least once per observation
+    let mut reader = stream.as_slice();
+    let mut messages = Vec::with_capacity(observations);
+    let before = allocations();
+    for _ in 0..observations {
+        messages.push(FromClient::deserialize(&mut reader).await.unwrap());
+    }
+    let generic = allocations() - before;
+    assert!(
+        generic >= observations,
+        "{} allocations for {} observations",
+        generic,
+        observations
+    );
+
+    // the first pass over the fleet interns its plates
+    let mut decoder = Decoder::default();
+    let mut reader = stream.as_slice();
+    let mut decoded = Vec::with_capacity(observations);
+    for _ in 0..FLEET {
+        decoded.push(decoder.deserialize(&mut reader).await.unwrap());
+    }
+
+    // from then on, no observation allocates
+    let before = allocations();
+    for _ in FLEET..observations {
+        decoded.push(decoder.deserialize(&mut reader).await.unwrap());
+    }
+    let fast = allocations() - before;
+    assert_eq!(
+        fast,
+        0,
+        "{} allocations for {} observations of known plates",
+        fast,
+        observations - FLEET
+    );
+
+    assert_eq!(decoded, messages);
+}
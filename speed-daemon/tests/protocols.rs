@@ -0,0 +1,143 @@
+use std::{net::SocketAddr, time::Duration};
+
+use speed_daemon::{
+    client::{self, Protocol},
+    systems::{record, ticket},
+    SharedSystems,
+};
+use throttle::{Limits, Throttle};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+// serves the clients of a single protocol, on an ephemeral port
+async fn listen(systems: SharedSystems, protocol: Protocol) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (conn, _) = listener.accept().await.unwrap();
+            tokio::spawn(client::handle(conn, systems.clone(), false, protocol));
+        }
+    });
+
+    addr
+}
+
+async fn json_camera(addr: SocketAddr, mile: u16) -> BufReader<TcpStream> {
+    let mut camera = TcpStream::connect(addr).await.unwrap();
+    let line = format!(
+        r#"{{"type":"IAmCamera","road":66,"mile":{},"limit":60}}"#,
+        mile
+    );
+    camera
+        .write_all(format!("{}\n", line).as_bytes())
+        .await
+        .unwrap();
+
+    BufReader::new(camera)
+}
+
+async fn report(camera: &mut BufReader<TcpStream>, plate: &str, timestamp: u32) {
+    let line = format!(
+        r#"{{"type":"Plate","plate":"{}","timestamp":{}}}"#,
+        plate, timestamp
+    );
+    camera
+        .get_mut()
+        .write_all(format!("{}\n", line).as_bytes())
+        .await
+        .unwrap();
+}
+
+// the plates reported over JSON are ticketed to dispatchers that speak the binary protocol
+#[tokio::test]
+async fn json_cameras_feed_binary_dispatchers() {
+    let ticket_system = ticket::System::spawn();
+    let record_system = record::System::start_in_memory(ticket_system.clone());
+    let systems = SharedSystems::new(ticket_system, record_system);
+    let binary = listen(systems.clone(), Protocol::Binary).await;
+    let json = listen(systems, Protocol::Json).await;
+
+    let mut dispatcher = TcpStream::connect(binary).await.unwrap();
+    dispatcher.write_all(&[0x81, 1, 0, 66]).await.unwrap();
+
+    // a plate that can't be sent over the binary protocol is refused
+    let mut camera = json_camera(json, 0).await;
+    report(&mut camera, &"A".repeat(256), 0).await;
+    let mut error = String::new();
+    camera.read_line(&mut error).await.unwrap();
+    assert!(error.contains(r#""type":"Error""#), "{}", error);
+
+    // while the longest plate the binary protocol can carry is ticketed
+    let plate = "B".repeat(255);
+    let mut first = json_camera(json, 0).await;
+    let mut second = json_camera(json, 10).await;
+    report(&mut first, &plate, 0).await;
+    report(&mut second, &plate, 60).await;
+
+    let mut expected = vec![0x21, 255];
+    expected.extend_from_slice(plate.as_bytes());
+    expected.extend_from_slice(&66u16.to_be_bytes());
+    expected.extend_from_slice(&0u16.to_be_bytes());
+    expected.extend_from_slice(&0u32.to_be_bytes());
+    expected.extend_from_slice(&10u16.to_be_bytes());
+    expected.extend_from_slice(&60u32.to_be_bytes());
+    expected.extend_from_slice(&60000u16.to_be_bytes());
+
+    let mut ticket = vec![0; expected.len()];
+    tokio::time::timeout(Duration::from_secs(5), dispatcher.read_exact(&mut ticket))
+        .await
+        .expect("the dispatcher should receive a ticket")
+        .unwrap();
+    assert_eq!(ticket, expected);
+}
+
+// a client that has gone without asking for heartbeats gives its connection back
+#[tokio::test]
+async fn release_the_connections_of_clients_that_hung_up() {
+    let ticket_system = ticket::System::spawn();
+    let record_system = record::System::start_in_memory(ticket_system.clone());
+    let systems = SharedSystems::new(ticket_system, record_system);
+
+    let throttle = Throttle::new(Limits {
+        max_connections: Some(1),
+        ..Default::default()
+    });
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (conn, _, permit) = throttle.accept(&listener).await.unwrap();
+            tokio::spawn(permit.hold(client::handle(
+                conn,
+                systems.clone(),
+                false,
+                Protocol::Binary,
+            )));
+        }
+    });
+
+    let mut camera = TcpStream::connect(addr).await.unwrap();
+    camera
+        .write_all(&[0x80, 0, 66, 0, 10, 0, 60])
+        .await
+        .unwrap();
+    drop(camera);
+
+    // until the camera's connection is torn down, the next one is turned away
+    let admitted = async {
+        loop {
+            let mut client = TcpStream::connect(addr).await.unwrap();
+            client.write_all(&[0x40, 0, 0, 0, 1]).await.unwrap();
+            if let Ok(0x41) = client.read_u8().await {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(5), admitted)
+        .await
+        .expect("the camera should have given its connection back");
+}
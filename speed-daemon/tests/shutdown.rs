@@ -0,0 +1,133 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use speed_daemon::{
+    protocol::message::ToClient,
+    shutdown::{Coordinator, Drained},
+    systems::{
+        policy::Policy,
+        record,
+        storage::{MemoryStorage, SharedStorage},
+        ticket::{self, DispatcherId, Ticket},
+        Road,
+    },
+};
+use tokio::sync::mpsc;
+
+const GRACE: Duration = Duration::from_secs(5);
+
+// a car that speeds between the two cameras of the road at 600 mph
+async fn speed_on(record_system: &record::Handler, road: Road, plate: &str) {
+    let mut first = record_system.clone().register_camera(road, 60).await;
+    let mut second = record_system.clone().register_camera(road, 60).await;
+    first.submit_record(0, plate.into(), 0).await;
+    second.submit_record(10, plate.into(), 60).await;
+}
+
+fn speeding_ticket(road: Road, plate: &str) -> Ticket {
+    Ticket::new(plate.into(), road, 0, 0, 10, 60, 600)
+}
+
+#[tokio::test]
+async fn flush_and_persist_tickets_on_shutdown() {
+    let storage: SharedStorage = Arc::new(MemoryStorage::default());
+    let mut ticket_system = ticket::System::spawn();
+    let record_system =
+        record::System::start(ticket_system.clone(), storage.clone(), Policy::default());
+
+    // a slow dispatcher, that takes its tickets one at a time
+    let (dispatcher, mut tickets) = mpsc::channel(1);
+    ticket_system
+        .register_dispatcher(DispatcherId::next(), vec![1], dispatcher)
+        .await;
+    let taken = Arc::new(Mutex::new(vec![]));
+    tokio::spawn({
+        let taken = taken.clone();
+        async move {
+            while let Some(ticket) = tickets.recv().await {
+                taken.lock().unwrap().push(ticket);
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        }
+    });
+
+    // tickets on a road that has a dispatcher, and one on a road that doesn't
+    for idx in 0..5 {
+        speed_on(&record_system, 1, &format!("CAR{}", idx)).await;
+    }
+    speed_on(&record_system, 2, "LOST").await;
+
+    let coordinator = Coordinator::new(
+        record_system.clone(),
+        ticket_system.clone(),
+        storage.clone(),
+        GRACE,
+    );
+    assert_eq!(
+        coordinator.drain().await.unwrap(),
+        Drained {
+            persisted: 1,
+            flushed: true,
+        }
+    );
+
+    // every ticket was taken by the dispatcher before the drain was over
+    let expected: Vec<ToClient> = (0..5)
+        .map(|idx| speeding_ticket(1, &format!("CAR{}", idx)).into())
+        .collect();
+    assert_eq!(*taken.lock().unwrap(), expected);
+
+    // records submitted during the shutdown are ignored
+    speed_on(&record_system, 2, "LATE").await;
+    assert_eq!(ticket_system.pending_tickets().await, []);
+
+    // the next run delivers the persisted ticket
+    let next_run = ticket::System::spawn();
+    let mut coordinator = Coordinator::new(
+        record::System::start_in_memory(next_run.clone()),
+        next_run.clone(),
+        storage,
+        GRACE,
+    );
+    assert_eq!(coordinator.restore().await.unwrap(), 1);
+    assert_eq!(
+        next_run.pending_tickets().await,
+        [speeding_ticket(2, "LOST")]
+    );
+}
+
+#[tokio::test]
+async fn persist_the_tickets_of_stuck_dispatchers() {
+    let storage: SharedStorage = Arc::new(MemoryStorage::default());
+    let mut ticket_system = ticket::System::spawn();
+    let record_system =
+        record::System::start(ticket_system.clone(), storage.clone(), Policy::default());
+
+    // a dispatcher that never takes its tickets, the second ticket doesn't fit in its channel
+    let (dispatcher, _tickets) = mpsc::channel(1);
+    ticket_system
+        .register_dispatcher(DispatcherId::next(), vec![1], dispatcher)
+        .await;
+    speed_on(&record_system, 1, "CAR0").await;
+    speed_on(&record_system, 1, "CAR1").await;
+
+    let coordinator = Coordinator::new(
+        record_system,
+        ticket_system,
+        storage.clone(),
+        Duration::from_millis(100),
+    );
+    assert_eq!(
+        coordinator.drain().await.unwrap(),
+        Drained {
+            persisted: 1,
+            flushed: false,
+        }
+    );
+    assert_eq!(
+        storage.take_tickets().unwrap(),
+        [speeding_ticket(1, "CAR1")]
+    );
+}
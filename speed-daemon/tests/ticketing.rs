@@ -0,0 +1,345 @@
+use std::collections::{hash_map::Entry, HashMap, HashSet};
+
+use proptest::prelude::*;
+use speed_daemon::systems::{
+    record::{self, DAY_IN_SECS},
+    ticket::{self, Ticket},
+    CameraPosition, Limit, Road, Timestamp,
+};
+
+const LIMIT: Limit = 60;
+
+#[derive(Debug, Clone)]
+struct Observation {
+    plate: String,
+    road: Road,
+    mile: CameraPosition,
+    timestamp: Timestamp,
+}
+
+impl Observation {
+    fn new(plate: &str, road: Road, mile: CameraPosition, timestamp: Timestamp) -> Self {
+        Self {
+            plate: plate.into(),
+            road,
+            mile,
+            timestamp,
+        }
+    }
+}
+
+// runs the observations through fresh systems, every road has the same limit,
+// no dispatcher ever connects, so every issued ticket stays pending
+//
+// each observation is settled before the next one is submitted,
+// so which road tickets a day first doesn't depend on how the roads are scheduled
+async fn issue_tickets(observations: &[Observation], limit: Limit) -> Vec<Ticket> {
+    let ticket_system = ticket::System::spawn();
+    let record_system = record::System::start_in_memory(ticket_system.clone());
+
+    let mut cameras = HashMap::new();
+    for observation in observations {
+        let camera = match cameras.entry(observation.road) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(
+                record_system
+                    .clone()
+                    .register_camera(observation.road, limit)
+                    .await,
+            ),
+        };
+
+        camera
+            .submit_record(
+                observation.mile,
+                observation.plate.as_str().into(),
+                observation.timestamp,
+            )
+            .await;
+        record_system.settled().await;
+    }
+
+    ticket_system.pending_tickets().await
+}
+
+fn issue_tickets_blocking(observations: &[Observation], limit: Limit) -> Vec<Ticket> {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(issue_tickets(observations, limit))
+}
+
+// the same computation the road workers do, rounded to the nearest mile per hour
+fn speed(first: (CameraPosition, Timestamp), second: (CameraPosition, Timestamp)) -> Option<u64> {
+    let distance = first.0.abs_diff(second.0);
+    let time = first.1.abs_diff(second.1) as f64 / 60f64 / 60f64;
+    if time == 0.0 || distance == 0 {
+        return None;
+    }
+
+    Some((distance as f64 / time).round() as u64)
+}
+
+// the ticketing rule in exact integer arithmetic, a car is speeding once its speed
+// reaches the limit plus the default tolerance of half a mile per hour.
+// returns the speed rounded half up, along with whether it's speeding
+fn reference_speed(distance: u64, secs: u64, limit: Limit) -> (u64, bool) {
+    let speed = (2 * distance * 3600 + secs) / (2 * secs);
+    let speeding = 2 * distance * 3600 >= (2 * limit as u64 + 1) * secs;
+    (speed, speeding)
+}
+
+fn days(ticket: &Ticket) -> impl Iterator<Item = u32> {
+    (ticket.first().1 / DAY_IN_SECS)..=(ticket.second().1 / DAY_IN_SECS)
+}
+
+#[test]
+fn round_speeds_to_the_nearest_mph() {
+    let tickets = issue_tickets_blocking(
+        &[
+            // 60.5 mph
+            Observation::new("ROUNDUP", 1, 0, 0),
+            Observation::new("ROUNDUP", 1, 121, 7200),
+            // 60.4 mph
+            Observation::new("ROUNDDOWN", 1, 0, 0),
+            Observation::new("ROUNDDOWN", 1, 302, 18000),
+            // exactly the limit
+            Observation::new("LIMIT", 1, 0, 0),
+            Observation::new("LIMIT", 1, 60, 3600),
+        ],
+        LIMIT,
+    );
+
+    assert_eq!(tickets.len(), 1);
+    assert_eq!(tickets[0].plate(), "ROUNDUP");
+    assert_eq!(tickets[0].speed(), 61);
+}
+
+#[test]
+fn ticket_once_per_day_across_roads() {
+    let tickets = issue_tickets_blocking(
+        &[
+            // spans the first two days
+            Observation::new("UN1X", 1, 0, DAY_IN_SECS - 10),
+            Observation::new("UN1X", 1, 1, DAY_IN_SECS + 10),
+            // on the second day, but on another road
+            Observation::new("UN1X", 2, 0, DAY_IN_SECS + 1000),
+            Observation::new("UN1X", 2, 1, DAY_IN_SECS + 1020),
+            // on the third day
+            Observation::new("UN1X", 2, 5, 2 * DAY_IN_SECS + 1000),
+            Observation::new("UN1X", 2, 6, 2 * DAY_IN_SECS + 1020),
+        ],
+        LIMIT,
+    );
+
+    let tickets: Vec<_> = tickets
+        .iter()
+        .map(|ticket| (ticket.road(), ticket.first(), ticket.second()))
+        .collect();
+    assert_eq!(
+        tickets,
+        [
+            (1, (0, DAY_IN_SECS - 10), (1, DAY_IN_SECS + 10)),
+            (2, (5, 2 * DAY_IN_SECS + 1000), (6, 2 * DAY_IN_SECS + 1020)),
+        ]
+    );
+}
+
+#[test]
+fn repeated_observations_are_ticketed_once() {
+    let tickets = issue_tickets_blocking(
+        &[
+            Observation::new("UN1X", 1, 0, 0),
+            Observation::new("UN1X", 1, 10, 60),
+            // cameras may send the same observation again
+            Observation::new("UN1X", 1, 10, 60),
+            Observation::new("UN1X", 1, 0, 0),
+            // the same pair, a day later
+            Observation::new("UN1X", 1, 0, DAY_IN_SECS),
+            Observation::new("UN1X", 1, 10, DAY_IN_SECS + 60),
+            Observation::new("UN1X", 1, 10, DAY_IN_SECS + 60),
+        ],
+        LIMIT,
+    );
+
+    let tickets: Vec<_> = tickets
+        .iter()
+        .map(|ticket| (ticket.first(), ticket.second()))
+        .collect();
+    assert_eq!(
+        tickets,
+        [
+            ((0, 0), (10, 60)),
+            ((0, DAY_IN_SECS), (10, DAY_IN_SECS + 60)),
+        ]
+    );
+}
+
+#[test]
+fn late_observations_of_a_camera_are_kept() {
+    let tickets = issue_tickets_blocking(
+        &[
+            // the camera at mile 0 sees the car on its way back, on the next day,
+            // before the observations of its way out arrive
+            Observation::new("RE05BKG", 1, 0, 0),
+            Observation::new("RE05BKG", 1, 0, DAY_IN_SECS + 600),
+            Observation::new("RE05BKG", 1, 10, 60),
+        ],
+        LIMIT,
+    );
+
+    let tickets: Vec<_> = tickets
+        .iter()
+        .map(|ticket| (ticket.first(), ticket.second(), ticket.speed()))
+        .collect();
+    assert_eq!(tickets, [((0, 0), (10, 60), 600)]);
+}
+
+// observations cluster around the day boundaries, so tickets often span two days
+fn observations() -> impl Strategy<Value = Vec<Observation>> {
+    let observation = (0..3usize, 1..=2u16, 0..30u16, 0..3u32, 0..1200u32).prop_map(
+        |(plate, road, mile, day, secs)| {
+            let plate = ["UN1X", "RE05BKG", "SPEED"][plate];
+            Observation::new(plate, road, mile, day * DAY_IN_SECS + 85800 + secs)
+        },
+    );
+
+    // a camera may see a plate several times, and every pair of observations is still checked
+    prop::collection::vec(observation, 0..40)
+}
+
+proptest! {
+    #[test]
+    fn tickets_hold_the_invariants(observations in observations()) {
+        let tickets = issue_tickets_blocking(&observations, LIMIT);
+
+        let observed: HashSet<_> = observations
+            .iter()
+            .map(|o| (o.plate.as_str(), o.road, o.mile, o.timestamp))
+            .collect();
+
+        let mut ticketed_days = HashSet::new();
+        for ticket in &tickets {
+            let (first, second) = (ticket.first(), ticket.second());
+
+            // a ticket is made of two real observations of a speeding car
+            prop_assert!(observed.contains(&(ticket.plate(), ticket.road(), first.0, first.1)));
+            prop_assert!(observed.contains(&(ticket.plate(), ticket.road(), second.0, second.1)));
+            prop_assert!(first.1 <= second.1);
+            prop_assert_eq!(speed(first, second), Some(ticket.speed() as u64));
+            prop_assert!(ticket.speed() > LIMIT);
+
+            // a plate is ticketed at most once per day
+            for day in days(ticket) {
+                prop_assert!(ticketed_days.insert((ticket.plate().to_string(), day)));
+            }
+        }
+
+        // a speeding plate gets at least one ticket, as long as its speed fits in one
+        for a in &observations {
+            for b in &observations {
+                let speeding = a.plate == b.plate
+                    && a.road == b.road
+                    && speed((a.mile, a.timestamp), (b.mile, b.timestamp))
+                        .is_some_and(|speed| speed > LIMIT as u64 && speed <= u16::MAX as u64);
+                if speeding {
+                    prop_assert!(tickets.iter().any(|ticket| ticket.plate() == a.plate));
+                }
+            }
+        }
+    }
+}
+
+// a pair of observations of a single car, seen in either order,
+// from the same second up to several days apart
+fn camera_pair() -> impl Strategy<Value = (Vec<Observation>, Limit)> {
+    let span = prop_oneof![
+        Just(0u32),
+        1..60u32,
+        60..DAY_IN_SECS,
+        DAY_IN_SECS..4 * DAY_IN_SECS
+    ];
+    (
+        0..1000u16,
+        0..1000u16,
+        0..DAY_IN_SECS,
+        span,
+        1..=150u16,
+        any::<bool>(),
+    )
+        .prop_filter(
+            "the speed must fit in a ticket",
+            |(mile1, mile2, _, span, _, _)| {
+                *span == 0
+                    || mile1.abs_diff(*mile2) as u64 * 3600 / (*span as u64) < u16::MAX as u64
+            },
+        )
+        .prop_map(|(mile1, mile2, timestamp, span, limit, reversed)| {
+            let mut observations = vec![
+                Observation::new("PAIR", 1, mile1, timestamp),
+                Observation::new("PAIR", 1, mile2, timestamp + span),
+            ];
+            if reversed {
+                observations.reverse();
+            }
+            (observations, limit)
+        })
+}
+
+// observations over several days, in any order
+fn multi_day_observations() -> impl Strategy<Value = Vec<Observation>> {
+    let observation = (0..2usize, 1..=3u16, 0..50u16, 0..4 * DAY_IN_SECS).prop_map(
+        |(plate, road, mile, timestamp)| {
+            let plate = ["UN1X", "RE05BKG"][plate];
+            Observation::new(plate, road, mile, timestamp)
+        },
+    );
+
+    prop::collection::vec(observation, 0..40)
+        .prop_map(|observations| {
+            let mut seen = HashSet::new();
+            observations
+                .into_iter()
+                .filter(|o| seen.insert((o.plate.clone(), o.road, o.mile)))
+                .collect::<Vec<_>>()
+        })
+        .prop_shuffle()
+}
+
+proptest! {
+    #[test]
+    fn speed_matches_the_reference((observations, limit) in camera_pair()) {
+        let tickets = issue_tickets_blocking(&observations, limit);
+
+        let (first, second) = (&observations[0], &observations[1]);
+        let distance = first.mile.abs_diff(second.mile) as u64;
+        let secs = first.timestamp.abs_diff(second.timestamp) as u64;
+        if distance == 0 || secs == 0 {
+            prop_assert!(tickets.is_empty());
+            return Ok(());
+        }
+
+        let (speed, speeding) = reference_speed(distance, secs, limit);
+        if speeding {
+            prop_assert_eq!(tickets.len(), 1);
+            prop_assert_eq!(tickets[0].speed() as u64, speed);
+            prop_assert!(tickets[0].first().1 <= tickets[0].second().1);
+        } else {
+            prop_assert!(tickets.is_empty());
+        }
+    }
+
+    #[test]
+    fn ticket_once_per_day_in_any_order(observations in multi_day_observations()) {
+        let tickets = issue_tickets_blocking(&observations, LIMIT);
+
+        let mut ticketed_days = HashSet::new();
+        for ticket in &tickets {
+            prop_assert!(ticket.speed() > LIMIT);
+            for day in days(ticket) {
+                prop_assert!(ticketed_days.insert((ticket.plate().to_string(), day)));
+            }
+        }
+    }
+}
@@ -0,0 +1,159 @@
+//! Logging shared by all of the servers
+//!
+//! [`init`] installs a subscriber that logs to stdout, filtered by `RUST_LOG` (info by default).
+//! every connection task runs through [`connection`] (or [`listener_connection`]), so its events
+//! carry the same context and its lifecycle is logged (and counted, see [`stats`]) the same way
+//! on every server.
+use std::{
+    fmt::Display,
+    future::Future,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use metrics::Label;
+use tracing::Instrument;
+use tracing_subscriber::EnvFilter;
+
+pub mod stats;
+
+const DEFAULT_FILTER: &str = "info";
+
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Installs the global tracing subscriber
+///
+/// the filter follows the `RUST_LOG` syntax, e.g. `RUST_LOG=info,job_centre=debug`
+///
+/// note: panics if a global subscriber was already installed
+pub fn init() {
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER));
+    tracing_subscriber::fmt().with_env_filter(filter).init();
+}
+
+/// Runs a connection task in a span that carries the peer and a connection id
+///
+/// the connection is logged once it opens and once the task completes,
+/// along with the error the task has failed with (if any)
+pub async fn connection<F>(peer: impl Display, task: F) -> F::Output
+where
+    F: Future,
+    F::Output: Outcome,
+{
+    run_connection(peer, Vec::new(), task).await
+}
+
+/// Runs a connection task like [`connection`],
+/// for a server with several listeners, whose connection metrics are labeled with the listener
+pub async fn listener_connection<F>(
+    listener: impl Into<String>,
+    peer: impl Display,
+    task: F,
+) -> F::Output
+where
+    F: Future,
+    F::Output: Outcome,
+{
+    run_connection(peer, vec![Label::new("listener", listener.into())], task).await
+}
+
+async fn run_connection<F>(peer: impl Display, labels: Vec<Label>, task: F) -> F::Output
+where
+    F: Future,
+    F::Output: Outcome,
+{
+    let id = NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed);
+    let span = tracing::info_span!("connection", id, peer = %peer);
+
+    async move {
+        tracing::info!("connection opened");
+        metrics::counter!(stats::CONNECTIONS, labels.clone()).increment(1);
+        let _active = Active::open(labels.clone());
+
+        let output = task.await;
+        match output.error() {
+            Some(err) => {
+                tracing::warn!(error = %err, "connection closed");
+                metrics::counter!(stats::CONNECTION_ERRORS, labels).increment(1);
+            }
+            None => tracing::info!("connection closed"),
+        }
+
+        output
+    }
+    .instrument(span)
+    .await
+}
+
+// counts a connection as active until it's dropped, even if its task is cancelled
+struct Active {
+    labels: Vec<Label>,
+}
+
+impl Active {
+    fn open(labels: Vec<Label>) -> Self {
+        metrics::gauge!(stats::ACTIVE_CONNECTIONS, labels.clone()).increment(1);
+        Self { labels }
+    }
+}
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        metrics::gauge!(stats::ACTIVE_CONNECTIONS, self.labels.clone()).decrement(1);
+    }
+}
+
+/// The result of a connection task
+pub trait Outcome {
+    /// The error the task has failed with
+    fn error(&self) -> Option<String>;
+}
+
+impl Outcome for () {
+    fn error(&self) -> Option<String> {
+        None
+    }
+}
+
+impl<T, E: Display> Outcome for Result<T, E> {
+    fn error(&self) -> Option<String> {
+        // the alternate form includes the causes of an anyhow error
+        self.as_ref().err().map(|err| format!("{:#}", err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt;
+
+    use super::{connection, listener_connection, Outcome};
+
+    #[derive(Debug)]
+    struct Failed;
+
+    impl fmt::Display for Failed {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "failed")
+        }
+    }
+
+    #[tokio::test]
+    async fn pass_the_output_through() {
+        let output = connection("127.0.0.1:3600", async { Err::<(), _>(Failed) }).await;
+        assert_eq!(output.error().as_deref(), Some("failed"));
+
+        assert_eq!(
+            connection("peer", async { Ok::<_, Failed>(7) })
+                .await
+                .unwrap(),
+            7
+        );
+        assert_eq!(
+            listener_connection("public", "peer", async { Ok::<_, Failed>(7) })
+                .await
+                .unwrap(),
+            7
+        );
+        assert_eq!(().error(), None);
+    }
+}
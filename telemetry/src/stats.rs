@@ -0,0 +1,165 @@
+//! Metrics shared by all of the servers, served for prometheus to scrape
+//!
+//! every server counts its connections and the bytes that pass through them the same way,
+//! on top of the requests (and errors) of its own protocol, labeled by their type
+use std::{
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use metrics::Counter;
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+// the connection metrics of a server with several listeners are labeled by the listener
+
+// the connections that are currently open
+pub const ACTIVE_CONNECTIONS: &str = "protohackers_active_connections";
+// the connections that were ever opened
+pub const CONNECTIONS: &str = "protohackers_connections_total";
+// the connections that were closed by an error
+pub const CONNECTION_ERRORS: &str = "protohackers_connection_errors_total";
+pub const RECEIVED_BYTES: &str = "protohackers_received_bytes_total";
+pub const SENT_BYTES: &str = "protohackers_sent_bytes_total";
+// the handled requests, labeled by their type
+pub const REQUESTS: &str = "protohackers_requests_total";
+// the requests that were rejected, labeled by the reason
+pub const ERRORS: &str = "protohackers_errors_total";
+
+// 10us up to ~40s, every bucket is 4 times the previous one
+const DURATION_BUCKETS: &[f64] = &[
+    0.00001, 0.00004, 0.00016, 0.00064, 0.00256, 0.01024, 0.04096, 0.16384, 0.65536, 2.62144,
+    10.48576, 41.94304,
+];
+
+/// Installs the global metrics recorder, and serves the metrics for prometheus to scrape
+///
+/// note: this function needs to be called from inside a tokio runtime context
+pub fn install(addr: &str) -> Result<(), String> {
+    install_with_buckets(addr, DURATION_BUCKETS)
+}
+
+/// Like [`install`], for a server that buckets its durations (the `_seconds` histograms) its own way
+pub fn install_with_buckets(addr: &str, duration_buckets: &[f64]) -> Result<(), String> {
+    let addr: SocketAddr = addr
+        .parse()
+        .map_err(|err| format!("bad metrics address {}: {}", addr, err))?;
+
+    PrometheusBuilder::new()
+        .with_http_listener(addr)
+        .set_buckets_for_metric(Matcher::Suffix("_seconds".into()), duration_buckets)
+        .and_then(|builder| builder.install())
+        .map_err(|err| err.to_string())?;
+
+    tracing::info!("Metrics are served on: {}", addr);
+    Ok(())
+}
+
+/// Counts a request of the type
+pub fn request(kind: &'static str) {
+    metrics::counter!(REQUESTS, "type" => kind).increment(1);
+}
+
+/// Counts a request that was rejected for the reason
+pub fn error(reason: &'static str) {
+    metrics::counter!(ERRORS, "reason" => reason).increment(1);
+}
+
+/// Counts the bytes of a datagram that was received, streams are counted by [`Metered`]
+pub fn received(bytes: usize) {
+    metrics::counter!(RECEIVED_BYTES).increment(bytes as u64);
+}
+
+/// Counts the bytes of a datagram that was sent, streams are counted by [`Metered`]
+pub fn sent(bytes: usize) {
+    metrics::counter!(SENT_BYTES).increment(bytes as u64);
+}
+
+/// A stream that counts the bytes that are read from it and written into it
+#[derive(Debug)]
+pub struct Metered<S> {
+    inner: S,
+    received: Counter,
+    sent: Counter,
+}
+
+impl<S> Metered<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            received: metrics::counter!(RECEIVED_BYTES),
+            sent: metrics::counter!(SENT_BYTES),
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Metered<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll {
+            this.received
+                .increment((buf.filled().len() - before) as u64);
+        }
+
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Metered<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = poll {
+            this.sent.increment(written as u64);
+        }
+
+        poll
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::Metered;
+
+    #[tokio::test]
+    async fn pass_the_data_through() {
+        let (client, server) = tokio::io::duplex(64);
+        let (mut client, mut server) = (Metered::new(client), Metered::new(server));
+
+        client.write_all(b"hello").await.unwrap();
+        client.shutdown().await.unwrap();
+        let mut received = String::new();
+        server.read_to_string(&mut received).await.unwrap();
+        assert_eq!(received, "hello");
+    }
+}
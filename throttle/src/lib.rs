@@ -0,0 +1,404 @@
+//! Connection limits shared by all of the TCP servers
+//!
+//! [`Throttle::accept`] stands in for `TcpListener::accept`: it caps the number of connections
+//! that are open at once, and the rate every IP may open new ones at. the connections it turns away
+//! get the overload policy, so a server only ever sees the connections it should serve.
+//!
+//! the limits are read from the environment by [`Limits::from_env`], and are all disabled by default:
+//! - `MAX_CONNECTIONS`: the number of connections that may be open at once
+//! - `IP_CONNECTION_RATE`: the number of connections per second a single IP may open
+//! - `IP_CONNECTION_BURST`: the number of connections an IP may open in a single burst,
+//!   a single second worth of connections by default
+//! - `OVERLOAD_RESPONSE`: a line that is sent to the connections that are turned away before
+//!   they're closed, they're closed right away when it isn't set
+use std::{
+    collections::HashMap,
+    env, fmt,
+    future::Future,
+    io,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpListener, TcpStream},
+};
+
+// how often the buckets of the IPs that went quiet are forgotten
+const SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+// a connection that is turned away doesn't get to hold on to the server for longer than this
+const OVERLOAD_RESPONSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rate {
+    // connections per second
+    pub rate: f64,
+    // the number of connections that may be opened in a single burst
+    pub burst: f64,
+}
+
+/// What the connections that are turned away get
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Overload {
+    #[default]
+    Close,
+    // the line is sent before the connection is closed
+    Respond(String),
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Limits {
+    pub max_connections: Option<usize>,
+    pub per_ip: Option<Rate>,
+    pub overload: Overload,
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("bad value for {name}: {reason}")]
+pub struct ConfigError {
+    name: &'static str,
+    reason: String,
+}
+
+impl Limits {
+    /// Reads the limits from the environment, any limit whose variable isn't set is disabled
+    pub fn from_env() -> Result<Self, ConfigError> {
+        let per_ip = match read_var::<f64>("IP_CONNECTION_RATE")? {
+            Some(rate) => Some(Rate::new(rate, read_var("IP_CONNECTION_BURST")?)?),
+            None => None,
+        };
+
+        Ok(Self {
+            max_connections: read_var("MAX_CONNECTIONS")?,
+            per_ip,
+            overload: match env::var("OVERLOAD_RESPONSE") {
+                Ok(mut line) => {
+                    if !line.ends_with('\n') {
+                        line.push('\n');
+                    }
+                    Overload::Respond(line)
+                }
+                Err(_) => Overload::Close,
+            },
+        })
+    }
+}
+
+impl Rate {
+    // a single second worth of connections is the default burst,
+    // the time it takes to refill a whole burst must fit in a duration
+    fn new(rate: f64, burst: Option<f64>) -> Result<Self, ConfigError> {
+        if !rate.is_finite() || rate <= 0.0 {
+            return Err(bad_value("IP_CONNECTION_RATE", "must be positive"));
+        }
+
+        let burst = burst.unwrap_or(rate).max(1.0);
+        if Duration::try_from_secs_f64(burst / rate).is_err() {
+            return Err(bad_value(
+                "IP_CONNECTION_BURST",
+                "takes too long to refill at IP_CONNECTION_RATE",
+            ));
+        }
+
+        Ok(Self { rate, burst })
+    }
+}
+
+fn read_var<T>(name: &'static str) -> Result<Option<T>, ConfigError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    match env::var(name) {
+        Ok(value) => value.parse().map(Some).map_err(|err| bad_value(name, err)),
+        Err(_) => Ok(None),
+    }
+}
+
+fn bad_value(name: &'static str, reason: impl fmt::Display) -> ConfigError {
+    ConfigError {
+        name,
+        reason: reason.to_string(),
+    }
+}
+
+/// Why a connection was turned away
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    TooManyConnections,
+    RateLimited,
+}
+
+impl Rejection {
+    // the label the rejection is counted under
+    fn label(self) -> &'static str {
+        match self {
+            Self::TooManyConnections => "too-many-connections",
+            Self::RateLimited => "rate-limited",
+        }
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooManyConnections => write!(f, "too many open connections"),
+            Self::RateLimited => write!(f, "opening connections too fast"),
+        }
+    }
+}
+
+/// An admitted connection, it counts towards the open connections until it's dropped
+#[derive(Debug)]
+pub struct Permit(Arc<AtomicUsize>);
+
+impl Permit {
+    /// Holds on to the permit until the connection task is done
+    pub async fn hold<F: Future>(self, task: F) -> F::Output {
+        let _permit = self;
+        task.await
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+#[derive(Debug)]
+struct Buckets {
+    buckets: HashMap<IpAddr, Bucket>,
+    swept: Instant,
+}
+
+/// Admits connections within the limits, a single throttle may be shared by several listeners
+#[derive(Debug)]
+pub struct Throttle {
+    limits: Limits,
+    open: Arc<AtomicUsize>,
+    buckets: Mutex<Buckets>,
+}
+
+impl Throttle {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            open: Arc::default(),
+            buckets: Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                swept: Instant::now(),
+            }),
+        }
+    }
+
+    /// Accepts the next connection that is within the limits,
+    /// the overload policy is applied to every connection that is turned away in the meantime
+    pub async fn accept(
+        &self,
+        listener: &TcpListener,
+    ) -> io::Result<(TcpStream, SocketAddr, Permit)> {
+        loop {
+            let (conn, addr) = listener.accept().await?;
+            match self.admit(addr.ip()) {
+                Ok(permit) => return Ok((conn, addr, permit)),
+                Err(rejection) => {
+                    tracing::debug!("turned {} away: {}", addr, rejection);
+                    telemetry::stats::error(rejection.label());
+                    self.turn_away(conn);
+                }
+            }
+        }
+    }
+
+    /// Admits a new connection from the IP, unless it's beyond one of the limits
+    pub fn admit(&self, peer: IpAddr) -> Result<Permit, Rejection> {
+        self.admit_at(peer, Instant::now())
+    }
+
+    fn admit_at(&self, peer: IpAddr, now: Instant) -> Result<Permit, Rejection> {
+        // a connection that is rate limited doesn't take a token, so the open ones are checked first
+        let max = self.limits.max_connections.unwrap_or(usize::MAX);
+        self.open
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |open| {
+                (open < max).then_some(open + 1)
+            })
+            .map_err(|_| Rejection::TooManyConnections)?;
+        let permit = Permit(self.open.clone());
+
+        if let Some(rate) = self.limits.per_ip {
+            if !self.take_token(rate, peer, now) {
+                return Err(Rejection::RateLimited);
+            }
+        }
+
+        Ok(permit)
+    }
+
+    // takes a token from the bucket of the IP, returns false if the bucket is empty
+    fn take_token(&self, rate: Rate, peer: IpAddr, now: Instant) -> bool {
+        let mut buckets = self.buckets.lock().unwrap();
+        if now.saturating_duration_since(buckets.swept) >= SWEEP_INTERVAL {
+            // a bucket that has been refilled to the top is just like the fresh one
+            // a returning IP would get
+            let refill_time = Duration::from_secs_f64(rate.burst / rate.rate);
+            buckets
+                .buckets
+                .retain(|_, bucket| now.saturating_duration_since(bucket.refilled) < refill_time);
+            buckets.swept = now;
+        }
+
+        let bucket = buckets.buckets.entry(peer).or_insert(Bucket {
+            tokens: rate.burst,
+            refilled: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.refilled).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate.rate).min(rate.burst);
+        bucket.refilled = now;
+
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    fn turn_away(&self, mut conn: TcpStream) {
+        let Overload::Respond(line) = &self.limits.overload else {
+            return; // dropping the connection closes it
+        };
+
+        let line = line.clone();
+        tokio::spawn(tokio::time::timeout(
+            OVERLOAD_RESPONSE_TIMEOUT,
+            async move {
+                conn.write_all(line.as_bytes()).await?;
+                conn.shutdown().await
+            },
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::IpAddr,
+        time::{Duration, Instant},
+    };
+
+    use tokio::{
+        io::AsyncReadExt,
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::{Limits, Overload, Rate, Rejection, Throttle};
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn cap_open_connections() {
+        let throttle = Throttle::new(Limits {
+            max_connections: Some(2),
+            ..Default::default()
+        });
+
+        let first = throttle.admit(ip("10.0.0.1")).unwrap();
+        let _second = throttle.admit(ip("10.0.0.2")).unwrap();
+        assert_eq!(
+            throttle.admit(ip("10.0.0.3")).unwrap_err(),
+            Rejection::TooManyConnections
+        );
+
+        // a connection that was closed makes room for a new one
+        drop(first);
+        assert!(throttle.admit(ip("10.0.0.3")).is_ok());
+    }
+
+    #[test]
+    fn limit_the_rate_of_every_ip() {
+        let throttle = Throttle::new(Limits {
+            per_ip: Some(Rate {
+                rate: 10.0,
+                burst: 3.0,
+            }),
+            ..Default::default()
+        });
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            throttle.admit_at(ip("10.0.0.1"), now).unwrap();
+        }
+        assert_eq!(
+            throttle.admit_at(ip("10.0.0.1"), now).unwrap_err(),
+            Rejection::RateLimited
+        );
+        // every IP has its own bucket
+        assert!(throttle.admit_at(ip("10.0.0.2"), now).is_ok());
+
+        // a token is refilled every 100ms
+        let later = now + Duration::from_millis(100);
+        assert!(throttle.admit_at(ip("10.0.0.1"), later).is_ok());
+        assert!(throttle.admit_at(ip("10.0.0.1"), later).is_err());
+
+        // a rate limited connection isn't counted as open
+        assert_eq!(throttle.open.load(std::sync::atomic::Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn reject_bad_rates() {
+        for rate in [0.0, -1.0, f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            assert!(Rate::new(rate, None).is_err(), "{}", rate);
+        }
+        // a whole burst would take longer to refill than a duration can hold
+        assert!(Rate::new(f64::MIN_POSITIVE, None).is_err());
+        assert!(Rate::new(1.0, Some(f64::INFINITY)).is_err());
+
+        assert_eq!(
+            Rate::new(0.5, None).unwrap(),
+            Rate {
+                rate: 0.5,
+                burst: 1.0
+            }
+        );
+        // a NaN burst falls back to a single connection
+        assert_eq!(Rate::new(2.0, Some(f64::NAN)).unwrap().burst, 1.0);
+    }
+
+    #[tokio::test]
+    async fn respond_to_turned_away_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let throttle = Throttle::new(Limits {
+            max_connections: Some(1),
+            overload: Overload::Respond("busy\n".into()),
+            ..Default::default()
+        });
+
+        let _admitted = TcpStream::connect(addr).await.unwrap();
+        let (_conn, _, _permit) = throttle.accept(&listener).await.unwrap();
+
+        let mut turned_away = TcpStream::connect(addr).await.unwrap();
+        let accept = tokio::spawn(async move {
+            let _ = throttle.accept(&listener).await;
+        });
+        let mut response = String::new();
+        turned_away.read_to_string(&mut response).await.unwrap();
+        assert_eq!(response, "busy\n");
+        accept.abort();
+    }
+}
@@ -0,0 +1,154 @@
+//: A packet-flood load generator used to benchmark the server's dispatch modes.
+//:
+//: usage: flood [target] [seconds] [sockets] [shards]
+//:
+//: every socket sends a stream of inserts followed by retrieves of the same keys,
+//: the number of answered retrieves tells how many requests the server managed to handle.
+//: with several shards, shard n is at the port that follows the one of shard n - 1,
+//: and every key is sent to its shard.
+
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use tokio::net::UdpSocket;
+use unusual_database_program::shard::Ring;
+
+const DEFAULT_TARGET: &str = "127.0.0.1:3606";
+const DEFAULT_DURATION_SECS: u64 = 5;
+const DEFAULT_SOCKETS: usize = 8;
+const DEFAULT_SHARDS: u16 = 1;
+
+// how long to keep listening for late responses once the flood has stopped
+const DRAIN_TIMEOUT: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Default)]
+struct Counters {
+    sent: AtomicU64,
+    answered: AtomicU64,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let target: SocketAddr = args
+        .next()
+        .unwrap_or_else(|| DEFAULT_TARGET.into())
+        .parse()?;
+    let duration = Duration::from_secs(
+        args.next()
+            .map(|secs| secs.parse())
+            .transpose()?
+            .unwrap_or(DEFAULT_DURATION_SECS),
+    );
+    let sockets = args
+        .next()
+        .map(|count| count.parse())
+        .transpose()?
+        .unwrap_or(DEFAULT_SOCKETS);
+    let shards = args
+        .next()
+        .map(|count| count.parse())
+        .transpose()?
+        .unwrap_or(DEFAULT_SHARDS)
+        .max(1);
+
+    let targets = (0..shards)
+        .map(|shard| {
+            let port = target
+                .port()
+                .checked_add(shard)
+                .ok_or_else(|| anyhow::anyhow!("there is no port for shard {}", shard))?;
+            Ok(SocketAddr::new(target.ip(), port))
+        })
+        .collect::<anyhow::Result<_>>()?;
+    let ring = Arc::new(Ring::new(targets));
+
+    println!(
+        "flooding {} shard(s) from {} for {:?} using {} sockets",
+        shards, target, duration, sockets
+    );
+
+    let counters = Arc::new(Counters::default());
+    let start = Instant::now();
+
+    let mut tasks = Vec::with_capacity(sockets);
+    for id in 0..sockets {
+        tasks.push(tokio::spawn(flood(
+            id,
+            ring.clone(),
+            duration,
+            counters.clone(),
+        )));
+    }
+    for task in tasks {
+        task.await??;
+    }
+
+    let elapsed = start.elapsed().as_secs_f64();
+    let sent = counters.sent.load(Ordering::Relaxed);
+    let answered = counters.answered.load(Ordering::Relaxed);
+    println!(
+        "sent: {} ({:.0}/s), answered retrieves: {} ({:.0}/s)",
+        sent,
+        sent as f64 / elapsed,
+        answered,
+        answered as f64 / elapsed
+    );
+
+    Ok(())
+}
+
+async fn flood(
+    id: usize,
+    ring: Arc<Ring<SocketAddr>>,
+    duration: Duration,
+    counters: Arc<Counters>,
+) -> anyhow::Result<()> {
+    let socket = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+
+    let deadline = Instant::now() + duration;
+
+    // count every answer until the flood is over and no answer arrived for the drain timeout
+    let receiver = {
+        let socket = socket.clone();
+        let counters = counters.clone();
+        tokio::spawn(async move {
+            let mut packet = [0; 1024];
+            loop {
+                let wait = deadline.saturating_duration_since(Instant::now()) + DRAIN_TIMEOUT;
+                match tokio::time::timeout(wait, socket.recv_from(&mut packet)).await {
+                    Ok(Ok(_)) => counters.answered.fetch_add(1, Ordering::Relaxed),
+                    _ => break,
+                };
+            }
+        })
+    };
+
+    let mut counter: u64 = 0;
+    while Instant::now() < deadline {
+        let key = format!("flood-{}-{}", id, (counter / 2) % 1024);
+        let target = *ring.shard(&key).expect("there is at least one shard");
+        let request = match counter % 2 {
+            0 => format!("{}={}", key, counter),
+            _ => key,
+        };
+
+        socket.send_to(request.as_bytes(), target).await?;
+        counters.sent.fetch_add(1, Ordering::Relaxed);
+        counter += 1;
+
+        // let other tasks (and the receiver) make progress
+        if counter.is_multiple_of(64) {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    receiver.await?;
+    Ok(())
+}
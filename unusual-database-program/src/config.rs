@@ -0,0 +1,224 @@
+use std::{env, path::PathBuf, str::FromStr, time::Duration};
+
+use crate::{db::Budget, limiter::Limit};
+
+const DEFAULT_WORKERS: usize = 4;
+const DEFAULT_WORKER_QUEUE_SIZE: usize = 1024;
+const DEFAULT_SNAPSHOT_INTERVAL: Duration = Duration::from_secs(60);
+const DEFAULT_DIGEST_INTERVAL: Duration = Duration::from_secs(5);
+
+// How incoming datagrams are handed off to request handlers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dispatch {
+    // a fixed set of workers, each fed by a bounded queue
+    Pool,
+    // a new task for every datagram
+    Spawn,
+}
+
+impl FromStr for Dispatch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pool" => Ok(Self::Pool),
+            "spawn" => Ok(Self::Spawn),
+            _ => Err(format!("unknown dispatch mode: {}", s)),
+        }
+    }
+}
+
+// Where to replicate the store to
+#[derive(Debug, Clone)]
+pub struct Replication {
+    // the address replication messages are received on
+    pub addr: String,
+    // the replication address of the peer instance
+    pub peer: String,
+    // how often a digest of the store is sent to the peer
+    pub digest_interval: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    // the number of independent shards, each on a port and with a store of its own
+    pub shards: usize,
+    pub dispatch: Dispatch,
+    pub workers: usize,
+    pub worker_queue_size: usize,
+    pub budget: Budget,
+    // when set, the store is persisted into this directory
+    pub data_dir: Option<PathBuf>,
+    pub snapshot_interval: Duration,
+    // when set, the store is also served over TCP on this address
+    pub tcp_addr: Option<String>,
+    // when set, datagrams above the limit are dropped per client
+    pub rate_limit: Option<Limit>,
+    // when set, the store is replicated with a peer instance
+    pub replication: Option<Replication>,
+    // when set, the metrics are served for prometheus to scrape on this address
+    pub metrics_addr: Option<String>,
+    // when set, a retrieve of `prefix*` scans every key with the prefix,
+    // off by default since the keys that end with `*` can't be retrieved then
+    pub prefix_scans: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            shards: 1,
+            dispatch: Dispatch::Pool,
+            workers: DEFAULT_WORKERS,
+            worker_queue_size: DEFAULT_WORKER_QUEUE_SIZE,
+            budget: Budget::default(),
+            data_dir: None,
+            snapshot_interval: DEFAULT_SNAPSHOT_INTERVAL,
+            tcp_addr: None,
+            rate_limit: None,
+            replication: None,
+            metrics_addr: None,
+            prefix_scans: false,
+        }
+    }
+}
+
+impl Config {
+    /// Builds the configuration from the environment,
+    /// falling back to the defaults for any variable that isn't set
+    pub fn from_env() -> anyhow::Result<Self> {
+        let default = Self::default();
+
+        let snapshot_interval = read_var("SNAPSHOT_INTERVAL_SECS")?
+            .map(Duration::from_secs)
+            .unwrap_or(default.snapshot_interval);
+        if snapshot_interval.is_zero() {
+            anyhow::bail!("SNAPSHOT_INTERVAL_SECS must be positive");
+        }
+
+        Ok(Self {
+            shards: read_var("SHARDS")?.unwrap_or(default.shards).max(1),
+            dispatch: read_var("DISPATCH")?.unwrap_or(default.dispatch),
+            workers: read_var("WORKERS")?.unwrap_or(default.workers).max(1),
+            worker_queue_size: read_var("WORKER_QUEUE_SIZE")?
+                .unwrap_or(default.worker_queue_size)
+                .max(1),
+            budget: Budget {
+                max_entries: read_var("MAX_ENTRIES")?,
+                max_bytes: read_var("MAX_BYTES")?,
+            },
+            data_dir: read_var("DATA_DIR")?,
+            snapshot_interval,
+            tcp_addr: read_var("TCP_ADDR")?,
+            rate_limit: read_rate_limit()?,
+            replication: read_replication()?,
+            metrics_addr: read_var("METRICS_ADDR")?,
+            prefix_scans: read_var("PREFIX_SCANS")?.unwrap_or(default.prefix_scans),
+        })
+    }
+
+    /// The directory the store of a shard is persisted into,
+    /// a single shard uses the data directory itself
+    pub fn shard_dir(&self, shard: usize) -> Option<PathBuf> {
+        let dir = self.data_dir.as_ref()?;
+        match self.shards {
+            1 => Some(dir.clone()),
+            _ => Some(dir.join(format!("shard-{}", shard))),
+        }
+    }
+}
+
+// both addresses must be set to enable replication
+fn read_replication() -> anyhow::Result<Option<Replication>> {
+    match (read_var("REPLICATION_ADDR")?, read_var("REPLICATION_PEER")?) {
+        (Some(addr), Some(peer)) => Ok(Some(Replication {
+            addr,
+            peer,
+            digest_interval: match read_var("DIGEST_INTERVAL_SECS")? {
+                Some(secs) => positive_secs("DIGEST_INTERVAL_SECS", secs)?,
+                None => DEFAULT_DIGEST_INTERVAL,
+            },
+        })),
+        (None, None) => Ok(None),
+        _ => anyhow::bail!("REPLICATION_ADDR and REPLICATION_PEER must be set together"),
+    }
+}
+
+// a fractional number of seconds, that must make a non-zero duration
+fn positive_secs(name: &str, secs: f64) -> anyhow::Result<Duration> {
+    match Duration::try_from_secs_f64(secs) {
+        Ok(duration) if !duration.is_zero() => Ok(duration),
+        _ => anyhow::bail!(
+            "bad value for {}: must be a positive number of seconds",
+            name
+        ),
+    }
+}
+
+fn read_rate_limit() -> anyhow::Result<Option<Limit>> {
+    match read_var("RATE_LIMIT")? {
+        Some(rate) => Ok(Some(rate_limit(rate, read_var("RATE_BURST")?)?)),
+        None => Ok(None),
+    }
+}
+
+// the burst defaults to a single second worth of datagrams,
+// the time it takes to refill a whole burst must fit in a duration
+fn rate_limit(rate: f64, burst: Option<f64>) -> anyhow::Result<Limit> {
+    if !rate.is_finite() || rate <= 0.0 {
+        anyhow::bail!("bad value for RATE_LIMIT: must be positive");
+    }
+
+    let burst = burst.unwrap_or(rate).max(1.0);
+    if Duration::try_from_secs_f64(burst / rate).is_err() {
+        anyhow::bail!("bad value for RATE_BURST: takes too long to refill at RATE_LIMIT");
+    }
+
+    Ok(Limit { rate, burst })
+}
+
+fn read_var<T>(name: &str) -> anyhow::Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|err| anyhow::anyhow!("bad value for {}: {}", name, err)),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(err) => Err(anyhow::anyhow!("bad value for {}: {}", name, err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{positive_secs, rate_limit};
+
+    #[test]
+    fn reject_bad_intervals() {
+        assert_eq!(
+            positive_secs("INTERVAL", 0.5).unwrap(),
+            Duration::from_millis(500)
+        );
+        for secs in [0.0, -1.0, 1e-12, f64::NAN, f64::INFINITY, f64::MAX] {
+            assert!(positive_secs("INTERVAL", secs).is_err(), "{}", secs);
+        }
+    }
+
+    #[test]
+    fn reject_bad_rate_limits() {
+        let limit = rate_limit(10.0, None).unwrap();
+        assert_eq!((limit.rate, limit.burst), (10.0, 10.0));
+        let limit = rate_limit(0.5, Some(3.0)).unwrap();
+        assert_eq!((limit.rate, limit.burst), (0.5, 3.0));
+
+        for rate in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(rate_limit(rate, None).is_err(), "{}", rate);
+        }
+        assert!(rate_limit(1.0, Some(f64::INFINITY)).is_err());
+        assert!(rate_limit(f64::MIN_POSITIVE, Some(f64::MAX)).is_err());
+    }
+}
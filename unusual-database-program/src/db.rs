@@ -1,22 +1,370 @@
-use dashmap::DashMap;
+use std::{
+    collections::BTreeMap,
+    io,
+    ops::Bound,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use crate::persistence::Wal;
 
 static RESERVED_KEYS: phf::Map<&'static str, &'static str> = phf::phf_map! {
     "version" => "Ken's Key-Value Store 1.0",
 };
 
+/// Limits on the amount of data the store may hold,
+/// once a limit is reached the least recently used entries are evicted
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Budget {
+    pub max_entries: Option<usize>,
+    // counts the length of both keys and values
+    pub max_bytes: Option<usize>,
+}
+
+#[derive(Debug, Default)]
+pub struct Stats {
+    evictions: AtomicU64,
+    // inserts that could never fit within the budget
+    rejections: AtomicU64,
+}
+
+impl Stats {
+    pub fn evictions(&self) -> u64 {
+        self.evictions.load(Ordering::Relaxed)
+    }
+
+    pub fn rejections(&self) -> u64 {
+        self.rejections.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    value: String,
+    // the tick in which the entry was last used
+    used: u64,
+}
+
 #[derive(Debug, Default)]
-pub struct KeyValue(DashMap<String, String>);
+struct Inner {
+    // ordered by key, so prefix scans are a single range lookup
+    entries: BTreeMap<String, Entry>,
+    // maps tick -> key, ordered from the least to the most recently used
+    recency: BTreeMap<u64, String>,
+    tick: u64,
+    bytes: usize,
+}
+
+impl Inner {
+    // marks the entry as the most recently used one
+    fn touch(&mut self, key: &str) {
+        let Some(entry) = self.entries.get_mut(key) else {
+            return;
+        };
+
+        self.recency.remove(&entry.used);
+        self.tick += 1;
+        entry.used = self.tick;
+        self.recency.insert(self.tick, key.to_string());
+    }
+
+    fn evict_least_recently_used(&mut self) -> bool {
+        let Some((_, key)) = self.recency.pop_first() else {
+            return false;
+        };
+
+        if let Some(entry) = self.entries.remove(&key) {
+            self.bytes -= key.len() + entry.value.len();
+        }
+
+        true
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct KeyValue {
+    inner: Mutex<Inner>,
+    budget: Budget,
+    stats: Stats,
+    // only set when persistence is enabled
+    wal: Option<Mutex<Wal>>,
+}
 
 impl KeyValue {
+    pub fn with_budget(budget: Budget) -> Self {
+        Self {
+            budget,
+            ..Default::default()
+        }
+    }
+
+    /// Creates a persistent store, restoring its previous state from the data directory
+    pub fn open(budget: Budget, dir: &Path) -> io::Result<Self> {
+        let (wal, entries) = Wal::open(dir)?;
+
+        let mut this = Self::with_budget(budget);
+        for (key, value) in entries {
+            this.apply(key, value);
+        }
+        this.wal = Some(Mutex::new(wal));
+
+        Ok(this)
+    }
+
+    /// Whether inserts are written to a log on disk
+    pub fn is_persistent(&self) -> bool {
+        self.wal.is_some()
+    }
+
+    /// Compacts the write-ahead log into a snapshot of the current entries
+    ///
+    /// does nothing when persistence is disabled
+    pub fn snapshot(&self) -> io::Result<()> {
+        let Some(wal) = &self.wal else {
+            return Ok(());
+        };
+
+        // inserts are blocked on the log until the snapshot is written,
+        // but reads can proceed once the entries are copied
+        let mut wal = wal.lock().unwrap();
+        let entries = self.entries();
+
+        wal.compact(
+            entries
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.as_str())),
+        )
+    }
+
+    /// Returns every stored entry ordered by key, without marking any of them as used
+    ///
+    /// reserved keys are left out, since they can't be changed anyway
+    pub fn entries(&self) -> Vec<(String, String)> {
+        self.inner
+            .lock()
+            .unwrap()
+            .entries
+            .iter()
+            .filter(|(key, _)| !RESERVED_KEYS.contains_key(key.as_str()))
+            .map(|(key, entry)| (key.clone(), entry.value.clone()))
+            .collect()
+    }
+
     pub fn get(&self, key: &str) -> Option<String> {
         if let Some(value) = RESERVED_KEYS.get(key) {
             return Some(value.to_string());
         }
 
-        self.0.get(key).map(|value| value.to_owned())
+        let mut inner = self.inner.lock().unwrap();
+        let value = inner.entries.get(key).map(|entry| entry.value.clone())?;
+        inner.touch(key);
+
+        Some(value)
+    }
+
+    /// Returns the value of the key, without marking it as used
+    pub fn peek(&self, key: &str) -> Option<String> {
+        let inner = self.inner.lock().unwrap();
+        inner.entries.get(key).map(|entry| entry.value.clone())
+    }
+
+    /// Returns every entry whose key starts with the prefix, ordered by key
+    pub fn scan(&self, prefix: &str) -> Vec<(String, String)> {
+        let mut matches: Vec<_> = RESERVED_KEYS
+            .entries()
+            .filter(|(key, _)| key.starts_with(prefix))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+
+        let mut inner = self.inner.lock().unwrap();
+        let found: Vec<_> = inner
+            .entries
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .filter(|(key, _)| !RESERVED_KEYS.contains_key(key.as_str()))
+            .map(|(key, entry)| (key.clone(), entry.value.clone()))
+            .collect();
+
+        // scanned entries count as used, just like retrieved ones
+        for (key, _) in &found {
+            inner.touch(key);
+        }
+        drop(inner);
+
+        matches.extend(found);
+        matches.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        matches
+    }
+
+    pub fn set(&self, key: String, value: String) -> io::Result<()> {
+        // a rejected insert changes nothing, so there's nothing to log
+        if self.is_oversize(&key, &value) {
+            return Ok(());
+        }
+
+        // keep the log locked while applying the insert,
+        // so the order of the log always matches the order of the inserts
+        let mut wal = self.wal.as_ref().map(|wal| wal.lock().unwrap());
+        if let Some(wal) = &mut wal {
+            wal.append(&key, &value)?;
+        }
+
+        self.apply(key, value);
+        Ok(())
+    }
+
+    // whether the entry can't fit even in an empty store, such an insert is rejected
+    fn is_oversize(&self, key: &str, value: &str) -> bool {
+        let oversize = self
+            .budget
+            .max_bytes
+            .is_some_and(|max_bytes| key.len() + value.len() > max_bytes);
+        if oversize {
+            self.stats.rejections.fetch_add(1, Ordering::Relaxed);
+        }
+
+        oversize
+    }
+
+    fn apply(&self, key: String, value: String) {
+        // the budget may have shrunk since the entry was logged
+        if self.is_oversize(&key, &value) {
+            return;
+        }
+
+        let size = key.len() + value.len();
+
+        let mut inner = self.inner.lock().unwrap();
+        match inner.entries.get_mut(&key) {
+            Some(entry) => {
+                let old_size = entry.value.len();
+                entry.value = value;
+                inner.bytes = inner.bytes - old_size + size - key.len();
+            }
+            None => {
+                inner.entries.insert(key.clone(), Entry { value, used: 0 });
+                inner.bytes += size;
+            }
+        }
+        inner.touch(&key);
+
+        // make room by evicting the least recently used entries,
+        // the entry we've just inserted is the most recently used one, so it's evicted last
+        while self.is_over_budget(&inner) && inner.evict_least_recently_used() {
+            self.stats.evictions.fetch_add(1, Ordering::Relaxed);
+        }
     }
 
-    pub fn set(&self, key: String, value: String) {
-        self.0.insert(key, value);
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    fn is_over_budget(&self, inner: &Inner) -> bool {
+        self.budget
+            .max_entries
+            .is_some_and(|max_entries| inner.entries.len() > max_entries)
+            || self
+                .budget
+                .max_bytes
+                .is_some_and(|max_bytes| inner.bytes > max_bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Budget, KeyValue};
+
+    #[test]
+    fn evict_least_recently_used_entries() {
+        let kv = KeyValue::with_budget(Budget {
+            max_entries: Some(2),
+            max_bytes: None,
+        });
+
+        kv.set("a".into(), "1".into()).unwrap();
+        kv.set("b".into(), "2".into()).unwrap();
+        // 'a' is now more recently used than 'b'
+        assert_eq!(kv.get("a"), Some("1".into()));
+
+        kv.set("c".into(), "3".into()).unwrap();
+        assert_eq!(kv.get("b"), None);
+        assert_eq!(kv.get("a"), Some("1".into()));
+        assert_eq!(kv.get("c"), Some("3".into()));
+        assert_eq!(kv.stats().evictions(), 1);
+    }
+
+    #[test]
+    fn respect_byte_budget() {
+        let kv = KeyValue::with_budget(Budget {
+            max_entries: None,
+            max_bytes: Some(10),
+        });
+
+        kv.set("key1".into(), "abc".into()).unwrap();
+        // replacing a value only accounts for the difference
+        kv.set("key1".into(), "abcde".into()).unwrap();
+        assert_eq!(kv.stats().evictions(), 0);
+
+        kv.set("key2".into(), "abc".into()).unwrap();
+        assert_eq!(kv.get("key1"), None);
+        assert_eq!(kv.get("key2"), Some("abc".into()));
+        assert_eq!(kv.stats().evictions(), 1);
+
+        // too big to ever fit
+        kv.set("key3".into(), "0123456789".into()).unwrap();
+        assert_eq!(kv.get("key3"), None);
+        assert_eq!(kv.get("key2"), Some("abc".into()));
+        assert_eq!(kv.stats().rejections(), 1);
+    }
+
+    #[test]
+    fn dont_log_rejected_inserts() {
+        let dir = std::env::temp_dir().join(format!("udb-rejected-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let budget = Budget {
+            max_entries: None,
+            max_bytes: Some(10),
+        };
+        let kv = KeyValue::open(budget, &dir).unwrap();
+        kv.set("key".into(), "0123456789".into()).unwrap();
+        assert_eq!(kv.stats().rejections(), 1);
+        drop(kv);
+
+        // a larger budget doesn't bring back an insert that was never accepted
+        let kv = KeyValue::open(Budget::default(), &dir).unwrap();
+        assert_eq!(kv.get("key"), None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reserved_keys_are_read_only() {
+        let kv = KeyValue::default();
+        kv.set("version".into(), "hacked".into()).unwrap();
+        assert_eq!(kv.get("version"), Some("Ken's Key-Value Store 1.0".into()));
+    }
+
+    #[test]
+    fn scan_by_prefix_in_order() {
+        let kv = KeyValue::default();
+        for key in ["foo", "fob", "foo2", "bar", "fo"] {
+            kv.set(key.into(), key.to_uppercase()).unwrap();
+        }
+
+        assert_eq!(
+            kv.scan("fo"),
+            [
+                ("fo".into(), "FO".into()),
+                ("fob".into(), "FOB".into()),
+                ("foo".into(), "FOO".into()),
+                ("foo2".into(), "FOO2".into()),
+            ]
+        );
+        assert_eq!(kv.scan("baz"), []);
+        assert_eq!(kv.scan("").len(), 6); // including the version
     }
 }
@@ -0,0 +1,132 @@
+use std::io::{self, BufRead, Write};
+
+// The export format is a line per entry, `key=value`, ordered by key.
+// backslashes, '=' and newlines are escaped with a backslash, so every line splits on its only bare '='
+
+#[derive(thiserror::Error, Debug)]
+pub enum ExportError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+
+    #[error("line {0}: missing '='")]
+    MissingSeparator(usize),
+
+    #[error("line {0}: bad escape sequence")]
+    BadEscape(usize),
+}
+
+/// Writes the entries, they are expected to be ordered by key already
+pub fn write<'a, W: Write>(
+    writer: &mut W,
+    entries: impl Iterator<Item = (&'a str, &'a str)>,
+) -> io::Result<()> {
+    for (key, value) in entries {
+        writeln!(writer, "{}={}", escape(key), escape(value))?;
+    }
+
+    writer.flush()
+}
+
+pub fn read<R: BufRead>(reader: R) -> Result<Vec<(String, String)>, ExportError> {
+    let mut entries = Vec::new();
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line_number = idx + 1;
+
+        let separator = find_separator(&line).ok_or(ExportError::MissingSeparator(line_number))?;
+        let key = unescape(&line[..separator]).ok_or(ExportError::BadEscape(line_number))?;
+        let value = unescape(&line[separator + 1..]).ok_or(ExportError::BadEscape(line_number))?;
+        entries.push((key, value));
+    }
+
+    Ok(entries)
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for ch in s.chars() {
+        match ch {
+            '\\' => escaped.push_str("\\\\"),
+            '=' => escaped.push_str("\\="),
+            '\n' => escaped.push_str("\\n"),
+            ch => escaped.push(ch),
+        }
+    }
+
+    escaped
+}
+
+fn unescape(s: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            unescaped.push(ch);
+            continue;
+        }
+
+        match chars.next()? {
+            '\\' => unescaped.push('\\'),
+            '=' => unescaped.push('='),
+            'n' => unescaped.push('\n'),
+            _ => return None,
+        }
+    }
+
+    Some(unescaped)
+}
+
+// the index of the first '=' that isn't escaped
+fn find_separator(line: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (idx, byte) in line.bytes().enumerate() {
+        match byte {
+            _ if escaped => escaped = false,
+            b'\\' => escaped = true,
+            b'=' => return Some(idx),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read, write, ExportError};
+
+    #[test]
+    fn round_trip_entries() {
+        let entries = [
+            ("", "empty key"),
+            ("a=b", "c=d"),
+            ("multi\nline", "back\\slash\\="),
+            ("plain", ""),
+        ];
+
+        let mut exported = Vec::new();
+        write(&mut exported, entries.iter().copied()).unwrap();
+        assert_eq!(
+            String::from_utf8(exported.clone()).unwrap(),
+            "=empty key\na\\=b=c\\=d\nmulti\\nline=back\\\\slash\\\\\\=\nplain=\n"
+        );
+
+        let imported = read(&exported[..]).unwrap();
+        assert_eq!(
+            imported,
+            entries.map(|(key, value)| (key.to_string(), value.to_string()))
+        );
+    }
+
+    #[test]
+    fn reject_bad_lines() {
+        assert!(matches!(
+            read(&b"ok=1\nno separator\n"[..]),
+            Err(ExportError::MissingSeparator(2))
+        ));
+        assert!(matches!(
+            read(&b"bad\\escape=1\n"[..]),
+            Err(ExportError::BadEscape(1))
+        ));
+    }
+}
@@ -0,0 +1,261 @@
+//! The store and the request handling behind the server, along with helpers for its clients,
+//! a library of their own so the tools in src/bin and the verifier can share them
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use config::{Config, Dispatch};
+use protocol::Request;
+use tokio::net::UdpSocket;
+
+pub mod config;
+pub mod db;
+pub mod export;
+mod limiter;
+mod persistence;
+mod pool;
+mod protocol;
+mod replication;
+pub mod shard;
+mod tcp;
+
+// how often the pool statistics are reported
+const STATS_INTERVAL: Duration = Duration::from_secs(10);
+// how often idle clients are removed from the rate limiter
+const LIMITER_SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
+// requests must fit in a single datagram
+const MAX_REQUEST_SIZE: usize = 1000;
+// larger responses are split into continuation packets
+const MAX_RESPONSE_SIZE: usize = 1000;
+
+struct SharedState {
+    kv: db::KeyValue,
+    socket: UdpSocket,
+    limiter: Option<limiter::RateLimiter>,
+    // only set when replication is enabled
+    replica: Option<replication::Replica>,
+    // whether a retrieve of a key that ends with `*` is taken as a prefix scan
+    prefix_scans: bool,
+}
+
+impl SharedState {
+    // whether a datagram from the client should be handled
+    fn is_allowed(&self, client: SocketAddr) -> bool {
+        let allowed = self
+            .limiter
+            .as_ref()
+            .is_none_or(|limiter| limiter.allow(client));
+        if !allowed {
+            telemetry::stats::error("rate-limited");
+        }
+
+        allowed
+    }
+}
+
+/// Serves a single shard on the socket, every shard has a socket and a store of its own
+pub async fn serve_shard(
+    config: Arc<Config>,
+    socket: UdpSocket,
+    shard: usize,
+) -> anyhow::Result<()> {
+    let port = socket.local_addr()?.port();
+    let data_dir = config.shard_dir(shard);
+    let kv = match &data_dir {
+        Some(dir) => {
+            tracing::info!("Persisting the store into: {:?}", dir);
+            db::KeyValue::open(config.budget, dir)?
+        }
+        None => db::KeyValue::with_budget(config.budget),
+    };
+
+    let replica = match &config.replication {
+        Some(replication) => {
+            let socket = UdpSocket::bind(&replication.addr).await?;
+            let peer = tokio::net::lookup_host(&replication.peer)
+                .await?
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("can't resolve {}", replication.peer))?;
+            tracing::info!("Replicating with {} on: {}", peer, socket.local_addr()?);
+            Some(replication::Replica::new(socket, peer, &kv)?)
+        }
+        None => None,
+    };
+
+    let limiter = config.rate_limit.map(limiter::RateLimiter::new);
+    let state = Arc::new(SharedState {
+        kv,
+        socket,
+        limiter,
+        replica,
+        prefix_scans: config.prefix_scans,
+    });
+    if state.limiter.is_some() {
+        tokio::spawn(sweep_limiter(state.clone()));
+    }
+    if data_dir.is_some() {
+        tokio::spawn(take_snapshots(state.clone(), config.snapshot_interval));
+    }
+    if let Some(replication) = &config.replication {
+        tokio::spawn(replication::run(state.clone(), replication.digest_interval));
+    }
+
+    if let Some(addr) = config.tcp_addr.clone() {
+        let state = state.clone();
+        tokio::spawn(async move {
+            if let Err(err) = tcp::serve(addr, state).await {
+                tracing::error!("the TCP interface has failed: {}", err);
+            }
+        });
+    }
+
+    match config.dispatch {
+        Dispatch::Pool => serve_with_pool(state, &config, port).await,
+        Dispatch::Spawn => serve_with_spawn(state).await,
+    }
+}
+
+// Feeds every datagram into a fixed pool of workers
+async fn serve_with_pool(
+    state: Arc<SharedState>,
+    config: &Config,
+    port: u16,
+) -> anyhow::Result<()> {
+    let mut pool = pool::Pool::start(state.clone(), config.workers, config.worker_queue_size);
+    tokio::spawn(report_stats(state.clone(), pool.stats(), port));
+
+    let mut packet = [0; 1024];
+    loop {
+        let (len, addr) = state.socket.recv_from(&mut packet).await?;
+        telemetry::stats::received(len);
+        if state.is_allowed(addr) {
+            pool.dispatch(addr, packet[..len].to_vec());
+        }
+    }
+}
+
+// Spawns a new task for every datagram
+async fn serve_with_spawn(state: Arc<SharedState>) -> anyhow::Result<()> {
+    let mut packet = [0; 1024];
+    loop {
+        let (len, addr) = state.socket.recv_from(&mut packet).await?;
+        telemetry::stats::received(len);
+        if state.is_allowed(addr) {
+            tokio::spawn(handle_request(state.clone(), addr, packet[..len].to_vec()));
+        }
+    }
+}
+
+// Periodically compacts the write-ahead log into a snapshot
+async fn take_snapshots(state: Arc<SharedState>, every: Duration) {
+    let mut interval = tokio::time::interval(every);
+    interval.tick().await; // first tick always return immediately
+
+    loop {
+        interval.tick().await;
+
+        let state = state.clone();
+        match tokio::task::spawn_blocking(move || state.kv.snapshot()).await {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => tracing::error!("failed to take a snapshot: {}", err),
+            Err(err) => tracing::error!("the snapshot task has failed: {}", err),
+        }
+    }
+}
+
+async fn sweep_limiter(state: Arc<SharedState>) {
+    let mut interval = tokio::time::interval(LIMITER_SWEEP_INTERVAL);
+
+    loop {
+        interval.tick().await;
+        if let Some(limiter) = &state.limiter {
+            limiter.sweep();
+        }
+    }
+}
+
+// the stats of every shard are labeled by its port
+async fn report_stats(state: Arc<SharedState>, stats: Arc<pool::Stats>, port: u16) {
+    let mut interval = tokio::time::interval(STATS_INTERVAL);
+    let mut last_received = 0;
+
+    loop {
+        interval.tick().await;
+
+        // only report when there was some activity since the last report
+        let received = stats.received();
+        if received != last_received {
+            tracing::info!(
+                "[{}] packets received: {}, dropped: {}, rate limited: {}, evicted entries: {}, rejected inserts: {}",
+                port,
+                received,
+                stats.dropped(),
+                state.limiter.as_ref().map_or(0, |limiter| limiter.dropped()),
+                state.kv.stats().evictions(),
+                state.kv.stats().rejections(),
+            );
+            last_received = received;
+        }
+    }
+}
+
+async fn handle_request(
+    state: Arc<SharedState>,
+    client: SocketAddr,
+    packet: Vec<u8>,
+) -> anyhow::Result<()> {
+    let request = Request::from_string(String::from_utf8(packet)?, state.prefix_scans);
+
+    let scan = matches!(request, Request::Scan(_));
+    let pairs = execute(&state, request).await?;
+    let responses = match scan {
+        true => protocol::pack_response(&pairs, MAX_RESPONSE_SIZE),
+        // a retrieved pair is sent as it is, it always fits since it was inserted by a single request
+        false => pairs
+            .into_iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect(),
+    };
+    for response in responses {
+        state.socket.send_to(response.as_bytes(), client).await?;
+        telemetry::stats::sent(response.len());
+    }
+
+    Ok(())
+}
+
+// Executes a request against the store, returns the key=value pairs to send back
+//
+// an insert may write to the log on disk or push it to the peer, so it runs on the blocking pool,
+// unless the store lives only in memory, where it's cheap enough to run inline
+async fn execute(
+    state: &Arc<SharedState>,
+    request: Request,
+) -> std::io::Result<Vec<(String, String)>> {
+    telemetry::stats::request(match request {
+        Request::Insert(..) => "insert",
+        Request::Retrieve(_) => "retrieve",
+        Request::Scan(_) => "scan",
+    });
+    match request {
+        Request::Insert(key, value) if !state.kv.is_persistent() && state.replica.is_none() => {
+            state.kv.set(key, value)?;
+            Ok(Vec::new())
+        }
+        Request::Insert(key, value) => {
+            let state = state.clone();
+            tokio::task::spawn_blocking(move || match &state.replica {
+                Some(replica) => replica.insert(&state.kv, key, value),
+                None => state.kv.set(key, value),
+            })
+            .await
+            .map_err(std::io::Error::other)??;
+            Ok(Vec::new())
+        }
+        Request::Retrieve(key) => Ok(state
+            .kv
+            .get(&key)
+            .map(|value| vec![(key, value)])
+            .unwrap_or_default()),
+        Request::Scan(prefix) => Ok(state.kv.scan(&prefix)),
+    }
+}
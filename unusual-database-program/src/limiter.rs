@@ -0,0 +1,131 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Limit {
+    // datagrams per second
+    pub rate: f64,
+    // the number of datagrams a client may send in a single burst
+    pub burst: f64,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+/// A token-bucket rate limiter, keyed by the client's address
+#[derive(Debug)]
+pub struct RateLimiter {
+    limit: Limit,
+    buckets: Mutex<HashMap<SocketAddr, Bucket>>,
+    dropped: AtomicU64,
+}
+
+impl RateLimiter {
+    pub fn new(limit: Limit) -> Self {
+        Self {
+            limit,
+            buckets: Mutex::default(),
+            dropped: AtomicU64::default(),
+        }
+    }
+
+    /// Takes a token from the client's bucket, returns false if the bucket is empty
+    pub fn allow(&self, client: SocketAddr) -> bool {
+        self.allow_at(client, Instant::now())
+    }
+
+    fn allow_at(&self, client: SocketAddr, now: Instant) -> bool {
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(client).or_insert(Bucket {
+            tokens: self.limit.burst,
+            refilled: now,
+        });
+
+        let elapsed = now.saturating_duration_since(bucket.refilled).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.limit.rate).min(self.limit.burst);
+        bucket.refilled = now;
+
+        if bucket.tokens < 1.0 {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    /// Forgets the buckets that have been refilled to the top,
+    /// those behave exactly like the fresh bucket a returning client would get
+    pub fn sweep(&self) {
+        self.sweep_at(Instant::now())
+    }
+
+    fn sweep_at(&self, now: Instant) {
+        let refill_time = Duration::from_secs_f64(self.limit.burst / self.limit.rate);
+        self.buckets
+            .lock()
+            .unwrap()
+            .retain(|_, bucket| now.saturating_duration_since(bucket.refilled) < refill_time);
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{Limit, RateLimiter};
+
+    #[test]
+    fn limit_bursts_and_refill() {
+        let limiter = RateLimiter::new(Limit {
+            rate: 10.0,
+            burst: 3.0,
+        });
+        let client = "127.0.0.1:1234".parse().unwrap();
+        let other = "127.0.0.1:4321".parse().unwrap();
+        let now = Instant::now();
+
+        assert!((0..3).all(|_| limiter.allow_at(client, now)));
+        assert!(!limiter.allow_at(client, now));
+        // every client has its own bucket
+        assert!(limiter.allow_at(other, now));
+
+        // a token is refilled every 100ms
+        let later = now + Duration::from_millis(100);
+        assert!(limiter.allow_at(client, later));
+        assert!(!limiter.allow_at(client, later));
+        assert_eq!(limiter.dropped(), 2);
+    }
+
+    #[test]
+    fn sweep_refilled_buckets() {
+        let limiter = RateLimiter::new(Limit {
+            rate: 10.0,
+            burst: 3.0,
+        });
+        let now = Instant::now();
+
+        limiter.allow_at("127.0.0.1:1".parse().unwrap(), now);
+        limiter.allow_at(
+            "127.0.0.1:2".parse().unwrap(),
+            now + Duration::from_millis(200),
+        );
+
+        limiter.sweep_at(now + Duration::from_millis(350));
+        assert_eq!(limiter.buckets.lock().unwrap().len(), 1);
+    }
+}
@@ -1,49 +1,107 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::Path,
+    sync::Arc,
+};
 
-use protocol::Request;
-use tokio::net::UdpSocket;
+use tokio::{net::UdpSocket, task::JoinSet};
+use unusual_database_program::{config::Config, db, export};
 
-mod db;
-mod protocol;
+// shard n listens on the port that follows the one of shard n - 1
+const BASE_PORT: u16 = 3606;
 
-struct SharedState {
-    kv: db::KeyValue,
-    socket: UdpSocket,
-}
+const USAGE: &str = "usage: unusual-database-program [--export <file> | --import <file>]";
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let socket = UdpSocket::bind("0.0.0.0:3606").await?;
-    println!("Server listening on: {}", socket.local_addr()?);
-
-    let state = Arc::new(SharedState {
-        kv: db::KeyValue::default(),
-        socket,
-    });
-
-    let mut packet = [0; 1024];
-    loop {
-        let (len, addr) = state.socket.recv_from(&mut packet).await?;
-        tokio::spawn(handle_request(state.clone(), addr, packet[..len].to_vec()));
+    telemetry::init();
+    let config = Config::from_env()?;
+
+    let mut args = std::env::args().skip(1);
+    match (args.next().as_deref(), args.next(), args.next()) {
+        (None, _, _) => {}
+        (Some("--export"), Some(path), None) => return export(&config, Path::new(&path)),
+        (Some("--import"), Some(path), None) => return import(&config, Path::new(&path)),
+        _ => anyhow::bail!(USAGE),
     }
-}
 
-async fn handle_request(
-    state: Arc<SharedState>,
-    client: SocketAddr,
-    packet: Vec<u8>,
-) -> anyhow::Result<()> {
-    let request = Request::from_string(String::from_utf8(packet)?);
-
-    match request {
-        Request::Insert(key, value) => state.kv.set(key, value),
-        Request::Retrieve(key) => {
-            if let Some(value) = state.kv.get(&key) {
-                let response = key + "=" + &value;
-                state.socket.send_to(response.as_bytes(), client).await?;
-            }
+    if let Some(addr) = &config.metrics_addr {
+        if let Err(err) = telemetry::stats::install(addr) {
+            tracing::error!("failed to start the metrics exporter: {}", err);
         }
     }
 
+    if config.shards > 1 && (config.tcp_addr.is_some() || config.replication.is_some()) {
+        anyhow::bail!("TCP_ADDR and replication are only supported with a single shard");
+    }
+
+    let config = Arc::new(config);
+    let mut shards = JoinSet::new();
+    for shard in 0..config.shards {
+        shards.spawn(run_shard(config.clone(), shard));
+    }
+
+    // shards only ever stop when they fail, which stops the whole server
+    while let Some(result) = shards.join_next().await {
+        result??;
+    }
+
+    Ok(())
+}
+
+// Binds the port of a shard and serves it
+async fn run_shard(config: Arc<Config>, shard: usize) -> anyhow::Result<()> {
+    let port = u16::try_from(shard)
+        .ok()
+        .and_then(|shard| BASE_PORT.checked_add(shard))
+        .ok_or_else(|| anyhow::anyhow!("there is no port for shard {}", shard))?;
+    let socket = UdpSocket::bind(("0.0.0.0", port)).await?;
+    tracing::info!("Server listening on: {}", socket.local_addr()?);
+
+    unusual_database_program::serve_shard(config, socket, shard).await
+}
+
+// Opens the persistent store, export and import only make sense for a store that outlives them
+//
+// note: the server must not be running on the same data directory
+fn open_store(config: &Config) -> anyhow::Result<db::KeyValue> {
+    let Some(dir) = &config.data_dir else {
+        anyhow::bail!("DATA_DIR must be set to export or import the store");
+    };
+    if config.shards > 1 {
+        anyhow::bail!("export and import are only supported with a single shard");
+    }
+
+    Ok(db::KeyValue::open(config.budget, dir)?)
+}
+
+// Dumps every entry of the store into the file, ordered by key
+fn export(config: &Config, path: &Path) -> anyhow::Result<()> {
+    let entries = open_store(config)?.entries();
+    let mut file = BufWriter::new(File::create(path)?);
+    export::write(
+        &mut file,
+        entries
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str())),
+    )?;
+
+    println!("exported {} entries into {:?}", entries.len(), path);
+    Ok(())
+}
+
+// Loads every entry of the file into the store, replacing existing values
+fn import(config: &Config, path: &Path) -> anyhow::Result<()> {
+    let entries = export::read(BufReader::new(File::open(path)?))?;
+    let kv = open_store(config)?;
+
+    let count = entries.len();
+    for (key, value) in entries {
+        kv.set(key, value)?;
+    }
+    kv.snapshot()?;
+
+    println!("imported {} entries from {:?}", count, path);
     Ok(())
 }
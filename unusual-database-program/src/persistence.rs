@@ -0,0 +1,272 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+// Both files start with a header of a 4 bytes magic followed by a single VERSION byte,
+// and continue with a sequence of records: key length (u32 BE), key, value length (u32 BE), value
+const SNAPSHOT_MAGIC: &[u8; 4] = b"UDBS";
+const WAL_MAGIC: &[u8; 4] = b"UDBW";
+const VERSION: u8 = 1;
+const HEADER_LEN: usize = 5;
+
+const SNAPSHOT_FILE: &str = "snapshot";
+const SNAPSHOT_TMP_FILE: &str = "snapshot.tmp";
+const WAL_FILE: &str = "wal";
+
+/// A write-ahead log of inserts, backed by a compacted snapshot
+#[derive(Debug)]
+pub struct Wal {
+    dir: PathBuf,
+    file: BufWriter<File>,
+}
+
+impl Wal {
+    /// Opens the data directory and restores its state
+    ///
+    /// returns the restored entries (snapshot first, then the log) in insertion order
+    pub fn open(dir: &Path) -> io::Result<(Self, Vec<(String, String)>)> {
+        fs::create_dir_all(dir)?;
+
+        let mut entries = Vec::new();
+        if let Some(snapshot) = read_optional(&dir.join(SNAPSHOT_FILE))? {
+            entries.extend(decode(SNAPSHOT_MAGIC, &snapshot)?.0);
+        }
+
+        let wal_path = dir.join(WAL_FILE);
+        let complete = match read_optional(&wal_path)? {
+            // a log shorter than its header was torn while it was created, it holds no records
+            Some(wal) if wal.len() >= HEADER_LEN => {
+                let (records, complete) = decode(WAL_MAGIC, &wal)?;
+                entries.extend(records);
+                (complete < wal.len()).then_some(complete)
+            }
+            _ => {
+                create_file(&wal_path, WAL_MAGIC, std::iter::empty())?;
+                None
+            }
+        };
+
+        let file = OpenOptions::new().append(true).open(&wal_path)?;
+        if let Some(complete) = complete {
+            // drop the partially written record, or the records appended after it
+            // would be read as a part of it
+            file.set_len(complete as u64)?;
+            file.sync_all()?;
+        }
+        Ok((
+            Self {
+                dir: dir.into(),
+                file: BufWriter::new(file),
+            },
+            entries,
+        ))
+    }
+
+    /// Appends an insert to the log
+    pub fn append(&mut self, key: &str, value: &str) -> io::Result<()> {
+        self.file.write_all(&encode(key, value))?;
+        self.file.flush()
+    }
+
+    /// Replaces the snapshot with the given entries and truncates the log
+    ///
+    /// the entries must reflect every insert that was appended to the log so far
+    pub fn compact<'a>(
+        &mut self,
+        entries: impl Iterator<Item = (&'a str, &'a str)>,
+    ) -> io::Result<()> {
+        // write the new snapshot aside, and only then replace the old one,
+        // so a crash mid-write never leaves us without a snapshot
+        let tmp_path = self.dir.join(SNAPSHOT_TMP_FILE);
+        create_file(&tmp_path, SNAPSHOT_MAGIC, entries)?;
+        fs::rename(&tmp_path, self.dir.join(SNAPSHOT_FILE))?;
+
+        let wal_path = self.dir.join(WAL_FILE);
+        create_file(&wal_path, WAL_MAGIC, std::iter::empty())?;
+        self.file = BufWriter::new(OpenOptions::new().append(true).open(&wal_path)?);
+
+        Ok(())
+    }
+}
+
+fn read_optional(path: &Path) -> io::Result<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(content) => Ok(Some(content)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+// (re)creates a file holding the given entries, and makes sure it reaches the disk
+fn create_file<'a>(
+    path: &Path,
+    magic: &[u8; 4],
+    entries: impl Iterator<Item = (&'a str, &'a str)>,
+) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(magic)?;
+    file.write_all(&[VERSION])?;
+    for (key, value) in entries {
+        file.write_all(&encode(key, value))?;
+    }
+
+    file.into_inner()?.sync_all()
+}
+
+fn encode(key: &str, value: &str) -> Vec<u8> {
+    let mut record = Vec::with_capacity(8 + key.len() + value.len());
+    for part in [key, value] {
+        record.extend_from_slice(&(part.len() as u32).to_be_bytes());
+        record.extend_from_slice(part.as_bytes());
+    }
+    record
+}
+
+// Decodes the records of a file
+//
+// a partially written record at the end of the file (e.g. the server crashed mid-write) is ignored,
+// returns the records along with the length of the file up to the end of the last complete record
+fn decode(magic: &[u8; 4], bytes: &[u8]) -> io::Result<(Vec<(String, String)>, usize)> {
+    if bytes.len() < HEADER_LEN || &bytes[..magic.len()] != magic || bytes[magic.len()] != VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "unknown persistence file format",
+        ));
+    }
+
+    let mut records = Vec::new();
+    let mut rest = &bytes[HEADER_LEN..];
+    while let Some((key, after_key)) = decode_part(rest) {
+        let Some((value, after_value)) = decode_part(after_key) else {
+            break;
+        };
+
+        let (Ok(key), Ok(value)) = (String::from_utf8(key), String::from_utf8(value)) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "a record is not a valid utf-8 string",
+            ));
+        };
+
+        records.push((key, value));
+        rest = after_value;
+    }
+
+    Ok((records, bytes.len() - rest.len()))
+}
+
+// decodes a length prefixed part, returns None if the part is incomplete
+fn decode_part(bytes: &[u8]) -> Option<(Vec<u8>, &[u8])> {
+    let len = u32::from_be_bytes(bytes.get(..4)?.try_into().ok()?) as usize;
+    let part = bytes.get(4..4 + len)?;
+    Some((part.to_vec(), &bytes[4 + len..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::OpenOptions, io::Write, path::PathBuf};
+
+    use super::{decode, encode, Wal, WAL_FILE, WAL_MAGIC};
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("udb-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn restore_from_log_and_snapshot() {
+        let dir = test_dir("restore");
+
+        let (mut wal, entries) = Wal::open(&dir).unwrap();
+        assert!(entries.is_empty());
+        wal.append("foo", "bar").unwrap();
+        wal.append("key=", "multi\nline").unwrap();
+        drop(wal);
+
+        let (mut wal, entries) = Wal::open(&dir).unwrap();
+        assert_eq!(
+            entries,
+            [
+                ("foo".into(), "bar".into()),
+                ("key=".into(), "multi\nline".into())
+            ]
+        );
+
+        // compact into a snapshot, and keep logging on top of it
+        wal.compact([("foo", "bar")].into_iter()).unwrap();
+        wal.append("foo", "baz").unwrap();
+        drop(wal);
+
+        let (_, entries) = Wal::open(&dir).unwrap();
+        assert_eq!(
+            entries,
+            [("foo".into(), "bar".into()), ("foo".into(), "baz".into())]
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn ignore_partial_record() {
+        let mut bytes = WAL_MAGIC.to_vec();
+        bytes.push(1);
+        bytes.extend(encode("a", "1"));
+        bytes.extend(&encode("b", "2")[..6]);
+
+        let (records, complete) = decode(WAL_MAGIC, &bytes).unwrap();
+        assert_eq!(records, [("a".into(), "1".into())]);
+        assert_eq!(complete, bytes.len() - 6);
+    }
+
+    #[test]
+    fn append_after_torn_record() {
+        let dir = test_dir("torn");
+
+        let (mut wal, _) = Wal::open(&dir).unwrap();
+        wal.append("a", "1").unwrap();
+        drop(wal);
+
+        // the server crashed in the middle of the next append
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(dir.join(WAL_FILE))
+            .unwrap();
+        file.write_all(&encode("b", "2")[..6]).unwrap();
+        drop(file);
+
+        let (mut wal, entries) = Wal::open(&dir).unwrap();
+        assert_eq!(entries, [("a".into(), "1".into())]);
+        wal.append("c", "3").unwrap();
+        drop(wal);
+
+        let (_, entries) = Wal::open(&dir).unwrap();
+        assert_eq!(
+            entries,
+            [("a".into(), "1".into()), ("c".into(), "3".into())]
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rewrite_torn_header() {
+        let dir = test_dir("torn-header");
+
+        // the server crashed while creating the log
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join(WAL_FILE), &WAL_MAGIC[..2]).unwrap();
+
+        let (mut wal, entries) = Wal::open(&dir).unwrap();
+        assert!(entries.is_empty());
+        wal.append("a", "1").unwrap();
+        drop(wal);
+
+        let (_, entries) = Wal::open(&dir).unwrap();
+        assert_eq!(entries, [("a".into(), "1".into())]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
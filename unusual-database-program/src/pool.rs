@@ -0,0 +1,86 @@
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use tokio::sync::mpsc;
+
+use crate::{handle_request, SharedState};
+
+type Packet = (SocketAddr, Vec<u8>);
+
+// Counters describing how well the pool keeps up with the incoming traffic
+#[derive(Debug, Default)]
+pub struct Stats {
+    received: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl Stats {
+    pub fn received(&self) -> u64 {
+        self.received.load(Ordering::Relaxed)
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// A fixed set of request handlers, each fed by its own bounded queue
+///
+/// packets are handed out in a round-robin fashion, when the selected
+/// worker's queue is full the packet is dropped (UDP gives no delivery guarantees anyway)
+pub struct Pool {
+    workers: Vec<mpsc::Sender<Packet>>,
+    next: usize,
+    stats: Arc<Stats>,
+}
+
+impl Pool {
+    /// Starts `workers` background workers
+    ///
+    /// note: this function needs to be called from inside a tokio runtime context
+    pub fn start(state: Arc<SharedState>, workers: usize, queue_size: usize) -> Self {
+        let workers = (0..workers)
+            .map(|_| {
+                let (tx, mut rx) = mpsc::channel::<Packet>(queue_size);
+                let state = state.clone();
+                tokio::spawn(async move {
+                    while let Some((addr, packet)) = rx.recv().await {
+                        if let Err(err) = handle_request(state.clone(), addr, packet).await {
+                            tracing::warn!("failed to handle a request from {}: {}", addr, err);
+                        }
+                    }
+                });
+
+                tx
+            })
+            .collect();
+
+        Self {
+            workers,
+            next: 0,
+            stats: Arc::default(),
+        }
+    }
+
+    /// Hands the packet over to the next worker, never waits
+    pub fn dispatch(&mut self, addr: SocketAddr, packet: Vec<u8>) {
+        self.stats.received.fetch_add(1, Ordering::Relaxed);
+
+        let worker = &self.workers[self.next];
+        self.next = (self.next + 1) % self.workers.len();
+
+        if worker.try_send((addr, packet)).is_err() {
+            self.stats.dropped.fetch_add(1, Ordering::Relaxed);
+            telemetry::stats::error("dropped");
+        }
+    }
+
+    pub fn stats(&self) -> Arc<Stats> {
+        self.stats.clone()
+    }
+}
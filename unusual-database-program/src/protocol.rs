@@ -3,10 +3,16 @@ pub enum Request {
     // Key, Value
     Insert(String, String),
     Retrieve(String),
+    // Key prefix, formated prefix*
+    Scan(String),
 }
 
+const SCAN_SUFFIX: char = '*';
+
 impl Request {
-    pub fn from_string(mut raw: String) -> Self {
+    /// Parses a request, a retrieve of a key that ends with `*` is only taken as a prefix scan
+    /// when scans are enabled, since the spec requires every key to be retrievable otherwise
+    pub fn from_string(mut raw: String, scans: bool) -> Self {
         match raw.find('=') {
             Some(split_index) => {
                 // An insert request formated key=value
@@ -14,6 +20,11 @@ impl Request {
                 raw.pop(); // remove the '=' sign from the end
                 Self::Insert(raw, value)
             }
+            None if scans && raw.ends_with(SCAN_SUFFIX) => {
+                // A scan request
+                raw.pop();
+                Self::Scan(raw)
+            }
             None => {
                 // A retreieve request
                 Self::Retrieve(raw)
@@ -22,9 +33,59 @@ impl Request {
     }
 }
 
+/// Packs the key=value pairs of a scan into packets of up to `max_len` bytes
+///
+/// the pairs are separated by newlines, with any newline or backslash within a key or a value
+/// escaped (as `\\n` and `\\\\`). every packet but the last one ends with an extra newline to mark
+/// that more packets follow, so the response is the concatenation of the packets without
+/// their markers. a pair is kept within a single packet, unless it can't fit in one on its own.
+pub fn pack_response(pairs: &[(String, String)], max_len: usize) -> Vec<String> {
+    // keep a byte aside for the continuation marker
+    let budget = max_len - 1;
+    let mut packets = Vec::new();
+    let mut packet = String::new();
+    let mut flush = |packet: &mut String| {
+        packet.push('\n');
+        packets.push(std::mem::take(packet));
+    };
+
+    for (idx, (key, value)) in pairs.iter().enumerate() {
+        let mut pair = format!("{}={}", escape(key), escape(value));
+        if idx + 1 < pairs.len() {
+            pair.push('\n');
+        }
+
+        if !packet.is_empty() && packet.len() + pair.len() > budget {
+            flush(&mut packet);
+        }
+
+        let mut pair = pair.as_str();
+        while packet.len() + pair.len() > budget {
+            let mut split = budget - packet.len();
+            while !pair.is_char_boundary(split) {
+                split -= 1;
+            }
+            packet.push_str(&pair[..split]);
+            pair = &pair[split..];
+            flush(&mut packet);
+        }
+        packet.push_str(pair);
+    }
+
+    if !packet.is_empty() {
+        packets.push(packet);
+    }
+
+    packets
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
 #[cfg(test)]
 mod tests {
-    use super::Request;
+    use super::{pack_response, Request};
 
     #[test]
     fn parse_insert_request() {
@@ -40,7 +101,7 @@ mod tests {
 
         let received_values = ["foo=bar", "foo=bar=baz", "foo=", "foo===", "=foo"]
             .into_iter()
-            .map(|value| Request::from_string(value.to_string()));
+            .map(|value| Request::from_string(value.to_string(), true));
 
         for (received, expected) in received_values.zip(expetced_values) {
             assert_eq!(received, expected);
@@ -49,16 +110,104 @@ mod tests {
 
     #[test]
     fn parse_retrieve_request() {
-        let expetced_values = ["foo", ""]
+        let expetced_values = ["foo", "", "foo*bar"]
             .into_iter()
             .map(|key| Request::Retrieve(key.to_string()));
 
-        let received_values = ["foo", ""]
+        let received_values = ["foo", "", "foo*bar"]
             .into_iter()
-            .map(|key| Request::from_string(key.to_string()));
+            .map(|key| Request::from_string(key.to_string(), true));
 
         for (received, expected) in received_values.zip(expetced_values) {
             assert_eq!(received, expected);
         }
     }
+
+    #[test]
+    fn parse_scan_request() {
+        assert_eq!(
+            Request::from_string("foo*".to_string(), true),
+            Request::Scan("foo".to_string())
+        );
+        assert_eq!(
+            Request::from_string("*".to_string(), true),
+            Request::Scan("".to_string())
+        );
+        assert_eq!(
+            Request::from_string("foo*=bar".to_string(), true),
+            Request::Insert("foo*".to_string(), "bar".to_string())
+        );
+
+        // every key is retrievable unless scans are enabled
+        assert_eq!(
+            Request::from_string("foo*".to_string(), false),
+            Request::Retrieve("foo*".to_string())
+        );
+    }
+
+    #[test]
+    fn pack_pairs_into_packets() {
+        let pairs: Vec<_> = [("a", "1"), ("b", "22"), ("c", "3"), ("dd", "0123456789")]
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+
+        assert_eq!(pack_response(&pairs[..1], 10), ["a=1"]);
+        assert_eq!(pack_response(&pairs[..3], 10), ["a=1\nb=22\n\n", "c=3"]);
+        // dd=0123456789 can't fit in a packet of its own, so it's split across packets
+        assert_eq!(
+            pack_response(&pairs, 10),
+            ["a=1\nb=22\n\n", "c=3\n\n", "dd=012345\n", "6789"]
+        );
+        assert!(pack_response(&[], 10).is_empty());
+    }
+
+    fn unescape(text: &str) -> String {
+        let mut unescaped = String::new();
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            // the escaped character is taken along with its backslash
+            unescaped.push(match c {
+                '\\' if chars.next() == Some('n') => '\n',
+                c => c,
+            });
+        }
+        unescaped
+    }
+
+    // the pairs, as a client reads them back out of the packets
+    fn unpack(packets: &[String]) -> Vec<(String, String)> {
+        let mut response = String::new();
+        for packet in packets {
+            response.push_str(packet.strip_suffix('\n').unwrap_or(packet));
+        }
+
+        response
+            .split('\n')
+            .map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap();
+                (unescape(key), unescape(value))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pack_pairs_of_any_size_and_content() {
+        let pairs: Vec<_> = [
+            ("key", "a value\nwith\nnewlines\n".to_string()),
+            ("back\\slash", "\\n".to_string()),
+            // a pair that's as long as a request can be
+            ("long", "x".repeat(995)),
+            ("last", "".to_string()),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .collect();
+
+        let packets = pack_response(&pairs, 1000);
+        assert!(packets.iter().all(|packet| packet.len() <= 1000));
+        // only the last packet goes without a continuation marker
+        assert!(!packets.last().unwrap().ends_with('\n'));
+        assert_eq!(unpack(&packets), pairs);
+    }
 }
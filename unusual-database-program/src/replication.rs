@@ -0,0 +1,605 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hasher},
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tokio::net::UdpSocket;
+
+use crate::{db::KeyValue, SharedState};
+
+// the digest splits the keys into this many buckets,
+// so only the buckets that differ between the instances are resent
+const DIGEST_BUCKETS: usize = 64;
+// a message holds at most a single request (which fits in a datagram) along with its header
+const MAX_MESSAGE_SIZE: usize = 2048;
+
+const INSERT_TAG: u8 = b'I';
+const REPAIR_TAG: u8 = b'R';
+const DIGEST_TAG: u8 = b'D';
+
+// both instances must agree on the hashes of the digest, even when they run different builds,
+// so they are 64 bit FNV-1a rather than the (unspecified) std hasher
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// The version of a value, a later version always wins over an earlier one
+///
+/// ties on the timestamp are broken by the id of the instance that wrote the value,
+/// so both instances pick the same winner
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version {
+    // milliseconds since the epoch
+    stamp: u64,
+    node: u64,
+}
+
+// Every message is a tag byte followed by its fields,
+// numbers are u64 BE and strings are prefixed by their length (u32 BE)
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Message {
+    // a local insert, numbered in the order it was made on the sending instance
+    Insert {
+        seq: u64,
+        version: Version,
+        key: String,
+        value: String,
+    },
+    // an entry that is resent because a digest has shown the instances disagree on it
+    Repair {
+        version: Version,
+        key: String,
+        value: String,
+    },
+    // a hash of every (key, version) pair, per bucket
+    Digest {
+        buckets: Vec<u64>,
+    },
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    // the version of every replicated key
+    versions: HashMap<String, Version>,
+    // the latest stamp seen so far, local stamps always move past it
+    stamp: u64,
+    next_seq: u64,
+    // the (node, seq) of the last insert received from the peer
+    received: Option<(u64, u64)>,
+}
+
+/// Replicates the inserts of this instance to a peer instance (and back) over UDP
+///
+/// inserts are pushed to the peer as they happen, and conflicts are resolved
+/// last-writer-wins. since datagrams may be lost, both instances periodically send
+/// a digest of their entries, to which the peer responds by resending every entry
+/// in the buckets that differ, so the instances eventually converge.
+///
+/// note: an entry that was evicted on one instance only can't be repaired from it
+#[derive(Debug)]
+pub struct Replica {
+    // a random id of this instance
+    node: u64,
+    socket: UdpSocket,
+    // a handle of the same socket for sending, unlike the tokio socket it doesn't
+    // depend on the runtime having seen it writable, so sending never has to wait
+    sender: std::net::UdpSocket,
+    peer: SocketAddr,
+    inner: Mutex<Inner>,
+}
+
+impl Replica {
+    /// Starts replicating the store
+    ///
+    /// the entries it already holds get the earliest stamp of this instance, so any insert wins over them,
+    /// while the entries both instances hold since before they started are settled by the node id
+    ///
+    /// note: this function needs to be called from inside a tokio runtime context
+    pub fn new(socket: UdpSocket, peer: SocketAddr, kv: &KeyValue) -> io::Result<Self> {
+        let socket = socket.into_std()?;
+        let sender = socket.try_clone()?;
+        let socket = UdpSocket::from_std(socket)?;
+
+        let node = RandomState::new().build_hasher().finish();
+        let loaded = Version { stamp: 0, node };
+        let versions = kv
+            .entries()
+            .into_iter()
+            .map(|(key, _)| (key, loaded))
+            .collect();
+
+        Ok(Self {
+            node,
+            socket,
+            sender,
+            peer,
+            inner: Mutex::new(Inner {
+                versions,
+                ..Default::default()
+            }),
+        })
+    }
+
+    /// Inserts a value into the store, and pushes the insert to the peer
+    pub fn insert(&self, kv: &KeyValue, key: String, value: String) -> io::Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.stamp = now().max(inner.stamp + 1);
+        let version = Version {
+            stamp: inner.stamp,
+            node: self.node,
+        };
+
+        kv.set(key.clone(), value.clone())?;
+        inner.versions.insert(key.clone(), version);
+
+        let seq = inner.next_seq;
+        inner.next_seq += 1;
+        drop(inner);
+
+        self.send(&Message::Insert {
+            seq,
+            version,
+            key,
+            value,
+        });
+        Ok(())
+    }
+
+    /// Handles a message received from the peer
+    pub fn handle(&self, kv: &KeyValue, packet: &[u8]) -> io::Result<()> {
+        let Some(message) = decode(packet) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "malformed replication message",
+            ));
+        };
+
+        match message {
+            Message::Insert {
+                seq,
+                version,
+                key,
+                value,
+            } => {
+                let mut inner = self.inner.lock().unwrap();
+                let missed = match inner.received {
+                    // a restarted peer starts counting all over
+                    Some((node, last)) if node == version.node => seq > last + 1,
+                    _ => seq > 0,
+                };
+                if inner
+                    .received
+                    .is_none_or(|(node, last)| node != version.node || seq > last)
+                {
+                    inner.received = Some((version.node, seq));
+                }
+                drop(inner);
+
+                if missed {
+                    // don't wait for the next round to find out what we've missed
+                    self.send_digest();
+                }
+                self.apply(kv, version, key, value)
+            }
+            Message::Repair {
+                version,
+                key,
+                value,
+            } => self.apply(kv, version, key, value),
+            Message::Digest { buckets } => {
+                self.repair(kv, &buckets);
+                Ok(())
+            }
+        }
+    }
+
+    /// Sends a digest of the entries to the peer
+    pub fn send_digest(&self) {
+        self.send(&Message::Digest {
+            buckets: self.digest(),
+        });
+    }
+
+    // stores the value unless we already hold a later (or the same) version of it
+    fn apply(&self, kv: &KeyValue, version: Version, key: String, value: String) -> io::Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.stamp = inner.stamp.max(version.stamp);
+        if inner
+            .versions
+            .get(&key)
+            .is_some_and(|current| *current >= version)
+        {
+            return Ok(());
+        }
+
+        kv.set(key.clone(), value)?;
+        inner.versions.insert(key, version);
+        Ok(())
+    }
+
+    fn digest(&self) -> Vec<u64> {
+        let mut buckets = vec![0; DIGEST_BUCKETS];
+        for (key, version) in self.inner.lock().unwrap().versions.iter() {
+            // xor doesn't depend on the order of the entries
+            let mut hash = fnv(FNV_OFFSET_BASIS, &(key.len() as u32).to_be_bytes());
+            hash = fnv(hash, key.as_bytes());
+            hash = fnv(hash, &version.stamp.to_be_bytes());
+            hash = fnv(hash, &version.node.to_be_bytes());
+            buckets[bucket(key)] ^= hash;
+        }
+        buckets
+    }
+
+    // resends every entry in the buckets that differ from the peer's digest
+    fn repair(&self, kv: &KeyValue, peer_buckets: &[u64]) {
+        if peer_buckets.len() != DIGEST_BUCKETS {
+            return;
+        }
+
+        let buckets = self.digest();
+        let entries: Vec<_> = self
+            .inner
+            .lock()
+            .unwrap()
+            .versions
+            .iter()
+            .filter(|(key, _)| {
+                let bucket = bucket(key);
+                buckets[bucket] != peer_buckets[bucket]
+            })
+            .map(|(key, version)| (key.clone(), *version))
+            .collect();
+
+        for (key, version) in entries {
+            let Some(value) = kv.peek(&key) else {
+                continue;
+            };
+            self.send(&Message::Repair {
+                version,
+                key,
+                value,
+            });
+        }
+    }
+
+    fn send(&self, message: &Message) {
+        // never wait on the peer, a lost message is repaired by the next digest anyway
+        let _ = self.sender.send_to(&encode(message), self.peer);
+    }
+}
+
+/// Handles the messages of the peer, and periodically sends it a digest
+pub async fn run(state: Arc<SharedState>, every: Duration) {
+    let Some(replica) = &state.replica else {
+        return;
+    };
+
+    let mut interval = tokio::time::interval(every);
+    let mut packet = [0; MAX_MESSAGE_SIZE];
+    loop {
+        tokio::select! {
+            _ = interval.tick() => replica.send_digest(),
+            received = replica.socket.recv_from(&mut packet) => match received {
+                Ok((len, addr)) if addr == replica.peer => {
+                    // applying a message may write to the log on disk
+                    let state = state.clone();
+                    let message = packet[..len].to_vec();
+                    let handled = tokio::task::spawn_blocking(move || {
+                        let Some(replica) = &state.replica else {
+                            return Ok(());
+                        };
+                        replica.handle(&state.kv, &message)
+                    })
+                    .await;
+                    match handled {
+                        Ok(Ok(())) => {}
+                        Ok(Err(err)) => tracing::warn!("failed to handle a replication message: {}", err),
+                        Err(err) => tracing::error!("the replication task has failed: {}", err),
+                    }
+                }
+                // only the peer may replicate into the store
+                Ok(_) => {}
+                // the peer isn't up (yet)
+                Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => {}
+                Err(err) => tracing::warn!("failed to receive a replication message: {}", err),
+            },
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+// continues the FNV-1a hash over the bytes
+fn fnv(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+fn bucket(key: &str) -> usize {
+    (fnv(FNV_OFFSET_BASIS, key.as_bytes()) % DIGEST_BUCKETS as u64) as usize
+}
+
+fn encode(message: &Message) -> Vec<u8> {
+    fn put_version(bytes: &mut Vec<u8>, version: &Version) {
+        bytes.extend_from_slice(&version.stamp.to_be_bytes());
+        bytes.extend_from_slice(&version.node.to_be_bytes());
+    }
+
+    fn put_str(bytes: &mut Vec<u8>, part: &str) {
+        bytes.extend_from_slice(&(part.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(part.as_bytes());
+    }
+
+    let mut bytes = Vec::new();
+    match message {
+        Message::Insert {
+            seq,
+            version,
+            key,
+            value,
+        } => {
+            bytes.push(INSERT_TAG);
+            bytes.extend_from_slice(&seq.to_be_bytes());
+            put_version(&mut bytes, version);
+            put_str(&mut bytes, key);
+            put_str(&mut bytes, value);
+        }
+        Message::Repair {
+            version,
+            key,
+            value,
+        } => {
+            bytes.push(REPAIR_TAG);
+            put_version(&mut bytes, version);
+            put_str(&mut bytes, key);
+            put_str(&mut bytes, value);
+        }
+        Message::Digest { buckets } => {
+            bytes.push(DIGEST_TAG);
+            for bucket in buckets {
+                bytes.extend_from_slice(&bucket.to_be_bytes());
+            }
+        }
+    }
+    bytes
+}
+
+// returns None if the message is malformed
+fn decode(bytes: &[u8]) -> Option<Message> {
+    struct Cursor<'a>(&'a [u8]);
+
+    impl Cursor<'_> {
+        fn u64(&mut self) -> Option<u64> {
+            let (number, rest) = self.0.split_first_chunk::<8>()?;
+            self.0 = rest;
+            Some(u64::from_be_bytes(*number))
+        }
+
+        fn version(&mut self) -> Option<Version> {
+            Some(Version {
+                stamp: self.u64()?,
+                node: self.u64()?,
+            })
+        }
+
+        fn string(&mut self) -> Option<String> {
+            let (len, rest) = self.0.split_first_chunk::<4>()?;
+            let len = u32::from_be_bytes(*len) as usize;
+            let part = rest.get(..len)?;
+            self.0 = &rest[len..];
+            String::from_utf8(part.to_vec()).ok()
+        }
+    }
+
+    let (&tag, rest) = bytes.split_first()?;
+    let mut cursor = Cursor(rest);
+    let message = match tag {
+        INSERT_TAG => Message::Insert {
+            seq: cursor.u64()?,
+            version: cursor.version()?,
+            key: cursor.string()?,
+            value: cursor.string()?,
+        },
+        REPAIR_TAG => Message::Repair {
+            version: cursor.version()?,
+            key: cursor.string()?,
+            value: cursor.string()?,
+        },
+        DIGEST_TAG => Message::Digest {
+            buckets: (0..DIGEST_BUCKETS)
+                .map(|_| cursor.u64())
+                .collect::<Option<_>>()?,
+        },
+        _ => return None,
+    };
+
+    // make sure we've consumed the entire message
+    cursor.0.is_empty().then_some(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::net::UdpSocket;
+
+    use super::{
+        decode, encode, fnv, Message, Replica, Version, DIGEST_BUCKETS, FNV_OFFSET_BASIS,
+        MAX_MESSAGE_SIZE,
+    };
+    use crate::db::KeyValue;
+
+    // two replicas of each other, along with their stores
+    async fn pair() -> ((Replica, KeyValue), (Replica, KeyValue)) {
+        let a = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let b = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let (a_addr, b_addr) = (a.local_addr().unwrap(), b.local_addr().unwrap());
+
+        let (a_kv, b_kv) = (KeyValue::default(), KeyValue::default());
+        (
+            (Replica::new(a, b_addr, &a_kv).unwrap(), a_kv),
+            (Replica::new(b, a_addr, &b_kv).unwrap(), b_kv),
+        )
+    }
+
+    // handles every message that is waiting for the replica
+    async fn pump(replica: &Replica, kv: &KeyValue) {
+        let mut packet = [0; MAX_MESSAGE_SIZE];
+        while let Ok(received) = tokio::time::timeout(
+            Duration::from_millis(100),
+            replica.socket.recv_from(&mut packet),
+        )
+        .await
+        {
+            let (len, _) = received.unwrap();
+            replica.handle(kv, &packet[..len]).unwrap();
+        }
+    }
+
+    // drops every message that is waiting for the replica
+    async fn lose(replica: &Replica) {
+        let mut packet = [0; MAX_MESSAGE_SIZE];
+        while tokio::time::timeout(
+            Duration::from_millis(100),
+            replica.socket.recv_from(&mut packet),
+        )
+        .await
+        .is_ok()
+        {}
+    }
+
+    #[test]
+    fn encode_decode_round_trip() {
+        let version = Version {
+            stamp: 1234,
+            node: 5678,
+        };
+        let messages = [
+            Message::Insert {
+                seq: 7,
+                version,
+                key: "foo".into(),
+                value: "bar=baz".into(),
+            },
+            Message::Repair {
+                version,
+                key: "".into(),
+                value: "".into(),
+            },
+            Message::Digest {
+                buckets: (0..DIGEST_BUCKETS as u64).collect(),
+            },
+        ];
+
+        for message in messages {
+            let bytes = encode(&message);
+            assert_eq!(decode(&bytes), Some(message));
+            // a truncated message is malformed
+            assert_eq!(decode(&bytes[..bytes.len() - 1]), None);
+        }
+    }
+
+    #[test]
+    fn hash_with_fnv() {
+        // the reference values of 64 bit FNV-1a
+        assert_eq!(fnv(FNV_OFFSET_BASIS, b""), 0xcbf29ce484222325);
+        assert_eq!(fnv(FNV_OFFSET_BASIS, b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv(FNV_OFFSET_BASIS, b"foobar"), 0x85944171f73967e8);
+        // hashing in parts is the same as hashing at once
+        assert_eq!(
+            fnv(fnv(FNV_OFFSET_BASIS, b"foo"), b"bar"),
+            fnv(FNV_OFFSET_BASIS, b"foobar")
+        );
+    }
+
+    #[tokio::test]
+    async fn converge_on_loaded_entries() {
+        let a = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let b = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let (a_addr, b_addr) = (a.local_addr().unwrap(), b.local_addr().unwrap());
+
+        // both instances were loaded with their own value of the same key
+        let (a_kv, b_kv) = (KeyValue::default(), KeyValue::default());
+        a_kv.set("foo".into(), "a".into()).unwrap();
+        b_kv.set("foo".into(), "b".into()).unwrap();
+        let a = Replica::new(a, b_addr, &a_kv).unwrap();
+        let b = Replica::new(b, a_addr, &b_kv).unwrap();
+
+        // whichever instance holds the winning version resends it upon the digest of the other
+        a.send_digest();
+        b.send_digest();
+        pump(&b, &b_kv).await;
+        pump(&a, &a_kv).await;
+        pump(&b, &b_kv).await;
+        assert_eq!(a_kv.get("foo"), b_kv.get("foo"));
+        assert_eq!(a.digest(), b.digest());
+
+        // an insert wins over a loaded entry
+        b.insert(&b_kv, "foo".into(), "c".into()).unwrap();
+        pump(&a, &a_kv).await;
+        assert_eq!(a_kv.get("foo"), Some("c".into()));
+    }
+
+    #[tokio::test]
+    async fn replicate_inserts() {
+        let ((a, a_kv), (b, b_kv)) = pair().await;
+
+        a.insert(&a_kv, "foo".into(), "1".into()).unwrap();
+        pump(&b, &b_kv).await;
+        assert_eq!(b_kv.get("foo"), Some("1".into()));
+
+        b.insert(&b_kv, "foo".into(), "2".into()).unwrap();
+        pump(&a, &a_kv).await;
+        assert_eq!(a_kv.get("foo"), Some("2".into()));
+    }
+
+    #[tokio::test]
+    async fn last_writer_wins() {
+        let ((a, a_kv), (b, b_kv)) = pair().await;
+
+        // both instances insert the same key before hearing from each other
+        a.insert(&a_kv, "foo".into(), "a".into()).unwrap();
+        b.insert(&b_kv, "foo".into(), "b".into()).unwrap();
+        pump(&a, &a_kv).await;
+        pump(&b, &b_kv).await;
+
+        assert_eq!(a_kv.get("foo"), b_kv.get("foo"));
+        assert_eq!(a.digest(), b.digest());
+    }
+
+    #[tokio::test]
+    async fn repair_lost_inserts() {
+        let ((a, a_kv), (b, b_kv)) = pair().await;
+
+        a.insert(&a_kv, "foo".into(), "1".into()).unwrap();
+        a.insert(&a_kv, "bar".into(), "2".into()).unwrap();
+        lose(&b).await;
+        assert_eq!(b_kv.get("foo"), None);
+
+        // the digest of b makes a resend what b is missing
+        b.send_digest();
+        pump(&a, &a_kv).await;
+        pump(&b, &b_kv).await;
+        assert_eq!(b_kv.get("foo"), Some("1".into()));
+        assert_eq!(b_kv.get("bar"), Some("2".into()));
+
+        // a gap in the sequence numbers triggers a digest right away
+        a.insert(&a_kv, "foo".into(), "3".into()).unwrap();
+        lose(&b).await;
+        a.insert(&a_kv, "baz".into(), "4".into()).unwrap();
+        pump(&b, &b_kv).await;
+        pump(&a, &a_kv).await;
+        pump(&b, &b_kv).await;
+        assert_eq!(b_kv.get("foo"), Some("3".into()));
+        assert_eq!(b_kv.get("baz"), Some("4".into()));
+        assert_eq!(a.digest(), b.digest());
+    }
+}
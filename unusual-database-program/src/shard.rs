@@ -0,0 +1,159 @@
+use std::hash::{Hash, Hasher};
+
+// the number of points every shard owns on the ring
+pub const DEFAULT_POINTS: usize = 64;
+
+/// Picks the shard of a key with consistent hashing
+///
+/// every shard owns a number of points on a ring of hashes, and a key belongs to the shard
+/// that owns the first point at or after the key's hash (wrapping around). the points only depend
+/// on the shards themselves, so adding a shard only moves the keys that land on its points.
+///
+/// the shards don't know about each other, so a scan has to be sent to all of them
+#[derive(Debug, Clone)]
+pub struct Ring<T> {
+    shards: Vec<T>,
+    // (hash, index of the shard), ordered by hash
+    points: Vec<(u64, usize)>,
+}
+
+impl<T: Hash> Ring<T> {
+    pub fn new(shards: Vec<T>) -> Self {
+        Self::with_points(shards, DEFAULT_POINTS)
+    }
+
+    pub fn with_points(shards: Vec<T>, points: usize) -> Self {
+        let mut ring: Vec<_> = shards
+            .iter()
+            .enumerate()
+            .flat_map(|(idx, shard)| {
+                (0..points).map(move |point| {
+                    let mut hasher = Fnv::default();
+                    shard.hash(&mut hasher);
+                    (point as u64).hash(&mut hasher);
+                    (hasher.finish(), idx)
+                })
+            })
+            .collect();
+        ring.sort_unstable();
+
+        Self {
+            shards,
+            points: ring,
+        }
+    }
+
+    /// The shard the key belongs to, None when there are no shards
+    pub fn shard(&self, key: &str) -> Option<&T> {
+        let mut hasher = Fnv::default();
+        key.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        let idx = self.points.partition_point(|(point, _)| *point < hash);
+        let (_, shard) = self.points.get(idx).or_else(|| self.points.first())?;
+        self.shards.get(*shard)
+    }
+
+    pub fn shards(&self) -> &[T] {
+        &self.shards
+    }
+}
+
+// FNV-1a, unlike the std hasher its output is stable across processes and releases,
+// so every client picks the same shard for a key
+struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl Hasher for Fnv {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    // FNV mixes its last bytes poorly, which would cluster the points of a shard
+    fn finish(&self) -> u64 {
+        let mut hash = self.0;
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+        hash ^ (hash >> 33)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::Ring;
+
+    const KEYS: usize = 20000;
+
+    fn keys() -> impl Iterator<Item = String> {
+        (0..KEYS).map(|idx| format!("key-{}", idx))
+    }
+
+    #[test]
+    fn spread_keys_evenly() {
+        let ring = Ring::new(vec!["a", "b", "c", "d"]);
+
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for key in keys() {
+            *counts.entry(ring.shard(&key).unwrap()).or_default() += 1;
+        }
+
+        // every shard gets about a quarter of the keys
+        for shard in ring.shards() {
+            let count = counts[shard];
+            assert!(
+                (KEYS * 15 / 100..KEYS * 35 / 100).contains(&count),
+                "{}: {}",
+                shard,
+                count
+            );
+        }
+    }
+
+    #[test]
+    fn only_move_keys_to_a_new_shard() {
+        let before = Ring::new(vec!["a", "b", "c"]);
+        let after = Ring::new(vec!["a", "b", "c", "d"]);
+
+        let mut moved = 0;
+        for key in keys() {
+            let (from, to) = (before.shard(&key).unwrap(), after.shard(&key).unwrap());
+            if from != to {
+                assert_eq!(*to, "d", "{} moved from {} to {}", key, from, to);
+                moved += 1;
+            }
+        }
+
+        // about a quarter of the keys move to the new shard
+        assert!(
+            (KEYS * 15 / 100..KEYS * 35 / 100).contains(&moved),
+            "{}",
+            moved
+        );
+    }
+
+    #[test]
+    fn pick_the_same_shard_everywhere() {
+        let ring = Ring::new(vec![3606u16, 3607]);
+        let picked: Vec<_> = ["a", "b", "c", "d", "e", "f"]
+            .into_iter()
+            .map(|key| *ring.shard(key).unwrap())
+            .collect();
+        // the picks are pinned, a change in the hashing would move keys between existing shards
+        assert_eq!(picked, [3607, 3607, 3606, 3607, 3607, 3606]);
+
+        assert_eq!(Ring::<u16>::new(vec![]).shard("key"), None);
+        assert_eq!(Ring::new(vec![3606]).shard("key"), Some(&3606));
+    }
+}
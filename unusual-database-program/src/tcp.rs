@@ -0,0 +1,94 @@
+use std::sync::Arc;
+
+use framing::LineReader;
+use telemetry::stats::Metered;
+use throttle::{Limits, Throttle};
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+};
+
+use crate::{execute, protocol::Request, SharedState, MAX_REQUEST_SIZE};
+
+/// Serves the same insert/retrieve protocol over TCP, one request per line
+///
+/// retrieve and scan responses are sent back as `key=value` lines (one per matching entry),
+/// and just like over UDP, retrieving a missing key gets no response.
+/// since a key or a value inserted over UDP may hold a newline, a response escapes
+/// every newline as `\n` and every backslash as `\\`.
+pub async fn serve<A: ToSocketAddrs>(addr: A, state: Arc<SharedState>) -> anyhow::Result<()> {
+    let throttle = Throttle::new(Limits::from_env()?);
+    let listener = TcpListener::bind(addr).await?;
+    tracing::info!("TCP interface listening on: {}", listener.local_addr()?);
+
+    loop {
+        let (conn, addr, permit) = throttle.accept(&listener).await?;
+        tokio::spawn(telemetry::connection(
+            addr,
+            permit.hold(handle_connection(conn, state.clone())),
+        ));
+    }
+}
+
+async fn handle_connection(stream: TcpStream, state: Arc<SharedState>) -> anyhow::Result<()> {
+    let (reader, mut writer) = tokio::io::split(Metered::new(stream));
+    let mut reader = LineReader::new(reader, MAX_REQUEST_SIZE);
+
+    while let Some(line) = reader.read_line().await? {
+        for (key, value) in execute(&state, Request::from_string(line, state.prefix_scans)).await? {
+            writer
+                .write_all(format!("{}={}\n", escape(&key), escape(&value)).as_bytes())
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+// so a response is always a single line
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::{TcpListener, TcpStream, UdpSocket},
+    };
+
+    use crate::{db::KeyValue, SharedState};
+
+    #[tokio::test]
+    async fn escape_newlines_in_responses() {
+        let state = Arc::new(SharedState {
+            kv: KeyValue::default(),
+            socket: UdpSocket::bind("127.0.0.1:0").await.unwrap(),
+            limiter: None,
+            replica: None,
+            prefix_scans: false,
+        });
+        // as if it was inserted over UDP, where a request may span lines
+        state.kv.set("key".into(), "multi\nline\\".into()).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (conn, _) = listener.accept().await.unwrap();
+        tokio::spawn(super::handle_connection(conn, state));
+
+        client.write_all(b"key\nversion\n").await.unwrap();
+        let mut lines = BufReader::new(client).lines();
+        assert_eq!(
+            lines.next_line().await.unwrap().unwrap(),
+            "key=multi\\nline\\\\"
+        );
+        assert_eq!(
+            lines.next_line().await.unwrap().unwrap(),
+            "version=Ken's Key-Value Store 1.0"
+        );
+    }
+}
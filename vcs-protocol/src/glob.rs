@@ -0,0 +1,96 @@
+use std::{fmt, str::FromStr};
+
+/// A pattern the names of listed files and dirs are matched against
+///
+/// '*' matches any run of characters (including none) and '?' matches a single one,
+/// anything else only matches itself
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Glob(String);
+
+#[derive(thiserror::Error, Debug)]
+#[error("illegal glob pattern")]
+pub struct IllegalPattern;
+
+impl FromStr for Glob {
+    type Err = IllegalPattern;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // a pattern matches a single path component
+        let legal = !s.is_empty()
+            && s.chars()
+                .all(|char| char.is_alphanumeric() || matches!(char, '.' | '_' | '-' | '*' | '?'));
+
+        match legal {
+            true => Ok(Self(s.into())),
+            false => Err(IllegalPattern),
+        }
+    }
+}
+
+impl fmt::Display for Glob {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Glob {
+    pub fn matches(&self, name: &str) -> bool {
+        let pattern: Vec<char> = self.0.chars().collect();
+        let name: Vec<char> = name.chars().collect();
+
+        // the positions right after the last '*', to backtrack to when a match fails
+        let mut star: Option<(usize, usize)> = None;
+        let (mut p, mut n) = (0, 0);
+        while n < name.len() {
+            match pattern.get(p) {
+                Some('*') => {
+                    star = Some((p + 1, n));
+                    p += 1;
+                }
+                Some(&char) if char == '?' || char == name[n] => {
+                    p += 1;
+                    n += 1;
+                }
+                // let the last '*' swallow one more character
+                _ => match star {
+                    Some((star_p, star_n)) => {
+                        star = Some((star_p, star_n + 1));
+                        p = star_p;
+                        n = star_n + 1;
+                    }
+                    None => return false,
+                },
+            }
+        }
+
+        pattern[p..].iter().all(|char| *char == '*')
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Glob;
+
+    #[test]
+    fn match_names() {
+        let matches = |pattern: &str, name: &str| pattern.parse::<Glob>().unwrap().matches(name);
+
+        assert!(matches("*.rs", "main.rs"));
+        assert!(matches("*.rs", ".rs"));
+        assert!(!matches("*.rs", "main.rs.bak"));
+        assert!(matches("ma?n.*", "main.rs"));
+        assert!(!matches("ma?n.*", "man.rs"));
+        assert!(matches("*a*b*", "xaybzb"));
+        assert!(!matches("*a*b*", "xbya"));
+        assert!(matches("*", ""));
+        assert!(matches("main.rs", "main.rs"));
+        assert!(!matches("main.rs", "main.rss"));
+    }
+
+    #[test]
+    fn reject_illegal_patterns() {
+        for pattern in ["", "src/*.rs", "[ab].rs", "a b"] {
+            assert!(pattern.parse::<Glob>().is_err(), "{}", pattern);
+        }
+    }
+}
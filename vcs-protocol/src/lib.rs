@@ -0,0 +1,16 @@
+//! The wire format of the voracious code storage, shared by the server and its client
+//!
+//! a request is a single line, and a response is a status line (followed by the lines or
+//! the content its request asks for) that ends with a READY line.
+//!
+//! see: https://protohackers.com/problem/10
+mod glob;
+mod request;
+mod response;
+
+pub use glob::{Glob, IllegalPattern};
+pub use request::{Encoding, Request, RequestErr};
+pub use response::{
+    format_child, format_revision, parse_child, parse_revision, ListResult, Metadata, Response,
+    Status, READY,
+};
@@ -0,0 +1,535 @@
+use std::{fmt, str::FromStr};
+
+use crate::Glob;
+
+/// How file contents are encoded on the wire
+///
+/// files are always stored decoded, the encoding only applies to the transfer itself
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Encoding {
+    #[default]
+    Plain,
+    Gzip,
+}
+
+const PUT_USAGE_MSG: &str = "PUT file length [gzip] newline data";
+const GET_USAGE_MSG: &str = "GET file [revision] [gzip]";
+const LIST_USAGE_MSG: &str = "LIST dir [pattern] [-r]";
+const DEL_USAGE_MSG: &str = "DEL file [revision]";
+const STAT_USAGE_MSG: &str = "STAT file";
+const TENANT_USAGE_MSG: &str = "TENANT token";
+
+/// A request line, as the client writes it and the server parses it
+#[derive(Debug, Clone, PartialEq)]
+pub enum Request {
+    Put {
+        filename: String,
+        // the number of bytes on the wire, i.e. after encoding
+        byte_count: u64,
+        encoding: Encoding,
+    },
+    Get {
+        filename: String,
+        revision: Option<u64>,
+        encoding: Encoding,
+    },
+    List {
+        path: String,
+        pattern: Option<Glob>,
+        recursive: bool,
+    },
+    Del {
+        filename: String,
+        revision: Option<u64>,
+    },
+    Stat {
+        filename: String,
+    },
+    Tenant {
+        token: String,
+    },
+    Help,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum RequestErr {
+    #[error("illegal method: {0}")]
+    IllegalMethod(String),
+
+    #[error("usage: {0}")]
+    BadUsage(String),
+
+    #[error("illegal file name")]
+    IllegalFileName,
+
+    #[error("illegal dir name")]
+    IllegalDirName,
+
+    #[error("illegal tenant token")]
+    IllegalToken,
+
+    #[error("illegal glob pattern")]
+    IllegalPattern,
+}
+
+impl FromStr for Request {
+    type Err = RequestErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().split_ascii_whitespace();
+
+        let method = parts
+            .next()
+            // method is case insensitive
+            .map(|method| method.to_uppercase())
+            .unwrap_or_default();
+
+        match method.as_str() {
+            "PUT" => {
+                // parse put request
+                let filename: String = parts
+                    .next()
+                    .ok_or_else(|| RequestErr::BadUsage(PUT_USAGE_MSG.into()))?
+                    .into();
+                if !check_filename(&filename) {
+                    return Err(RequestErr::IllegalFileName);
+                }
+
+                let byte_count = parts
+                    .next()
+                    .and_then(|value| value.parse().ok())
+                    .ok_or_else(|| RequestErr::BadUsage(PUT_USAGE_MSG.into()))?;
+
+                let mut parts = parts.peekable();
+                let encoding = parse_encoding(&mut parts);
+
+                // make sure we've consumed the entire line
+                if parts.next().is_some() {
+                    return Err(RequestErr::BadUsage(PUT_USAGE_MSG.into()));
+                }
+
+                Ok(Self::Put {
+                    filename,
+                    byte_count,
+                    encoding,
+                })
+            }
+            "GET" => {
+                // parse get request
+                let filename: String = parts
+                    .next()
+                    .ok_or_else(|| RequestErr::BadUsage(GET_USAGE_MSG.into()))?
+                    .into();
+                if !check_filename(&filename) {
+                    return Err(RequestErr::IllegalFileName);
+                }
+
+                // the revision is optional, so the encoding may come right after the file name
+                let mut parts = parts.peekable();
+                let mut encoding = parse_encoding(&mut parts);
+
+                let revision = match encoding {
+                    Encoding::Plain => parts
+                        .next()
+                        .map(|value| value.strip_prefix('r').unwrap_or(value)),
+                    Encoding::Gzip => None,
+                };
+
+                let revision = match revision {
+                    Some(revision) => Some(
+                        revision
+                            .parse()
+                            .map_err(|_| RequestErr::BadUsage(GET_USAGE_MSG.into()))?,
+                    ),
+                    None => None,
+                };
+
+                if revision.is_some() {
+                    encoding = parse_encoding(&mut parts);
+                }
+
+                // make sure we've consumed the entire line
+                if parts.next().is_some() {
+                    return Err(RequestErr::BadUsage(GET_USAGE_MSG.into()));
+                }
+
+                Ok(Self::Get {
+                    filename,
+                    revision,
+                    encoding,
+                })
+            }
+            "LIST" => {
+                let path: String = validate_dirpath(
+                    parts
+                        .next()
+                        .ok_or_else(|| RequestErr::BadUsage(LIST_USAGE_MSG.into()))?
+                        .into(),
+                )?;
+
+                // the pattern and the recursive flag may come in any order
+                let mut pattern = None;
+                let mut recursive = false;
+                for part in parts {
+                    if part == "-r" && !recursive {
+                        recursive = true;
+                    } else if part != "-r" && pattern.is_none() {
+                        pattern = Some(part.parse().map_err(|_| RequestErr::IllegalPattern)?);
+                    } else {
+                        return Err(RequestErr::BadUsage(LIST_USAGE_MSG.into()));
+                    }
+                }
+
+                Ok(Self::List {
+                    path,
+                    pattern,
+                    recursive,
+                })
+            }
+            "DEL" => {
+                let filename: String = parts
+                    .next()
+                    .ok_or_else(|| RequestErr::BadUsage(DEL_USAGE_MSG.into()))?
+                    .into();
+                if !check_filename(&filename) {
+                    return Err(RequestErr::IllegalFileName);
+                }
+
+                let revision = match parts.next() {
+                    Some(value) => Some(
+                        value
+                            .strip_prefix('r')
+                            .unwrap_or(value)
+                            .parse()
+                            .map_err(|_| RequestErr::BadUsage(DEL_USAGE_MSG.into()))?,
+                    ),
+                    None => None,
+                };
+
+                // make sure we've consumed the entire line
+                if parts.next().is_some() {
+                    return Err(RequestErr::BadUsage(DEL_USAGE_MSG.into()));
+                }
+
+                Ok(Self::Del { filename, revision })
+            }
+            "STAT" => {
+                let filename: String = parts
+                    .next()
+                    .ok_or_else(|| RequestErr::BadUsage(STAT_USAGE_MSG.into()))?
+                    .into();
+                if !check_filename(&filename) {
+                    return Err(RequestErr::IllegalFileName);
+                }
+
+                // make sure we've consumed the entire line
+                if parts.next().is_some() {
+                    return Err(RequestErr::BadUsage(STAT_USAGE_MSG.into()));
+                }
+
+                Ok(Self::Stat { filename })
+            }
+            "TENANT" => {
+                let token: String = parts
+                    .next()
+                    .ok_or_else(|| RequestErr::BadUsage(TENANT_USAGE_MSG.into()))?
+                    .into();
+                // the token names a single root dir of the namespace
+                if token.contains('/') || !validate_strippted_path(&token) {
+                    return Err(RequestErr::IllegalToken);
+                }
+
+                // make sure we've consumed the entire line
+                if parts.next().is_some() {
+                    return Err(RequestErr::BadUsage(TENANT_USAGE_MSG.into()));
+                }
+
+                Ok(Self::Tenant { token })
+            }
+            "HELP" => Ok(Self::Help),
+            _ => Err(RequestErr::IllegalMethod(method.to_string())),
+        }
+    }
+}
+
+// the inverse of parsing, writes a request line (without its newline)
+impl fmt::Display for Request {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Put {
+                filename,
+                byte_count,
+                encoding,
+            } => {
+                write!(f, "PUT {} {}", filename, byte_count)?;
+                write_encoding(f, *encoding)
+            }
+            Self::Get {
+                filename,
+                revision,
+                encoding,
+            } => {
+                write!(f, "GET {}", filename)?;
+                if let Some(revision) = revision {
+                    write!(f, " r{}", revision)?;
+                }
+                write_encoding(f, *encoding)
+            }
+            Self::List {
+                path,
+                pattern,
+                recursive,
+            } => {
+                write!(f, "LIST {}", path)?;
+                if let Some(pattern) = pattern {
+                    write!(f, " {}", pattern)?;
+                }
+                match recursive {
+                    true => write!(f, " -r"),
+                    false => Ok(()),
+                }
+            }
+            Self::Del { filename, revision } => {
+                write!(f, "DEL {}", filename)?;
+                match revision {
+                    Some(revision) => write!(f, " r{}", revision),
+                    None => Ok(()),
+                }
+            }
+            Self::Stat { filename } => write!(f, "STAT {}", filename),
+            Self::Tenant { token } => write!(f, "TENANT {}", token),
+            Self::Help => write!(f, "HELP"),
+        }
+    }
+}
+
+fn write_encoding(f: &mut fmt::Formatter<'_>, encoding: Encoding) -> fmt::Result {
+    match encoding {
+        Encoding::Plain => Ok(()),
+        Encoding::Gzip => write!(f, " gzip"),
+    }
+}
+
+// consumes the next part if it names a supported encoding (case insensitive)
+fn parse_encoding<'a>(parts: &mut std::iter::Peekable<impl Iterator<Item = &'a str>>) -> Encoding {
+    match parts.next_if(|part| part.eq_ignore_ascii_case("gzip")) {
+        Some(_) => Encoding::Gzip,
+        None => Encoding::Plain,
+    }
+}
+
+// checks that the filename matches the expected format
+fn check_filename(filename: &str) -> bool {
+    // files should always start at root
+    if !filename.starts_with('/') {
+        return false;
+    }
+
+    let filename = &filename[1..];
+
+    // file name can not be empty
+    if filename.trim().is_empty() {
+        return false;
+    }
+
+    // each part of the path most contain something
+    if !validate_strippted_path(filename) {
+        return false;
+    }
+
+    true
+}
+
+// checks that a dir name matches the expected format
+// and return a unified view of this dir
+fn validate_dirpath(mut dir: String) -> Result<String, RequestErr> {
+    // dir path should always start at root
+    if !dir.starts_with('/') {
+        return Err(RequestErr::IllegalDirName);
+    }
+
+    // check for proper naming
+    if !dir
+        .chars()
+        .all(|char| char.is_alphanumeric() || char == '.' || char == '_' || char == '/')
+    {
+        return Err(RequestErr::IllegalDirName);
+    }
+
+    // dir may, or may not, end with a '/'
+    if !dir.ends_with('/') {
+        dir.push('/');
+    }
+
+    // each part of the path most contain something and be one of "alphanumeric, dot, underscore"
+    if dir.len() > 1 && !validate_strippted_path(&dir[1..dir.len() - 1]) {
+        return Err(RequestErr::IllegalDirName);
+    }
+
+    Ok(dir)
+}
+
+fn validate_strippted_path(path: &str) -> bool {
+    path.split('/').all(|part| {
+        !part.is_empty()
+            && part
+                .chars()
+                .all(|char| char.is_alphanumeric() || char == '.' || char == '_' || char == '-')
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Encoding, Request};
+
+    #[test]
+    fn check_valid_request_parsing() {
+        let raw_requests = [
+            "puT /test.txt 35",
+            "GEt /text.txt",
+            "GeT /text.txt 90",
+            "gET /text.txt r5",
+            "LIST /test/",
+            "LIST /test/test2/test44/../test5",
+            "PuT /v.-WC1CDakNoPWm4YiOxD7p-F2VC8-AahIWXRQ/gHDhPY8euDkFdTa3lo5oPsV7-KpOQKknmnNSRHX4jKxm9omKLVrZPB3WIQ27nLB.h2KjsMx-q5H_GU0F9eIXyFPcgu 57",
+            "PUT /test.txt 35 gzip",
+            "GET /text.txt GZIP",
+            "GET /text.txt r5 gzip",
+            "tenant team-1.a_b",
+            "DEL /dir/text.txt",
+            "del /text.txt r3",
+            "LIST /src/ *.rs -r",
+            "list / -r ma?n.*",
+            "LIST /src *",
+            "stat /dir/text.txt",
+        ];
+
+        let expected_requests = [
+            Request::Put {
+                filename: "/test.txt".into(),
+                byte_count: 35,
+                encoding: Encoding::Plain,
+            },
+            Request::Get {
+                filename: "/text.txt".into(),
+                revision: None,
+                encoding: Encoding::Plain,
+            },
+            Request::Get {
+                filename: "/text.txt".into(),
+                revision: Some(90),
+                encoding: Encoding::Plain,
+            },
+            Request::Get {
+                filename: "/text.txt".into(),
+                revision: Some(5),
+                encoding: Encoding::Plain,
+            },
+            Request::List {
+                path: "/test/".into(),
+                pattern: None,
+                recursive: false,
+            },
+            Request::List {
+                path: "/test/test2/test44/../test5/".into(),
+                pattern: None,
+                recursive: false,
+            },
+            Request::Put { filename: "/v.-WC1CDakNoPWm4YiOxD7p-F2VC8-AahIWXRQ/gHDhPY8euDkFdTa3lo5oPsV7-KpOQKknmnNSRHX4jKxm9omKLVrZPB3WIQ27nLB.h2KjsMx-q5H_GU0F9eIXyFPcgu".into(), byte_count: 57, encoding: Encoding::Plain },
+            Request::Put {
+                filename: "/test.txt".into(),
+                byte_count: 35,
+                encoding: Encoding::Gzip,
+            },
+            Request::Get {
+                filename: "/text.txt".into(),
+                revision: None,
+                encoding: Encoding::Gzip,
+            },
+            Request::Get {
+                filename: "/text.txt".into(),
+                revision: Some(5),
+                encoding: Encoding::Gzip,
+            },
+            Request::Tenant {
+                token: "team-1.a_b".into(),
+            },
+            Request::Del {
+                filename: "/dir/text.txt".into(),
+                revision: None,
+            },
+            Request::Del {
+                filename: "/text.txt".into(),
+                revision: Some(3),
+            },
+            Request::List {
+                path: "/src/".into(),
+                pattern: Some("*.rs".parse().unwrap()),
+                recursive: true,
+            },
+            Request::List {
+                path: "/".into(),
+                pattern: Some("ma?n.*".parse().unwrap()),
+                recursive: true,
+            },
+            Request::List {
+                path: "/src/".into(),
+                pattern: Some("*".parse().unwrap()),
+                recursive: false,
+            },
+            Request::Stat {
+                filename: "/dir/text.txt".into(),
+            },
+        ];
+
+        for (request, expected) in raw_requests.into_iter().zip(expected_requests.iter()) {
+            let request = match request.parse::<Request>() {
+                Ok(request) => request,
+                Err(reason) => panic!("failed to parse\n{}\nreason: {}", request, reason),
+            };
+
+            assert_eq!(request, *expected);
+        }
+    }
+
+    #[test]
+    fn check_bad_request_parsing() {
+        let bad_request = [
+            "PUT /text.txt",
+            "PUT /text abc",
+            "PUT /text r2",
+            "GET /text\\. text",
+            "GET /text.txt 123 123",
+            "GET /text/ 12",
+            "GET /text//test 12",
+            "LIST /test//",
+            "LIST",
+            "LISt /test//test/",
+            "LiSt /test/../test//",
+            "PuT PUT /mbA+u|=]hj)oMraH0pS 123",
+            "PUT /text.txt 35 gzip gzip",
+            "PUT /text.txt 35 deflate",
+            "GET /text.txt gzip r5",
+            "TENANT",
+            "TENANT team/1",
+            "TENANT @127.0.0.1",
+            "TENANT a b",
+            "DEL",
+            "DEL /dir/",
+            "DEL text.txt",
+            "DEL /text.txt rr3",
+            "DEL /text.txt r3 r4",
+            "LIST / *.rs *.md",
+            "LIST / -r -r",
+            "LIST / src/*.rs",
+            "STAT",
+            "STAT /dir/",
+            "STAT /text.txt r1",
+        ];
+
+        for request in bad_request {
+            let request: Result<Request, _> = request.parse();
+            assert!(request.is_err())
+        }
+    }
+}
@@ -0,0 +1,249 @@
+use std::{
+    fmt,
+    time::{Duration, SystemTime},
+};
+
+/// The line every response ends with (along with its newline),
+/// the server also sends it once a client connects
+pub const READY: &str = "READY\n";
+
+const USAGE_MSG: &str = "usage: HELP|GET|PUT|LIST|DEL|STAT";
+
+/// What's known about a revision besides its content
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    /// When the revision was stored, it's sent in whole seconds
+    pub created: SystemTime,
+    /// The size of the decoded content, in bytes
+    pub size: u64,
+}
+
+impl Metadata {
+    /// The metadata of a revision that is stored right now
+    pub fn now(size: u64) -> Self {
+        Self {
+            created: SystemTime::now(),
+            size,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListResult {
+    Dir(String),
+    File { name: String, last_revision: u64 },
+}
+
+impl ListResult {
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Dir(name) => name,
+            Self::File { name, .. } => name,
+        }
+    }
+}
+
+/// The lines of a response, up to (and not including) its READY
+///
+/// the content of a GET follows its status line, it's written by the server on its own
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Response {
+    Put { revision: u64 },
+    // the size of the content on the wire, i.e. after encoding
+    Get { size: u64 },
+    List { children: Vec<ListResult> },
+    Stat { revisions: Vec<(u64, Metadata)> },
+    Help,
+    Ok,
+    Err(String),
+}
+
+impl fmt::Display for Response {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Put { revision } => writeln!(f, "OK r{}", revision),
+            Self::Get { size } => writeln!(f, "OK {}", size),
+            // the status is followed by a line per child
+            Self::List { children } => {
+                writeln!(f, "OK {}", children.len())?;
+                for child in children {
+                    writeln!(f, "{}", format_child(child))?;
+                }
+                Ok(())
+            }
+            // the status is followed by a line per revision
+            Self::Stat { revisions } => {
+                writeln!(f, "OK {}", revisions.len())?;
+                for (revision, meta) in revisions {
+                    writeln!(f, "{}", format_revision(*revision, meta))?;
+                }
+                Ok(())
+            }
+            Self::Help => writeln!(f, "OK {}", USAGE_MSG),
+            Self::Ok => writeln!(f, "OK"),
+            Self::Err(reason) => writeln!(f, "ERR {}", reason),
+        }
+    }
+}
+
+/// The status line of a response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    // whatever follows the OK, which depends on the request
+    Ok(String),
+    Err(String),
+}
+
+impl Status {
+    /// Parses a status line (without its newline), None if it's neither an OK nor an ERR
+    pub fn parse(line: &str) -> Option<Self> {
+        if let Some(reason) = line.strip_prefix("ERR ") {
+            return Some(Self::Err(reason.into()));
+        }
+
+        match line.strip_prefix("OK")? {
+            "" => Some(Self::Ok(String::new())),
+            rest => rest.strip_prefix(' ').map(|rest| Self::Ok(rest.into())),
+        }
+    }
+}
+
+/// The line of a listed child, "<name>/ DIR" for a dir and "<name> r<revision>" for a file
+pub fn format_child(child: &ListResult) -> String {
+    match child {
+        ListResult::Dir(name) => format!("{}/ DIR", name),
+        ListResult::File {
+            name,
+            last_revision,
+        } => format!("{} r{}", name, last_revision),
+    }
+}
+
+pub fn parse_child(line: &str) -> Option<ListResult> {
+    match line.split_once(' ')? {
+        (name, "DIR") => name
+            .strip_suffix('/')
+            .map(|name| ListResult::Dir(name.into())),
+        (name, revision) => Some(ListResult::File {
+            name: name.into(),
+            last_revision: revision.strip_prefix('r')?.parse().ok()?,
+        }),
+    }
+}
+
+/// The line of a revision of a STAT, "r<revision> <created> <size>"
+/// with the creation time in seconds since the unix epoch
+pub fn format_revision(revision: u64, meta: &Metadata) -> String {
+    let created = meta
+        .created
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    format!("r{} {} {}", revision, created, meta.size)
+}
+
+pub fn parse_revision(line: &str) -> Option<(u64, Metadata)> {
+    let mut parts = line.split(' ');
+    let revision = parts.next()?.strip_prefix('r')?.parse().ok()?;
+    let created = parts.next()?.parse().ok()?;
+    let size = parts.next()?.parse().ok()?;
+    if parts.next().is_some() {
+        return None;
+    }
+
+    let meta = Metadata {
+        created: SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(created))?,
+        size,
+    };
+    Some((revision, meta))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::{parse_child, parse_revision, ListResult, Metadata, Response, Status};
+
+    // parses the lines of a response back, the way the client reads them
+    fn parse(response: Response) -> (Status, Vec<String>) {
+        let response = response.to_string();
+        let mut lines = response.lines();
+        let status = Status::parse(lines.next().unwrap()).unwrap();
+        (status, lines.map(String::from).collect())
+    }
+
+    #[test]
+    fn parse_written_responses() {
+        let meta = Metadata {
+            created: SystemTime::UNIX_EPOCH + Duration::from_secs(1700000000),
+            size: 12,
+        };
+        let children = vec![
+            ListResult::File {
+                name: "a.txt".into(),
+                last_revision: 3,
+            },
+            ListResult::Dir("src".into()),
+        ];
+
+        let (status, lines) = parse(Response::List {
+            children: children.clone(),
+        });
+        assert_eq!(status, Status::Ok("2".into()));
+        let parsed: Vec<_> = lines.iter().map(|line| parse_child(line)).collect();
+        assert_eq!(parsed, children.into_iter().map(Some).collect::<Vec<_>>());
+
+        let (status, lines) = parse(Response::Stat {
+            revisions: vec![(1, meta)],
+        });
+        assert_eq!(status, Status::Ok("1".into()));
+        assert_eq!(
+            lines
+                .iter()
+                .map(|line| parse_revision(line))
+                .collect::<Vec<_>>(),
+            [Some((1, meta))]
+        );
+
+        assert_eq!(
+            parse(Response::Put { revision: 2 }),
+            (Status::Ok("r2".into()), vec![])
+        );
+        assert_eq!(
+            parse(Response::Get { size: 5 }),
+            (Status::Ok("5".into()), vec![])
+        );
+        assert_eq!(parse(Response::Ok), (Status::Ok("".into()), vec![]));
+        assert_eq!(
+            parse(Response::Err("no such file".into())),
+            (Status::Err("no such file".into()), vec![])
+        );
+    }
+
+    #[test]
+    fn parse_bad_lines() {
+        assert_eq!(Status::parse("READY"), None);
+        assert_eq!(Status::parse("OKAY"), None);
+        assert_eq!(parse_child("a.txt"), None);
+        assert_eq!(parse_child("a.txt 3"), None);
+        assert_eq!(parse_child("src DIR"), None);
+    }
+
+    #[test]
+    fn parse_revision_lines() {
+        let (revision, meta) = parse_revision("r3 1700000000 12").unwrap();
+        assert_eq!(revision, 3);
+        assert_eq!(
+            meta.created,
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1700000000)
+        );
+        assert_eq!(meta.size, 12);
+
+        assert_eq!(parse_revision("3 1700000000 12"), None);
+        assert_eq!(parse_revision("r3 1700000000"), None);
+        assert_eq!(parse_revision("r3 1700000000 12 1"), None);
+        // a creation time that no system time can hold
+        assert_eq!(parse_revision(&format!("r3 {} 12", u64::MAX)), None);
+    }
+}
@@ -0,0 +1,101 @@
+use std::time::Duration;
+
+use crate::{scenarios::Kind, servers::Server, DEFAULT_TIMEOUT};
+
+pub const USAGE: &str =
+    "usage: verifier [--kind happy-path|malformed|concurrency|slow-loris]... [--timeout <secs>] [<server>...]";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    // every server when none is given
+    pub servers: Vec<Server>,
+    // every kind of scenario when none is given
+    pub kinds: Vec<Kind>,
+    // how long a single scenario may take
+    pub timeout: Duration,
+}
+
+impl Options {
+    /// Parses the command line arguments, without the program name
+    pub fn parse(args: impl IntoIterator<Item = String>) -> anyhow::Result<Self> {
+        let mut servers = Vec::new();
+        let mut kinds = Vec::new();
+        let mut timeout = DEFAULT_TIMEOUT;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if !arg.starts_with("--") {
+                servers.push(
+                    arg.parse()
+                        .map_err(|err| anyhow::anyhow!("{}\n{}", err, USAGE))?,
+                );
+                continue;
+            }
+
+            let value = args
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("{} is missing its value\n{}", arg, USAGE))?;
+            match arg.as_str() {
+                "--kind" => kinds.push(value.parse()?),
+                "--timeout" => {
+                    timeout = Duration::from_secs_f64(
+                        value
+                            .parse()
+                            .map_err(|err| anyhow::anyhow!("bad value for --timeout: {}", err))?,
+                    )
+                }
+                _ => anyhow::bail!("unknown flag: {}\n{}", arg, USAGE),
+            }
+        }
+
+        if servers.is_empty() {
+            servers = Server::ALL.to_vec();
+        }
+        if kinds.is_empty() {
+            kinds = Kind::ALL.to_vec();
+        }
+
+        Ok(Self {
+            servers,
+            kinds,
+            timeout,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Options;
+    use crate::{scenarios::Kind, servers::Server, DEFAULT_TIMEOUT};
+
+    fn parse(args: &[&str]) -> anyhow::Result<Options> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parse_selection() {
+        let options = parse(&[]).unwrap();
+        assert_eq!(options.servers, Server::ALL);
+        assert_eq!(options.kinds, Kind::ALL);
+        assert_eq!(options.timeout, DEFAULT_TIMEOUT);
+
+        let options = parse(&[
+            "prime-time",
+            "--kind",
+            "slow-loris",
+            "budget-chat",
+            "--timeout",
+            "2.5",
+        ])
+        .unwrap();
+        assert_eq!(options.servers, [Server::PrimeTime, Server::BudgetChat]);
+        assert_eq!(options.kinds, [Kind::SlowLoris]);
+        assert_eq!(options.timeout, Duration::from_millis(2500));
+
+        assert!(parse(&["prime-time", "--kind"]).is_err());
+        assert!(parse(&["--kind", "fast"]).is_err());
+        assert!(parse(&["pest-control"]).is_err());
+    }
+}
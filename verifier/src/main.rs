@@ -0,0 +1,62 @@
+//! Runs protocol conformance scenarios against the servers of this repo, a local substitute
+//! for the protohackers checker
+//!
+//! every scenario starts its own instance of the server in-process, on an ephemeral port,
+//! and talks to it through real sockets
+use std::{env, time::Duration};
+
+use args::Options;
+use scenarios::Scenario;
+use tokio::time::Instant;
+
+mod args;
+mod scenarios;
+mod servers;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let options = Options::parse(env::args().skip(1))?;
+
+    let mut passed = 0;
+    let mut failed = Vec::new();
+    for server in &options.servers {
+        for scenario in scenarios::of(*server) {
+            if !options.kinds.contains(&scenario.kind) {
+                continue;
+            }
+
+            match run(&scenario, options.timeout).await {
+                Ok(elapsed) => {
+                    println!("PASS {} ({:?})", scenario, elapsed);
+                    passed += 1;
+                }
+                Err(err) => {
+                    println!("FAIL {}: {:#}", scenario, err);
+                    failed.push(scenario.to_string());
+                }
+            }
+        }
+    }
+
+    println!();
+    println!("{} passed, {} failed", passed, failed.len());
+    for scenario in &failed {
+        println!("  {}", scenario);
+    }
+
+    anyhow::ensure!(failed.is_empty(), "some of the scenarios have failed");
+    Ok(())
+}
+
+/// Runs the scenario against a fresh instance of its server, returns how long it took
+async fn run(scenario: &Scenario, timeout: Duration) -> anyhow::Result<Duration> {
+    let addr = scenario.server.start().await?;
+
+    let started = Instant::now();
+    match tokio::time::timeout(timeout, (scenario.check)(addr)).await {
+        Ok(result) => result.map(|()| started.elapsed()),
+        Err(_) => anyhow::bail!("timed out after {:?}", timeout),
+    }
+}
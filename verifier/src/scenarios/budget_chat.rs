@@ -0,0 +1,118 @@
+use std::net::SocketAddr;
+
+use super::{expect_eq, while_trickling, Check, Kind, Lines};
+
+const USERS: usize = 5;
+
+pub fn scenarios() -> Vec<(Kind, &'static str, Check)> {
+    vec![
+        scenario!(Kind::HappyPath, "chat", chat),
+        scenario!(Kind::Malformed, "illegal-name", illegal_name),
+        scenario!(Kind::Concurrency, "broadcast", broadcast),
+        scenario!(Kind::SlowLoris, "trickled-name", trickled_name),
+    ]
+}
+
+// Connects and joins the room, returns the user list the room has sent
+async fn join(addr: SocketAddr, name: &str) -> anyhow::Result<(Lines, String)> {
+    let mut user = Lines::connect(addr).await?;
+    user.recv().await?; // the welcome message
+    user.send(name).await?;
+
+    let userlist = user.recv().await?;
+    anyhow::ensure!(
+        userlist.starts_with('*'),
+        "expected the user list, received {:?}",
+        userlist
+    );
+    Ok((user, userlist))
+}
+
+async fn chat(addr: SocketAddr) -> anyhow::Result<()> {
+    let (mut alice, _) = join(addr, "alice").await?;
+    let (mut bob, userlist) = join(addr, "bob").await?;
+    anyhow::ensure!(
+        userlist.contains("alice"),
+        "alice is missing from the user list {:?}",
+        userlist
+    );
+
+    let joined = alice.recv().await?;
+    anyhow::ensure!(
+        joined.starts_with("* bob "),
+        "expected the join message of bob, received {:?}",
+        joined
+    );
+
+    bob.send("hello, alice").await?;
+    alice.expect("[bob] hello, alice").await?;
+
+    drop(bob);
+    let left = alice.recv().await?;
+    anyhow::ensure!(
+        left.starts_with("* bob "),
+        "expected the leave message of bob, received {:?}",
+        left
+    );
+    Ok(())
+}
+
+async fn illegal_name(addr: SocketAddr) -> anyhow::Result<()> {
+    let mut user = Lines::connect(addr).await?;
+    user.recv().await?; // the welcome message
+    user.send("b@d name").await?;
+    user.closed().await
+}
+
+async fn broadcast(addr: SocketAddr) -> anyhow::Result<()> {
+    // everyone joins before anyone speaks, so everyone hears all the messages of the others
+    let mut users = Vec::new();
+    for user in 0..USERS {
+        users.push(join(addr, &format!("user{}", user)).await?.0);
+    }
+
+    for (user, lines) in users.iter_mut().enumerate() {
+        lines.send(&format!("message from user{}", user)).await?;
+    }
+
+    for (user, lines) in users.iter_mut().enumerate() {
+        let mut received = Vec::new();
+        while received.len() < USERS - 1 {
+            let line = lines.recv().await?;
+            // skip the join messages of the users that joined later
+            if !line.starts_with('*') {
+                received.push(line);
+            }
+        }
+
+        received.sort();
+        let expected: Vec<_> = (0..USERS)
+            .filter(|other| *other != user)
+            .map(|other| format!("[user{}] message from user{}", other, other))
+            .collect();
+        expect_eq(received, expected)?;
+    }
+
+    Ok(())
+}
+
+async fn trickled_name(addr: SocketAddr) -> anyhow::Result<()> {
+    let mut slow = Lines::connect(addr).await?;
+    slow.recv().await?; // the welcome message
+
+    // quick stays in the room, so it's on the user list of slowpoke
+    let mut quick = None;
+    while_trickling(&mut slow.writer, b"slowpoke\n", async {
+        quick = Some(join(addr, "quick").await?);
+        Ok(())
+    })
+    .await?;
+
+    let userlist = slow.recv().await?;
+    anyhow::ensure!(
+        userlist.contains("quick"),
+        "quick is missing from the user list {:?}",
+        userlist
+    );
+    Ok(())
+}
@@ -0,0 +1,137 @@
+use std::net::SocketAddr;
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+    task::JoinSet,
+};
+
+use super::{expect_eq, while_trickling, Check, Kind};
+
+const CLIENTS: u8 = 5;
+
+pub fn scenarios() -> Vec<(Kind, &'static str, Check)> {
+    vec![
+        scenario!(Kind::HappyPath, "toys", toys),
+        scenario!(Kind::Malformed, "noop-cipher", noop_cipher),
+        scenario!(Kind::Concurrency, "parallel-sessions", parallel_sessions),
+        scenario!(Kind::SlowLoris, "trickled-spec", trickled_spec),
+    ]
+}
+
+// the cipher spec of xor(key), applied to every byte regardless of its position
+fn xor_spec(key: u8) -> [u8; 3] {
+    [0x02, key, 0x00]
+}
+
+/// A client of a session that is ciphered by xor(key) in both directions
+struct Session {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+    key: u8,
+}
+
+impl Session {
+    async fn connect(addr: SocketAddr, key: u8) -> anyhow::Result<Self> {
+        let mut session = Self::connect_quietly(addr, key).await?;
+        session.writer.write_all(&xor_spec(key)).await?;
+        Ok(session)
+    }
+
+    // connects without sending the cipher spec
+    async fn connect_quietly(addr: SocketAddr, key: u8) -> anyhow::Result<Self> {
+        let (reader, writer) = TcpStream::connect(addr).await?.into_split();
+        Ok(Self {
+            reader: BufReader::new(reader),
+            writer,
+            key,
+        })
+    }
+
+    fn cipher(&self, bytes: &[u8]) -> Vec<u8> {
+        bytes.iter().map(|byte| byte ^ self.key).collect()
+    }
+
+    async fn send(&mut self, line: &str) -> anyhow::Result<()> {
+        let line = self.cipher(format!("{}\n", line).as_bytes());
+        self.writer.write_all(&line).await?;
+        Ok(())
+    }
+
+    async fn expect(&mut self, expected: &str) -> anyhow::Result<()> {
+        // a ciphered newline is the key xor '\n'
+        let mut line = Vec::new();
+        if self.reader.read_until(b'\n' ^ self.key, &mut line).await? == 0 {
+            anyhow::bail!("the server has closed the connection");
+        }
+
+        let line = String::from_utf8(self.cipher(&line))?;
+        expect_eq(line.as_str(), &format!("{}\n", expected))
+    }
+}
+
+async fn toys(addr: SocketAddr) -> anyhow::Result<()> {
+    let mut session = Session::connect(addr, 0x5a).await?;
+    session.send("4x dog,5x car").await?;
+    session.expect("5x car").await?;
+    session.send("3x rat,2x cat").await?;
+    session.expect("3x rat").await?;
+
+    Ok(())
+}
+
+// a cipher that leaves every byte as it is must be refused
+async fn noop_cipher(addr: SocketAddr) -> anyhow::Result<()> {
+    for spec in [&[0x00][..], &xor_spec(0), &[0x02, 0xa0, 0x02, 0xa0, 0x00]] {
+        let mut session = Session::connect_quietly(addr, 0).await?;
+        session.writer.write_all(spec).await?;
+        session.writer.write_all(b"4x dog,5x car\n").await?;
+
+        let mut response = Vec::new();
+        session.reader.read_to_end(&mut response).await?;
+        expect_eq(response, Vec::new())?;
+    }
+
+    Ok(())
+}
+
+async fn parallel_sessions(addr: SocketAddr) -> anyhow::Result<()> {
+    // every session is open before any of them is served
+    let mut sessions = Vec::new();
+    for key in 1..=CLIENTS {
+        sessions.push(Session::connect(addr, key).await?);
+    }
+
+    let mut clients = JoinSet::new();
+    for (count, mut session) in (1..).zip(sessions) {
+        clients.spawn(async move {
+            session
+                .send(&format!("{}x car,{}x dog", count, count + 1))
+                .await?;
+            session.expect(&format!("{}x dog", count + 1)).await
+        });
+    }
+
+    while let Some(result) = clients.join_next().await {
+        result??;
+    }
+    Ok(())
+}
+
+async fn trickled_spec(addr: SocketAddr) -> anyhow::Result<()> {
+    let mut slow = Session::connect_quietly(addr, 0x11).await?;
+    let mut bytes = xor_spec(0x11).to_vec();
+    bytes.extend(slow.cipher(b"10x toy car,2x doll\n"));
+
+    let mut quick = Session::connect(addr, 0x22).await?;
+    while_trickling(&mut slow.writer, &bytes, async {
+        quick.send("1x ball,7x kite").await?;
+        quick.expect("7x kite").await
+    })
+    .await?;
+
+    slow.expect("10x toy car").await
+}
@@ -0,0 +1,98 @@
+use std::{collections::HashSet, net::SocketAddr};
+
+use protohackers_client::{job_centre::Client, ClientError};
+use serde_json::{json, Value};
+use tokio::task::JoinSet;
+
+use super::{expect_eq, while_trickling, Check, Kind, Lines};
+
+const QUEUE: &str = "verifier";
+const WORKERS: usize = 3;
+
+pub fn scenarios() -> Vec<(Kind, &'static str, Check)> {
+    vec![
+        scenario!(Kind::HappyPath, "put-get-delete", put_get_delete),
+        scenario!(Kind::Malformed, "bad-requests", bad_requests),
+        scenario!(Kind::Concurrency, "waiting-workers", waiting_workers),
+        scenario!(Kind::SlowLoris, "trickled-request", trickled_request),
+    ]
+}
+
+async fn put_get_delete(addr: SocketAddr) -> anyhow::Result<()> {
+    let mut client = Client::connect(addr).await?;
+    let low = client.put(QUEUE, json!({"n": 1}), 1).await?;
+    let high = client.put(QUEUE, json!({"n": 2}), 2).await?;
+
+    // the job with the highest priority comes first, and an aborted job goes back to its queue
+    let job = client.get(&[QUEUE], false).await?.map(|job| job.id);
+    expect_eq(job, Some(high))?;
+    anyhow::ensure!(client.abort(high).await?, "failed to abort {}", high);
+    anyhow::ensure!(client.delete(high).await?, "failed to delete {}", high);
+    anyhow::ensure!(!client.delete(high).await?, "deleted {} twice", high);
+
+    let job = client.get(&[QUEUE], false).await?.map(|job| job.id);
+    expect_eq(job, Some(low))?;
+    anyhow::ensure!(client.delete(low).await?, "failed to delete {}", low);
+    expect_eq(client.get(&[QUEUE], false).await?, None)
+}
+
+// a bad request is answered with an error, and the session goes on
+async fn bad_requests(addr: SocketAddr) -> anyhow::Result<()> {
+    let mut lines = Lines::connect(addr).await?;
+    for request in [
+        "not json".to_owned(),
+        json!({"request": "nope"}).to_string(),
+        json!({"request": "put", "queue": QUEUE, "pri": -1, "job": {}}).to_string(),
+        json!({"request": "get"}).to_string(),
+    ] {
+        lines.send(&request).await?;
+        let response: Value = serde_json::from_str(&lines.recv().await?)?;
+        expect_eq(&response["status"], &json!("error"))?;
+    }
+
+    let mut client = Client::connect(addr).await?;
+    match client.request(json!({"request": "delete"})).await {
+        Err(ClientError::Server(_)) => {}
+        response => anyhow::bail!("expected an error, received {:?}", response),
+    }
+    expect_eq(client.get(&[QUEUE], false).await?, None)
+}
+
+// every waiting worker takes a job of its own,
+// the workers stay connected, since the jobs of a worker that disconnects go back to their queue
+async fn waiting_workers(addr: SocketAddr) -> anyhow::Result<()> {
+    let mut workers = JoinSet::new();
+    for _ in 0..WORKERS {
+        let mut worker = Client::connect(addr).await?;
+        workers.spawn(async move {
+            match worker.get(&[QUEUE], true).await? {
+                Some(job) => Ok((worker, job.id)),
+                None => anyhow::bail!("a waiting worker was answered without a job"),
+            }
+        });
+    }
+
+    let mut producer = Client::connect(addr).await?;
+    let mut ids = HashSet::new();
+    for n in 0..WORKERS {
+        ids.insert(producer.put(QUEUE, json!({"n": n}), 1).await?);
+    }
+
+    let mut connected = Vec::new();
+    let mut taken = HashSet::new();
+    while let Some(result) = workers.join_next().await {
+        let (worker, id) = result??;
+        connected.push(worker);
+        taken.insert(id);
+    }
+    expect_eq(taken, ids)
+}
+
+async fn trickled_request(addr: SocketAddr) -> anyhow::Result<()> {
+    let mut slow = Lines::connect(addr).await?;
+    let request = json!({"request": "put", "queue": QUEUE, "pri": 1, "job": {}}).to_string() + "\n";
+    while_trickling(&mut slow.writer, request.as_bytes(), put_get_delete(addr)).await?;
+
+    let response: Value = serde_json::from_str(&slow.recv().await?)?;
+    expect_eq(&response["status"], &json!("ok"))
+}
@@ -0,0 +1,112 @@
+use std::{net::SocketAddr, time::Duration};
+
+use protohackers_client::line_reversal::Client;
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    net::UdpSocket,
+    task::JoinSet,
+};
+
+use super::{expect_eq, while_trickling, Check, Kind};
+
+const CLIENTS: usize = 5;
+// how long the server is given to (wrongly) answer an invalid packet
+const SILENCE: Duration = Duration::from_millis(500);
+
+pub fn scenarios() -> Vec<(Kind, &'static str, Check)> {
+    vec![
+        scenario!(Kind::HappyPath, "reverse-lines", reverse_lines),
+        scenario!(Kind::Malformed, "invalid-packets", invalid_packets),
+        scenario!(Kind::Concurrency, "parallel-sessions", parallel_sessions),
+        scenario!(Kind::SlowLoris, "trickled-line", trickled_line),
+    ]
+}
+
+fn reversed(line: &str) -> String {
+    line.chars().rev().collect()
+}
+
+async fn reverse_lines(addr: SocketAddr) -> anyhow::Result<()> {
+    let mut client = Client::connect(addr, lrcp::Config::default()).await?;
+    expect_eq(client.reverse("hello").await?, "olleh".into())?;
+
+    // a line longer than a single message, full of characters that have to be escaped
+    let line = "ab/\\".repeat(500);
+    expect_eq(client.reverse(&line).await?, reversed(&line))?;
+
+    client.close().await?;
+    Ok(())
+}
+
+// invalid packets are ignored, and don't get in the way of a valid session
+async fn invalid_packets(addr: SocketAddr) -> anyhow::Result<()> {
+    let socket = UdpSocket::bind("127.0.0.1:0").await?;
+    socket.connect(addr).await?;
+
+    let oversized = format!("/data/1/0/{}/", "a".repeat(1000));
+    for packet in [
+        "hello",
+        "/connect/",
+        "/connect/2147483648/",
+        "/data/1/0/",
+        "/ack/1/",
+        oversized.as_str(),
+    ] {
+        socket.send(packet.as_bytes()).await?;
+    }
+
+    let mut buffer = [0; 1000];
+    if let Ok(len) = tokio::time::timeout(SILENCE, socket.recv(&mut buffer)).await {
+        let len = len?;
+        anyhow::bail!(
+            "an invalid packet was answered with {:?}",
+            String::from_utf8_lossy(&buffer[..len])
+        );
+    }
+
+    for (packet, expected) in [("/connect/7/", "/ack/7/0/"), ("/close/7/", "/close/7/")] {
+        socket.send(packet.as_bytes()).await?;
+        let len = socket.recv(&mut buffer).await?;
+        expect_eq(String::from_utf8_lossy(&buffer[..len]).as_ref(), expected)?;
+    }
+
+    Ok(())
+}
+
+async fn parallel_sessions(addr: SocketAddr) -> anyhow::Result<()> {
+    let mut clients = JoinSet::new();
+    for client in 0..CLIENTS {
+        clients.spawn(async move {
+            let mut session = Client::connect(addr, lrcp::Config::default()).await?;
+            for line in 0..10 {
+                let line = format!("line {} of session {}", line, client);
+                expect_eq(session.reverse(&line).await?, reversed(&line))?;
+            }
+
+            session.close().await?;
+            Ok::<_, anyhow::Error>(())
+        });
+    }
+
+    while let Some(result) = clients.join_next().await {
+        result??;
+    }
+
+    Ok(())
+}
+
+async fn trickled_line(addr: SocketAddr) -> anyhow::Result<()> {
+    let (reader, mut writer) =
+        tokio::io::split(lrcp::connect(addr, lrcp::Config::default()).await?);
+    let line = "a line that takes its time";
+    while_trickling(
+        &mut writer,
+        format!("{}\n", line).as_bytes(),
+        reverse_lines(addr),
+    )
+    .await?;
+
+    let mut received = String::new();
+    BufReader::new(reader).read_line(&mut received).await?;
+    expect_eq(received, format!("{}\n", reversed(line)))
+}
@@ -0,0 +1,88 @@
+use std::net::SocketAddr;
+
+use protohackers_client::means_to_an_end::Client;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, ErrorKind},
+    net::TcpStream,
+    task::JoinSet,
+};
+
+use super::{expect_eq, while_trickling, Check, Kind};
+
+const CLIENTS: i32 = 5;
+
+pub fn scenarios() -> Vec<(Kind, &'static str, Check)> {
+    vec![
+        scenario!(Kind::HappyPath, "mean-price", mean_price),
+        scenario!(Kind::Malformed, "undefined-type", undefined_type),
+        scenario!(Kind::Concurrency, "isolated-sessions", isolated_sessions),
+        scenario!(Kind::SlowLoris, "trickled-frames", trickled_frames),
+    ]
+}
+
+fn frame(ty: u8, first: i32, second: i32) -> Vec<u8> {
+    [&[ty][..], &first.to_be_bytes(), &second.to_be_bytes()].concat()
+}
+
+async fn mean_price(addr: SocketAddr) -> anyhow::Result<()> {
+    let mut client = Client::connect(addr).await?;
+    for (timestamp, price) in [(12345, 101), (12346, 102), (12347, 100), (40960, 5)] {
+        client.insert(timestamp, price).await?;
+    }
+
+    expect_eq(client.query(12288, 16384).await?, 101)?;
+    // an empty (or reversed) period has a mean of 0
+    expect_eq(client.query(16384, 12288).await?, 0)
+}
+
+// a frame of an undefined type closes the connection, without an answer
+async fn undefined_type(addr: SocketAddr) -> anyhow::Result<()> {
+    let mut conn = TcpStream::connect(addr).await?;
+    conn.write_all(&frame(b'X', 0, 0)).await?;
+    conn.write_all(&frame(b'Q', i32::MIN, i32::MAX)).await?;
+
+    // the query may still be unread once the connection is closed, which resets it
+    let mut answered = Vec::new();
+    match conn.read_to_end(&mut answered).await {
+        Err(err) if err.kind() == ErrorKind::ConnectionReset => {}
+        result => {
+            result?;
+        }
+    }
+    anyhow::ensure!(
+        answered.len() < 4,
+        "the query after the malformed frame was answered"
+    );
+    Ok(())
+}
+
+// the prices are private to the session
+async fn isolated_sessions(addr: SocketAddr) -> anyhow::Result<()> {
+    let mut clients = JoinSet::new();
+    for client in 0..CLIENTS {
+        clients.spawn(async move {
+            let mut conn = Client::connect(addr).await?;
+            for timestamp in 0..100 {
+                conn.insert(timestamp, client * 1000 + timestamp % 2)
+                    .await?;
+            }
+
+            // the average of alternating prices is rounded down
+            expect_eq(conn.query(0, 99).await?, client * 1000)
+        });
+    }
+
+    while let Some(result) = clients.join_next().await {
+        result??;
+    }
+
+    Ok(())
+}
+
+async fn trickled_frames(addr: SocketAddr) -> anyhow::Result<()> {
+    let mut slow = TcpStream::connect(addr).await?;
+    let frames = [frame(b'I', 1000, 42), frame(b'Q', 0, 2000)].concat();
+    while_trickling(&mut slow, &frames, mean_price(addr)).await?;
+
+    expect_eq(slow.read_i32().await?, 42)
+}
@@ -0,0 +1,119 @@
+use std::net::SocketAddr;
+
+use super::{expect_eq, while_trickling, Check, Kind, Lines};
+
+const USERS: usize = 5;
+// the address every Boguscoin address is rewritten to
+const TONYS_ADDR: &str = "7YWHMfk9JZe0LM0g1ZauHuiSxhI";
+// one byte over the longest line the proxy relays
+const OVERSIZED_LINE_LEN: usize = 8 * 1024 + 1;
+
+pub fn scenarios() -> Vec<(Kind, &'static str, Check)> {
+    vec![
+        scenario!(Kind::HappyPath, "rewrite-addresses", rewrite_addresses),
+        scenario!(Kind::Malformed, "oversized-line", oversized_line),
+        scenario!(Kind::Concurrency, "broadcast", broadcast),
+        scenario!(Kind::SlowLoris, "trickled-name", trickled_name),
+    ]
+}
+
+// Connects through the proxy and joins the room behind it
+async fn join(addr: SocketAddr, name: &str) -> anyhow::Result<Lines> {
+    let mut user = Lines::connect(addr).await?;
+    user.recv().await?; // the welcome message
+    user.send(name).await?;
+
+    let userlist = user.recv().await?;
+    anyhow::ensure!(
+        userlist.starts_with('*'),
+        "expected the user list, received {:?}",
+        userlist
+    );
+    Ok(user)
+}
+
+async fn rewrite_addresses(addr: SocketAddr) -> anyhow::Result<()> {
+    let mut alice = join(addr, "alice").await?;
+    let mut bob = join(addr, "bob").await?;
+    alice.recv().await?; // the join message of bob
+
+    bob.send("7iKDZEwPZSqIvDnHvVN2r0hUWXD5rHX is my address")
+        .await?;
+    alice
+        .expect(&format!("[bob] {} is my address", TONYS_ADDR))
+        .await?;
+
+    // only whole words are addresses
+    bob.send("not an address: 7iKDZEwPZSqIvDnHvVN2r0hUWXD5rHX-1234")
+        .await?;
+    alice
+        .expect("[bob] not an address: 7iKDZEwPZSqIvDnHvVN2r0hUWXD5rHX-1234")
+        .await
+}
+
+async fn oversized_line(addr: SocketAddr) -> anyhow::Result<()> {
+    let mut user = join(addr, "alice").await?;
+    user.send(&"a".repeat(OVERSIZED_LINE_LEN)).await?;
+    user.closed().await
+}
+
+async fn broadcast(addr: SocketAddr) -> anyhow::Result<()> {
+    // everyone joins before anyone speaks, so everyone hears all the messages of the others
+    let mut users = Vec::new();
+    for user in 0..USERS {
+        users.push(join(addr, &format!("user{}", user)).await?);
+    }
+
+    for (user, lines) in users.iter_mut().enumerate() {
+        lines
+            .send(&format!("user{} pays 7F1u3wSD5RbOHQmupo9nx4TnhQ", user))
+            .await?;
+    }
+
+    for (user, lines) in users.iter_mut().enumerate() {
+        let mut received = Vec::new();
+        while received.len() < USERS - 1 {
+            let line = lines.recv().await?;
+            // the join messages of the users that have joined later
+            if !line.starts_with('*') {
+                received.push(line);
+            }
+        }
+
+        received.sort();
+        let expected = (0..USERS)
+            .filter(|other| *other != user)
+            .map(|other| format!("[user{0}] user{0} pays {1}", other, TONYS_ADDR))
+            .collect::<Vec<_>>();
+        expect_eq(received, expected)?;
+    }
+
+    Ok(())
+}
+
+async fn trickled_name(addr: SocketAddr) -> anyhow::Result<()> {
+    let mut slow = Lines::connect(addr).await?;
+    slow.recv().await?; // the welcome message
+
+    // alice and bob stay in the room, so they're on the user list of slowpoke
+    let mut users = None;
+    while_trickling(&mut slow.writer, b"slowpoke\n", async {
+        let mut alice = join(addr, "alice").await?;
+        let mut bob = join(addr, "bob").await?;
+        alice.recv().await?; // the join message of bob
+
+        bob.send("hi").await?;
+        alice.expect("[bob] hi").await?;
+        users = Some((alice, bob));
+        Ok(())
+    })
+    .await?;
+
+    let userlist = slow.recv().await?;
+    anyhow::ensure!(
+        userlist.contains("alice") && userlist.contains("bob"),
+        "expected alice and bob in the user list, received {:?}",
+        userlist
+    );
+    Ok(())
+}
@@ -0,0 +1,242 @@
+//! The conformance scenarios of every server
+//!
+//! each server is checked on its happy path, on malformed input, with concurrent clients,
+//! and with a slow client (slow loris) that trickles its bytes while other clients are served
+use std::{
+    fmt::{self, Debug},
+    future::Future,
+    net::SocketAddr,
+    pin::Pin,
+    str::FromStr,
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+    time::Instant,
+};
+
+use crate::servers::Server;
+
+// wraps an async fn that takes the address of the server as a check
+macro_rules! scenario {
+    ($kind:expr, $name:literal, $check:path) => {
+        (
+            $kind,
+            $name,
+            (|addr| Box::pin($check(addr))) as $crate::scenarios::Check,
+        )
+    };
+}
+
+mod budget_chat;
+mod insecure_sockets_layer;
+mod job_centre;
+mod line_reversal;
+mod means_to_an_end;
+mod mob_in_the_middle;
+mod prime_time;
+mod smoke_test;
+mod speed_daemon;
+mod unusual_database_program;
+mod vcs;
+
+// the pause between every byte a slow client sends
+const TRICKLE_DELAY: Duration = Duration::from_millis(25);
+
+pub type Check = fn(SocketAddr) -> Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    HappyPath,
+    Malformed,
+    Concurrency,
+    SlowLoris,
+}
+
+impl Kind {
+    pub const ALL: [Kind; 4] = [
+        Kind::HappyPath,
+        Kind::Malformed,
+        Kind::Concurrency,
+        Kind::SlowLoris,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Kind::HappyPath => "happy-path",
+            Kind::Malformed => "malformed",
+            Kind::Concurrency => "concurrency",
+            Kind::SlowLoris => "slow-loris",
+        }
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Kind {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Kind::ALL
+            .into_iter()
+            .find(|kind| kind.name() == name)
+            .ok_or_else(|| anyhow::anyhow!("unknown scenario kind: {}", name))
+    }
+}
+
+pub struct Scenario {
+    pub server: Server,
+    pub kind: Kind,
+    pub name: &'static str,
+    pub check: Check,
+}
+
+impl fmt::Display for Scenario {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}/{}", self.server, self.kind, self.name)
+    }
+}
+
+/// The scenarios of the server, every one of them expects a fresh instance of the server
+pub fn of(server: Server) -> Vec<Scenario> {
+    let checks = match server {
+        Server::SmokeTest => smoke_test::scenarios(),
+        Server::PrimeTime => prime_time::scenarios(),
+        Server::MeansToAnEnd => means_to_an_end::scenarios(),
+        Server::BudgetChat => budget_chat::scenarios(),
+        Server::UnusualDatabaseProgram => unusual_database_program::scenarios(),
+        Server::MobInTheMiddle => mob_in_the_middle::scenarios(),
+        Server::SpeedDaemon => speed_daemon::scenarios(),
+        Server::LineReversal => line_reversal::scenarios(),
+        Server::InsecureSocketsLayer => insecure_sockets_layer::scenarios(),
+        Server::JobCentre => job_centre::scenarios(),
+        Server::Vcs => vcs::scenarios(),
+    };
+
+    checks
+        .into_iter()
+        .map(|(kind, name, check)| Scenario {
+            server,
+            kind,
+            name,
+            check,
+        })
+        .collect()
+}
+
+fn expect_eq<T: PartialEq + Debug>(received: T, expected: T) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        received == expected,
+        "expected {:?}, received {:?}",
+        expected,
+        received
+    );
+    Ok(())
+}
+
+/// A client of a line based protocol
+struct Lines {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl Lines {
+    async fn connect(addr: SocketAddr) -> anyhow::Result<Self> {
+        let (reader, writer) = TcpStream::connect(addr).await?.into_split();
+        Ok(Self {
+            reader: BufReader::new(reader),
+            writer,
+        })
+    }
+
+    async fn send(&mut self, line: &str) -> anyhow::Result<()> {
+        self.writer
+            .write_all(format!("{}\n", line).as_bytes())
+            .await?;
+        Ok(())
+    }
+
+    /// Receives the next line, without its newline
+    async fn recv(&mut self) -> anyhow::Result<String> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line).await? == 0 {
+            anyhow::bail!("the server has closed the connection");
+        }
+        if line.pop() != Some('\n') {
+            anyhow::bail!("the server has closed the connection mid-line");
+        }
+
+        Ok(line)
+    }
+
+    async fn expect(&mut self, expected: &str) -> anyhow::Result<()> {
+        expect_eq(self.recv().await?.as_str(), expected)
+    }
+
+    /// Waits for the server to close the connection, skipping any line it sends before that
+    async fn closed(&mut self) -> anyhow::Result<()> {
+        let mut line = String::new();
+        while self.reader.read_line(&mut line).await? > 0 {
+            line.clear();
+        }
+
+        Ok(())
+    }
+}
+
+/// Runs the check while the slow client trickles the bytes to the server, a byte at a time
+///
+/// fails when the check has to wait for the slow client, which is what a server that serves
+/// one client at a time (or that blocks on a partial request) does
+async fn while_trickling<W, F>(slow: &mut W, bytes: &[u8], check: F) -> anyhow::Result<()>
+where
+    W: AsyncWrite + Unpin,
+    F: Future<Output = anyhow::Result<()>>,
+{
+    let started = Instant::now();
+    let trickle = async {
+        for byte in bytes {
+            slow.write_all(&[*byte]).await?;
+            slow.flush().await?;
+            tokio::time::sleep(TRICKLE_DELAY).await;
+        }
+
+        Ok::<_, anyhow::Error>(started.elapsed())
+    };
+    let check = async {
+        check.await?;
+        Ok(started.elapsed())
+    };
+
+    let (trickled, checked) = tokio::try_join!(trickle, check)?;
+    anyhow::ensure!(
+        checked < trickled,
+        "the other clients were held up by the slow client"
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{run, servers::Server, DEFAULT_TIMEOUT};
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn servers_pass_their_scenarios() {
+        for server in Server::ALL {
+            for scenario in super::of(server) {
+                if let Err(err) = run(&scenario, DEFAULT_TIMEOUT).await {
+                    panic!("{} has failed: {}", scenario, err);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use std::net::SocketAddr;
+
+use serde_json::{json, Value};
+use tokio::{io::AsyncReadExt, task::JoinSet};
+
+use super::{expect_eq, while_trickling, Check, Kind, Lines};
+
+const CLIENTS: u64 = 5;
+const REQUESTS_PER_CLIENT: u64 = 100;
+
+pub fn scenarios() -> Vec<(Kind, &'static str, Check)> {
+    vec![
+        scenario!(Kind::HappyPath, "is-prime", is_prime),
+        scenario!(Kind::Malformed, "number-as-string", number_as_string),
+        scenario!(Kind::Concurrency, "pipelined-clients", pipelined_clients),
+        scenario!(Kind::SlowLoris, "trickled-request", trickled_request),
+    ]
+}
+
+fn request(number: Value) -> String {
+    json!({"method": "isPrime", "number": number}).to_string()
+}
+
+async fn expect_answer(lines: &mut Lines, prime: bool) -> anyhow::Result<()> {
+    let response: Value = serde_json::from_str(&lines.recv().await?)?;
+    expect_eq(response, json!({"method": "isPrime", "prime": prime}))
+}
+
+// trial division, independent of the server's checks
+fn check_prime(number: u64) -> bool {
+    number >= 2
+        && (2..)
+            .take_while(|d| d * d <= number)
+            .all(|d| !number.is_multiple_of(d))
+}
+
+async fn is_prime(addr: SocketAddr) -> anyhow::Result<()> {
+    let mut lines = Lines::connect(addr).await?;
+    for (number, prime) in [
+        (json!(7), true),
+        (json!(8), false),
+        (json!(7919), true),
+        (json!(-7), false),
+        (json!(7.5), false),
+    ] {
+        lines.send(&request(number)).await?;
+        expect_answer(&mut lines, prime).await?;
+    }
+
+    Ok(())
+}
+
+// a malformed request is answered with a malformed response, and the connection is closed
+async fn number_as_string(addr: SocketAddr) -> anyhow::Result<()> {
+    let mut lines = Lines::connect(addr).await?;
+    lines.send(&request(json!("7"))).await?;
+
+    // the malformed response doesn't have to end with a newline
+    let mut response = String::new();
+    lines.reader.read_to_string(&mut response).await?;
+    expect_eq(response.trim_end(), "{}")
+}
+
+async fn pipelined_clients(addr: SocketAddr) -> anyhow::Result<()> {
+    let mut clients = JoinSet::new();
+    for client in 0..CLIENTS {
+        clients.spawn(async move {
+            let numbers = client * REQUESTS_PER_CLIENT..(client + 1) * REQUESTS_PER_CLIENT;
+            let mut lines = Lines::connect(addr).await?;
+            // all of the requests are sent before any response is read
+            let batch: String = numbers
+                .clone()
+                .map(|number| request(json!(number)) + "\n")
+                .collect();
+            lines.send(batch.trim_end()).await?;
+
+            for number in numbers {
+                expect_answer(&mut lines, check_prime(number)).await?;
+            }
+
+            Ok::<_, anyhow::Error>(())
+        });
+    }
+
+    while let Some(result) = clients.join_next().await {
+        result??;
+    }
+
+    Ok(())
+}
+
+async fn trickled_request(addr: SocketAddr) -> anyhow::Result<()> {
+    let mut slow = Lines::connect(addr).await?;
+    let trickled = request(json!(97)) + "\n";
+    while_trickling(&mut slow.writer, trickled.as_bytes(), is_prime(addr)).await?;
+
+    expect_answer(&mut slow, true).await
+}
@@ -0,0 +1,75 @@
+use std::net::SocketAddr;
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    task::JoinSet,
+};
+
+use super::{expect_eq, while_trickling, Check, Kind};
+
+const CLIENTS: usize = 5;
+
+pub fn scenarios() -> Vec<(Kind, &'static str, Check)> {
+    vec![
+        scenario!(Kind::HappyPath, "echo", echo),
+        scenario!(Kind::Malformed, "binary-data", binary_data),
+        scenario!(Kind::Concurrency, "parallel-clients", parallel_clients),
+        scenario!(Kind::SlowLoris, "trickled-data", trickled_data),
+    ]
+}
+
+// Sends the data, closes the writing side and expects the same data back
+async fn round_trip(addr: SocketAddr, data: Vec<u8>) -> anyhow::Result<()> {
+    let mut conn = TcpStream::connect(addr).await?;
+    let (mut reader, mut writer) = conn.split();
+
+    // the data is read while it's written, so a large echo doesn't fill the socket buffers
+    let send = async {
+        writer.write_all(&data).await?;
+        writer.shutdown().await
+    };
+    let mut echoed = Vec::new();
+    tokio::try_join!(send, reader.read_to_end(&mut echoed))?;
+
+    anyhow::ensure!(
+        echoed == data,
+        "sent {} bytes, received {} different bytes back",
+        data.len(),
+        echoed.len()
+    );
+    Ok(())
+}
+
+async fn echo(addr: SocketAddr) -> anyhow::Result<()> {
+    round_trip(addr, b"hello, world\n".repeat(10_000)).await
+}
+
+// the echo is byte for byte, not line by line
+async fn binary_data(addr: SocketAddr) -> anyhow::Result<()> {
+    round_trip(addr, (0..=u8::MAX).cycle().take(64 * 1024).collect()).await
+}
+
+async fn parallel_clients(addr: SocketAddr) -> anyhow::Result<()> {
+    let mut clients = JoinSet::new();
+    for client in 0..CLIENTS {
+        let data = format!("client {}\n", client).repeat(10_000);
+        clients.spawn(round_trip(addr, data.into_bytes()));
+    }
+
+    while let Some(result) = clients.join_next().await {
+        result??;
+    }
+
+    Ok(())
+}
+
+async fn trickled_data(addr: SocketAddr) -> anyhow::Result<()> {
+    let data = b"slowly but surely\n";
+    let mut slow = TcpStream::connect(addr).await?;
+    while_trickling(&mut slow, data, round_trip(addr, b"quick\n".to_vec())).await?;
+
+    let mut echoed = vec![0; data.len()];
+    slow.read_exact(&mut echoed).await?;
+    expect_eq(&echoed[..], data)
+}
@@ -0,0 +1,131 @@
+use std::{collections::HashSet, net::SocketAddr, time::Duration};
+
+use protohackers_client::speed_daemon::{Client, FromClient, ToClient};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    task::JoinSet,
+};
+
+use super::{expect_eq, while_trickling, Check, Kind};
+
+// the types of the messages that are checked byte by byte
+const ERROR: u8 = 0x10;
+const WANT_HEARTBEAT: u8 = 0x40;
+const HEARTBEAT: u8 = 0x41;
+const I_AM_DISPATCHER: u8 = 0x81;
+
+const ROAD: u16 = 123;
+const CARS: usize = 4;
+// the slow dispatcher takes long enough to send, for a ticket to be issued meanwhile
+const SLOW_ROADS: u16 = 20;
+// a camera stays connected until its plate was handled
+const CAMERA_LINGER: Duration = Duration::from_millis(100);
+
+pub fn scenarios() -> Vec<(Kind, &'static str, Check)> {
+    vec![
+        scenario!(Kind::HappyPath, "ticket", ticket),
+        scenario!(Kind::Malformed, "unknown-message", unknown_message),
+        scenario!(Kind::Concurrency, "parallel-cameras", parallel_cameras),
+        scenario!(Kind::SlowLoris, "trickled-heartbeat", trickled_heartbeat),
+    ]
+}
+
+async fn observe(
+    addr: SocketAddr,
+    road: u16,
+    mile: u16,
+    plate: String,
+    timestamp: u32,
+) -> anyhow::Result<()> {
+    let mut camera = Client::connect(addr).await?;
+    camera
+        .send(&FromClient::IAmCamera {
+            road,
+            mile,
+            limit: 60,
+        })
+        .await?;
+    camera.send(&FromClient::Plate { plate, timestamp }).await?;
+    tokio::time::sleep(CAMERA_LINGER).await;
+
+    Ok(())
+}
+
+async fn dispatcher(addr: SocketAddr, road: u16) -> anyhow::Result<Client> {
+    let mut dispatcher = Client::connect(addr).await?;
+    dispatcher
+        .send(&FromClient::IAmDispatcher { roads: vec![road] })
+        .await?;
+
+    Ok(dispatcher)
+}
+
+async fn ticket(addr: SocketAddr) -> anyhow::Result<()> {
+    let mut dispatcher = dispatcher(addr, ROAD).await?;
+    observe(addr, ROAD, 8, "UN1X".into(), 0).await?;
+    observe(addr, ROAD, 9, "UN1X".into(), 45).await?;
+
+    expect_eq(
+        dispatcher.ticket().await?,
+        ToClient::Ticket {
+            plate: "UN1X".into(),
+            road: ROAD,
+            mile1: 8,
+            timestamp1: 0,
+            mile2: 9,
+            timestamp2: 45,
+            speed: 8000,
+        },
+    )
+}
+
+// a message of an unknown type is answered with an error
+async fn unknown_message(addr: SocketAddr) -> anyhow::Result<()> {
+    let mut conn = TcpStream::connect(addr).await?;
+    conn.write_all(&[0xff]).await?;
+    expect_eq(conn.read_u8().await?, ERROR)
+}
+
+// the cars are observed at the same time, every one of them is ticketed once
+async fn parallel_cameras(addr: SocketAddr) -> anyhow::Result<()> {
+    let mut dispatcher = dispatcher(addr, ROAD).await?;
+
+    let mut cameras = JoinSet::new();
+    for car in 0..CARS {
+        let plate = format!("CAR{}", car);
+        cameras.spawn(observe(addr, ROAD, 8, plate.clone(), 0));
+        cameras.spawn(observe(addr, ROAD, 9, plate, 45));
+    }
+    while let Some(result) = cameras.join_next().await {
+        result??;
+    }
+
+    let mut ticketed = HashSet::new();
+    for _ in 0..CARS {
+        match dispatcher.ticket().await? {
+            ToClient::Ticket { plate, .. } => {
+                anyhow::ensure!(
+                    ticketed.insert(plate.clone()),
+                    "{} was ticketed twice",
+                    plate
+                )
+            }
+            message => anyhow::bail!("expected a ticket, received {:?}", message),
+        }
+    }
+
+    Ok(())
+}
+
+async fn trickled_heartbeat(addr: SocketAddr) -> anyhow::Result<()> {
+    let mut slow = TcpStream::connect(addr).await?;
+    // a dispatcher of a few other roads, that wants a heartbeat every decisecond
+    let roads = 1..=SLOW_ROADS;
+    let mut messages = vec![I_AM_DISPATCHER, roads.len() as u8];
+    messages.extend(roads.flat_map(u16::to_be_bytes));
+    messages.extend([WANT_HEARTBEAT, 0, 0, 0, 1]);
+    while_trickling(&mut slow, &messages, ticket(addr)).await?;
+
+    expect_eq(slow.read_u8().await?, HEARTBEAT)
+}
@@ -0,0 +1,125 @@
+use std::{net::SocketAddr, time::Duration};
+
+use tokio::{net::UdpSocket, task::JoinSet};
+
+use super::{expect_eq, Check, Kind};
+
+const CLIENTS: usize = 5;
+// how long the server is given to (wrongly) answer a request that has no response
+const SILENCE: Duration = Duration::from_millis(500);
+// how many times a retrieve is retried before an insert is taken as lost
+const RETRIES: usize = 10;
+
+// every request fits in a single datagram, so there is no partial request to trickle
+pub fn scenarios() -> Vec<(Kind, &'static str, Check)> {
+    vec![
+        scenario!(Kind::HappyPath, "insert-retrieve", insert_retrieve),
+        scenario!(Kind::Malformed, "read-only-version", read_only_version),
+        scenario!(Kind::Concurrency, "parallel-clients", parallel_clients),
+    ]
+}
+
+/// A client of the store, each request is a datagram of its own
+struct Client {
+    socket: UdpSocket,
+}
+
+impl Client {
+    async fn connect(addr: SocketAddr) -> anyhow::Result<Self> {
+        let socket = UdpSocket::bind("127.0.0.1:0").await?;
+        socket.connect(addr).await?;
+        Ok(Self { socket })
+    }
+
+    async fn insert(&self, key: &str, value: &str) -> anyhow::Result<()> {
+        self.socket
+            .send(format!("{}={}", key, value).as_bytes())
+            .await?;
+        Ok(())
+    }
+
+    // the store doesn't respond to the retrieve of a key it doesn't have
+    async fn retrieve(&self, key: &str) -> anyhow::Result<Option<String>> {
+        self.socket.send(key.as_bytes()).await?;
+
+        let mut buffer = [0; 1000];
+        match tokio::time::timeout(SILENCE, self.socket.recv(&mut buffer)).await {
+            Ok(len) => Ok(Some(String::from_utf8(buffer[..len?].to_vec())?)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Retrieves the key until the store responds with the pair,
+    /// the requests of a client may be handled out of order, so an insert isn't visible right away
+    async fn expect(&self, key: &str, value: &str) -> anyhow::Result<()> {
+        let expected = format!("{}={}", key, value);
+        let mut received = None;
+        for _ in 0..RETRIES {
+            received = self.retrieve(key).await?;
+            if received.as_deref() == Some(expected.as_str()) {
+                return Ok(());
+            }
+        }
+
+        expect_eq(received, Some(expected))
+    }
+}
+
+async fn insert_retrieve(addr: SocketAddr) -> anyhow::Result<()> {
+    let client = Client::connect(addr).await?;
+    expect_eq(client.retrieve("missing").await?, None)?;
+
+    client.insert("foo", "bar").await?;
+    client.expect("foo", "bar").await?;
+
+    // only the first '=' separates the key from the value
+    client.insert("foo", "bar=baz").await?;
+    client.expect("foo", "bar=baz").await?;
+
+    // as with any other key, the empty one can be set
+    client.insert("", "empty").await?;
+    client.expect("", "empty").await
+}
+
+async fn read_only_version(addr: SocketAddr) -> anyhow::Result<()> {
+    let client = Client::connect(addr).await?;
+    let version = client
+        .retrieve("version")
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("the server has not reported its version"))?;
+    anyhow::ensure!(
+        version.starts_with("version=") && version.len() > "version=".len(),
+        "expected the version of the server, received {:?}",
+        version
+    );
+
+    // the insert that follows is only seen once the one of the version has been handled
+    client.insert("version", "hacked").await?;
+    client.insert("after", "hacked").await?;
+    client.expect("after", "hacked").await?;
+    expect_eq(client.retrieve("version").await?, Some(version))
+}
+
+async fn parallel_clients(addr: SocketAddr) -> anyhow::Result<()> {
+    let mut clients = JoinSet::new();
+    for client in 0..CLIENTS {
+        clients.spawn(async move {
+            let key = format!("client{}", client);
+            let client = Client::connect(addr).await?;
+            client.insert(&key, "value").await?;
+            client.expect(&key, "value").await
+        });
+    }
+
+    while let Some(result) = clients.join_next().await {
+        result??;
+    }
+
+    // every client sees the inserts of the others
+    let client = Client::connect(addr).await?;
+    for other in 0..CLIENTS {
+        client.expect(&format!("client{}", other), "value").await?;
+    }
+
+    Ok(())
+}
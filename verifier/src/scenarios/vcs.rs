@@ -0,0 +1,90 @@
+use std::{collections::HashSet, net::SocketAddr};
+
+use protohackers_client::vcs::Client;
+use tokio::task::JoinSet;
+
+use super::{expect_eq, while_trickling, Check, Kind, Lines};
+
+const CLIENTS: u64 = 5;
+
+pub fn scenarios() -> Vec<(Kind, &'static str, Check)> {
+    vec![
+        scenario!(Kind::HappyPath, "revisions", revisions),
+        scenario!(Kind::Malformed, "illegal-requests", illegal_requests),
+        scenario!(Kind::Concurrency, "parallel-puts", parallel_puts),
+        scenario!(Kind::SlowLoris, "trickled-put", trickled_put),
+    ]
+}
+
+async fn revisions(addr: SocketAddr) -> anyhow::Result<()> {
+    let mut client = Client::connect(addr).await?;
+    expect_eq(client.put("/notes.txt", b"one\n").await?, 1)?;
+    expect_eq(client.put("/notes.txt", b"two\n").await?, 2)?;
+    // the same content doesn't make a new revision
+    expect_eq(client.put("/notes.txt", b"two\n").await?, 2)?;
+
+    expect_eq(client.get("/notes.txt", Some(1)).await?, b"one\n".to_vec())?;
+    expect_eq(client.get("/notes.txt", None).await?, b"two\n".to_vec())
+}
+
+// a bad request is answered with an error, an illegal method also closes the connection
+async fn illegal_requests(addr: SocketAddr) -> anyhow::Result<()> {
+    let mut lines = Lines::connect(addr).await?;
+    lines.expect("READY").await?;
+
+    for request in ["PUT no-slash.txt 1", "GET /notes.txt r1 extra", "LIST"] {
+        lines.send(request).await?;
+        let response = lines.recv().await?;
+        anyhow::ensure!(
+            response.starts_with("ERR "),
+            "expected {:?} to be refused, received {:?}",
+            request,
+            response
+        );
+        lines.expect("READY").await?;
+    }
+
+    lines.send("FETCH /notes.txt").await?;
+    let response = lines.recv().await?;
+    anyhow::ensure!(
+        response.starts_with("ERR "),
+        "expected an illegal method error, received {:?}",
+        response
+    );
+    lines.closed().await
+}
+
+// concurrent puts to the same file make a revision each
+async fn parallel_puts(addr: SocketAddr) -> anyhow::Result<()> {
+    let mut clients = JoinSet::new();
+    for client in 0..CLIENTS {
+        clients.spawn(async move {
+            let mut conn = Client::connect(addr).await?;
+            let revision = conn
+                .put("/shared.txt", format!("client {}\n", client).as_bytes())
+                .await?;
+
+            Ok::<_, anyhow::Error>(revision)
+        });
+    }
+
+    let mut revisions = HashSet::new();
+    while let Some(result) = clients.join_next().await {
+        revisions.insert(result??);
+    }
+    expect_eq(revisions, (1..=CLIENTS).collect())
+}
+
+async fn trickled_put(addr: SocketAddr) -> anyhow::Result<()> {
+    let mut slow = Lines::connect(addr).await?;
+    slow.expect("READY").await?;
+    while_trickling(
+        &mut slow.writer,
+        b"PUT /slow.txt 11\nat its pace",
+        revisions(addr),
+    )
+    .await?;
+
+    slow.expect("OK r1").await?;
+    slow.expect("READY").await
+}
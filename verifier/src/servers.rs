@@ -0,0 +1,216 @@
+use std::{fmt, future::Future, net::SocketAddr, str::FromStr, sync::Arc};
+
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+
+/// A server the verifier knows how to run in-process
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Server {
+    SmokeTest,
+    PrimeTime,
+    MeansToAnEnd,
+    BudgetChat,
+    UnusualDatabaseProgram,
+    MobInTheMiddle,
+    SpeedDaemon,
+    LineReversal,
+    InsecureSocketsLayer,
+    JobCentre,
+    Vcs,
+}
+
+impl Server {
+    pub const ALL: [Server; 11] = [
+        Server::SmokeTest,
+        Server::PrimeTime,
+        Server::MeansToAnEnd,
+        Server::BudgetChat,
+        Server::UnusualDatabaseProgram,
+        Server::MobInTheMiddle,
+        Server::SpeedDaemon,
+        Server::LineReversal,
+        Server::InsecureSocketsLayer,
+        Server::JobCentre,
+        Server::Vcs,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Server::SmokeTest => "smoke-test",
+            Server::PrimeTime => "prime-time",
+            Server::MeansToAnEnd => "means-to-an-end",
+            Server::BudgetChat => "budget-chat",
+            Server::UnusualDatabaseProgram => "unusual-database-program",
+            Server::MobInTheMiddle => "mob-in-the-middle",
+            Server::SpeedDaemon => "speed-daemon",
+            Server::LineReversal => "line-reversal",
+            Server::InsecureSocketsLayer => "insecure-sockets-layer",
+            Server::JobCentre => "job-centre",
+            Server::Vcs => "voracious-code-storage",
+        }
+    }
+
+    /// Starts a fresh instance of the server with its default configuration,
+    /// returns the (ephemeral) address it listens on
+    ///
+    /// the server runs on the current runtime, until the runtime shuts down
+    pub async fn start(&self) -> anyhow::Result<SocketAddr> {
+        let addr = match self {
+            Server::SmokeTest => {
+                let options = smoke_test::args::Options::default();
+                serve(move |conn| {
+                    let options = options.clone();
+                    async move { smoke_test::modes::handle_connection(conn, &options).await }
+                })
+                .await?
+            }
+            Server::PrimeTime => {
+                use prime_time::{admission::Admission, server};
+
+                let admission = Arc::new(Admission::new(None, None));
+                serve(move |conn| {
+                    let pending = admission.admit();
+                    async move {
+                        // a shed connection is closed right away, like the server does
+                        let Some(pending) = pending else {
+                            return Ok(());
+                        };
+                        // strict mode, and the default limit of a request
+                        server::serve(conn, pending, false, server::DEFAULT_MAX_REQUEST_SIZE).await
+                    }
+                })
+                .await?
+            }
+            Server::MeansToAnEnd => {
+                use means_to_an_end::{admin::Sessions, config::Config, server};
+
+                let sessions = Arc::new(Sessions::default());
+                let config = Arc::new(Config::default());
+                serve(move |conn| {
+                    let entry = conn.peer_addr().map(|addr| sessions.register(addr));
+                    let config = config.clone();
+                    async move { server::handle_connection(conn, entry?, None, config).await }
+                })
+                .await?
+            }
+            Server::BudgetChat => {
+                use budget_chat::{chatroom::ChatRoom, server, settings::SettingsStore};
+
+                let chatroom = ChatRoom::create(SettingsStore::default(), None);
+                serve(move |conn| server::handle_connection(conn, chatroom.clone(), false)).await?
+            }
+            Server::SpeedDaemon => {
+                use speed_daemon::{client, SharedSystems};
+
+                let systems = SharedSystems::in_memory()?;
+                serve(move |conn| {
+                    client::handle(conn, systems.clone(), false, client::Protocol::Binary)
+                })
+                .await?
+            }
+            Server::JobCentre => {
+                use job_centre::{
+                    client::Client,
+                    jobs::{Manager, TieBreak},
+                    server,
+                };
+
+                let job_manager = Manager::new(TieBreak::default()).start();
+                serve(move |conn| server::handle_request(Client::new(job_manager.clone()), conn))
+                    .await?
+            }
+            Server::Vcs => {
+                use voracious_code_storage::{
+                    admission::Admission, server, storage::TempFileSystem, uploads::Uploads,
+                    SharedAdmission, SharedFileSystem, SharedUploads,
+                };
+
+                let fs: SharedFileSystem = Box::leak(Box::new(TempFileSystem::default()));
+                let admission: SharedAdmission = Box::leak(Box::new(Admission::new(None)));
+                let uploads: SharedUploads = Box::leak(Box::new(Uploads::default()));
+                serve(move |conn| server::handle_connection(conn, fs, admission, uploads, false))
+                    .await?
+            }
+            Server::UnusualDatabaseProgram => {
+                use unusual_database_program::config::Config;
+
+                let socket = UdpSocket::bind("127.0.0.1:0").await?;
+                let addr = socket.local_addr()?;
+                tokio::spawn(unusual_database_program::serve_shard(
+                    Arc::new(Config::default()),
+                    socket,
+                    0,
+                ));
+
+                addr
+            }
+            Server::MobInTheMiddle => {
+                use mob_in_the_middle::{config::Config, Proxy};
+
+                // the proxy is put in front of a chat server of its own, rather than the real one
+                let upstream = Box::pin(Server::BudgetChat.start()).await?;
+                let proxy = Proxy::new(&Config {
+                    upstream_addr: upstream.to_string(),
+                    ..Config::default()
+                })?;
+                serve(move |conn| proxy.clone().handle(conn)).await?
+            }
+            Server::InsecureSocketsLayer => {
+                use insecure_sockets_layer::{config::Config, metrics::Events, server};
+
+                let server =
+                    server::Server::new(Arc::new(Config::default()), vec![Arc::new(Events)]);
+                serve(move |conn| server.clone().handle(conn)).await?
+            }
+            Server::LineReversal => {
+                let mut listener =
+                    lrcp::Listener::bind("127.0.0.1:0", lrcp::Config::default()).await?;
+                let addr = listener.local_addr();
+                tokio::spawn(async move {
+                    while let Ok(conn) = listener.accept().await {
+                        tokio::spawn(line_reversal::handle_connection(conn));
+                    }
+                });
+
+                addr
+            }
+        };
+
+        Ok(addr)
+    }
+}
+
+impl fmt::Display for Server {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Server {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Server::ALL
+            .into_iter()
+            .find(|server| server.name() == name)
+            .ok_or_else(|| anyhow::anyhow!("unknown server: {}", name))
+    }
+}
+
+// Binds an ephemeral port and spawns an accept loop that hands every connection to the handler,
+// the connections are expected to fail on bad input, so their errors are dropped
+async fn serve<H, F>(handler: H) -> std::io::Result<SocketAddr>
+where
+    H: Fn(TcpStream) -> F + Send + 'static,
+    F: Future + Send + 'static,
+    F::Output: Send,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(async move {
+        while let Ok((conn, _)) = listener.accept().await {
+            tokio::spawn(handler(conn));
+        }
+    });
+
+    Ok(addr)
+}
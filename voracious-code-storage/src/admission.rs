@@ -0,0 +1,174 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+// latencies older than this no longer count towards the percentiles,
+// so the server recovers once a storm has passed
+const WINDOW: Duration = Duration::from_secs(10);
+
+// too few samples make for a meaningless percentile
+const MIN_SAMPLES: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Put,
+    Get,
+}
+
+#[derive(Debug, Default)]
+struct Samples {
+    put: VecDeque<(Instant, Duration)>,
+    get: VecDeque<(Instant, Duration)>,
+}
+
+impl Samples {
+    fn of(&mut self, kind: Kind) -> &mut VecDeque<(Instant, Duration)> {
+        match kind {
+            Kind::Put => &mut self.put,
+            Kind::Get => &mut self.get,
+        }
+    }
+
+    fn evict(&mut self, now: Instant) {
+        for samples in [&mut self.put, &mut self.get] {
+            while samples
+                .front()
+                .is_some_and(|(at, _)| now.saturating_duration_since(*at) > WINDOW)
+            {
+                samples.pop_front();
+            }
+        }
+    }
+}
+
+/// Sheds new PUTs while the server is slow, so GETs keep being served during write storms
+///
+/// the server is slow when the p99 latency of either PUTs or GETs, over the recent window,
+/// is above the SLO
+#[derive(Debug, Default)]
+pub struct Admission {
+    // admission control is disabled without an SLO
+    slo: Option<Duration>,
+    samples: Mutex<Samples>,
+    // whether PUTs were shed by the last check, only used to log the transitions
+    shedding: AtomicBool,
+}
+
+impl Admission {
+    pub fn new(slo: Option<Duration>) -> Self {
+        Self {
+            slo,
+            ..Default::default()
+        }
+    }
+
+    /// Records the time it took to handle a request
+    pub fn record(&self, kind: Kind, latency: Duration) {
+        self.record_at(kind, latency, Instant::now())
+    }
+
+    fn record_at(&self, kind: Kind, latency: Duration, now: Instant) {
+        if self.slo.is_none() {
+            return;
+        }
+
+        let mut samples = self.samples.lock().unwrap();
+        samples.evict(now);
+        samples.of(kind).push_back((now, latency));
+    }
+
+    /// Whether a new PUT should be accepted
+    pub fn admit_put(&self) -> bool {
+        self.admit_put_at(Instant::now())
+    }
+
+    fn admit_put_at(&self, now: Instant) -> bool {
+        let Some(slo) = self.slo else {
+            return true;
+        };
+
+        let mut samples = self.samples.lock().unwrap();
+        samples.evict(now);
+
+        let slow = [Kind::Put, Kind::Get]
+            .into_iter()
+            .filter_map(|kind| p99(samples.of(kind)))
+            .any(|p99| p99 > slo);
+
+        if self.shedding.swap(slow, Ordering::Relaxed) != slow {
+            match slow {
+                true => tracing::warn!("the p99 latency is above {:?}, shedding PUTs", slo),
+                false => tracing::info!("the p99 latency is back under {:?}, admitting PUTs", slo),
+            }
+        }
+
+        !slow
+    }
+}
+
+fn p99(samples: &VecDeque<(Instant, Duration)>) -> Option<Duration> {
+    if samples.len() < MIN_SAMPLES {
+        return None;
+    }
+
+    let mut latencies: Vec<_> = samples.iter().map(|(_, latency)| *latency).collect();
+    latencies.sort_unstable();
+
+    let idx = (latencies.len() * 99).div_ceil(100) - 1;
+    Some(latencies[idx])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{Admission, Kind, MIN_SAMPLES, WINDOW};
+
+    const SLO: Duration = Duration::from_millis(100);
+
+    #[test]
+    fn shed_puts_while_slow() {
+        let admission = Admission::new(Some(SLO));
+        let now = Instant::now();
+
+        // a single slow request isn't enough to shed
+        admission.record_at(Kind::Get, SLO * 10, now);
+        assert!(admission.admit_put_at(now));
+
+        for _ in 0..MIN_SAMPLES {
+            admission.record_at(Kind::Get, SLO * 2, now);
+        }
+        assert!(!admission.admit_put_at(now));
+
+        // the slow requests fall out of the window
+        let later = now + WINDOW + Duration::from_secs(1);
+        for _ in 0..MIN_SAMPLES {
+            admission.record_at(Kind::Put, SLO / 2, later);
+        }
+        assert!(admission.admit_put_at(later));
+    }
+
+    #[test]
+    fn ignore_rare_outliers() {
+        let admission = Admission::new(Some(SLO));
+        let now = Instant::now();
+
+        for idx in 0..200 {
+            let latency = if idx % 100 == 0 { SLO * 10 } else { SLO / 2 };
+            admission.record_at(Kind::Put, latency, now);
+        }
+        assert!(admission.admit_put_at(now));
+
+        // without an SLO everything is admitted
+        let admission = Admission::new(None);
+        for _ in 0..MIN_SAMPLES {
+            admission.record_at(Kind::Put, SLO * 10, now);
+        }
+        assert!(admission.admit_put_at(now));
+    }
+}
@@ -0,0 +1,65 @@
+use std::{env, path::PathBuf, time::Duration};
+
+use voracious_code_storage::uploads::DEFAULT_LARGE_UPLOAD;
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    // when set, new PUTs are rejected while the p99 latency is above it
+    pub latency_slo: Option<Duration>,
+    // every client sees its own isolated root, picked by its address
+    // or by the token of a TENANT request
+    pub tenancy: bool,
+    // when set, prometheus metrics are served on this address
+    pub metrics_addr: Option<String>,
+    // when set, PUTs of bigger files are rejected
+    pub max_file_size: Option<u64>,
+    // PUTs of at least this many bytes are large
+    pub large_upload: u64,
+    // when set, at most this many large PUTs are stored at the same time,
+    // the rest wait for their turn
+    pub max_large_uploads: Option<usize>,
+    // when set, the files are stored under this dir and survive restarts,
+    // otherwise they only live in tempfiles
+    pub storage_dir: Option<PathBuf>,
+}
+
+impl Config {
+    /// Builds the configuration from the environment,
+    /// falling back to the defaults for any variable that isn't set
+    pub fn from_env() -> anyhow::Result<Self> {
+        let latency_slo = match env::var("LATENCY_SLO_MS") {
+            Ok(value) => Some(Duration::from_millis(value.parse().map_err(|err| {
+                anyhow::anyhow!("bad value for LATENCY_SLO_MS: {}", err)
+            })?)),
+            Err(_) => None,
+        };
+
+        let tenancy = env::var("TENANCY")
+            .map(|value| value == "1" || value.eq_ignore_ascii_case("true"))
+            .unwrap_or(false);
+
+        Ok(Self {
+            latency_slo,
+            tenancy,
+            metrics_addr: env::var("METRICS_ADDR").ok(),
+            max_file_size: read_var("MAX_FILE_SIZE")?,
+            large_upload: read_var("LARGE_UPLOAD_BYTES")?.unwrap_or(DEFAULT_LARGE_UPLOAD),
+            max_large_uploads: read_var("MAX_LARGE_UPLOADS")?,
+            storage_dir: env::var_os("STORAGE_DIR").map(PathBuf::from),
+        })
+    }
+}
+
+fn read_var<T>(name: &str) -> anyhow::Result<Option<T>>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|err| anyhow::anyhow!("bad value for {}: {}", name, err)),
+        Err(_) => Ok(None),
+    }
+}
@@ -0,0 +1,11 @@
+//! The storage server, a library of its own so the binary, scripts and tests can share it
+pub mod admission;
+mod protocol;
+pub mod server;
+pub mod stats;
+pub mod storage;
+pub mod uploads;
+
+pub type SharedFileSystem = &'static dyn storage::Storage;
+pub type SharedAdmission = &'static admission::Admission;
+pub type SharedUploads = &'static uploads::Uploads;
@@ -1,59 +1,57 @@
-use protocol::{
-    connection::Connection,
-    message::{Request, Response},
+use config::Config;
+use throttle::{Limits, Throttle};
+use tokio::net::TcpListener;
+use voracious_code_storage::{
+    admission::Admission,
+    server::handle_connection,
+    stats,
+    storage::{DiskStorage, TempFileSystem},
+    uploads::Uploads,
+    SharedFileSystem,
 };
-use storage::TempFileSystem;
-use tokio::net::{TcpListener, TcpStream};
 
-mod protocol;
-mod storage;
-
-type SharedFileSystem = &'static TempFileSystem;
+mod config;
 
 #[tokio::main]
-async fn main() -> tokio::io::Result<()> {
-    tracing_subscriber::fmt::init();
-
-    let shared_filesystem = Box::leak(Box::default());
+async fn main() -> anyhow::Result<()> {
+    telemetry::init();
+
+    let config = Config::from_env()?;
+    let shared_filesystem: SharedFileSystem = match &config.storage_dir {
+        Some(root) => {
+            tracing::info!("storing files in: {}", root.display());
+            Box::leak(Box::new(DiskStorage::open(root.clone()).await?))
+        }
+        None => Box::leak(Box::new(TempFileSystem::default())),
+    };
+    let shared_admission = Box::leak(Box::new(Admission::new(config.latency_slo)));
+    let shared_uploads = Box::leak(Box::new(Uploads::new(
+        config.max_file_size,
+        config.large_upload,
+        config.max_large_uploads,
+    )));
+
+    if let Some(addr) = &config.metrics_addr {
+        if let Err(err) = telemetry::stats::install_with_buckets(addr, stats::DURATION_BUCKETS) {
+            tracing::error!("failed to start the metrics exporter: {}", err);
+        }
+    }
 
+    let throttle = Throttle::new(Limits::from_env()?);
     let listener = TcpListener::bind("0.0.0.0:3600").await?;
     tracing::info!("server is listening on: {}", listener.local_addr()?);
 
     loop {
-        let (conn, _) = listener.accept().await?;
-        tokio::spawn(handle_connection(conn, shared_filesystem));
-    }
-}
-
-async fn handle_connection(stream: TcpStream, fs: SharedFileSystem) -> anyhow::Result<()> {
-    let mut client = Connection::new(stream).await?;
-
-    while let Some(request) = client.read_request().await? {
-        tracing::debug!("received request: {:?}", request);
-
-        let response = match request {
-            Request::Put {
-                filename,
-                file,
-                hash,
-            } => {
-                let revision = fs.insert(filename, file, hash);
-                Response::put(revision)
-            }
-            Request::Get { filename, revision } => match fs.get(&filename, revision).await {
-                Ok(file) => Response::get(file),
-                Err(reason) => Response::error(reason.to_string()),
-            },
-            Request::List { path } => {
-                let children = fs.list(&path);
-                Response::list(children)
-            }
-            Request::Help => Response::help(),
-        };
-
-        tracing::debug!("responded: {:?}", response);
-        client.send_response(response).await?;
+        let (conn, addr, permit) = throttle.accept(&listener).await?;
+        tokio::spawn(telemetry::connection(
+            addr,
+            permit.hold(handle_connection(
+                conn,
+                shared_filesystem,
+                shared_admission,
+                shared_uploads,
+                config.tenancy,
+            )),
+        ));
     }
-
-    Ok(())
 }
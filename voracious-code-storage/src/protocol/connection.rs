@@ -1,20 +1,25 @@
+use async_compression::tokio::{bufread::GzipDecoder, write::GzipEncoder};
 use async_tempfile::TempFile;
 use sha1::{Digest, Sha1};
+use telemetry::stats::Metered;
 use tokio::{
-    io::{AsyncBufReadExt, AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader, BufWriter},
+    io::{
+        AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader, BufWriter,
+    },
     net::TcpStream,
 };
 
-use crate::{protocol::message, storage::ListResult};
+use crate::{protocol::message, SharedAdmission, SharedUploads};
 
-use super::message::{Request, Response};
+use super::message::{Encoding, Request, Response};
+use vcs_protocol::READY;
 
 const BLOCK_SIZE: usize = 4096;
 
-const READY_MSG: &[u8] = "READY\n".as_bytes();
-
 pub struct Connection {
-    stream: BufReader<TcpStream>,
+    stream: BufReader<Metered<TcpStream>>,
+    admission: SharedAdmission,
+    uploads: SharedUploads,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -30,18 +35,28 @@ pub enum ConnectionErr {
 
     #[error("Reached eof")]
     Eof,
+
+    #[error("Refused to receive a file of {0} bytes")]
+    FileTooLarge(u64),
 }
 
 impl Connection {
     /// Creates a new connection out of a TcpStream
     ///
     /// notifies the client that the server is ready on creation.
-    pub async fn new(mut stream: TcpStream) -> tokio::io::Result<Self> {
-        stream.write_all(READY_MSG).await?;
+    pub async fn new(
+        stream: TcpStream,
+        admission: SharedAdmission,
+        uploads: SharedUploads,
+    ) -> tokio::io::Result<Self> {
+        let mut stream = Metered::new(stream);
+        stream.write_all(READY.as_bytes()).await?;
         tracing::debug!("a new connection has been initialized!");
 
         Ok(Self {
             stream: BufReader::new(stream),
+            admission,
+            uploads,
         })
     }
 
@@ -60,7 +75,10 @@ impl Connection {
 
             match self.process_raw_request(request).await? {
                 Ok(request) => return Ok(Some(request)),
-                Err(response) => self.send_response(response).await?,
+                Err(response) => {
+                    telemetry::stats::error("rejected");
+                    self.send_response(response).await?
+                }
             }
         }
     }
@@ -71,61 +89,84 @@ impl Connection {
     // - an error that can be ignored, and we only need to notify the client
     async fn process_raw_request(
         &mut self,
-        request: message::raw::Request,
+        request: vcs_protocol::Request,
     ) -> Result<Result<Request, Response>, ConnectionErr> {
         let request = match request {
-            message::raw::Request::Help => Request::Help,
-            message::raw::Request::List { path } => Request::List { path },
-            message::raw::Request::Get { filename, revision } => {
-                Request::Get { filename, revision }
+            vcs_protocol::Request::Help => Request::Help,
+            vcs_protocol::Request::List {
+                path,
+                pattern,
+                recursive,
+            } => Request::List {
+                path,
+                pattern,
+                recursive,
+            },
+            vcs_protocol::Request::Tenant { token } => Request::Tenant { token },
+            vcs_protocol::Request::Stat { filename } => Request::Stat { filename },
+            vcs_protocol::Request::Del { filename, revision } => {
+                Request::Del { filename, revision }
             }
-            message::raw::Request::Put {
+            vcs_protocol::Request::Get {
+                filename,
+                revision,
+                encoding,
+            } => Request::Get {
+                filename,
+                revision,
+                encoding,
+            },
+            vcs_protocol::Request::Put {
                 filename,
                 byte_count,
+                encoding,
             } => {
-                // create a tempfile and attemp the read the requested number of bytes from the socket
-                let mut file = TempFile::new().await?;
+                // the body of an oversized file is never read, and the client may not stop
+                // sending it, so there is no telling where the next request starts
+                if !self.uploads.fits(byte_count) {
+                    self.send_response(Response::error("file too large".into()))
+                        .await?;
+                    return Err(ConnectionErr::FileTooLarge(byte_count));
+                }
 
-                // use this opportunity to also calculate the hash
-                // of the file to avoid re-reading the file down the line
-                let mut hasher = Sha1::new();
+                let mut body = (&mut self.stream).take(byte_count);
 
-                // avoid creating a block that is bigger than the file itself
-                let mut block = vec![0u8; BLOCK_SIZE.min(byte_count as usize)];
-                let mut wcount = 0usize;
-                loop {
-                    // we've read the entire file
-                    if (byte_count as usize) <= wcount {
-                        break;
+                if !self.admission.admit_put() {
+                    // the server is overloaded, skip the body without storing it
+                    tokio::io::copy(&mut body, &mut tokio::io::sink()).await?;
+                    if body.limit() > 0 {
+                        return Err(ConnectionErr::Eof);
                     }
 
-                    // block is too big, we must resize it to avoid over-reading
-                    let remain = (byte_count as usize) - wcount;
-                    if block.len() > remain {
-                        block.resize(remain, 0)
-                    }
+                    return Ok(Err(Response::error("busy".into())));
+                }
 
-                    let rcount = self.stream.read(&mut block).await?;
-                    if rcount == 0 {
-                        break;
-                    }
+                // the body waits on the socket until the upload can start
+                let _permit = self.uploads.start(byte_count).await;
 
-                    if block[..rcount].iter().any(|byte| {
-                        !byte.is_ascii_graphic()
-                            && *byte != b'\r'
-                            && *byte != b'\n'
-                            && *byte != b' '
-                            && *byte != b'\t'
-                    }) {
-                        return Ok(Err(Response::error("text files only".into())));
+                // create a tempfile and attemp the read the requested number of bytes from the socket
+                let mut file = TempFile::new().await?;
+
+                let max_size = self.uploads.max_file_size();
+                let received = match encoding {
+                    Encoding::Plain => receive_file(&mut body, &mut file, max_size).await?,
+                    Encoding::Gzip => {
+                        let received =
+                            receive_file(GzipDecoder::new(&mut body), &mut file, max_size).await;
+
+                        // the compressed stream may end before its declared length,
+                        // skip whatever is left so the next request is read from the right place
+                        tokio::io::copy(&mut body, &mut tokio::io::sink()).await?;
+                        received?
                     }
+                };
 
-                    hasher.update(&block[..rcount]);
-                    file.write_all(&block[..rcount]).await?;
-                    wcount += rcount;
-                }
+                let hash = match received {
+                    Ok(hash) => hash,
+                    Err(response) => return Ok(Err(response)),
+                };
 
-                if wcount < byte_count as usize {
+                if body.limit() > 0 {
                     // reached EOF before reading the entirety of the file
                     return Err(ConnectionErr::Eof);
                 }
@@ -133,7 +174,7 @@ impl Connection {
                 Request::Put {
                     filename,
                     file,
-                    hash: hasher.finalize().to_vec(),
+                    hash,
                 }
             }
         };
@@ -142,8 +183,8 @@ impl Connection {
     }
 
     // same as read_request, but for raw request
-    async fn read_raw_request(&mut self) -> Result<Option<message::raw::Request>, ConnectionErr> {
-        use message::raw::{Request, RequestErr};
+    async fn read_raw_request(&mut self) -> Result<Option<vcs_protocol::Request>, ConnectionErr> {
+        use vcs_protocol::{Request, RequestErr};
 
         loop {
             // read new line
@@ -164,6 +205,7 @@ impl Connection {
             match line.parse::<Request>() {
                 Ok(request) => return Ok(Some(request)),
                 Err(err) => {
+                    telemetry::stats::error("parse");
                     // report error to client
                     self.send_response(Response::error(err.to_string())).await?;
 
@@ -183,18 +225,20 @@ impl Connection {
     /// Writes the given response to the client
     pub async fn send_response(&mut self, response: Response) -> Result<(), ConnectionErr> {
         use message::raw::Response;
-        match response.raw {
-            Response::Err(reason) => {
-                self.stream
-                    .write_all(format!("ERR {}\n", reason).as_bytes())
-                    .await?
-            }
-            Response::Help => {
-                self.stream
-                    .write_all("OK usage: HELP|GET|PUT|LIST\n".as_bytes())
-                    .await?
-            }
-            Response::Get { mut file } => {
+
+        let lines = match response.raw {
+            Response::Err(reason) => vcs_protocol::Response::Err(reason),
+            Response::Help => vcs_protocol::Response::Help,
+            Response::Ok => vcs_protocol::Response::Ok,
+            Response::Put { revision } => vcs_protocol::Response::Put { revision },
+            Response::List { children } => vcs_protocol::Response::List { children },
+            Response::Stat { revisions } => vcs_protocol::Response::Stat { revisions },
+            Response::Get { file, encoding } => {
+                let mut file = match encoding {
+                    Encoding::Plain => file,
+                    Encoding::Gzip => compress(file).await?,
+                };
+
                 // make sure to read the file from the beginning
                 file.seek(std::io::SeekFrom::Start(0)).await?;
                 let metadata = file.metadata().await?;
@@ -204,9 +248,10 @@ impl Connection {
                 let mut writer = BufWriter::new(&mut self.stream);
 
                 // write an OK status with file size information
-                writer
-                    .write_all(format!("OK {}\n", metadata.len()).as_bytes())
-                    .await?;
+                let status = vcs_protocol::Response::Get {
+                    size: metadata.len(),
+                };
+                writer.write_all(status.to_string().as_bytes()).await?;
 
                 // dump the into the stream, in blocks
                 // avoid creating a block with a size bigger than the file itself
@@ -223,46 +268,76 @@ impl Connection {
 
                 // make sure to clean the buffer before we drop it
                 writer.flush().await?;
+                self.stream.write_all(READY.as_bytes()).await?;
+
+                return Ok(());
             }
-            Response::Put { revision } => {
-                self.stream
-                    .write_all(format!("OK r{}\n", revision).as_bytes())
-                    .await?
-            }
-            Response::List { children } => {
-                // use a buffer to avoid too many syscalls
-                let mut writer = BufWriter::new(&mut self.stream);
+        };
 
-                // write an OK status with the number of children
-                writer
-                    .write_all(format!("OK {}\n", children.len()).as_bytes())
-                    .await?;
-
-                // list the children
-                for child in children {
-                    match child {
-                        ListResult::Dir(name) => {
-                            writer
-                                .write_all(format!("{}/ DIR\n", name).as_bytes())
-                                .await?
-                        }
-                        ListResult::File {
-                            name,
-                            last_revision,
-                        } => {
-                            writer
-                                .write_all(format!("{} r{}\n", name, last_revision).as_bytes())
-                                .await?
-                        }
-                    }
-                }
+        // a listing is written at once, rather than a syscall per line
+        self.stream.write_all(lines.to_string().as_bytes()).await?;
+        self.stream.write_all(READY.as_bytes()).await?;
 
-                writer.flush().await?;
+        Ok(())
+    }
+}
+
+// Reads a file from the source into the tempfile, checking that it only contains text
+// and that it's no bigger than the max size (a compressed file may grow well past its length)
+//
+// returns the hash of the file, or an error response when the content isn't acceptable
+async fn receive_file<R: AsyncRead + Unpin>(
+    mut source: R,
+    file: &mut TempFile,
+    max_size: Option<u64>,
+) -> Result<Result<Vec<u8>, Response>, ConnectionErr> {
+    // use this opportunity to also calculate the hash
+    // of the file to avoid re-reading the file down the line
+    let mut hasher = Sha1::new();
+
+    let mut size = 0;
+    let mut block = vec![0u8; BLOCK_SIZE];
+    loop {
+        let rcount = match source.read(&mut block).await {
+            Ok(0) => break,
+            Ok(rcount) => rcount,
+            // only the decoder ever reports invalid data
+            Err(err) if err.kind() == std::io::ErrorKind::InvalidData => {
+                return Ok(Err(Response::error("invalid gzip data".into())));
             }
+            Err(err) => return Err(err.into()),
         };
 
-        self.stream.write_all(READY_MSG).await?;
+        if block[..rcount].iter().any(|byte| {
+            !byte.is_ascii_graphic()
+                && *byte != b'\r'
+                && *byte != b'\n'
+                && *byte != b' '
+                && *byte != b'\t'
+        }) {
+            return Ok(Err(Response::error("text files only".into())));
+        }
 
-        Ok(())
+        size += rcount as u64;
+        if max_size.is_some_and(|max| size > max) {
+            return Ok(Err(Response::error("file too large".into())));
+        }
+
+        hasher.update(&block[..rcount]);
+        file.write_all(&block[..rcount]).await?;
     }
+
+    Ok(Ok(hasher.finalize().to_vec()))
+}
+
+// Compresses a file into a new tempfile, so its compressed size is known before sending it
+async fn compress(mut file: TempFile) -> Result<TempFile, ConnectionErr> {
+    file.seek(std::io::SeekFrom::Start(0)).await?;
+
+    let mut compressed = TempFile::new().await?;
+    let mut encoder = GzipEncoder::new(&mut compressed);
+    tokio::io::copy(&mut file, &mut encoder).await?;
+    encoder.shutdown().await?;
+
+    Ok(compressed)
 }
@@ -1,6 +1,8 @@
 use async_tempfile::TempFile;
 
-use crate::storage::ListResult;
+pub use vcs_protocol::Encoding;
+
+use crate::storage::{Glob, ListResult, Metadata};
 
 #[derive(Debug)]
 pub enum Request {
@@ -12,13 +14,46 @@ pub enum Request {
     Get {
         filename: String,
         revision: Option<u64>,
+        encoding: Encoding,
     },
     List {
         path: String,
+        // only the names that match are listed
+        pattern: Option<Glob>,
+        // lists every file under the dir, by its relative path
+        recursive: bool,
+    },
+    // removes a single revision, or the whole file
+    Del {
+        filename: String,
+        revision: Option<u64>,
+    },
+    // the metadata of every revision of a file
+    Stat {
+        filename: String,
+    },
+    // switches the namespace of the connection
+    Tenant {
+        token: String,
     },
     Help,
 }
 
+impl Request {
+    /// The name of the request's method, as it's labeled in the metrics
+    pub fn method(&self) -> &'static str {
+        match self {
+            Self::Put { .. } => "put",
+            Self::Get { .. } => "get",
+            Self::List { .. } => "list",
+            Self::Del { .. } => "del",
+            Self::Stat { .. } => "stat",
+            Self::Tenant { .. } => "tenant",
+            Self::Help => "help",
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Response {
     pub(super) raw: raw::Response,
@@ -31,9 +66,9 @@ impl Response {
         }
     }
 
-    pub fn get(file: TempFile) -> Self {
+    pub fn get(file: TempFile, encoding: Encoding) -> Self {
         Self {
-            raw: raw::Response::Get { file },
+            raw: raw::Response::Get { file, encoding },
         }
     }
 
@@ -49,286 +84,40 @@ impl Response {
         }
     }
 
+    pub fn stat(revisions: Vec<(u64, Metadata)>) -> Self {
+        Self {
+            raw: raw::Response::Stat { revisions },
+        }
+    }
+
     pub fn help() -> Self {
         Self {
             raw: raw::Response::Help,
         }
     }
-}
-// Raw structures for internal use
-pub(super) mod raw {
-    use std::str::FromStr;
 
+    pub fn ok() -> Self {
+        Self {
+            raw: raw::Response::Ok,
+        }
+    }
+}
+// A response as the server keeps it, the content of a GET is only read when it's sent
+pub(crate) mod raw {
     use async_tempfile::TempFile;
 
-    use crate::storage::ListResult;
+    use crate::storage::{ListResult, Metadata};
 
-    const PUT_USAGE_MSG: &str = "PUT file length newline data";
-    const GET_USAGE_MSG: &str = "GET file [revision]";
-    const LIST_USAGE_MSG: &str = "LIST dir";
+    use super::Encoding;
 
     #[derive(Debug)]
     pub enum Response {
         Put { revision: u64 },
-        Get { file: TempFile },
+        Get { file: TempFile, encoding: Encoding },
         List { children: Vec<ListResult> },
+        Stat { revisions: Vec<(u64, Metadata)> },
         Help,
+        Ok,
         Err(String),
     }
-
-    #[derive(Debug, PartialEq)]
-    pub enum Request {
-        Put {
-            filename: String,
-            byte_count: u64,
-        },
-        Get {
-            filename: String,
-            revision: Option<u64>,
-        },
-        List {
-            path: String,
-        },
-        Help,
-    }
-
-    #[derive(thiserror::Error, Debug)]
-    pub enum RequestErr {
-        #[error("illegal method: {0}")]
-        IllegalMethod(String),
-
-        #[error("usage: {0}")]
-        BadUsage(String),
-
-        #[error("illegal file name")]
-        IllegalFileName,
-
-        #[error("illegal dir name")]
-        IllegalDirName,
-    }
-
-    impl FromStr for Request {
-        type Err = RequestErr;
-
-        fn from_str(s: &str) -> Result<Self, Self::Err> {
-            let mut parts = s.trim().split_ascii_whitespace();
-
-            let method = parts
-                .next()
-                // method is case insensitive
-                .map(|method| method.to_uppercase())
-                .unwrap_or_default();
-
-            match method.as_str() {
-                "PUT" => {
-                    // parse put request
-                    let filename: String = parts
-                        .next()
-                        .ok_or_else(|| RequestErr::BadUsage(PUT_USAGE_MSG.into()))?
-                        .into();
-                    if !check_filename(&filename) {
-                        return Err(RequestErr::IllegalFileName);
-                    }
-
-                    let byte_count = parts
-                        .next()
-                        .and_then(|value| value.parse().ok())
-                        .ok_or_else(|| RequestErr::BadUsage(PUT_USAGE_MSG.into()))?;
-
-                    // make sure we've consumed the entire line
-                    if parts.next().is_some() {
-                        return Err(RequestErr::BadUsage(PUT_USAGE_MSG.into()));
-                    }
-
-                    Ok(Self::Put {
-                        filename,
-                        byte_count,
-                    })
-                }
-                "GET" => {
-                    // parse get request
-                    let filename: String = parts
-                        .next()
-                        .ok_or_else(|| RequestErr::BadUsage(GET_USAGE_MSG.into()))?
-                        .into();
-                    if !check_filename(&filename) {
-                        return Err(RequestErr::IllegalFileName);
-                    }
-
-                    let revision = parts
-                        .next()
-                        .map(|value| value.strip_prefix('r').unwrap_or(value));
-
-                    let revision = match revision {
-                        Some(revision) => Some(
-                            revision
-                                .parse()
-                                .map_err(|_| RequestErr::BadUsage(GET_USAGE_MSG.into()))?,
-                        ),
-                        None => None,
-                    };
-
-                    // make sure we've consumed the entire line
-                    if parts.next().is_some() {
-                        return Err(RequestErr::BadUsage(GET_USAGE_MSG.into()));
-                    }
-
-                    Ok(Self::Get { filename, revision })
-                }
-                "LIST" => {
-                    let path: String = validate_dirpath(
-                        parts
-                            .next()
-                            .ok_or_else(|| RequestErr::BadUsage(LIST_USAGE_MSG.into()))?
-                            .into(),
-                    )?;
-
-                    // make sure we've consumed the entire line
-                    if parts.next().is_some() {
-                        return Err(RequestErr::BadUsage(LIST_USAGE_MSG.into()));
-                    }
-
-                    Ok(Self::List { path })
-                }
-                "HELP" => Ok(Self::Help),
-                _ => Err(RequestErr::IllegalMethod(method.to_string())),
-            }
-        }
-    }
-
-    // checks that the filename matches the expected format
-    fn check_filename(filename: &str) -> bool {
-        // files should always start at root
-        if !filename.starts_with('/') {
-            return false;
-        }
-
-        let filename = &filename[1..];
-
-        // file name can not be empty
-        if filename.trim().is_empty() {
-            return false;
-        }
-
-        // each part of the path most contain something
-        if !validate_strippted_path(filename) {
-            return false;
-        }
-
-        true
-    }
-
-    // checks that a dir name matches the expected format
-    // and return a unified view of this dir
-    fn validate_dirpath(mut dir: String) -> Result<String, RequestErr> {
-        // dir path should always start at root
-        if !dir.starts_with('/') {
-            return Err(RequestErr::IllegalDirName);
-        }
-
-        // check for proper naming
-        if !dir
-            .chars()
-            .all(|char| char.is_alphanumeric() || char == '.' || char == '_' || char == '/')
-        {
-            return Err(RequestErr::IllegalDirName);
-        }
-
-        // dir may, or may not, end with a '/'
-        if !dir.ends_with('/') {
-            dir.push('/');
-        }
-
-        // each part of the path most contain something and be one of "alphanumeric, dot, underscore"
-        if dir.len() > 1 && !validate_strippted_path(&dir[1..dir.len() - 1]) {
-            return Err(RequestErr::IllegalDirName);
-        }
-
-        Ok(dir)
-    }
-
-    fn validate_strippted_path(path: &str) -> bool {
-        path.split('/').all(|part| {
-            !part.is_empty()
-                && part
-                    .chars()
-                    .all(|char| char.is_alphanumeric() || char == '.' || char == '_' || char == '-')
-        })
-    }
-
-    #[cfg(test)]
-    mod tests {
-        use super::Request;
-
-        #[test]
-        fn check_valid_request_parsing() {
-            let raw_requests = [
-                "puT /test.txt 35",
-                "GEt /text.txt",
-                "GeT /text.txt 90",
-                "gET /text.txt r5",
-                "LIST /test/",
-                "LIST /test/test2/test44/../test5",
-                "PuT /v.-WC1CDakNoPWm4YiOxD7p-F2VC8-AahIWXRQ/gHDhPY8euDkFdTa3lo5oPsV7-KpOQKknmnNSRHX4jKxm9omKLVrZPB3WIQ27nLB.h2KjsMx-q5H_GU0F9eIXyFPcgu 57"
-            ];
-
-            let expected_requests = [
-                Request::Put {
-                    filename: "/test.txt".into(),
-                    byte_count: 35,
-                },
-                Request::Get {
-                    filename: "/text.txt".into(),
-                    revision: None,
-                },
-                Request::Get {
-                    filename: "/text.txt".into(),
-                    revision: Some(90),
-                },
-                Request::Get {
-                    filename: "/text.txt".into(),
-                    revision: Some(5),
-                },
-                Request::List {
-                    path: "/test/".into(),
-                },
-                Request::List {
-                    path: "/test/test2/test44/../test5/".into(),
-                },
-                Request::Put { filename: "/v.-WC1CDakNoPWm4YiOxD7p-F2VC8-AahIWXRQ/gHDhPY8euDkFdTa3lo5oPsV7-KpOQKknmnNSRHX4jKxm9omKLVrZPB3WIQ27nLB.h2KjsMx-q5H_GU0F9eIXyFPcgu".into(), byte_count: 57 }
-            ];
-
-            for (request, expected) in raw_requests.into_iter().zip(expected_requests.iter()) {
-                let request = match request.parse::<Request>() {
-                    Ok(request) => request,
-                    Err(reason) => panic!("failed to parse\n{}\nreason: {}", request, reason),
-                };
-
-                assert_eq!(request, *expected);
-            }
-        }
-
-        #[test]
-        fn check_bad_request_parsing() {
-            let bad_request = [
-                "PUT /text.txt",
-                "PUT /text abc",
-                "PUT /text r2",
-                "GET /text\\. text",
-                "GET /text.txt 123 123",
-                "GET /text/ 12",
-                "GET /text//test 12",
-                "LIST /test//",
-                "LIST",
-                "LISt /test//test/",
-                "LiSt /test/../test//",
-                "PuT PUT /mbA+u|=]hj)oMraH0pS 123",
-            ];
-
-            for request in bad_request {
-                let request: Result<Request, _> = request.parse();
-                assert!(request.is_err())
-            }
-        }
-    }
 }
@@ -0,0 +1,133 @@
+use std::time::Instant;
+
+use tokio::net::TcpStream;
+
+use crate::{
+    admission::Kind,
+    protocol::{
+        connection::Connection,
+        message::{Request, Response},
+    },
+    stats::{self, RequestCounts},
+    storage::Namespace,
+    SharedAdmission, SharedFileSystem, SharedUploads,
+};
+
+/// Serves a single client until it disconnects
+pub async fn handle_connection(
+    stream: TcpStream,
+    fs: SharedFileSystem,
+    admission: SharedAdmission,
+    uploads: SharedUploads,
+    tenancy: bool,
+) -> anyhow::Result<()> {
+    let mut counts = RequestCounts::default();
+
+    let result = serve(stream, fs, admission, uploads, tenancy, &mut counts).await;
+    tracing::info!(
+        "the session has ended after {} requests: {}",
+        counts.total(),
+        counts
+    );
+
+    result
+}
+
+async fn serve(
+    stream: TcpStream,
+    fs: SharedFileSystem,
+    admission: SharedAdmission,
+    uploads: SharedUploads,
+    tenancy: bool,
+    counts: &mut RequestCounts,
+) -> anyhow::Result<()> {
+    // until the client picks a namespace, it gets the one of its address
+    let mut namespace = match tenancy {
+        true => Namespace::address(stream.peer_addr()?.ip()),
+        false => Namespace::default(),
+    };
+    let mut client = Connection::new(stream, admission, uploads).await?;
+
+    while let Some(request) = client.read_request().await? {
+        tracing::debug!("received request: {:?}", request);
+
+        // the latency of a request runs from the moment it was received until it's fully answered
+        let received_at = Instant::now();
+        let method = request.method();
+        let kind = match request {
+            Request::Put { .. } => Some(Kind::Put),
+            Request::Get { .. } => Some(Kind::Get),
+            _ => None,
+        };
+
+        let response = match request {
+            Request::Put {
+                filename,
+                file,
+                hash,
+            } => match fs.insert(namespace.resolve(&filename), file, hash).await {
+                Ok(revision) => Response::put(revision),
+                Err(reason) => Response::error(reason.to_string()),
+            },
+            Request::Get {
+                filename,
+                revision,
+                encoding,
+            } => match fs.get(&namespace.resolve(&filename), revision).await {
+                Ok(file) => Response::get(file, encoding),
+                Err(reason) => Response::error(reason.to_string()),
+            },
+            Request::List {
+                path,
+                pattern,
+                recursive,
+            } => {
+                let path = namespace.resolve(&path);
+                let mut children = match recursive {
+                    true => fs.list_recursive(&path),
+                    false => fs.list(&path),
+                };
+                // a nested file is matched by its own name, not by its path
+                if let Some(pattern) = pattern {
+                    children.retain(|child| {
+                        pattern.matches(child.name().rsplit('/').next().unwrap_or_default())
+                    });
+                }
+
+                Response::list(children)
+            }
+            Request::Del { filename, revision } => {
+                match fs.remove(&namespace.resolve(&filename), revision).await {
+                    Ok(()) => Response::ok(),
+                    Err(reason) => Response::error(reason.to_string()),
+                }
+            }
+            Request::Stat { filename } => match fs.stat(&namespace.resolve(&filename)) {
+                Ok(revisions) => Response::stat(revisions),
+                Err(reason) => Response::error(reason.to_string()),
+            },
+            Request::Tenant { token } => match tenancy {
+                true => {
+                    namespace = Namespace::token(&token);
+                    Response::ok()
+                }
+                false => Response::error("tenancy is disabled".into()),
+            },
+            Request::Help => Response::help(),
+        };
+
+        tracing::debug!("responded: {:?}", response);
+        client.send_response(response).await?;
+
+        let latency = received_at.elapsed();
+        if let Some(kind) = kind {
+            admission.record(kind, latency);
+        }
+
+        counts.record(method);
+        telemetry::stats::request(method);
+        metrics::histogram!(stats::REQUEST_DURATION, "method" => method).record(latency);
+    }
+
+    Ok(())
+}
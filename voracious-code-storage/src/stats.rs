@@ -0,0 +1,54 @@
+use std::{collections::BTreeMap, fmt};
+
+// the time it takes to handle a request, from the moment it was received until it's fully answered,
+// labeled by the method (the requests themselves are counted by telemetry::stats, like every server's)
+pub const REQUEST_DURATION: &str = "voracious_code_storage_request_duration_seconds";
+
+// 100us up to ~100s, every bucket is 4 times the previous one
+pub const DURATION_BUCKETS: &[f64] = &[
+    0.0001, 0.0004, 0.0016, 0.0064, 0.0256, 0.1024, 0.4096, 1.6384, 6.5536, 26.2144, 104.8576,
+];
+
+/// The number of requests of every method a single connection has made
+#[derive(Debug, Default)]
+pub struct RequestCounts(BTreeMap<&'static str, u64>);
+
+impl RequestCounts {
+    pub fn record(&mut self, method: &'static str) {
+        *self.0.entry(method).or_default() += 1;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.0.values().sum()
+    }
+}
+
+impl fmt::Display for RequestCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let counts: Vec<_> = self
+            .0
+            .iter()
+            .map(|(method, count)| format!("{}={}", method, count))
+            .collect();
+
+        write!(f, "{}", counts.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RequestCounts;
+
+    #[test]
+    fn count_requests() {
+        let mut counts = RequestCounts::default();
+        assert_eq!(counts.total(), 0);
+        assert_eq!(counts.to_string(), "");
+
+        for method in ["put", "get", "put", "list"] {
+            counts.record(method);
+        }
+        assert_eq!(counts.total(), 4);
+        assert_eq!(counts.to_string(), "get=1 list=1 put=2");
+    }
+}
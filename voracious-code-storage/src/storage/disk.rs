@@ -0,0 +1,565 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime},
+};
+
+use async_tempfile::{Ownership, TempFile};
+use async_trait::async_trait;
+use sha1::{Digest, Sha1};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    sync::Mutex,
+};
+
+use super::{
+    locks::FileLocks,
+    tree::{Entry, Revision, Tree},
+    ListResult, Metadata, Storage, StorageErr,
+};
+
+const INDEX_FILE: &str = "index";
+const BLOBS_DIR: &str = "blobs";
+
+/// Keeps the files under a root dir, so they survive restarts
+///
+/// every revision is stored as a numbered blob, and an index file maps the files to their blobs.
+/// the index has a line per file: its path, followed by a "<blob>:<hash>:<created>:<size>"
+/// for every revision (or a "-" for a deleted revision), and it's rewritten after every change.
+/// the creation time is in seconds since the unix epoch
+#[derive(Debug)]
+pub struct DiskStorage {
+    root: PathBuf,
+    tree: Tree<u64>,
+    next_blob: AtomicU64,
+    // the blobs of different files are written concurrently, but their changes to the tree
+    // are applied one at a time, so the index is always written in the order of the changes
+    index: Mutex<()>,
+    locks: FileLocks,
+}
+
+impl DiskStorage {
+    /// Opens the storage at the root dir, a missing dir is treated as an empty storage
+    pub async fn open(root: PathBuf) -> Result<Self, StorageErr> {
+        tokio::fs::create_dir_all(root.join(BLOBS_DIR)).await?;
+
+        let tree = Tree::default();
+        let mut next_blob = 0;
+        match tokio::fs::read_to_string(root.join(INDEX_FILE)).await {
+            Ok(index) => {
+                for (idx, line) in index.lines().enumerate() {
+                    let (filepath, index_entries) =
+                        parse_line(line).ok_or(StorageErr::CorruptedIndex(idx + 1))?;
+
+                    let mut entries = Vec::with_capacity(index_entries.len());
+                    for entry in index_entries {
+                        let Some((blob, hash, meta)) = entry else {
+                            entries.push(None);
+                            continue;
+                        };
+                        next_blob = next_blob.max(blob + 1);
+
+                        // an index written before the metadata was tracked,
+                        // the blob was written when the revision was stored
+                        let meta = match meta {
+                            Some(meta) => meta,
+                            None => {
+                                blob_metadata(&root.join(BLOBS_DIR).join(blob.to_string())).await?
+                            }
+                        };
+                        entries.push(Some(Revision {
+                            content: blob,
+                            hash,
+                            meta,
+                        }));
+                    }
+
+                    tree.restore(filepath, entries);
+                }
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        Ok(Self {
+            root,
+            tree,
+            next_blob: AtomicU64::new(next_blob),
+            index: Mutex::new(()),
+            locks: FileLocks::default(),
+        })
+    }
+
+    /// Checks the storage at the root dir against its index, the server must not be running on it
+    ///
+    /// every blob is hashed again and compared with the hash in the index,
+    /// and the blobs that the index doesn't refer to are reported as orphaned.
+    /// on repair, the revisions of missing blobs are deleted, mismatched hashes are replaced
+    /// by the hashes of their blobs, and orphaned blobs are removed
+    pub async fn verify(root: &Path, repair: bool) -> Result<Verification, StorageErr> {
+        let blobs_dir = root.join(BLOBS_DIR);
+        let index = match tokio::fs::read_to_string(root.join(INDEX_FILE)).await {
+            Ok(index) => index,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut verification = Verification::default();
+        let mut referenced = HashSet::new();
+        let mut lines = Vec::new();
+        for (idx, line) in index.lines().enumerate() {
+            let (filepath, index_entries) =
+                parse_line(line).ok_or(StorageErr::CorruptedIndex(idx + 1))?;
+
+            let mut entries = Vec::with_capacity(index_entries.len());
+            for (revision, entry) in (1..).zip(index_entries) {
+                let Some((blob, hash, meta)) = entry else {
+                    entries.push(None);
+                    continue;
+                };
+                verification.revisions += 1;
+                referenced.insert(blob.to_string());
+
+                let blob_path = blobs_dir.join(blob.to_string());
+                let actual = match hash_blob(&blob_path).await {
+                    Ok(actual) => actual,
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                        verification.missing.push((filepath.clone(), revision));
+                        entries.push(None);
+                        continue;
+                    }
+                    Err(err) => return Err(err.into()),
+                };
+                if actual != hash {
+                    verification.mismatched.push((filepath.clone(), revision));
+                }
+
+                let meta = match meta {
+                    Some(meta) => meta,
+                    None => blob_metadata(&blob_path).await?,
+                };
+                entries.push(Some(Revision {
+                    content: blob,
+                    hash: actual,
+                    meta,
+                }));
+            }
+
+            lines.push(format_line(&filepath, &entries));
+        }
+
+        match tokio::fs::read_dir(&blobs_dir).await {
+            Ok(mut dir) => {
+                while let Some(entry) = dir.next_entry().await? {
+                    let name = entry.file_name().to_string_lossy().into_owned();
+                    if !referenced.contains(&name) {
+                        verification.orphaned.push(name);
+                    }
+                }
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+        verification.orphaned.sort();
+
+        if repair && !verification.is_clean() {
+            if !verification.missing.is_empty() || !verification.mismatched.is_empty() {
+                let mut index = String::new();
+                for line in lines {
+                    index += &line;
+                    index.push('\n');
+                }
+
+                let path = root.join(INDEX_FILE);
+                let tmp = path.with_extension("tmp");
+                tokio::fs::write(&tmp, index).await?;
+                tokio::fs::rename(tmp, path).await?;
+            }
+
+            // the index is already repaired, an orphan that is left behind is only wasted space
+            for name in &verification.orphaned {
+                tokio::fs::remove_file(blobs_dir.join(name)).await?;
+            }
+            verification.repaired = true;
+        }
+
+        Ok(verification)
+    }
+
+    fn blob_path(&self, blob: u64) -> PathBuf {
+        self.root.join(BLOBS_DIR).join(blob.to_string())
+    }
+
+    // the index is written to a temporary file first,
+    // so a crash in the middle of a write can't leave a corrupted index behind
+    async fn save_index(&self) -> Result<(), StorageErr> {
+        let mut index = String::new();
+        for (filepath, entries) in self.tree.snapshot() {
+            index += &format_line(&filepath, &entries);
+            index.push('\n');
+        }
+
+        let path = self.root.join(INDEX_FILE);
+        let tmp = path.with_extension("tmp");
+        tokio::fs::write(&tmp, index).await?;
+        tokio::fs::rename(tmp, path).await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Storage for DiskStorage {
+    async fn insert(
+        &self,
+        filepath: String,
+        mut file: TempFile,
+        hash: Vec<u8>,
+    ) -> Result<u64, StorageErr> {
+        let _file = self.locks.lock(&filepath).await;
+
+        // the upload may still have writes in flight
+        file.flush().await?;
+        let blob = self.next_blob.fetch_add(1, Ordering::Relaxed);
+        let size = tokio::fs::copy(file.file_path(), self.blob_path(blob)).await?;
+
+        let _index = self.index.lock().await;
+        let (revision, duplicate) = self.tree.insert(filepath, blob, hash, Metadata::now(size));
+        match duplicate {
+            Some(blob) => tokio::fs::remove_file(self.blob_path(blob)).await?,
+            None => self.save_index().await?,
+        }
+
+        Ok(revision)
+    }
+
+    async fn get(&self, name: &str, revision: Option<u64>) -> Result<TempFile, StorageErr> {
+        let blob = self.tree.get(name, revision)?;
+
+        // the blob belongs to the storage, reading it must not delete it
+        Ok(TempFile::from_existing(self.blob_path(blob), Ownership::Borrowed).await?)
+    }
+
+    fn list(&self, dir_path: &str) -> Vec<ListResult> {
+        self.tree.list(dir_path)
+    }
+
+    fn list_recursive(&self, dir_path: &str) -> Vec<ListResult> {
+        self.tree.list_recursive(dir_path)
+    }
+
+    fn stat(&self, name: &str) -> Result<Vec<(u64, Metadata)>, StorageErr> {
+        self.tree.stat(name)
+    }
+
+    async fn remove(&self, name: &str, revision: Option<u64>) -> Result<(), StorageErr> {
+        let _file = self.locks.lock(name).await;
+        let _index = self.index.lock().await;
+
+        let blobs = self.tree.remove(name, revision)?;
+        self.save_index().await?;
+
+        // the index no longer refers to the blobs, a blob that is left behind is only wasted space
+        for blob in blobs {
+            if let Err(err) = tokio::fs::remove_file(self.blob_path(blob)).await {
+                tracing::warn!("failed to remove blob {}: {}", blob, err);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// What `DiskStorage::verify` has found, every revision is named by its file and revision number
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Verification {
+    /// The number of revisions in the index (that weren't deleted)
+    pub revisions: usize,
+    /// Revisions whose blob is missing
+    pub missing: Vec<(String, u64)>,
+    /// Revisions whose blob doesn't match their hash
+    pub mismatched: Vec<(String, u64)>,
+    /// Blobs the index doesn't refer to
+    pub orphaned: Vec<String>,
+    /// Whether the problems were repaired
+    pub repaired: bool,
+}
+
+impl Verification {
+    pub fn is_clean(&self) -> bool {
+        self.missing.is_empty() && self.mismatched.is_empty() && self.orphaned.is_empty()
+    }
+}
+
+// the hash of a blob, the same one the server computes while receiving the file
+async fn hash_blob(path: &Path) -> std::io::Result<Vec<u8>> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = Sha1::new();
+    let mut block = vec![0u8; 64 * 1024];
+    loop {
+        match file.read(&mut block).await? {
+            0 => break,
+            rcount => hasher.update(&block[..rcount]),
+        }
+    }
+
+    Ok(hasher.finalize().to_vec())
+}
+
+async fn blob_metadata(path: &Path) -> Result<Metadata, StorageErr> {
+    let metadata = tokio::fs::metadata(path).await?;
+    Ok(Metadata {
+        created: metadata.modified()?,
+        size: metadata.len(),
+    })
+}
+
+// a revision in the index, the metadata is missing from the entries of an index
+// that was written before it was tracked
+type IndexEntry = Option<(u64, Vec<u8>, Option<Metadata>)>;
+
+fn format_line(filepath: &str, entries: &[Entry<u64>]) -> String {
+    let mut line = filepath.to_string();
+    for entry in entries {
+        match entry {
+            Some(revision) => {
+                let hash: String = revision
+                    .hash
+                    .iter()
+                    .map(|byte| format!("{:02x}", byte))
+                    .collect();
+                let created = revision
+                    .meta
+                    .created
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                line += &format!(
+                    " {}:{}:{}:{}",
+                    revision.content, hash, created, revision.meta.size
+                );
+            }
+            None => line += " -",
+        }
+    }
+
+    line
+}
+
+fn parse_line(line: &str) -> Option<(String, Vec<IndexEntry>)> {
+    let mut parts = line.split_ascii_whitespace();
+    let filepath = parts.next().filter(|path| path.starts_with('/'))?;
+
+    let entries = parts
+        .map(|part| match part {
+            "-" => Some(None),
+            _ => {
+                let mut fields = part.split(':');
+                let (blob, hash) = (fields.next()?, fields.next()?);
+                let meta = match (fields.next(), fields.next(), fields.next()) {
+                    (None, _, _) => None,
+                    (Some(created), Some(size), None) => Some(Metadata {
+                        created: SystemTime::UNIX_EPOCH
+                            .checked_add(Duration::from_secs(created.parse().ok()?))?,
+                        size: size.parse().ok()?,
+                    }),
+                    _ => return None,
+                };
+                if hash.len() % 2 != 0 || !hash.is_ascii() {
+                    return None;
+                }
+                let hash = (0..hash.len())
+                    .step_by(2)
+                    .map(|idx| u8::from_str_radix(&hash[idx..idx + 2], 16).ok())
+                    .collect::<Option<_>>()?;
+
+                Some(Some((blob.parse().ok()?, hash, meta)))
+            }
+        })
+        .collect::<Option<_>>()?;
+
+    Some((filepath.into(), entries))
+}
+
+#[cfg(test)]
+mod tests {
+    use sha1::{Digest, Sha1};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use std::time::{Duration, SystemTime};
+
+    use crate::storage::{tree::Revision, ListResult, Metadata, Storage};
+
+    use super::{format_line, parse_line, DiskStorage, Verification};
+
+    #[test]
+    fn index_lines_round_trip() {
+        let meta = |created, size| Metadata {
+            created: SystemTime::UNIX_EPOCH + Duration::from_secs(created),
+            size,
+        };
+        let entries = vec![
+            Some(Revision {
+                content: 0,
+                hash: vec![0x0a, 0xff],
+                meta: meta(1700000000, 12),
+            }),
+            None,
+            Some(Revision {
+                content: 7,
+                hash: vec![],
+                meta: meta(0, 0),
+            }),
+        ];
+        let line = format_line("/dir/a.txt", &entries);
+        assert_eq!(line, "/dir/a.txt 0:0aff:1700000000:12 - 7::0:0");
+        assert_eq!(
+            parse_line(&line),
+            Some((
+                "/dir/a.txt".into(),
+                vec![
+                    Some((0, vec![0x0a, 0xff], Some(meta(1700000000, 12)))),
+                    None,
+                    Some((7, vec![], Some(meta(0, 0)))),
+                ]
+            ))
+        );
+
+        // an index written before the metadata was tracked
+        assert_eq!(
+            parse_line("/a.txt 0:0a"),
+            Some(("/a.txt".into(), vec![Some((0, vec![0x0a], None))]))
+        );
+
+        assert_eq!(parse_line("a.txt 0:0a"), None);
+        assert_eq!(parse_line("/a.txt 0:0"), None);
+        assert_eq!(parse_line("/a.txt x:0a"), None);
+        assert_eq!(parse_line("/a.txt 0:zz"), None);
+        assert_eq!(parse_line("/a.txt 0:0a:1"), None);
+        assert_eq!(parse_line("/a.txt 0:0a:1:x"), None);
+        // a creation time that no system time can hold
+        let line = format!("/a.txt 0:0a:{}:1", u64::MAX);
+        assert_eq!(parse_line(&line), None);
+        assert_eq!(parse_line("/a.txt 0:0a:1:2:3"), None);
+    }
+
+    #[tokio::test]
+    async fn survive_reopening() {
+        let dir = std::env::temp_dir().join(format!(
+            "voracious-code-storage-reopen-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let storage = DiskStorage::open(dir.clone()).await.unwrap();
+        for (name, content) in [
+            ("/a.txt", "one"),
+            ("/a.txt", "two"),
+            ("/a.txt", "one"),
+            ("/dir/b.txt", "b"),
+        ] {
+            let mut file = async_tempfile::TempFile::new().await.unwrap();
+            file.write_all(content.as_bytes()).await.unwrap();
+            storage
+                .insert(name.into(), file, content.as_bytes().to_vec())
+                .await
+                .unwrap();
+        }
+        storage.remove("/a.txt", Some(1)).await.unwrap();
+        drop(storage);
+
+        let storage = DiskStorage::open(dir.clone()).await.unwrap();
+        let mut content = String::new();
+        storage
+            .get("/a.txt", None)
+            .await
+            .unwrap()
+            .read_to_string(&mut content)
+            .await
+            .unwrap();
+        assert_eq!(content, "two");
+        assert!(storage.get("/a.txt", Some(1)).await.is_err());
+        let stat = storage.stat("/a.txt").unwrap();
+        assert_eq!(stat.len(), 1);
+        assert_eq!((stat[0].0, stat[0].1.size), (2, 3));
+        assert_eq!(
+            storage.list("/"),
+            [
+                ListResult::File {
+                    name: "a.txt".into(),
+                    last_revision: 2
+                },
+                ListResult::Dir("dir".into()),
+            ]
+        );
+
+        // the removed revision and the duplicate upload left no blobs behind
+        assert_eq!(std::fs::read_dir(dir.join("blobs")).unwrap().count(), 2);
+        // and neither does a file that lost its last revision
+        storage.remove("/dir/b.txt", Some(1)).await.unwrap();
+        assert_eq!(std::fs::read_dir(dir.join("blobs")).unwrap().count(), 1);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn verify_and_repair() {
+        let dir = std::env::temp_dir().join(format!(
+            "voracious-code-storage-verify-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let storage = DiskStorage::open(dir.clone()).await.unwrap();
+        for (name, content) in [("/a.txt", "one"), ("/a.txt", "two"), ("/b.txt", "b")] {
+            let mut file = async_tempfile::TempFile::new().await.unwrap();
+            file.write_all(content.as_bytes()).await.unwrap();
+            let hash = Sha1::digest(content.as_bytes()).to_vec();
+            storage.insert(name.into(), file, hash).await.unwrap();
+        }
+        drop(storage);
+
+        let clean = DiskStorage::verify(&dir, false).await.unwrap();
+        assert_eq!(clean.revisions, 3);
+        assert!(clean.is_clean());
+
+        // blob 0 is r1 of /a.txt, blob 1 is r2 of /a.txt and blob 2 is r1 of /b.txt
+        std::fs::write(dir.join("blobs").join("0"), "corrupted").unwrap();
+        std::fs::remove_file(dir.join("blobs").join("2")).unwrap();
+        std::fs::write(dir.join("blobs").join("7"), "orphan").unwrap();
+
+        let expected = Verification {
+            revisions: 3,
+            missing: vec![("/b.txt".into(), 1)],
+            mismatched: vec![("/a.txt".into(), 1)],
+            orphaned: vec!["7".into()],
+            repaired: false,
+        };
+        assert_eq!(DiskStorage::verify(&dir, false).await.unwrap(), expected);
+        // verifying alone changes nothing
+        assert_eq!(DiskStorage::verify(&dir, false).await.unwrap(), expected);
+
+        let repaired = DiskStorage::verify(&dir, true).await.unwrap();
+        assert!(repaired.repaired);
+        let verification = DiskStorage::verify(&dir, false).await.unwrap();
+        assert_eq!(verification.revisions, 2);
+        assert!(verification.is_clean());
+
+        // the repaired revision is served as it's stored, and the missing one is gone
+        let storage = DiskStorage::open(dir.clone()).await.unwrap();
+        let mut content = String::new();
+        storage
+            .get("/a.txt", Some(1))
+            .await
+            .unwrap()
+            .read_to_string(&mut content)
+            .await
+            .unwrap();
+        assert_eq!(content, "corrupted");
+        assert!(storage.get("/b.txt", None).await.is_err());
+        drop(storage);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,84 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+
+/// Applies the changes to a single file one at a time, in the order they were requested,
+/// while the changes to other files run concurrently
+///
+/// a change holds the lock of its file from the moment it starts writing the content
+/// until its revision is assigned, so revisions are numbered in the order they were written
+#[derive(Debug, Default)]
+pub struct FileLocks {
+    // a lock is only kept around while it's held or waited for
+    locks: Mutex<HashMap<String, Arc<AsyncMutex<()>>>>,
+}
+
+impl FileLocks {
+    pub async fn lock(&self, path: &str) -> FileGuard<'_> {
+        let lock = self
+            .locks
+            .lock()
+            .unwrap()
+            .entry(path.into())
+            .or_default()
+            .clone();
+
+        FileGuard {
+            locks: self,
+            path: path.into(),
+            guard: Some(lock.lock_owned().await),
+        }
+    }
+}
+
+pub struct FileGuard<'a> {
+    locks: &'a FileLocks,
+    path: String,
+    guard: Option<OwnedMutexGuard<()>>,
+}
+
+impl Drop for FileGuard<'_> {
+    fn drop(&mut self) {
+        let mut locks = self.locks.locks.lock().unwrap();
+        drop(self.guard.take());
+
+        // nobody else can get a hold of the lock while the map is locked
+        if locks
+            .get(&self.path)
+            .is_some_and(|lock| Arc::strong_count(lock) == 1)
+        {
+            locks.remove(&self.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::FileLocks;
+
+    #[tokio::test]
+    async fn lock_each_file_separately() {
+        let locks = FileLocks::default();
+
+        let a = locks.lock("/a.txt").await;
+        // another file isn't held up
+        drop(locks.lock("/b.txt").await);
+
+        let waiting = locks.lock("/a.txt");
+        tokio::pin!(waiting);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(10), &mut waiting)
+                .await
+                .is_err()
+        );
+
+        drop(a);
+        drop(waiting.await);
+        assert!(locks.locks.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,414 @@
+use std::{cmp::Ordering, net::IpAddr};
+
+use async_tempfile::TempFile;
+use async_trait::async_trait;
+
+pub use disk::{DiskStorage, Verification};
+pub use temp::TempFileSystem;
+pub use vcs_protocol::{Glob, ListResult, Metadata};
+
+mod disk;
+mod locks;
+mod temp;
+mod tree;
+
+/// Where the files of the server are kept
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// inserts a new file into the storage
+    /// returns the revision number
+    async fn insert(
+        &self,
+        filepath: String,
+        file: TempFile,
+        hash: Vec<u8>,
+    ) -> Result<u64, StorageErr>;
+
+    /// if the file exists, will return a tempfile
+    /// that can then be used to read the file content.
+    /// the storage trusts and relies on the caller to not write to the file, only read it.
+    ///
+    /// returns an error if the correct revision of the file can't be found
+    async fn get(&self, name: &str, revision: Option<u64>) -> Result<TempFile, StorageErr>;
+
+    // returns the list of children of a given directory, in the order of `list_order`
+    fn list(&self, dir_path: &str) -> Vec<ListResult>;
+
+    // returns every file under a given directory, named by its path relative to the directory,
+    // in the order of `list_order`
+    fn list_recursive(&self, dir_path: &str) -> Vec<ListResult>;
+
+    /// returns the metadata of every revision of a file (that wasn't deleted), by revision number
+    fn stat(&self, name: &str) -> Result<Vec<(u64, Metadata)>, StorageErr>;
+
+    /// removes a single revision of a file, or the whole file when no revision is given
+    ///
+    /// a file without revisions is removed, along with the dirs that are left empty
+    async fn remove(&self, name: &str, revision: Option<u64>) -> Result<(), StorageErr>;
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum StorageErr {
+    #[error("no such file")]
+    FileNotFound,
+
+    #[error("no such revision")]
+    RevisionNotFound,
+
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("{0}")]
+    TempFile(#[from] async_tempfile::Error),
+
+    #[error("the index is corrupted at line {0}")]
+    CorruptedIndex(usize),
+}
+
+/// The view of the filesystem a client has, all of its paths are resolved against its root
+///
+/// the shared namespace is the filesystem itself, every tenant's namespace is rooted
+/// at a directory of its own, which can't be named from within another namespace
+#[derive(Debug, Clone, Default)]
+pub struct Namespace {
+    // empty for the shared namespace
+    root: String,
+}
+
+impl Namespace {
+    /// The namespace of the clients connecting from an address
+    pub fn address(addr: IpAddr) -> Self {
+        // '@' is never part of a token, so addresses and tokens never share a namespace
+        Self {
+            root: format!("/@{}", addr),
+        }
+    }
+
+    /// The namespace picked by a client with a token,
+    /// the token must be a valid path component
+    pub fn token(token: &str) -> Self {
+        Self {
+            root: format!("/{}", token),
+        }
+    }
+
+    /// Maps a path of the namespace (a file, or a dir ending with '/') to its path in the filesystem
+    pub fn resolve(&self, path: &str) -> String {
+        format!("{}{}", self.root, path)
+    }
+}
+
+/// The order listed children are returned in
+///
+/// names are compared byte by byte, whatever the locale of the server: uppercase letters come
+/// before lowercase ones, and digits are compared one at a time rather than by their value
+/// (so "a10" comes before "a9"). a file comes before a dir of the same name
+pub fn list_order(a: &ListResult, b: &ListResult) -> Ordering {
+    let is_dir = |child: &ListResult| matches!(child, ListResult::Dir(_));
+    compare_names(a.name(), b.name()).then_with(|| is_dir(a).cmp(&is_dir(b)))
+}
+
+// the name part of `list_order`
+fn compare_names(a: &str, b: &str) -> Ordering {
+    a.as_bytes().cmp(b.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::SeekFrom, path::PathBuf, sync::Arc, time::SystemTime};
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+        task::JoinSet,
+    };
+
+    use super::{DiskStorage, ListResult, Namespace, Storage, StorageErr, TempFileSystem};
+
+    fn names(children: Vec<ListResult>) -> Vec<String> {
+        children
+            .into_iter()
+            .map(|child| match child {
+                ListResult::Dir(name) => format!("{}/", name),
+                ListResult::File { name, .. } => name,
+            })
+            .collect()
+    }
+
+    async fn put(fs: &dyn Storage, name: &str, content: &[u8]) -> u64 {
+        let mut file = async_tempfile::TempFile::new().await.unwrap();
+        file.write_all(content).await.unwrap();
+        fs.insert(name.into(), file, content.to_vec())
+            .await
+            .unwrap()
+    }
+
+    fn storage_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "voracious-code-storage-{}-{}",
+            test,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    // runs a test against a fresh instance of every backend
+    async fn backends(test: &str) -> Vec<(Box<dyn Storage>, Option<PathBuf>)> {
+        let dir = storage_dir(test);
+        vec![
+            (Box::new(TempFileSystem::default()), None),
+            (
+                Box::new(DiskStorage::open(dir.clone()).await.unwrap()),
+                Some(dir),
+            ),
+        ]
+    }
+
+    #[tokio::test]
+    async fn isolate_namespaces() {
+        let fs = TempFileSystem::default();
+        let alice = Namespace::token("alice");
+        let bob = Namespace::address("127.0.0.1".parse().unwrap());
+
+        put(&fs, &alice.resolve("/dir/a.txt"), b"a").await;
+        put(&fs, &bob.resolve("/b.txt"), b"b").await;
+
+        assert_eq!(names(fs.list(&alice.resolve("/"))), ["dir/"]);
+        assert_eq!(names(fs.list(&alice.resolve("/dir/"))), ["a.txt"]);
+        assert_eq!(names(fs.list(&bob.resolve("/"))), ["b.txt"]);
+
+        assert!(fs.get(&alice.resolve("/dir/a.txt"), None).await.is_ok());
+        assert!(fs.get(&bob.resolve("/dir/a.txt"), None).await.is_err());
+        assert!(fs.get("/dir/a.txt", None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn remove_revisions() {
+        for (fs, dir) in backends("remove-revisions").await {
+            let fs = fs.as_ref();
+            for content in [b"one", b"two", b"six"] {
+                put(fs, "/a.txt", content).await;
+            }
+
+            fs.remove("/a.txt", Some(3)).await.unwrap();
+            assert!(fs.get("/a.txt", Some(3)).await.is_err());
+            assert!(matches!(
+                fs.list("/").as_slice(),
+                [ListResult::File {
+                    last_revision: 2,
+                    ..
+                }]
+            ));
+            assert!(matches!(
+                fs.remove("/a.txt", Some(3)).await,
+                Err(StorageErr::RevisionNotFound)
+            ));
+            assert!(matches!(
+                fs.remove("/a.txt", Some(0)).await,
+                Err(StorageErr::RevisionNotFound)
+            ));
+
+            // revision numbers are never reused, even for content that was deleted
+            assert_eq!(put(fs, "/a.txt", b"six").await, 4);
+            fs.remove("/a.txt", Some(1)).await.unwrap();
+            assert!(fs.get("/a.txt", Some(2)).await.is_ok());
+
+            // removing the last revision removes the file
+            for revision in [2, 4] {
+                fs.remove("/a.txt", Some(revision)).await.unwrap();
+            }
+            assert!(matches!(
+                fs.get("/a.txt", None).await,
+                Err(StorageErr::FileNotFound)
+            ));
+            assert!(fs.list("/").is_empty());
+
+            if let Some(dir) = dir {
+                std::fs::remove_dir_all(dir).unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn clean_up_empty_dirs() {
+        for (fs, dir) in backends("clean-up-empty-dirs").await {
+            let fs = fs.as_ref();
+            put(fs, "/a/b/c/deep.txt", b"deep").await;
+            put(fs, "/a/b/c/other.txt", b"other").await;
+            put(fs, "/a/top.txt", b"top").await;
+            // a file that shares its name with a dir
+            put(fs, "/a/b", b"b").await;
+
+            fs.remove("/a/b/c/deep.txt", None).await.unwrap();
+            assert_eq!(names(fs.list("/a/b/c/")), ["other.txt"]);
+
+            fs.remove("/a/b/c/other.txt", None).await.unwrap();
+            assert!(fs.list("/a/b/c/").is_empty());
+            assert!(fs.list("/a/b/").is_empty());
+            // the entry of /a/b is left to the file
+            assert_eq!(names(fs.list("/a/")), ["b", "top.txt"]);
+
+            fs.remove("/a/b", None).await.unwrap();
+            assert_eq!(names(fs.list("/a/")), ["top.txt"]);
+
+            fs.remove("/a/top.txt", None).await.unwrap();
+            assert!(fs.list("/a/").is_empty());
+            assert!(fs.list("/").is_empty());
+
+            assert!(matches!(
+                fs.remove("/a/top.txt", None).await,
+                Err(StorageErr::FileNotFound)
+            ));
+
+            if let Some(dir) = dir {
+                std::fs::remove_dir_all(dir).unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn list_nested_files() {
+        for (fs, dir) in backends("list-nested-files").await {
+            let fs = fs.as_ref();
+            put(fs, "/src/main.rs", b"main").await;
+            put(fs, "/src/bin/cli.rs", b"cli").await;
+            put(fs, "/src/bin/cli.rs", b"cli 2").await;
+            // a file that shares its name with a dir
+            put(fs, "/src/bin", b"bin").await;
+            put(fs, "/readme.md", b"readme").await;
+
+            assert_eq!(
+                fs.list_recursive("/src/"),
+                [
+                    ListResult::File {
+                        name: "bin".into(),
+                        last_revision: 1
+                    },
+                    ListResult::File {
+                        name: "bin/cli.rs".into(),
+                        last_revision: 2
+                    },
+                    ListResult::File {
+                        name: "main.rs".into(),
+                        last_revision: 1
+                    },
+                ]
+            );
+            assert_eq!(names(fs.list_recursive("/")).len(), 4);
+            assert!(fs.list_recursive("/missing/").is_empty());
+
+            if let Some(dir) = dir {
+                std::fs::remove_dir_all(dir).unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn list_in_byte_order() {
+        for (fs, dir) in backends("list-in-byte-order").await {
+            let fs = fs.as_ref();
+            for name in [
+                "/b.txt", "/a9", "/é.txt", "/a10", "/B.txt", "/a/x", "/_u", "/a-b", "/Zeta/x", "/a",
+            ] {
+                put(fs, name, b"data").await;
+            }
+
+            // a file and a dir of the same name are both listed, the file first
+            assert_eq!(
+                names(fs.list("/")),
+                ["B.txt", "Zeta/", "_u", "a", "a/", "a-b", "a10", "a9", "b.txt", "é.txt"]
+            );
+            // nested paths are ordered as a whole, '-' comes before '/'
+            assert_eq!(
+                names(fs.list_recursive("/")),
+                ["B.txt", "Zeta/x", "_u", "a", "a-b", "a/x", "a10", "a9", "b.txt", "é.txt"]
+            );
+
+            if let Some(dir) = dir {
+                std::fs::remove_dir_all(dir).unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn stat_revisions() {
+        for (fs, dir) in backends("stat-revisions").await {
+            let fs = fs.as_ref();
+            let before = SystemTime::now();
+            put(fs, "/a.txt", b"one").await;
+            put(fs, "/a.txt", b"three").await;
+            put(fs, "/a.txt", b"").await;
+            fs.remove("/a.txt", Some(2)).await.unwrap();
+
+            let stat = fs.stat("/a.txt").unwrap();
+            let sizes: Vec<_> = stat
+                .iter()
+                .map(|(revision, meta)| (*revision, meta.size))
+                .collect();
+            assert_eq!(sizes, [(1, 3), (3, 0)]);
+            assert!(stat.iter().all(|(_, meta)| meta.created >= before));
+
+            assert!(matches!(fs.stat("/b.txt"), Err(StorageErr::FileNotFound)));
+
+            if let Some(dir) = dir {
+                std::fs::remove_dir_all(dir).unwrap();
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn linearize_concurrent_puts() {
+        const PUTS: u64 = 64;
+
+        for (fs, dir) in backends("linearize-concurrent-puts").await {
+            let fs: Arc<dyn Storage> = fs.into();
+
+            let mut tasks = JoinSet::new();
+            for idx in 0..PUTS {
+                let fs = fs.clone();
+                tasks.spawn(async move {
+                    let content = format!("content {}", idx);
+                    let revision = put(fs.as_ref(), "/dir/sub/a.txt", content.as_bytes()).await;
+
+                    // other files come and go in the same dirs
+                    let other = format!("/dir/sub/other-{}.txt", idx);
+                    put(fs.as_ref(), &other, b"other").await;
+                    fs.remove(&other, None).await.unwrap();
+
+                    (revision, content)
+                });
+            }
+
+            let mut revisions = Vec::new();
+            while let Some(result) = tasks.join_next().await {
+                revisions.push(result.unwrap());
+            }
+            revisions.sort();
+
+            // every put got a revision of its own, that holds its content
+            let numbers: Vec<_> = revisions.iter().map(|(revision, _)| *revision).collect();
+            assert_eq!(numbers, (1..=PUTS).collect::<Vec<_>>());
+            for (revision, content) in revisions {
+                let mut file = fs.get("/dir/sub/a.txt", Some(revision)).await.unwrap();
+                // the file may be left at the end of its content, the connections seek back too
+                file.seek(SeekFrom::Start(0)).await.unwrap();
+                let mut stored = String::new();
+                file.read_to_string(&mut stored).await.unwrap();
+                assert_eq!(stored, content);
+            }
+
+            // the revisions were created in the order of their numbers
+            let stat = fs.stat("/dir/sub/a.txt").unwrap();
+            assert!(stat
+                .windows(2)
+                .all(|pair| pair[0].1.created <= pair[1].1.created));
+
+            assert_eq!(names(fs.list("/")), ["dir/"]);
+            assert_eq!(names(fs.list("/dir/sub/")), ["a.txt"]);
+
+            if let Some(dir) = dir {
+                std::fs::remove_dir_all(dir).unwrap();
+            }
+        }
+    }
+}
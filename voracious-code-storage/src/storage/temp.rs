@@ -0,0 +1,57 @@
+use std::sync::Arc;
+
+use async_tempfile::TempFile;
+use async_trait::async_trait;
+use tokio::io::AsyncWriteExt;
+
+use super::{locks::FileLocks, tree::Tree, ListResult, Metadata, Storage, StorageErr};
+
+/// Keeps every revision in a tempfile of its own, so the files are gone once the server stops
+#[derive(Debug, Default)]
+pub struct TempFileSystem {
+    // a revision is only deleted once the last reader of it is done
+    tree: Tree<Arc<TempFile>>,
+    locks: FileLocks,
+}
+
+#[async_trait]
+impl Storage for TempFileSystem {
+    async fn insert(
+        &self,
+        filepath: String,
+        mut file: TempFile,
+        hash: Vec<u8>,
+    ) -> Result<u64, StorageErr> {
+        let _file = self.locks.lock(&filepath).await;
+
+        // the upload may still have writes in flight
+        file.flush().await?;
+        let meta = Metadata::now(file.metadata().await?.len());
+
+        let (revision, _) = self.tree.insert(filepath, Arc::new(file), hash, meta);
+        Ok(revision)
+    }
+
+    async fn get(&self, name: &str, revision: Option<u64>) -> Result<TempFile, StorageErr> {
+        let file = self.tree.get(name, revision)?;
+        Ok(file.try_clone().await?)
+    }
+
+    fn list(&self, dir_path: &str) -> Vec<ListResult> {
+        self.tree.list(dir_path)
+    }
+
+    fn list_recursive(&self, dir_path: &str) -> Vec<ListResult> {
+        self.tree.list_recursive(dir_path)
+    }
+
+    fn stat(&self, name: &str) -> Result<Vec<(u64, Metadata)>, StorageErr> {
+        self.tree.stat(name)
+    }
+
+    async fn remove(&self, name: &str, revision: Option<u64>) -> Result<(), StorageErr> {
+        let _file = self.locks.lock(name).await;
+        self.tree.remove(name, revision)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,406 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    hash::Hash,
+    sync::Mutex,
+};
+
+use dashmap::DashMap;
+
+use super::{compare_names, list_order, ListResult, Metadata, StorageErr};
+
+/// A revision as it's persisted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Revision<T> {
+    pub content: T,
+    pub hash: Vec<u8>,
+    pub meta: Metadata,
+}
+
+// The revisions of a single file
+#[derive(Debug)]
+struct Revisions<T> {
+    // deleted revisions leave a hole, so revision numbers are never reused
+    revisions: Vec<Option<Revision<T>>>,
+    hashes: HashMap<Vec<u8>, u64>,
+}
+
+impl<T> Default for Revisions<T> {
+    fn default() -> Self {
+        Self {
+            revisions: Vec::new(),
+            hashes: HashMap::new(),
+        }
+    }
+}
+
+impl<T> Revisions<T> {
+    // returns the revision number, and the content back if it duplicates an existing revision
+    fn insert(&mut self, content: T, hash: Vec<u8>, meta: Metadata) -> (u64, Option<T>) {
+        // no need to store duplicate of existing files
+        if let Some(revision) = self.hashes.get(&hash) {
+            return (*revision, Some(content));
+        }
+
+        self.revisions.push(Some(Revision {
+            content,
+            hash: hash.clone(),
+            meta,
+        }));
+        let revision = self.revisions.len() as u64;
+
+        self.hashes.insert(hash, revision);
+
+        (revision, None)
+    }
+
+    fn get(&self, revision: u64) -> Option<&T> {
+        let index = revision.checked_sub(1)? as usize;
+        self.revisions
+            .get(index)?
+            .as_ref()
+            .map(|revision| &revision.content)
+    }
+
+    // the metadata of every revision that wasn't deleted, by its revision number
+    fn stat(&self) -> Vec<(u64, Metadata)> {
+        self.revisions
+            .iter()
+            .enumerate()
+            .filter_map(|(index, revision)| {
+                revision
+                    .as_ref()
+                    .map(|revision| (index as u64 + 1, revision.meta))
+            })
+            .collect()
+    }
+
+    fn remove(&mut self, revision: u64) -> Option<T> {
+        let index = revision.checked_sub(1)? as usize;
+        let removed = self.revisions.get_mut(index)?.take()?;
+
+        // uploading the same content again makes a new revision
+        self.hashes.remove(&removed.hash);
+        Some(removed.content)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.get_last_revision() == 0
+    }
+
+    // the latest revision that wasn't deleted, 0 when there is none
+    fn get_last_revision(&self) -> u64 {
+        self.revisions
+            .iter()
+            .rposition(Option::is_some)
+            .map_or(0, |index| index as u64 + 1)
+    }
+}
+
+// Represents an item in a dir, the items are ordered by their names (see `list_order`)
+#[derive(Debug, Eq)]
+enum DirItemStab {
+    File(String),
+    Dir(String),
+}
+
+/// A revision as it's persisted, None for a deleted revision
+pub type Entry<T> = Option<Revision<T>>;
+
+/// The layout of the files and dirs of a storage,
+/// every revision of a file refers to its content in the storage
+#[derive(Debug)]
+pub struct Tree<T> {
+    files: DashMap<String, Revisions<T>>,
+    dirs: DashMap<String, BTreeSet<DirItemStab>>,
+    // held while files are added or removed, so a removal never cleans up a dir
+    // that a concurrent insert is filling
+    layout: Mutex<()>,
+}
+
+impl<T> Default for Tree<T> {
+    fn default() -> Self {
+        Self {
+            files: DashMap::new(),
+            dirs: DashMap::new(),
+            layout: Mutex::new(()),
+        }
+    }
+}
+
+impl<T: Clone> Tree<T> {
+    /// inserts a new file into the tree
+    /// returns the revision number, and the content back if it duplicates an existing revision
+    pub fn insert(
+        &self,
+        filepath: String,
+        content: T,
+        hash: Vec<u8>,
+        meta: Metadata,
+    ) -> (u64, Option<T>) {
+        let _layout = self.layout.lock().unwrap();
+
+        let inserted = self
+            .files
+            .entry(filepath.clone())
+            .or_default()
+            .insert(content, hash, meta);
+        self.link(&filepath);
+
+        inserted
+    }
+
+    /// adds a file with all of its revisions, as they were persisted
+    pub fn restore(&self, filepath: String, entries: Vec<Entry<T>>) {
+        let _layout = self.layout.lock().unwrap();
+
+        let revisions = Revisions {
+            hashes: entries
+                .iter()
+                .enumerate()
+                .filter_map(|(index, entry)| {
+                    entry
+                        .as_ref()
+                        .map(|revision| (revision.hash.clone(), index as u64 + 1))
+                })
+                .collect(),
+            revisions: entries,
+        };
+        if revisions.is_empty() {
+            return;
+        }
+
+        self.files.insert(filepath.clone(), revisions);
+        self.link(&filepath);
+    }
+
+    /// returns every file with all of its revisions, ordered by their paths
+    pub fn snapshot(&self) -> Vec<(String, Vec<Entry<T>>)> {
+        let mut files: Vec<_> = self
+            .files
+            .iter()
+            .map(|file| (file.key().clone(), file.revisions.clone()))
+            .collect();
+        files.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        files
+    }
+
+    /// returns the content of a revision of a file, the latest revision when none is given
+    pub fn get(&self, name: &str, revision: Option<u64>) -> Result<T, StorageErr> {
+        let Some(file) = self.files.get(name) else {
+            return Err(StorageErr::FileNotFound);
+        };
+
+        let revision = revision.unwrap_or_else(|| file.get_last_revision());
+        file.get(revision)
+            .cloned()
+            .ok_or(StorageErr::RevisionNotFound)
+    }
+
+    /// returns the metadata of every revision of a file that wasn't deleted
+    pub fn stat(&self, name: &str) -> Result<Vec<(u64, Metadata)>, StorageErr> {
+        let file = self.files.get(name).ok_or(StorageErr::FileNotFound)?;
+        Ok(file.stat())
+    }
+
+    /// removes a single revision of a file, or the whole file when no revision is given
+    /// returns the contents of the removed revisions
+    ///
+    /// a file without revisions is removed, along with the dirs that are left empty
+    pub fn remove(&self, name: &str, revision: Option<u64>) -> Result<Vec<T>, StorageErr> {
+        let _layout = self.layout.lock().unwrap();
+
+        let mut removed = Vec::new();
+        if let Some(revision) = revision {
+            let mut file = self.files.get_mut(name).ok_or(StorageErr::FileNotFound)?;
+            removed.push(file.remove(revision).ok_or(StorageErr::RevisionNotFound)?);
+
+            if !file.is_empty() {
+                return Ok(removed);
+            }
+        }
+
+        let Some((_, file)) = self.files.remove(name) else {
+            return Err(StorageErr::FileNotFound);
+        };
+        self.unlink(name);
+
+        removed.extend(
+            file.revisions
+                .into_iter()
+                .flatten()
+                .map(|revision| revision.content),
+        );
+        Ok(removed)
+    }
+
+    // returns the list of children of a given directory
+    pub fn list(&self, dir_path: &str) -> Vec<ListResult> {
+        let Some(names) = self.names(dir_path) else {
+            return vec![];
+        };
+
+        let mut children = Vec::with_capacity(names.len());
+        for name in names {
+            // a file and a dir may share a name (and a single entry), either way both are listed.
+            // the file may be removed while its dir is listed
+            if let Some(file) = self.files.get(&format!("{}{}", dir_path, name)) {
+                children.push(ListResult::File {
+                    name: name.clone(),
+                    last_revision: file.get_last_revision(),
+                });
+            }
+            if self.dirs.contains_key(&format!("{}{}/", dir_path, name)) {
+                children.push(ListResult::Dir(name));
+            }
+        }
+
+        children
+    }
+
+    /// returns every file under a given directory, named by its path relative to the directory
+    pub fn list_recursive(&self, dir_path: &str) -> Vec<ListResult> {
+        let mut files = Vec::new();
+        self.walk(dir_path, "", &mut files);
+        // a dir is walked right after the file of its name, but a name that extends it
+        // with a character below '/' (e.g. '-') comes before the paths under the dir
+        files.sort_by(list_order);
+        files
+    }
+
+    // the names of the children of a dir, in order
+    //
+    // the names are copied out, so no lock is held while the children are looked up
+    fn names(&self, dir_path: &str) -> Option<Vec<String>> {
+        self.dirs
+            .get(dir_path)
+            .map(|dir| dir.iter().map(|stab| stab.name().to_string()).collect())
+    }
+
+    fn walk(&self, dir_path: &str, prefix: &str, files: &mut Vec<ListResult>) {
+        let Some(names) = self.names(dir_path) else {
+            return;
+        };
+
+        for name in names {
+            // a file and a dir may share a name (and a single entry), either way both are listed
+            if let Some(file) = self.files.get(&format!("{}{}", dir_path, name)) {
+                files.push(ListResult::File {
+                    name: format!("{}{}", prefix, name),
+                    last_revision: file.get_last_revision(),
+                });
+            }
+
+            self.walk(
+                &format!("{}{}/", dir_path, name),
+                &format!("{}{}/", prefix, name),
+                files,
+            );
+        }
+    }
+
+    // adds a file to its dir, and every dir on its path to its parent
+    fn link(&self, filepath: &str) {
+        let mut path = "/".to_string();
+        // skip the starting '/'
+        let mut parts = filepath[1..].split('/');
+        let filename = parts.next_back().expect("file name can't be empty");
+        for dirname in parts {
+            self.dirs
+                .entry(path.clone())
+                .or_default()
+                .insert(DirItemStab::Dir(dirname.to_string()));
+
+            path += dirname;
+            path += "/";
+        }
+
+        self.dirs
+            .entry(path.clone())
+            .or_default()
+            .insert(DirItemStab::File(filename.into()));
+    }
+
+    // removes a file from its dir, and every dir on its path that is left empty
+    fn unlink(&self, filepath: &str) {
+        let (dir_path, filename) = filepath
+            .rsplit_once('/')
+            .expect("files always start at root");
+        let mut dir_path = format!("{}/", dir_path);
+        let mut stab = DirItemStab::File(filename.into());
+
+        loop {
+            // a file and a dir may share a name, in which case they share a single entry,
+            // that now belongs to the one that is left
+            let remaining = match &stab {
+                DirItemStab::File(name) => self
+                    .dirs
+                    .contains_key(&format!("{}{}/", dir_path, name))
+                    .then(|| DirItemStab::Dir(name.clone())),
+                DirItemStab::Dir(name) => self
+                    .files
+                    .contains_key(&format!("{}{}", dir_path, name))
+                    .then(|| DirItemStab::File(name.clone())),
+            };
+
+            let Some(mut dir) = self.dirs.get_mut(&dir_path) else {
+                return;
+            };
+            match remaining {
+                Some(remaining) => {
+                    dir.replace(remaining);
+                }
+                None => {
+                    dir.remove(&stab);
+                }
+            }
+            if !dir.is_empty() {
+                return;
+            }
+
+            drop(dir);
+            self.dirs.remove(&dir_path);
+
+            // continue with the parent of the dir, until the root was removed
+            let Some((parent, name)) = dir_path[..dir_path.len() - 1].rsplit_once('/') else {
+                return;
+            };
+            stab = DirItemStab::Dir(name.into());
+            dir_path = format!("{}/", parent);
+        }
+    }
+}
+
+// necessary traits impl for the list of dirs to be ordered
+impl PartialEq for DirItemStab {
+    fn eq(&self, other: &Self) -> bool {
+        self.name().eq(other.name())
+    }
+}
+
+impl Ord for DirItemStab {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        compare_names(self.name(), other.name())
+    }
+}
+
+impl PartialOrd for DirItemStab {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Hash for DirItemStab {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.name().hash(state)
+    }
+}
+
+impl DirItemStab {
+    fn name(&self) -> &str {
+        match self {
+            Self::Dir(name) => name,
+            Self::File(name) => name,
+        }
+    }
+}
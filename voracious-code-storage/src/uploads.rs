@@ -0,0 +1,98 @@
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+// uploads below this size are cheap enough to never wait for a permit
+pub const DEFAULT_LARGE_UPLOAD: u64 = 64 * 1024;
+
+/// Bounds the disk space and file handles taken by the uploads in progress
+///
+/// a file can't be bigger than the max file size, and only so many large uploads
+/// may be written into their tempfiles at the same time
+#[derive(Debug)]
+pub struct Uploads {
+    max_file_size: Option<u64>,
+    // uploads of at least this many bytes need a permit
+    large: u64,
+    // no limit on concurrent uploads without it
+    permits: Option<Semaphore>,
+}
+
+impl Default for Uploads {
+    fn default() -> Self {
+        Self::new(None, DEFAULT_LARGE_UPLOAD, None)
+    }
+}
+
+impl Uploads {
+    pub fn new(max_file_size: Option<u64>, large: u64, max_large_uploads: Option<usize>) -> Self {
+        Self {
+            max_file_size,
+            large,
+            permits: max_large_uploads.map(Semaphore::new),
+        }
+    }
+
+    pub fn max_file_size(&self) -> Option<u64> {
+        self.max_file_size
+    }
+
+    /// Whether a file of this size can be stored at all
+    pub fn fits(&self, size: u64) -> bool {
+        self.max_file_size.is_none_or(|max| size <= max)
+    }
+
+    /// Waits until an upload of this many bytes can start,
+    /// the upload should hold on to the returned permit until it's done
+    pub async fn start(&self, byte_count: u64) -> Option<SemaphorePermit<'_>> {
+        if byte_count < self.large {
+            return None;
+        }
+
+        let permits = self.permits.as_ref()?;
+        Some(
+            permits
+                .acquire()
+                .await
+                .expect("the semaphore is never closed"),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Uploads;
+
+    #[test]
+    fn limit_file_size() {
+        let uploads = Uploads::new(Some(100), 10, None);
+        assert!(uploads.fits(0));
+        assert!(uploads.fits(100));
+        assert!(!uploads.fits(101));
+
+        assert!(Uploads::default().fits(u64::MAX));
+    }
+
+    #[tokio::test]
+    async fn limit_concurrent_large_uploads() {
+        let uploads = Uploads::new(None, 10, Some(1));
+
+        let large = uploads.start(10).await;
+        assert!(large.is_some());
+
+        // small uploads never wait
+        assert!(uploads.start(9).await.is_none());
+
+        // another large upload waits for the first one to finish
+        let waiting = uploads.start(1000);
+        tokio::pin!(waiting);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(10), &mut waiting)
+                .await
+                .is_err()
+        );
+
+        drop(large);
+        assert!(waiting.await.is_some());
+    }
+}
@@ -0,0 +1,47 @@
+//: Verifies a storage dir of the server against its index, the server must not be running on it.
+//:
+//: usage: verify-store <storage dir> [--repair]
+//:
+//: every blob is hashed again and compared with its hash in the index, revisions whose blob
+//: is missing or doesn't match are reported, along with the blobs the index doesn't refer to.
+//: with --repair, the index is fixed and the orphaned blobs are removed.
+
+use std::path::PathBuf;
+
+use voracious_code_storage::storage::DiskStorage;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let root: PathBuf = args
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("usage: verify-store <storage dir> [--repair]"))?
+        .into();
+    let repair = match args.next().as_deref() {
+        None => false,
+        Some("--repair") => true,
+        Some(arg) => anyhow::bail!("unknown argument: {}", arg),
+    };
+
+    let verification = DiskStorage::verify(&root, repair).await?;
+    println!("verified {} revisions", verification.revisions);
+    for (filepath, revision) in &verification.missing {
+        println!("missing blob: {} r{}", filepath, revision);
+    }
+    for (filepath, revision) in &verification.mismatched {
+        println!("hash mismatch: {} r{}", filepath, revision);
+    }
+    for blob in &verification.orphaned {
+        println!("orphaned blob: {}", blob);
+    }
+
+    if verification.is_clean() {
+        println!("the storage is consistent");
+    } else if verification.repaired {
+        println!("the storage was repaired");
+    } else {
+        anyhow::bail!("the storage is inconsistent, run with --repair to fix it");
+    }
+
+    Ok(())
+}